@@ -0,0 +1,65 @@
+#!/bin/bash
+set -e
+
+# Per-proof latency benchmark for proving key pinning. Submits PROOFS
+# measurements one at a time and reports how long each took from submission
+# until it completed, together with the server's PROVING_KEY_PIN mode.
+#
+# Run it once against a server started with PROVING_KEY_PIN=off and once with
+# PROVING_KEY_PIN=eager to compare. With DROP_CACHES=1 the page cache is
+# dropped before every proof, as memory pressure would (needs root on the
+# server's host): pinned keys survive it, unpinned ones are read from disk
+# again. The mock circuit artifacts work too, though a mock prover that never
+# reads the key only shows the overhead of pinning. Needs jq; set API_KEY to
+# an operator key when access control is on.
+
+# Colors for output
+GREEN='\033[0;32m'
+RED='\033[0;31m'
+NC='\033[0m' # No Color
+
+SERVER_URL="${SERVER_URL:-http://localhost:3001}"
+PROOFS="${PROOFS:-10}"
+IMAGE="test_vectors/inputs/image.jpg"
+AUTH=()
+[ -n "$API_KEY" ] && AUTH=(-H "X-Api-Key: $API_KEY")
+
+MODE=$(curl -s "${AUTH[@]}" "$SERVER_URL/admin/stats" | jq -r '.proving_keys.mode // "unknown"')
+echo "Proving key pinning: $MODE, $PROOFS proofs"
+
+TOTAL=0 MIN="" MAX=0
+for i in $(seq 1 "$PROOFS"); do
+  if [ -n "$DROP_CACHES" ]; then
+    sync
+    echo 1 > /proc/sys/vm/drop_caches
+  fi
+
+  START=$(date +%s%3N)
+  ID=$(curl -s -X POST "${AUTH[@]}" "$SERVER_URL/measurements" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' | jq -r '.measurement_id // empty')
+  if [ -z "$ID" ]; then
+    echo -e "${RED}Submission $i failed${NC}"
+    exit 1
+  fi
+
+  STATUS=""
+  until [ "$STATUS" == "Completed" ] || [ "$STATUS" == "Failed" ]; do
+    sleep 0.2
+    STATUS=$(curl -s "${AUTH[@]}" "$SERVER_URL/status/$ID" | jq -r '.status // empty')
+  done
+  if [ "$STATUS" == "Failed" ]; then
+    echo -e "${RED}Measurement $ID failed${NC}"
+    exit 1
+  fi
+
+  # Milliseconds from submission to completion
+  ELAPSED=$(($(date +%s%3N) - START))
+  echo "Proof $i: ${ELAPSED}ms"
+  TOTAL=$((TOTAL + ELAPSED))
+  if [ -z "$MIN" ] || [ "$ELAPSED" -lt "$MIN" ]; then MIN=$ELAPSED; fi
+  if [ "$ELAPSED" -gt "$MAX" ]; then MAX=$ELAPSED; fi
+done
+
+echo -e "${GREEN}$MODE: mean $((TOTAL / PROOFS))ms, min ${MIN}ms, max ${MAX}ms over $PROOFS proofs${NC}"
@@ -0,0 +1,34 @@
+#!/bin/bash
+set -e
+
+# Benchmark of the in-process Groth16 verifier. Verifies the known-good proof
+# in test_vectors/groth16 REPEAT times with verify_proof and compares the mean
+# time of a verification with BUDGET_MS, the time a status request could
+# spend verifying a proof inline. The key is parsed and prepared once, as a
+# server would keep it. The fixture has one public signal like the zkHotdog
+# circuit, and verification time grows only slightly with more.
+#
+# Build the tool with optimizations first (cargo build --release --bin
+# verify_proof); set VERIFY_PROOF to use another binary.
+
+# Colors for output
+GREEN='\033[0;32m'
+RED='\033[0;31m'
+NC='\033[0m' # No Color
+
+VERIFY_PROOF="${VERIFY_PROOF:-target/release/verify_proof}"
+REPEAT="${REPEAT:-200}"
+BUDGET_MS="${BUDGET_MS:-10}"
+FIXTURES="test_vectors/groth16"
+
+RESULT=$("$VERIFY_PROOF" --repeat "$REPEAT" \
+  "$FIXTURES/verification_key.json" "$FIXTURES/public.json" "$FIXTURES/proof.json")
+echo "$RESULT"
+MEAN_MS=$(echo "$RESULT" | sed -n 's/.*, \([0-9.]*\) ms each/\1/p')
+
+if awk -v mean="$MEAN_MS" -v budget="$BUDGET_MS" 'BEGIN { exit !(mean <= budget) }'; then
+  echo -e "${GREEN}Mean verification of $MEAN_MS ms is within the $BUDGET_MS ms budget${NC}"
+else
+  echo -e "${RED}Mean verification of $MEAN_MS ms exceeds the $BUDGET_MS ms budget${NC}"
+  exit 1
+fi
@@ -0,0 +1,21 @@
+use std::{env, process::Command};
+
+// Embed the git commit the server is built from so proofs can be traced back
+// to the exact binary. Builds outside a git checkout (e.g. Docker) can pass it
+// in through the GIT_HASH environment variable instead.
+fn main() {
+    println!("cargo:rerun-if-env-changed=GIT_HASH");
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/refs");
+
+    let hash = env::var("GIT_HASH").ok().filter(|h| !h.trim().is_empty()).or_else(|| {
+        Command::new("git")
+            .args(["rev-parse", "--short", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    });
+
+    println!("cargo:rustc-env=ZKHOTDOG_GIT_HASH={}", hash.unwrap_or_else(|| "unknown".to_string()));
+}
@@ -0,0 +1,61 @@
+pragma circom 2.1.3;
+
+/*
+ * Calculates the cosine of the angle at the mid point of three 3D points,
+ * as a numerator and the square of its denominator
+ * Inputs:
+ *   - vector1[3]: Vector from the mid point to the start point (x,y,z)
+ *   - vector2[3]: Vector from the mid point to the end point (x,y,z)
+ *   - cos_numerator: Public input for the claimed dot product of the vectors
+ *   - cos_denominator_squared: Public input for the claimed product of the
+ *     vectors' squared lengths
+ *
+ * The cosine of the angle is cos_numerator / sqrt(cos_denominator_squared).
+ */
+
+// Helper template to calculate the dot product of two 3D vectors
+template Dot() {
+    signal input a[3];
+    signal input b[3];
+    signal output out;
+
+    signal products[3];
+    for (var i = 0; i < 3; i++) {
+        products[i] <== a[i] * b[i];
+    }
+    out <== products[0] + products[1] + products[2];
+}
+
+// Main template for ZK hotdog angle measurements
+template ZkHotdogAngle() {
+    // Private input signals - the vectors from the mid point to each end point
+    signal input vector1[3];
+    signal input vector2[3];
+
+    // Public input signals - the claimed numerator and squared denominator of the cosine
+    signal input cos_numerator;
+    signal input cos_denominator_squared;
+
+    component dot = Dot();
+    component length1 = Dot();
+    component length2 = Dot();
+    for (var i = 0; i < 3; i++) {
+        dot.a[i] <== vector1[i];
+        dot.b[i] <== vector2[i];
+        length1.a[i] <== vector1[i];
+        length1.b[i] <== vector1[i];
+        length2.a[i] <== vector2[i];
+        length2.b[i] <== vector2[i];
+    }
+
+    // Constraint: the numerator must equal the dot product of the vectors
+    cos_numerator === dot.out;
+
+    // Constraint: the squared denominator must equal the product of their squared lengths
+    signal denominator_squared;
+    denominator_squared <== length1.out * length2.out;
+    cos_denominator_squared === denominator_squared;
+}
+
+// Main component instantiation
+component main {public [cos_numerator, cos_denominator_squared]} = ZkHotdogAngle();
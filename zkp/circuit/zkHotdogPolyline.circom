@@ -0,0 +1,73 @@
+pragma circom 2.1.3;
+
+/*
+ * Calculates the squared length of every segment of a polyline of 3D points
+ * Inputs:
+ *   - points[MAX_SEGMENTS + 1][3]: The points in order (x,y,z), the last one
+ *     repeated after the end of the polyline so unused segments have no length
+ *   - segment_count: Public input for the number of segments measured
+ *   - segment_distances_squared[MAX_SEGMENTS]: Public input for the claimed
+ *     squared length of each segment, 0 for unused ones
+ *
+ * segment_count is not constrained by the circuit itself; verifiers check
+ * that the segments from segment_count on have no length.
+ */
+
+// Helper template to calculate square of a number
+template Square() {
+    signal input in;
+    signal output out;
+
+    out <== in * in;
+}
+
+// Template for calculating the square of the distance between two 3D points
+template PointDistanceSquared() {
+    // Private inputs - 3D coordinates (x,y,z) for each point
+    signal input point1[3];
+    signal input point2[3];
+
+    // Output - squared distance
+    signal output distanceSquared;
+
+    // Calculate differences between coordinates
+    signal diff[3];
+    for (var i = 0; i < 3; i++) {
+        diff[i] <== point1[i] - point2[i];
+    }
+
+    // Square the differences
+    component squarer[3];
+    for (var i = 0; i < 3; i++) {
+        squarer[i] = Square();
+        squarer[i].in <== diff[i];
+    }
+
+    // Sum the squares to get the squared distance
+    distanceSquared <== squarer[0].out + squarer[1].out + squarer[2].out;
+}
+
+// Main template for ZK hotdog measurements along a polyline
+template ZkHotdogPolyline(MAX_SEGMENTS) {
+    // Private input signals - the points of the polyline
+    signal input points[MAX_SEGMENTS + 1][3];
+
+    // Public input signals - the number of segments and their claimed squared lengths
+    signal input segment_count;
+    signal input segment_distances_squared[MAX_SEGMENTS];
+
+    // Constraint: each claimed squared length must equal the one calculated
+    // between the segment's two points
+    component segment[MAX_SEGMENTS];
+    for (var s = 0; s < MAX_SEGMENTS; s++) {
+        segment[s] = PointDistanceSquared();
+        for (var i = 0; i < 3; i++) {
+            segment[s].point1[i] <== points[s][i];
+            segment[s].point2[i] <== points[s + 1][i];
+        }
+        segment_distances_squared[s] === segment[s].distanceSquared;
+    }
+}
+
+// Main component instantiation, for polylines of up to 16 segments
+component main {public [segment_count, segment_distances_squared]} = ZkHotdogPolyline(16);
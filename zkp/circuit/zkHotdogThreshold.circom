@@ -0,0 +1,70 @@
+pragma circom 2.1.3;
+
+/*
+ * Proves that the distance between two 3D points is at least a public minimum
+ * without revealing the points or the distance
+ * Inputs:
+ *   - point1[3]: First point coordinates (x,y,z)
+ *   - point2[3]: Second point coordinates (x,y,z)
+ *   - min_distance_squared: Public input for the claimed minimum of the
+ *     squared distance
+ */
+
+// Helper template to calculate square of a number
+template Square() {
+    signal input in;
+    signal output out;
+
+    out <== in * in;
+}
+
+// Helper template constraining a number to n bits, so it is not negative
+template Num2Bits(n) {
+    signal input in;
+    signal bits[n];
+
+    var sum = 0;
+    var power = 1;
+    for (var i = 0; i < n; i++) {
+        bits[i] <-- (in >> i) & 1;
+        bits[i] * (bits[i] - 1) === 0;
+        sum += bits[i] * power;
+        power = power + power;
+    }
+    sum === in;
+}
+
+// Main template for ZK hotdog threshold proofs
+template ZkHotdogThreshold() {
+    // Private input signals - 3D coordinates (x,y,z) for each point
+    signal input point1[3];
+    signal input point2[3];
+
+    // Public input signal - the claimed minimum of the squared distance
+    signal input min_distance_squared;
+
+    // Calculate differences between coordinates
+    signal diff[3];
+    for (var i = 0; i < 3; i++) {
+        diff[i] <== point1[i] - point2[i];
+    }
+
+    // Square the differences
+    component squarer[3];
+    for (var i = 0; i < 3; i++) {
+        squarer[i] = Square();
+        squarer[i].in <== diff[i];
+    }
+
+    // Sum the squares to get the squared distance
+    signal distance_squared;
+    distance_squared <== squarer[0].out + squarer[1].out + squarer[2].out;
+
+    // Constraint: the squared distance minus the minimum must fit in 64 bits,
+    // which it only does when it is not negative
+    component excess = Num2Bits(64);
+    excess.in <== distance_squared - min_distance_squared;
+}
+
+// Main component instantiation
+component main {public [min_distance_squared]} = ZkHotdogThreshold();
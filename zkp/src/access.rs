@@ -0,0 +1,151 @@
+// Access to single measurements, and what a refusal gives away.
+//
+// Every endpoint taking a measurement id decides through this module whether
+// the caller may see or change that measurement, so that a refusal cannot be
+// told apart from an unknown id. Measurements of other tenants, embargoed
+// ones on the public endpoints, and on the status endpoints for callers other
+// than their submitter and admins, and, for callers other than admins, those
+// submitted by someone else on the endpoints changing them are all answered
+// with the same 404 and body as an id that names no measurement. The
+// submitter is the API key a measurement was submitted with, wherever it
+// calls from; only without a stored key, as for anonymous callers, is it the
+// address the measurement was submitted from. Admins still get 403 for
+// measurements they see but may not change, as telling them apart reveals
+// nothing they cannot list anyway; tenant-scoped admins only for their own
+// tenant.
+//
+// The decision is made on the measurement held in memory, or on the one read
+// from the store when the endpoint reads it for every id, before any work
+// specific to a measurement that exists, so a refusal takes as long as a
+// miss.
+
+use axum::http::StatusCode;
+use chrono::{DateTime, Utc};
+use std::{net::IpAddr, ops::Deref};
+
+use crate::{
+    Measurement,
+    auth::{Caller, Role},
+    embargo,
+};
+
+// Who besides the submitter may change a measurement through an endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Managers {
+    SubmitterOnly,
+    SubmitterOrAdmin,
+}
+
+// The one answer for a measurement that is missing or may not be seen
+pub fn not_found(id: &str) -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, format!("Measurement with ID {} not found", id))
+}
+
+// Whether the caller may see the measurement on endpoints showing it to its
+// tenant, embargo or not
+pub fn may_see(caller: &Caller, m: &Measurement) -> bool {
+    caller.can_access(m.tenant.as_deref())
+}
+
+// Whether the caller may see the measurement on the public endpoints, which
+// hide it while it is embargoed
+pub fn may_see_published(caller: &Caller, m: &Measurement, now: DateTime<Utc>) -> bool {
+    may_see(caller, m) && embargo::is_public(m, now)
+}
+
+// Whether the caller may see the measurement on the endpoints reporting it in
+// full, such as its status and listings: its submitter and admins see it
+// while it is embargoed, others only on its release
+pub fn may_see_in_full(
+    caller: &Caller,
+    remote: IpAddr,
+    m: &Measurement,
+    now: DateTime<Utc>,
+) -> bool {
+    let admin = caller.role == Some(Role::Admin);
+    may_see_published(caller, m, now)
+        || (may_see(caller, m) && (admin || m.owner == submitter(caller, remote)))
+}
+
+// The measurement if the caller may see it, otherwise 404
+pub fn visible<M: Deref<Target = Measurement>>(
+    m: Option<M>,
+    caller: &Caller,
+    id: &str,
+) -> Result<M, (StatusCode, String)> {
+    m.filter(|m| may_see(caller, m)).ok_or_else(|| not_found(id))
+}
+
+// Who a submission is recorded as made by: the id of the caller's API key,
+// or the address it calls from when it has no stored key
+pub fn submitter(caller: &Caller, remote: IpAddr) -> String {
+    caller.key_id.clone().unwrap_or_else(|| remote.to_string())
+}
+
+// The measurement if the caller may change it: the submitter, identified as
+// by submitter(), or an admin where the endpoint allows.
+// Other callers get 404 like for a missing measurement, admins 403 naming the
+// action they may not take.
+pub fn managed<M: Deref<Target = Measurement>>(
+    m: Option<M>,
+    caller: &Caller,
+    remote: IpAddr,
+    id: &str,
+    managers: Managers,
+    action: &str,
+) -> Result<M, (StatusCode, String)> {
+    let m = visible(m, caller, id)?;
+    let admin = caller.role == Some(Role::Admin);
+    if m.owner == submitter(caller, remote) || (admin && managers == Managers::SubmitterOrAdmin) {
+        return Ok(m);
+    }
+    if !admin {
+        return Err(not_found(id));
+    }
+    Err((StatusCode::FORBIDDEN, format!("Only the submitter can {}", action)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProofStatus;
+
+    fn caller(key_id: Option<&str>) -> Caller {
+        Caller {
+            name: key_id.unwrap_or("anonymous").to_string(),
+            key_id: key_id.map(str::to_string),
+            role: key_id.map(|_| Role::Submitter),
+            tenant: None,
+        }
+    }
+
+    fn owned_by(caller: &Caller, remote: &str) -> Measurement {
+        let mut m = Measurement::fixture("m");
+        m.owner = submitter(caller, remote.parse().unwrap());
+        m.status = ProofStatus::Failed;
+        m
+    }
+
+    fn may_change(m: &Measurement, caller: &Caller, remote: &str) -> bool {
+        managed(Some(m), caller, remote.parse().unwrap(), "m", Managers::SubmitterOnly, "retry")
+            .is_ok()
+    }
+
+    #[test]
+    fn keyed_submitter_is_its_key_wherever_it_calls_from() {
+        let (key, other) = (caller(Some("key-1")), caller(Some("key-2")));
+        let m = owned_by(&key, "10.0.0.1");
+        assert_eq!(m.owner, "key-1");
+        assert!(may_change(&m, &key, "10.0.0.2"));
+        assert!(!may_change(&m, &other, "10.0.0.1"));
+        assert!(!may_change(&m, &caller(None), "10.0.0.1"));
+    }
+
+    #[test]
+    fn anonymous_submitter_is_its_address() {
+        let anonymous = caller(None);
+        let m = owned_by(&anonymous, "10.0.0.1");
+        assert!(may_change(&m, &anonymous, "10.0.0.1"));
+        assert!(!may_change(&m, &anonymous, "10.0.0.2"));
+    }
+}
@@ -0,0 +1,350 @@
+// Operator-facing endpoints.
+
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+use crate::{
+    AppState, Measurement, ProofStatus,
+    access::{self, Managers},
+    artifacts::{self, RemovalReport},
+    attestations::AttestationStats,
+    auth::{Admin, Authorized, Operator, Submitter},
+    broker::BrokerStats,
+    cache::CacheStats,
+    commitment::CommitmentStats,
+    intake::IntakeState,
+    listing::MeasurementFilter,
+    proving_keys::ProvingKeyStats,
+    queue::QueueStats,
+    rpc::RpcStats,
+    shadow::ShadowStats,
+    status_reads::StatusReadStats,
+    store::StoreGuard,
+    store_cache::MeasurementCacheStats,
+    tenants,
+    thumbnails::PrewarmStats,
+    timecheck::ClockSkewStats,
+    uploads::AbandonedUploadStats,
+};
+
+// Shared queue, pre-warm and broker figures are only shown to global callers,
+// as they mix every tenant's activity
+#[derive(Serialize)]
+pub struct AdminStats {
+    // Number of measurements in each proof status
+    measurements: BTreeMap<String, usize>,
+    // The same counts per tenant, in tenant mode
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    tenants: BTreeMap<String, BTreeMap<String, usize>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    queue: Option<QueueStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prewarm: Option<PrewarmStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    broker: Option<BrokerStats>,
+    // Present when intake windows are configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    intake: Option<IntakeState>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache: Option<CacheStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clock_skew: Option<ClockSkewStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    proving_keys: Option<ProvingKeyStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rpc: Option<RpcStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shadow: Option<ShadowStats>,
+    // Present when attestations are polled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attestations: Option<AttestationStats>,
+    // Present when abandoned uploads are swept
+    #[serde(skip_serializing_if = "Option::is_none")]
+    abandoned_uploads: Option<AbandonedUploadStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status_reads: Option<StatusReadStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    measurement_cache: Option<MeasurementCacheStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    commitments: Option<CommitmentStats>,
+}
+
+// Handler reporting measurement and queue statistics
+pub async fn stats(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Operator>,
+) -> Json<AdminStats> {
+    let mut measurements = BTreeMap::new();
+    let mut tenants: BTreeMap<String, BTreeMap<String, usize>> = BTreeMap::new();
+    for m in state.measurements.lock().values() {
+        if !caller.can_access(m.tenant.as_deref()) {
+            continue;
+        }
+        let status = format!("{:?}", m.status);
+        if let Some(tenant) = &m.tenant {
+            *tenants.entry(tenant.clone()).or_default().entry(status.clone()).or_insert(0) += 1;
+        }
+        *measurements.entry(status).or_insert(0) += 1;
+    }
+
+    let global = caller.tenant.is_none();
+    Json(AdminStats {
+        measurements,
+        tenants,
+        queue: global.then(|| state.queue.stats()),
+        prewarm: global.then(|| {
+            state.prewarmer.stats(state.config.thumbnail_prewarm, state.config.thumbnail_workers)
+        }),
+        broker: state.broker.as_ref().filter(|_| global).map(|broker| broker.stats()),
+        intake: state
+            .config
+            .intake_windows
+            .as_ref()
+            .map(|schedule| schedule.state(state.config.intake_outside, Utc::now())),
+        cache: global.then(|| state.cache.stats()),
+        clock_skew: global.then(|| state.clock.stats()),
+        proving_keys: global.then(|| state.proving_keys.stats()),
+        rpc: global.then(|| state.rpc.stats()),
+        shadow: global.then(|| state.shadow.stats()),
+        attestations: (global && state.config.attestation_polling)
+            .then(|| state.attestations.stats()),
+        abandoned_uploads: (global && state.config.upload_abandoned_ttl_secs > 0)
+            .then(|| state.abandoned_uploads.stats()),
+        status_reads: global.then(|| state.status_reads.stats()),
+        measurement_cache: global.then(|| state.measurements.cache_stats()),
+        commitments: global.then(|| state.commitments.stats()),
+    })
+}
+
+// How long a batch deletion confirmation token stays valid
+const CONFIRM_TOKEN_TTL: Duration = Duration::from_secs(300);
+
+// Deletions that were previewed and await confirmation, keyed by token
+#[derive(Default)]
+pub struct PendingDeletions {
+    tokens: Mutex<HashMap<String, (MeasurementFilter, Instant)>>,
+}
+
+#[derive(Deserialize)]
+pub struct BatchDeleteRequest {
+    #[serde(flatten)]
+    filter: MeasurementFilter,
+    confirm_token: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct BatchDeletePreview {
+    matched: usize,
+    confirm_token: String,
+    expires_in_seconds: u64,
+}
+
+#[derive(Serialize)]
+pub struct BatchDeleteStarted {
+    job_id: String,
+    matched: usize,
+}
+
+#[derive(Serialize, Clone)]
+struct SkippedMeasurement {
+    id: String,
+    reason: &'static str,
+}
+
+#[derive(Serialize, Default, Clone)]
+struct BatchDeleteReport {
+    deleted: usize,
+    skipped: Vec<SkippedMeasurement>,
+    file_errors: Vec<String>,
+}
+
+// Handler deleting all measurements matching a filter in two steps: without a
+// token it only counts the matches and issues a token bound to the filter;
+// with the token it starts the deletion as a background job
+pub async fn batch_delete(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Admin>,
+    Json(mut request): Json<BatchDeleteRequest>,
+) -> Result<Response, (StatusCode, String)> {
+    // Scoped admins only ever delete within their own tenant
+    if caller.tenant.is_some() {
+        if request.filter.tenant.is_some() && request.filter.tenant != caller.tenant {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "Only measurements of your own tenant can be deleted".to_string(),
+            ));
+        }
+        request.filter.tenant = caller.tenant.clone();
+    }
+    let matched: Vec<String> = state
+        .measurements
+        .lock()
+        .values()
+        .filter(|m| request.filter.matches(m))
+        .map(|m| m.id.clone())
+        .collect();
+
+    let Some(token) = request.confirm_token else {
+        let token = Uuid::new_v4().to_string();
+        let mut tokens = state.pending_deletions.tokens.lock().unwrap();
+        tokens.retain(|_, (_, issued)| issued.elapsed() < CONFIRM_TOKEN_TTL);
+        tokens.insert(token.clone(), (request.filter, Instant::now()));
+
+        return Ok(Json(BatchDeletePreview {
+            matched: matched.len(),
+            confirm_token: token,
+            expires_in_seconds: CONFIRM_TOKEN_TTL.as_secs(),
+        })
+        .into_response());
+    };
+
+    // The token is single use and only valid for the exact filter it was issued for
+    let issued = state.pending_deletions.tokens.lock().unwrap().remove(&token);
+    match issued {
+        Some((filter, issued)) if issued.elapsed() < CONFIRM_TOKEN_TTL => {
+            if filter != request.filter {
+                return Err((
+                    StatusCode::CONFLICT,
+                    "Confirmation token was issued for a different filter".to_string(),
+                ));
+            }
+        }
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Confirmation token is invalid or expired".to_string(),
+            ));
+        }
+    }
+
+    let job_id = state.jobs.start("batch_delete", matched.len(), caller.tenant);
+    state.audit.record(
+        "batch_delete_started",
+        json!({ "job_id": job_id, "filter": request.filter, "matched": matched.len() }),
+    );
+
+    let response = BatchDeleteStarted { job_id: job_id.clone(), matched: matched.len() };
+    tokio::spawn(run_batch_delete(state.clone(), job_id, matched));
+
+    Ok((StatusCode::ACCEPTED, Json(response)).into_response())
+}
+
+// Background job deleting the given measurements and their files
+async fn run_batch_delete(state: Arc<AppState>, job_id: String, ids: Vec<String>) {
+    let mut report = BatchDeleteReport::default();
+
+    for (processed, id) in ids.iter().enumerate() {
+        let skip_reason = take_measurement(&state, &mut state.measurements.lock(), id).err();
+
+        if let Some(reason) = skip_reason {
+            report.skipped.push(SkippedMeasurement { id: id.clone(), reason });
+        } else {
+            let task_id = id.clone();
+            match tokio::task::spawn_blocking(move || artifacts::remove_all(&task_id)).await {
+                Ok(removal) => report.file_errors.extend(removal.errors),
+                Err(e) => report.file_errors.push(format!("{}: {}", id, e)),
+            }
+            state.cache.forget(id);
+            report.deleted += 1;
+        }
+
+        state.jobs.update(&job_id, processed + 1, json!(report));
+        if (processed + 1) % 100 == 0 {
+            state.audit.record(
+                "batch_delete_progress",
+                json!({ "job_id": job_id, "processed": processed + 1, "deleted": report.deleted }),
+            );
+        }
+    }
+
+    state.audit.record(
+        "batch_delete_finished",
+        json!({ "job_id": job_id, "deleted": report.deleted, "skipped": report.skipped }),
+    );
+    state.jobs.finish(&job_id, json!(report));
+}
+
+// Remove a measurement unless its files are still in use, returning why not.
+// Measurements being proved keep their files until the pipeline is done.
+pub fn take_measurement(
+    state: &AppState,
+    measurements: &mut StoreGuard<'_>,
+    id: &str,
+) -> Result<Measurement, &'static str> {
+    match measurements.get(id).map(|m| &m.status) {
+        None => return Err("already deleted"),
+        Some(ProofStatus::Processing) => return Err("processing"),
+        Some(ProofStatus::Pending) if !state.queue.remove(id) => return Err("processing"),
+        Some(_) => {}
+    }
+    let m = measurements.remove(id).ok_or("already deleted")?;
+    state.deadlines.forget(id);
+    let owner = tenants::owner_key(m.tenant.as_deref(), &m.owner);
+    if let Some(external_id) = &m.external_id {
+        state.external_ids.remove(&owner, external_id);
+    }
+    if let Some(key) = &m.idempotency_key {
+        state.idempotency_keys.remove(&owner, key);
+    }
+    Ok(m)
+}
+
+#[derive(Serialize)]
+pub struct DeletedMeasurement {
+    #[serde(flatten)]
+    measurement: Measurement,
+    // Files removed and those that could not be, which stay behind
+    files: RemovalReport,
+}
+
+// Handler deleting a single measurement and its files, for its submitter or
+// an admin
+pub async fn delete_measurement(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Submitter>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    Path(id): Path<String>,
+) -> Result<Json<DeletedMeasurement>, (StatusCode, String)> {
+    let measurement = {
+        let mut measurements = state.measurements.lock();
+        access::managed(
+            measurements.get(&id),
+            &caller,
+            remote.ip(),
+            &id,
+            Managers::SubmitterOrAdmin,
+            "delete a measurement",
+        )?;
+        take_measurement(&state, &mut measurements, &id).map_err(|reason| {
+            (StatusCode::CONFLICT, format!("Measurement {} cannot be deleted: {}", id, reason))
+        })?
+    };
+
+    let task_id = id.clone();
+    let files = tokio::task::spawn_blocking(move || artifacts::remove_all(&task_id))
+        .await
+        .unwrap_or_else(|e| RemovalReport { removed: Vec::new(), errors: vec![e.to_string()] });
+    state.cache.forget(&id);
+    if !files.errors.is_empty() {
+        println!("Measurement {} deleted, but not all of its files: {:?}", id, files.errors);
+    }
+    state.audit.record(
+        "measurement_deleted",
+        json!({ "id": id, "caller": caller.name, "removed": files.removed, "errors": files.errors }),
+    );
+
+    Ok(Json(DeletedMeasurement { measurement, files }))
+}
@@ -0,0 +1,86 @@
+// Measurements of the angle at a bend, between three points.
+//
+// A submission's measurementType is length, the default, or angle. An angle
+// measurement also sends the point where the object bends as midPoint, which
+// is required with angle and refused without it, and cannot be a polyline.
+// The mid point is scaled like the others and kept in mid_point, and must
+// differ from both end points, as no angle is defined at a point without
+// extent.
+//
+// Angle measurements are proved with ZKHOTDOG_ANGLE, which takes the vectors
+// from the mid point to the start and end point and proves the numerator and
+// squared denominator of the cosine of the angle between them. Everything
+// else about the measurement, its pipeline and its status, is the same as for
+// a length: its points are the start, mid and end point in order, its length
+// runs along both arms, and only its inputs and circuit differ.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+use crate::{Point3D, circuit, polyline};
+
+// What a measurement attests about its points
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MeasurementType {
+    #[default]
+    Length,
+    Angle,
+}
+
+impl FromStr for MeasurementType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "length" => Ok(MeasurementType::Length),
+            "angle" => Ok(MeasurementType::Angle),
+            _ => Err(format!("unknown measurement type '{}', expected length or angle", s)),
+        }
+    }
+}
+
+impl fmt::Display for MeasurementType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeasurementType::Length => write!(f, "length"),
+            MeasurementType::Angle => write!(f, "angle"),
+        }
+    }
+}
+
+// Every point of a measurement in order: the start, mid and end point of an
+// angle, or those of a length
+pub fn path(
+    start: &Point3D,
+    mid: Option<&Point3D>,
+    end: &Point3D,
+    points: &[Point3D],
+) -> Vec<Point3D> {
+    match mid {
+        Some(mid) => vec![start.clone(), mid.clone(), end.clone()],
+        None => polyline::path(start, end, points),
+    }
+}
+
+// Circuit a new measurement of the type is proved with, None for the default one
+pub fn circuit_for(measurement_type: MeasurementType, points: &[Point3D]) -> Option<String> {
+    match measurement_type {
+        MeasurementType::Angle => Some(circuit::ZKHOTDOG_ANGLE.version.to_string()),
+        MeasurementType::Length => polyline::circuit_for(points),
+    }
+}
+
+// Check that the scaled mid point of an angle is apart from both end points
+pub fn check_mid_point(start: &Point3D, mid: &Point3D, end: &Point3D) -> Result<(), String> {
+    for (point, name) in [(start, "startPoint"), (end, "endPoint")] {
+        if point == mid {
+            return Err(format!(
+                "midPoint coincides with {}, so the measurement has no angle",
+                name
+            ));
+        }
+    }
+    Ok(())
+}
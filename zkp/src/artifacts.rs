@@ -0,0 +1,279 @@
+// Bookkeeping for the files produced for each measurement.
+//
+// The pipeline records an artifact whenever it writes or removes the backing
+// file, so status responses can report what is downloadable without probing
+// the filesystem or making clients issue extra requests.
+//
+// Files of a tenant's measurements live below uploads/{tenant}/ and
+// proofs/{tenant}/. Paths are derived from the measurement id all over the
+// pipeline, so the tenant of each id is registered here once at submission
+// instead of being passed along with it. A submission claims the tenant of
+// its new id and releases it again if it is rejected before the measurement
+// is stored, so only stored measurements keep an entry.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, hash_map::Entry},
+    fs, io,
+    sync::{LazyLock, RwLock},
+};
+
+// Tenant of every measurement stored under a tenant prefix
+static TENANT_PREFIXES: LazyLock<RwLock<HashMap<String, String>>> = LazyLock::new(Default::default);
+
+// Store the files of a measurement under its tenant's directories
+pub fn assign_tenant(id: &str, tenant: &str) {
+    TENANT_PREFIXES.write().unwrap().insert(id.to_string(), tenant.to_string());
+}
+
+// Assign the tenant of a new measurement, unless its id already has one
+pub fn claim_tenant(id: &str, tenant: &str) -> bool {
+    match TENANT_PREFIXES.write().unwrap().entry(id.to_string()) {
+        Entry::Vacant(entry) => {
+            entry.insert(tenant.to_string());
+            true
+        }
+        Entry::Occupied(_) => false,
+    }
+}
+
+// Forget the tenant of a measurement that is gone or was never stored
+pub fn release_tenant(id: &str) {
+    TENANT_PREFIXES.write().unwrap().remove(id);
+}
+
+// Location of a measurement relative to uploads/ and proofs/, which is also
+// what the verification client takes to find the proof
+pub fn storage_key(id: &str) -> String {
+    match TENANT_PREFIXES.read().unwrap().get(id) {
+        Some(tenant) => format!("{}/{}", tenant, id),
+        None => id.to_string(),
+    }
+}
+
+// Directory holding the image of a measurement and its variants
+fn uploads_dir(id: &str) -> String {
+    match TENANT_PREFIXES.read().unwrap().get(id) {
+        Some(tenant) => format!("uploads/{}", tenant),
+        None => "uploads".to_string(),
+    }
+}
+
+// Every file the server may hold for a measurement
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Artifact {
+    Image,
+    // Second photo sent with the image, such as a close-up
+    SecondImage,
+    // Depth map sent with the image, as PNG or raw float16 depths
+    DepthMap,
+    Input,
+    Proof,
+    PublicSignals,
+    Attestation,
+    // Downscaled JPEG whose longest side is the given number of pixels
+    Thumbnail(u32),
+    // WebP re-encoding of the full image
+    Webp,
+    // Payload the native submitter would send to zkVerify (debug mode only)
+    SubmissionPayload,
+    // Output of the proving tools and the verification client
+    Log,
+}
+
+impl Artifact {
+    // Artifacts written by the proving pipeline inside proofs/{id}
+    pub const PROOF_OUTPUTS: [Artifact; 4] =
+        [Artifact::Input, Artifact::Proof, Artifact::PublicSignals, Artifact::Log];
+
+    // Location of the artifact on disk for the given measurement
+    pub fn path(self, id: &str) -> String {
+        match self {
+            Artifact::Image => format!("{}/{}.jpg", uploads_dir(id), id),
+            Artifact::SecondImage => format!("{}/{}_2.jpg", uploads_dir(id), id),
+            Artifact::DepthMap => format!("{}/{}_depth.bin", uploads_dir(id), id),
+            Artifact::Input => format!("{}/input.json", proof_dir(id)),
+            Artifact::Proof => format!("{}/proof.json", proof_dir(id)),
+            Artifact::PublicSignals => format!("{}/public.json", proof_dir(id)),
+            Artifact::Attestation => format!("{}/attestation.json", proof_dir(id)),
+            Artifact::Thumbnail(size) => format!("{}/{}_thumb_{}.jpg", uploads_dir(id), id, size),
+            Artifact::Webp => format!("{}/{}.webp", uploads_dir(id), id),
+            Artifact::SubmissionPayload => format!("{}/submission_payload.json", proof_dir(id)),
+            Artifact::Log => format!("{}/pipeline.log", proof_dir(id)),
+        }
+    }
+}
+
+// Directory holding all proving artifacts of a measurement
+pub fn proof_dir(id: &str) -> String {
+    format!("proofs/{}", storage_key(id))
+}
+
+// Remove everything an attempt to prove a measurement left in its proof
+// directory but input.json
+pub fn clean_proof_dir(id: &str) -> Result<(), String> {
+    let dir = proof_dir(id);
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(format!("Failed to read {}: {}", dir, e)),
+    };
+    let input = Artifact::Input.path(id);
+    for entry in entries {
+        let path = entry.map_err(|e| format!("Failed to read {}: {}", dir, e))?.path();
+        if path.to_str() == Some(input.as_str()) {
+            continue;
+        }
+        let removed =
+            if path.is_dir() { fs::remove_dir_all(&path) } else { fs::remove_file(&path) };
+        match removed {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("Failed to remove {}: {}", path.display(), e)),
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, JsonSchema)]
+pub struct ArtifactInfo {
+    pub available: bool,
+    pub bytes: u64,
+}
+
+impl ArtifactInfo {
+    // Current state of the file at the given path
+    fn stat(path: &str) -> Self {
+        match fs::metadata(path) {
+            Ok(meta) if meta.is_file() => ArtifactInfo { available: true, bytes: meta.len() },
+            _ => ArtifactInfo::default(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, JsonSchema)]
+pub struct Artifacts {
+    pub image: ArtifactInfo,
+    #[serde(default, skip_serializing_if = "is_unavailable")]
+    pub image2: ArtifactInfo,
+    pub input: ArtifactInfo,
+    pub proof: ArtifactInfo,
+    #[serde(rename = "publicSignals")]
+    pub public_signals: ArtifactInfo,
+    pub attestation: ArtifactInfo,
+    // Thumbnails keyed by their size in pixels
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub thumbnails: BTreeMap<u32, ArtifactInfo>,
+    #[serde(default)]
+    pub webp: ArtifactInfo,
+    #[serde(default, rename = "submissionPayload", skip_serializing_if = "is_unavailable")]
+    pub submission_payload: ArtifactInfo,
+    #[serde(default, rename = "depthMap", skip_serializing_if = "is_unavailable")]
+    pub depth_map: ArtifactInfo,
+    #[serde(default)]
+    pub log: ArtifactInfo,
+}
+
+impl Artifacts {
+    fn slot(&mut self, artifact: Artifact) -> &mut ArtifactInfo {
+        match artifact {
+            Artifact::Image => &mut self.image,
+            Artifact::SecondImage => &mut self.image2,
+            Artifact::DepthMap => &mut self.depth_map,
+            Artifact::Input => &mut self.input,
+            Artifact::Proof => &mut self.proof,
+            Artifact::PublicSignals => &mut self.public_signals,
+            Artifact::Attestation => &mut self.attestation,
+            Artifact::Thumbnail(size) => self.thumbnails.entry(size).or_default(),
+            Artifact::Webp => &mut self.webp,
+            Artifact::SubmissionPayload => &mut self.submission_payload,
+            Artifact::Log => &mut self.log,
+        }
+    }
+
+    // Refresh the entry for an artifact after the pipeline touched its file
+    pub fn record(&mut self, id: &str, artifact: Artifact) {
+        *self.slot(artifact) = ArtifactInfo::stat(&artifact.path(id));
+    }
+
+    // Compact form for listings: available artifacts mapped to their size
+    pub fn compact(&self) -> BTreeMap<String, u64> {
+        let fixed = [
+            ("image", &self.image),
+            ("image2", &self.image2),
+            ("input", &self.input),
+            ("proof", &self.proof),
+            ("publicSignals", &self.public_signals),
+            ("attestation", &self.attestation),
+            ("webp", &self.webp),
+            ("submissionPayload", &self.submission_payload),
+            ("depthMap", &self.depth_map),
+            ("log", &self.log),
+        ]
+        .into_iter()
+        .map(|(name, info)| (name.to_string(), info));
+        let thumbnails =
+            self.thumbnails.iter().map(|(size, info)| (format!("thumbnail{}", size), info));
+
+        fixed
+            .chain(thumbnails)
+            .filter(|(_, info)| info.available)
+            .map(|(name, info)| (name, info.bytes))
+            .collect()
+    }
+
+    // Refresh several entries at once
+    pub fn record_all(&mut self, id: &str, artifacts: &[Artifact]) {
+        for artifact in artifacts {
+            self.record(id, *artifact);
+        }
+    }
+}
+
+// The second image, the depth map and the debug-only submission payload are
+// omitted from responses when absent
+fn is_unavailable(info: &ArtifactInfo) -> bool {
+    !info.available
+}
+
+// Outcome of removing the files of a deleted measurement
+#[derive(Debug, Serialize, Default)]
+pub struct RemovalReport {
+    pub removed: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+// Remove every file belonging to a measurement: the images and variants in
+// uploads/ and the whole proofs/{id} directory. Missing files are not errors.
+pub fn remove_all(id: &str) -> RemovalReport {
+    let mut report = RemovalReport::default();
+
+    let image_prefixes = [format!("{}.", id), format!("{}_", id)];
+    let uploads = uploads_dir(id);
+    match fs::read_dir(&uploads) {
+        Ok(entries) => {
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if image_prefixes.iter().any(|prefix| name.starts_with(prefix)) {
+                    let path = format!("{}/{}", uploads, name);
+                    match fs::remove_file(&path) {
+                        Ok(()) => report.removed.push(path),
+                        Err(e) => report.errors.push(format!("{}: {}", path, e)),
+                    }
+                }
+            }
+        }
+        Err(e) => report.errors.push(format!("{}: {}", uploads, e)),
+    }
+
+    let dir = proof_dir(id);
+    match fs::remove_dir_all(&dir) {
+        Ok(()) => report.removed.push(dir),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => report.errors.push(format!("{}: {}", dir, e)),
+    }
+
+    release_tenant(id);
+    report
+}
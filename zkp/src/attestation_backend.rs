@@ -0,0 +1,366 @@
+// Networks attesting the proofs of measurements.
+//
+// The pipeline submits a proof and waits for its attestation through an
+// AttestationBackend only, so another proof network can be added next to
+// zkVerify without touching it. A backend submits a proof and answers either
+// the attestation or a receipt naming the attestation to wait for, looks
+// awaited attestations up for the attestation poller, and verifies an
+// attestation before it is attached to a measurement.
+//
+// ATTESTATION_BACKEND picks the backend of a deployment, and
+// ATTESTATION_BACKEND_BY_CIRCUIT overrides it per circuit version, so proofs
+// of a new circuit can go to another network than those of older ones:
+//   - zkverify submits with dist/verify_client.js, in turn per signing account
+//     (signer.rs) and failing over between RPC endpoints (rpc.rs). With
+//     ATTESTATION_POLLING the client returns once its transaction is
+//     finalized, leaving pending_attestation.json for the poller;
+//   - local fabricates an attestation of a single leaf for every proof without
+//     any network, for development and tests. Production mode refuses it.
+// A measurement records the backend it was submitted to as
+// attestation_backend, which the poller also asks after a restart.
+
+use chrono::Utc;
+use futures_util::{FutureExt, future::BoxFuture};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    env, fmt, fs,
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    AppState, AttestationData,
+    artifacts::{self, Artifact},
+    circuit,
+    config::Config,
+    signer,
+};
+
+// Exit status of the zkVerify client when it could not connect to the RPC endpoint
+const EXIT_UNREACHABLE: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    ZkVerify,
+    // Fabricated attestations, for development and tests
+    Local,
+}
+
+impl BackendKind {
+    pub const ALL: [BackendKind; 2] = [BackendKind::ZkVerify, BackendKind::Local];
+}
+
+impl FromStr for BackendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "zkverify" => Ok(BackendKind::ZkVerify),
+            "local" => Ok(BackendKind::Local),
+            other => {
+                Err(format!("unknown attestation backend '{}', expected zkverify or local", other))
+            }
+        }
+    }
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendKind::ZkVerify => write!(f, "zkverify"),
+            BackendKind::Local => write!(f, "local"),
+        }
+    }
+}
+
+// One entry of ATTESTATION_BACKEND_BY_CIRCUIT, written as version:backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitBackend {
+    pub version: &'static str,
+    pub backend: BackendKind,
+}
+
+impl FromStr for CircuitBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (version, backend) = s.split_once(':').ok_or_else(|| {
+            format!("'{}' should be a circuit version and a backend, like zkhotdog-v1:zkverify", s)
+        })?;
+        let circuit = circuit::find(version.trim())
+            .ok_or_else(|| format!("unknown circuit version '{}'", version.trim()))?;
+        Ok(CircuitBackend { version: circuit.version, backend: backend.trim().parse()? })
+    }
+}
+
+// The proof of a measurement, as written to proofs/{id}
+pub struct ProofArtifacts<'a> {
+    pub id: &'a str,
+    pub tenant: Option<&'a str>,
+}
+
+// Attestation a submitted proof will be part of, awaited by the poller
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingAttestation {
+    #[serde(rename = "attestationId")]
+    pub attestation_id: u64,
+    #[serde(rename = "leafDigest")]
+    pub leaf_digest: String,
+}
+
+impl PendingAttestation {
+    // What the submission left in proofs/{id}/pending_attestation.json
+    pub fn load(id: &str) -> Result<Self, String> {
+        let path = format!("{}/pending_attestation.json", artifacts::proof_dir(id));
+        let content = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+        serde_json::from_str(&content).map_err(|e| e.to_string())
+    }
+}
+
+// A leaf as handed to the zkVerify client in --poll mode
+#[derive(Serialize)]
+struct AwaitedLeaf<'a> {
+    id: &'a str,
+    #[serde(rename = "attestationId")]
+    attestation_id: u64,
+    #[serde(rename = "leafDigest")]
+    leaf_digest: &'a str,
+}
+
+// What a backend answers for a submitted proof
+pub enum Receipt {
+    Attested(AttestationData),
+    // The proof is accepted and its attestation not published yet
+    Awaiting(PendingAttestation),
+}
+
+#[derive(Deserialize, Default)]
+pub struct PollResults {
+    #[serde(rename = "rpcCalls")]
+    pub rpc_calls: u64,
+    // Attestations found, by measurement
+    pub published: HashMap<String, AttestationData>,
+}
+
+pub trait AttestationBackend: Send + Sync {
+    fn kind(&self) -> BackendKind;
+
+    // Submit a measurement's proof
+    fn submit<'a>(
+        &'a self,
+        state: &'a AppState,
+        proof: ProofArtifacts<'a>,
+    ) -> BoxFuture<'a, Result<Receipt, String>>;
+
+    // Look up the attestations of submitted proofs, answering those already
+    // published. The poller hands over every due measurement at once, so a
+    // backend can look them up together.
+    fn poll<'a>(
+        &'a self,
+        state: &'a AppState,
+        awaited: &'a [(String, PendingAttestation)],
+    ) -> BoxFuture<'a, Result<PollResults, String>>;
+
+    // Whether an attestation is one this backend can have produced
+    fn verify(&self, attestation: &AttestationData) -> bool;
+}
+
+// Whether a node of a merkle path is a 0x-prefixed 32-byte hex string
+pub fn is_merkle_node(node: &str) -> bool {
+    node.strip_prefix("0x")
+        .is_some_and(|hex| hex.len() == 64 && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+// Whether every element of a merkle path is a node
+pub fn is_merkle_path(path: &[String]) -> bool {
+    path.iter().all(|node| is_merkle_node(node))
+}
+
+// Whether an attestation places its leaf within its tree
+fn is_well_formed(attestation: &AttestationData) -> bool {
+    attestation.index < attestation.leaf_count && is_merkle_path(&attestation.merkle_path)
+}
+
+pub struct ZkVerify;
+
+impl AttestationBackend for ZkVerify {
+    fn kind(&self) -> BackendKind {
+        BackendKind::ZkVerify
+    }
+
+    fn submit<'a>(
+        &'a self,
+        state: &'a AppState,
+        proof: ProofArtifacts<'a>,
+    ) -> BoxFuture<'a, Result<Receipt, String>> {
+        async move {
+            signer::submit(state, proof.id, proof.tenant).await?;
+            // A deferring client leaves the attestation to wait for instead
+            if state.config.attestation_polling {
+                match PendingAttestation::load(proof.id) {
+                    Ok(pending) => return Ok(Receipt::Awaiting(pending)),
+                    Err(e) => println!("No pending attestation for {}: {}", proof.id, e),
+                }
+            }
+            let path = Artifact::Attestation.path(proof.id);
+            let content = fs::read_to_string(&path)
+                .map_err(|e| format!("The client left no attestation at {}: {}", path, e))?;
+            serde_json::from_str(&content)
+                .map(Receipt::Attested)
+                .map_err(|e| format!("Invalid attestation at {}: {}", path, e))
+        }
+        .boxed()
+    }
+
+    // One run of the client in --poll mode on the preferred RPC endpoint
+    fn poll<'a>(
+        &'a self,
+        state: &'a AppState,
+        awaited: &'a [(String, PendingAttestation)],
+    ) -> BoxFuture<'a, Result<PollResults, String>> {
+        async move {
+            let leaves: Vec<AwaitedLeaf> = awaited
+                .iter()
+                .map(|(id, pending)| AwaitedLeaf {
+                    id,
+                    attestation_id: pending.attestation_id,
+                    leaf_digest: &pending.leaf_digest,
+                })
+                .collect();
+            let dir = env::temp_dir();
+            let in_path = dir.join(format!("zkhotdog-attestations-{}.json", std::process::id()));
+            let out_path =
+                dir.join(format!("zkhotdog-attestations-{}.out.json", std::process::id()));
+            let json = serde_json::to_vec(&leaves).map_err(|e| e.to_string())?;
+            fs::write(&in_path, json)
+                .map_err(|e| format!("Cannot write {}: {}", in_path.display(), e))?;
+            let _ = fs::remove_file(&out_path);
+
+            let endpoint = *state.rpc.order().first().ok_or("No zkVerify RPC endpoint")?;
+            let mut command = tokio::process::Command::new("node");
+            command.arg("dist/verify_client.js").arg("--poll").arg(&in_path).arg(&out_path);
+            if let Some(url) = state.rpc.url(endpoint) {
+                command.env("ZK_VERIFY_RPC_URL", url);
+            }
+            let output =
+                command.output().await.map_err(|e| format!("Failed to run node client: {}", e))?;
+            let _ = fs::remove_file(&in_path);
+            if output.status.code() == Some(EXIT_UNREACHABLE) {
+                let error = format!("Node client exited with {}", output.status);
+                state.rpc.unreachable(endpoint, &error);
+                return Err(error);
+            }
+            if !output.status.success() {
+                return Err(format!(
+                    "Node client exited with {}: {}",
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+            let content = fs::read_to_string(&out_path)
+                .map_err(|e| format!("Cannot read {}: {}", out_path.display(), e))?;
+            let _ = fs::remove_file(&out_path);
+            serde_json::from_str(&content).map_err(|e| format!("Invalid poll results: {}", e))
+        }
+        .boxed()
+    }
+
+    fn verify(&self, attestation: &AttestationData) -> bool {
+        is_well_formed(attestation)
+    }
+}
+
+// Attests every proof at once with an attestation of its own
+pub struct Local {
+    // Next attestation ID, starting from the time the server started in
+    // milliseconds so IDs are not handed out twice across restarts
+    next_id: AtomicU64,
+}
+
+impl AttestationBackend for Local {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Local
+    }
+
+    fn submit<'a>(
+        &'a self,
+        _state: &'a AppState,
+        proof: ProofArtifacts<'a>,
+    ) -> BoxFuture<'a, Result<Receipt, String>> {
+        async move {
+            // Only proofs that were written are attested
+            for artifact in [Artifact::Proof, Artifact::PublicSignals] {
+                let path = artifact.path(proof.id);
+                fs::metadata(&path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+            }
+            let attestation = AttestationData {
+                attestation_id: self.next_id.fetch_add(1, Ordering::Relaxed),
+                merkle_path: Vec::new(),
+                leaf_count: 1,
+                index: 0,
+            };
+            println!(
+                "Local backend attested proof {} in attestation {}",
+                proof.id, attestation.attestation_id
+            );
+            Ok(Receipt::Attested(attestation))
+        }
+        .boxed()
+    }
+
+    // Nothing is ever awaited
+    fn poll<'a>(
+        &'a self,
+        _state: &'a AppState,
+        _awaited: &'a [(String, PendingAttestation)],
+    ) -> BoxFuture<'a, Result<PollResults, String>> {
+        async move { Ok(PollResults::default()) }.boxed()
+    }
+
+    fn verify(&self, attestation: &AttestationData) -> bool {
+        is_well_formed(attestation) && attestation.leaf_count == 1
+    }
+}
+
+// Backend of a circuit version, given the deployment's and the overrides
+pub fn select(default: BackendKind, by_circuit: &[CircuitBackend], version: &str) -> BackendKind {
+    by_circuit.iter().find(|entry| entry.version == version).map_or(default, |entry| entry.backend)
+}
+
+// The backends of this deployment and which circuit uses which
+pub struct Backends {
+    zkverify: ZkVerify,
+    local: Local,
+    default: BackendKind,
+    by_circuit: Vec<CircuitBackend>,
+}
+
+impl Backends {
+    pub fn new(config: &Config) -> Self {
+        Backends {
+            zkverify: ZkVerify,
+            local: Local { next_id: AtomicU64::new(Utc::now().timestamp_millis() as u64) },
+            default: config.attestation_backend,
+            by_circuit: config.attestation_backend_by_circuit.clone(),
+        }
+    }
+
+    pub fn get(&self, kind: BackendKind) -> &dyn AttestationBackend {
+        match kind {
+            BackendKind::ZkVerify => &self.zkverify,
+            BackendKind::Local => &self.local,
+        }
+    }
+
+    // Backend the proofs of a circuit version are submitted to
+    pub fn kind_for(&self, version: &str) -> BackendKind {
+        select(self.default, &self.by_circuit, version)
+    }
+
+    pub fn for_circuit(&self, version: &str) -> &dyn AttestationBackend {
+        self.get(self.kind_for(version))
+    }
+}
@@ -0,0 +1,405 @@
+// Polling zkVerify for the attestations of submitted proofs.
+//
+// Left to itself, the verification client keeps a session open per proof
+// until the attestation containing it is published, which ties up a
+// connection for every proof awaiting attestation. With ATTESTATION_POLLING
+// on, the client stops once its transaction is finalized and leaves
+// proofs/{id}/pending_attestation.json naming the attestation and its leaf.
+// The measurement stays Processing while a single poller looks the awaited
+// attestations up for all measurements together.
+//
+// Every round the poller takes the due measurements, most recently submitted
+// first and at most ATTESTATION_POLL_BATCH of them, and hands them to the
+// attestation backend they were submitted to in one call (see
+// attestation_backend.rs). For zkVerify that is one run of the client in
+// --poll mode, which uses a single session and asks in order of attestation
+// ID, stopping at the first attestation not yet published, as later ones
+// cannot be published either. A single proof or thousands waiting thus cost
+// one connection per round and one call per published leaf plus one. A
+// published attestation the backend does not verify fails its measurement.
+//
+// A measurement found unpublished is not asked about again for the poll
+// interval, doubled with every miss up to ATTESTATION_BACKOFF_MAX_SECS. Both
+// the interval between rounds and these delays are jittered by a fifth, so
+// measurements submitted together spread out. One still unpublished after
+// ATTESTATION_MAX_WAIT_SECS fails. Delays and waits are measured on the
+// monotonic clock, so stepping the host clock neither fails every awaited
+// measurement at once nor leaves them unasked. Rounds, RPC calls, throughput
+// and the distribution of waits are reported in /admin/stats.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, VecDeque},
+    fs,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{sync::Notify, time::Instant};
+use uuid::Uuid;
+
+use crate::{
+    AppState, AttestationData, FailureClass, ProofStatus,
+    artifacts::Artifact,
+    attestation_backend::{BackendKind, PendingAttestation},
+    clock::Clock,
+    commitment,
+    config::Config,
+    events::EventKind,
+    history::Source,
+    pipeline,
+    plan::{self, StageState},
+};
+
+// Waits of recently published attestations kept for the distribution
+const RECENT_WAITS: usize = 1000;
+// Share by which delays are randomly lengthened or shortened
+const JITTER: f64 = 0.2;
+// Window of the recent throughput in the admin stats
+const HOUR: Duration = Duration::from_secs(3600);
+
+struct Awaiting {
+    backend: BackendKind,
+    pending: PendingAttestation,
+    submitted_at: Instant,
+    misses: u32,
+    next_check: Instant,
+}
+
+// Seconds measurements waited for their attestation, over the recent ones
+#[derive(Debug, Serialize)]
+pub struct WaitDistribution {
+    samples: usize,
+    p50: f64,
+    p90: f64,
+    p99: f64,
+    max: f64,
+}
+
+// Attestation poller figures for the admin stats endpoint
+#[derive(Debug, Serialize)]
+pub struct AttestationStats {
+    awaiting: usize,
+    rounds: u64,
+    // Rounds the client could not complete
+    failed_rounds: u64,
+    rpc_calls: u64,
+    published: u64,
+    // Measurements failed for waiting longer than ATTESTATION_MAX_WAIT_SECS
+    timed_out: u64,
+    // Attestations found in the last hour
+    published_last_hour: usize,
+    last_round_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wait_seconds: Option<WaitDistribution>,
+}
+
+#[derive(Default)]
+struct Totals {
+    rounds: u64,
+    failed_rounds: u64,
+    rpc_calls: u64,
+    published: u64,
+    timed_out: u64,
+    last_round_at: Option<DateTime<Utc>>,
+    // When recent attestations were found and how long each was awaited
+    recent: VecDeque<(Instant, f64)>,
+}
+
+pub struct AttestationPoller {
+    interval: Duration,
+    batch: usize,
+    backoff_max: Duration,
+    max_wait: Duration,
+    awaiting: Mutex<HashMap<String, Awaiting>>,
+    notify: Notify,
+    totals: Mutex<Totals>,
+    clock: Clock,
+}
+
+impl AttestationPoller {
+    pub fn new(config: &Config) -> Self {
+        AttestationPoller {
+            interval: Duration::from_secs(config.attestation_poll_interval_secs),
+            batch: config.attestation_poll_batch,
+            backoff_max: Duration::from_secs(config.attestation_backoff_max_secs),
+            max_wait: Duration::from_secs(config.attestation_max_wait_secs),
+            awaiting: Mutex::default(),
+            notify: Notify::new(),
+            totals: Mutex::default(),
+            clock: Clock::new(config),
+        }
+    }
+
+    // Start waiting for the attestation of a measurement the backend accepted
+    pub fn register(&self, id: &str, backend: BackendKind, pending: PendingAttestation) {
+        let now = Instant::now();
+        self.awaiting.lock().unwrap().insert(
+            id.to_string(),
+            Awaiting {
+                backend,
+                pending,
+                submitted_at: now,
+                misses: 0,
+                next_check: now + jittered(self.interval),
+            },
+        );
+        self.notify.notify_one();
+    }
+
+    // Stop waiting for the attestation of a cancelled measurement
+    pub fn forget(&self, id: &str) {
+        self.awaiting.lock().unwrap().remove(id);
+    }
+
+    pub fn stats(&self) -> AttestationStats {
+        let totals = self.totals.lock().unwrap();
+        let mut waits: Vec<f64> = totals.recent.iter().map(|(_, wait)| *wait).collect();
+        waits.sort_by(f64::total_cmp);
+        let percentile = |p: f64| waits[((waits.len() - 1) as f64 * p).round() as usize];
+        AttestationStats {
+            awaiting: self.awaiting.lock().unwrap().len(),
+            rounds: totals.rounds,
+            failed_rounds: totals.failed_rounds,
+            rpc_calls: totals.rpc_calls,
+            published: totals.published,
+            timed_out: totals.timed_out,
+            published_last_hour: totals.recent.iter().filter(|(at, _)| at.elapsed() < HOUR).count(),
+            last_round_at: totals.last_round_at,
+            wait_seconds: (!waits.is_empty()).then(|| WaitDistribution {
+                samples: waits.len(),
+                p50: percentile(0.5),
+                p90: percentile(0.9),
+                p99: percentile(0.99),
+                max: waits[waits.len() - 1],
+            }),
+        }
+    }
+
+    // Measurements due for a check, most recently submitted first, up to a batch
+    fn due(&self, now: Instant) -> Vec<(String, BackendKind, PendingAttestation)> {
+        let awaiting = self.awaiting.lock().unwrap();
+        let mut due: Vec<(&String, &Awaiting)> =
+            awaiting.iter().filter(|(_, a)| a.next_check <= now).collect();
+        due.sort_by_key(|(_, a)| Reverse(a.submitted_at));
+        due.into_iter()
+            .take(self.batch)
+            .map(|(id, a)| (id.clone(), a.backend, a.pending.clone()))
+            .collect()
+    }
+
+    // Time until the next measurement is due, None when none is awaited
+    fn until_next(&self, now: Instant) -> Option<Duration> {
+        let awaiting = self.awaiting.lock().unwrap();
+        let next = awaiting.values().map(|a| a.next_check).min()?;
+        Some(next.saturating_duration_since(now))
+    }
+}
+
+// A delay lengthened or shortened at random by up to JITTER
+fn jittered(delay: Duration) -> Duration {
+    let random = (Uuid::new_v4().as_u128() % 1000) as f64 / 1000.0;
+    delay.mul_f64(1.0 - JITTER + 2.0 * JITTER * random)
+}
+
+// The single poller task, idle while no measurement awaits an attestation
+pub async fn poller(state: Arc<AppState>) {
+    if !state.config.attestation_polling {
+        return;
+    }
+    let poller = &state.attestations;
+    loop {
+        match poller.until_next(Instant::now()) {
+            None => poller.notify.notified().await,
+            Some(wait) if !wait.is_zero() => {
+                // A new registration may be due earlier than the current next
+                tokio::select! {
+                    _ = tokio::time::sleep(wait) => {}
+                    _ = poller.notify.notified() => {}
+                }
+            }
+            Some(_) => {
+                round(&state).await;
+                // Rounds are spaced out even while more is due
+                tokio::time::sleep(jittered(poller.interval)).await;
+            }
+        }
+    }
+}
+
+// Look up the due attestations, in one call of each backend they await
+async fn round(state: &AppState) {
+    let due = state.attestations.due(Instant::now());
+    for kind in BackendKind::ALL {
+        let awaited: Vec<(String, PendingAttestation)> = due
+            .iter()
+            .filter(|(_, backend, _)| *backend == kind)
+            .map(|(id, _, pending)| (id.clone(), pending.clone()))
+            .collect();
+        if !awaited.is_empty() {
+            poll(state, kind, &awaited).await;
+        }
+    }
+}
+
+async fn poll(state: &AppState, kind: BackendKind, due: &[(String, PendingAttestation)]) {
+    let poller = &state.attestations;
+    let backend = state.attestation_backends.get(kind);
+    let mut results = match backend.poll(state, due).await {
+        Ok(results) => results,
+        Err(e) => {
+            println!("Attestation poll of {} measurements failed: {}", due.len(), e);
+            let mut totals = poller.totals.lock().unwrap();
+            totals.rounds += 1;
+            totals.failed_rounds += 1;
+            totals.last_round_at = Some(poller.clock.now());
+            drop(totals);
+            // Nothing was learned, so every polled measurement backs off
+            back_off(state, due.iter().map(|(id, _)| id.as_str()));
+            return;
+        }
+    };
+
+    let now = Instant::now();
+    let mut published = Vec::new();
+    {
+        let mut awaiting = poller.awaiting.lock().unwrap();
+        let mut totals = poller.totals.lock().unwrap();
+        totals.rounds += 1;
+        totals.rpc_calls += results.rpc_calls;
+        totals.last_round_at = Some(poller.clock.now());
+        for (id, _) in due {
+            if results.published.contains_key(id)
+                && let Some(a) = awaiting.remove(id)
+            {
+                let wait = (now - a.submitted_at).as_secs_f64();
+                totals.published += 1;
+                if totals.recent.len() == RECENT_WAITS {
+                    totals.recent.pop_front();
+                }
+                totals.recent.push_back((now, wait));
+                published.push(id.clone());
+            }
+        }
+    }
+    println!(
+        "Attestation poll: {} of {} measurements published, {} RPC calls",
+        published.len(),
+        due.len(),
+        results.rpc_calls
+    );
+
+    for id in &published {
+        if let Some(attestation) = results.published.remove(id) {
+            complete(state, id, kind, attestation);
+        }
+    }
+    back_off(
+        state,
+        due.iter().map(|(id, _)| id.as_str()).filter(|id| !published.iter().any(|p| p == id)),
+    );
+}
+
+// Schedule the next check of measurements still awaiting their attestation,
+// failing those that waited too long
+fn back_off<'a>(state: &AppState, ids: impl Iterator<Item = &'a str>) {
+    let poller = &state.attestations;
+    let now = Instant::now();
+    let mut timed_out = Vec::new();
+    {
+        let mut awaiting = poller.awaiting.lock().unwrap();
+        for id in ids {
+            let Some(a) = awaiting.get_mut(id) else { continue };
+            if now - a.submitted_at >= poller.max_wait {
+                awaiting.remove(id);
+                timed_out.push(id.to_string());
+                continue;
+            }
+            a.misses += 1;
+            let delay = poller
+                .interval
+                .saturating_mul(2u32.saturating_pow(a.misses - 1))
+                .min(poller.backoff_max);
+            a.next_check = now + jittered(delay);
+        }
+    }
+    if timed_out.is_empty() {
+        return;
+    }
+    poller.totals.lock().unwrap().timed_out += timed_out.len() as u64;
+    for id in timed_out {
+        println!("Attestation of {} was not published in time", id);
+        let message = format!(
+            "Attestation not published within {} seconds of submission",
+            poller.max_wait.as_secs()
+        );
+        crate::fail_measurement(
+            state,
+            &id,
+            Source::AttestationPoller,
+            FailureClass::Verification,
+            message,
+        );
+    }
+}
+
+// Keep an attestation in proofs/{id}/attestation.json
+pub fn write(id: &str, attestation: &AttestationData) {
+    let path = Artifact::Attestation.path(id);
+    let tmp_path = format!("{}.tmp", path);
+    let json = serde_json::json!({
+        "attestationId": attestation.attestation_id,
+        "merklePath": attestation.merkle_path,
+        "leafCount": attestation.leaf_count,
+        "index": attestation.index,
+    });
+    let written = serde_json::to_vec_pretty(&json)
+        .map_err(|e| e.to_string())
+        .and_then(|json| fs::write(&tmp_path, json).map_err(|e| e.to_string()))
+        .and_then(|()| fs::rename(&tmp_path, &path).map_err(|e| e.to_string()));
+    if let Err(e) = written {
+        println!("Failed to write {}: {}", path, e);
+    }
+}
+
+// Store a published attestation and complete its measurement, or fail it when
+// the backend does not vouch for the attestation
+fn complete(state: &AppState, id: &str, kind: BackendKind, attestation: AttestationData) {
+    if !state.attestation_backends.get(kind).verify(&attestation) {
+        println!("Attestation {} of {} failed verification", attestation.attestation_id, id);
+        let message =
+            format!("Attestation {} from {} failed verification", attestation.attestation_id, kind);
+        crate::fail_measurement(
+            state,
+            id,
+            Source::AttestationPoller,
+            FailureClass::Verification,
+            message,
+        );
+        return;
+    }
+    write(id, &attestation);
+    pipeline::append_log(
+        &Artifact::Log.path(id),
+        &format!(
+            "[{}] Attestation {} published\n",
+            Utc::now().to_rfc3339(),
+            attestation.attestation_id
+        ),
+    );
+
+    if !commitment::confirm(state, id, Source::AttestationPoller) {
+        return;
+    }
+    let mut measurements = state.measurements.lock_as(Source::AttestationPoller);
+    // A measurement cancelled during the round is not completed
+    if let Some(m) = measurements.get_mut(id).filter(|m| m.status == ProofStatus::Processing) {
+        println!("Proof {} attested in attestation {}", id, attestation.attestation_id);
+        m.attestation = Some(attestation);
+        m.artifacts.record(id, Artifact::Attestation);
+        plan::end_running(m, StageState::Done, None);
+        m.status = ProofStatus::Completed;
+        state.events.publish(m, EventKind::StatusChanged);
+    }
+}
@@ -0,0 +1,40 @@
+// Append-only audit log of administrative actions.
+//
+// Each entry is one JSON object per line in AUDIT_LOG_PATH, so the file can be
+// tailed, grepped or shipped to a log pipeline without extra tooling. Failing
+// to write an entry is reported but never fails the action being audited.
+
+use chrono::Utc;
+use serde_json::{Value, json};
+use std::{fs::OpenOptions, io::Write, sync::Mutex};
+
+pub struct AuditLog {
+    path: String,
+    // Serializes writers so concurrent entries never interleave
+    lock: Mutex<()>,
+}
+
+impl AuditLog {
+    pub fn new(path: String) -> Self {
+        AuditLog { path, lock: Mutex::new(()) }
+    }
+
+    // Append an entry describing an action and its details
+    pub fn record(&self, action: &str, details: Value) {
+        let entry = json!({
+            "timestamp": Utc::now().to_rfc3339(),
+            "action": action,
+            "details": details,
+        });
+
+        let _guard = self.lock.lock().unwrap();
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| writeln!(file, "{}", entry));
+        if let Err(e) = result {
+            println!("Failed to write audit log entry {}: {}", entry, e);
+        }
+    }
+}
@@ -0,0 +1,846 @@
+// Role-based access control for API keys.
+//
+// Callers identify themselves with `Authorization: Bearer <key>` or an
+// X-Api-Key header. Every key has one role, and each role includes the ones
+// below it:
+//
+//   viewer     read-only listing
+//   submitter  submit measurements and manage their own
+//   operator   admin statistics, jobs and summaries
+//   admin      everything, including deletion and key management
+//
+// Handlers declare the role they need with the Authorized<R> extractor.
+// Requests without a key get ANONYMOUS_ROLE. ADMIN_API_KEY is always an admin
+// key; further keys are created through /admin/keys and persisted, hashed, in
+// API_KEYS_PATH. Until ADMIN_API_KEY is set or a key exists, enforcement is
+// off and every caller is treated as an admin; the server warns about this at
+// startup and the production audit refuses it (safety.rs).
+//
+// In tenant mode (see tenants.rs) a key may belong to a tenant. Such keys,
+// admin ones included, only see and manage their own tenant, and requests
+// without a key get no role at all.
+//
+// Every key counts its requests and the measurements submitted with it, and
+// remembers when it was last used, so unused keys can be found and revoked.
+// The counts are written back to API_KEYS_PATH once a minute. A disabled key
+// is refused with the error code "key_disabled" until it is enabled again.
+// Rotating a key issues a replacement, and the old key keeps working for
+// API_KEY_ROTATION_GRACE_SECS so clients can switch over; afterwards it is
+// refused with "key_expired".
+
+use axum::{
+    extract::{FromRequestParts, Path, Query, State},
+    http::{HeaderMap, StatusCode, header, request::Parts},
+    response::{IntoResponse, Json, Response},
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{
+    fmt, fs, io,
+    marker::PhantomData,
+    str::FromStr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+use uuid::Uuid;
+
+use crate::{AppState, schemas::ErrorBody};
+
+const API_KEY_HEADER: &str = "x-api-key";
+const MAX_KEY_NAME_CHARS: usize = 120;
+const USAGE_FLUSH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Viewer,
+    Submitter,
+    Operator,
+    Admin,
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "viewer" => Ok(Role::Viewer),
+            "submitter" => Ok(Role::Submitter),
+            "operator" => Ok(Role::Operator),
+            "admin" => Ok(Role::Admin),
+            other => Err(format!(
+                "unknown role '{}', expected viewer, submitter, operator or admin",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Viewer => write!(f, "viewer"),
+            Role::Submitter => write!(f, "submitter"),
+            Role::Operator => write!(f, "operator"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+// Parse ANONYMOUS_ROLE, where "none" requires a key for every protected endpoint
+pub fn parse_anonymous_role(value: &str) -> Result<Option<Role>, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "none" => Ok(None),
+        role => role.parse().map(Some),
+    }
+}
+
+// Stored API key; only the hash of the key itself is kept
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiKey {
+    id: String,
+    name: String,
+    role: Role,
+    // Tenant the key is confined to; keys without one are global
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tenant: Option<String>,
+    created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "String::is_empty", default)]
+    key_sha256: String,
+    // When the key was disabled; disabled keys are refused but kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    disabled_at: Option<DateTime<Utc>>,
+    // Key this one was rotated from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    replaces: Option<String>,
+    // Key this one was rotated to, and when it stops working
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    replaced_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<DateTime<Utc>>,
+    #[serde(flatten)]
+    usage: KeyUsage,
+}
+
+// What a key has been used for
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct KeyUsage {
+    #[serde(default)]
+    last_used_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    requests: u64,
+    #[serde(default)]
+    measurements: u64,
+}
+
+pub struct ApiKeys {
+    path: String,
+    keys: Mutex<Vec<ApiKey>>,
+    // Changes made to the keys and their usage, counted under the keys' lock
+    changes: AtomicU64,
+    // Changes API_KEYS_PATH holds; its lock serializes writes of the file, so
+    // an older snapshot never replaces a newer one
+    written: Mutex<u64>,
+}
+
+impl ApiKeys {
+    // Load the stored keys, starting empty if the file does not exist yet
+    pub fn load(path: String) -> Result<Self, String> {
+        let keys = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| format!("Invalid API keys file {}: {}", path, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(format!("Failed to read API keys file {}: {}", path, e)),
+        };
+        Ok(ApiKeys {
+            path,
+            keys: Mutex::new(keys),
+            changes: AtomicU64::new(0),
+            written: Mutex::new(0),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.lock().unwrap().is_empty()
+    }
+
+    // Look up the key and count the request against it, refusing disabled and
+    // expired keys
+    fn authenticate(&self, key: &str) -> Result<ApiKey, AuthError> {
+        let hash = hash_key(key);
+        let now = Utc::now();
+        let mut keys = self.keys.lock().unwrap();
+        let Some(record) = keys.iter_mut().find(|k| k.key_sha256 == hash) else {
+            return Err((StatusCode::UNAUTHORIZED, "Unknown API key".to_string()).into());
+        };
+        if let Some(since) = record.disabled_at {
+            let body = ErrorBody {
+                error: "key_disabled",
+                message: format!("API key {} is disabled", record.id),
+                since: Some(since),
+            };
+            return Err(AuthError::Refused(StatusCode::FORBIDDEN, body));
+        }
+        if let Some(expired) = record.expires_at.filter(|expires| *expires <= now) {
+            let body = ErrorBody {
+                error: "key_expired",
+                message: format!(
+                    "API key {} was rotated to {}; use the new key",
+                    record.id,
+                    record.replaced_by.as_deref().unwrap_or("another key")
+                ),
+                since: Some(expired),
+            };
+            return Err(AuthError::Refused(StatusCode::UNAUTHORIZED, body));
+        }
+
+        record.usage.last_used_at = Some(now);
+        record.usage.requests += 1;
+        self.changes.fetch_add(1, Ordering::Relaxed);
+        Ok(record.clone())
+    }
+
+    // Count a measurement submitted with the given key
+    pub fn record_measurement(&self, key_id: Option<&str>) {
+        let Some(key_id) = key_id else {
+            return;
+        };
+        let mut keys = self.keys.lock().unwrap();
+        if let Some(record) = keys.iter_mut().find(|k| k.id == key_id) {
+            record.usage.measurements += 1;
+            self.changes.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Write the keys after changing them, with their lock held
+    fn save(&self, keys: &[ApiKey]) -> io::Result<()> {
+        let change = self.changes.fetch_add(1, Ordering::Relaxed) + 1;
+        self.write(keys, change)
+    }
+
+    // Write a snapshot of the keys taken after the given change, unless the
+    // file already holds a later one
+    fn write(&self, keys: &[ApiKey], change: u64) -> io::Result<()> {
+        let mut written = self.written.lock().unwrap();
+        if *written >= change {
+            return Ok(());
+        }
+        let content = serde_json::to_string_pretty(keys)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let temp_path = format!("{}.tmp", self.path);
+        fs::write(&temp_path, content)?;
+        fs::rename(&temp_path, &self.path)?;
+        *written = change;
+        Ok(())
+    }
+
+    // Write the keys if their usage changed since the file was last written,
+    // without holding their lock while writing
+    fn flush_usage(&self) -> io::Result<()> {
+        let (keys, change) = {
+            let keys = self.keys.lock().unwrap();
+            let change = self.changes.load(Ordering::Relaxed);
+            if change <= *self.written.lock().unwrap() {
+                return Ok(());
+            }
+            (keys.clone(), change)
+        };
+        self.write(&keys, change)
+    }
+
+    // Work out the caller and their role from the API key in the headers
+    fn identify(
+        &self,
+        headers: &HeaderMap,
+        admin_key: Option<&str>,
+        anonymous_role: Option<Role>,
+    ) -> Result<Caller, AuthError> {
+        // Without any key there is nothing to check callers against, so
+        // access control is off; main warns about it at startup
+        if admin_key.is_none() && self.is_empty() {
+            return Ok(Caller {
+                name: "anonymous".to_string(),
+                key_id: None,
+                role: Some(Role::Admin),
+                tenant: None,
+            });
+        }
+
+        let bearer = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        let api_key = headers.get(API_KEY_HEADER).and_then(|v| v.to_str().ok());
+        let Some(key) = bearer.or(api_key).map(str::trim) else {
+            let role = anonymous_role;
+            return Ok(Caller { name: "anonymous".to_string(), key_id: None, role, tenant: None });
+        };
+
+        if admin_key.is_some_and(|admin| hash_key(admin) == hash_key(key)) {
+            return Ok(Caller {
+                name: "admin".to_string(),
+                key_id: None,
+                role: Some(Role::Admin),
+                tenant: None,
+            });
+        }
+        let k = self.authenticate(key)?;
+        Ok(Caller { name: k.name, key_id: Some(k.id), role: Some(k.role), tenant: k.tenant })
+    }
+}
+
+// Background task writing changed key usage back to API_KEYS_PATH
+pub async fn usage_flusher(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(USAGE_FLUSH_INTERVAL);
+    loop {
+        interval.tick().await;
+        let api_keys = &state.api_keys;
+        if let Err(e) = api_keys.flush_usage() {
+            println!("Failed to save API key usage to {}: {}", api_keys.path, e);
+        }
+    }
+}
+
+fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+// Who is making a request
+#[derive(Debug, Clone)]
+pub struct Caller {
+    // Name of the key used, "admin" for ADMIN_API_KEY or "anonymous"
+    pub name: String,
+    // Id of the stored key used, None for ADMIN_API_KEY and anonymous callers
+    pub key_id: Option<String>,
+    pub role: Option<Role>,
+    // Tenant the caller is confined to, None for global callers
+    pub tenant: Option<String>,
+}
+
+impl Caller {
+    // Whether the caller may see data belonging to the given tenant
+    pub fn can_access(&self, tenant: Option<&str>) -> bool {
+        self.tenant.is_none() || self.tenant.as_deref() == tenant
+    }
+
+    // Reject tenant keys on endpoints covering every tenant at once
+    pub fn require_global(&self) -> Result<(), (StatusCode, String)> {
+        match &self.tenant {
+            Some(_) => {
+                Err((StatusCode::FORBIDDEN, "This endpoint requires a global key".to_string()))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+// Why a request was not admitted; keys that exist but may not be used are
+// refused with a machine-readable error code
+pub enum AuthError {
+    Denied(StatusCode, String),
+    Refused(StatusCode, ErrorBody),
+}
+
+impl From<(StatusCode, String)> for AuthError {
+    fn from((status, message): (StatusCode, String)) -> Self {
+        AuthError::Denied(status, message)
+    }
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        match self {
+            AuthError::Denied(status, message) => (status, message).into_response(),
+            AuthError::Refused(status, body) => (status, Json(body)).into_response(),
+        }
+    }
+}
+
+// Role an Authorized extractor demands
+pub trait RequiredRole {
+    const ROLE: Role;
+}
+
+pub struct Viewer;
+pub struct Submitter;
+pub struct Operator;
+pub struct Admin;
+
+impl RequiredRole for Viewer {
+    const ROLE: Role = Role::Viewer;
+}
+impl RequiredRole for Submitter {
+    const ROLE: Role = Role::Submitter;
+}
+impl RequiredRole for Operator {
+    const ROLE: Role = Role::Operator;
+}
+impl RequiredRole for Admin {
+    const ROLE: Role = Role::Admin;
+}
+
+// Extractor admitting only callers holding at least the role R
+pub struct Authorized<R: RequiredRole>(pub Caller, pub PhantomData<R>);
+
+impl<R: RequiredRole> FromRequestParts<Arc<AppState>> for Authorized<R> {
+    type Rejection = AuthError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        Self::admit(identify(parts, state)?)
+    }
+}
+
+impl<R: RequiredRole> Authorized<R> {
+    // Admit the caller if their role is at least R
+    fn admit(caller: Caller) -> Result<Self, AuthError> {
+        if caller.role.is_some_and(|role| role >= R::ROLE) {
+            return Ok(Authorized(caller, PhantomData));
+        }
+        let status =
+            if caller.role.is_none() { StatusCode::UNAUTHORIZED } else { StatusCode::FORBIDDEN };
+        Err((status, format!("This endpoint requires the {} role", R::ROLE)).into())
+    }
+}
+
+// Work out the caller and their role from the request's API key
+pub fn identify(parts: &Parts, state: &AppState) -> Result<Caller, AuthError> {
+    let config = &state.config;
+    // Requests without a key get no role in tenant mode
+    let anonymous_role = config.anonymous_role.filter(|_| !config.tenants.enabled());
+    state.api_keys.identify(&parts.headers, config.admin_api_key.as_deref(), anonymous_role)
+}
+
+#[derive(Deserialize)]
+pub struct NewApiKey {
+    name: String,
+    role: Role,
+    // Defaults to the creator's own tenant
+    tenant: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    record: ApiKey,
+    // The key itself, only ever shown in this response
+    key: String,
+}
+
+// Handler creating an API key with a role
+pub async fn create_key(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Admin>,
+    Json(request): Json<NewApiKey>,
+) -> Result<(StatusCode, Json<CreatedApiKey>), (StatusCode, String)> {
+    let name = request.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > MAX_KEY_NAME_CHARS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("name must be 1 to {} characters long", MAX_KEY_NAME_CHARS),
+        ));
+    }
+
+    // Scoped admins can only create keys for their own tenant
+    let tenant = request.tenant.or(caller.tenant.clone());
+    if let Some(tenant) = &tenant {
+        if !caller.can_access(Some(tenant)) {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "Keys can only be created for your own tenant".to_string(),
+            ));
+        }
+        if !state.config.tenants.contains(tenant) {
+            return Err((StatusCode::BAD_REQUEST, format!("Unknown tenant {}", tenant)));
+        }
+    }
+
+    let key = format!("zkh_{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    let record = ApiKey {
+        id: Uuid::new_v4().to_string(),
+        name,
+        role: request.role,
+        tenant,
+        created_at: Utc::now(),
+        key_sha256: hash_key(&key),
+        disabled_at: None,
+        replaces: None,
+        replaced_by: None,
+        expires_at: None,
+        usage: KeyUsage::default(),
+    };
+    {
+        let mut keys = state.api_keys.keys.lock().unwrap();
+        keys.push(record.clone());
+        if let Err(e) = state.api_keys.save(&keys) {
+            keys.pop();
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to save API keys: {}", e),
+            ));
+        }
+    }
+
+    state.audit.record(
+        "api_key_created",
+        json!({
+            "by": caller.name,
+            "id": record.id,
+            "name": record.name,
+            "role": record.role,
+            "tenant": record.tenant,
+        }),
+    );
+    let record = ApiKey { key_sha256: String::new(), ..record };
+    Ok((StatusCode::CREATED, Json(CreatedApiKey { record, key })))
+}
+
+#[derive(Deserialize)]
+pub struct KeyListQuery {
+    // Only keys unused for at least this many days, counting from creation
+    // for keys never used
+    idle_days: Option<u32>,
+}
+
+// Handler listing the API keys visible to the caller, without their hashes
+pub async fn list_keys(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Admin>,
+    Query(query): Query<KeyListQuery>,
+) -> Json<Vec<ApiKey>> {
+    let idle_since = query.idle_days.map(|days| Utc::now() - Duration::days(days.into()));
+    let keys = state.api_keys.keys.lock().unwrap();
+    Json(
+        keys.iter()
+            .filter(|k| caller.can_access(k.tenant.as_deref()))
+            .filter(|k| {
+                idle_since.is_none_or(|since| k.usage.last_used_at.unwrap_or(k.created_at) <= since)
+            })
+            .map(|k| ApiKey { key_sha256: String::new(), ..k.clone() })
+            .collect(),
+    )
+}
+
+// Position of a key the caller may manage; keys of other tenants are not found
+fn find_managed(keys: &[ApiKey], caller: &Caller, id: &str) -> Result<usize, (StatusCode, String)> {
+    keys.iter()
+        .position(|k| k.id == id && caller.can_access(k.tenant.as_deref()))
+        .ok_or((StatusCode::NOT_FOUND, format!("API key {} not found", id)))
+}
+
+#[derive(Deserialize)]
+pub struct RoleChange {
+    role: Role,
+}
+
+// Handler changing the role of an API key
+pub async fn update_key(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Admin>,
+    Path(id): Path<String>,
+    Json(change): Json<RoleChange>,
+) -> Result<Json<ApiKey>, (StatusCode, String)> {
+    let updated = {
+        let mut keys = state.api_keys.keys.lock().unwrap();
+        let index = find_managed(&keys, &caller, &id)?;
+        let previous = keys[index].role;
+        keys[index].role = change.role;
+        if let Err(e) = state.api_keys.save(&keys) {
+            keys[index].role = previous;
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to save API keys: {}", e),
+            ));
+        }
+        state.audit.record(
+            "api_key_role_changed",
+            json!({ "by": caller.name, "id": id, "from": previous, "to": change.role }),
+        );
+        keys[index].clone()
+    };
+    Ok(Json(ApiKey { key_sha256: String::new(), ..updated }))
+}
+
+// Handler revoking an API key
+pub async fn delete_key(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Admin>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut keys = state.api_keys.keys.lock().unwrap();
+    let index = find_managed(&keys, &caller, &id)?;
+    let removed = keys.remove(index);
+    if let Err(e) = state.api_keys.save(&keys) {
+        keys.insert(index, removed);
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save API keys: {}", e)));
+    }
+
+    state.audit.record(
+        "api_key_revoked",
+        json!({ "by": caller.name, "id": id, "name": removed.name, "role": removed.role }),
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Handler disabling an API key, which is refused until enabled again
+pub async fn disable_key(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Admin>,
+    Path(id): Path<String>,
+) -> Result<Json<ApiKey>, (StatusCode, String)> {
+    set_disabled(&state, &caller, &id, true).map(Json)
+}
+
+// Handler enabling a disabled API key
+pub async fn enable_key(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Admin>,
+    Path(id): Path<String>,
+) -> Result<Json<ApiKey>, (StatusCode, String)> {
+    set_disabled(&state, &caller, &id, false).map(Json)
+}
+
+fn set_disabled(
+    state: &AppState,
+    caller: &Caller,
+    id: &str,
+    disabled: bool,
+) -> Result<ApiKey, (StatusCode, String)> {
+    let mut keys = state.api_keys.keys.lock().unwrap();
+    let index = find_managed(&keys, caller, id)?;
+    let previous = keys[index].disabled_at;
+    if previous.is_some() != disabled {
+        keys[index].disabled_at = disabled.then(Utc::now);
+        if let Err(e) = state.api_keys.save(&keys) {
+            keys[index].disabled_at = previous;
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to save API keys: {}", e),
+            ));
+        }
+        state.audit.record(
+            if disabled { "api_key_disabled" } else { "api_key_enabled" },
+            json!({ "by": caller.name, "id": id, "name": keys[index].name }),
+        );
+    }
+    Ok(ApiKey { key_sha256: String::new(), ..keys[index].clone() })
+}
+
+#[derive(Deserialize)]
+pub struct RotationQuery {
+    // How long the old key keeps working, API_KEY_ROTATION_GRACE_SECS by default
+    grace_secs: Option<u64>,
+}
+
+// Handler replacing an API key with a new one of the same name, role and
+// tenant; the old key keeps working until its grace period ends
+pub async fn rotate_key(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Admin>,
+    Path(id): Path<String>,
+    Query(query): Query<RotationQuery>,
+) -> Result<(StatusCode, Json<CreatedApiKey>), (StatusCode, String)> {
+    let grace_secs = query.grace_secs.unwrap_or(state.config.api_key_rotation_grace_secs);
+    let grace = i64::try_from(grace_secs)
+        .ok()
+        .and_then(Duration::try_seconds)
+        .ok_or((StatusCode::BAD_REQUEST, format!("grace_secs {} is too large", grace_secs)))?;
+
+    let key = format!("zkh_{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    let now = Utc::now();
+    let (record, expires_at) = {
+        let mut keys = state.api_keys.keys.lock().unwrap();
+        let index = find_managed(&keys, &caller, &id)?;
+        let old = &keys[index];
+        if old.disabled_at.is_some() {
+            return Err((StatusCode::CONFLICT, format!("API key {} is disabled", id)));
+        }
+        if let Some(replacement) = &old.replaced_by {
+            return Err((
+                StatusCode::CONFLICT,
+                format!("API key {} has already been rotated to {}", id, replacement),
+            ));
+        }
+
+        let record = ApiKey {
+            id: Uuid::new_v4().to_string(),
+            name: old.name.clone(),
+            role: old.role,
+            tenant: old.tenant.clone(),
+            created_at: now,
+            key_sha256: hash_key(&key),
+            disabled_at: None,
+            replaces: Some(id.clone()),
+            replaced_by: None,
+            expires_at: None,
+            usage: KeyUsage::default(),
+        };
+        let expires_at = now + grace;
+        keys[index].replaced_by = Some(record.id.clone());
+        keys[index].expires_at = Some(expires_at);
+        keys.push(record.clone());
+        if let Err(e) = state.api_keys.save(&keys) {
+            keys.pop();
+            keys[index].replaced_by = None;
+            keys[index].expires_at = None;
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to save API keys: {}", e),
+            ));
+        }
+        (record, expires_at)
+    };
+
+    state.audit.record(
+        "api_key_rotated",
+        json!({
+            "by": caller.name,
+            "id": id,
+            "replaced_by": record.id,
+            "name": record.name,
+            "old_key_expires_at": expires_at,
+        }),
+    );
+    let record = ApiKey { key_sha256: String::new(), ..record };
+    Ok((StatusCode::CREATED, Json(CreatedApiKey { record, key })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADMIN_KEY: &str = "zkh_admin";
+
+    // Keys with every role, a disabled one and an expired one, with the
+    // secret each is presented with
+    fn api_keys() -> ApiKeys {
+        let keys = empty_keys();
+        let key = |name: &str, role| ApiKey {
+            id: name.to_string(),
+            name: name.to_string(),
+            role,
+            tenant: None,
+            created_at: Utc::now(),
+            key_sha256: hash_key(name),
+            disabled_at: None,
+            replaces: None,
+            replaced_by: None,
+            expires_at: None,
+            usage: KeyUsage::default(),
+        };
+        *keys.keys.lock().unwrap() = vec![
+            key("viewer", Role::Viewer),
+            key("submitter", Role::Submitter),
+            key("operator", Role::Operator),
+            key("admin", Role::Admin),
+            ApiKey { disabled_at: Some(Utc::now()), ..key("disabled", Role::Admin) },
+            ApiKey { expires_at: Some(Utc::now()), ..key("expired", Role::Admin) },
+        ];
+        keys
+    }
+
+    // Keys stored in a file of their own, not created until written
+    fn empty_keys() -> ApiKeys {
+        let path = std::env::temp_dir().join(format!("zkhotdog-api-keys-{}.json", Uuid::new_v4()));
+        ApiKeys::load(path.to_string_lossy().into_owned()).unwrap()
+    }
+
+    fn headers(key: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(key) = key {
+            headers.insert(API_KEY_HEADER, key.parse().unwrap());
+        }
+        headers
+    }
+
+    fn status<R: RequiredRole>(caller: Result<Caller, AuthError>) -> StatusCode {
+        match caller.and_then(Authorized::<R>::admit) {
+            Ok(_) => StatusCode::OK,
+            Err(AuthError::Denied(status, _) | AuthError::Refused(status, _)) => status,
+        }
+    }
+
+    // Status of each endpoint class, viewer to admin, for the key presented
+    fn admitted(keys: &ApiKeys, key: Option<&str>, anonymous_role: Option<Role>) -> [u16; 4] {
+        let caller = || keys.identify(&headers(key), Some(ADMIN_KEY), anonymous_role);
+        [
+            status::<Viewer>(caller()),
+            status::<Submitter>(caller()),
+            status::<Operator>(caller()),
+            status::<Admin>(caller()),
+        ]
+        .map(|status| status.as_u16())
+    }
+
+    #[test]
+    fn every_role_reaches_its_endpoints_and_those_below() {
+        let keys = api_keys();
+        assert_eq!(admitted(&keys, Some("viewer"), None), [200, 403, 403, 403]);
+        assert_eq!(admitted(&keys, Some("submitter"), None), [200, 200, 403, 403]);
+        assert_eq!(admitted(&keys, Some("operator"), None), [200, 200, 200, 403]);
+        assert_eq!(admitted(&keys, Some("admin"), None), [200, 200, 200, 200]);
+        assert_eq!(admitted(&keys, Some(ADMIN_KEY), None), [200, 200, 200, 200]);
+    }
+
+    #[test]
+    fn callers_without_a_usable_key_are_refused() {
+        let keys = api_keys();
+        assert_eq!(admitted(&keys, None, None), [401, 401, 401, 401]);
+        assert_eq!(admitted(&keys, Some("unknown"), None), [401, 401, 401, 401]);
+        assert_eq!(admitted(&keys, Some("disabled"), None), [403, 403, 403, 403]);
+        assert_eq!(admitted(&keys, Some("expired"), None), [401, 401, 401, 401]);
+    }
+
+    #[test]
+    fn anonymous_callers_get_the_anonymous_role() {
+        let keys = api_keys();
+        assert_eq!(admitted(&keys, None, Some(Role::Viewer)), [200, 403, 403, 403]);
+        assert_eq!(admitted(&keys, None, Some(Role::Submitter)), [200, 200, 403, 403]);
+        // A presented key is checked even where anonymous callers would pass
+        assert_eq!(admitted(&keys, Some("unknown"), Some(Role::Viewer)), [401, 401, 401, 401]);
+    }
+
+    #[test]
+    fn bearer_tokens_identify_like_api_key_headers() {
+        let keys = api_keys();
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, "Bearer operator".parse().unwrap());
+        let Ok(caller) = keys.identify(&headers, Some(ADMIN_KEY), None) else {
+            panic!("bearer token refused");
+        };
+        assert_eq!(
+            (caller.key_id.as_deref(), caller.role),
+            (Some("operator"), Some(Role::Operator))
+        );
+    }
+
+    #[test]
+    fn without_any_key_every_caller_is_an_admin() {
+        let keys = empty_keys();
+        let caller = keys.identify(&headers(None), None, None);
+        assert_eq!(status::<Admin>(caller), StatusCode::OK);
+    }
+
+    #[test]
+    fn usage_is_flushed_once_per_change() {
+        let keys = api_keys();
+        assert!(keys.authenticate("viewer").is_ok());
+        keys.record_measurement(Some("viewer"));
+        keys.flush_usage().unwrap();
+        let stored: Vec<ApiKey> =
+            serde_json::from_str(&fs::read_to_string(&keys.path).unwrap()).unwrap();
+        let viewer = stored.iter().find(|k| k.id == "viewer").unwrap();
+        assert_eq!((viewer.usage.requests, viewer.usage.measurements), (1, 1));
+
+        // Nothing changed since, so the file is left alone
+        fs::remove_file(&keys.path).unwrap();
+        keys.flush_usage().unwrap();
+        assert!(fs::metadata(&keys.path).is_err());
+    }
+}
@@ -0,0 +1,441 @@
+// Derived measurement fields and their backfill.
+//
+// Some fields of a measurement are derived from its stored image and points
+// at ingest: the image's SHA-256 and perceptual hash, its dimensions and the
+// measured length. Measurements recorded before a field was introduced lack
+// it, which leaves filters and duplicate detection incomplete.
+// POST /admin/backfill?fields=image_hash,length starts a job computing the
+// requested fields where they are missing and writing them back; fields that
+// are already set are left alone.
+//
+// The job visits measurements in ID order, BACKFILL_BATCH_SIZE at a time,
+// and sleeps BACKFILL_BATCH_DELAY_MS between batches so live traffic keeps
+// the CPU and disk. After every batch its cursor, the last ID processed, and
+// its counts are written to BACKFILL_STATE_PATH, so a server restarted in
+// the middle of a backfill resumes it under the same job id. Only one
+// backfill runs at a time.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::Json,
+};
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    AppState, Point3D,
+    auth::{Admin, Authorized},
+    config::Config,
+    coords, similarity, version,
+};
+
+// Failures kept in the report, the most recent last
+const MAX_REPORTED_FAILURES: usize = 100;
+
+// Size of an image in pixels
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, JsonSchema)]
+pub struct ImageDimensions {
+    pub width: u32,
+    pub height: u32,
+}
+
+// SHA-256 of an image file
+pub fn image_sha256(path: &str) -> Result<String, String> {
+    version::hash_file(path).ok_or_else(|| format!("Failed to read image {}", path))
+}
+
+// Dimensions of an image file, read from its header
+pub fn image_dimensions(path: &str) -> Result<ImageDimensions, String> {
+    image::image_dimensions(path)
+        .map(|(width, height)| ImageDimensions { width, height })
+        .map_err(|e| format!("Failed to read the size of image {}: {}", path, e))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackfillField {
+    ImageHash,
+    PerceptualHash,
+    Dimensions,
+    Length,
+}
+
+impl BackfillField {
+    const ALL: [BackfillField; 4] = [
+        BackfillField::ImageHash,
+        BackfillField::PerceptualHash,
+        BackfillField::Dimensions,
+        BackfillField::Length,
+    ];
+}
+
+impl FromStr for BackfillField {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "image_hash" => Ok(BackfillField::ImageHash),
+            "perceptual_hash" => Ok(BackfillField::PerceptualHash),
+            "dimensions" => Ok(BackfillField::Dimensions),
+            "length" => Ok(BackfillField::Length),
+            other => Err(format!(
+                "unknown field '{}', expected image_hash, perceptual_hash, dimensions or length",
+                other
+            )),
+        }
+    }
+}
+
+// Value computed for a field
+enum Derived {
+    ImageHash(String),
+    PerceptualHash(String),
+    Dimensions(ImageDimensions),
+    Length(f64),
+}
+
+type FieldValue = (BackfillField, Result<Derived, String>);
+
+fn derive(field: BackfillField, item: &BatchItem) -> Result<Derived, String> {
+    match field {
+        BackfillField::ImageHash => image_sha256(&item.image_path).map(Derived::ImageHash),
+        BackfillField::PerceptualHash => {
+            similarity::perceptual_hash(&item.image_path).map(Derived::PerceptualHash)
+        }
+        BackfillField::Dimensions => image_dimensions(&item.image_path).map(Derived::Dimensions),
+        BackfillField::Length => Ok(Derived::Length(coords::length_cm(&item.path))),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+struct FieldCounts {
+    // Computed and written back
+    updated: usize,
+    // Already set, left alone
+    present: usize,
+    failed: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct FieldFailure {
+    id: String,
+    field: BackfillField,
+    error: String,
+}
+
+// Progress of a backfill, as persisted in BACKFILL_STATE_PATH
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct Progress {
+    job_id: String,
+    // Tenant whose admin started the backfill, None for every tenant
+    tenant: Option<String>,
+    fields: Vec<BackfillField>,
+    started_at: DateTime<Utc>,
+    // Measurements in scope when the backfill started
+    total: usize,
+    // Last measurement ID processed; IDs are visited in order
+    cursor: Option<String>,
+    processed: usize,
+    counts: BTreeMap<BackfillField, FieldCounts>,
+    failures: Vec<FieldFailure>,
+    finished: bool,
+}
+
+impl Progress {
+    fn report(&self) -> serde_json::Value {
+        json!({
+            "fields": self.fields,
+            "cursor": self.cursor,
+            "counts": self.counts,
+            "failures": self.failures,
+        })
+    }
+
+    fn in_scope(&self, tenant: Option<&str>) -> bool {
+        self.tenant.is_none() || self.tenant.as_deref() == tenant
+    }
+}
+
+pub struct Backfill {
+    path: String,
+    batch_size: usize,
+    delay: Duration,
+    // Id of the job currently running
+    running: Mutex<Option<String>>,
+}
+
+impl Backfill {
+    pub fn new(config: &Config) -> Self {
+        Backfill {
+            path: config.backfill_state_path.clone(),
+            batch_size: config.backfill_batch_size,
+            delay: Duration::from_millis(config.backfill_batch_delay_ms),
+            running: Mutex::new(None),
+        }
+    }
+
+    fn load(&self) -> Result<Option<Progress>, String> {
+        match fs::read_to_string(&self.path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map(Some)
+                .map_err(|e| format!("Invalid backfill state {}: {}", self.path, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Failed to read backfill state {}: {}", self.path, e)),
+        }
+    }
+
+    fn save(&self, progress: &Progress) -> io::Result<()> {
+        let content = serde_json::to_string_pretty(progress)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let temp_path = format!("{}.tmp", self.path);
+        fs::write(&temp_path, content)?;
+        fs::rename(&temp_path, &self.path)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BackfillQuery {
+    // Comma-separated fields to compute, all of them by default
+    fields: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct BackfillStarted {
+    job_id: String,
+    fields: Vec<BackfillField>,
+    total: usize,
+}
+
+// Handler starting a backfill of derived fields as a background job
+pub async fn start(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Admin>,
+    Query(query): Query<BackfillQuery>,
+) -> Result<(StatusCode, Json<BackfillStarted>), (StatusCode, String)> {
+    let fields: Vec<BackfillField> = match query.fields.as_deref().map(str::trim) {
+        None | Some("") => BackfillField::ALL.to_vec(),
+        Some(value) => value
+            .split(',')
+            .map(|field| field.trim().parse())
+            .collect::<Result<BTreeSet<_>, _>>()
+            .map_err(|e| (StatusCode::BAD_REQUEST, e))?
+            .into_iter()
+            .collect(),
+    };
+
+    let mut running = state.backfill.running.lock().unwrap();
+    if let Some(job_id) = running.as_ref() {
+        return Err((StatusCode::CONFLICT, format!("Backfill job {} is still running", job_id)));
+    }
+
+    // Scoped admins only ever backfill their own tenant
+    let tenant = caller.tenant.clone();
+    let total = state
+        .measurements
+        .lock()
+        .values()
+        .filter(|m| tenant.is_none() || m.tenant == tenant)
+        .count();
+    let job_id = state.jobs.start("backfill", total, tenant.clone());
+    let progress = Progress {
+        job_id: job_id.clone(),
+        tenant,
+        fields: fields.clone(),
+        started_at: Utc::now(),
+        total,
+        cursor: None,
+        processed: 0,
+        counts: fields.iter().map(|field| (*field, FieldCounts::default())).collect(),
+        failures: Vec::new(),
+        finished: false,
+    };
+    if let Err(e) = state.backfill.save(&progress) {
+        state.jobs.finish(&job_id, json!({ "error": e.to_string() }));
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to save backfill state: {}", e),
+        ));
+    }
+    *running = Some(job_id.clone());
+    drop(running);
+
+    state.audit.record(
+        "backfill_started",
+        json!({ "by": caller.name, "job_id": job_id, "fields": fields, "total": total }),
+    );
+    tokio::spawn(run(state.clone(), progress));
+    Ok((StatusCode::ACCEPTED, Json(BackfillStarted { job_id, fields, total })))
+}
+
+// Background task resuming a backfill interrupted by a restart
+pub async fn resume(state: Arc<AppState>) {
+    let progress = match state.backfill.load() {
+        Ok(Some(progress)) if !progress.finished => progress,
+        Ok(_) => return,
+        Err(e) => {
+            println!("Not resuming the backfill: {}", e);
+            return;
+        }
+    };
+    println!("Resuming backfill job {} after {} measurements", progress.job_id, progress.processed);
+    state.jobs.resume(
+        &progress.job_id,
+        "backfill",
+        progress.total,
+        progress.tenant.clone(),
+        progress.started_at,
+    );
+    state.jobs.update(&progress.job_id, progress.processed, progress.report());
+    *state.backfill.running.lock().unwrap() = Some(progress.job_id.clone());
+    run(state, progress).await;
+}
+
+// A measurement of the current batch and the fields it lacks
+struct BatchItem {
+    id: String,
+    image_path: String,
+    // Scaled points from the start point to the end point
+    path: Vec<Point3D>,
+    missing: Vec<BackfillField>,
+}
+
+async fn run(state: Arc<AppState>, mut progress: Progress) {
+    loop {
+        let batch = next_batch(&state, &mut progress);
+        let computed = tokio::task::spawn_blocking(move || {
+            batch
+                .into_iter()
+                .map(|item| {
+                    let values: Vec<_> =
+                        item.missing.iter().map(|field| (*field, derive(*field, &item))).collect();
+                    (item.id, values)
+                })
+                .collect::<Vec<_>>()
+        })
+        .await
+        .unwrap_or_default();
+        write_back(&state, &mut progress, computed);
+
+        state.jobs.update(&progress.job_id, progress.processed, progress.report());
+        if let Err(e) = state.backfill.save(&progress) {
+            println!("Failed to save backfill state {}: {}", state.backfill.path, e);
+        }
+        if !has_more(&state, &progress) {
+            break;
+        }
+        tokio::time::sleep(state.backfill.delay).await;
+    }
+
+    progress.finished = true;
+    if let Err(e) = state.backfill.save(&progress) {
+        println!("Failed to save backfill state {}: {}", state.backfill.path, e);
+    }
+    state.jobs.finish(&progress.job_id, progress.report());
+    *state.backfill.running.lock().unwrap() = None;
+    state.audit.record(
+        "backfill_finished",
+        json!({ "job_id": progress.job_id, "processed": progress.processed, "counts": progress.counts }),
+    );
+}
+
+// Take the next measurements after the cursor, counting fields already set
+// and moving the cursor past them
+fn next_batch(state: &AppState, progress: &mut Progress) -> Vec<BatchItem> {
+    let measurements = state.measurements.lock();
+    let mut ids: Vec<&String> = measurements
+        .values()
+        .filter(|m| progress.in_scope(m.tenant.as_deref()))
+        .filter(|m| progress.cursor.as_ref().is_none_or(|cursor| m.id > *cursor))
+        .map(|m| &m.id)
+        .collect();
+    ids.sort();
+    ids.truncate(state.backfill.batch_size);
+
+    let mut batch = Vec::new();
+    for id in ids {
+        let m = &measurements[id];
+        let mut missing = Vec::new();
+        for field in &progress.fields {
+            let present = match field {
+                BackfillField::ImageHash => m.image_sha256.is_some(),
+                BackfillField::PerceptualHash => m.perceptual_hash.is_some(),
+                BackfillField::Dimensions => m.image_dimensions.is_some(),
+                BackfillField::Length => m.length_cm.is_some(),
+            };
+            if present {
+                progress.counts.entry(*field).or_default().present += 1;
+            } else {
+                missing.push(*field);
+            }
+        }
+        progress.cursor = Some(id.clone());
+        progress.processed += 1;
+        if !missing.is_empty() {
+            batch.push(BatchItem {
+                id: id.clone(),
+                image_path: m.image_path.clone(),
+                path: m.path(),
+                missing,
+            });
+        }
+    }
+    batch
+}
+
+// Whether measurements remain after the cursor
+fn has_more(state: &AppState, progress: &Progress) -> bool {
+    state.measurements.lock().values().any(|m| {
+        progress.in_scope(m.tenant.as_deref())
+            && progress.cursor.as_ref().is_none_or(|cursor| m.id > *cursor)
+    })
+}
+
+// Store the computed values on measurements still lacking them
+fn write_back(state: &AppState, progress: &mut Progress, computed: Vec<(String, Vec<FieldValue>)>) {
+    let mut measurements = state.measurements.lock();
+    for (id, values) in computed {
+        let Some(m) = measurements.get_mut(&id) else {
+            continue;
+        };
+        for (field, value) in values {
+            let counts = progress.counts.entry(field).or_default();
+            let updated = match value {
+                Ok(Derived::ImageHash(hash)) => set_missing(&mut m.image_sha256, hash),
+                Ok(Derived::PerceptualHash(hash)) => set_missing(&mut m.perceptual_hash, hash),
+                Ok(Derived::Dimensions(size)) => set_missing(&mut m.image_dimensions, size),
+                Ok(Derived::Length(length)) => set_missing(&mut m.length_cm, length),
+                Err(error) => {
+                    counts.failed += 1;
+                    progress.failures.push(FieldFailure { id: id.clone(), field, error });
+                    continue;
+                }
+            };
+            // A submission racing the backfill may have set it meanwhile
+            if updated {
+                counts.updated += 1;
+            } else {
+                counts.present += 1;
+            }
+        }
+    }
+    let excess = progress.failures.len().saturating_sub(MAX_REPORTED_FAILURES);
+    progress.failures.drain(..excess);
+}
+
+fn set_missing<T>(slot: &mut Option<T>, value: T) -> bool {
+    if slot.is_some() {
+        return false;
+    }
+    *slot = Some(value);
+    true
+}
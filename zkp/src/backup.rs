@@ -0,0 +1,282 @@
+// Export and import of the measurement registry.
+//
+// GET /admin/export streams every measurement record, attestation included,
+// as NDJSON: one JSON document per line, ordered by (created_at, id). Like
+// the listing, the ids are snapshotted first and the records copied and
+// serialized a batch at a time while the client reads.
+//
+// POST /admin/import takes the same format and merges it into the registry,
+// line by line, so one bad record is reported without losing the others.
+// Records whose id is already known are skipped unless ?overwrite=true, and
+// an overwritten measurement is taken out as DELETE /measurements/{id} does,
+// keeping its files. Only records are moved; the uploads/ and proofs/
+// directories are copied to the new host separately. Imported measurements
+// that are pending are queued again and their deadlines watched, while
+// records still being proved are refused, as their proof would never finish
+// on the new host.
+//
+// Scoped admins export and import only their own tenant's measurements.
+
+use axum::{
+    Json,
+    body::{Body, Bytes},
+    extract::{Query, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use futures_util::{TryStreamExt, stream};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{convert::Infallible, io, sync::Arc};
+use tokio::io::AsyncBufReadExt;
+use tokio_util::io::StreamReader;
+use uuid::Uuid;
+
+use crate::{
+    AppState, Measurement, ProofStatus, admin,
+    artifacts::{self, Artifact},
+    auth::{Admin, Authorized},
+    deadline, tenants,
+};
+
+// Number of records copied out of the store per lock acquisition
+const EXPORT_BATCH_SIZE: usize = 256;
+
+// Handler streaming the measurement records as NDJSON
+pub async fn export(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Admin>,
+) -> Response {
+    let mut keys: Vec<_> = state
+        .measurements
+        .lock()
+        .values()
+        .filter(|m| caller.tenant.is_none() || m.tenant == caller.tenant)
+        .map(|m| (m.created_at, m.id.clone()))
+        .collect();
+    keys.sort();
+    let ids: Vec<String> = keys.into_iter().map(|(_, id)| id).collect();
+    state
+        .audit
+        .record("measurements_exported", json!({ "caller": caller.name, "count": ids.len() }));
+
+    let body = stream::unfold((state, ids, 0), |(state, ids, position)| async move {
+        if position >= ids.len() {
+            return None;
+        }
+        let end = (position + EXPORT_BATCH_SIZE).min(ids.len());
+        let mut chunk = Vec::new();
+        {
+            let measurements = state.measurements.lock();
+            // Measurements deleted since the snapshot are skipped
+            for m in ids[position..end].iter().filter_map(|id| measurements.get(id)) {
+                if let Err(e) = serde_json::to_writer(&mut chunk, m) {
+                    println!("Failed to serialize measurement {}: {}", m.id, e);
+                    continue;
+                }
+                chunk.push(b'\n');
+            }
+        }
+        Some((Ok::<_, Infallible>(Bytes::from(chunk)), (state, ids, end)))
+    });
+
+    ([(header::CONTENT_TYPE, "application/x-ndjson")], Body::from_stream(body)).into_response()
+}
+
+#[derive(Deserialize, Default)]
+pub struct ImportQuery {
+    #[serde(default)]
+    overwrite: bool,
+}
+
+// A record that could not be imported
+#[derive(Serialize)]
+struct RecordError {
+    // Line of the record, counting from 1
+    line: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    error: String,
+}
+
+#[derive(Serialize, Default)]
+pub struct ImportReport {
+    imported: usize,
+    overwritten: usize,
+    // Records whose id was already known, without ?overwrite=true
+    skipped: usize,
+    errors: Vec<RecordError>,
+}
+
+// What became of one valid record
+enum Merged {
+    Imported,
+    Overwritten,
+    Skipped,
+}
+
+// Handler merging NDJSON measurement records into the registry
+pub async fn import(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Admin>,
+    Query(query): Query<ImportQuery>,
+    body: Body,
+) -> Result<Json<ImportReport>, (StatusCode, String)> {
+    let reader = StreamReader::new(body.into_data_stream().map_err(io::Error::other));
+    let mut lines = reader.lines();
+    let mut report = ImportReport::default();
+    let mut line = 0;
+
+    while let Some(text) = lines.next_line().await.map_err(|e| {
+        (StatusCode::BAD_REQUEST, format!("Failed to read the import after line {}: {}", line, e))
+    })? {
+        line += 1;
+        if text.trim().is_empty() {
+            continue;
+        }
+        let m: Measurement = match serde_json::from_str(&text) {
+            Ok(m) => m,
+            Err(e) => {
+                let error = format!("Not a measurement record: {}", e);
+                report.errors.push(RecordError { line, id: None, error });
+                continue;
+            }
+        };
+        let id = m.id.clone();
+        let merged = match validate(&state, caller.tenant.as_deref(), &m) {
+            Ok(()) => merge(&state, m, query.overwrite).await,
+            Err(e) => Err(e),
+        };
+        match merged {
+            Ok(Merged::Imported) => report.imported += 1,
+            Ok(Merged::Overwritten) => report.overwritten += 1,
+            Ok(Merged::Skipped) => report.skipped += 1,
+            Err(error) => report.errors.push(RecordError { line, id: Some(id), error }),
+        }
+    }
+
+    println!(
+        "Imported {} measurements, overwrote {}, skipped {}; {} records failed",
+        report.imported,
+        report.overwritten,
+        report.skipped,
+        report.errors.len()
+    );
+    state.audit.record(
+        "measurements_imported",
+        json!({
+            "caller": caller.name,
+            "imported": report.imported,
+            "overwritten": report.overwritten,
+            "skipped": report.skipped,
+            "failed": report.errors.len(),
+        }),
+    );
+    Ok(Json(report))
+}
+
+// Check that a record can be trusted with the server's files and tenants
+fn validate(state: &AppState, scope: Option<&str>, m: &Measurement) -> Result<(), String> {
+    if Uuid::parse_str(&m.id).map(|id| id.to_string()).ok().as_deref() != Some(m.id.as_str()) {
+        return Err("id is not a measurement id".to_string());
+    }
+    if let Some(tenant) = &m.tenant
+        && !state.config.tenants.contains(tenant)
+    {
+        return Err(format!("Tenant {} is not configured on this server", tenant));
+    }
+    if scope.is_some() && m.tenant.as_deref() != scope {
+        return Err("Only measurements of your own tenant can be imported".to_string());
+    }
+    if m.status == ProofStatus::Processing {
+        return Err("The measurement is being proved; export it again once it finishes".to_string());
+    }
+    Ok(())
+}
+
+// Add a validated record to the registry and the store
+async fn merge(
+    state: &Arc<AppState>,
+    mut m: Measurement,
+    overwrite: bool,
+) -> Result<Merged, String> {
+    let id = m.id.clone();
+    let owner_key = tenants::owner_key(m.tenant.as_deref(), &m.owner);
+    let mut merged = Merged::Imported;
+    {
+        let mut measurements = state.measurements.lock();
+        if let Some(external_id) = &m.external_id
+            && let Some(existing) = state.external_ids.get(&owner_key, external_id)
+            && existing != id
+        {
+            return Err(format!(
+                "External id {} is already used by measurement {}",
+                external_id, existing
+            ));
+        }
+        if let Some(key) = &m.idempotency_key
+            && let Some(existing) = state.idempotency_keys.get(&owner_key, key)
+            && existing != id
+        {
+            return Err(format!(
+                "Idempotency key {} is already used by measurement {}",
+                key, existing
+            ));
+        }
+        if measurements.contains_key(&id) {
+            if !overwrite {
+                return Ok(Merged::Skipped);
+            }
+            admin::take_measurement(state, &mut measurements, &id).map_err(|reason| {
+                format!("The existing measurement cannot be replaced: {}", reason)
+            })?;
+            merged = Merged::Overwritten;
+        }
+    }
+    // The replaced record leaves the store before the imported one is written
+    if matches!(merged, Merged::Overwritten) {
+        state.measurements.flushed().await;
+    }
+
+    // Files are looked up where this server keeps them, whatever the old host's layout
+    if let Some(tenant) = &m.tenant {
+        artifacts::assign_tenant(&id, tenant);
+    }
+    let images = m.image_paths.len();
+    m.image_path = Artifact::Image.path(&id);
+    m.image_paths = [Artifact::Image, Artifact::SecondImage]
+        .iter()
+        .take(images)
+        .map(|artifact| artifact.path(&id))
+        .collect();
+    {
+        let mut measurements = state.measurements.lock();
+        if let Some(external_id) = &m.external_id {
+            state.external_ids.insert(&owner_key, external_id, &id);
+        }
+        if let Some(key) = &m.idempotency_key {
+            state.idempotency_keys.insert(&owner_key, key, &id);
+        }
+        measurements.refresh(m.clone());
+    }
+    if let Err(e) = state.measurements.store().insert(&m).await {
+        state.measurements.lock().forget(&id);
+        if let Some(external_id) = &m.external_id {
+            state.external_ids.remove(&owner_key, external_id);
+        }
+        if let Some(key) = &m.idempotency_key {
+            state.idempotency_keys.remove(&owner_key, key);
+        }
+        return Err(format!("Failed to store the measurement: {}", e));
+    }
+
+    if m.status == ProofStatus::Pending {
+        state.queue.push(&m.owner, &id);
+    }
+    if matches!(m.status, ProofStatus::Pending | ProofStatus::Scheduled)
+        && let Some(deadline) = m.deadline
+    {
+        tokio::spawn(deadline::watch(state.clone(), id, deadline));
+    }
+    Ok(merged)
+}
@@ -0,0 +1,54 @@
+// Prove a witness with the backend's native Groth16 prover.
+//
+// Takes the arguments of rapidsnark: the proving key (a snarkjs zkey), the
+// witness (.wtns) and the paths to write proof.json and public.json to, so
+// it can also stand in as SHADOW_PROVER. Exits 0 once both files are written
+// and 2 when a file cannot be read, parsed or written, or the witness does
+// not fit the key. The files are in snarkjs' layout, so `snarkjs groth16
+// verify` and verify_proof accept them.
+
+use backend::{
+    groth16,
+    groth16_prover::{self, ProvingKey, Witness},
+};
+use std::{fs, process::ExitCode, time::Instant};
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [zkey_path, witness_path, proof_path, public_path] = args.as_slice() else {
+        println!("Usage: prove_native <circuit.zkey> <witness.wtns> <proof.json> <public.json>");
+        return ExitCode::from(2);
+    };
+
+    let started = Instant::now();
+    let proved = read(zkey_path).and_then(|zkey| {
+        let key = ProvingKey::from_zkey(&zkey).map_err(|e| format!("{}: {}", zkey_path, e))?;
+        let witness = Witness::from_wtns(&read(witness_path)?)
+            .map_err(|e| format!("{}: {}", witness_path, e))?;
+        groth16_prover::prove(&key, &witness).map_err(|e| format!("{}: {}", witness_path, e))
+    });
+    let (proof, public) = match proved {
+        Ok(proved) => proved,
+        Err(e) => {
+            println!("{}", e);
+            return ExitCode::from(2);
+        }
+    };
+
+    let written = write(proof_path, &proof.to_snarkjs())
+        .and_then(|_| write(public_path, &groth16::public_signals_to_snarkjs(&public)));
+    if let Err(e) = written {
+        println!("{}", e);
+        return ExitCode::from(2);
+    }
+    println!("Proved in {:.3} s", started.elapsed().as_secs_f64());
+    ExitCode::SUCCESS
+}
+
+fn read(path: &str) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|e| format!("Cannot read {}: {}", path, e))
+}
+
+fn write(path: &str, contents: &str) -> Result<(), String> {
+    fs::write(path, contents).map_err(|e| format!("Cannot write {}: {}", path, e))
+}
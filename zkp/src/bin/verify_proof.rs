@@ -0,0 +1,79 @@
+// Verify a snarkjs Groth16 proof with the backend's in-process verifier.
+//
+// Takes the arguments of `snarkjs groth16 verify`: the verification key, the
+// public signals and the proof. Exits 0 when the proof verifies, 1 when it
+// does not and 2 when a file cannot be read or parsed. With --repeat N the
+// proof is verified N times and the mean time of a verification is printed,
+// which bench_verify.sh uses to benchmark the verifier.
+
+use backend::groth16::{self, Groth16Proof, VerificationKey};
+use std::{fs, process::ExitCode, time::Instant};
+
+fn main() -> ExitCode {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let mut repeat = 1;
+    if let Some(at) = args.iter().position(|arg| arg == "--repeat") {
+        let count = args.get(at + 1).and_then(|count| count.parse().ok()).filter(|n| *n > 0);
+        let Some(count) = count else {
+            println!("--repeat needs a positive number of verifications");
+            return ExitCode::from(2);
+        };
+        repeat = count;
+        args.drain(at..at + 2);
+    }
+    let [vkey_path, public_path, proof_path] = args.as_slice() else {
+        println!(
+            "Usage: verify_proof [--repeat N] <verification_key.json> <public.json> <proof.json>"
+        );
+        return ExitCode::from(2);
+    };
+
+    let parsed = read(vkey_path).and_then(|vkey| {
+        let vkey =
+            VerificationKey::from_snarkjs(&vkey).map_err(|e| format!("{}: {}", vkey_path, e))?;
+        let public = groth16::parse_public_signals(&read(public_path)?)
+            .map_err(|e| format!("{}: {}", public_path, e))?;
+        let proof = Groth16Proof::from_snarkjs(&read(proof_path)?)
+            .map_err(|e| format!("{}: {}", proof_path, e))?;
+        Ok((vkey, public, proof))
+    });
+    let (vkey, public, proof) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            println!("{}", e);
+            return ExitCode::from(2);
+        }
+    };
+
+    let started = Instant::now();
+    let mut verified = Ok(false);
+    for _ in 0..repeat {
+        verified = groth16::verify_groth16(&vkey, &proof, &public);
+    }
+    let elapsed = started.elapsed();
+    if repeat > 1 {
+        println!(
+            "{} verifications, {:.3} ms each",
+            repeat,
+            elapsed.as_secs_f64() * 1000.0 / repeat as f64
+        );
+    }
+    match verified {
+        Ok(true) => {
+            println!("OK");
+            ExitCode::SUCCESS
+        }
+        Ok(false) => {
+            println!("Invalid proof");
+            ExitCode::from(1)
+        }
+        Err(e) => {
+            println!("{}", e);
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn read(path: &str) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path, e))
+}
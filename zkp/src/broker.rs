@@ -0,0 +1,377 @@
+// Publishing measurement events to a message broker.
+//
+// When BROKER is set, every event on the event bus is forwarded to the
+// BROKER_TOPIC subject (NATS) or topic (Kafka) as a versioned JSON document
+// keyed by measurement id. Delivery is at least once: events are buffered in
+// memory while the broker is unreachable, failed publishes are retried with
+// exponential backoff, and events that still fail after BROKER_MAX_ATTEMPTS
+// are appended to the dead-letter file for replay. Each client is compiled in
+// only with its cargo feature (nats, kafka).
+
+use futures_util::future::BoxFuture;
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::json;
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::{broadcast, mpsc};
+
+use crate::{AppState, audit::AuditLog, config::Config, events::MeasurementEvent, schemas};
+
+// Version of the published document and its schema, bumped on incompatible changes
+pub const EVENT_SCHEMA_VERSION: u32 = 3;
+
+// Longest a single publish may take before it counts as failed
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(10);
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BrokerKind {
+    None,
+    Nats,
+    Kafka,
+}
+
+impl FromStr for BrokerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(BrokerKind::None),
+            "nats" => Ok(BrokerKind::Nats),
+            "kafka" => Ok(BrokerKind::Kafka),
+            other => Err(format!("unknown broker '{}', expected none, nats or kafka", other)),
+        }
+    }
+}
+
+impl fmt::Display for BrokerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BrokerKind::None => write!(f, "none"),
+            BrokerKind::Nats => write!(f, "nats"),
+            BrokerKind::Kafka => write!(f, "kafka"),
+        }
+    }
+}
+
+// Client for one kind of broker
+pub trait BrokerPublisher: Send + Sync {
+    // Publish a message and resolve once the broker has accepted it
+    fn publish<'a>(
+        &'a self,
+        topic: &'a str,
+        key: &'a str,
+        payload: Vec<u8>,
+    ) -> BoxFuture<'a, Result<(), String>>;
+
+    // Check that the broker is reachable, for readiness
+    fn check(&self) -> BoxFuture<'_, Result<(), String>>;
+}
+
+// Document published for each event
+#[derive(Serialize, JsonSchema)]
+pub struct BrokerEvent<'a> {
+    schema_version: u32,
+    // URL of the JSON Schema the document follows
+    schema: &'a str,
+    #[serde(flatten)]
+    event: &'a MeasurementEvent,
+}
+
+// Broker figures for the admin stats endpoint
+#[derive(Serialize)]
+pub struct BrokerStats {
+    pub kind: BrokerKind,
+    pub topic: String,
+    pub published: u64,
+    pub failed_attempts: u64,
+    pub dead_lettered: u64,
+    // Events lost because the forwarder fell behind the event bus
+    pub dropped: u64,
+    pub last_latency_ms: u64,
+    pub average_latency_ms: u64,
+}
+
+pub struct Broker {
+    kind: BrokerKind,
+    topic: String,
+    max_attempts: u32,
+    schema_url: String,
+    publisher: Box<dyn BrokerPublisher>,
+    dead_letter: AuditLog,
+    published: AtomicU64,
+    failed_attempts: AtomicU64,
+    dead_lettered: AtomicU64,
+    dropped: AtomicU64,
+    last_latency_ms: AtomicU64,
+    total_latency_ms: AtomicU64,
+}
+
+impl Broker {
+    // Create the configured publisher, or None when the integration is disabled
+    pub async fn connect(config: &Config) -> Result<Option<Broker>, String> {
+        if config.broker == BrokerKind::None {
+            return Ok(None);
+        }
+        let publisher = create_publisher(config.broker, &config.broker_url).await?;
+
+        Ok(Some(Broker {
+            kind: config.broker,
+            topic: config.broker_topic.clone(),
+            max_attempts: config.broker_max_attempts,
+            schema_url: schemas::url(&config.public_base_url, "event"),
+            publisher,
+            dead_letter: AuditLog::new(config.broker_dead_letter_path.clone()),
+            published: AtomicU64::new(0),
+            failed_attempts: AtomicU64::new(0),
+            dead_lettered: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            last_latency_ms: AtomicU64::new(0),
+            total_latency_ms: AtomicU64::new(0),
+        }))
+    }
+
+    pub async fn check(&self) -> Result<(), String> {
+        self.publisher.check().await
+    }
+
+    pub fn stats(&self) -> BrokerStats {
+        let published = self.published.load(Ordering::Relaxed);
+        BrokerStats {
+            kind: self.kind,
+            topic: self.topic.clone(),
+            published,
+            failed_attempts: self.failed_attempts.load(Ordering::Relaxed),
+            dead_lettered: self.dead_lettered.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            last_latency_ms: self.last_latency_ms.load(Ordering::Relaxed),
+            average_latency_ms: self
+                .total_latency_ms
+                .load(Ordering::Relaxed)
+                .checked_div(published)
+                .unwrap_or(0),
+        }
+    }
+
+    // Publish one event, retrying until it is accepted or dead-lettered
+    async fn deliver(&self, event: &MeasurementEvent) {
+        let document =
+            BrokerEvent { schema_version: EVENT_SCHEMA_VERSION, schema: &self.schema_url, event };
+        let payload = match serde_json::to_vec(&document) {
+            Ok(payload) => payload,
+            Err(e) => {
+                println!("Failed to serialize event for measurement {}: {}", event.id, e);
+                return;
+            }
+        };
+
+        let mut backoff = INITIAL_BACKOFF;
+        for attempt in 1..=self.max_attempts {
+            let started = Instant::now();
+            let publish = self.publisher.publish(&self.topic, &event.id, payload.clone());
+            let result = tokio::time::timeout(PUBLISH_TIMEOUT, publish)
+                .await
+                .unwrap_or_else(|_| Err("publish timed out".to_string()));
+            match result {
+                Ok(()) => {
+                    let latency = started.elapsed().as_millis() as u64;
+                    self.published.fetch_add(1, Ordering::Relaxed);
+                    self.last_latency_ms.store(latency, Ordering::Relaxed);
+                    self.total_latency_ms.fetch_add(latency, Ordering::Relaxed);
+                    return;
+                }
+                Err(e) => {
+                    self.failed_attempts.fetch_add(1, Ordering::Relaxed);
+                    println!(
+                        "Publishing event for measurement {} failed (attempt {}/{}): {}",
+                        event.id, attempt, self.max_attempts, e
+                    );
+                    if attempt == self.max_attempts {
+                        self.dead_lettered.fetch_add(1, Ordering::Relaxed);
+                        self.dead_letter.record(
+                            "broker_dead_letter",
+                            json!({ "topic": self.topic, "error": e, "attempts": attempt, "event": document }),
+                        );
+                        return;
+                    }
+                }
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+}
+
+// Client for the given broker, if it was compiled in
+#[cfg_attr(not(any(feature = "nats", feature = "kafka")), allow(unused_variables))]
+async fn create_publisher(kind: BrokerKind, url: &str) -> Result<Box<dyn BrokerPublisher>, String> {
+    match kind {
+        #[cfg(feature = "nats")]
+        BrokerKind::Nats => Ok(Box::new(nats::NatsPublisher::connect(url).await?)),
+        #[cfg(feature = "kafka")]
+        BrokerKind::Kafka => Ok(Box::new(kafka::KafkaPublisher::connect(url)?)),
+        kind => Err(format!("BROKER={} requires building with the {} feature", kind, kind)),
+    }
+}
+
+// Start forwarding events from the bus to the broker. Events are moved to an
+// unbounded buffer right away so a slow or unreachable broker cannot make the
+// subscription lag behind and lose events.
+pub fn start(state: Arc<AppState>) {
+    let mut subscription = state.events.subscribe();
+    let (sender, mut buffer) = mpsc::unbounded_channel::<MeasurementEvent>();
+
+    let forwarder_state = state.clone();
+    tokio::spawn(async move {
+        loop {
+            match subscription.recv().await {
+                Ok(event) => {
+                    if sender.send(event).is_err() {
+                        return;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    if let Some(broker) = &forwarder_state.broker {
+                        broker.dropped.fetch_add(missed, Ordering::Relaxed);
+                    }
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        while let Some(event) = buffer.recv().await {
+            if let Some(broker) = &state.broker {
+                broker.deliver(&event).await;
+            }
+        }
+    });
+}
+
+#[cfg(feature = "nats")]
+mod nats {
+    use futures_util::{FutureExt, future::BoxFuture};
+
+    use super::BrokerPublisher;
+
+    pub struct NatsPublisher {
+        client: async_nats::Client,
+    }
+
+    impl NatsPublisher {
+        pub async fn connect(url: &str) -> Result<Self, String> {
+            let url = if url.is_empty() { "nats://localhost:4222" } else { url };
+            // Keep retrying in the background rather than failing startup
+            let client = async_nats::ConnectOptions::new()
+                .retry_on_initial_connect()
+                .connect(url)
+                .await
+                .map_err(|e| format!("Failed to connect to NATS at {}: {}", url, e))?;
+            Ok(NatsPublisher { client })
+        }
+    }
+
+    impl BrokerPublisher for NatsPublisher {
+        fn publish<'a>(
+            &'a self,
+            topic: &'a str,
+            _key: &'a str,
+            payload: Vec<u8>,
+        ) -> BoxFuture<'a, Result<(), String>> {
+            async move {
+                self.client
+                    .publish(topic.to_string(), payload.into())
+                    .await
+                    .map_err(|e| e.to_string())?;
+                // Publishing only buffers; flushing hands the message to the server
+                self.client.flush().await.map_err(|e| e.to_string())
+            }
+            .boxed()
+        }
+
+        fn check(&self) -> BoxFuture<'_, Result<(), String>> {
+            async move {
+                match self.client.connection_state() {
+                    async_nats::connection::State::Connected => Ok(()),
+                    state => Err(format!("NATS connection is {:?}", state)),
+                }
+            }
+            .boxed()
+        }
+    }
+}
+
+#[cfg(feature = "kafka")]
+mod kafka {
+    use futures_util::{FutureExt, future::BoxFuture};
+    use rdkafka::{
+        ClientConfig,
+        producer::{FutureProducer, FutureRecord, Producer},
+    };
+    use std::time::Duration;
+
+    use super::BrokerPublisher;
+
+    const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+    const METADATA_TIMEOUT: Duration = Duration::from_secs(2);
+
+    pub struct KafkaPublisher {
+        producer: FutureProducer,
+    }
+
+    impl KafkaPublisher {
+        pub fn connect(url: &str) -> Result<Self, String> {
+            let url = if url.is_empty() { "localhost:9092" } else { url };
+            let producer = ClientConfig::new()
+                .set("bootstrap.servers", url)
+                .set("message.timeout.ms", SEND_TIMEOUT.as_millis().to_string())
+                .create()
+                .map_err(|e| format!("Failed to create Kafka producer for {}: {}", url, e))?;
+            Ok(KafkaPublisher { producer })
+        }
+    }
+
+    impl BrokerPublisher for KafkaPublisher {
+        fn publish<'a>(
+            &'a self,
+            topic: &'a str,
+            key: &'a str,
+            payload: Vec<u8>,
+        ) -> BoxFuture<'a, Result<(), String>> {
+            async move {
+                let record = FutureRecord::to(topic).key(key).payload(&payload);
+                self.producer
+                    .send(record, SEND_TIMEOUT)
+                    .await
+                    .map(|_| ())
+                    .map_err(|(e, _)| e.to_string())
+            }
+            .boxed()
+        }
+
+        fn check(&self) -> BoxFuture<'_, Result<(), String>> {
+            let producer = self.producer.clone();
+            async move {
+                // Fetching metadata blocks, so keep it off the async workers
+                tokio::task::spawn_blocking(move || {
+                    producer.client().fetch_metadata(None, METADATA_TIMEOUT).map(|_| ())
+                })
+                .await
+                .map_err(|e| e.to_string())?
+                .map_err(|e| format!("Kafka is unreachable: {}", e))
+            }
+            .boxed()
+        }
+    }
+}
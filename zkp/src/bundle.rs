@@ -0,0 +1,340 @@
+// Import of measurements captured offline.
+//
+// Field devices without connectivity measure locally and later upload a
+// bundle: a zip archive holding
+//
+//   manifest.json  the measurement, capture time and SHA-256 of every file
+//   manifest.sig   hex Ed25519 signature of manifest.json's exact bytes
+//   <image>        the photo named by the manifest's image field
+//
+// The signature covers the manifest and the manifest covers every file, so a
+// bundle is only accepted if nothing was altered after signing. The signing
+// key must be one of IMPORT_TRUSTED_KEYS and the capture time must lie within
+// IMPORT_MAX_AGE_DAYS, give or take the clock skew of timecheck.rs. Problems
+// are reported per file so a device can tell a corrupt photo from a bad
+// signature. Accepted bundles go through the normal pipeline, keeping the
+// device's capture time as captured_at.
+
+use axum::{
+    body::Bytes,
+    extract::{ConnectInfo, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use chrono::{DateTime, Duration, Utc};
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    io::{Cursor, Read},
+    net::SocketAddr,
+    sync::Arc,
+};
+use zip::ZipArchive;
+
+use crate::{
+    AppState, MeasurementResponse, Point3D, Submission, access,
+    angle::MeasurementType,
+    auth::{Authorized, Submitter},
+    config::Config,
+    coords::{self, CoordinateSystem, OriginalPoints},
+    external_id,
+    metadata::{self, Metadata},
+    submit,
+    timecheck::ClockSkew,
+    uploads::IncomingImage,
+};
+
+// Bundle format version understood by this server
+pub const SCHEMA_VERSION: u32 = 1;
+const MANIFEST: &str = "manifest.json";
+const SIGNATURE: &str = "manifest.sig";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    schema_version: u32,
+    // Hex Ed25519 public key of the device that signed the bundle
+    public_key: String,
+    captured_at: DateTime<Utc>,
+    // Name of the photo inside the bundle
+    image: String,
+    // Hex SHA-256 of every file in the bundle besides the manifest and signature
+    files: BTreeMap<String, String>,
+    start_point: Point3D,
+    end_point: Point3D,
+    // Space the points were captured in, ARKit world space when absent
+    #[serde(default)]
+    coordinate_system: Option<String>,
+    #[serde(default)]
+    metadata: Option<Value>,
+    #[serde(default)]
+    external_id: Option<String>,
+}
+
+// A problem with one file of a bundle
+#[derive(Debug, Serialize)]
+pub struct FileError {
+    file: String,
+    error: String,
+}
+
+impl FileError {
+    fn new(file: &str, error: impl Into<String>) -> Self {
+        FileError { file: file.to_string(), error: error.into() }
+    }
+}
+
+#[derive(Serialize)]
+struct BundleErrors {
+    errors: Vec<FileError>,
+}
+
+// Contents of a bundle that passed validation
+struct Bundle {
+    image: Vec<u8>,
+    start_point: Point3D,
+    end_point: Point3D,
+    original_points: OriginalPoints,
+    coordinate_system: CoordinateSystem,
+    metadata: Metadata,
+    external_id: Option<String>,
+    captured_at: DateTime<Utc>,
+}
+
+// Handler importing an offline bundle sent as the request body
+pub async fn import_bundle(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Submitter>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    body: Bytes,
+) -> Result<(StatusCode, Json<MeasurementResponse>), Response> {
+    if state.config.import_trusted_keys.is_empty() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Bundle import is disabled: no trusted keys are configured".to_string(),
+        )
+            .into_response());
+    }
+
+    let task_state = state.clone();
+    let bundle = tokio::task::spawn_blocking(move || {
+        open(&body, &task_state.config, &task_state.clock, Utc::now())
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response())?
+    .map_err(|errors| (StatusCode::BAD_REQUEST, Json(BundleErrors { errors })).into_response())?;
+
+    let submission = Submission {
+        owner: access::submitter(&caller, remote.ip()),
+        key_id: caller.key_id,
+        tenant: caller.tenant,
+        external_id: bundle.external_id,
+        idempotency_key: None,
+        image: IncomingImage::Bytes(Bytes::from(bundle.image)),
+        image2: None,
+        depth_map: None,
+        start_point: bundle.start_point,
+        end_point: bundle.end_point,
+        points: Vec::new(),
+        measurement_type: MeasurementType::Length,
+        mid_point: None,
+        min_length_cm: None,
+        original_points: Some(bundle.original_points),
+        coordinate_system: bundle.coordinate_system,
+        metadata: bundle.metadata,
+        client_info: None,
+        deadline: None,
+        publish_at: None,
+        captured_at: Some(bundle.captured_at),
+        imported: true,
+        reprove_of: None,
+        circuit: None,
+        callback_url: None,
+        skip_stages: Vec::new(),
+    };
+    submit(&state, submission).await.map_err(IntoResponse::into_response)
+}
+
+// Unpack and validate a bundle, collecting every problem found
+fn open(
+    data: &[u8],
+    config: &Config,
+    clock: &ClockSkew,
+    now: DateTime<Utc>,
+) -> Result<Bundle, Vec<FileError>> {
+    let mut archive = ZipArchive::new(Cursor::new(data))
+        .map_err(|e| vec![FileError::new("bundle", format!("Not a valid zip archive: {}", e))])?;
+
+    let mut errors = Vec::new();
+    let mut files = BTreeMap::new();
+    for index in 0..archive.len() {
+        let mut entry = match archive.by_index(index) {
+            Ok(entry) => entry,
+            Err(e) => {
+                errors.push(FileError::new(&format!("entry {}", index), e.to_string()));
+                continue;
+            }
+        };
+        if entry.is_dir() {
+            continue;
+        }
+
+        // Bound decompression so a small archive cannot expand without limit
+        let name = entry.name().to_string();
+        let mut content = Vec::new();
+        let limit = config.import_max_bytes as u64;
+        match entry.by_ref().take(limit + 1).read_to_end(&mut content) {
+            Ok(_) if content.len() as u64 > limit => {
+                errors.push(FileError::new(&name, format!("Larger than {} bytes", limit)))
+            }
+            Ok(_) => {
+                files.insert(name, content);
+            }
+            Err(e) => errors.push(FileError::new(&name, format!("Failed to extract: {}", e))),
+        }
+    }
+
+    let Some(manifest_bytes) = files.remove(MANIFEST) else {
+        errors.push(FileError::new(MANIFEST, "Missing"));
+        return Err(errors);
+    };
+    let manifest: Manifest = match serde_json::from_slice(&manifest_bytes) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            errors.push(FileError::new(MANIFEST, format!("Invalid manifest: {}", e)));
+            return Err(errors);
+        }
+    };
+
+    if manifest.schema_version != SCHEMA_VERSION {
+        errors.push(FileError::new(
+            MANIFEST,
+            format!(
+                "Unsupported schema_version {}, expected {}",
+                manifest.schema_version, SCHEMA_VERSION
+            ),
+        ));
+    }
+
+    let signed = match files.remove(SIGNATURE) {
+        Some(signature) => match verify_signature(&manifest, &manifest_bytes, &signature, config) {
+            Ok(()) => true,
+            Err(e) => {
+                errors.push(FileError::new(SIGNATURE, e));
+                false
+            }
+        },
+        None => {
+            errors.push(FileError::new(SIGNATURE, "Missing"));
+            false
+        }
+    };
+
+    // Only a trusted device's capture time tells about its clock
+    if signed {
+        let device = format!("device {}", manifest.public_key);
+        clock.observe(&device, manifest.captured_at, now);
+    }
+    let captured = clock.not_newer_than(manifest.captured_at, now).and_then(|()| {
+        clock.not_older_than(manifest.captured_at, now, Duration::days(config.import_max_age_days))
+    });
+    if let Err(e) = captured {
+        errors.push(FileError::new(MANIFEST, format!("captured_at {}", e)));
+    }
+
+    // Every listed file must be present and intact, and nothing else may be
+    for (name, expected) in &manifest.files {
+        match files.get(name) {
+            Some(content)
+                if !hex::encode(Sha256::digest(content)).eq_ignore_ascii_case(expected) =>
+            {
+                errors.push(FileError::new(name, "SHA-256 does not match the manifest"))
+            }
+            Some(_) => {}
+            None => errors.push(FileError::new(name, "Listed in the manifest but missing")),
+        }
+    }
+    for name in files.keys().filter(|name| !manifest.files.contains_key(*name)) {
+        errors.push(FileError::new(name, "Not listed in the manifest"));
+    }
+    if !manifest.files.contains_key(&manifest.image) {
+        errors.push(FileError::new(&manifest.image, "Image is not listed in the manifest"));
+    }
+
+    let coordinate_system = coords::coordinate_system(
+        manifest.coordinate_system.as_deref(),
+        config.strict_coordinate_system,
+    )
+    .map_err(|e| errors.push(FileError::new(MANIFEST, e)))
+    .unwrap_or_default();
+    let start_point = coords::canonical_point(coordinate_system, &manifest.start_point)
+        .map_err(|e| errors.push(FileError::new(MANIFEST, format!("Invalid start point: {}", e))));
+    let end_point = coords::canonical_point(coordinate_system, &manifest.end_point)
+        .map_err(|e| errors.push(FileError::new(MANIFEST, format!("Invalid end point: {}", e))));
+    let metadata = match &manifest.metadata {
+        Some(value) => metadata::parse(value.to_string().as_bytes())
+            .map_err(|e| errors.push(FileError::new(MANIFEST, e))),
+        None => Ok(Metadata::default()),
+    };
+    let external_id = manifest
+        .external_id
+        .as_deref()
+        .map(external_id::validate)
+        .transpose()
+        .map_err(|e| errors.push(FileError::new(MANIFEST, e)));
+
+    match (start_point, end_point, metadata, external_id, files.remove(&manifest.image)) {
+        (Ok(start_point), Ok(end_point), Ok(metadata), Ok(external_id), Some(image))
+            if errors.is_empty() =>
+        {
+            Ok(Bundle {
+                image,
+                start_point,
+                end_point,
+                original_points: OriginalPoints {
+                    start: manifest.start_point,
+                    end: manifest.end_point,
+                    mid: None,
+                    points: Vec::new(),
+                },
+                coordinate_system,
+                metadata,
+                external_id,
+                captured_at: manifest.captured_at,
+            })
+        }
+        _ => Err(errors),
+    }
+}
+
+// Check the manifest signature against the trusted device keys
+fn verify_signature(
+    manifest: &Manifest,
+    manifest_bytes: &[u8],
+    signature: &[u8],
+    config: &Config,
+) -> Result<(), String> {
+    let public_key = manifest.public_key.trim().to_ascii_lowercase();
+    if !config.import_trusted_keys.iter().any(|key| key.eq_ignore_ascii_case(&public_key)) {
+        return Err("Signed with a key that is not trusted".to_string());
+    }
+
+    let key_bytes: [u8; 32] = hex::decode(&public_key)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or("public_key must be 32 hex-encoded bytes")?;
+    let key =
+        VerifyingKey::from_bytes(&key_bytes).map_err(|e| format!("Invalid public key: {}", e))?;
+
+    let signature_bytes: [u8; 64] = std::str::from_utf8(signature)
+        .ok()
+        .and_then(|text| hex::decode(text.trim()).ok())
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or("Signature must be 64 hex-encoded bytes")?;
+
+    key.verify_strict(manifest_bytes, &Signature::from_bytes(&signature_bytes))
+        .map_err(|_| "Signature does not match the manifest".to_string())
+}
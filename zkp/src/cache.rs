@@ -0,0 +1,424 @@
+// Disk budget for regenerable image variants.
+//
+// Thumbnails and WebP re-encodings are derived from the stored original, so
+// they are a cache: any of them can be deleted and is rendered again on the
+// next request. This manager tracks every variant file with its size and last
+// access and keeps them within CACHE_MAX_BYTES overall and within an optional
+// budget per category, evicting the least recently used first. Variants not
+// requested for CACHE_TTL_SECS are evicted whatever the budget.
+//
+// Access times are saved to CACHE_INDEX_PATH, so the eviction order survives
+// restarts; files found on disk without an entry count as last used when they
+// were written. A variant is pinned while it is rendered and opened for a
+// response, and pinned files are never evicted. Originals and proof files are
+// never managed here.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    sync::{Arc, Mutex},
+};
+
+use crate::{AppState, artifacts::Artifact, config::Config};
+
+// Interval between TTL sweeps and saves of the access index
+const SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    Thumbnail,
+    Webp,
+}
+
+impl Category {
+    const ALL: [Category; 2] = [Category::Thumbnail, Category::Webp];
+
+    fn of(artifact: Artifact) -> Option<Self> {
+        match artifact {
+            Artifact::Thumbnail(_) => Some(Category::Thumbnail),
+            Artifact::Webp => Some(Category::Webp),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Category::Thumbnail => "thumbnail",
+            Category::Webp => "webp",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    id: String,
+    // Thumbnail size in pixels, None for the WebP variant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    size: Option<u32>,
+    bytes: u64,
+    last_access: DateTime<Utc>,
+}
+
+impl Entry {
+    fn artifact(&self) -> Artifact {
+        match self.size {
+            Some(size) => Artifact::Thumbnail(size),
+            None => Artifact::Webp,
+        }
+    }
+
+    fn category(&self) -> Category {
+        if self.size.is_some() { Category::Thumbnail } else { Category::Webp }
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+    evicted_bytes: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    // Keyed by file path
+    entries: HashMap<String, Entry>,
+    pinned: HashMap<String, usize>,
+    counters: HashMap<Category, Counters>,
+    // Whether entries changed since the index was last saved
+    dirty: bool,
+}
+
+pub struct DiskCache {
+    index_path: String,
+    max_bytes: u64,
+    category_max_bytes: HashMap<Category, u64>,
+    ttl: Option<Duration>,
+    inner: Mutex<Inner>,
+}
+
+// Cache figures for the admin stats endpoint
+#[derive(Serialize)]
+pub struct CacheStats {
+    max_bytes: u64,
+    bytes: u64,
+    entries: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ttl_secs: Option<i64>,
+    categories: BTreeMap<&'static str, CategoryStats>,
+}
+
+#[derive(Serialize)]
+struct CategoryStats {
+    bytes: u64,
+    entries: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_bytes: Option<u64>,
+    hits: u64,
+    misses: u64,
+    // Share of requests served from an existing file, None before any request
+    hit_rate: Option<f64>,
+    evictions: u64,
+    evicted_bytes: u64,
+}
+
+// Keeps a variant from being evicted while it is alive
+pub struct Pin<'a> {
+    cache: &'a DiskCache,
+    path: String,
+}
+
+impl Drop for Pin<'_> {
+    fn drop(&mut self) {
+        let mut inner = self.cache.inner.lock().unwrap();
+        if let Some(count) = inner.pinned.get_mut(&self.path) {
+            *count -= 1;
+            if *count == 0 {
+                inner.pinned.remove(&self.path);
+            }
+        }
+    }
+}
+
+impl DiskCache {
+    // Restore the access index and reconcile it with the variants on disk
+    pub fn load(config: &Config) -> Result<Self, String> {
+        let index_path = config.cache_index_path.clone();
+        let mut entries: HashMap<String, Entry> = match fs::read_to_string(&index_path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| format!("Invalid cache index {}: {}", index_path, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(format!("Failed to read cache index {}: {}", index_path, e)),
+        };
+        let on_disk = scan_variants();
+        entries.retain(|path, _| on_disk.contains_key(path));
+        for (path, entry) in on_disk {
+            let known = entries.entry(path).or_insert_with(|| entry.clone());
+            known.bytes = entry.bytes;
+        }
+
+        let category_max_bytes = [
+            (Category::Thumbnail, config.cache_thumbnail_max_bytes),
+            (Category::Webp, config.cache_webp_max_bytes),
+        ]
+        .into_iter()
+        .filter_map(|(category, max)| max.map(|max| (category, max)))
+        .collect();
+
+        Ok(DiskCache {
+            index_path,
+            max_bytes: config.cache_max_bytes,
+            category_max_bytes,
+            ttl: (config.cache_ttl_secs > 0).then(|| Duration::seconds(config.cache_ttl_secs)),
+            inner: Mutex::new(Inner { entries, dirty: true, ..Inner::default() }),
+        })
+    }
+
+    // Protect a variant from eviction until the pin is dropped
+    pub fn pin(&self, id: &str, artifact: Artifact) -> Pin<'_> {
+        let path = artifact.path(id);
+        *self.inner.lock().unwrap().pinned.entry(path.clone()).or_insert(0) += 1;
+        Pin { cache: self, path }
+    }
+
+    // Note that a variant was requested; hit is whether it existed already
+    pub fn accessed(&self, id: &str, artifact: Artifact, hit: bool) {
+        let Some(category) = Category::of(artifact) else {
+            return;
+        };
+        let mut inner = self.inner.lock().unwrap();
+        let counters = inner.counters.entry(category).or_default();
+        if hit {
+            counters.hits += 1;
+        } else {
+            counters.misses += 1;
+        }
+        self.track(&mut inner, id, artifact);
+    }
+
+    // Note a variant rendered ahead of any request
+    pub fn stored(&self, id: &str, artifact: Artifact) {
+        if Category::of(artifact).is_some() {
+            self.track(&mut self.inner.lock().unwrap(), id, artifact);
+        }
+    }
+
+    fn track(&self, inner: &mut Inner, id: &str, artifact: Artifact) {
+        let path = artifact.path(id);
+        let Ok(meta) = fs::metadata(&path) else {
+            return;
+        };
+        let size = match artifact {
+            Artifact::Thumbnail(size) => Some(size),
+            _ => None,
+        };
+        let entry = Entry { id: id.to_string(), size, bytes: meta.len(), last_access: Utc::now() };
+        inner.entries.insert(path, entry);
+        inner.dirty = true;
+    }
+
+    // Stop tracking the variants of a deleted measurement
+    pub fn forget(&self, id: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.entries.retain(|_, entry| entry.id != id);
+        inner.dirty = true;
+    }
+
+    fn over_budget(&self, inner: &Inner) -> bool {
+        let mut total = 0;
+        let mut per_category: HashMap<Category, u64> = HashMap::new();
+        for entry in inner.entries.values() {
+            total += entry.bytes;
+            *per_category.entry(entry.category()).or_default() += entry.bytes;
+        }
+        total > self.max_bytes
+            || self.category_max_bytes.iter().any(|(category, max)| {
+                per_category.get(category).copied().unwrap_or_default() > *max
+            })
+    }
+
+    // Evict expired variants, then least recently used ones until every
+    // budget is met, returning what was evicted as (id, artifact)
+    fn evict(&self) -> Vec<(String, Artifact)> {
+        let mut inner = self.inner.lock().unwrap();
+        let now = Utc::now();
+        let expired = |entry: &Entry| self.ttl.is_some_and(|ttl| now - entry.last_access > ttl);
+        if !inner.entries.values().any(expired) && !self.over_budget(&inner) {
+            return Vec::new();
+        }
+
+        // Oldest access first; pinned variants stay whatever their age
+        let mut candidates: Vec<(String, Entry)> = inner
+            .entries
+            .iter()
+            .filter(|(path, _)| !inner.pinned.contains_key(*path))
+            .map(|(path, entry)| (path.clone(), entry.clone()))
+            .collect();
+        candidates.sort_by(|a, b| a.1.last_access.cmp(&b.1.last_access).then(a.0.cmp(&b.0)));
+
+        let mut total: u64 = inner.entries.values().map(|e| e.bytes).sum();
+        let mut per_category: HashMap<Category, u64> = HashMap::new();
+        for entry in inner.entries.values() {
+            *per_category.entry(entry.category()).or_default() += entry.bytes;
+        }
+
+        let mut evicted = Vec::new();
+        for (path, entry) in candidates {
+            let category = entry.category();
+            let category_over = self
+                .category_max_bytes
+                .get(&category)
+                .is_some_and(|max| per_category.get(&category).copied().unwrap_or_default() > *max);
+            if !expired(&entry) && total <= self.max_bytes && !category_over {
+                continue;
+            }
+            match fs::remove_file(&path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    println!("Failed to evict {}: {}", path, e);
+                    continue;
+                }
+            }
+            inner.entries.remove(&path);
+            total -= entry.bytes;
+            *per_category.entry(category).or_default() -= entry.bytes;
+            let counters = inner.counters.entry(category).or_default();
+            counters.evictions += 1;
+            counters.evicted_bytes += entry.bytes;
+            evicted.push((entry.id.clone(), entry.artifact()));
+        }
+        inner.dirty = true;
+        evicted
+    }
+
+    // Write the access index if it changed since the last save
+    fn save(&self) -> io::Result<()> {
+        let content = {
+            let mut inner = self.inner.lock().unwrap();
+            if !inner.dirty {
+                return Ok(());
+            }
+            inner.dirty = false;
+            serde_json::to_string(&inner.entries)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        };
+        let temp_path = format!("{}.tmp", self.index_path);
+        fs::write(&temp_path, content)?;
+        fs::rename(&temp_path, &self.index_path)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let inner = self.inner.lock().unwrap();
+        let categories = Category::ALL
+            .into_iter()
+            .map(|category| {
+                let entries: Vec<&Entry> =
+                    inner.entries.values().filter(|e| e.category() == category).collect();
+                let counters = inner.counters.get(&category);
+                let hits = counters.map_or(0, |c| c.hits);
+                let misses = counters.map_or(0, |c| c.misses);
+                let stats = CategoryStats {
+                    bytes: entries.iter().map(|e| e.bytes).sum(),
+                    entries: entries.len(),
+                    max_bytes: self.category_max_bytes.get(&category).copied(),
+                    hits,
+                    misses,
+                    hit_rate: (hits + misses > 0).then(|| hits as f64 / (hits + misses) as f64),
+                    evictions: counters.map_or(0, |c| c.evictions),
+                    evicted_bytes: counters.map_or(0, |c| c.evicted_bytes),
+                };
+                (category.name(), stats)
+            })
+            .collect();
+        CacheStats {
+            max_bytes: self.max_bytes,
+            bytes: inner.entries.values().map(|e| e.bytes).sum(),
+            entries: inner.entries.len(),
+            ttl_secs: self.ttl.map(|ttl| ttl.num_seconds()),
+            categories,
+        }
+    }
+}
+
+// Bring the cache within its budgets and update the artifacts of measurements
+// that lost a variant. Nothing is deleted during maintenance.
+pub fn enforce(state: &AppState) {
+    if state.maintenance.is_on() {
+        return;
+    }
+    let evicted = state.cache.evict();
+    if evicted.is_empty() {
+        return;
+    }
+    println!("Evicted {} cached image variants", evicted.len());
+    let mut measurements = state.measurements.lock();
+    for (id, artifact) in evicted {
+        if let Some(m) = measurements.get_mut(&id) {
+            m.artifacts.record(&id, artifact);
+        }
+    }
+}
+
+// Background task evicting expired variants and saving the access index
+pub async fn sweeper(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let task_state = state.clone();
+        let _ = tokio::task::spawn_blocking(move || {
+            enforce(&task_state);
+            if let Err(e) = task_state.cache.save() {
+                println!("Failed to save cache index {}: {}", task_state.cache.index_path, e);
+            }
+        })
+        .await;
+    }
+}
+
+// Image variants currently on disk, as last used when they were written
+fn scan_variants() -> HashMap<String, Entry> {
+    let mut dirs = vec!["uploads".to_string()];
+    if let Ok(entries) = fs::read_dir("uploads") {
+        for entry in entries.flatten() {
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                dirs.push(format!("uploads/{}", entry.file_name().to_string_lossy()));
+            }
+        }
+    }
+
+    let mut found = HashMap::new();
+    for dir in dirs {
+        let Ok(files) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for file in files.flatten() {
+            let name = file.file_name().to_string_lossy().to_string();
+            let Some((id, size)) = parse_variant_name(&name) else {
+                continue;
+            };
+            let Ok(meta) = file.metadata() else {
+                continue;
+            };
+            let last_access =
+                meta.modified().map(DateTime::<Utc>::from).unwrap_or_else(|_| Utc::now());
+            let entry = Entry { id: id.to_string(), size, bytes: meta.len(), last_access };
+            found.insert(format!("{}/{}", dir, name), entry);
+        }
+    }
+    found
+}
+
+// Measurement id and thumbnail size of a variant file name, as written by
+// Artifact::path: {id}_thumb_{size}.jpg or {id}.webp
+fn parse_variant_name(name: &str) -> Option<(&str, Option<u32>)> {
+    if let Some(id) = name.strip_suffix(".webp") {
+        return Some((id, None));
+    }
+    let (id, size) = name.strip_suffix(".jpg")?.split_once("_thumb_")?;
+    Some((id, Some(size.parse().ok()?)))
+}
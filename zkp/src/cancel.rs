@@ -0,0 +1,167 @@
+// Cancelling measurements.
+//
+// A measurement the client no longer wants, for example because the user
+// deleted it on their phone, can be withdrawn with POST
+// /measurements/{id}/cancel until it finishes, and ends as Cancelled. One
+// still waiting, Scheduled or Pending, only changes its status and leaves the
+// queue. One being proved or submitted also has its work stopped: the worker
+// holds a token for it while the measurement is Processing, and cancelling
+// trips the token, upon which the worker drops the running step, which kills
+// the node or snarkjs process it started, removes what the attempt left in
+// proofs/{id} but input.json, and leaves the status alone. A measurement
+// awaiting its attestation is no longer waited for. Completed, Failed and
+// already cancelled measurements are answered with 409.
+//
+// Tokens only reach the work of this process: in a shared store a proof
+// running on another instance goes on, but the status it would write is
+// dropped, as the compare-and-set sees Cancelled instead of Processing. What
+// the instance cancelling holds in proofs/{id} is removed either way.
+
+use axum::{
+    Json,
+    extract::{ConnectInfo, Path, State},
+    http::StatusCode,
+};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    AppState, ProofStatus,
+    access::{self, Managers},
+    artifacts::{self, Artifact},
+    auth::{Authorized, Submitter},
+    events::EventKind,
+    history::Source,
+    plan::{self, StageState},
+};
+
+// Tokens of the measurements this process is proving or submitting, by ID
+#[derive(Default)]
+pub struct Cancellations {
+    tokens: Arc<Mutex<HashMap<String, CancellationToken>>>,
+}
+
+// The token of one measurement's work, given up when dropped
+pub struct Cancellable {
+    id: String,
+    token: CancellationToken,
+    tokens: Arc<Mutex<HashMap<String, CancellationToken>>>,
+}
+
+impl Cancellations {
+    // Hold a token for the work on a measurement until the returned guard is dropped
+    pub fn register(&self, id: &str) -> Cancellable {
+        let token = CancellationToken::new();
+        self.tokens.lock().unwrap().insert(id.to_string(), token.clone());
+        Cancellable { id: id.to_string(), token, tokens: self.tokens.clone() }
+    }
+
+    // Stop the work on a measurement, returning whether any was running
+    fn cancel(&self, id: &str) -> bool {
+        match self.tokens.lock().unwrap().get(id) {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Cancellable {
+    // Resolves once the measurement was cancelled
+    pub async fn cancelled(&self) {
+        self.token.cancelled().await
+    }
+}
+
+impl Drop for Cancellable {
+    fn drop(&mut self) {
+        self.tokens.lock().unwrap().remove(&self.id);
+    }
+}
+
+// Remove what a cancelled attempt left in the proof directory and record the
+// files that are gone. Called once nothing works on the measurement anymore.
+pub async fn clean_up(state: &AppState, id: &str) {
+    let task_id = id.to_string();
+    let cleaned = tokio::task::spawn_blocking(move || artifacts::clean_proof_dir(&task_id))
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|cleaned| cleaned);
+    if let Err(e) = cleaned {
+        println!("Failed to clean up cancelled measurement {}: {}", id, e);
+    }
+    let mut measurements = state.measurements.lock();
+    if let Some(m) = measurements.get_mut(id) {
+        m.artifacts.record_all(id, &Artifact::PROOF_OUTPUTS);
+        m.artifacts.record_all(id, &[Artifact::Attestation, Artifact::SubmissionPayload]);
+    }
+}
+
+#[derive(Serialize)]
+pub struct CancelResponse {
+    measurement_id: String,
+    status: ProofStatus,
+    // The status the measurement was cancelled in
+    previous_status: ProofStatus,
+}
+
+// Handler letting the submitter, or an admin, withdraw an unfinished measurement
+pub async fn cancel(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Submitter>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    Path(id): Path<String>,
+) -> Result<Json<CancelResponse>, (StatusCode, String)> {
+    let previous_status = {
+        let mut measurements = state.measurements.lock_as(Source::Cancellation);
+        // Checked on a shared reference first, so a refused cancel changes
+        // nothing the guard would write back
+        let m = access::managed(
+            measurements.get(&id),
+            &caller,
+            remote.ip(),
+            &id,
+            Managers::SubmitterOrAdmin,
+            "cancel a measurement",
+        )?;
+        let previous_status = m.status.clone();
+        if matches!(
+            previous_status,
+            ProofStatus::Completed | ProofStatus::Failed | ProofStatus::Cancelled
+        ) {
+            return Err((
+                StatusCode::CONFLICT,
+                format!("Measurement {} is {:?} and can no longer be cancelled", id, m.status),
+            ));
+        }
+        let Some(m) = measurements.get_mut(&id) else {
+            return Err(access::not_found(&id));
+        };
+        plan::end_running(m, StageState::Cancelled, None);
+        m.status = ProofStatus::Cancelled;
+        state.events.publish(m, EventKind::StatusChanged);
+        previous_status
+    };
+
+    state.queue.remove(&id);
+    state.deadlines.forget(&id);
+    if previous_status == ProofStatus::Processing {
+        state.attestations.forget(&id);
+        // Without a running step here, such as while the attestation is
+        // awaited or when another instance proves it, this instance's files
+        // can go at once; the worker of another instance keeps its own
+        if !state.cancellations.cancel(&id) {
+            clean_up(&state, &id).await;
+        }
+    }
+    println!("Measurement {} cancelled while {:?}", id, previous_status);
+
+    Ok(Json(CancelResponse { measurement_id: id, status: ProofStatus::Cancelled, previous_status }))
+}
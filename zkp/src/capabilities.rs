@@ -0,0 +1,284 @@
+// Machine-readable description of what this deployment supports.
+//
+// Clients gate optional UI on GET /capabilities instead of comparing version
+// numbers, so the document is built from the live configuration, the compiled
+// cargo features and the circuit registry. Every enabled feature lists the
+// routes it relies on; test_capabilities.sh checks those against the router,
+// so a flag can never claim a feature the server does not serve.
+
+use axum::{extract::State, response::Json};
+use serde::Serialize;
+use std::{collections::BTreeMap, sync::Arc};
+
+use crate::{
+    AppState,
+    attestation_backend::BackendKind,
+    circuit::{self, Encoding, Signal},
+    circuit_artifacts, client_info,
+    coords::{MAX_SCALED_MAGNITUDE, SCALE},
+    external_id, listing, metadata,
+    native_prover::ProverKind,
+    similarity::DuplicateMode,
+    uploads,
+    version::SERVER_VERSION,
+};
+
+// Content types accepted for the measurement image
+const IMAGE_CONTENT_TYPES: [&str; 1] = ["image/jpeg"];
+
+#[derive(Serialize)]
+pub struct Capabilities {
+    server_version: &'static str,
+    features: BTreeMap<&'static str, Feature>,
+    limits: Limits,
+    circuits: Vec<CircuitInfo>,
+    image_content_types: &'static [&'static str],
+}
+
+#[derive(Serialize)]
+struct Feature {
+    enabled: bool,
+    // Routes the feature adds, as "METHOD /path"
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    endpoints: &'static [&'static str],
+}
+
+#[derive(Serialize)]
+struct Limits {
+    // Whole multipart request of POST /measurements
+    max_upload_bytes: usize,
+    // Whole body of POST /measurements/json, base64 image included
+    max_json_bytes: usize,
+    // Image fetched from the imageUrl of a submission
+    max_image_url_bytes: usize,
+    // Depth map sent with a measurement
+    max_depth_map_bytes: usize,
+    // Image uploaded in chunks, and each of its chunks
+    max_upload_session_bytes: u64,
+    max_chunk_bytes: usize,
+    // Offline bundles, when import is enabled
+    max_import_bytes: usize,
+    // Segments of a polyline measurement, one fewer than its points
+    max_segments: usize,
+    max_metadata_bytes: usize,
+    max_client_info_bytes: usize,
+    max_external_id_chars: usize,
+    thumbnail_sizes: Vec<u32>,
+    // Largest part of a measurement's log served in one response
+    max_log_response_bytes: u64,
+    // Largest page of GET /measurements
+    max_list_page_size: usize,
+}
+
+#[derive(Serialize)]
+struct CircuitInfo {
+    version: &'static str,
+    // Coordinates in metres are multiplied by this and rounded before proving
+    scale: f64,
+    // Largest absolute coordinate accepted, in metres
+    max_coordinate_metres: f64,
+    // Whether the proving key is installed on this server
+    available: bool,
+    // Range of each input signal, checked when a measurement is submitted
+    inputs: Vec<SignalInfo>,
+    // Signals of the proof's public.json, in order
+    public_signals: Vec<SignalInfo>,
+    // Artifacts served at /circuits/{version}/{artifact}
+    published_artifacts: Vec<&'static str>,
+    // Network attesting the proofs of the circuit
+    attestation_backend: BackendKind,
+}
+
+#[derive(Serialize)]
+struct SignalInfo {
+    signal: &'static str,
+    // Elements of an array signal
+    #[serde(skip_serializing_if = "Option::is_none")]
+    length: Option<usize>,
+    // Values are below 2^bits, in magnitude for signed signals
+    bits: u32,
+    // Whether negative values, written as p - v, are accepted
+    signed: bool,
+    encoding: Encoding,
+}
+
+fn signals(signals: &[Signal]) -> Vec<SignalInfo> {
+    signals
+        .iter()
+        .map(|signal| SignalInfo {
+            signal: signal.name,
+            length: signal.length,
+            bits: signal.bits,
+            signed: signal.encoding == Encoding::Signed,
+            encoding: signal.encoding,
+        })
+        .collect()
+}
+
+fn feature(enabled: bool, endpoints: &'static [&'static str]) -> Feature {
+    Feature { enabled, endpoints }
+}
+
+// Handler describing the features and limits of this deployment
+pub async fn capabilities(State(state): State<Arc<AppState>>) -> Json<Capabilities> {
+    let config = &state.config;
+    let features = BTreeMap::from([
+        // Keys can be managed before any exist, while enforcement is still off
+        (
+            "api_keys",
+            feature(
+                true,
+                &[
+                    "POST /admin/keys",
+                    "GET /admin/keys",
+                    "POST /admin/keys/{id}/disable",
+                    "POST /admin/keys/{id}/enable",
+                    "POST /admin/keys/{id}/rotate",
+                ],
+            ),
+        ),
+        (
+            "access_control",
+            feature(config.admin_api_key.is_some() || !state.api_keys.is_empty(), &[]),
+        ),
+        ("tenants", feature(config.tenants.enabled(), &[])),
+        (
+            "circuit_artifacts",
+            feature(
+                !config.public_circuit_artifacts.is_empty(),
+                &["GET /circuits/{version}/{artifact}"],
+            ),
+        ),
+        (
+            "bundle_import",
+            feature(!config.import_trusted_keys.is_empty(), &["POST /measurements/import"]),
+        ),
+        ("json_submission", feature(true, &["POST /measurements/json"])),
+        ("image_urls", feature(true, &[])),
+        ("second_images", feature(true, &["GET /img/{id}/2"])),
+        ("depth_maps", feature(true, &["GET /depth/{id}"])),
+        ("flat_points", feature(true, &[])),
+        ("polylines", feature(true, &[])),
+        ("angle_mode", feature(true, &[])),
+        ("threshold_proofs", feature(true, &[])),
+        ("upload_progress", feature(true, &["GET /uploads/progress/{id}"])),
+        (
+            "resumable_uploads",
+            feature(
+                true,
+                &[
+                    "POST /uploads",
+                    "GET /uploads/{id}",
+                    "PUT /uploads/{id}/chunks/{n}",
+                    "POST /uploads/{id}/complete",
+                ],
+            ),
+        ),
+        ("external_ids", feature(true, &["GET /measurements/by-external-id/{external_id}"])),
+        ("batch_status", feature(true, &["POST /status/batch"])),
+        ("idempotency_keys", feature(true, &[])),
+        ("webhooks", feature(config.webhook_secret.is_some(), &[])),
+        ("embargo", feature(true, &["PATCH /measurements/{id}"])),
+        (
+            "deletion",
+            feature(true, &["DELETE /measurements/{id}", "POST /admin/measurements/delete"]),
+        ),
+        ("backups", feature(true, &["GET /admin/export", "POST /admin/import"])),
+        ("deadlines", feature(true, &[])),
+        ("maintenance_mode", feature(true, &["GET /admin/maintenance", "PUT /admin/maintenance"])),
+        ("thumbnails", feature(true, &["GET /img/{id}/thumb/{size}", "GET /img/{id}/webp"])),
+        (
+            "artifact_downloads",
+            feature(
+                true,
+                &[
+                    "GET /measurements/{id}/artifacts/{name}",
+                    "GET /measurements/{id}/log",
+                    "GET /proofs/{id}",
+                ],
+            ),
+        ),
+        ("status_history", feature(true, &["GET /measurements/{id}/history"])),
+        ("status_events", feature(true, &["GET /status/{id}/events"])),
+        ("long_polling", feature(true, &[])),
+        ("websocket", feature(true, &["GET /ws"])),
+        ("contract_attestations", feature(true, &["GET /attestation/{id}"])),
+        ("intake_windows", feature(config.intake_windows.is_some(), &[])),
+        (
+            "reproving",
+            feature(true, &["POST /measurements/{id}/reprove", "POST /admin/measurements/reprove"]),
+        ),
+        ("retries", feature(true, &["POST /measurements/{id}/retry"])),
+        ("pipeline_plans", feature(true, &[])),
+        ("cancellation", feature(true, &["POST /measurements/{id}/cancel"])),
+        ("backfill", feature(true, &["POST /admin/backfill"])),
+        (
+            "duplicate_detection",
+            feature(config.duplicate_mode != DuplicateMode::Off, &["GET /admin/duplicates"]),
+        ),
+        ("broker_events", feature(state.broker.is_some(), &[])),
+        ("attestation_polling", feature(config.attestation_polling, &[])),
+        ("shadow_proving", feature(config.shadow_proving, &["GET /admin/shadow"])),
+        ("native_prover", feature(config.prover == ProverKind::Native, &[])),
+        // The preview is served before opting in as well
+        (
+            "telemetry",
+            feature(config.telemetry_enabled && config.telemetry_endpoint.is_some(), &[]),
+        ),
+        ("read_only_mirror", feature(config.read_only, &[])),
+        ("json_schemas", feature(true, &["GET /schemas", "GET /schemas/{name}/{version}.json"])),
+        ("doctor", feature(true, &["GET /admin/doctor"])),
+        (
+            "support_bundles",
+            feature(true, &["POST /admin/support-bundle", "GET /support-bundles/{name}"]),
+        ),
+        (
+            "submission_compare",
+            feature(
+                cfg!(feature = "submission-compare"),
+                &["POST /admin/submissions/{id}/compare"],
+            ),
+        ),
+        // Not implemented by this server
+        ("point_clouds", feature(false, &[])),
+        ("siwe_auth", feature(false, &[])),
+        ("ipfs", feature(false, &[])),
+    ]);
+
+    Json(Capabilities {
+        server_version: SERVER_VERSION,
+        features,
+        limits: Limits {
+            max_upload_bytes: uploads::MAX_REQUEST_BYTES,
+            max_json_bytes: config.json_max_bytes,
+            max_image_url_bytes: config.image_url_max_bytes,
+            max_depth_map_bytes: config.depth_map_max_bytes,
+            max_upload_session_bytes: config.upload_session_max_bytes,
+            max_chunk_bytes: config.upload_chunk_max_bytes,
+            max_import_bytes: config.import_max_bytes,
+            max_segments: config.max_polyline_points - 1,
+            max_metadata_bytes: metadata::MAX_RAW_BYTES,
+            max_client_info_bytes: client_info::MAX_RAW_BYTES,
+            max_external_id_chars: external_id::MAX_EXTERNAL_ID_CHARS,
+            thumbnail_sizes: config.thumbnail_sizes.clone(),
+            max_log_response_bytes: config.log_max_response_bytes,
+            max_list_page_size: listing::MAX_PAGE_SIZE,
+        },
+        circuits: circuit::all()
+            .map(|circuit| CircuitInfo {
+                version: circuit.version,
+                scale: SCALE,
+                max_coordinate_metres: MAX_SCALED_MAGNITUDE as f64 / SCALE,
+                available: circuit.is_installed(),
+                inputs: signals(circuit.spec.inputs),
+                public_signals: signals(circuit.spec.public),
+                published_artifacts: circuit_artifacts::published_for(
+                    &config.public_circuit_artifacts,
+                    circuit.version,
+                ),
+                attestation_backend: state.attestation_backends.kind_for(circuit.version),
+            })
+            .collect(),
+        image_content_types: &IMAGE_CONTENT_TYPES,
+    })
+}
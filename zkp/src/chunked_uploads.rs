@@ -0,0 +1,478 @@
+// Resumable uploads of large images in chunks.
+//
+// High-resolution captures run to several megabytes, more than a phone on a
+// flaky connection reliably gets through in one request, so a client can
+// upload the image in chunks instead: POST /uploads opens an upload session,
+// PUT /uploads/{id}/chunks/{n} appends chunk n and POST /uploads/{id}/complete
+// finishes it, answering with an upload token that a submission sends as
+// uploadToken in place of image. GET /uploads/{id} tells how many chunks and
+// bytes arrived, so a client whose connection died carries on from there.
+//
+// Chunks are numbered from 0 and written in order. Chunk n sent again while
+// it is the last one received replaces it, so a chunk whose answer was lost
+// can simply be sent again, and whatever a chunk cut off midway wrote is
+// discarded by the next attempt. A Content-Range header, when sent, must
+// start where the chunk does. A chunk may hold UPLOAD_CHUNK_MAX_BYTES and the
+// upload UPLOAD_SESSION_MAX_BYTES, or the size declared when it was opened,
+// which completing then requires.
+//
+// Sessions belong to the tenant and API key that opened them rather than to
+// an address, as a phone may change networks midway; the session id and the
+// token are unguessable. A session, completed or not, expires
+// UPLOAD_SESSION_TTL_SECS after it was last written to, and a background task
+// removes expired ones with their data. A submission claims the upload of its
+// token only once nothing else refuses it, after which the token is gone.
+// Sessions are held in the memory of the instance that opened them and do not
+// survive a restart; data left behind by an earlier run is removed like that
+// of an expired session.
+
+use axum::{
+    Json,
+    body::Bytes,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode, header},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs, io,
+    io::SeekFrom,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    auth::{Authorized, Caller, Submitter},
+    uploads::TempUpload,
+};
+
+// Name prefix of the data of upload sessions in uploads/
+const SESSION_PREFIX: &str = ".session-";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionStatus {
+    Open,
+    // Finished and waiting to be claimed by a submission
+    Completed,
+}
+
+struct Session {
+    tenant: Option<String>,
+    // Stored API key the session was opened with
+    key_id: Option<String>,
+    // Size the client declared when opening the session
+    size: Option<u64>,
+    // Offset of each chunk received, in order
+    offsets: Vec<u64>,
+    received: u64,
+    // Set once the upload is complete
+    token: Option<String>,
+    sha256: Option<String>,
+    // Whether a chunk is being written
+    busy: bool,
+    touched: Instant,
+}
+
+impl Session {
+    fn status(&self) -> SessionStatus {
+        if self.token.is_some() { SessionStatus::Completed } else { SessionStatus::Open }
+    }
+
+    fn owned_by(&self, caller: &Caller) -> bool {
+        caller.can_access(self.tenant.as_deref())
+            && self.key_id.as_ref().is_none_or(|key_id| caller.key_id.as_ref() == Some(key_id))
+    }
+}
+
+// An upload session as reported to the client
+#[derive(Debug, Serialize)]
+pub struct SessionInfo {
+    upload_id: String,
+    status: SessionStatus,
+    // Chunks received, which is also the number of the next one to send
+    chunks: usize,
+    received_bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+    // Sent as uploadToken with the measurement, once complete
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upload_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct NewSession {
+    // Size of the whole image, checked when the upload is completed
+    size: Option<u64>,
+}
+
+#[derive(Default)]
+pub struct ChunkedUploads {
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+// Clears the busy flag of a session when a chunk is done with, also when
+// the request is cancelled midway
+struct Writing<'a> {
+    uploads: &'a ChunkedUploads,
+    id: &'a str,
+}
+
+impl Drop for Writing<'_> {
+    fn drop(&mut self) {
+        if let Some(session) = self.uploads.sessions.lock().unwrap().get_mut(self.id) {
+            session.busy = false;
+        }
+    }
+}
+
+fn data_path(id: &str) -> String {
+    format!("uploads/{}{}", SESSION_PREFIX, id)
+}
+
+fn not_found(id: &str) -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, format!("No upload with ID {}", id))
+}
+
+impl ChunkedUploads {
+    fn info(&self, id: &str, session: &Session, ttl: Duration) -> SessionInfo {
+        let left = ttl.saturating_sub(session.touched.elapsed());
+        SessionInfo {
+            upload_id: id.to_string(),
+            status: session.status(),
+            chunks: session.offsets.len(),
+            received_bytes: session.received,
+            size: session.size,
+            upload_token: session.token.clone(),
+            sha256: session.sha256.clone(),
+            expires_at: Utc::now() + left,
+        }
+    }
+
+    // Whether a completed upload the caller may use has the token
+    pub fn check(&self, token: &str, caller: &Caller) -> Result<(), String> {
+        let sessions = self.sessions.lock().unwrap();
+        match sessions.values().find(|s| s.token.as_deref() == Some(token)) {
+            Some(session) if session.owned_by(caller) => Ok(()),
+            _ => Err("uploadToken names no completed upload, or it has expired".to_string()),
+        }
+    }
+
+    // Take the upload of a token for a new measurement
+    pub fn claim(&self, token: &str) -> Result<TempUpload, String> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let id = sessions
+            .iter()
+            .find(|(_, s)| s.token.as_deref() == Some(token))
+            .map(|(id, _)| id.clone())
+            .ok_or("uploadToken expired before the measurement was stored")?;
+        sessions.remove(&id);
+        Ok(TempUpload::at(data_path(&id)))
+    }
+}
+
+// Handler opening an upload session, optionally with the image's size
+pub async fn create(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Submitter>,
+    body: Bytes,
+) -> Result<(StatusCode, Json<SessionInfo>), (StatusCode, String)> {
+    let request: NewSession = if body.iter().all(u8::is_ascii_whitespace) {
+        NewSession::default()
+    } else {
+        serde_json::from_slice(&body)
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid upload session: {}", e)))?
+    };
+    let max = state.config.upload_session_max_bytes;
+    match request.size {
+        Some(0) => return Err((StatusCode::BAD_REQUEST, "size must be positive".to_string())),
+        Some(size) if size > max => {
+            return Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("An upload may hold at most {} bytes", max),
+            ));
+        }
+        _ => {}
+    }
+
+    let id = Uuid::new_v4().to_string();
+    fs::File::create(data_path(&id)).map_err(|e| {
+        (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create upload file: {}", e))
+    })?;
+    let session = Session {
+        tenant: caller.tenant,
+        key_id: caller.key_id,
+        size: request.size,
+        offsets: Vec::new(),
+        received: 0,
+        token: None,
+        sha256: None,
+        busy: false,
+        touched: Instant::now(),
+    };
+    let ttl = Duration::from_secs(state.config.upload_session_ttl_secs);
+    let info = state.chunked_uploads.info(&id, &session, ttl);
+    state.chunked_uploads.sessions.lock().unwrap().insert(id.clone(), session);
+    println!("Opened upload session {}", id);
+    Ok((StatusCode::CREATED, Json(info)))
+}
+
+// Handler reporting an upload session
+pub async fn get_session(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Submitter>,
+    Path(id): Path<String>,
+) -> Result<Json<SessionInfo>, (StatusCode, String)> {
+    let ttl = Duration::from_secs(state.config.upload_session_ttl_secs);
+    let sessions = state.chunked_uploads.sessions.lock().unwrap();
+    let session =
+        sessions.get(&id).filter(|s| s.owned_by(&caller)).ok_or_else(|| not_found(&id))?;
+    Ok(Json(state.chunked_uploads.info(&id, session, ttl)))
+}
+
+// Start of the range a Content-Range header gives, checked against the
+// chunk's length and the upload's declared size
+fn range_start(value: &str, length: u64, size: Option<u64>) -> Result<u64, String> {
+    let invalid =
+        || format!("Invalid Content-Range '{}', expected bytes <start>-<end>/<size>", value);
+    let range = value.trim().strip_prefix("bytes ").ok_or_else(invalid)?;
+    let (range, total) = range.split_once('/').ok_or_else(invalid)?;
+    let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+    let start: u64 = start.trim().parse().map_err(|_| invalid())?;
+    let end: u64 = end.trim().parse().map_err(|_| invalid())?;
+    if end < start || end - start + 1 != length {
+        return Err(format!(
+            "Content-Range '{}' covers {} bytes, the chunk has {}",
+            value,
+            end.saturating_sub(start).saturating_add(1),
+            length
+        ));
+    }
+    let total = match total.trim() {
+        "*" => None,
+        total => Some(total.parse::<u64>().map_err(|_| invalid())?),
+    };
+    if let (Some(total), Some(size)) = (total, size)
+        && total != size
+    {
+        return Err(format!(
+            "Content-Range '{}' gives another size than the upload's {}",
+            value, size
+        ));
+    }
+    Ok(start)
+}
+
+// Handler appending chunk n to an upload, or replacing it when it was the
+// last one received
+pub async fn put_chunk(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Submitter>,
+    Path((id, n)): Path<(String, usize)>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<SessionInfo>, (StatusCode, String)> {
+    let uploads = &state.chunked_uploads;
+    let ttl = Duration::from_secs(state.config.upload_session_ttl_secs);
+    let length = body.len() as u64;
+    if length == 0 {
+        return Err((StatusCode::BAD_REQUEST, "A chunk must not be empty".to_string()));
+    }
+
+    let offset = {
+        let mut sessions = uploads.sessions.lock().unwrap();
+        let session =
+            sessions.get_mut(&id).filter(|s| s.owned_by(&caller)).ok_or_else(|| not_found(&id))?;
+        if session.status() == SessionStatus::Completed {
+            return Err((StatusCode::CONFLICT, format!("Upload {} is already complete", id)));
+        }
+        if session.busy {
+            return Err((
+                StatusCode::CONFLICT,
+                format!("Another chunk of upload {} is being written", id),
+            ));
+        }
+        let received = session.offsets.len();
+        let offset = match n {
+            n if n == received => session.received,
+            n if n + 1 == received => session.offsets[n],
+            _ => {
+                return Err((
+                    StatusCode::CONFLICT,
+                    format!("Upload {} expects chunk {} next", id, received),
+                ));
+            }
+        };
+        if let Some(value) = headers.get(header::CONTENT_RANGE) {
+            let value = value.to_str().unwrap_or_default();
+            let start = range_start(value, length, session.size)
+                .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+            if start != offset {
+                return Err((
+                    StatusCode::CONFLICT,
+                    format!("Content-Range starts at {}, chunk {} at {}", start, n, offset),
+                ));
+            }
+        }
+        let limit = session.size.unwrap_or(state.config.upload_session_max_bytes);
+        if offset + length > limit {
+            return Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Upload {} may hold at most {} bytes", id, limit),
+            ));
+        }
+        session.busy = true;
+        session.touched = Instant::now();
+        offset
+    };
+    let _writing = Writing { uploads, id: &id };
+
+    // Drop whatever an earlier attempt at this chunk or a later one wrote
+    let write = async {
+        let mut file = tokio::fs::OpenOptions::new().write(true).open(data_path(&id)).await?;
+        file.set_len(offset).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        file.write_all(&body).await?;
+        file.flush().await
+    };
+    write.await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to write chunk {} of upload {}: {}", n, id, e),
+        )
+    })?;
+
+    let mut sessions = uploads.sessions.lock().unwrap();
+    let session = sessions.get_mut(&id).ok_or_else(|| not_found(&id))?;
+    session.offsets.truncate(n);
+    session.offsets.push(offset);
+    session.received = offset + length;
+    session.touched = Instant::now();
+    Ok(Json(uploads.info(&id, session, ttl)))
+}
+
+// Handler finishing an upload, answering with the token to submit it with.
+// Completing a completed upload answers the same again.
+pub async fn complete(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Submitter>,
+    Path(id): Path<String>,
+) -> Result<Json<SessionInfo>, (StatusCode, String)> {
+    let uploads = &state.chunked_uploads;
+    let ttl = Duration::from_secs(state.config.upload_session_ttl_secs);
+    {
+        let mut sessions = uploads.sessions.lock().unwrap();
+        let session =
+            sessions.get_mut(&id).filter(|s| s.owned_by(&caller)).ok_or_else(|| not_found(&id))?;
+        if session.status() == SessionStatus::Completed {
+            return Ok(Json(uploads.info(&id, session, ttl)));
+        }
+        if session.busy {
+            return Err((
+                StatusCode::CONFLICT,
+                format!("A chunk of upload {} is still being written", id),
+            ));
+        }
+        if session.received == 0 {
+            return Err((StatusCode::CONFLICT, format!("Upload {} has no chunks yet", id)));
+        }
+        if let Some(size) = session.size.filter(|size| *size != session.received) {
+            return Err((
+                StatusCode::CONFLICT,
+                format!("Upload {} has {} of its {} bytes", id, session.received, size),
+            ));
+        }
+        session.busy = true;
+    }
+    let _writing = Writing { uploads, id: &id };
+
+    let path = data_path(&id);
+    let sha256 = tokio::task::spawn_blocking(move || -> io::Result<String> {
+        let mut hasher = Sha256::new();
+        io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+        Ok(hex::encode(hasher.finalize()))
+    })
+    .await
+    .unwrap_or_else(|e| Err(io::Error::other(e)))
+    .map_err(|e| {
+        (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read upload {}: {}", id, e))
+    })?;
+
+    let mut sessions = uploads.sessions.lock().unwrap();
+    let session = sessions.get_mut(&id).ok_or_else(|| not_found(&id))?;
+    session.token = Some(Uuid::new_v4().simple().to_string());
+    session.sha256 = Some(sha256);
+    session.touched = Instant::now();
+    println!("Completed upload session {} ({} bytes)", id, session.received);
+    Ok(Json(uploads.info(&id, session, ttl)))
+}
+
+// Remove the data of sessions no longer held, untouched for the TTL
+fn remove_orphans(known: &[String], ttl: Duration) -> io::Result<Vec<String>> {
+    let mut removed = Vec::new();
+    for entry in fs::read_dir("uploads")? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let Some(id) = name.strip_prefix(SESSION_PREFIX) else {
+            continue;
+        };
+        let stale = entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|idle| idle >= ttl);
+        if stale && !known.iter().any(|known| known == id) {
+            fs::remove_file(entry.path())?;
+            removed.push(id.to_string());
+        }
+    }
+    Ok(removed)
+}
+
+// Background task removing expired upload sessions with their data
+pub async fn sweeper(state: Arc<AppState>) {
+    let ttl = state.config.upload_session_ttl_secs;
+    let every = ttl.min(state.config.retention_sweep_interval_secs);
+    let mut interval = tokio::time::interval(Duration::from_secs(every));
+    let ttl = Duration::from_secs(ttl);
+    loop {
+        interval.tick().await;
+        let (expired, known) = {
+            let mut sessions = state.chunked_uploads.sessions.lock().unwrap();
+            let expired: Vec<(String, u64)> = sessions
+                .iter()
+                .filter(|(_, s)| !s.busy && s.touched.elapsed() >= ttl)
+                .map(|(id, s)| (id.clone(), s.received))
+                .collect();
+            for (id, _) in &expired {
+                sessions.remove(id);
+            }
+            (expired, sessions.keys().cloned().collect::<Vec<_>>())
+        };
+        for (id, bytes) in expired {
+            match fs::remove_file(data_path(&id)) {
+                Ok(()) => println!("Removed expired upload session {} ({} bytes)", id, bytes),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => println!("Failed to remove expired upload session {}: {}", id, e),
+            }
+        }
+        match tokio::task::spawn_blocking(move || remove_orphans(&known, ttl)).await {
+            Ok(Ok(removed)) => {
+                for id in removed {
+                    println!("Removed upload session {} left by an earlier run", id);
+                }
+            }
+            Ok(Err(e)) => println!("Upload session sweep failed: {}", e),
+            Err(e) => println!("Upload session sweep failed: {}", e),
+        }
+    }
+}
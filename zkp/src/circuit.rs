@@ -0,0 +1,524 @@
+// Compiled circuit artifacts used for proving and verification.
+//
+// Each circuit also declares the layout of its signals in a CircuitSpec: the
+// inputs it is given, in order, and the signals snarkjs writes to public.json,
+// each with its width and how its values are encoded. input.json is built
+// from the spec (inputs.rs) and public.json decoded with it, so the two cannot
+// drift apart for a circuit version, and a public.json with a different number
+// of signals than the spec declares is refused.
+//
+// A circuit committing to the measurement's image names the public signal
+// holding the image's SHA-256 in image_commitment: an unsigned array whose
+// elements are the digest's big-endian limbs, together 256 bits wide and each
+// a whole number of bytes. The hash is decoded from public.json with the spec
+// and compared with the stored image before the measurement completes, see
+// commitment.rs.
+//
+// Values are elements of the BN254 scalar field, so anything at or above the
+// modulus wraps around, and the circuit's arithmetic is only meaningful while
+// its intermediate results stay below it too. Every input is checked against
+// its declared width before a measurement is accepted or proved. The specs of
+// all registered circuits are checked for consistency when the server is
+// compiled, see CircuitSpec::check.
+
+use serde::Serialize;
+use std::{collections::BTreeMap, path::Path, sync::OnceLock};
+
+use crate::{
+    coords::{self, CoordinateError},
+    inputs::InputViolation,
+    pipeline::ArtifactProblem,
+};
+
+// Values below 2^253 are always smaller than the BN254 scalar field modulus
+pub const FIELD_SAFE_BITS: u32 = 253;
+// Widest signed signal to_field and from_field can encode
+const SIGNED_MAX_BITS: u32 = 63;
+
+// How the values of a signal are written in input.json and public.json
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    // A non-negative integer in decimal
+    Unsigned,
+    // An integer in decimal, negative values written as the field element p - v
+    Signed,
+}
+
+// A signal of a circuit: an input it is given or a public signal it exposes
+#[derive(Debug, Clone, Copy)]
+pub struct Signal {
+    pub name: &'static str,
+    // Number of elements of an array signal, None for a single value
+    pub length: Option<usize>,
+    // Values must be below 2^bits, in magnitude for signed signals
+    pub bits: u32,
+    pub encoding: Encoding,
+}
+
+impl Signal {
+    pub const fn elements(&self) -> usize {
+        match self.length {
+            Some(length) => length,
+            None => 1,
+        }
+    }
+
+    // Check a value of the signal, or of its element, against its width;
+    // widths are never wider than what is guaranteed to stay below the modulus
+    pub fn check(&self, element: &str, value: i128) -> Result<(), InputViolation> {
+        let bits = self.bits.min(FIELD_SAFE_BITS);
+        let signed = self.encoding == Encoding::Signed;
+        let fits = (signed || value >= 0)
+            && value.unsigned_abs().checked_shr(bits).is_none_or(|high| high == 0);
+        if fits {
+            Ok(())
+        } else {
+            Err(InputViolation::OutOfRange { signal: element.to_string(), value, bits, signed })
+        }
+    }
+
+    fn encode(&self, value: i128) -> String {
+        match self.encoding {
+            // Checked against a width of at most SIGNED_MAX_BITS before
+            Encoding::Signed => coords::to_field(value as i64),
+            Encoding::Unsigned => value.to_string(),
+        }
+    }
+
+    fn decode(&self, element: &str, value: &str) -> Result<i128, ArtifactProblem> {
+        let decoded = match self.encoding {
+            Encoding::Signed => coords::from_field(value).map(i128::from),
+            Encoding::Unsigned => value
+                .parse::<u128>()
+                .ok()
+                .and_then(|value| i128::try_from(value).ok())
+                .ok_or_else(|| CoordinateError::InvalidFieldElement(value.to_string())),
+        }
+        .map_err(ArtifactProblem::InvalidField)?;
+        self.check(element, decoded).map_err(ArtifactProblem::OutOfRange)?;
+        Ok(decoded)
+    }
+}
+
+// Layout of a circuit's signals, which input.json is built from and
+// public.json decoded with
+#[derive(Debug, Clone, Copy)]
+pub struct CircuitSpec {
+    // Input signals, in the order the circuit declares them
+    pub inputs: &'static [Signal],
+    // Signals of public.json in its order, as snarkjs writes them: the public
+    // outputs first, then the public inputs, which are also listed in inputs
+    pub public: &'static [Signal],
+    // Public signal holding the SHA-256 of the measurement's image, for
+    // circuits committing to it
+    pub image_commitment: Option<&'static str>,
+}
+
+// Values of the public signals of a proof, by signal
+#[derive(Debug)]
+pub struct PublicSignals(BTreeMap<&'static str, Vec<i128>>);
+
+impl PublicSignals {
+    // Value of a single-valued signal
+    pub fn value(&self, name: &str) -> Option<i128> {
+        self.0.get(name).and_then(|values| values.first()).copied()
+    }
+
+    // Values of an array signal
+    pub fn values(&self, name: &str) -> Option<&[i128]> {
+        self.0.get(name).map(Vec::as_slice)
+    }
+}
+
+impl CircuitSpec {
+    // Input signal of the given name, or of which the given name is an element
+    pub fn input(&self, name: &str) -> Option<&Signal> {
+        let name = name.split('[').next().unwrap_or(name);
+        self.inputs.iter().find(|signal| signal.name == name)
+    }
+
+    // Hex SHA-256 of the image the public signals commit to, None for
+    // circuits committing to no image
+    pub fn committed_image(&self, signals: &PublicSignals) -> Option<String> {
+        let name = self.image_commitment?;
+        let bytes = self.public.iter().find(|signal| signal.name == name)?.bits as usize / 8;
+        let digest: Vec<u8> = signals
+            .values(name)?
+            .iter()
+            .flat_map(|limb| limb.to_be_bytes()[16 - bytes..].to_vec())
+            .collect();
+        Some(hex::encode(digest))
+    }
+
+    // Number of values in public.json
+    pub fn public_count(&self) -> usize {
+        self.public.iter().map(Signal::elements).sum()
+    }
+
+    // Build the inputs of input.json from the values of each input signal,
+    // checking every value against its signal's width
+    pub fn encode_inputs(
+        &self,
+        values: &[(&str, &[i128])],
+    ) -> Result<serde_json::Value, InputViolation> {
+        if let Some((name, _)) = values.iter().find(|(name, _)| self.input(name).is_none()) {
+            return Err(InputViolation::Undeclared { signal: name.to_string() });
+        }
+        let mut inputs = serde_json::Map::new();
+        for signal in self.inputs {
+            let Some((_, elements)) = values.iter().find(|(name, _)| *name == signal.name) else {
+                return Err(InputViolation::Missing { signal: signal.name.to_string() });
+            };
+            if elements.len() != signal.elements() {
+                return Err(InputViolation::Length {
+                    signal: signal.name.to_string(),
+                    expected: signal.elements(),
+                    found: elements.len(),
+                });
+            }
+            let encoded = match signal.length {
+                Some(_) => {
+                    let mut encoded = Vec::with_capacity(elements.len());
+                    for (index, value) in elements.iter().enumerate() {
+                        signal.check(&format!("{}[{}]", signal.name, index), *value)?;
+                        encoded.push(serde_json::Value::String(signal.encode(*value)));
+                    }
+                    serde_json::Value::Array(encoded)
+                }
+                None => {
+                    signal.check(signal.name, elements[0])?;
+                    serde_json::Value::String(signal.encode(elements[0]))
+                }
+            };
+            inputs.insert(signal.name.to_string(), encoded);
+        }
+        Ok(serde_json::Value::Object(inputs))
+    }
+
+    // Decode the values of public.json, which must hold exactly the declared signals
+    pub fn decode_public(&self, values: &[String]) -> Result<PublicSignals, ArtifactProblem> {
+        if values.len() != self.public_count() {
+            return Err(ArtifactProblem::SignalCount {
+                expected: self.public_count(),
+                found: values.len(),
+            });
+        }
+        let mut values = values.iter();
+        let mut decoded = BTreeMap::new();
+        for signal in self.public {
+            let elements = (0..signal.elements())
+                .zip(values.by_ref())
+                .map(|(index, value)| match signal.length {
+                    Some(_) => signal.decode(&format!("{}[{}]", signal.name, index), value),
+                    None => signal.decode(signal.name, value),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            decoded.insert(signal.name, elements);
+        }
+        Ok(PublicSignals(decoded))
+    }
+
+    // Panic unless the spec is consistent: signal names are unique among the
+    // inputs and among the public signals, arrays have elements, widths are
+    // within the field, a public input is declared like the input it is, and
+    // the image commitment names a public signal laid out like a digest.
+    // Run on every registered circuit at compile time, see below.
+    const fn check(&self) {
+        let mut i = 0;
+        while i < self.inputs.len() {
+            self.inputs[i].check_width();
+            let mut j = i + 1;
+            while j < self.inputs.len() {
+                if str_eq(self.inputs[i].name, self.inputs[j].name) {
+                    panic!("a circuit declares an input signal twice");
+                }
+                j += 1;
+            }
+            i += 1;
+        }
+        let mut i = 0;
+        while i < self.public.len() {
+            let public = &self.public[i];
+            public.check_width();
+            let mut j = i + 1;
+            while j < self.public.len() {
+                if str_eq(public.name, self.public[j].name) {
+                    panic!("a circuit declares a public signal twice");
+                }
+                j += 1;
+            }
+            let mut k = 0;
+            while k < self.inputs.len() {
+                let input = &self.inputs[k];
+                if str_eq(public.name, input.name)
+                    && (public.bits != input.bits
+                        || public.elements() != input.elements()
+                        || public.encoding as u8 != input.encoding as u8)
+                {
+                    panic!("a circuit declares a public input unlike the input");
+                }
+                k += 1;
+            }
+            i += 1;
+        }
+        if let Some(name) = self.image_commitment {
+            let mut found = false;
+            let mut i = 0;
+            while i < self.public.len() {
+                let public = &self.public[i];
+                if str_eq(public.name, name) {
+                    found = true;
+                    let fits = public.bits.is_multiple_of(8) && public.bits <= 120;
+                    let digest = match public.length {
+                        Some(length) => length as u32 * public.bits == 256,
+                        None => false,
+                    };
+                    if !fits || !digest || !matches!(public.encoding, Encoding::Unsigned) {
+                        panic!("a circuit commits to its image in a signal unlike a digest");
+                    }
+                }
+                i += 1;
+            }
+            if !found {
+                panic!("a circuit commits to its image in an undeclared signal");
+            }
+        }
+    }
+}
+
+impl Signal {
+    const fn check_width(&self) {
+        if self.bits == 0 || self.bits > FIELD_SAFE_BITS {
+            panic!("a circuit declares a signal wider than the field");
+        }
+        if matches!(self.encoding, Encoding::Signed) && self.bits > SIGNED_MAX_BITS {
+            panic!("a circuit declares a signed signal wider than 63 bits");
+        }
+        if matches!(self.length, Some(0)) {
+            panic!("a circuit declares an array signal without elements");
+        }
+    }
+}
+
+const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+// Files making up one build of a circuit
+#[derive(Debug, Clone, Copy)]
+pub struct Circuit {
+    // Identifier recorded on every measurement proved with this circuit
+    pub version: &'static str,
+    pub wasm_path: &'static str,
+    pub witness_generator: &'static str,
+    pub zkey_path: &'static str,
+    pub vkey_path: &'static str,
+    pub spec: CircuitSpec,
+}
+
+impl Circuit {
+    // Whether the proving key is installed on this server
+    pub fn is_installed(&self) -> bool {
+        Path::new(self.zkey_path).exists()
+    }
+}
+
+// Every circuit proofs can be generated with, oldest first. New measurements
+// are proved with ZKHOTDOG, ZKHOTDOG_POLYLINE for polylines of more than two
+// points (polyline.rs), ZKHOTDOG_ANGLE for angles (angle.rs) or
+// ZKHOTDOG_THRESHOLD for minimum lengths (threshold.rs); any others are only
+// used for reproofs. Read them with all or find, which apply the
+// configured paths of the angle circuit.
+pub const CIRCUITS: [Circuit; 4] =
+    [ZKHOTDOG, ZKHOTDOG_POLYLINE, ZKHOTDOG_ANGLE, ZKHOTDOG_THRESHOLD];
+
+// Wasm, witness generator and proving key of ZKHOTDOG_ANGLE, once configured
+static ANGLE_PATHS: OnceLock<(&'static str, &'static str, &'static str)> = OnceLock::new();
+
+// Set the angle circuit's wasm and proving key from the configuration, at
+// startup before any circuit is used. Its witness generator is the one
+// circom writes next to the wasm.
+pub fn configure(angle_wasm: &str, angle_zkey: &str) {
+    let leak = |path: String| -> &'static str { Box::leak(path.into_boxed_str()) };
+    let generator = Path::new(angle_wasm).with_file_name("generate_witness.js");
+    let _ = ANGLE_PATHS.set((
+        leak(angle_wasm.to_string()),
+        leak(generator.to_string_lossy().into_owned()),
+        leak(angle_zkey.to_string()),
+    ));
+}
+
+fn configured(circuit: Circuit) -> Circuit {
+    match ANGLE_PATHS.get() {
+        Some(&(wasm_path, witness_generator, zkey_path))
+            if circuit.version == ZKHOTDOG_ANGLE.version =>
+        {
+            Circuit { wasm_path, witness_generator, zkey_path, ..circuit }
+        }
+        _ => circuit,
+    }
+}
+
+// Every registered circuit, with the configured paths
+pub fn all() -> impl Iterator<Item = Circuit> {
+    CIRCUITS.into_iter().map(configured)
+}
+
+// Circuit with the given version
+pub fn find(version: &str) -> Option<Circuit> {
+    all().find(|c| c.version == version)
+}
+
+// Circuit a measurement is proved with: the one requested for a reproof, or ZKHOTDOG
+pub fn resolve(version: Option<&str>) -> Circuit {
+    version.and_then(find).unwrap_or(ZKHOTDOG)
+}
+
+// Every registered circuit's spec is consistent, or the server does not compile
+const _: () = {
+    let mut i = 0;
+    while i < CIRCUITS.len() {
+        CIRCUITS[i].spec.check();
+        i += 1;
+    }
+};
+
+// Squared distance between the points, the circuit's only public signal
+const DISTANCE_SQUARED: Signal =
+    Signal { name: "distance_squared", length: None, bits: 62, encoding: Encoding::Unsigned };
+
+// The distance circuit in circuit/zkHotdog.circom
+pub const ZKHOTDOG: Circuit = Circuit {
+    version: "zkhotdog-v1",
+    wasm_path: "circuit-compiled/zkHotdog_js/zkHotdog.wasm",
+    witness_generator: "circuit-compiled/zkHotdog_js/generate_witness.js",
+    zkey_path: "keys/zkHotdog_final.zkey",
+    vkey_path: "keys/verification_key.json",
+    // Coordinates below 2^30 keep each difference below 2^31 and the sum of
+    // the three squares below 2^64, far from the modulus; the circuit has no
+    // range checks of its own, so this is what keeps its result exact
+    spec: CircuitSpec {
+        inputs: &[
+            Signal { name: "point1", length: Some(3), bits: 30, encoding: Encoding::Signed },
+            Signal { name: "point2", length: Some(3), bits: 30, encoding: Encoding::Signed },
+            DISTANCE_SQUARED,
+        ],
+        // distance_squared is a public input; the circuit has no outputs
+        public: &[DISTANCE_SQUARED],
+        // The circuit proves the distance alone, not which image it was measured on
+        image_commitment: None,
+    },
+};
+
+// Segments of the longest polyline ZKHOTDOG_POLYLINE proves, as compiled into
+// circuit/zkHotdogPolyline.circom
+pub const POLYLINE_MAX_SEGMENTS: usize = 16;
+
+const SEGMENT_COUNT: Signal =
+    Signal { name: "segment_count", length: None, bits: 5, encoding: Encoding::Unsigned };
+// Squared length of each segment, 0 for the unused ones after segment_count
+const SEGMENT_DISTANCES_SQUARED: Signal = Signal {
+    name: "segment_distances_squared",
+    length: Some(POLYLINE_MAX_SEGMENTS),
+    bits: 62,
+    encoding: Encoding::Unsigned,
+};
+
+// The polyline circuit in circuit/zkHotdogPolyline.circom
+pub const ZKHOTDOG_POLYLINE: Circuit = Circuit {
+    version: "zkhotdog-polyline-v1",
+    wasm_path: "circuit-compiled/zkHotdogPolyline_js/zkHotdogPolyline.wasm",
+    witness_generator: "circuit-compiled/zkHotdogPolyline_js/generate_witness.js",
+    zkey_path: "keys/zkHotdogPolyline_final.zkey",
+    vkey_path: "keys/polyline_verification_key.json",
+    // Coordinates and segment lengths are bounded like ZKHOTDOG's
+    spec: CircuitSpec {
+        inputs: &[
+            // The points' x, y and z one after another, the last point
+            // repeated up to the circuit's capacity
+            Signal {
+                name: "points",
+                length: Some((POLYLINE_MAX_SEGMENTS + 1) * 3),
+                bits: 30,
+                encoding: Encoding::Signed,
+            },
+            SEGMENT_COUNT,
+            SEGMENT_DISTANCES_SQUARED,
+        ],
+        public: &[SEGMENT_COUNT, SEGMENT_DISTANCES_SQUARED],
+        image_commitment: None,
+    },
+};
+
+// Numerator of the cosine of the angle, the dot product of the two vectors
+const COS_NUMERATOR: Signal =
+    Signal { name: "cos_numerator", length: None, bits: 63, encoding: Encoding::Signed };
+// Square of the cosine's denominator, the product of the vectors' squared lengths
+const COS_DENOMINATOR_SQUARED: Signal = Signal {
+    name: "cos_denominator_squared",
+    length: None,
+    bits: 126,
+    encoding: Encoding::Unsigned,
+};
+
+// The angle circuit in circuit/zkHotdogAngle.circom. Its wasm and proving key
+// are set with ANGLE_CIRCUIT_WASM and ANGLE_CIRCUIT_ZKEY, see configure; these
+// are the defaults
+pub const ZKHOTDOG_ANGLE: Circuit = Circuit {
+    version: "zkhotdog-angle-v1",
+    wasm_path: "circuit-compiled/zkHotdogAngle_js/zkHotdogAngle.wasm",
+    witness_generator: "circuit-compiled/zkHotdogAngle_js/generate_witness.js",
+    zkey_path: "keys/zkHotdogAngle_final.zkey",
+    vkey_path: "keys/angle_verification_key.json",
+    // The vectors are differences of coordinates below 2^30, so below 2^31,
+    // and their dot product and the product of their squared lengths stay
+    // within the widths below for coordinates within MAX_SCALED_MAGNITUDE
+    spec: CircuitSpec {
+        inputs: &[
+            // From the mid point to the start point, and to the end point
+            Signal { name: "vector1", length: Some(3), bits: 31, encoding: Encoding::Signed },
+            Signal { name: "vector2", length: Some(3), bits: 31, encoding: Encoding::Signed },
+            COS_NUMERATOR,
+            COS_DENOMINATOR_SQUARED,
+        ],
+        public: &[COS_NUMERATOR, COS_DENOMINATOR_SQUARED],
+        image_commitment: None,
+    },
+};
+
+// Claimed minimum of the squared distance between the points
+const MIN_DISTANCE_SQUARED: Signal =
+    Signal { name: "min_distance_squared", length: None, bits: 62, encoding: Encoding::Unsigned };
+
+// The threshold circuit in circuit/zkHotdogThreshold.circom
+pub const ZKHOTDOG_THRESHOLD: Circuit = Circuit {
+    version: "zkhotdog-threshold-v1",
+    wasm_path: "circuit-compiled/zkHotdogThreshold_js/zkHotdogThreshold.wasm",
+    witness_generator: "circuit-compiled/zkHotdogThreshold_js/generate_witness.js",
+    zkey_path: "keys/zkHotdogThreshold_final.zkey",
+    vkey_path: "keys/threshold_verification_key.json",
+    // Coordinates are bounded like ZKHOTDOG's, so the squared distance minus
+    // the minimum stays within the 64 bits the circuit checks it against
+    spec: CircuitSpec {
+        inputs: &[
+            Signal { name: "point1", length: Some(3), bits: 30, encoding: Encoding::Signed },
+            Signal { name: "point2", length: Some(3), bits: 30, encoding: Encoding::Signed },
+            MIN_DISTANCE_SQUARED,
+        ],
+        // The points and their distance stay private
+        public: &[MIN_DISTANCE_SQUARED],
+        image_commitment: None,
+    },
+};
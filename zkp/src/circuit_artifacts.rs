@@ -0,0 +1,190 @@
+// Circuit artifacts published for client-side verification.
+//
+// A web frontend verifying proofs in the browser needs the verification key
+// of the circuit a proof was made with, and experiments with local witness
+// generation its wasm. GET /circuits/{version}/{artifact} serves them without
+// a key, but only the pairs listed in PUBLIC_CIRCUIT_ARTIFACTS, such as
+// "zkhotdog-v1:vkey.json,zkhotdog-v1:circuit.wasm". Everything else is
+// answered with 404, and the proving key cannot be listed at all.
+//
+// A circuit version names one build, so responses are cached as immutable.
+// The ETag is the SHA-256 of the file, kept in memory until the file's size
+// or modification time changes, so revalidation does not read the file.
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use crate::{
+    AppState,
+    circuit::{self, Circuit},
+    downloads,
+};
+
+const CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+// Files of a circuit that may be published
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PublicArtifact {
+    Vkey,
+    Wasm,
+}
+
+impl PublicArtifact {
+    pub fn name(self) -> &'static str {
+        match self {
+            PublicArtifact::Vkey => "vkey.json",
+            PublicArtifact::Wasm => "circuit.wasm",
+        }
+    }
+
+    fn path(self, circuit: &Circuit) -> &'static str {
+        match self {
+            PublicArtifact::Vkey => circuit.vkey_path,
+            PublicArtifact::Wasm => circuit.wasm_path,
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            PublicArtifact::Vkey => "application/json",
+            PublicArtifact::Wasm => "application/wasm",
+        }
+    }
+}
+
+impl FromStr for PublicArtifact {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "vkey.json" => Ok(PublicArtifact::Vkey),
+            "circuit.wasm" => Ok(PublicArtifact::Wasm),
+            other if other.contains("zkey") => {
+                Err(format!("'{}' cannot be published: proving keys are never served", other))
+            }
+            other => Err(format!(
+                "unknown circuit artifact '{}', expected vkey.json or circuit.wasm",
+                other
+            )),
+        }
+    }
+}
+
+// One entry of PUBLIC_CIRCUIT_ARTIFACTS, written as version:artifact
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublishedArtifact {
+    pub version: &'static str,
+    pub artifact: PublicArtifact,
+}
+
+impl FromStr for PublishedArtifact {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (version, artifact) = s.split_once(':').ok_or_else(|| {
+            format!(
+                "'{}' should be a circuit version and an artifact, like zkhotdog-v1:vkey.json",
+                s
+            )
+        })?;
+        let circuit = circuit::find(version.trim())
+            .ok_or_else(|| format!("unknown circuit version '{}'", version.trim()))?;
+        Ok(PublishedArtifact { version: circuit.version, artifact: artifact.trim().parse()? })
+    }
+}
+
+// Artifacts published for each circuit version, for /capabilities
+pub fn published_for(published: &[PublishedArtifact], version: &str) -> Vec<&'static str> {
+    published.iter().filter(|p| p.version == version).map(|p| p.artifact.name()).collect()
+}
+
+// SHA-256 of each served file, with the size and modification time it was
+// computed for
+#[derive(Default)]
+pub struct ArtifactHashes {
+    hashes: Mutex<HashMap<&'static str, (u64, SystemTime, String)>>,
+}
+
+impl ArtifactHashes {
+    fn get(&self, path: &str, len: u64, modified: SystemTime) -> Option<String> {
+        let hashes = self.hashes.lock().unwrap();
+        hashes
+            .get(path)
+            .filter(|(l, m, _)| *l == len && *m == modified)
+            .map(|(_, _, hash)| hash.clone())
+    }
+
+    fn insert(&self, path: &'static str, len: u64, modified: SystemTime, hash: String) {
+        self.hashes.lock().unwrap().insert(path, (len, modified, hash));
+    }
+}
+
+// Handler serving a published circuit artifact
+pub async fn serve(
+    State(state): State<Arc<AppState>>,
+    Path((version, name)): Path<(String, String)>,
+    request_headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let not_found = || {
+        (StatusCode::NOT_FOUND, format!("Circuit artifact {}/{} is not published", version, name))
+    };
+    let artifact: PublicArtifact = name.parse().map_err(|_| not_found())?;
+    let published = state
+        .config
+        .public_circuit_artifacts
+        .iter()
+        .find(|p| p.version == version && p.artifact == artifact)
+        .ok_or_else(not_found)?;
+    let circuit = circuit::find(published.version).ok_or_else(not_found)?;
+    let path = artifact.path(&circuit);
+
+    let internal = |e: std::io::Error| {
+        (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read {}: {}", path, e))
+    };
+    let meta = match tokio::fs::metadata(path).await {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err((
+                StatusCode::NOT_FOUND,
+                format!("Circuit artifact {}/{} is not installed", version, name),
+            ));
+        }
+        Err(e) => return Err(internal(e)),
+    };
+    let modified = meta.modified().map_err(internal)?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(CACHE_CONTROL));
+    if let Some(hash) = state.circuit_artifacts.get(path, meta.len(), modified) {
+        let etag = format!("\"{}\"", hash);
+        if downloads::matches_etag(&request_headers, &etag) {
+            headers.insert(header::ETAG, header_value(&etag)?);
+            return Ok((StatusCode::NOT_MODIFIED, headers).into_response());
+        }
+    }
+
+    let content = tokio::fs::read(path).await.map_err(internal)?;
+    let hash = hex::encode(Sha256::digest(&content));
+    state.circuit_artifacts.insert(path, content.len() as u64, modified, hash.clone());
+    let etag = format!("\"{}\"", hash);
+    headers.insert(header::ETAG, header_value(&etag)?);
+    if downloads::matches_etag(&request_headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, headers).into_response());
+    }
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(artifact.content_type()));
+    Ok((StatusCode::OK, headers, content).into_response())
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, (StatusCode, String)> {
+    HeaderValue::from_str(value).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
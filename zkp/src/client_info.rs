@@ -0,0 +1,80 @@
+// What the submitting app says about itself (device, app version, platform
+// and capture method).
+//
+// The clientInfo of a submission helps tell apart measurements from
+// different phones and app releases when a batch of them looks off. It is a
+// flat JSON object of short strings with known keys, at most MAX_RAW_BYTES
+// long; anything else, nested values included, is rejected with 400 rather
+// than stored. Like metadata it ends up in API responses, so values with
+// control or bidirectional override characters are refused as well.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::metadata::is_unsafe_char;
+
+// Largest clientInfo payload accepted before parsing, in bytes
+pub const MAX_RAW_BYTES: usize = 1024;
+const MAX_DEVICE_CHARS: usize = 64;
+const MAX_APP_VERSION_CHARS: usize = 32;
+const MAX_PLATFORM_CHARS: usize = 32;
+const MAX_CAPTURE_METHOD_CHARS: usize = 32;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientInfo {
+    // Model of the device, such as "iPhone15,2"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app_version: Option<String>,
+    // Operating system, such as "iOS 17.4"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+    // How the points were taken, such as "lidar" or "manual"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capture_method: Option<String>,
+}
+
+// Parse and validate a raw clientInfo payload
+pub fn parse(raw: &[u8]) -> Result<ClientInfo, String> {
+    if raw.len() > MAX_RAW_BYTES {
+        return Err(format!("clientInfo exceeds the maximum size of {} bytes", MAX_RAW_BYTES));
+    }
+
+    let value: Value = serde_json::from_slice(raw)
+        .map_err(|e| format!("Failed to parse clientInfo JSON: {}", e))?;
+    let Value::Object(fields) = value else {
+        return Err("clientInfo must be a JSON object".to_string());
+    };
+
+    let mut info = ClientInfo::default();
+    for (key, value) in fields {
+        let (slot, max_chars) = match key.as_str() {
+            "device" => (&mut info.device, MAX_DEVICE_CHARS),
+            "appVersion" => (&mut info.app_version, MAX_APP_VERSION_CHARS),
+            "platform" => (&mut info.platform, MAX_PLATFORM_CHARS),
+            "captureMethod" => (&mut info.capture_method, MAX_CAPTURE_METHOD_CHARS),
+            other => return Err(format!("Unknown clientInfo field '{}'", other)),
+        };
+        let Value::String(text) = value else {
+            return Err(format!("clientInfo {} must be a string", key));
+        };
+        *slot = Some(check_text(&key, &text, max_chars)?);
+    }
+
+    Ok(info)
+}
+
+// Enforce the length limit of a field and refuse invisible characters
+fn check_text(field: &str, value: &str, max_chars: usize) -> Result<String, String> {
+    let value = value.trim();
+    if value.is_empty() || value.chars().count() > max_chars {
+        return Err(format!("clientInfo {} must be 1 to {} characters long", field, max_chars));
+    }
+    if value.chars().any(is_unsafe_char) {
+        return Err(format!("clientInfo {} must not contain control characters", field));
+    }
+    Ok(value.to_string())
+}
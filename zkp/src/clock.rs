@@ -0,0 +1,70 @@
+// Wall-clock and monotonic time.
+//
+// The host clock can be stepped while the server runs, when NTP corrects it
+// or a VM resumes, and a timer kept as a wall-clock time then fires early or
+// never. Timers within the process therefore run on the monotonic clock of
+// tokio::time::Instant: client deadlines, the attestation poller's delays and
+// maximum wait, and the cooldowns of RPC endpoints. Wall-clock time is kept
+// for what clients see and what is persisted, such as the deadline itself,
+// and converted into an Instant once with Clock::instant_at, when the time is
+// received or loaded from the store at startup.
+//
+// Testing builds step the wall clock by the seconds in the file named by
+// CLOCK_STEP_PATH, read anew on every reading, so tests can step the clock of
+// a running server; unit tests step theirs through Clock::stepped_by.
+
+use chrono::{DateTime, Duration, Utc};
+use tokio::time::Instant;
+
+use crate::config::Config;
+
+// The host's wall clock, unstepped by default
+#[derive(Default)]
+pub struct Clock {
+    #[cfg(any(test, feature = "testing"))]
+    step_path: Option<String>,
+}
+
+impl Clock {
+    #[cfg_attr(not(feature = "testing"), allow(unused_variables))]
+    pub fn new(config: &Config) -> Self {
+        Clock {
+            #[cfg(feature = "testing")]
+            step_path: config.clock_step_path.clone(),
+            #[cfg(all(test, not(feature = "testing")))]
+            step_path: None,
+        }
+    }
+
+    // A clock stepped by the seconds in the file at path
+    #[cfg(test)]
+    pub fn stepped_by(path: &str) -> Self {
+        Clock { step_path: Some(path.to_string()) }
+    }
+
+    // Current wall-clock time
+    pub fn now(&self) -> DateTime<Utc> {
+        Utc::now() + self.step()
+    }
+
+    // Seconds the wall clock is stepped by
+    #[cfg(any(test, feature = "testing"))]
+    fn step(&self) -> Duration {
+        self.step_path
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|step| step.trim().parse().ok())
+            .map_or(Duration::zero(), Duration::seconds)
+    }
+
+    #[cfg(not(any(test, feature = "testing")))]
+    fn step(&self) -> Duration {
+        Duration::zero()
+    }
+
+    // Monotonic instant at which a wall-clock time is reached, as far as the
+    // wall clock can tell now; times already past map to now
+    pub fn instant_at(&self, at: DateTime<Utc>) -> Instant {
+        Instant::now() + (at - self.now()).to_std().unwrap_or_default()
+    }
+}
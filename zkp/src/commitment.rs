@@ -0,0 +1,142 @@
+// Verification of a measurement's stored image against what vouches for it.
+//
+// A bug anywhere in hashing or file handling could complete a measurement
+// whose attestation stands for another image than the one served for it, and
+// nothing downstream would notice. So before a measurement is marked
+// Completed, its stored image is hashed again and compared with
+//   - the SHA-256 recorded when the image was accepted (image_sha256), and
+//   - for circuits committing to the image, the hash the proof commits to,
+//     decoded from public.json with the circuit's CircuitSpec.
+// zkhotdog-v1 commits to no image, so its measurements are compared with the
+// recorded hash alone; one with neither, such as a rebuilt measurement of
+// that circuit, has nothing to be compared with.
+//
+// A mismatch fails the measurement with the class CommitmentMismatch instead
+// of completing it. Both hashes go to its failure message and pipeline log,
+// and an alert is logged and counted under commitments in /admin/stats. The
+// consistency scan (reconcile.rs) checks the completed measurements it
+// rebuilds the same way, and the image_commitments check of the doctor every
+// completed measurement on record.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{
+    fs,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use crate::{
+    AppState, FailureClass,
+    artifacts::Artifact,
+    circuit::{self, Circuit},
+    history::Source,
+    pipeline, version,
+};
+
+// Commitment check figures for the admin stats endpoint
+#[derive(Serialize)]
+pub struct CommitmentStats {
+    checked: u64,
+    mismatched: u64,
+    // Latest measurement failed by a mismatch
+    last_mismatch: Option<LastMismatch>,
+}
+
+#[derive(Serialize, Clone)]
+struct LastMismatch {
+    id: String,
+    at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+pub struct CommitmentChecks {
+    checked: AtomicU64,
+    mismatched: AtomicU64,
+    last_mismatch: Mutex<Option<LastMismatch>>,
+}
+
+impl CommitmentChecks {
+    // Log and count a mismatch found for a measurement
+    pub fn alert(&self, id: &str, message: &str) {
+        println!("ALERT: image commitment mismatch for measurement {}: {}", id, message);
+        self.mismatched.fetch_add(1, Ordering::Relaxed);
+        *self.last_mismatch.lock().unwrap() =
+            Some(LastMismatch { id: id.to_string(), at: Utc::now() });
+    }
+
+    pub fn stats(&self) -> CommitmentStats {
+        CommitmentStats {
+            checked: self.checked.load(Ordering::Relaxed),
+            mismatched: self.mismatched.load(Ordering::Relaxed),
+            last_mismatch: self.last_mismatch.lock().unwrap().clone(),
+        }
+    }
+}
+
+// Compare the stored image of a measurement with the hash recorded for it and
+// the hash its proof commits to, describing the first that differs
+pub fn verify(id: &str, recorded: Option<&str>, circuit: &Circuit) -> Result<(), String> {
+    let committed = match circuit.spec.image_commitment {
+        Some(_) => Some(committed_image(id, circuit)?),
+        None => None,
+    };
+    if recorded.is_none() && committed.is_none() {
+        return Ok(());
+    }
+    let path = Artifact::Image.path(id);
+    let stored = version::hash_file(&path).ok_or_else(|| format!("Cannot read image {}", path))?;
+    let references =
+        [(recorded, "recorded at submission"), (committed.as_deref(), "committed by the proof")];
+    for (expected, what) in references {
+        if let Some(expected) = expected
+            && expected != stored
+        {
+            return Err(format!(
+                "Stored image has SHA-256 {}, the hash {} is {}",
+                stored, what, expected
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Image hash the public signals of a measurement's proof commit to
+fn committed_image(id: &str, circuit: &Circuit) -> Result<String, String> {
+    let path = Artifact::PublicSignals.path(id);
+    let content = fs::read_to_string(&path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+    let values: Vec<String> =
+        serde_json::from_str(&content).map_err(|e| format!("Malformed {}: {}", path, e))?;
+    let signals = circuit.spec.decode_public(&values).map_err(|e| format!("{}: {}", path, e))?;
+    circuit
+        .spec
+        .committed_image(&signals)
+        .ok_or_else(|| format!("{} holds no image commitment", path))
+}
+
+// Check a measurement about to complete, failing it with CommitmentMismatch
+// when its image differs from what vouches for it. Returns whether it may
+// complete.
+pub fn confirm(state: &AppState, id: &str, source: Source) -> bool {
+    let Some((recorded, circuit)) = state
+        .measurements
+        .lock()
+        .get(id)
+        .map(|m| (m.image_sha256.clone(), circuit::resolve(m.circuit.as_deref())))
+    else {
+        return false;
+    };
+    state.commitments.checked.fetch_add(1, Ordering::Relaxed);
+    let Err(message) = verify(id, recorded.as_deref(), &circuit) else {
+        return true;
+    };
+    state.commitments.alert(id, &message);
+    pipeline::append_log(
+        &Artifact::Log.path(id),
+        &format!("[{}] Image commitment mismatch: {}\n", Utc::now().to_rfc3339(), message),
+    );
+    crate::fail_measurement(state, id, source, FailureClass::CommitmentMismatch, message);
+    false
+}
@@ -0,0 +1,447 @@
+// Runtime configuration, read from environment variables at startup.
+
+use chrono::NaiveTime;
+use std::{env, str::FromStr};
+
+use crate::{
+    attestation_backend::{BackendKind, CircuitBackend},
+    auth::{self, Role},
+    broker::BrokerKind,
+    circuit,
+    circuit_artifacts::PublishedArtifact,
+    contract::CircuitVkHash,
+    intake::{IntakeSchedule, OutsidePolicy},
+    native_prover::ProverKind,
+    plan::{self, StageName},
+    polyline,
+    proving_keys::PinMode,
+    queue::QueuePolicy,
+    similarity::DuplicateMode,
+    store::StoreKind,
+    supervisor::ProcessRole,
+    tenants::Tenants,
+    uploads,
+};
+
+// Fields holding secrets are masked in support bundles by support::masked_config
+#[derive(Debug, Clone)]
+pub struct Config {
+    // Number of proofs generated concurrently (PROOF_WORKERS)
+    pub proof_workers: usize,
+    // Order in which queued proofs are started (QUEUE_POLICY=fifo|fair)
+    pub queue_policy: QueuePolicy,
+    // Thumbnail sizes in pixels that may be requested (THUMBNAIL_SIZES=256,1024)
+    pub thumbnail_sizes: Vec<u32>,
+    // Generate thumbnails and the WebP variant right after upload (THUMBNAIL_PREWARM)
+    pub thumbnail_prewarm: bool,
+    // Size of the pre-warm worker pool, separate from the proof workers (THUMBNAIL_WORKERS)
+    pub thumbnail_workers: usize,
+    // File receiving the audit log of administrative actions (AUDIT_LOG_PATH)
+    pub audit_log_path: String,
+    // Key that always has the admin role; enables access control (ADMIN_API_KEY)
+    pub admin_api_key: Option<String>,
+    // File holding the hashed API keys and their roles (API_KEYS_PATH)
+    pub api_keys_path: String,
+    // File holding the key signing listing cursors, created if missing (CURSOR_SECRET_PATH)
+    pub cursor_secret_path: String,
+    // How long a rotated key keeps working next to its replacement (API_KEY_ROTATION_GRACE_SECS)
+    pub api_key_rotation_grace_secs: u64,
+    // Circuit artifacts served to anyone, as version:artifact (PUBLIC_CIRCUIT_ARTIFACTS)
+    pub public_circuit_artifacts: Vec<PublishedArtifact>,
+    // Measurements a backfill derives fields for at a time (BACKFILL_BATCH_SIZE)
+    pub backfill_batch_size: usize,
+    // Pause between backfill batches, leaving room for live traffic (BACKFILL_BATCH_DELAY_MS)
+    pub backfill_batch_delay_ms: u64,
+    // File holding the cursor of the current backfill (BACKFILL_STATE_PATH)
+    pub backfill_state_path: String,
+    // Backend measurements are stored in (MEASUREMENT_STORE=sled|memory|postgres)
+    pub measurement_store: StoreKind,
+    // Embedded database measurements are persisted to with the sled store (MEASUREMENTS_DB_PATH)
+    pub measurements_db_path: String,
+    // Postgres database of the postgres store (DATABASE_URL)
+    pub database_url: String,
+    // Measurements whose files are checked at once during startup recovery (RECOVERY_CONCURRENCY)
+    pub recovery_concurrency: usize,
+    // Faults injected by the faulty store of testing builds (STORE_FAULTS)
+    #[cfg_attr(not(feature = "testing"), allow(dead_code))]
+    pub store_faults: String,
+    // File holding seconds to step the wall clock by, in testing builds (CLOCK_STEP_PATH)
+    #[cfg_attr(not(feature = "testing"), allow(dead_code))]
+    pub clock_step_path: Option<String>,
+    // Role of requests without an API key, None to require one (ANONYMOUS_ROLE)
+    pub anonymous_role: Option<Role>,
+    // Write the natively built zkVerify payload next to each proof (SUBMISSION_PAYLOAD_DEBUG)
+    pub submission_payload_debug: bool,
+    // Fail a proof instead of regenerating an input.json that disagrees with
+    // its measurement (STRICT_INPUTS)
+    pub strict_inputs: bool,
+    // Refuse submissions that do not name their coordinate system (STRICT_COORDINATE_SYSTEM)
+    pub strict_coordinate_system: bool,
+    // Answer duplicate externalIds with the existing measurement instead of 409
+    // (EXTERNAL_ID_RETURN_EXISTING)
+    pub external_id_return_existing: bool,
+    // Hex Ed25519 public keys whose offline bundles are accepted (IMPORT_TRUSTED_KEYS)
+    pub import_trusted_keys: Vec<String>,
+    // Longest a single witness or proving step may run, in seconds (PROOF_STEP_TIMEOUT_SECS)
+    pub proof_step_timeout_secs: u64,
+    // Oldest capture time accepted for offline bundles, in days (IMPORT_MAX_AGE_DAYS)
+    pub import_max_age_days: i64,
+    // When proving keys are pinned in memory (PROVING_KEY_PIN=eager|lazy|off)
+    pub proving_key_pin: PinMode,
+    // What generates proofs, snarkjs or the native prover (PROVER=native|snarkjs)
+    pub prover: ProverKind,
+    // Network attesting proofs (ATTESTATION_BACKEND=zkverify|local)
+    pub attestation_backend: BackendKind,
+    // Backends of particular circuit versions, as version:backend (ATTESTATION_BACKEND_BY_CIRCUIT)
+    pub attestation_backend_by_circuit: Vec<CircuitBackend>,
+    // Hashes zkVerify registered the verification keys of circuit versions
+    // under, as version:0x... (VK_HASH_BY_CIRCUIT)
+    pub vk_hashes: Vec<CircuitVkHash>,
+    // Leave waiting for attestations to a single poller (ATTESTATION_POLLING)
+    pub attestation_polling: bool,
+    // Seconds between poll rounds and before the first check (ATTESTATION_POLL_INTERVAL_SECS)
+    pub attestation_poll_interval_secs: u64,
+    // Most measurements looked up in one poll round (ATTESTATION_POLL_BATCH)
+    pub attestation_poll_batch: usize,
+    // Longest delay between checks of one measurement (ATTESTATION_BACKOFF_MAX_SECS)
+    pub attestation_backoff_max_secs: u64,
+    // Measurements not attested this many seconds after submission fail (ATTESTATION_MAX_WAIT_SECS)
+    pub attestation_max_wait_secs: u64,
+    // Prove a sample of measurements again with a shadow prover (SHADOW_PROVING)
+    pub shadow_proving: bool,
+    // Command of the shadow prover, taking rapidsnark's arguments (SHADOW_PROVER)
+    pub shadow_prover: String,
+    // Percentage of proofs repeated by the shadow prover (SHADOW_SAMPLE_PERCENT)
+    pub shadow_sample_percent: u32,
+    // Clock skew tolerated in client timestamps, in seconds (CLOCK_SKEW_SECS)
+    pub clock_skew_secs: u64,
+    // Client clocks ahead by more than this many seconds are logged (CLOCK_SKEW_WARN_SECS)
+    pub clock_skew_warn_secs: u64,
+    // Largest offline bundle, and largest file inside one, in bytes (IMPORT_MAX_BYTES)
+    pub import_max_bytes: usize,
+    // Largest POST /measurements/json body, base64 image included, in bytes (JSON_MAX_BYTES)
+    pub json_max_bytes: usize,
+    // Longest fetch of an imageUrl, redirects included, in seconds (IMAGE_URL_TIMEOUT_SECS)
+    pub image_url_timeout_secs: u64,
+    // Largest image fetched from an imageUrl, in bytes (IMAGE_URL_MAX_BYTES)
+    pub image_url_max_bytes: usize,
+    // Fetch imageUrls resolving to loopback or private addresses (IMAGE_URL_ALLOW_PRIVATE)
+    pub image_url_allow_private: bool,
+    // Largest depth map sent with a measurement, in bytes (DEPTH_MAP_MAX_BYTES)
+    pub depth_map_max_bytes: usize,
+    // Most points of a polyline measurement, up to what its circuit takes (MAX_POLYLINE_POINTS)
+    pub max_polyline_points: usize,
+    // Wasm of the angle circuit, its witness generator beside it (ANGLE_CIRCUIT_WASM)
+    pub angle_circuit_wasm: String,
+    // Proving key of the angle circuit (ANGLE_CIRCUIT_ZKEY)
+    pub angle_circuit_zkey: String,
+    // Interval between detections of the node and snarkjs versions (TOOLCHAIN_REFRESH_SECS)
+    pub toolchain_refresh_secs: u64,
+    // Weekly hours during which submissions are accepted (INTAKE_WINDOWS, INTAKE_TIMEZONE)
+    pub intake_windows: Option<IntakeSchedule>,
+    // Reject or schedule submissions outside the intake windows (INTAKE_OUTSIDE)
+    pub intake_outside: OutsidePolicy,
+    // Time of day, in UTC, at which the previous day is summarized (SUMMARY_TIME=HH:MM)
+    pub summary_time: NaiveTime,
+    // File holding the daily summaries (SUMMARIES_PATH)
+    pub summaries_path: String,
+    // Send anonymous usage reports every day (TELEMETRY_ENABLED)
+    pub telemetry_enabled: bool,
+    // URL the usage reports are POSTed to (TELEMETRY_ENDPOINT)
+    pub telemetry_endpoint: Option<String>,
+    // Measurements read from the store kept in memory (MEASUREMENT_CACHE_CAPACITY, 0 for none)
+    pub measurement_cache_capacity: usize,
+    // Window in which status requests for one measurement share a store read
+    // (STATUS_COALESCE_WINDOW_MS, 0 to read for every request)
+    pub status_coalesce_window_ms: u64,
+    // Milliseconds between refreshes of the measurements in memory from a
+    // shared store (STORE_SYNC_INTERVAL_MS, 0 for never)
+    pub store_sync_interval_ms: u64,
+    // Key signing webhook callbacks; submissions cannot name a callbackUrl
+    // without it (WEBHOOK_SECRET)
+    pub webhook_secret: Option<String>,
+    // Attempts at delivering a webhook before giving up (WEBHOOK_MAX_ATTEMPTS)
+    pub webhook_max_attempts: u32,
+    // Deliver webhooks to callback URLs resolving to loopback or private
+    // addresses (WEBHOOK_ALLOW_PRIVATE)
+    pub webhook_allow_private: bool,
+    // Stages left out of the plan of every new measurement (PIPELINE_SKIP_STAGES)
+    pub pipeline_skip_stages: Vec<StageName>,
+    // Message broker receiving measurement events (BROKER=none|nats|kafka)
+    pub broker: BrokerKind,
+    // Broker address; empty uses the client's local default (BROKER_URL)
+    pub broker_url: String,
+    // NATS subject or Kafka topic events are published to (BROKER_TOPIC)
+    pub broker_topic: String,
+    // Publish attempts before an event is dead-lettered (BROKER_MAX_ATTEMPTS)
+    pub broker_max_attempts: u32,
+    // File receiving events that could not be published (BROKER_DEAD_LETTER_PATH)
+    pub broker_dead_letter_path: String,
+    // Tenants sharing the deployment, with optional quotas (TENANTS=name[:quota],...)
+    pub tenants: Tenants,
+    // Seconds before a zkVerify transaction not yet in a block counts as stuck (SIGNER_STUCK_SECS)
+    pub signer_stuck_secs: u64,
+    // zkVerify RPC endpoints, preferred first; empty uses the client's default (ZK_VERIFY_RPC_URLS)
+    pub zkverify_rpc_urls: Vec<String>,
+    // Connection failures in a row that quarantine an RPC endpoint (RPC_BREAKER_FAILURES)
+    pub rpc_breaker_failures: u32,
+    // First quarantine of an RPC endpoint in seconds, doubled on repeats (RPC_BREAKER_COOLDOWN_SECS)
+    pub rpc_breaker_cooldown_secs: u64,
+    // Largest part of a measurement's log served in one response (LOG_MAX_RESPONSE_BYTES)
+    pub log_max_response_bytes: u64,
+    // Origins allowed to call the API from a browser; empty allows any (CORS_ALLOWED_ORIGINS)
+    pub cors_allowed_origins: Vec<String>,
+    // Address clients reach the server at, used in status URLs (PUBLIC_BASE_URL)
+    pub public_base_url: String,
+    // Refuse to start with unsafe settings (PRODUCTION, or --production)
+    pub production: bool,
+    // Safety rules waived in production mode (ALLOW_UNSAFE, or --allow-unsafe=)
+    pub allow_unsafe: Vec<String>,
+    // Flag or reject images that look like another owner's (DUPLICATE_MODE=off|flag|reject)
+    pub duplicate_mode: DuplicateMode,
+    // Largest perceptual hash distance, out of 64 bits, counted as similar (DUPLICATE_MAX_DISTANCE)
+    pub duplicate_max_distance: u32,
+    // Start in read-only maintenance mode, unless the state file says otherwise (MAINTENANCE)
+    pub maintenance: bool,
+    // Message returned with refused changes during maintenance (MAINTENANCE_MESSAGE)
+    pub maintenance_message: Option<String>,
+    // File keeping the maintenance mode set through the admin endpoint (MAINTENANCE_STATE_PATH)
+    pub maintenance_state_path: String,
+    // Disk budget for thumbnails and WebP variants together (CACHE_MAX_BYTES)
+    pub cache_max_bytes: u64,
+    // Optional budget for thumbnails alone (CACHE_THUMBNAIL_MAX_BYTES)
+    pub cache_thumbnail_max_bytes: Option<u64>,
+    // Optional budget for WebP variants alone (CACHE_WEBP_MAX_BYTES)
+    pub cache_webp_max_bytes: Option<u64>,
+    // Seconds after its last request a variant is evicted, 0 for never (CACHE_TTL_SECS)
+    pub cache_ttl_secs: i64,
+    // File keeping the last access of every cached variant (CACHE_INDEX_PATH)
+    pub cache_index_path: String,
+    // Directory support bundles are written to (SUPPORT_BUNDLE_DIR)
+    pub support_bundle_dir: String,
+    // Seconds a support bundle download link stays valid (SUPPORT_BUNDLE_TTL_SECS)
+    pub support_bundle_ttl_secs: u64,
+    // Key signing support bundle links, random per process when unset (SUPPORT_BUNDLE_SECRET)
+    pub support_bundle_secret: Option<String>,
+    // File the server's output is redirected to, searched for support bundles (SERVER_LOG_PATH)
+    pub server_log_path: Option<String>,
+    // Serve replicated storage as a read-only mirror (READ_ONLY or --read-only)
+    pub read_only: bool,
+    // Seconds since the last replicated write after which a mirror is unready (REPLICA_MAX_LAG_SECS)
+    pub replica_max_lag_secs: u64,
+    // Generate proofs in a child process of the server (SUPERVISE or --supervise)
+    pub supervise: bool,
+    // Process this is, the server or its prover child (--role)
+    pub role: ProcessRole,
+    // Unix socket the prover process connects to the server at (PROVER_SOCKET_PATH)
+    pub prover_socket_path: String,
+    // Days after which measurements are deleted with their files, 0 for never (RETENTION_DAYS)
+    pub retention_days: u64,
+    // Days attested measurements are kept instead, 0 for never (RETENTION_ATTESTED_DAYS)
+    pub retention_attested_days: u64,
+    // Seconds between retention sweeps (RETENTION_SWEEP_INTERVAL_SECS)
+    pub retention_sweep_interval_secs: u64,
+    // Seconds an unfinished upload may go unwritten before it is removed, 0
+    // to keep them (UPLOAD_ABANDONED_TTL_SECS)
+    pub upload_abandoned_ttl_secs: u64,
+    // Only count abandoned uploads instead of removing them (UPLOAD_SWEEP_DRY_RUN)
+    pub upload_sweep_dry_run: bool,
+    // Seconds a chunked upload may go unwritten before it expires (UPLOAD_SESSION_TTL_SECS)
+    pub upload_session_ttl_secs: u64,
+    // Largest image uploaded in chunks, in bytes (UPLOAD_SESSION_MAX_BYTES)
+    pub upload_session_max_bytes: u64,
+    // Largest chunk of a chunked upload, in bytes (UPLOAD_CHUNK_MAX_BYTES)
+    pub upload_chunk_max_bytes: usize,
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self, String> {
+        // Attested measurements follow the general retention unless set apart
+        let retention_days = parse_var("RETENTION_DAYS", 0)?;
+        Ok(Config {
+            proof_workers: parse_var("PROOF_WORKERS", 2)?.max(1),
+            queue_policy: parse_var("QUEUE_POLICY", QueuePolicy::Fair)?,
+            thumbnail_sizes: parse_list("THUMBNAIL_SIZES", vec![256, 1024])?,
+            thumbnail_prewarm: parse_var("THUMBNAIL_PREWARM", false)?,
+            thumbnail_workers: parse_var("THUMBNAIL_WORKERS", 1)?.max(1),
+            audit_log_path: parse_var("AUDIT_LOG_PATH", "audit.log".to_string())?,
+            admin_api_key: Some(parse_var("ADMIN_API_KEY", String::new())?)
+                .filter(|key| !key.is_empty()),
+            api_keys_path: parse_var("API_KEYS_PATH", "api-keys.json".to_string())?,
+            cursor_secret_path: parse_var("CURSOR_SECRET_PATH", "cursor-secret".to_string())?,
+            api_key_rotation_grace_secs: parse_var("API_KEY_ROTATION_GRACE_SECS", 24 * 3600)?,
+            public_circuit_artifacts: parse_list("PUBLIC_CIRCUIT_ARTIFACTS", Vec::new())?,
+            backfill_batch_size: parse_var("BACKFILL_BATCH_SIZE", 50)?.max(1),
+            backfill_batch_delay_ms: parse_var("BACKFILL_BATCH_DELAY_MS", 500)?,
+            backfill_state_path: parse_var("BACKFILL_STATE_PATH", "backfill.json".to_string())?,
+            measurement_store: parse_var("MEASUREMENT_STORE", StoreKind::Sled)?,
+            measurements_db_path: parse_var("MEASUREMENTS_DB_PATH", "measurements.db".to_string())?,
+            database_url: parse_var("DATABASE_URL", String::new())?,
+            recovery_concurrency: parse_var("RECOVERY_CONCURRENCY", 16)?.max(1),
+            store_faults: parse_var("STORE_FAULTS", String::new())?,
+            clock_step_path: Some(parse_var("CLOCK_STEP_PATH", String::new())?)
+                .filter(|path| !path.is_empty()),
+            anonymous_role: auth::parse_anonymous_role(&parse_var(
+                "ANONYMOUS_ROLE",
+                "submitter".to_string(),
+            )?)
+            .map_err(|e| format!("Invalid value for ANONYMOUS_ROLE: {}", e))?,
+            submission_payload_debug: parse_var("SUBMISSION_PAYLOAD_DEBUG", false)?,
+            strict_inputs: parse_var("STRICT_INPUTS", false)?,
+            strict_coordinate_system: parse_var("STRICT_COORDINATE_SYSTEM", false)?,
+            external_id_return_existing: parse_var("EXTERNAL_ID_RETURN_EXISTING", false)?,
+            import_trusted_keys: parse_list("IMPORT_TRUSTED_KEYS", Vec::new())?,
+            proof_step_timeout_secs: parse_var("PROOF_STEP_TIMEOUT_SECS", 1800)?.max(1),
+            import_max_age_days: parse_var("IMPORT_MAX_AGE_DAYS", 30)?,
+            proving_key_pin: parse_var("PROVING_KEY_PIN", PinMode::Eager)?,
+            prover: parse_var("PROVER", ProverKind::Snarkjs)?,
+            attestation_backend: parse_var("ATTESTATION_BACKEND", BackendKind::ZkVerify)?,
+            attestation_backend_by_circuit: parse_list(
+                "ATTESTATION_BACKEND_BY_CIRCUIT",
+                Vec::new(),
+            )?,
+            vk_hashes: parse_list("VK_HASH_BY_CIRCUIT", Vec::new())?,
+            attestation_polling: parse_var("ATTESTATION_POLLING", false)?,
+            attestation_poll_interval_secs: parse_var("ATTESTATION_POLL_INTERVAL_SECS", 30)?.max(1),
+            attestation_poll_batch: parse_var("ATTESTATION_POLL_BATCH", 500)?.max(1),
+            attestation_backoff_max_secs: parse_var("ATTESTATION_BACKOFF_MAX_SECS", 600)?,
+            attestation_max_wait_secs: parse_var("ATTESTATION_MAX_WAIT_SECS", 24 * 3600)?,
+            shadow_proving: parse_var("SHADOW_PROVING", false)?,
+            shadow_prover: parse_var("SHADOW_PROVER", "prover".to_string())?,
+            shadow_sample_percent: parse_var("SHADOW_SAMPLE_PERCENT", 10)?.min(100),
+            clock_skew_secs: parse_var("CLOCK_SKEW_SECS", 300)?,
+            clock_skew_warn_secs: parse_var("CLOCK_SKEW_WARN_SECS", 60)?,
+            import_max_bytes: parse_var("IMPORT_MAX_BYTES", 32 * 1024 * 1024)?,
+            json_max_bytes: parse_var("JSON_MAX_BYTES", 3 * 1024 * 1024)?,
+            image_url_timeout_secs: parse_var("IMAGE_URL_TIMEOUT_SECS", 10)?,
+            image_url_max_bytes: parse_var("IMAGE_URL_MAX_BYTES", uploads::MAX_REQUEST_BYTES)?,
+            image_url_allow_private: parse_var("IMAGE_URL_ALLOW_PRIVATE", false)?,
+            depth_map_max_bytes: parse_var("DEPTH_MAP_MAX_BYTES", 512 * 1024)?,
+            max_polyline_points: polyline::check_limit(parse_var(
+                "MAX_POLYLINE_POINTS",
+                polyline::CAPACITY,
+            )?)?,
+            angle_circuit_wasm: parse_var(
+                "ANGLE_CIRCUIT_WASM",
+                circuit::ZKHOTDOG_ANGLE.wasm_path.to_string(),
+            )?,
+            angle_circuit_zkey: parse_var(
+                "ANGLE_CIRCUIT_ZKEY",
+                circuit::ZKHOTDOG_ANGLE.zkey_path.to_string(),
+            )?,
+            toolchain_refresh_secs: parse_var("TOOLCHAIN_REFRESH_SECS", 3600)?.max(1),
+            intake_windows: IntakeSchedule::parse(
+                &parse_var("INTAKE_WINDOWS", String::new())?,
+                &parse_var("INTAKE_TIMEZONE", "UTC".to_string())?,
+            )
+            .map_err(|e| format!("Invalid value for INTAKE_WINDOWS: {}", e))?,
+            intake_outside: parse_var("INTAKE_OUTSIDE", OutsidePolicy::Reject)?,
+            summary_time: NaiveTime::parse_from_str(
+                &parse_var("SUMMARY_TIME", "00:05".to_string())?,
+                "%H:%M",
+            )
+            .map_err(|e| format!("Invalid value for SUMMARY_TIME: {}", e))?,
+            summaries_path: parse_var("SUMMARIES_PATH", "summaries.json".to_string())?,
+            telemetry_enabled: parse_var("TELEMETRY_ENABLED", false)?,
+            telemetry_endpoint: Some(parse_var("TELEMETRY_ENDPOINT", String::new())?)
+                .filter(|url| !url.is_empty()),
+            measurement_cache_capacity: parse_var("MEASUREMENT_CACHE_CAPACITY", 10000)?,
+            status_coalesce_window_ms: parse_var("STATUS_COALESCE_WINDOW_MS", 50)?,
+            store_sync_interval_ms: parse_var("STORE_SYNC_INTERVAL_MS", 1000)?,
+            webhook_secret: Some(parse_var("WEBHOOK_SECRET", String::new())?)
+                .filter(|secret| !secret.is_empty()),
+            webhook_max_attempts: parse_var("WEBHOOK_MAX_ATTEMPTS", 4)?,
+            webhook_allow_private: parse_var("WEBHOOK_ALLOW_PRIVATE", false)?,
+            pipeline_skip_stages: plan::parse_skips(&parse_var(
+                "PIPELINE_SKIP_STAGES",
+                String::new(),
+            )?)
+            .map_err(|e| format!("Invalid value for PIPELINE_SKIP_STAGES: {}", e))?,
+            broker: parse_var("BROKER", BrokerKind::None)?,
+            broker_url: parse_var("BROKER_URL", String::new())?,
+            broker_topic: parse_var("BROKER_TOPIC", "zkhotdog.measurements".to_string())?,
+            broker_max_attempts: parse_var("BROKER_MAX_ATTEMPTS", 5)?.max(1),
+            broker_dead_letter_path: parse_var(
+                "BROKER_DEAD_LETTER_PATH",
+                "broker-dead-letter.log".to_string(),
+            )?,
+            tenants: Tenants::parse(&parse_var("TENANTS", String::new())?)
+                .map_err(|e| format!("Invalid value for TENANTS: {}", e))?,
+            signer_stuck_secs: parse_var("SIGNER_STUCK_SECS", 300)?.max(1),
+            zkverify_rpc_urls: parse_list("ZK_VERIFY_RPC_URLS", Vec::new())?,
+            rpc_breaker_failures: parse_var("RPC_BREAKER_FAILURES", 3)?.max(1),
+            rpc_breaker_cooldown_secs: parse_var("RPC_BREAKER_COOLDOWN_SECS", 60)?,
+            log_max_response_bytes: parse_var("LOG_MAX_RESPONSE_BYTES", 1024 * 1024)?.max(1),
+            cors_allowed_origins: parse_list("CORS_ALLOWED_ORIGINS", Vec::new())?,
+            public_base_url: parse_var("PUBLIC_BASE_URL", "http://localhost:3000".to_string())?
+                .trim_end_matches('/')
+                .to_string(),
+            production: parse_var("PRODUCTION", false)?,
+            allow_unsafe: parse_list("ALLOW_UNSAFE", Vec::new())?,
+            duplicate_mode: parse_var("DUPLICATE_MODE", DuplicateMode::Flag)?,
+            duplicate_max_distance: parse_var("DUPLICATE_MAX_DISTANCE", 10)?.min(64),
+            maintenance: parse_var("MAINTENANCE", false)?,
+            maintenance_message: Some(parse_var("MAINTENANCE_MESSAGE", String::new())?)
+                .filter(|message| !message.is_empty()),
+            maintenance_state_path: parse_var(
+                "MAINTENANCE_STATE_PATH",
+                "maintenance.json".to_string(),
+            )?,
+            cache_max_bytes: parse_var("CACHE_MAX_BYTES", 1024 * 1024 * 1024)?,
+            cache_thumbnail_max_bytes: Some(parse_var("CACHE_THUMBNAIL_MAX_BYTES", 0)?)
+                .filter(|bytes| *bytes > 0),
+            cache_webp_max_bytes: Some(parse_var("CACHE_WEBP_MAX_BYTES", 0)?)
+                .filter(|bytes| *bytes > 0),
+            cache_ttl_secs: parse_var("CACHE_TTL_SECS", 30 * 24 * 3600)?.max(0),
+            cache_index_path: parse_var("CACHE_INDEX_PATH", "cache-index.json".to_string())?,
+            support_bundle_dir: parse_var("SUPPORT_BUNDLE_DIR", "support-bundles".to_string())?,
+            support_bundle_ttl_secs: parse_var("SUPPORT_BUNDLE_TTL_SECS", 24 * 3600)?.max(1),
+            support_bundle_secret: Some(parse_var("SUPPORT_BUNDLE_SECRET", String::new())?)
+                .filter(|secret| !secret.is_empty()),
+            server_log_path: Some(parse_var("SERVER_LOG_PATH", String::new())?)
+                .filter(|path| !path.is_empty()),
+            read_only: parse_var("READ_ONLY", false)?,
+            replica_max_lag_secs: parse_var("REPLICA_MAX_LAG_SECS", 300)?,
+            supervise: parse_var("SUPERVISE", false)?,
+            role: ProcessRole::Server,
+            prover_socket_path: parse_var("PROVER_SOCKET_PATH", "prover.sock".to_string())?,
+            retention_days,
+            retention_attested_days: parse_var("RETENTION_ATTESTED_DAYS", retention_days)?,
+            retention_sweep_interval_secs: parse_var("RETENTION_SWEEP_INTERVAL_SECS", 3600)?.max(1),
+            upload_abandoned_ttl_secs: parse_var("UPLOAD_ABANDONED_TTL_SECS", 3600)?,
+            upload_sweep_dry_run: parse_var("UPLOAD_SWEEP_DRY_RUN", false)?,
+            upload_session_ttl_secs: parse_var("UPLOAD_SESSION_TTL_SECS", 24 * 3600)?.max(1),
+            upload_session_max_bytes: parse_var("UPLOAD_SESSION_MAX_BYTES", 32 * 1024 * 1024)?,
+            upload_chunk_max_bytes: parse_var("UPLOAD_CHUNK_MAX_BYTES", 4 * 1024 * 1024)?,
+        })
+    }
+}
+
+// Parse an optional environment variable, falling back to a default when unset
+fn parse_var<T: FromStr>(name: &str, default: T) -> Result<T, String>
+where
+    T::Err: std::fmt::Display,
+{
+    match env::var(name) {
+        Ok(value) if !value.trim().is_empty() => {
+            value.trim().parse().map_err(|e| format!("Invalid value for {}: {}", name, e))
+        }
+        _ => Ok(default),
+    }
+}
+
+// Parse an optional comma-separated environment variable
+fn parse_list<T: FromStr>(name: &str, default: Vec<T>) -> Result<Vec<T>, String>
+where
+    T::Err: std::fmt::Display,
+{
+    match env::var(name) {
+        Ok(value) if !value.trim().is_empty() => value
+            .split(',')
+            .map(|item| {
+                item.trim().parse().map_err(|e| format!("Invalid value for {}: {}", name, e))
+            })
+            .collect(),
+        _ => Ok(default),
+    }
+}
@@ -0,0 +1,283 @@
+// Attestations shaped for the ZkHotdog contract.
+//
+// GET /attestation/{id} answers the arguments of mintWithAttestation for a
+// measurement, named as in the contract's ABI and encoded the way ethers
+// takes them: uint256 values as decimal strings and the merkle path as
+// bytes32 hex strings, so a frontend passes them on unchanged. The
+// attestation comes from the measurement, or from proofs/{id}/attestation.json
+// when it was written but not picked up yet, and every node of its merkle
+// path is checked to be 32 bytes of hex before it is answered.
+//
+// The leaf digest is computed as the contract computes it, the keccak256 of
+// keccak256("groth16"), the hash zkVerify registered the circuit's
+// verification key under and the keccak256 of the public signals as 32-byte
+// little-endian words. zkVerify's key hash cannot be derived here, so the
+// digest is only answered for circuits given in VK_HASH_BY_CIRCUIT.
+//
+// Errors carry a code: measurement_not_found (404) when the id names no
+// measurement the caller may see, attestation_pending (409) while a proof is
+// written but its attestation has not arrived, attestation_not_available
+// (404) before the proof is written or after the measurement failed,
+// not_mintable (409) for a measurement whose circuit proves no single
+// distance, such as a polyline, and invalid_attestation (500) for an
+// attestation the contract would refuse: a merkle path node that is not 32
+// bytes or a leaf index outside the tree.
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use backend::groth16::FieldElement;
+use serde::Serialize;
+use sha3::{Digest, Keccak256};
+use std::{str::FromStr, sync::Arc};
+
+use crate::{
+    AppState, AttestationData, ProofStatus, access,
+    artifacts::Artifact,
+    attestation_backend::{self, BackendKind, PendingAttestation},
+    circuit, proofs,
+    schemas::ErrorBody,
+    tenants::Visitor,
+};
+
+// One entry of VK_HASH_BY_CIRCUIT, written as version:0x<64 hex digits>
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitVkHash {
+    pub version: &'static str,
+    pub hash: [u8; 32],
+}
+
+impl FromStr for CircuitVkHash {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (version, hash) = s.split_once(':').ok_or_else(|| {
+            format!("'{}' should be a circuit version and a key hash, like zkhotdog-v1:0x...", s)
+        })?;
+        let circuit = circuit::find(version.trim())
+            .ok_or_else(|| format!("unknown circuit version '{}'", version.trim()))?;
+        let hash = hash.trim();
+        let bytes = hash
+            .strip_prefix("0x")
+            .and_then(|hex| hex::decode(hex).ok())
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+            .ok_or_else(|| format!("'{}' should be 0x and 64 hex digits", hash))?;
+        Ok(CircuitVkHash { version: circuit.version, hash: bytes })
+    }
+}
+
+#[derive(Serialize)]
+pub struct ContractAttestation {
+    measurement_id: String,
+    // Network the proof was attested by; absent on measurements stored before it was recorded
+    attestation_backend: Option<BackendKind>,
+    // The leaf the contract checks the merkle path against, null when the
+    // circuit's key hash is not configured
+    leaf_digest: Option<String>,
+    mint_with_attestation: MintArguments,
+}
+
+// Arguments of mintWithAttestation, in the order of its ABI
+#[derive(Serialize)]
+struct MintArguments {
+    #[serde(rename = "imageUrl")]
+    image_url: String,
+    // The public signal, which the contract hashes as the proof's statement
+    #[serde(rename = "lengthInCm")]
+    length_in_cm: String,
+    #[serde(rename = "_attestationId")]
+    attestation_id: String,
+    #[serde(rename = "_merklePath")]
+    merkle_path: Vec<String>,
+    #[serde(rename = "_leafCount")]
+    leaf_count: String,
+    #[serde(rename = "_index")]
+    index: String,
+}
+
+fn error(status: StatusCode, error: &'static str, message: String) -> Response {
+    (status, Json(ErrorBody { error, message, since: None })).into_response()
+}
+
+fn internal_error(id: &str, message: String) -> Response {
+    println!("Failed to answer the attestation of measurement {}: {}", id, message);
+    (StatusCode::INTERNAL_SERVER_ERROR, message).into_response()
+}
+
+// The leaf of a proof in zkVerify's attestation tree, as the contract computes it
+fn leaf_digest(vk_hash: &[u8; 32], public_signals: &[FieldElement]) -> [u8; 32] {
+    let mut statement = Keccak256::new();
+    for signal in public_signals {
+        statement.update(signal.to_le_bytes());
+    }
+    let mut leaf = Keccak256::new();
+    leaf.update(Keccak256::digest(b"groth16"));
+    leaf.update(vk_hash);
+    leaf.update(statement.finalize());
+    leaf.finalize().into()
+}
+
+// Handler answering the attestation of a measurement as the contract takes it
+pub async fn get_attestation(
+    State(state): State<Arc<AppState>>,
+    Visitor(caller): Visitor,
+    Path(id): Path<String>,
+) -> Response {
+    let known = state
+        .measurements
+        .lock()
+        .get(&id)
+        .filter(|m| access::may_see_published(&caller, m, state.time.now()))
+        .map(|m| {
+            (m.status.clone(), m.circuit.clone(), m.attestation.clone(), m.attestation_backend)
+        });
+    let Some((status, circuit, attestation, backend)) = known else {
+        return error(
+            StatusCode::NOT_FOUND,
+            "measurement_not_found",
+            format!("Measurement with ID {} not found", id),
+        );
+    };
+    // The contract mints the single distance of a proof as lengthInCm
+    let circuit = circuit::resolve(circuit.as_deref());
+    if !circuit.spec.public.iter().any(|signal| signal.name == "distance_squared") {
+        return error(
+            StatusCode::CONFLICT,
+            "not_mintable",
+            format!(
+                "Measurement {} is proved with circuit {}, which the contract cannot mint",
+                id, circuit.version
+            ),
+        );
+    }
+
+    let attestation = match attestation {
+        Some(attestation) => Some(attestation),
+        // Written by the pipeline but not attached to the measurement yet
+        None => match proofs::read(&id, Artifact::Attestation).await {
+            Ok(written) => match written.map(serde_json::from_value::<AttestationData>).transpose()
+            {
+                Ok(attestation) => attestation,
+                Err(e) => return internal_error(&id, format!("Invalid attestation: {}", e)),
+            },
+            Err(e) => return internal_error(&id, e),
+        },
+    };
+    let public_signals = match proofs::read(&id, Artifact::PublicSignals).await {
+        Ok(public_signals) => public_signals,
+        Err(e) => return internal_error(&id, e),
+    };
+
+    let (attestation, public_signals) = match (attestation, public_signals) {
+        (Some(attestation), Some(public_signals)) => (attestation, public_signals),
+        (None, _) if status == ProofStatus::Failed => {
+            return error(
+                StatusCode::NOT_FOUND,
+                "attestation_not_available",
+                format!("Measurement {} failed without an attestation", id),
+            );
+        }
+        (None, _) if status == ProofStatus::Cancelled => {
+            return error(
+                StatusCode::NOT_FOUND,
+                "attestation_not_available",
+                format!("Measurement {} was cancelled without an attestation", id),
+            );
+        }
+        (None, Some(_)) => {
+            let awaited = match PendingAttestation::load(&id) {
+                Ok(pending) => {
+                    format!(" It is expected in attestation {}.", pending.attestation_id)
+                }
+                Err(_) => String::new(),
+            };
+            return error(
+                StatusCode::CONFLICT,
+                "attestation_pending",
+                format!(
+                    "The proof of measurement {} is complete but its attestation has not arrived \
+                     yet; ask again later.{}",
+                    id, awaited
+                ),
+            );
+        }
+        (None, None) => {
+            return error(
+                StatusCode::NOT_FOUND,
+                "attestation_not_available",
+                format!("Measurement {} has no proof to attest yet ({:?})", id, status),
+            );
+        }
+        (Some(_), None) => {
+            return internal_error(&id, format!("Measurement {} has no public signals", id));
+        }
+    };
+
+    if let Some(node) =
+        attestation.merkle_path.iter().find(|node| !attestation_backend::is_merkle_node(node))
+    {
+        return error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "invalid_attestation",
+            format!(
+                "Attestation {} of measurement {} has a merkle path node '{}' that is not 32 bytes of hex",
+                attestation.attestation_id, id, node
+            ),
+        );
+    }
+    if attestation.index >= attestation.leaf_count {
+        return error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "invalid_attestation",
+            format!(
+                "Attestation {} of measurement {} places its leaf at {} of {} leaves",
+                attestation.attestation_id, id, attestation.index, attestation.leaf_count
+            ),
+        );
+    }
+    let signals: Vec<String> = match serde_json::from_value(public_signals) {
+        Ok(signals) => signals,
+        Err(e) => return internal_error(&id, format!("Invalid public signals: {}", e)),
+    };
+    let parsed: Vec<FieldElement> = match signals.iter().map(|s| s.parse()).collect() {
+        Ok(parsed) => parsed,
+        Err(e) => return internal_error(&id, format!("Invalid public signals: {}", e)),
+    };
+    let length_in_cm = match circuit.spec.decode_public(&signals) {
+        Ok(decoded) => decoded.value("distance_squared"),
+        Err(e) => return internal_error(&id, format!("Invalid public signals: {}", e)),
+    };
+    let Some(length_in_cm) = length_in_cm.map(|value| value.to_string()) else {
+        return internal_error(&id, format!("Measurement {} has no public signals", id));
+    };
+
+    let version = circuit.version;
+    let digest = state
+        .config
+        .vk_hashes
+        .iter()
+        .find(|vk| vk.version == version)
+        .map(|vk| format!("0x{}", hex::encode(leaf_digest(&vk.hash, &parsed))));
+
+    Json(ContractAttestation {
+        measurement_id: id.clone(),
+        attestation_backend: backend,
+        leaf_digest: digest,
+        mint_with_attestation: MintArguments {
+            image_url: format!("{}/img/{}", state.config.public_base_url, id),
+            length_in_cm,
+            attestation_id: attestation.attestation_id.to_string(),
+            merkle_path: attestation
+                .merkle_path
+                .iter()
+                .map(|node| node.to_ascii_lowercase())
+                .collect(),
+            leaf_count: attestation.leaf_count.to_string(),
+            index: attestation.index.to_string(),
+        },
+    })
+    .into_response()
+}
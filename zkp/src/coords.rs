@@ -0,0 +1,502 @@
+// Canonical encoding of measurement coordinates for the circuit.
+//
+// Clients submit points in metres in the space of their AR framework, named
+// by the coordinateSystem of the submission, and every point is converted to
+// the canonical convention of ARKit world space before scaling: right-handed
+// with +Y up and -Z pointing away from the camera at session start. The
+// circuit only proves the distance between two points, which does not depend
+// on where the origin sits or how the axes are oriented, so negative
+// coordinates are routine and carry no special meaning. Axis conventions only
+// matter when points from different frames are mixed, which the API does not
+// allow within a single measurement, but converting keeps the stored points
+// comparable across clients.
+//
+// The native camera and world spaces of ARKit and ARCore all share these
+// axes and are sent as arkit_world. Two systems name the other conventions
+// clients meet. arkit_image is ARKit camera space in the convention of the
+// captured image, which points unprojected with the camera intrinsics come
+// in: +X right, +Y down and +Z forward, the canonical axes turned half a turn
+// about X. arfoundation_world is world space as Unity's AR Foundation reports
+// it: left-handed with +Y up and +Z forward, the canonical axes with Z
+// mirrored. Both keep distances and angles, so only the stored points differ.
+// They were once named arkit_camera and arcore_world, which stored records
+// may still carry. Points in a custom space are proved as given and reported
+// as not canonical, since the server cannot know their axes. Legacy clients
+// that name no system are taken to send ARKit world space, unless
+// STRICT_COORDINATE_SYSTEM requires the field.
+//
+// Every coordinate is multiplied by SCALE and rounded to the nearest integer.
+// The circuit operates over the BN254 scalar field, so a negative scaled value
+// -v is written to input.json as the field element p - v. Subtraction in the
+// field then matches signed integer subtraction, and the squared differences
+// computed by the circuit equal the distance_squared computed here exactly.
+//
+// Scaled magnitudes are capped at MAX_SCALED_MAGNITUDE (about 5.4 km at the
+// current scale). That keeps every delta within 2^30 and the sum of three
+// squared deltas within i64, so the server-side arithmetic can never overflow
+// or drift from what the circuit computes.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+use crate::Point3D;
+
+// Fixed-point scale applied to coordinates in metres (0.01 mm resolution)
+pub const SCALE: f64 = 100000.0;
+
+// Largest absolute scaled coordinate accepted for proving
+pub const MAX_SCALED_MAGNITUDE: i64 = 1 << 29;
+
+// Order of the BN254 scalar field used by circom and snarkjs
+const FIELD_MODULUS: &str =
+    "21888242871839275222246405745257275088548364400416034343698204186575808495617";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CoordinateError {
+    NotFinite { axis: char },
+    OutOfRange { axis: char, value: f64 },
+    InvalidFieldElement(String),
+}
+
+impl fmt::Display for CoordinateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoordinateError::NotFinite { axis } => {
+                write!(f, "{} coordinate is not a finite number", axis)
+            }
+            CoordinateError::OutOfRange { axis, value } => write!(
+                f,
+                "{} coordinate {} is outside the supported range of +/-{} m",
+                axis,
+                value,
+                MAX_SCALED_MAGNITUDE as f64 / SCALE
+            ),
+            CoordinateError::InvalidFieldElement(value) => {
+                write!(f, "{} is not a field element in the supported range", value)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CoordinateError {}
+
+// Space the points of a submission were captured in
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CoordinateSystem {
+    #[serde(alias = "arkit_camera")]
+    #[schemars(description = "ARKit camera space in the convention of the captured image: \
+                              +X right, +Y down, +Z forward")]
+    ArkitImage,
+    #[default]
+    #[schemars(description = "ARKit world space, and every native ARKit or ARCore space: \
+                              right-handed, +Y up, -Z forward")]
+    ArkitWorld,
+    #[serde(alias = "arcore_world")]
+    #[schemars(description = "World space as Unity's AR Foundation reports it: left-handed, \
+                              +Y up, +Z forward")]
+    ArfoundationWorld,
+    #[schemars(description = "Any other space; points are proved as given")]
+    Custom,
+}
+
+// Canonical axis as a source axis and its sign
+type AxisMap = [(usize, f64); 3];
+
+// The source axes as they are
+const SAME_AXES: AxisMap = [(0, 1.0), (1, 1.0), (2, 1.0)];
+// Y and Z flipped, turning +Y down and +Z forward into +Y up and -Z forward
+const IMAGE_AXES: AxisMap = [(0, 1.0), (1, -1.0), (2, -1.0)];
+// Z mirrored, turning a left-handed +Z forward into a right-handed -Z forward
+const LEFT_HANDED_AXES: AxisMap = [(0, 1.0), (1, 1.0), (2, -1.0)];
+
+impl CoordinateSystem {
+    pub const ALL: [CoordinateSystem; 4] = [
+        CoordinateSystem::ArkitImage,
+        CoordinateSystem::ArkitWorld,
+        CoordinateSystem::ArfoundationWorld,
+        CoordinateSystem::Custom,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CoordinateSystem::ArkitImage => "arkit_image",
+            CoordinateSystem::ArkitWorld => "arkit_world",
+            CoordinateSystem::ArfoundationWorld => "arfoundation_world",
+            CoordinateSystem::Custom => "custom",
+        }
+    }
+
+    // How the canonical axes are read from this space, None when unknown
+    fn axes(self) -> Option<AxisMap> {
+        match self {
+            CoordinateSystem::ArkitWorld => Some(SAME_AXES),
+            CoordinateSystem::ArkitImage => Some(IMAGE_AXES),
+            CoordinateSystem::ArfoundationWorld => Some(LEFT_HANDED_AXES),
+            CoordinateSystem::Custom => None,
+        }
+    }
+
+    // Whether points in this space are converted to the canonical convention
+    pub fn is_canonical(self) -> bool {
+        self.axes().is_some()
+    }
+
+    // A point in metres in the canonical convention, or as given when the
+    // axes of this space are unknown
+    pub fn to_canonical(self, point: &Point3D) -> Point3D {
+        let Some(axes) = self.axes() else {
+            return point.clone();
+        };
+        let source = [point.x, point.y, point.z];
+        let [x, y, z] = axes.map(|(axis, sign)| sign * source[axis]);
+        Point3D { x, y, z }
+    }
+}
+
+impl fmt::Display for CoordinateSystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for CoordinateSystem {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        CoordinateSystem::ALL.into_iter().find(|system| system.name() == s).ok_or_else(|| {
+            let accepted: Vec<&str> = CoordinateSystem::ALL.iter().map(|s| s.name()).collect();
+            format!(
+                "Unknown coordinate system '{}'; accepted values are {}",
+                s,
+                accepted.join(", ")
+            )
+        })
+    }
+}
+
+// The coordinate system of a submission, defaulting to ARKit world space for
+// legacy clients unless it is required
+pub fn coordinate_system(value: Option<&str>, required: bool) -> Result<CoordinateSystem, String> {
+    match value {
+        Some(value) => value.trim().parse(),
+        None if required => Err(format!(
+            "Missing coordinate system; this server requires one of {}",
+            CoordinateSystem::ALL.map(|s| s.name()).join(", ")
+        )),
+        None => Ok(CoordinateSystem::default()),
+    }
+}
+
+// Points in metres exactly as the client captured them, in their coordinate
+// system. Only the canonical scaled points are proved; these are kept for
+// display and for re-deriving with another scale.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+pub struct OriginalPoints {
+    pub start: Point3D,
+    pub end: Point3D,
+    // Where an angle measurement bends
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mid: Option<Point3D>,
+    // Every point of a polyline of more than two points
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub points: Vec<Point3D>,
+}
+
+// Scale a single coordinate in metres to its canonical integer representation
+pub fn scale(axis: char, value: f64) -> Result<i64, CoordinateError> {
+    if !value.is_finite() {
+        return Err(CoordinateError::NotFinite { axis });
+    }
+
+    let scaled = (value * SCALE).round();
+    if scaled.abs() > MAX_SCALED_MAGNITUDE as f64 {
+        return Err(CoordinateError::OutOfRange { axis, value });
+    }
+
+    Ok(scaled as i64)
+}
+
+// Convert a point in metres to the canonical convention and scale it; the
+// result holds integral values only
+pub fn canonical_point(
+    system: CoordinateSystem,
+    point: &Point3D,
+) -> Result<Point3D, CoordinateError> {
+    scale_point(&system.to_canonical(point))
+}
+
+// Scale a point in metres; the result holds integral values only
+pub fn scale_point(point: &Point3D) -> Result<Point3D, CoordinateError> {
+    Ok(Point3D {
+        x: scale('x', point.x)? as f64,
+        y: scale('y', point.y)? as f64,
+        z: scale('z', point.z)? as f64,
+    })
+}
+
+// Integer components of a point previously produced by scale_point. The values
+// are integral and within MAX_SCALED_MAGNITUDE, so the conversion is exact.
+pub fn components(point: &Point3D) -> [i64; 3] {
+    [point.x as i64, point.y as i64, point.z as i64]
+}
+
+// Squared Euclidean distance between two scaled points. Saturates rather
+// than overflows for points outside the scaled range, so the width check of
+// the circuit's inputs refuses them.
+pub fn distance_squared(a: &Point3D, b: &Point3D) -> u64 {
+    components(a)
+        .iter()
+        .zip(components(b).iter())
+        .map(|(a, b)| {
+            let d = b.abs_diff(*a);
+            d.saturating_mul(d)
+        })
+        .fold(0, u64::saturating_add)
+}
+
+// Squared length of each segment between consecutive scaled points
+pub fn segment_distances_squared(points: &[Point3D]) -> Vec<u64> {
+    points.windows(2).map(|segment| distance_squared(&segment[0], &segment[1])).collect()
+}
+
+// Length of the path through the scaled points in centimetres, the sum of its
+// segments' lengths, rounded to the resolution of SCALE. For two points the
+// result is exactly what was computed before polylines existed.
+pub fn length_cm(points: &[Point3D]) -> f64 {
+    let metres = segment_distances_squared(points)
+        .into_iter()
+        .map(|squared| (squared as f64).sqrt())
+        .sum::<f64>()
+        / SCALE;
+    (metres * 100_000.0).round() / 1000.0
+}
+
+// Encode a signed integer as the decimal representation of a field element
+pub fn to_field(value: i64) -> String {
+    if value >= 0 {
+        value.to_string()
+    } else {
+        sub_decimal(FIELD_MODULUS, &value.unsigned_abs().to_string())
+    }
+}
+
+// Decode a field element produced by to_field (or by the circuit) back into a
+// signed integer. Elements in the upper half of the field are negative.
+pub fn from_field(value: &str) -> Result<i64, CoordinateError> {
+    let invalid = || CoordinateError::InvalidFieldElement(value.to_string());
+
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let digits = value.trim_start_matches('0');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+
+    if digits.len() < 20 {
+        return digits.parse::<i64>().map_err(|_| invalid());
+    }
+
+    if cmp_decimal(digits, FIELD_MODULUS).is_ge() {
+        return Err(invalid());
+    }
+    let magnitude = sub_decimal(FIELD_MODULUS, digits);
+    if magnitude.len() >= 20 {
+        return Err(invalid());
+    }
+    magnitude.parse::<i64>().map(|m| -m).map_err(|_| invalid())
+}
+
+// Compare two canonical (no leading zeros) decimal strings
+fn cmp_decimal(a: &str, b: &str) -> std::cmp::Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+// Subtract decimal string b from decimal string a, assuming a >= b
+fn sub_decimal(a: &str, b: &str) -> String {
+    let a = a.as_bytes();
+    let b = b.as_bytes();
+    let mut out = Vec::with_capacity(a.len());
+    let mut borrow = 0;
+
+    for i in 0..a.len() {
+        let da = (a[a.len() - 1 - i] - b'0') as i32;
+        let db = if i < b.len() { (b[b.len() - 1 - i] - b'0') as i32 } else { 0 };
+        let mut digit = da - db - borrow;
+        borrow = 0;
+        if digit < 0 {
+            digit += 10;
+            borrow = 1;
+        }
+        out.push(b'0' + digit as u8);
+    }
+
+    while out.len() > 1 && out.last() == Some(&b'0') {
+        out.pop();
+    }
+    out.reverse();
+    String::from_utf8(out).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: i64, y: i64, z: i64) -> Point3D {
+        Point3D { x: x as f64, y: y as f64, z: z as f64 }
+    }
+
+    #[test]
+    fn each_system_converts_to_canonical_axes() {
+        // 0.1 m right, 0.2 m up and 0.3 m ahead, written in each space
+        let ahead = [
+            (CoordinateSystem::ArkitWorld, (0.1, 0.2, -0.3)),
+            (CoordinateSystem::ArkitImage, (0.1, -0.2, 0.3)),
+            (CoordinateSystem::ArfoundationWorld, (0.1, 0.2, 0.3)),
+        ];
+        for (system, (x, y, z)) in ahead {
+            let canonical = canonical_point(system, &Point3D { x, y, z }).unwrap();
+            assert_eq!(canonical, point(10000, 20000, -30000), "{}", system);
+        }
+        let custom = Point3D { x: 0.1, y: -0.2, z: 0.3 };
+        assert_eq!(CoordinateSystem::Custom.to_canonical(&custom), custom);
+        assert!(!CoordinateSystem::Custom.is_canonical());
+    }
+
+    #[test]
+    fn stored_records_keep_their_renamed_systems() {
+        let stored = |name: &str| serde_json::from_value(serde_json::json!(name)).ok();
+        assert_eq!(stored("arkit_camera"), Some(CoordinateSystem::ArkitImage));
+        assert_eq!(stored("arcore_world"), Some(CoordinateSystem::ArfoundationWorld));
+        // Submissions only take the current names
+        assert!("arkit_camera".parse::<CoordinateSystem>().is_err());
+        assert!("arcore_world".parse::<CoordinateSystem>().is_err());
+    }
+
+    #[test]
+    fn scale_rounds_to_the_nearest_step() {
+        assert_eq!(scale('x', 0.0), Ok(0));
+        assert_eq!(scale('x', -0.0), Ok(0));
+        assert_eq!(scale('x', 0.123456), Ok(12346));
+        assert_eq!(scale('x', -0.123454), Ok(-12345));
+        assert_eq!(scale('x', -1.5), Ok(-150000));
+    }
+
+    #[test]
+    fn scale_refuses_what_the_circuit_cannot_take() {
+        let limit = MAX_SCALED_MAGNITUDE as f64 / SCALE;
+        assert_eq!(scale('y', limit), Ok(MAX_SCALED_MAGNITUDE));
+        assert_eq!(scale('y', -limit), Ok(-MAX_SCALED_MAGNITUDE));
+        assert_eq!(
+            scale('y', limit + 0.0001),
+            Err(CoordinateError::OutOfRange { axis: 'y', value: limit + 0.0001 })
+        );
+        for value in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert_eq!(scale('z', value), Err(CoordinateError::NotFinite { axis: 'z' }));
+        }
+    }
+
+    #[test]
+    fn to_field_wraps_negative_values_below_the_modulus() {
+        assert_eq!(to_field(0), "0");
+        assert_eq!(to_field(42), "42");
+        assert_eq!(to_field(-1), format!("{}6", &FIELD_MODULUS[..FIELD_MODULUS.len() - 1]));
+        assert_eq!(
+            to_field(-150000),
+            "21888242871839275222246405745257275088548364400416034343698204186575808345617"
+        );
+        assert_eq!(
+            to_field(-i64::MAX),
+            "21888242871839275222246405745257275088548364400416034343688980814538953719810"
+        );
+    }
+
+    #[test]
+    fn from_field_round_trips_to_field() {
+        let values = [
+            0,
+            1,
+            -1,
+            12345,
+            -12345,
+            MAX_SCALED_MAGNITUDE,
+            -MAX_SCALED_MAGNITUDE,
+            i64::MAX,
+            -i64::MAX,
+        ];
+        for value in values {
+            assert_eq!(from_field(&to_field(value)), Ok(value), "{}", value);
+        }
+    }
+
+    #[test]
+    fn from_field_takes_leading_zeros() {
+        assert_eq!(from_field("000"), Ok(0));
+        assert_eq!(from_field("0042"), Ok(42));
+    }
+
+    #[test]
+    fn from_field_refuses_values_outside_the_supported_range() {
+        let invalid = |value: &str| Err(CoordinateError::InvalidFieldElement(value.to_string()));
+        // The modulus itself and anything above are not field elements
+        assert_eq!(from_field(FIELD_MODULUS), invalid(FIELD_MODULUS));
+        let above = format!("{}8", &FIELD_MODULUS[..FIELD_MODULUS.len() - 1]);
+        assert_eq!(from_field(&above), invalid(&above));
+        // Elements far from either end of the field are no i64
+        assert_eq!(from_field("100000000000000000000"), invalid("100000000000000000000"));
+        assert_eq!(from_field("9223372036854775808"), invalid("9223372036854775808"));
+        let min = to_field(i64::MIN);
+        assert_eq!(from_field(&min), invalid(&min));
+        for value in ["", "-1", "1.5", " 1", "0x10"] {
+            assert_eq!(from_field(value), invalid(value));
+        }
+    }
+
+    #[test]
+    fn sub_decimal_borrows_across_digits() {
+        assert_eq!(sub_decimal("1000", "1"), "999");
+        assert_eq!(sub_decimal("1000000", "999999"), "1");
+        assert_eq!(sub_decimal("10", "9"), "1");
+        assert_eq!(sub_decimal("1203", "1195"), "8");
+        assert_eq!(sub_decimal("100", "100"), "0");
+        assert_eq!(sub_decimal("5", "0"), "5");
+        assert_eq!(sub_decimal("5", ""), "5");
+    }
+
+    #[test]
+    fn distance_squared_across_the_origin() {
+        // Both points negative
+        assert_eq!(distance_squared(&point(-100, -200, -300), &point(-400, -200, -300)), 90000);
+        // One negative and one positive coordinate on every axis
+        assert_eq!(distance_squared(&point(-1, -2, -2), &point(1, 2, 2)), 36);
+        assert_eq!(distance_squared(&point(150, 0, 0), &point(-150, 0, 0)), 90000);
+        // Coinciding points and points at the origin
+        assert_eq!(distance_squared(&point(-7, 3, -9), &point(-7, 3, -9)), 0);
+        assert_eq!(distance_squared(&point(0, 0, 0), &point(0, -3, 4)), 25);
+    }
+
+    #[test]
+    fn distance_squared_holds_the_widest_points() {
+        let max = MAX_SCALED_MAGNITUDE;
+        assert_eq!(
+            distance_squared(&point(-max, -max, -max), &point(max, max, max)),
+            3 * (2 * max as u64).pow(2)
+        );
+    }
+
+    #[test]
+    fn field_encoding_preserves_differences() {
+        // What the circuit computes: (b - a) in the field equals b - a
+        for (a, b) in [(-5, 3), (-5, -8), (0, -1), (7, 2)] {
+            let (fa, fb) = (to_field(a), to_field(b));
+            let difference = if cmp_decimal(&fb, &fa).is_ge() {
+                sub_decimal(&fb, &fa)
+            } else {
+                sub_decimal(FIELD_MODULUS, &sub_decimal(&fa, &fb))
+            };
+            assert_eq!(from_field(&difference), Ok(b - a), "{} - {}", b, a);
+        }
+    }
+}
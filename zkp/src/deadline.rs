@@ -0,0 +1,120 @@
+// Client-supplied deadlines bounding how long a submission may take end to end.
+//
+// A deadline is checked before a queued job starts and between pipeline
+// stages. A watcher task also fails measurements that are still waiting in the
+// queue the moment their deadline passes, so clients learn about the expiry
+// without waiting for a worker to pick the job up.
+//
+// Deadlines are stored as wall-clock times, but expire on the monotonic clock
+// (see clock.rs): each is converted into an Instant the first time it is
+// needed after being received or loaded at startup, so stepping the host
+// clock neither expires queued measurements early nor keeps them waiting.
+
+use chrono::{DateTime, Duration, Utc};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio::time::Instant;
+
+use crate::{
+    AppState, FailureClass, ProofStatus, clock::Clock, config::Config, fail_measurement,
+    history::Source, timecheck::ClockSkew,
+};
+
+// Monotonic deadlines of measurements, by ID
+pub struct Deadlines {
+    clock: Clock,
+    instants: Mutex<HashMap<String, Instant>>,
+}
+
+// Parse a deadline given either as seconds from now or as an RFC 3339 timestamp
+pub fn parse(value: &str, clock: &ClockSkew, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+    parse_future("deadline", value, clock, now)
+}
+
+// Parse a future point in time given as seconds from now or an RFC 3339
+// timestamp. A timestamp may be behind by the allowed clock skew, since the
+// client's clock may be; such a deadline simply expires at once.
+pub fn parse_future(
+    name: &str,
+    value: &str,
+    clock: &ClockSkew,
+    now: DateTime<Utc>,
+) -> Result<DateTime<Utc>, String> {
+    let value = value.trim();
+
+    if let Ok(seconds) = value.parse::<u32>() {
+        if seconds == 0 {
+            return Err(format!("The {} must be at least one second from now", name));
+        }
+        return Ok(now + Duration::seconds(seconds as i64));
+    }
+
+    let at = DateTime::parse_from_rfc3339(value).map(|d| d.with_timezone(&Utc)).map_err(|_| {
+        format!("Invalid {} '{}': expected seconds or an RFC 3339 timestamp", name, value)
+    })?;
+    clock
+        .not_older_than(at, now, Duration::zero())
+        .map_err(|e| format!("The {} {} {}", name, at.to_rfc3339(), e))?;
+    Ok(at)
+}
+
+impl Deadlines {
+    pub fn new(config: &Config) -> Self {
+        Deadlines { clock: Clock::new(config), instants: Mutex::default() }
+    }
+
+    // The instant a measurement's deadline passes, converted on first use
+    fn instant(&self, id: &str, deadline: DateTime<Utc>) -> Instant {
+        *self
+            .instants
+            .lock()
+            .unwrap()
+            .entry(id.to_string())
+            .or_insert_with(|| self.clock.instant_at(deadline))
+    }
+
+    // Whether a measurement's deadline (if any) has passed
+    pub fn is_expired(&self, id: &str, deadline: Option<DateTime<Utc>>) -> bool {
+        deadline.is_some_and(|d| self.instant(id, d) <= Instant::now())
+    }
+
+    // Time left until a measurement's deadline, never negative
+    pub fn remaining(&self, id: &str, deadline: DateTime<Utc>) -> std::time::Duration {
+        self.instant(id, deadline).saturating_duration_since(Instant::now())
+    }
+
+    // Seconds left until a measurement's deadline, never negative
+    pub fn remaining_seconds(&self, id: &str, deadline: DateTime<Utc>) -> i64 {
+        self.remaining(id, deadline).as_secs() as i64
+    }
+
+    // Drop the deadline of a deleted measurement
+    pub fn forget(&self, id: &str) {
+        self.instants.lock().unwrap().remove(id);
+    }
+}
+
+// Fail a measurement as soon as its deadline passes if it is still queued.
+// Jobs that already started are handled by the checks between stages.
+pub async fn watch(state: Arc<AppState>, id: String, deadline: DateTime<Utc>) {
+    tokio::time::sleep_until(state.deadlines.instant(&id, deadline)).await;
+
+    let status = state.measurements.lock().get(&id).map(|m| m.status.clone());
+    let waiting = match status {
+        Some(ProofStatus::Pending) => state.queue.remove(&id),
+        Some(ProofStatus::Scheduled) => true,
+        _ => false,
+    };
+    if waiting {
+        println!("Deadline passed while measurement {} was queued", id);
+        fail_measurement(
+            &state,
+            &id,
+            Source::Watchdog,
+            FailureClass::DeadlineExceeded,
+            "Deadline passed before proof generation started".to_string(),
+        );
+    }
+}
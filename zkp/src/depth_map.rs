@@ -0,0 +1,187 @@
+// Depth maps sent alongside the photo.
+//
+// LiDAR-equipped phones can export a depth map of the scene, which lets a
+// verifier check that the measured points lie on the object. A submission
+// may send one as depthMap: either a PNG, whose size is read from the file,
+// or a raw grid of little-endian float16 depths in row order, whose size is
+// given as depthMapWidth and depthMapHeight. It is stored as
+// uploads/{id}_depth.bin, is limited to DEPTH_MAP_MAX_BYTES, and is described
+// by the depth_map of the measurement: its format, size and SHA-256.
+//
+// The proof pipeline ignores depth maps for now. Their hash is kept so that a
+// later circuit can commit to it in its input. GET /depth/{id} serves a depth
+// map to whoever may see the image of its measurement.
+
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+};
+use image::ImageFormat;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+use crate::{
+    AppState, access,
+    artifacts::Artifact,
+    downloads::{self, ArtifactFile, DownloadQuery},
+    tenants::Visitor,
+};
+
+// Longest side of a depth map in pixels, far beyond what phones export
+const MAX_SIDE: u32 = 4096;
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DepthFormat {
+    Png,
+    // Raw little-endian float16 depths in row order
+    Float16,
+}
+
+impl DepthFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            DepthFormat::Png => "image/png",
+            DepthFormat::Float16 => "application/octet-stream",
+        }
+    }
+
+    fn suffix(self) -> &'static str {
+        match self {
+            DepthFormat::Png => "-depth.png",
+            DepthFormat::Float16 => "-depth.bin",
+        }
+    }
+}
+
+// What a measurement records about its depth map
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+pub struct DepthMap {
+    pub format: DepthFormat,
+    pub width: u32,
+    pub height: u32,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+// A validated depth map waiting to be stored with its measurement
+pub struct IncomingDepthMap {
+    pub data: Bytes,
+    pub info: DepthMap,
+}
+
+// Validate the depthMap of a submission and the size sent with it
+pub fn validate(
+    data: Option<Vec<u8>>,
+    width: Option<String>,
+    height: Option<String>,
+    limit: usize,
+) -> Result<Option<IncomingDepthMap>, (StatusCode, String)> {
+    let bad_request = |message: String| (StatusCode::BAD_REQUEST, message);
+    let Some(data) = data else {
+        if width.is_some() || height.is_some() {
+            return Err(bad_request(
+                "depthMapWidth and depthMapHeight are only accepted with depthMap".to_string(),
+            ));
+        }
+        return Ok(None);
+    };
+    if data.len() > limit {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("depthMap exceeds the maximum size of {} bytes", limit),
+        ));
+    }
+    if data.is_empty() {
+        return Err(bad_request("depthMap is empty".to_string()));
+    }
+    let width =
+        width.map(|value| side("depthMapWidth", &value)).transpose().map_err(bad_request)?;
+    let height =
+        height.map(|value| side("depthMapHeight", &value)).transpose().map_err(bad_request)?;
+
+    let (format, size) = if data.starts_with(PNG_SIGNATURE) {
+        let png = image::load_from_memory_with_format(&data, ImageFormat::Png)
+            .map_err(|e| bad_request(format!("depthMap is not a valid PNG: {}", e)))?;
+        let size = (png.width(), png.height());
+        if width.is_some_and(|width| width != size.0)
+            || height.is_some_and(|height| height != size.1)
+        {
+            return Err(bad_request(format!(
+                "depthMapWidth and depthMapHeight do not match the {} x {} PNG",
+                size.0, size.1
+            )));
+        }
+        (DepthFormat::Png, size)
+    } else {
+        let (Some(width), Some(height)) = (width, height) else {
+            return Err(bad_request(
+                "A depthMap of float16 depths needs depthMapWidth and depthMapHeight".to_string(),
+            ));
+        };
+        let expected = width as usize * height as usize * 2;
+        if data.len() != expected {
+            return Err(bad_request(format!(
+                "A depthMap of {} x {} float16 depths holds {} bytes, not {}",
+                width,
+                height,
+                expected,
+                data.len()
+            )));
+        }
+        (DepthFormat::Float16, (width, height))
+    };
+    if size.0 > MAX_SIDE || size.1 > MAX_SIDE {
+        return Err(bad_request(format!("depthMap may be at most {} pixels a side", MAX_SIDE)));
+    }
+
+    let info = DepthMap {
+        format,
+        width: size.0,
+        height: size.1,
+        bytes: data.len() as u64,
+        sha256: hex::encode(Sha256::digest(&data)),
+    };
+    Ok(Some(IncomingDepthMap { data: Bytes::from(data), info }))
+}
+
+// A side of a float16 depth map
+fn side(field: &str, value: &str) -> Result<u32, String> {
+    match value.trim().parse::<u32>() {
+        Ok(side) if side > 0 => Ok(side),
+        _ => Err(format!("{} must be a positive number of pixels, not '{}'", field, value)),
+    }
+}
+
+// Handler serving the depth map of a measurement sent with one
+pub async fn serve_depth_map(
+    State(state): State<Arc<AppState>>,
+    Visitor(caller): Visitor,
+    Path(id): Path<String>,
+    Query(query): Query<DownloadQuery>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    // Hidden measurements answer like unknown ones, as for the image
+    let depth_map = state
+        .measurements
+        .lock()
+        .get(&id)
+        .filter(|m| access::may_see_published(&caller, m, state.time.now()))
+        .map(|m| m.depth_map.clone())
+        .ok_or_else(|| access::not_found(&id))?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("Measurement {} has no depth map", id)))?;
+
+    let file = ArtifactFile {
+        path: Artifact::DepthMap.path(&id),
+        content_type: depth_map.format.content_type(),
+        filename: Some(downloads::download_name(&id, depth_map.format.suffix())),
+        attachment: query.download,
+        label: format!("Depth map of measurement {}", id),
+    };
+    downloads::serve(&headers, file, None, None).await
+}
@@ -0,0 +1,704 @@
+// Environment and state diagnostics: `backend doctor` and GET /admin/doctor.
+//
+// Both run the same battery of checks and report each as pass, warn, fail or
+// skip, with what was found and, unless it passed, how to fix it:
+//   - production_safety: the rules of the startup safety audit
+//   - circuit_artifacts: the files of every circuit and the zkVerify client
+//   - toolchain: node and snarkjs answer with their versions
+//   - process_spawn: a child process starts and exits like a pipeline step
+//   - disk_space: free space where uploads and proofs are written
+//   - store: a probe record can be written, read back and removed
+//   - file_storage: a probe file can be written, read back and removed in
+//     uploads/ and proofs/
+//   - rpc: every endpoint in ZK_VERIFY_RPC_URLS answers a JSON-RPC request
+//   - clock: this server's clock against the timestamp of the latest block
+//   - queue: no measurement is Processing or Pending for longer than all
+//     pipeline steps could take
+//   - image_commitments: the stored image of every completed measurement
+//     still matches the hash recorded and committed for it (commitment.rs)
+//   - broker, replication and prover_process: the checks of /ready, when the
+//     integration is enabled
+// Checks that do not apply, like writes on a read-only mirror, are skipped.
+//
+// The command runs the checks from the environment and arguments the server
+// would start with, without a server running, so it can gate a deployment:
+// it exits with 0 when nothing is worse than a pass, 1 on warnings and 2 on
+// failures, and prints JSON with --json. A sled database is locked by the
+// server using it, so the command's store check fails while that server
+// runs; GET /admin/doctor runs the checks within the running server, against
+// its own store and prover process.
+
+use axum::{extract::State, http::StatusCode, response::Json};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::json;
+use std::{
+    path::Path,
+    process::Stdio,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+use crate::{
+    AppState, Measurement, ProofStatus, artifacts,
+    attestation_backend::{self, BackendKind},
+    auth::{Admin, ApiKeys, Authorized},
+    broker::Broker,
+    circuit::{self, ZKHOTDOG},
+    clock::Clock,
+    commitment,
+    config::Config,
+    health, replica, rpc, safety,
+    store::{self, MeasurementStore},
+    toolchain::Toolchain,
+};
+
+// Free space below which writing uploads and proofs is a warning, and a failure
+const DISK_WARN_BYTES: u64 = 5 << 30;
+const DISK_FAIL_BYTES: u64 = 1 << 30;
+// Longest a probe of an external program or endpoint may take
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+// A child process taking longer than this to start and exit is a warning
+const SLOW_SPAWN: Duration = Duration::from_secs(2);
+// Storage key of Timestamp::Now, the time of the latest block in milliseconds
+const TIMESTAMP_NOW_KEY: &str =
+    "0xf0c365c3cf59d671eb72da0e7a4113c49f1f0515f462cdcf84e0f1d6045dfcbb";
+// Contents of probe files
+const PROBE_CONTENT: &[u8] = b"zkhotdog doctor probe";
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Level {
+    Skip,
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl Level {
+    fn label(self) -> &'static str {
+        match self {
+            Level::Skip => "SKIP",
+            Level::Pass => "PASS",
+            Level::Warn => "WARN",
+            Level::Fail => "FAIL",
+        }
+    }
+
+    // Exit code of the command when this is the worst finding
+    fn exit_code(self) -> i32 {
+        match self {
+            Level::Skip | Level::Pass => 0,
+            Level::Warn => 1,
+            Level::Fail => 2,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Check {
+    name: &'static str,
+    status: Level,
+    detail: String,
+    // What to do about it, unless the check passed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remediation: Option<String>,
+}
+
+impl Check {
+    fn new(name: &'static str, status: Level, detail: impl Into<String>) -> Self {
+        Check { name, status, detail: detail.into(), remediation: None }
+    }
+
+    fn fix(mut self, remediation: impl Into<String>) -> Self {
+        if self.status > Level::Pass {
+            self.remediation = Some(remediation.into());
+        }
+        self
+    }
+}
+
+#[derive(Serialize)]
+pub struct Report {
+    // The worst status of any check
+    status: Level,
+    // "command" or "server", where the checks ran
+    ran_in: &'static str,
+    checked_at: DateTime<Utc>,
+    checks: Vec<Check>,
+}
+
+// Output format of the command
+#[derive(Debug, Clone, Copy)]
+pub enum Output {
+    Text,
+    Json,
+}
+
+// Take `doctor` and its --json out of the arguments, leaving the server's own
+pub fn take_command(args: &mut Vec<String>) -> Option<Output> {
+    if args.first().is_none_or(|arg| arg != "doctor") {
+        return None;
+    }
+    args.remove(0);
+    let json = args.iter().any(|arg| arg == "--json");
+    args.retain(|arg| arg != "--json");
+    Some(if json { Output::Json } else { Output::Text })
+}
+
+// Run the checks without a server and print them, returning the exit code
+pub async fn run_command(config: &Config, output: Output) -> i32 {
+    let report = diagnose(config, None).await;
+    match output {
+        Output::Json => match serde_json::to_string_pretty(&report) {
+            Ok(json) => println!("{}", json),
+            Err(e) => println!("Failed to serialize the report: {}", e),
+        },
+        Output::Text => {
+            for check in &report.checks {
+                println!("{}  {:<18} {}", check.status.label(), check.name, check.detail);
+                if let Some(remediation) = &check.remediation {
+                    println!("      {:<18} fix: {}", "", remediation);
+                }
+            }
+            println!("Overall: {}", report.status.label());
+        }
+    }
+    report.status.exit_code()
+}
+
+// Handler running the checks within this server
+pub async fn doctor(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Admin>,
+) -> Result<Json<Report>, (StatusCode, String)> {
+    caller.require_global()?;
+    Ok(Json(diagnose(&state.config, Some(&state)).await))
+}
+
+// Run every check, within the given server or from the environment alone
+async fn diagnose(config: &Config, server: Option<&AppState>) -> Report {
+    let clock = Clock::new(config);
+    let mut checks = Vec::new();
+
+    let loaded_keys;
+    let api_keys = match server {
+        Some(state) => Ok(&state.api_keys),
+        None => {
+            loaded_keys = ApiKeys::load(config.api_keys_path.clone());
+            loaded_keys.as_ref().map_err(String::clone)
+        }
+    };
+    checks.push(production_safety(config, api_keys));
+    checks.push(circuit_artifacts(config));
+    checks.push(toolchain().await);
+    checks.push(process_spawn().await);
+    checks.push(disk_space().await);
+
+    // A mirror keeps no records and writes no files of its own
+    if config.read_only {
+        for name in ["store", "file_storage", "queue", "image_commitments"] {
+            checks.push(Check::new(name, Level::Skip, "read-only mirrors write nothing"));
+        }
+    } else {
+        let opened;
+        let store = match server {
+            Some(state) => Ok(state.measurements.uncached_store()),
+            None => {
+                opened = store::open_store(config).await;
+                opened.as_deref().map_err(String::clone)
+            }
+        };
+        checks.push(store_probe(config, store.clone()).await);
+        checks.push(file_storage().await);
+        let now = clock.now();
+        let listed: Result<Vec<Measurement>, String> = match (server, store) {
+            (Some(state), _) => Ok(state.measurements.lock().values().cloned().collect()),
+            (None, Ok(store)) => store.list().await.map_err(|e| format!("cannot list: {}", e)),
+            (None, Err(_)) => Err("the store cannot be opened".to_string()),
+        };
+        match listed {
+            Ok(measurements) => {
+                checks.push(queue(config, now, measurements.iter()));
+                checks.push(image_commitments(measurements).await);
+            }
+            Err(reason) => {
+                for name in ["queue", "image_commitments"] {
+                    checks.push(Check::new(name, Level::Skip, reason.clone()));
+                }
+            }
+        }
+    }
+
+    let (rpc, latest_block) = rpc_endpoints(config).await;
+    checks.push(rpc);
+    checks.push(clock_skew(config, &clock, latest_block));
+
+    let readiness = match server {
+        Some(state) => health::checks(state).await.into_iter().collect(),
+        None => readiness(config).await,
+    };
+    for (name, outcome) in readiness {
+        let remediation = match name {
+            "broker" => "check BROKER_URL and that the broker is up",
+            "replication" => "check that uploads/ and proofs/ are still being replicated",
+            _ => "see the server log for why the prover process is not connected",
+        };
+        let check = match outcome.as_str() {
+            "ok" => Check::new(name, Level::Pass, "ok"),
+            _ => Check::new(name, Level::Fail, outcome),
+        };
+        checks.push(check.fix(remediation));
+    }
+
+    Report {
+        status: checks.iter().map(|check| check.status).max().unwrap_or(Level::Pass),
+        ran_in: if server.is_some() { "server" } else { "command" },
+        checked_at: Utc::now(),
+        checks,
+    }
+}
+
+fn production_safety(config: &Config, api_keys: Result<&ApiKeys, String>) -> Check {
+    let api_keys = match api_keys {
+        Ok(api_keys) => api_keys,
+        Err(e) => {
+            return Check::new("production_safety", Level::Fail, e)
+                .fix("fix or remove the file named by API_KEYS_PATH");
+        }
+    };
+    let findings = safety::findings(config, api_keys);
+    if findings.is_empty() {
+        return Check::new("production_safety", Level::Pass, "no unsafe settings");
+    }
+    let refused = config.production && findings.iter().any(|finding| !finding.waived);
+    let problems: Vec<String> = findings
+        .iter()
+        .map(|finding| {
+            let waived = if finding.waived && config.production { ", waived" } else { "" };
+            format!("{}: {}{}", finding.rule, finding.problem, waived)
+        })
+        .collect();
+    let hints: Vec<&str> = findings.iter().map(|finding| finding.hint).collect();
+    let status = if refused { Level::Fail } else { Level::Warn };
+    Check::new("production_safety", status, problems.join("; ")).fix(hints.join("; "))
+}
+
+fn circuit_artifacts(config: &Config) -> Check {
+    let mut missing = Vec::new();
+    let mut default_missing = false;
+    for circuit in circuit::all() {
+        let files =
+            [circuit.wasm_path, circuit.witness_generator, circuit.zkey_path, circuit.vkey_path];
+        for path in files.into_iter().filter(|path| !Path::new(path).exists()) {
+            missing.push(format!("{} ({})", path, circuit.version));
+            default_missing |= circuit.version == ZKHOTDOG.version;
+        }
+    }
+    // Only zkVerify attestations are made with the client
+    let needs_client = circuit::all().any(|circuit| {
+        let by_circuit = &config.attestation_backend_by_circuit;
+        attestation_backend::select(config.attestation_backend, by_circuit, circuit.version)
+            == BackendKind::ZkVerify
+    });
+    let client_missing = needs_client && !Path::new("dist/verify_client.js").exists();
+    if client_missing {
+        missing.push("dist/verify_client.js".to_string());
+    }
+
+    if missing.is_empty() {
+        let count = circuit::all().count();
+        return Check::new(
+            "circuit_artifacts",
+            Level::Pass,
+            format!("{} circuits installed", count),
+        );
+    }
+    // Other circuits only prove polylines, angles, minimum lengths and reproofs
+    let status = if default_missing || client_missing { Level::Fail } else { Level::Warn };
+    Check::new("circuit_artifacts", status, format!("missing {}", missing.join(", "))).fix(
+        "run ./build_scripts/rebuild_circuit.sh for the circuit files and npm run build for the client",
+    )
+}
+
+async fn toolchain() -> Check {
+    let toolchain = Toolchain::detect().await;
+    match (&toolchain.node, &toolchain.snarkjs) {
+        (Some(node), Some(snarkjs)) => {
+            Check::new("toolchain", Level::Pass, format!("node {}, snarkjs {}", node, snarkjs))
+        }
+        (None, _) => Check::new("toolchain", Level::Fail, "node --version gives no version")
+            .fix("install Node.js and put it on the PATH of the server"),
+        (Some(node), None) => Check::new(
+            "toolchain",
+            Level::Fail,
+            format!("node {}, but npx snarkjs --version gives no version", node),
+        )
+        .fix("install snarkjs, e.g. with npm install -g snarkjs"),
+    }
+}
+
+// Start a child process the way pipeline steps are started
+async fn process_spawn() -> Check {
+    let started = Instant::now();
+    let child = tokio::process::Command::new("node")
+        .arg("--version")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn();
+    let child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            return Check::new("process_spawn", Level::Fail, format!("cannot start node: {}", e))
+                .fix("check the PATH and process limits of the server");
+        }
+    };
+    let outcome = match tokio::time::timeout(PROBE_TIMEOUT, child.wait_with_output()).await {
+        Ok(Ok(output)) if output.status.success() => Ok(started.elapsed()),
+        Ok(Ok(output)) => Err(format!("node --version exited with {}", output.status)),
+        Ok(Err(e)) => Err(format!("waiting for node failed: {}", e)),
+        Err(_) => Err(format!("node --version ran for more than {:?}", PROBE_TIMEOUT)),
+    };
+    match outcome {
+        Ok(took) => {
+            let status = if took > SLOW_SPAWN { Level::Warn } else { Level::Pass };
+            let detail = format!("node started and exited in {} ms", took.as_millis());
+            Check::new("process_spawn", status, detail)
+                .fix("look for an overloaded host or a slow filesystem holding node")
+        }
+        Err(e) => Check::new("process_spawn", Level::Fail, e)
+            .fix("check that node runs for the user the server runs as"),
+    }
+}
+
+// Free bytes on the filesystem of each directory, from df
+async fn free_space(dirs: &[&str]) -> Result<Vec<u64>, String> {
+    let output = tokio::process::Command::new("df")
+        .arg("-Pk")
+        .args(dirs)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = match tokio::time::timeout(PROBE_TIMEOUT, output).await {
+        Ok(Ok(output)) if output.status.success() => output,
+        Ok(Ok(output)) => return Err(format!("df exited with {}", output.status)),
+        Ok(Err(e)) => return Err(format!("cannot run df: {}", e)),
+        Err(_) => return Err("df did not answer".to_string()),
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .skip(1)
+        .map(|line| {
+            let available = line.split_whitespace().nth(3);
+            let kilobytes = available.and_then(|kb| kb.parse::<u64>().ok());
+            kilobytes.map(|kb| kb * 1024).ok_or_else(|| format!("unexpected df output: {}", line))
+        })
+        .collect()
+}
+
+async fn disk_space() -> Check {
+    let dirs = ["uploads", "proofs"];
+    let free = match free_space(&dirs).await {
+        Ok(free) if free.len() == dirs.len() => free,
+        Ok(_) => return Check::new("disk_space", Level::Warn, "df did not report every directory"),
+        Err(e) => {
+            return Check::new("disk_space", Level::Warn, e).fix("check that df is installed");
+        }
+    };
+    let least = free.iter().copied().min().unwrap_or_default();
+    let status = match least {
+        free if free < DISK_FAIL_BYTES => Level::Fail,
+        free if free < DISK_WARN_BYTES => Level::Warn,
+        _ => Level::Pass,
+    };
+    let detail: Vec<String> = dirs
+        .iter()
+        .zip(&free)
+        .map(|(dir, free)| format!("{}/: {:.1} GiB free", dir, *free as f64 / (1u64 << 30) as f64))
+        .collect();
+    Check::new("disk_space", status, detail.join(", "))
+        .fix("free space or grow the volume; RETENTION_DAYS removes the files of old measurements")
+}
+
+// Write, read back and remove a record that no request can reach
+async fn store_probe(config: &Config, store: Result<&dyn MeasurementStore, String>) -> Check {
+    let store = match store {
+        Ok(store) => store,
+        Err(e) => {
+            return Check::new("store", Level::Fail, e).fix(match config.measurement_store {
+                store::StoreKind::Sled => {
+                    "a running server locks MEASUREMENTS_DB_PATH; run GET /admin/doctor on it instead"
+                }
+                _ => "check MEASUREMENT_STORE and DATABASE_URL, and that the database is up",
+            });
+        }
+    };
+    let id = format!("doctor-probe-{}", Uuid::new_v4());
+    let probe = json!({
+        "id": id,
+        "image_path": "",
+        "start_point": { "x": 0.0, "y": 0.0, "z": 0.0 },
+        "end_point": { "x": 0.0, "y": 0.0, "z": 0.0 },
+        "status": ProofStatus::Cancelled,
+        "attestation": null,
+    });
+    let probe: Measurement = match serde_json::from_value(probe) {
+        Ok(probe) => probe,
+        Err(e) => return Check::new("store", Level::Fail, format!("cannot build a probe: {}", e)),
+    };
+
+    let started = Instant::now();
+    let outcome = match store.insert(&probe).await {
+        Ok(()) => {
+            let read = match store.get(&id).await {
+                Ok(Some(read)) if read.id == id && read.status == ProofStatus::Cancelled => Ok(()),
+                Ok(_) => Err("the probe record read back differs from the one written".to_string()),
+                Err(e) => Err(format!("cannot read the probe record back: {}", e)),
+            };
+            let removed = store
+                .remove(&id)
+                .await
+                .map_err(|e| format!("cannot remove the probe record {}: {}", id, e));
+            read.and(removed)
+        }
+        Err(e) => Err(format!("cannot write a probe record: {}", e)),
+    };
+    match outcome {
+        Ok(()) => {
+            let took = started.elapsed().as_millis();
+            let detail = format!("wrote, read back and removed a probe record in {} ms", took);
+            Check::new("store", Level::Pass, detail)
+        }
+        Err(e) => Check::new("store", Level::Fail, e)
+            .fix("check the permissions and health of the measurement store"),
+    }
+}
+
+async fn file_storage() -> Check {
+    let mut problems = Vec::new();
+    for dir in ["uploads", "proofs"] {
+        let path = format!("{}/.doctor-probe-{}", dir, Uuid::new_v4());
+        let outcome = match tokio::fs::write(&path, PROBE_CONTENT).await {
+            Ok(()) => {
+                let read = match tokio::fs::read(&path).await {
+                    Ok(content) if content == PROBE_CONTENT => Ok(()),
+                    Ok(_) => Err("the file read back differs".to_string()),
+                    Err(e) => Err(format!("cannot read back: {}", e)),
+                };
+                let removed = tokio::fs::remove_file(&path)
+                    .await
+                    .map_err(|e| format!("cannot remove {}: {}", path, e));
+                read.and(removed)
+            }
+            Err(e) => Err(format!("cannot write: {}", e)),
+        };
+        if let Err(e) = outcome {
+            problems.push(format!("{}/: {}", dir, e));
+        }
+    }
+    if problems.is_empty() {
+        return Check::new("file_storage", Level::Pass, "uploads/ and proofs/ are writable");
+    }
+    Check::new("file_storage", Level::Fail, problems.join("; "))
+        .fix("check that uploads/ and proofs/ exist and are writable by the server")
+}
+
+// Time of the latest block an endpoint knows of, over HTTP JSON-RPC
+async fn latest_block(url: &str) -> Result<Option<DateTime<Utc>>, String> {
+    let url = match url.split_once("://") {
+        Some(("ws", rest)) => format!("http://{}", rest),
+        Some(("wss", rest)) => format!("https://{}", rest),
+        _ => url.to_string(),
+    };
+    let body = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "state_getStorage",
+        "params": [TIMESTAMP_NOW_KEY],
+    });
+    let agent = ureq::AgentBuilder::new().timeout(PROBE_TIMEOUT).build();
+    let response = tokio::task::spawn_blocking(move || {
+        let response = agent
+            .post(&url)
+            .set("Content-Type", "application/json")
+            .send_string(&body.to_string())
+            .map_err(|e| e.to_string())?;
+        let response = response.into_string().map_err(|e| e.to_string())?;
+        serde_json::from_str::<serde_json::Value>(&response).map_err(|e| e.to_string())
+    });
+    let response = response.await.map_err(|e| e.to_string())??;
+    if let Some(error) = response.get("error") {
+        return Err(format!("answered with an error: {}", error));
+    }
+    // SCALE-encoded u64, little-endian
+    let millis = response["result"]
+        .as_str()
+        .and_then(|hex| hex::decode(hex.trim_start_matches("0x")).ok())
+        .and_then(|bytes| <[u8; 8]>::try_from(bytes).ok())
+        .map(u64::from_le_bytes);
+    Ok(millis.and_then(|millis| DateTime::from_timestamp_millis(millis as i64)))
+}
+
+// Reachability of every RPC endpoint, and the latest block time one reported
+async fn rpc_endpoints(config: &Config) -> (Check, Option<(String, DateTime<Utc>)>) {
+    if config.zkverify_rpc_urls.is_empty() {
+        let detail = "ZK_VERIFY_RPC_URLS is unset; the client's built-in endpoint is not checked";
+        return (Check::new("rpc", Level::Skip, detail), None);
+    }
+    let mut answered = Vec::new();
+    let mut failed = Vec::new();
+    let mut block = None;
+    for url in &config.zkverify_rpc_urls {
+        let label = rpc::redact(url);
+        let started = Instant::now();
+        match latest_block(url).await {
+            Ok(at) => {
+                answered.push(format!("{} in {} ms", label, started.elapsed().as_millis()));
+                block = block.or(at.map(|at| (label, at)));
+            }
+            Err(e) => failed.push(format!("{}: {}", label, e)),
+        }
+    }
+    let status = match (answered.is_empty(), failed.is_empty()) {
+        (_, true) => Level::Pass,
+        (false, false) => Level::Warn,
+        (true, false) => Level::Fail,
+    };
+    let mut detail = Vec::new();
+    if !answered.is_empty() {
+        detail.push(format!("answered: {}", answered.join(", ")));
+    }
+    if !failed.is_empty() {
+        detail.push(format!("unreachable: {}", failed.join(", ")));
+    }
+    let check = Check::new("rpc", status, detail.join("; ")).fix(
+        "check the network path to the endpoints, or remove dead ones from ZK_VERIFY_RPC_URLS",
+    );
+    (check, block)
+}
+
+fn clock_skew(config: &Config, clock: &Clock, block: Option<(String, DateTime<Utc>)>) -> Check {
+    let Some((endpoint, at)) = block else {
+        return Check::new("clock", Level::Skip, "no RPC endpoint reported its latest block");
+    };
+    let skew = clock.now() - at;
+    let seconds = skew.num_seconds().abs();
+    // Blocks are a few seconds apart, so the latest one always lags a little
+    let status = match seconds as u64 {
+        s if s > config.clock_skew_secs => Level::Fail,
+        s if s > config.clock_skew_warn_secs => Level::Warn,
+        _ => Level::Pass,
+    };
+    let direction = if skew.num_seconds() >= 0 { "ahead of" } else { "behind" };
+    let detail = format!("{} seconds {} the latest block of {}", seconds, direction, endpoint);
+    Check::new("clock", status, detail).fix("synchronise the server's clock, e.g. with NTP")
+}
+
+// Measurements waiting for longer than every pipeline step could take
+fn queue<'a>(
+    config: &Config,
+    now: DateTime<Utc>,
+    measurements: impl Iterator<Item = &'a Measurement>,
+) -> Check {
+    let limit = 2 * config.proof_step_timeout_secs + config.signer_stuck_secs;
+    let (mut processing, mut pending) = (0, 0);
+    let mut stuck: Vec<&str> = Vec::new();
+    let mut oldest_pending: Option<(i64, &str)> = None;
+    for m in measurements {
+        let since = m.updated_at.or(m.created_at);
+        let waited = since.map_or(0, |since| (now - since).num_seconds());
+        match m.status {
+            ProofStatus::Processing => {
+                processing += 1;
+                if waited as u64 > limit {
+                    stuck.push(&m.id);
+                }
+            }
+            ProofStatus::Pending => {
+                pending += 1;
+                if oldest_pending.is_none_or(|(oldest, _)| waited > oldest) {
+                    oldest_pending = Some((waited, &m.id));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if !stuck.is_empty() {
+        let detail = format!(
+            "{} measurements processing for more than {} seconds: {}",
+            stuck.len(),
+            limit,
+            stuck.join(", ")
+        );
+        return Check::new("queue", Level::Fail, detail).fix(
+            "read their logs at /measurements/:id/log and cancel them with POST /measurements/:id/cancel; a restart fails them as Interrupted",
+        );
+    }
+    if let Some((waited, id)) = oldest_pending.filter(|(waited, _)| *waited as u64 > limit) {
+        let detail = format!("{} pending, the oldest, {}, for {} seconds", pending, id, waited);
+        return Check::new("queue", Level::Warn, detail)
+            .fix("check that proof workers are running and see the queue in /admin/stats");
+    }
+    Check::new("queue", Level::Pass, format!("{} processing, {} pending", processing, pending))
+}
+
+// Completed measurements whose stored image no longer matches what vouches for it
+async fn image_commitments(measurements: Vec<Measurement>) -> Check {
+    let compared = tokio::task::spawn_blocking(move || {
+        let mut checked = 0;
+        let mut mismatched = Vec::new();
+        for m in measurements.iter().filter(|m| m.status == ProofStatus::Completed) {
+            if let Some(tenant) = &m.tenant {
+                artifacts::assign_tenant(&m.id, tenant);
+            }
+            checked += 1;
+            let circuit = circuit::resolve(m.circuit.as_deref());
+            if let Err(e) = commitment::verify(&m.id, m.image_sha256.as_deref(), &circuit) {
+                mismatched.push(format!("{} ({})", m.id, e));
+            }
+        }
+        (checked, mismatched)
+    })
+    .await;
+    let Ok((checked, mismatched)) = compared else {
+        return Check::new("image_commitments", Level::Skip, "the comparison did not finish");
+    };
+    if !mismatched.is_empty() {
+        let detail = format!(
+            "{} of {} completed measurements no longer match their image hash: {}",
+            mismatched.len(),
+            checked,
+            mismatched.join(", ")
+        );
+        return Check::new("image_commitments", Level::Fail, detail).fix(
+            "their images changed after they were proved; restore uploads/ from a backup and see the server log for how they were written",
+        );
+    }
+    Check::new(
+        "image_commitments",
+        Level::Pass,
+        format!("{} completed measurements match", checked),
+    )
+}
+
+// The checks of /ready that can be made without a running server
+async fn readiness(config: &Config) -> Vec<(&'static str, String)> {
+    let mut checks = Vec::new();
+    match Broker::connect(config).await {
+        Ok(Some(broker)) => {
+            checks.push(("broker", broker.check().await.map_or_else(|e| e, |()| "ok".into())));
+        }
+        Ok(None) => {}
+        Err(e) => checks.push(("broker", e)),
+    }
+    if config.read_only {
+        let replica = replica::Replica::open(config.replica_max_lag_secs);
+        let outcome = replica.and_then(|replica| replica.check());
+        checks.push(("replication", outcome.map_or_else(|e| e, |()| "ok".into())));
+    }
+    checks
+}
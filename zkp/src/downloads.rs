@@ -0,0 +1,421 @@
+// Serving the files of a measurement over HTTP.
+//
+// Every route handing out a file goes through serve, so they agree on headers
+// and behaviour: the file is streamed from disk with its exact Content-Length,
+// carries an ETag derived from its size and modification time so clients can
+// revalidate with If-None-Match, and a single byte range may be requested with
+// Range. Handlers check that the caller may see the measurement before a file
+// is opened.
+//
+// Files are shown inline by default. With ?download=true they are sent as an
+// attachment instead, and either way they carry a file name naming the
+// measurement, like zkhotdog-3f2a1b4c-proof.json, so saved files of different
+// measurements are not mixed up.
+//
+// The pipeline log grows with every step and submission, so it is only served
+// up to LOG_MAX_RESPONSE_BYTES at a time; ?tail= fetches the end of it, as a
+// number of lines or of kilobytes, without reading the rest of the file.
+
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::{
+    fmt,
+    io::{self, Read, Seek, SeekFrom},
+    str::FromStr,
+    sync::Arc,
+    time::UNIX_EPOCH,
+};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    AppState, access,
+    artifacts::Artifact,
+    auth::{Authorized, Caller, Operator},
+    pending,
+    tenants::Visitor,
+};
+
+// Bytes read at a time while looking for line breaks from the end of a log
+const TAIL_CHUNK_BYTES: usize = 64 * 1024;
+// Longest file name offered for saving a file, extension included
+const MAX_FILENAME_BYTES: usize = 120;
+// Characters of a measurement ID kept in file names
+const SHORT_ID_CHARS: usize = 8;
+
+// A file to serve and how to describe it
+pub struct ArtifactFile {
+    pub path: String,
+    pub content_type: &'static str,
+    // Name to save the file as, sent in Content-Disposition
+    pub filename: Option<String>,
+    // Whether to have the file saved rather than shown
+    pub attachment: bool,
+    // Names the file in the 404 answered when it does not exist
+    pub label: String,
+}
+
+#[derive(Deserialize)]
+pub struct DownloadQuery {
+    #[serde(default)]
+    pub download: bool,
+    // Answer files the pipeline has yet to write with 409 instead of 404
+    #[serde(default)]
+    pub strict: bool,
+}
+
+// Name to save a file of a measurement as, such as zkhotdog-3f2a1b4c-proof.json
+pub fn download_name(id: &str, suffix: &str) -> String {
+    let short_id: String = id.chars().take(SHORT_ID_CHARS).collect();
+    format!("zkhotdog-{}{}", short_id, suffix)
+}
+
+// Content-Disposition offering a file under a name. Everything but ASCII
+// letters, digits, '-', '_' and '.' is replaced, so no part of the name can
+// end the quoted string or name another directory, and over-long names are
+// shortened keeping their extension.
+pub fn content_disposition(filename: &str, attachment: bool) -> String {
+    let mut name: String = filename
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+        .collect();
+    name = name.trim_start_matches('.').to_string();
+    if name.is_empty() {
+        name = "download".to_string();
+    }
+    if name.len() > MAX_FILENAME_BYTES {
+        let extension = name.rfind('.').map_or("", |dot| &name[dot..]);
+        let extension = if extension.len() < MAX_FILENAME_BYTES / 2 {
+            extension.to_string()
+        } else {
+            String::new()
+        };
+        name = format!("{}{}", &name[..MAX_FILENAME_BYTES - extension.len()], extension);
+    }
+    let kind = if attachment { "attachment" } else { "inline" };
+    format!("{}; filename=\"{}\"", kind, name)
+}
+
+// End of a file to serve instead of the whole of it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tail {
+    Lines(u64),
+    Kilobytes(u64),
+}
+
+impl FromStr for Tail {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim().to_ascii_lowercase();
+        let parsed = match value.strip_suffix("kb").or_else(|| value.strip_suffix('k')) {
+            Some(kilobytes) => kilobytes.trim().parse().map(Tail::Kilobytes),
+            None => value.parse().map(Tail::Lines),
+        };
+        parsed.map_err(|_| {
+            format!(
+                "Invalid tail '{}': expected a number of lines, or of kilobytes like 64KB",
+                value
+            )
+        })
+    }
+}
+
+impl fmt::Display for Tail {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Tail::Lines(lines) => write!(f, "{}", lines),
+            Tail::Kilobytes(kilobytes) => write!(f, "{}KB", kilobytes),
+        }
+    }
+}
+
+// Serve a file, or its tail, streaming at most max_bytes of it
+pub async fn serve(
+    request_headers: &HeaderMap,
+    file: ArtifactFile,
+    tail: Option<Tail>,
+    max_bytes: Option<u64>,
+) -> Result<Response, (StatusCode, String)> {
+    let internal = |e: io::Error| {
+        (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read {}: {}", file.label, e))
+    };
+    let mut handle = match tokio::fs::File::open(&file.path).await {
+        Ok(handle) => handle,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err((StatusCode::NOT_FOUND, format!("{} not found", file.label)));
+        }
+        Err(e) => return Err(internal(e)),
+    };
+    let meta = handle.metadata().await.map_err(internal)?;
+    let len = meta.len();
+
+    // A tail is a different representation than the whole file
+    let modified = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_nanos());
+    let etag = match tail {
+        Some(tail) => format!("\"{:x}-{:x}-tail-{}\"", len, modified, tail),
+        None => format!("\"{:x}-{:x}\"", len, modified),
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::ETAG, header_value(&etag)?);
+    if let Some(filename) = &file.filename {
+        headers.insert(
+            header::CONTENT_DISPOSITION,
+            header_value(&content_disposition(filename, file.attachment))?,
+        );
+    }
+    if matches_etag(request_headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, headers).into_response());
+    }
+
+    // Bytes to send as [start, end), and whether they are a requested range
+    let (start, end, partial) = match tail {
+        Some(tail) => {
+            let std_handle = handle.try_clone().await.map_err(internal)?.into_std().await;
+            let start = tokio::task::spawn_blocking(move || tail_start(std_handle, len, tail))
+                .await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+                .map_err(internal)?;
+            // An over-long tail is cut to the limit rather than refused
+            let start = max_bytes.map_or(start, |max| start.max(len.saturating_sub(max)));
+            (start, len, false)
+        }
+        None => {
+            headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+            let range = request_headers
+                .get(header::RANGE)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| resolve_range(v, len));
+            match range {
+                Some(Ok((start, end))) => (start, end, true),
+                Some(Err(())) => {
+                    headers
+                        .insert(header::CONTENT_RANGE, header_value(&format!("bytes */{}", len))?);
+                    return Ok((StatusCode::RANGE_NOT_SATISFIABLE, headers).into_response());
+                }
+                None => (0, len, false),
+            }
+        }
+    };
+
+    let length = end - start;
+    if let Some(max) = max_bytes
+        && length > max
+    {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!(
+                "{} is {} bytes, more than the {} served at once; request its end with ?tail= or a part of it with a Range header",
+                file.label, length, max
+            ),
+        ));
+    }
+
+    handle.seek(SeekFrom::Start(start)).await.map_err(internal)?;
+    let body = Body::from_stream(ReaderStream::new(handle.take(length)));
+
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(file.content_type));
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(length));
+    let status = if partial {
+        headers.insert(
+            header::CONTENT_RANGE,
+            header_value(&format!("bytes {}-{}/{}", start, end - 1, len))?,
+        );
+        StatusCode::PARTIAL_CONTENT
+    } else {
+        StatusCode::OK
+    };
+    Ok((status, headers, body).into_response())
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, (StatusCode, String)> {
+    HeaderValue::from_str(value).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+// Whether If-None-Match names the current version of the file
+pub fn matches_etag(request_headers: &HeaderMap, etag: &str) -> bool {
+    request_headers.get_all(header::IF_NONE_MATCH).iter().filter_map(|v| v.to_str().ok()).any(
+        |value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+        },
+    )
+}
+
+// Bytes [start, end) selected by a Range header, Err when no byte of the file
+// is in the range, or None when the header is malformed or asks for several
+// ranges, which is answered with the whole file
+fn resolve_range(value: &str, len: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (first, last) = spec.split_once('-')?;
+    let (first, last) = (first.trim(), last.trim());
+
+    if first.is_empty() {
+        // The last N bytes
+        let suffix: u64 = last.parse().ok()?;
+        if suffix == 0 || len == 0 {
+            return Some(Err(()));
+        }
+        return Some(Ok((len.saturating_sub(suffix), len)));
+    }
+
+    let first: u64 = first.parse().ok()?;
+    let last = if last.is_empty() { u64::MAX } else { last.parse().ok()? };
+    if last < first {
+        return None;
+    }
+    if first >= len {
+        return Some(Err(()));
+    }
+    Some(Ok((first, last.min(len - 1) + 1)))
+}
+
+// Offset where the requested tail of a file starts. Lines are counted by
+// walking back from the end, so only the tail itself is read; a line break
+// ending the file does not start another line.
+fn tail_start(mut file: std::fs::File, len: u64, tail: Tail) -> io::Result<u64> {
+    let lines = match tail {
+        Tail::Kilobytes(kilobytes) => return Ok(len.saturating_sub(kilobytes.saturating_mul(1024))),
+        Tail::Lines(0) => return Ok(len),
+        Tail::Lines(lines) => lines,
+    };
+
+    let mut buffer = vec![0; TAIL_CHUNK_BYTES];
+    let mut end = len;
+    let mut seen = 0;
+    while end > 0 {
+        let start = end.saturating_sub(TAIL_CHUNK_BYTES as u64);
+        let chunk = &mut buffer[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(chunk)?;
+        for (offset, byte) in chunk.iter().enumerate().rev() {
+            let position = start + offset as u64;
+            if *byte == b'\n' && position + 1 < len {
+                seen += 1;
+                if seen == lines {
+                    return Ok(position + 1);
+                }
+            }
+        }
+        end = start;
+    }
+    Ok(0)
+}
+
+// Answer 404 unless the caller may see the measurement and its files
+pub fn check_visible(
+    state: &AppState,
+    caller: &Caller,
+    id: &str,
+    include_embargoed: bool,
+) -> Result<(), (StatusCode, String)> {
+    let visible = state.measurements.lock().get(id).is_some_and(|m| {
+        if include_embargoed {
+            access::may_see(caller, m)
+        } else {
+            access::may_see_published(caller, m, state.time.now())
+        }
+    });
+    if visible { Ok(()) } else { Err(access::not_found(id)) }
+}
+
+// Handler serving a file written by the proving pipeline, named as in the
+// artifacts of /status/:id
+pub async fn serve_artifact(
+    State(state): State<Arc<AppState>>,
+    Visitor(caller): Visitor,
+    Path((id, name)): Path<(String, String)>,
+    Query(query): Query<DownloadQuery>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let (artifact, suffix) = match name.as_str() {
+        "input" => (Artifact::Input, "-input.json"),
+        "proof" => (Artifact::Proof, "-proof.json"),
+        "publicSignals" => (Artifact::PublicSignals, "-public-signals.json"),
+        "attestation" => (Artifact::Attestation, "-attestation.json"),
+        "submissionPayload" => (Artifact::SubmissionPayload, "-submission-payload.json"),
+        _ => {
+            return Err((
+                StatusCode::NOT_FOUND,
+                format!(
+                    "Unknown artifact {}, available are input, proof, publicSignals, attestation and submissionPayload",
+                    name
+                ),
+            ));
+        }
+    };
+    check_visible(&state, &caller, &id, false)?;
+    if artifact == Artifact::Input
+        && state.measurements.lock().get(&id).is_some_and(|m| m.min_length_cm.is_some())
+    {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!("The input of measurement {} holds the points its proof keeps private", id),
+        ));
+    }
+
+    let path = artifact.path(&id);
+    if query.strict
+        && !std::path::Path::new(&path).exists()
+        && let Some(m) = state.measurements.lock().get(&id)
+        && let Some(response) = pending::artifact_pending(m, artifact)
+    {
+        return Ok(response);
+    }
+
+    let file = ArtifactFile {
+        path,
+        content_type: "application/json",
+        filename: Some(download_name(&id, suffix)),
+        attachment: query.download,
+        label: format!("The {} of measurement {}", name, id),
+    };
+    serve(&headers, file, None, None).await
+}
+
+#[derive(Deserialize)]
+pub struct LogQuery {
+    tail: Option<String>,
+    #[serde(default)]
+    download: bool,
+}
+
+// Handler serving the pipeline log of a measurement, or its tail
+pub async fn serve_log(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Operator>,
+    Path(id): Path<String>,
+    Query(query): Query<LogQuery>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let tail = query
+        .tail
+        .map(|tail| tail.parse::<Tail>())
+        .transpose()
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    check_visible(&state, &caller, &id, true)?;
+
+    let file = ArtifactFile {
+        path: Artifact::Log.path(&id),
+        content_type: "text/plain; charset=utf-8",
+        filename: Some(download_name(&id, "-log.txt")),
+        attachment: query.download,
+        label: format!("The log of measurement {}", id),
+    };
+    serve(&headers, file, tail, Some(state.config.log_max_response_bytes)).await
+}
@@ -0,0 +1,211 @@
+// Embargoes delaying when a measurement becomes publicly visible.
+//
+// A measurement submitted with publishAt is proved and verified as usual, but
+// the public view and image endpoints answer 404 until that instant, whatever
+// its status, and so do its status and listings for callers other than the
+// owner and admins. The owner sees the embargo in the status response and can
+// move or lift it with PATCH /measurements/{id} until it has passed, or add
+// one while the measurement is still pending or processing. A ticker announces
+// each release with a Published event once the measurement is both completed
+// and past its publish time.
+
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::StatusCode,
+    response::Json,
+};
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use crate::{
+    AppState, Measurement, ProofStatus,
+    access::{self, Managers},
+    auth::{Authorized, Submitter},
+    deadline,
+    events::EventKind,
+};
+
+// How often the ticker looks for embargoes that have passed
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+// Embargo as reported to the owner
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct EmbargoState {
+    publish_at: DateTime<Utc>,
+    public: bool,
+}
+
+// Whether the public endpoints may show the measurement at the given time
+pub fn is_public(measurement: &Measurement, now: DateTime<Utc>) -> bool {
+    measurement.publish_at.is_none_or(|publish_at| publish_at <= now)
+}
+
+pub fn describe(measurement: &Measurement, now: DateTime<Utc>) -> Option<EmbargoState> {
+    measurement
+        .publish_at
+        .map(|publish_at| EmbargoState { publish_at, public: is_public(measurement, now) })
+}
+
+// Whether the release of an embargoed measurement is yet to be announced
+fn is_released(m: &Measurement, now: DateTime<Utc>) -> bool {
+    m.publish_at.is_some()
+        && !m.publication_announced
+        && m.status == ProofStatus::Completed
+        && is_public(m, now)
+}
+
+// Announce embargoed measurements once they are completed and released
+pub async fn ticker(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(TICK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let now = state.time.now();
+
+        let mut measurements = state.measurements.lock();
+        let released = measurements.filter_mut(|m| is_released(m, now));
+        for m in released {
+            m.publication_announced = true;
+            println!("Measurement {} is now public", m.id);
+            state.events.publish(m, EventKind::Published);
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct MeasurementPatch {
+    // Seconds from now or an RFC 3339 timestamp; null lifts the embargo
+    publish_at: Option<String>,
+}
+
+// Handler letting the owner move or lift the embargo of a measurement
+pub async fn patch_measurement(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Submitter>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    Path(id): Path<String>,
+    Json(patch): Json<MeasurementPatch>,
+) -> Result<Json<Option<EmbargoState>>, (StatusCode, String)> {
+    let now = state.time.now();
+    let publish_at = patch
+        .publish_at
+        .map(|value| deadline::parse_future("publish_at", &value, &state.clock, now))
+        .transpose()
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    let mut measurements = state.measurements.lock();
+    let measurement = access::managed(
+        measurements.get_mut(&id),
+        &caller,
+        remote.ip(),
+        &id,
+        Managers::SubmitterOnly,
+        "change the embargo",
+    )?;
+    change(measurement, publish_at, now).map(Json)
+}
+
+// Move, add or lift the embargo of a measurement
+fn change(
+    measurement: &mut Measurement,
+    publish_at: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> Result<Option<EmbargoState>, (StatusCode, String)> {
+    match measurement.publish_at {
+        Some(publish_at) if publish_at <= now => {
+            return Err((StatusCode::CONFLICT, "The embargo has already passed".to_string()));
+        }
+        None if matches!(
+            measurement.status,
+            ProofStatus::Completed | ProofStatus::Failed | ProofStatus::Cancelled
+        ) =>
+        {
+            return Err((
+                StatusCode::CONFLICT,
+                "An embargo can only be added before the measurement finishes".to_string(),
+            ));
+        }
+        _ => {}
+    }
+
+    measurement.publish_at = publish_at;
+    Ok(describe(measurement, now))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::Clock;
+    use chrono::Duration;
+    use uuid::Uuid;
+
+    fn measurement(status: ProofStatus, publish_at: Option<DateTime<Utc>>) -> Measurement {
+        let mut m = Measurement::fixture("m");
+        m.status = status;
+        m.publish_at = publish_at;
+        m
+    }
+
+    #[test]
+    fn embargo_ends_at_its_publish_time() {
+        let publish_at = Utc::now();
+        let m = measurement(ProofStatus::Completed, Some(publish_at));
+        let second = Duration::seconds(1);
+        assert!(!is_public(&m, publish_at - second));
+        assert!(!is_released(&m, publish_at - second));
+        assert!(is_public(&m, publish_at));
+        assert!(is_released(&m, publish_at));
+        assert!(is_public(&measurement(ProofStatus::Pending, None), publish_at - second));
+    }
+
+    #[test]
+    fn embargo_follows_a_stepped_clock() {
+        let step_path = std::env::temp_dir().join(format!("clock-step-{}", Uuid::new_v4()));
+        let step_path = step_path.to_string_lossy().into_owned();
+        let clock = Clock::stepped_by(&step_path);
+        let m = measurement(ProofStatus::Completed, Some(Utc::now() + Duration::hours(1)));
+
+        std::fs::write(&step_path, "3590").unwrap();
+        assert!(!is_public(&m, clock.now()));
+        assert!(!describe(&m, clock.now()).unwrap().public);
+        std::fs::write(&step_path, "3600").unwrap();
+        assert!(is_public(&m, clock.now()));
+        assert!(describe(&m, clock.now()).unwrap().public);
+        std::fs::write(&step_path, "-60").unwrap();
+        assert!(!is_released(&m, clock.now()));
+        let _ = std::fs::remove_file(&step_path);
+    }
+
+    #[test]
+    fn pending_embargoes_can_be_moved_lifted_and_added() {
+        let now = Utc::now();
+        let later = now + Duration::hours(2);
+        let mut m = measurement(ProofStatus::Pending, Some(now + Duration::hours(1)));
+
+        let moved = change(&mut m, Some(later), now).unwrap().unwrap();
+        assert_eq!((moved.publish_at, moved.public), (later, false));
+        assert_eq!(change(&mut m, None, now).unwrap().map(|e| e.publish_at), None);
+        assert_eq!(m.publish_at, None);
+        assert_eq!(change(&mut m, Some(later), now).unwrap().map(|e| e.publish_at), Some(later));
+
+        m.status = ProofStatus::Processing;
+        assert!(change(&mut m, Some(later + Duration::hours(1)), now).is_ok());
+    }
+
+    #[test]
+    fn passed_and_finished_embargoes_are_not_changed() {
+        let now = Utc::now();
+        let passed = now - Duration::seconds(1);
+        let mut m = measurement(ProofStatus::Pending, Some(passed));
+        let (status, _) = change(&mut m, None, now).unwrap_err();
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(m.publish_at, Some(passed));
+
+        // An embargo still running can be lifted once finished, but none added
+        let mut m = measurement(ProofStatus::Completed, Some(now + Duration::hours(1)));
+        assert!(change(&mut m, None, now).is_ok());
+        let (status, _) = change(&mut m, Some(now + Duration::hours(1)), now).unwrap_err();
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+}
@@ -0,0 +1,64 @@
+// Measurement lifecycle events.
+//
+// Status transitions and embargo releases are published on a broadcast
+// channel so integrations can follow measurements without hooking into the
+// pipeline. Publishing never blocks; events are dropped when nobody listens.
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::{Measurement, ProofStatus};
+
+// Events kept for subscribers that fall behind
+const EVENT_BUFFER: usize = 1024;
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    StatusChanged,
+    // An embargoed measurement became publicly visible
+    Published,
+}
+
+#[derive(Debug, Serialize, Clone, JsonSchema)]
+pub struct MeasurementEvent {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+    pub kind: EventKind,
+    pub status: ProofStatus,
+    pub at: DateTime<Utc>,
+}
+
+pub struct EventBus {
+    sender: broadcast::Sender<MeasurementEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_BUFFER);
+        EventBus { sender }
+    }
+
+    // Publish an event reflecting the measurement's current status
+    pub fn publish(&self, measurement: &Measurement, kind: EventKind) {
+        let event = MeasurementEvent {
+            id: measurement.id.clone(),
+            tenant: measurement.tenant.clone(),
+            external_id: measurement.external_id.clone(),
+            kind,
+            status: measurement.status.clone(),
+            at: Utc::now(),
+        };
+        // Sending only fails when there are no subscribers
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<MeasurementEvent> {
+        self.sender.subscribe()
+    }
+}
@@ -0,0 +1,83 @@
+// Client-supplied measurement identifiers.
+//
+// Partners may tag a submission with their own externalId so retries and
+// cross-references don't depend on our UUIDs. External ids are unique per
+// owner, the API key submitting or the address of an anonymous caller: a
+// second submission with the same id is rejected with 409 and the existing
+// measurement, or answered with the existing measurement when
+// EXTERNAL_ID_RETURN_EXISTING is set. The index is only modified while the
+// measurement map is locked, so both always change together; measurements
+// another instance submitted to a shared store are synced into the map but
+// only indexed at the next startup.
+
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    http::StatusCode,
+    response::Json,
+};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, sync::Mutex};
+
+use crate::{
+    AppState, MeasurementStatus, access,
+    auth::{Authorized, Submitter},
+    check_proof_status,
+    pending::StrictQuery,
+    tenants::{self, Visitor},
+};
+
+pub const MAX_EXTERNAL_ID_CHARS: usize = 128;
+
+// Measurement ids keyed by owner and external id
+#[derive(Default)]
+pub struct ExternalIdIndex {
+    ids: Mutex<HashMap<(String, String), String>>,
+}
+
+impl ExternalIdIndex {
+    pub fn get(&self, owner: &str, external_id: &str) -> Option<String> {
+        self.ids.lock().unwrap().get(&(owner.to_string(), external_id.to_string())).cloned()
+    }
+
+    pub fn insert(&self, owner: &str, external_id: &str, id: &str) {
+        self.ids
+            .lock()
+            .unwrap()
+            .insert((owner.to_string(), external_id.to_string()), id.to_string());
+    }
+
+    pub fn remove(&self, owner: &str, external_id: &str) {
+        self.ids.lock().unwrap().remove(&(owner.to_string(), external_id.to_string()));
+    }
+}
+
+// Check an external id supplied by a client
+pub fn validate(value: &str) -> Result<String, String> {
+    let value = value.trim();
+    if value.is_empty() || value.chars().count() > MAX_EXTERNAL_ID_CHARS {
+        return Err(format!("externalId must be 1 to {} characters long", MAX_EXTERNAL_ID_CHARS));
+    }
+    if !value.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':')) {
+        return Err(
+            "externalId may only contain letters, digits, '-', '_', '.' and ':'".to_string()
+        );
+    }
+    Ok(value.to_string())
+}
+
+// Handler returning the status of the caller's measurement with an external id
+pub async fn get_by_external_id(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Submitter>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    Path(external_id): Path<String>,
+    query: Query<StrictQuery>,
+) -> Result<Json<MeasurementStatus>, (StatusCode, String)> {
+    let owner =
+        tenants::owner_key(caller.tenant.as_deref(), &access::submitter(&caller, remote.ip()));
+    let id = state.external_ids.get(&owner, &external_id).ok_or((
+        StatusCode::NOT_FOUND,
+        format!("Measurement with external ID {} not found", external_id),
+    ))?;
+
+    check_proof_status(State(state), Visitor(caller), ConnectInfo(remote), Path(id), query).await
+}
@@ -0,0 +1,129 @@
+// Points of a multipart submission sent as one field per coordinate.
+//
+// Besides startPoint and endPoint as JSON objects, the form of
+// POST /measurements may carry each coordinate in a field of its own, startX,
+// startY, startZ, endX, endY and endZ, as a plain decimal number of metres
+// such as -0.25, so clients need not encode JSON into form fields. Each point
+// may come in either style, independently of the other. A point sent in both
+// styles must agree exactly, a point sent as fields needs all three of them,
+// and a coordinate field may be sent only once; anything else is rejected
+// naming the fields involved, before the points are converted like JSON ones.
+
+use crate::Point3D;
+
+// One of the two points of a measurement
+#[derive(Debug, Clone, Copy)]
+pub enum End {
+    Start,
+    End,
+}
+
+impl End {
+    fn prefix(self) -> &'static str {
+        match self {
+            End::Start => "start",
+            End::End => "end",
+        }
+    }
+
+    // Name of the field holding the point as a JSON object
+    fn json_field(self) -> &'static str {
+        match self {
+            End::Start => "startPoint",
+            End::End => "endPoint",
+        }
+    }
+}
+
+const AXES: [char; 3] = ['X', 'Y', 'Z'];
+
+// Coordinates received as fields so far
+#[derive(Default)]
+pub struct FlatPoints {
+    values: [[Option<f64>; 3]; 2],
+}
+
+// Point and axis of a coordinate field name, such as startX
+fn parse_name(name: &str) -> Option<(End, usize)> {
+    let (end, axis) = if let Some(axis) = name.strip_prefix("start") {
+        (End::Start, axis)
+    } else {
+        (End::End, name.strip_prefix("end")?)
+    };
+    let mut chars = axis.chars();
+    let (Some(axis), None) = (chars.next(), chars.next()) else {
+        return None;
+    };
+    Some((end, AXES.iter().position(|a| *a == axis)?))
+}
+
+// A plain decimal number: an optional sign, digits and an optional fraction
+fn parse_decimal(text: &str) -> Option<f64> {
+    let unsigned = text.strip_prefix(['-', '+']).unwrap_or(text);
+    let (whole, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+    let digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+    if whole.is_empty() && fraction.is_empty() || !digits(whole) || !digits(fraction) {
+        return None;
+    }
+    text.parse().ok()
+}
+
+impl FlatPoints {
+    // Whether the form field is a coordinate field
+    pub fn is_field(name: &str) -> bool {
+        parse_name(name).is_some()
+    }
+
+    // Record the value of a coordinate field
+    pub fn set(&mut self, name: &str, text: &str) -> Result<(), String> {
+        let Some((end, axis)) = parse_name(name) else {
+            return Err(format!("{} is not a coordinate field", name));
+        };
+        let value = parse_decimal(text.trim()).ok_or_else(|| {
+            format!("{} must be a decimal number of metres, got '{}'", name, text)
+        })?;
+        let slot = &mut self.values[end as usize][axis];
+        if slot.is_some() {
+            return Err(format!("{} was sent more than once", name));
+        }
+        *slot = Some(value);
+        Ok(())
+    }
+
+    // The point from its coordinate fields, its JSON field or both, which must agree
+    pub fn resolve(&self, end: End, json: Option<Point3D>) -> Result<Option<Point3D>, String> {
+        let names = AXES.map(|axis| format!("{}{}", end.prefix(), axis));
+        let values = self.values[end as usize];
+        if values.iter().all(Option::is_none) {
+            return Ok(json);
+        }
+        let [Some(x), Some(y), Some(z)] = values else {
+            let missing: Vec<&str> = names
+                .iter()
+                .zip(values)
+                .filter(|(_, value)| value.is_none())
+                .map(|(name, _)| name.as_str())
+                .collect();
+            return Err(format!(
+                "Missing {}: a point sent as separate fields needs {}",
+                missing.join(" and "),
+                names.join(", ")
+            ));
+        };
+        let flat = Point3D { x, y, z };
+        match json {
+            Some(json) if json != flat => Err(format!(
+                "{} ({}, {}, {}) and {} ({}, {}, {}) disagree; send the point in one style",
+                end.json_field(),
+                json.x,
+                json.y,
+                json.z,
+                names.join("/"),
+                flat.x,
+                flat.y,
+                flat.z
+            )),
+            _ => Ok(Some(flat)),
+        }
+    }
+}
@@ -0,0 +1,442 @@
+// In-process verification of snarkjs Groth16 proofs on BN254.
+//
+// verify_groth16 checks a proof and its public signals against a
+// verification key without running snarkjs or touching the filesystem, so
+// other services can link it and the server can verify proofs inline. The
+// key, proof and public signals are parsed from the JSON snarkjs writes
+// (verification_key.json, proof.json and public.json) with from_snarkjs and
+// parse_public_signals; callers read the files themselves. Proofs made in
+// process (see groth16_prover.rs) are written back out with to_snarkjs and
+// public_signals_to_snarkjs in the exact layout snarkjs uses.
+//
+// Parsing is strict: every point must lie on its curve and in the prime-order
+// subgroup, with z = 1 or, for the point at infinity, z = 0, and field
+// elements must be canonical decimals below the modulus rather than reduced,
+// so a proof has exactly one encoding.
+
+use ark_bn254::{Bn254, Fq, Fq2, Fr, G1Affine, G2Affine};
+use ark_ec::AffineRepr;
+use ark_ff::{BigInt, BigInteger, Field, PrimeField, Zero};
+use ark_groth16::{Groth16, PreparedVerifyingKey, Proof, VerifyingKey, prepare_verifying_key};
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+// Why a key, proof or public signal could not be used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyError {
+    // The JSON does not have the shape snarkjs writes
+    Json(String),
+    // A protocol or curve other than groth16 on bn128
+    Unsupported(String),
+    // A number that is not a canonical element of its field
+    InvalidFieldElement(String),
+    // A point off its curve or outside the prime-order subgroup
+    InvalidPoint(&'static str),
+    // The key expects another number of public signals
+    PublicInputCount { expected: usize, got: usize },
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VerifyError::Json(e) => write!(f, "malformed JSON: {}", e),
+            VerifyError::Unsupported(what) => write!(f, "unsupported {}", what),
+            VerifyError::InvalidFieldElement(value) => {
+                write!(f, "'{}' is not a canonical field element", value)
+            }
+            VerifyError::InvalidPoint(name) => write!(f, "{} is not a valid curve point", name),
+            VerifyError::PublicInputCount { expected, got } => {
+                write!(f, "expected {} public signals, got {}", expected, got)
+            }
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+// A verification key, prepared for checking proofs
+pub struct VerificationKey {
+    prepared: PreparedVerifyingKey<Bn254>,
+}
+
+// A Groth16 proof
+#[derive(Debug, Clone, PartialEq)]
+pub struct Groth16Proof(pub(crate) Proof<Bn254>);
+
+// A public signal, an element of the BN254 scalar field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldElement(pub(crate) Fr);
+
+// A G1 point as snarkjs writes it: x, y, z
+type SnarkjsG1 = [String; 3];
+// A G2 point as snarkjs writes it: [x.c0, x.c1], [y.c0, y.c1], [z.c0, z.c1]
+type SnarkjsG2 = [[String; 2]; 3];
+
+#[derive(Deserialize)]
+struct SnarkjsKey {
+    protocol: String,
+    curve: String,
+    #[serde(rename = "nPublic")]
+    n_public: usize,
+    vk_alpha_1: SnarkjsG1,
+    vk_beta_2: SnarkjsG2,
+    vk_gamma_2: SnarkjsG2,
+    vk_delta_2: SnarkjsG2,
+    #[serde(rename = "IC")]
+    ic: Vec<SnarkjsG1>,
+}
+
+// Fields in the order snarkjs writes them
+#[derive(Deserialize, Serialize)]
+struct SnarkjsProof {
+    pi_a: SnarkjsG1,
+    pi_b: SnarkjsG2,
+    pi_c: SnarkjsG1,
+    protocol: String,
+    curve: String,
+}
+
+impl VerificationKey {
+    // Parse a key from snarkjs' verification_key.json
+    pub fn from_snarkjs(json: &str) -> Result<Self, VerifyError> {
+        let key: SnarkjsKey =
+            serde_json::from_str(json).map_err(|e| VerifyError::Json(e.to_string()))?;
+        check_scheme(&key.protocol, &key.curve)?;
+        if key.ic.len() != key.n_public + 1 {
+            return Err(VerifyError::Json(format!(
+                "nPublic is {} but IC has {} points",
+                key.n_public,
+                key.ic.len()
+            )));
+        }
+        let key = VerifyingKey {
+            alpha_g1: g1(&key.vk_alpha_1, "vk_alpha_1")?,
+            beta_g2: g2(&key.vk_beta_2, "vk_beta_2")?,
+            gamma_g2: g2(&key.vk_gamma_2, "vk_gamma_2")?,
+            delta_g2: g2(&key.vk_delta_2, "vk_delta_2")?,
+            gamma_abc_g1: key.ic.iter().map(|p| g1(p, "IC")).collect::<Result<_, _>>()?,
+        };
+        Ok(VerificationKey { prepared: prepare_verifying_key(&key) })
+    }
+
+    // Number of public signals a proof must come with
+    pub fn public_inputs(&self) -> usize {
+        self.prepared.vk.gamma_abc_g1.len() - 1
+    }
+}
+
+impl Groth16Proof {
+    // Parse a proof from snarkjs' proof.json
+    pub fn from_snarkjs(json: &str) -> Result<Self, VerifyError> {
+        let proof: SnarkjsProof =
+            serde_json::from_str(json).map_err(|e| VerifyError::Json(e.to_string()))?;
+        check_scheme(&proof.protocol, &proof.curve)?;
+        Ok(Groth16Proof(Proof {
+            a: g1(&proof.pi_a, "pi_a")?,
+            b: g2(&proof.pi_b, "pi_b")?,
+            c: g1(&proof.pi_c, "pi_c")?,
+        }))
+    }
+
+    // The proof as snarkjs writes proof.json
+    pub fn to_snarkjs(&self) -> String {
+        to_snarkjs_json(&SnarkjsProof {
+            pi_a: snarkjs_g1(&self.0.a),
+            pi_b: snarkjs_g2(&self.0.b),
+            pi_c: snarkjs_g1(&self.0.c),
+            protocol: "groth16".to_string(),
+            curve: "bn128".to_string(),
+        })
+    }
+}
+
+impl FieldElement {
+    // The element as 32 little-endian bytes, as zkVerify hashes public inputs
+    pub fn to_le_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0; 32];
+        bytes.copy_from_slice(&self.0.into_bigint().to_bytes_le());
+        bytes
+    }
+}
+
+impl fmt::Display for FieldElement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.into_bigint())
+    }
+}
+
+impl FromStr for FieldElement {
+    type Err = VerifyError;
+
+    // A decimal below the scalar field's modulus
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        field(s).map(FieldElement)
+    }
+}
+
+// Parse the public signals from snarkjs' public.json
+pub fn parse_public_signals(json: &str) -> Result<Vec<FieldElement>, VerifyError> {
+    let signals: Vec<String> =
+        serde_json::from_str(json).map_err(|e| VerifyError::Json(e.to_string()))?;
+    signals.iter().map(|s| s.parse()).collect()
+}
+
+// Public signals as snarkjs writes public.json
+pub fn public_signals_to_snarkjs(public: &[FieldElement]) -> String {
+    let signals: Vec<String> = public.iter().map(|signal| signal.to_string()).collect();
+    to_snarkjs_json(&signals)
+}
+
+// Check a proof and its public signals against a verification key. Ok(false)
+// means the proof does not verify; errors mean it cannot be checked.
+pub fn verify_groth16(
+    vkey: &VerificationKey,
+    proof: &Groth16Proof,
+    public: &[FieldElement],
+) -> Result<bool, VerifyError> {
+    let expected = vkey.public_inputs();
+    if public.len() != expected {
+        return Err(VerifyError::PublicInputCount { expected, got: public.len() });
+    }
+    let inputs: Vec<Fr> = public.iter().map(|signal| signal.0).collect();
+    Groth16::<Bn254>::verify_proof(&vkey.prepared, &proof.0, &inputs)
+        .map_err(|_| VerifyError::PublicInputCount { expected, got: public.len() })
+}
+
+fn check_scheme(protocol: &str, curve: &str) -> Result<(), VerifyError> {
+    if protocol != "groth16" {
+        return Err(VerifyError::Unsupported(format!("protocol '{}'", protocol)));
+    }
+    if curve != "bn128" {
+        return Err(VerifyError::Unsupported(format!("curve '{}'", curve)));
+    }
+    Ok(())
+}
+
+// A canonical field element, rejecting values at or above the modulus
+fn field<F: PrimeField<BigInt = BigInt<4>>>(value: &str) -> Result<F, VerifyError> {
+    let invalid = || VerifyError::InvalidFieldElement(value.to_string());
+    let digits = !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit());
+    if !digits || (value.len() > 1 && value.starts_with('0')) {
+        return Err(invalid());
+    }
+    let int = BigInt::<4>::from_str(value).map_err(|_| invalid())?;
+    F::from_bigint(int).ok_or_else(invalid)
+}
+
+fn g1(point: &SnarkjsG1, name: &'static str) -> Result<G1Affine, VerifyError> {
+    let [x, y, z] = point;
+    let z: Fq = field(z)?;
+    if z.is_zero() {
+        return Ok(G1Affine::zero());
+    }
+    if z != Fq::ONE {
+        return Err(VerifyError::InvalidPoint(name));
+    }
+    let point = G1Affine::new_unchecked(field(x)?, field(y)?);
+    if !point.is_on_curve() || !point.is_in_correct_subgroup_assuming_on_curve() {
+        return Err(VerifyError::InvalidPoint(name));
+    }
+    Ok(point)
+}
+
+fn g2(point: &SnarkjsG2, name: &'static str) -> Result<G2Affine, VerifyError> {
+    let [x, y, z] = point;
+    let coordinate = |[c0, c1]: &[String; 2]| -> Result<Fq2, VerifyError> {
+        Ok(Fq2::new(field(c0)?, field(c1)?))
+    };
+    let z = coordinate(z)?;
+    if z.is_zero() {
+        return Ok(G2Affine::zero());
+    }
+    if z != Fq2::ONE {
+        return Err(VerifyError::InvalidPoint(name));
+    }
+    let point = G2Affine::new_unchecked(coordinate(x)?, coordinate(y)?);
+    if !point.is_on_curve() || !point.is_in_correct_subgroup_assuming_on_curve() {
+        return Err(VerifyError::InvalidPoint(name));
+    }
+    Ok(point)
+}
+
+// JSON indented by one space and without a final newline, like snarkjs
+fn to_snarkjs_json<T: Serialize>(value: &T) -> String {
+    let mut json = Vec::new();
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(b" ");
+    let mut serializer = serde_json::Serializer::with_formatter(&mut json, formatter);
+    value.serialize(&mut serializer).expect("snarkjs JSON serializes");
+    String::from_utf8(json).expect("JSON is UTF-8")
+}
+
+// Affine points are written with z = 1, the point at infinity as (0, 1, 0)
+fn snarkjs_g1(point: &G1Affine) -> SnarkjsG1 {
+    match point.xy() {
+        Some((x, y)) => [x.to_string(), y.to_string(), "1".to_string()],
+        None => ["0".to_string(), "1".to_string(), "0".to_string()],
+    }
+}
+
+fn snarkjs_g2(point: &G2Affine) -> SnarkjsG2 {
+    let coordinate = |c: Fq2| [c.c0.to_string(), c.c1.to_string()];
+    match point.xy() {
+        Some((x, y)) => [coordinate(x), coordinate(y), coordinate(Fq2::ONE)],
+        None => [coordinate(Fq2::zero()), coordinate(Fq2::ONE), coordinate(Fq2::zero())],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+
+    const KEY: &str = include_str!("../test_vectors/groth16/verification_key.json");
+    const PROOF: &str = include_str!("../test_vectors/groth16/proof.json");
+    const PUBLIC: &str = include_str!("../test_vectors/groth16/public.json");
+    // Key of the mock toolchain, for another circuit with one public signal
+    const OTHER_KEY: &str = include_str!("../test_vectors/mock_toolchain/verification_key.json");
+
+    fn verify(key: &str, proof: &str, public: &str) -> Result<bool, VerifyError> {
+        verify_groth16(
+            &VerificationKey::from_snarkjs(key)?,
+            &Groth16Proof::from_snarkjs(proof)?,
+            &parse_public_signals(public)?,
+        )
+    }
+
+    // The proof fixture with one of its fields replaced
+    fn proof_with(field: &str, value: Value) -> String {
+        let mut proof: Value = serde_json::from_str(PROOF).unwrap();
+        proof[field] = value;
+        proof.to_string()
+    }
+
+    #[test]
+    fn good_proof_verifies() {
+        assert_eq!(verify(KEY, PROOF, PUBLIC), Ok(true));
+    }
+
+    #[test]
+    fn good_proof_survives_writing_it_back_out() {
+        let proof = Groth16Proof::from_snarkjs(PROOF).unwrap();
+        assert_eq!(verify(KEY, &proof.to_snarkjs(), PUBLIC), Ok(true));
+        let public = parse_public_signals(PUBLIC).unwrap();
+        assert_eq!(
+            public_signals_to_snarkjs(&public),
+            r#"[
+ "9"
+]"#
+        );
+    }
+
+    #[test]
+    fn tampered_proof_points_do_not_verify() {
+        let proof: Value = serde_json::from_str(PROOF).unwrap();
+        // Valid points in the wrong place
+        assert_eq!(verify(KEY, &proof_with("pi_a", proof["pi_c"].clone()), PUBLIC), Ok(false));
+        assert_eq!(verify(KEY, &proof_with("pi_c", proof["pi_a"].clone()), PUBLIC), Ok(false));
+        // The negated point, still on the curve
+        let negated = G1Affine::new_unchecked(
+            field(proof["pi_a"][0].as_str().unwrap()).unwrap(),
+            -field::<Fq>(proof["pi_a"][1].as_str().unwrap()).unwrap(),
+        );
+        let tampered = proof_with("pi_a", json!(snarkjs_g1(&negated)));
+        assert_eq!(verify(KEY, &tampered, PUBLIC), Ok(false));
+    }
+
+    #[test]
+    fn points_off_the_curve_are_refused() {
+        let proof: Value = serde_json::from_str(PROOF).unwrap();
+        let mut off_curve = proof["pi_a"].clone();
+        off_curve[1] = json!("1");
+        assert_eq!(
+            verify(KEY, &proof_with("pi_a", off_curve), PUBLIC),
+            Err(VerifyError::InvalidPoint("pi_a"))
+        );
+        let mut projective = proof["pi_c"].clone();
+        projective[2] = json!("2");
+        assert_eq!(
+            verify(KEY, &proof_with("pi_c", projective), PUBLIC),
+            Err(VerifyError::InvalidPoint("pi_c"))
+        );
+        let mut b = proof["pi_b"].clone();
+        b[0][0] = json!("1");
+        assert_eq!(
+            verify(KEY, &proof_with("pi_b", b), PUBLIC),
+            Err(VerifyError::InvalidPoint("pi_b"))
+        );
+    }
+
+    #[test]
+    fn tampered_public_signal_does_not_verify() {
+        assert_eq!(verify(KEY, PROOF, r#"["10"]"#), Ok(false));
+        assert_eq!(verify(KEY, PROOF, r#"["0"]"#), Ok(false));
+        // 9 plus the modulus names the same element, but is not canonical
+        let wrapped =
+            "21888242871839275222246405745257275088548364400416034343698204186575808495626";
+        assert_eq!(
+            verify(KEY, PROOF, &format!(r#"["{}"]"#, wrapped)),
+            Err(VerifyError::InvalidFieldElement(wrapped.to_string()))
+        );
+        assert_eq!(
+            verify(KEY, PROOF, r#"["09"]"#),
+            Err(VerifyError::InvalidFieldElement("09".to_string()))
+        );
+        assert_eq!(
+            verify(KEY, PROOF, r#"["9", "9"]"#),
+            Err(VerifyError::PublicInputCount { expected: 1, got: 2 })
+        );
+    }
+
+    #[test]
+    fn proof_for_another_key_does_not_verify() {
+        assert_eq!(verify(OTHER_KEY, PROOF, PUBLIC), Ok(false));
+    }
+
+    #[test]
+    fn other_schemes_are_refused() {
+        assert_eq!(
+            verify(KEY, &proof_with("protocol", json!("plonk")), PUBLIC),
+            Err(VerifyError::Unsupported("protocol 'plonk'".to_string()))
+        );
+        assert_eq!(
+            verify(KEY, &proof_with("curve", json!("bls12381")), PUBLIC),
+            Err(VerifyError::Unsupported("curve 'bls12381'".to_string()))
+        );
+    }
+
+    #[test]
+    fn malformed_json_is_refused() {
+        let malformed_proofs = [
+            String::new(),
+            "{".to_string(),
+            "[]".to_string(),
+            PROOF[..PROOF.len() / 2].to_string(),
+            proof_with("pi_c", Value::Null),
+            proof_with("pi_a", json!(["1", "2"])),
+            proof_with("pi_a", json!([1, 2, 1])),
+        ];
+        for proof in &malformed_proofs {
+            assert!(
+                matches!(Groth16Proof::from_snarkjs(proof), Err(VerifyError::Json(_))),
+                "{}",
+                proof
+            );
+        }
+        for key in ["", "null", &KEY[..KEY.len() - 2]] {
+            assert!(matches!(VerificationKey::from_snarkjs(key), Err(VerifyError::Json(_))));
+        }
+        let mut key: Value = serde_json::from_str(KEY).unwrap();
+        key["nPublic"] = json!(2);
+        assert_eq!(
+            VerificationKey::from_snarkjs(&key.to_string()).err(),
+            Some(VerifyError::Json("nPublic is 2 but IC has 2 points".to_string()))
+        );
+        for public in ["", "9", "[9]", r#"{"0": "9"}"#, r#"["9""#] {
+            assert!(
+                matches!(parse_public_signals(public), Err(VerifyError::Json(_))),
+                "{}",
+                public
+            );
+        }
+    }
+}
@@ -0,0 +1,531 @@
+// In-process Groth16 proving with snarkjs proving keys on BN254.
+//
+// prove generates the proof `snarkjs groth16 prove` would from the same zkey
+// and witness, without starting Node: ProvingKey::from_zkey reads a proving
+// key as snarkjs writes it, once, and Witness::from_wtns the witness a
+// circuit's generate_witness.js writes. Proofs are randomized, so one never
+// equals snarkjs' proof byte for byte, but it verifies against the same
+// verification key, proves the same public signals and is written as the
+// same proof.json and public.json (see groth16.rs).
+//
+// snarkjs does not reduce the circuit to a QAP the way arkworks does by
+// default. Its zkey holds the H query as Lagrange bases over the odd powers
+// of a domain twice the size of the circuit's, so CircomReduction evaluates
+// A·B - C on that coset instead of dividing by the vanishing polynomial. The
+// zkey also stores only the A and B matrices, with one more row in A for
+// signal 0 and every public signal after the circuit's own constraints.
+//
+// Files are checked for their layout and every field element for being
+// canonical, but points are only checked to lie on their curve: the proving
+// key is trusted like the snarkjs install it replaces.
+
+use ark_bn254::{Bn254, Fq, Fq2, Fr, G1Affine, G2Affine};
+use ark_ec::AffineRepr;
+use ark_ff::{BigInt, One, PrimeField, UniformRand, Zero};
+use ark_groth16::{
+    Groth16,
+    r1cs_to_qap::{LibsnarkReduction, R1CSToQAP, evaluate_constraint},
+};
+use ark_poly::{EvaluationDomain, GeneralEvaluationDomain};
+use ark_relations::r1cs::{ConstraintMatrices, ConstraintSystemRef, SynthesisError};
+use std::{collections::HashMap, fmt};
+
+use crate::groth16::{FieldElement, Groth16Proof};
+
+// Prover type of a Groth16 zkey in its header section
+const GROTH16: u32 = 1;
+// Bytes of a field element of either field
+const N8: usize = 32;
+
+// Why a proving key or witness could not be used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProveError {
+    // The file does not have the layout snarkjs writes
+    Format(String),
+    // A proving system or curve other than groth16 on bn128
+    Unsupported(String),
+    // The witness is not one of the key's circuit
+    WitnessSize { expected: usize, got: usize },
+    Synthesis(String),
+}
+
+impl fmt::Display for ProveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProveError::Format(e) => write!(f, "malformed file: {}", e),
+            ProveError::Unsupported(what) => write!(f, "unsupported {}", what),
+            ProveError::WitnessSize { expected, got } => {
+                write!(f, "expected a witness of {} signals, got {}", expected, got)
+            }
+            ProveError::Synthesis(e) => write!(f, "proving failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ProveError {}
+
+// A proving key read from a snarkjs zkey
+pub struct ProvingKey {
+    key: ark_groth16::ProvingKey<Bn254>,
+    matrices: ConstraintMatrices<Fr>,
+    n_vars: usize,
+    n_public: usize,
+}
+
+// Every signal of a circuit as computed for one input, signal 0 being 1
+pub struct Witness(Vec<Fr>);
+
+impl ProvingKey {
+    // Read a proving key from the bytes of a zkey
+    pub fn from_zkey(bytes: &[u8]) -> Result<Self, ProveError> {
+        let sections = Sections::read(bytes, b"zkey")?;
+
+        let mut header = sections.get(1)?;
+        let prover = header.u32()?;
+        if prover != GROTH16 {
+            return Err(ProveError::Unsupported(format!("prover type {}", prover)));
+        }
+
+        let mut groth = sections.get(2)?;
+        groth.modulus::<Fq>("base field")?;
+        groth.modulus::<Fr>("scalar field")?;
+        let n_vars = groth.u32()? as usize;
+        let n_public = groth.u32()? as usize;
+        let domain_size = groth.u32()? as usize;
+        if n_public + 1 > n_vars || !domain_size.is_power_of_two() {
+            return Err(ProveError::Format(format!(
+                "{} signals, {} of them public, in a domain of {}",
+                n_vars, n_public, domain_size
+            )));
+        }
+        // Each point of the domain has an H point, so a domain larger than
+        // section 9 is refused before anything is allocated for it
+        sections.get(9)?.holds(domain_size, 2 * N8)?;
+        let alpha_g1 = groth.g1()?;
+        let beta_g1 = groth.g1()?;
+        let beta_g2 = groth.g2()?;
+        let gamma_g2 = groth.g2()?;
+        let delta_g1 = groth.g1()?;
+        let delta_g2 = groth.g2()?;
+
+        let vk = ark_groth16::VerifyingKey {
+            alpha_g1,
+            beta_g2,
+            gamma_g2,
+            delta_g2,
+            gamma_abc_g1: sections.get(3)?.g1s(n_public + 1)?,
+        };
+        let key = ark_groth16::ProvingKey {
+            vk,
+            beta_g1,
+            delta_g1,
+            a_query: sections.get(5)?.g1s(n_vars)?,
+            b_g1_query: sections.get(6)?.g1s(n_vars)?,
+            b_g2_query: sections.get(7)?.g2s(n_vars)?,
+            l_query: sections.get(8)?.g1s(n_vars - n_public - 1)?,
+            h_query: sections.get(9)?.g1s(domain_size)?,
+        };
+        let matrices = read_matrices(sections.get(4)?, n_vars, n_public, domain_size)?;
+        Ok(ProvingKey { key, matrices, n_vars, n_public })
+    }
+
+    // Number of public signals the proofs of this key come with
+    pub fn public_inputs(&self) -> usize {
+        self.n_public
+    }
+
+    // Number of constraints of the circuit, public signals left out
+    pub fn constraints(&self) -> usize {
+        self.matrices.num_constraints
+    }
+}
+
+impl Witness {
+    // Read a witness from the bytes of a wtns file
+    pub fn from_wtns(bytes: &[u8]) -> Result<Self, ProveError> {
+        let sections = Sections::read(bytes, b"wtns")?;
+        let mut header = sections.get(1)?;
+        header.modulus::<Fr>("scalar field")?;
+        let count = header.u32()? as usize;
+
+        let mut values = sections.get(2)?;
+        let signals = (0..count).map(|_| values.fr()).collect::<Result<Vec<_>, _>>()?;
+        if signals.first() != Some(&Fr::one()) {
+            return Err(ProveError::Format("signal 0 of the witness is not 1".to_string()));
+        }
+        Ok(Witness(signals))
+    }
+}
+
+// Prove a witness with a proving key, returning the proof and its public signals
+pub fn prove(
+    key: &ProvingKey,
+    witness: &Witness,
+) -> Result<(Groth16Proof, Vec<FieldElement>), ProveError> {
+    let signals = &witness.0;
+    if signals.len() != key.n_vars {
+        return Err(ProveError::WitnessSize { expected: key.n_vars, got: signals.len() });
+    }
+    let mut rng = rand::thread_rng();
+    let (r, s) = (Fr::rand(&mut rng), Fr::rand(&mut rng));
+    let proof = Groth16::<Bn254, CircomReduction>::create_proof_with_reduction_and_matrices(
+        &key.key,
+        r,
+        s,
+        &key.matrices,
+        key.n_public + 1,
+        key.matrices.num_constraints,
+        signals,
+    )
+    .map_err(|e| ProveError::Synthesis(e.to_string()))?;
+    let public = signals[1..=key.n_public].iter().map(|signal| FieldElement(*signal)).collect();
+    Ok((Groth16Proof(proof), public))
+}
+
+// The A and B matrices of section 4, without the rows of the public signals
+fn read_matrices(
+    mut section: Reader,
+    n_vars: usize,
+    n_public: usize,
+    domain_size: usize,
+) -> Result<ConstraintMatrices<Fr>, ProveError> {
+    let count = section.u32()? as usize;
+    // Matrix, row and signal, then the coefficient
+    section.holds(count, 3 * 4 + N8)?;
+    let mut rows = [vec![Vec::new(); domain_size], vec![Vec::new(); domain_size]];
+    let mut last_row = 0;
+    for _ in 0..count {
+        let matrix = section.u32()? as usize;
+        let row = section.u32()? as usize;
+        let signal = section.u32()? as usize;
+        // Coefficients are stored in Montgomery form multiplied by R once more
+        let value = Fr::new_unchecked(section.below_modulus::<Fr>()?);
+        let value = Fr::new_unchecked(value.into_bigint());
+        if matrix > 1 || row >= domain_size || signal >= n_vars {
+            return Err(ProveError::Format(format!(
+                "coefficient of signal {} in row {} of matrix {}",
+                signal, row, matrix
+            )));
+        }
+        last_row = last_row.max(row);
+        rows[matrix][row].push((value, signal));
+    }
+
+    // The rows of signal 0 and the public signals follow the constraints
+    let constraints = (last_row + 1).checked_sub(n_public + 1).ok_or_else(|| {
+        ProveError::Format(format!("{} rows for {} public signals", last_row + 1, n_public))
+    })?;
+    let domain = GeneralEvaluationDomain::<Fr>::new(constraints + n_public + 1);
+    if domain.map(|domain| domain.size()) != Some(domain_size) {
+        return Err(ProveError::Format(format!(
+            "a domain of {} for {} constraints",
+            domain_size, constraints
+        )));
+    }
+    let [mut a, mut b] = rows;
+    a.truncate(constraints);
+    b.truncate(constraints);
+    Ok(ConstraintMatrices {
+        num_instance_variables: n_public + 1,
+        num_witness_variables: n_vars - n_public - 1,
+        num_constraints: constraints,
+        a_num_non_zero: a.iter().map(Vec::len).sum(),
+        b_num_non_zero: b.iter().map(Vec::len).sum(),
+        c_num_non_zero: 0,
+        a,
+        b,
+        c: Vec::new(),
+    })
+}
+
+// The witness map of snarkjs, see the top of this file
+struct CircomReduction;
+
+impl R1CSToQAP for CircomReduction {
+    fn instance_map_with_evaluation<F: PrimeField, D: EvaluationDomain<F>>(
+        cs: ConstraintSystemRef<F>,
+        t: &F,
+    ) -> Result<(Vec<F>, Vec<F>, Vec<F>, F, usize, usize), SynthesisError> {
+        LibsnarkReduction::instance_map_with_evaluation::<F, D>(cs, t)
+    }
+
+    fn witness_map_from_matrices<F: PrimeField, D: EvaluationDomain<F>>(
+        matrices: &ConstraintMatrices<F>,
+        num_inputs: usize,
+        num_constraints: usize,
+        full_assignment: &[F],
+    ) -> Result<Vec<F>, SynthesisError> {
+        let domain =
+            D::new(num_constraints + num_inputs).ok_or(SynthesisError::PolynomialDegreeTooLarge)?;
+        let size = domain.size();
+        let mut a = vec![F::zero(); size];
+        let mut b = vec![F::zero(); size];
+        let mut c = vec![F::zero(); size];
+        for (row, (a_row, b_row)) in matrices.a.iter().zip(&matrices.b).enumerate() {
+            a[row] = evaluate_constraint(a_row, full_assignment);
+            b[row] = evaluate_constraint(b_row, full_assignment);
+            c[row] = a[row] * b[row];
+        }
+        a[num_constraints..num_constraints + num_inputs]
+            .copy_from_slice(&full_assignment[..num_inputs]);
+
+        // Move each polynomial onto the odd powers of the doubled domain
+        let shift = D::new(2 * size).ok_or(SynthesisError::PolynomialDegreeTooLarge)?.element(1);
+        for evaluations in [&mut a, &mut b, &mut c] {
+            domain.ifft_in_place(evaluations);
+            D::distribute_powers_and_mul_by_const(evaluations, shift, F::one());
+            domain.fft_in_place(evaluations);
+        }
+
+        let mut h = domain.mul_polynomials_in_evaluation_domain(&a, &b);
+        for (h, c) in h.iter_mut().zip(c) {
+            *h -= c;
+        }
+        Ok(h)
+    }
+
+    fn h_query_scalars<F: PrimeField, D: EvaluationDomain<F>>(
+        max_power: usize,
+        t: F,
+        _: F,
+        delta_inverse: F,
+    ) -> Result<Vec<F>, SynthesisError> {
+        // Only used for setups, which snarkjs does; kept consistent with the witness map
+        let mut scalars: Vec<F> =
+            (0..2 * max_power + 1).map(|i| delta_inverse * t.pow([i as u64])).collect();
+        let domain = D::new(scalars.len()).ok_or(SynthesisError::PolynomialDegreeTooLarge)?;
+        domain.ifft_in_place(&mut scalars);
+        Ok(scalars.into_iter().skip(1).step_by(2).collect())
+    }
+}
+
+// Sections of a snarkjs binary file by id
+struct Sections<'a>(HashMap<u32, &'a [u8]>);
+
+impl<'a> Sections<'a> {
+    fn read(bytes: &'a [u8], magic: &[u8; 4]) -> Result<Self, ProveError> {
+        let mut file = Reader { bytes, name: "file" };
+        if file.take(4)? != magic {
+            return Err(ProveError::Format(format!(
+                "not a {} file",
+                String::from_utf8_lossy(magic)
+            )));
+        }
+        let _version = file.u32()?;
+        let count = file.u32()?;
+        let mut sections = HashMap::new();
+        for _ in 0..count {
+            let id = file.u32()?;
+            let size = usize::try_from(file.u64()?)
+                .map_err(|_| ProveError::Format(format!("section {} is too large", id)))?;
+            // snarkjs reads the first of repeated sections
+            let section = file.take(size)?;
+            sections.entry(id).or_insert(section);
+        }
+        Ok(Sections(sections))
+    }
+
+    fn get(&self, id: u32) -> Result<Reader<'a>, ProveError> {
+        let name = match id {
+            1 => "header",
+            2 => "Groth16 header",
+            3 => "IC",
+            4 => "coefficients",
+            5 => "A points",
+            6 => "B1 points",
+            7 => "B2 points",
+            8 => "C points",
+            9 => "H points",
+            _ => "section",
+        };
+        match self.0.get(&id) {
+            Some(bytes) => Ok(Reader { bytes, name }),
+            None => Err(ProveError::Format(format!("section {} ({}) is missing", id, name))),
+        }
+    }
+}
+
+// Little-endian reads from the front of a section
+struct Reader<'a> {
+    bytes: &'a [u8],
+    name: &'static str,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], ProveError> {
+        if self.bytes.len() < n {
+            return Err(ProveError::Format(format!("{} ends early", self.name)));
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    // Fail unless n entries of size bytes are left, before allocating for them
+    fn holds(&self, n: usize, size: usize) -> Result<(), ProveError> {
+        if n.checked_mul(size).is_none_or(|bytes| bytes > self.bytes.len()) {
+            return Err(ProveError::Format(format!(
+                "{} is too short for {} entries",
+                self.name, n
+            )));
+        }
+        Ok(())
+    }
+
+    fn u32(&mut self) -> Result<u32, ProveError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, ProveError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bigint(&mut self) -> Result<BigInt<4>, ProveError> {
+        let bytes = self.take(N8)?;
+        let mut limbs = [0u64; 4];
+        for (limb, chunk) in limbs.iter_mut().zip(bytes.chunks_exact(8)) {
+            *limb = u64::from_le_bytes(chunk.try_into().unwrap());
+        }
+        Ok(BigInt::new(limbs))
+    }
+
+    // A field size and modulus, which must be those of F
+    fn modulus<F: PrimeField<BigInt = BigInt<4>>>(
+        &mut self,
+        field: &str,
+    ) -> Result<(), ProveError> {
+        let n8 = self.u32()? as usize;
+        if n8 != N8 || self.bigint()? != F::MODULUS {
+            return Err(ProveError::Unsupported(format!("{} of {}", field, self.name)));
+        }
+        Ok(())
+    }
+
+    // A scalar field element in standard form, as witnesses are stored
+    fn fr(&mut self) -> Result<Fr, ProveError> {
+        let int = self.below_modulus::<Fr>()?;
+        Ok(Fr::from_bigint(int).expect("below the modulus"))
+    }
+
+    // The bits of an element of F, which must be below its modulus
+    fn below_modulus<F: PrimeField<BigInt = BigInt<4>>>(
+        &mut self,
+    ) -> Result<BigInt<4>, ProveError> {
+        let int = self.bigint()?;
+        if int >= F::MODULUS {
+            return Err(ProveError::Format(format!(
+                "{} holds a value above the modulus",
+                self.name
+            )));
+        }
+        Ok(int)
+    }
+
+    // A base field element in Montgomery form, as points are stored
+    fn fq(&mut self) -> Result<Fq, ProveError> {
+        Ok(Fq::new_unchecked(self.below_modulus::<Fq>()?))
+    }
+
+    // An affine point, (0, 0) standing for the point at infinity
+    fn g1(&mut self) -> Result<G1Affine, ProveError> {
+        let (x, y) = (self.fq()?, self.fq()?);
+        if x.is_zero() && y.is_zero() {
+            return Ok(G1Affine::zero());
+        }
+        let point = G1Affine::new_unchecked(x, y);
+        if !point.is_on_curve() {
+            return Err(ProveError::Format(format!("{} holds a point off the curve", self.name)));
+        }
+        Ok(point)
+    }
+
+    fn g2(&mut self) -> Result<G2Affine, ProveError> {
+        let x = Fq2::new(self.fq()?, self.fq()?);
+        let y = Fq2::new(self.fq()?, self.fq()?);
+        if x.is_zero() && y.is_zero() {
+            return Ok(G2Affine::zero());
+        }
+        let point = G2Affine::new_unchecked(x, y);
+        if !point.is_on_curve() {
+            return Err(ProveError::Format(format!("{} holds a point off the curve", self.name)));
+        }
+        Ok(point)
+    }
+
+    fn g1s(&mut self, n: usize) -> Result<Vec<G1Affine>, ProveError> {
+        (0..n).map(|_| self.g1()).collect()
+    }
+
+    fn g2s(&mut self, n: usize) -> Result<Vec<G2Affine>, ProveError> {
+        (0..n).map(|_| self.g2()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::groth16::{self, Groth16Proof, VerificationKey};
+
+    const ZKEY: &[u8] = include_bytes!("../test_vectors/native_prover/multiplier.zkey");
+    const WTNS: &[u8] = include_bytes!("../test_vectors/native_prover/multiplier.wtns");
+    const VKEY: &str = include_str!("../test_vectors/native_prover/verification_key.json");
+
+    // Offset of a section's contents in a snarkjs binary file
+    fn section_offset(bytes: &[u8], id: u32) -> usize {
+        let read_u32 = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        let mut at = 12;
+        loop {
+            let size = u64::from_le_bytes(bytes[at + 4..at + 12].try_into().unwrap()) as usize;
+            if read_u32(at) == id {
+                return at + 12;
+            }
+            at += 12 + size;
+        }
+    }
+
+    // The fixture key with a u32 of a section overwritten
+    fn zkey_with(id: u32, offset: usize, value: u32) -> Vec<u8> {
+        let mut zkey = ZKEY.to_vec();
+        let at = section_offset(&zkey, id) + offset;
+        zkey[at..at + 4].copy_from_slice(&value.to_le_bytes());
+        zkey
+    }
+
+    #[test]
+    fn proofs_verify_against_the_fixture_key() {
+        let key = ProvingKey::from_zkey(ZKEY).unwrap();
+        let (proof, public) = prove(&key, &Witness::from_wtns(WTNS).unwrap()).unwrap();
+        assert_eq!(public.len(), key.public_inputs());
+
+        // Written out and read back as snarkjs would
+        let proof = Groth16Proof::from_snarkjs(&proof.to_snarkjs()).unwrap();
+        let public =
+            groth16::parse_public_signals(&groth16::public_signals_to_snarkjs(&public)).unwrap();
+        let vkey = VerificationKey::from_snarkjs(VKEY).unwrap();
+        assert_eq!(groth16::verify_groth16(&vkey, &proof, &public), Ok(true));
+
+        let mut other = public.clone();
+        other[0] = groth16::parse_public_signals("[\"1\"]").unwrap()[0];
+        assert_eq!(groth16::verify_groth16(&vkey, &proof, &other), Ok(false));
+    }
+
+    #[test]
+    fn witnesses_of_other_circuits_are_refused() {
+        let key = ProvingKey::from_zkey(ZKEY).unwrap();
+        let mut wtns = WTNS.to_vec();
+        // The signal count follows the field size and modulus of the header
+        let at = section_offset(&wtns, 1) + 4 + N8;
+        wtns[at..at + 4].copy_from_slice(&1u32.to_le_bytes());
+        let witness = Witness::from_wtns(&wtns).unwrap();
+        assert!(matches!(prove(&key, &witness), Err(ProveError::WitnessSize { got: 1, .. })));
+    }
+
+    #[test]
+    fn sizes_beyond_their_sections_are_refused_before_allocating() {
+        // The domain size follows both fields and the signal counts
+        let domain = zkey_with(2, 2 * (4 + N8) + 2 * 4, 1 << 31);
+        assert!(matches!(ProvingKey::from_zkey(&domain), Err(ProveError::Format(_))));
+        let coefficients = zkey_with(4, 0, u32::MAX);
+        assert!(matches!(ProvingKey::from_zkey(&coefficients), Err(ProveError::Format(_))));
+    }
+}
@@ -0,0 +1,169 @@
+// Health of the proving environment, and readiness of the server and the
+// integrations it depends on.
+//
+// GET /health checks what every proof needs, which otherwise only shows when
+// the first proof fails: that uploads/ and proofs/ are writable, that the
+// circuit WASM and proving key are installed, and that node and snarkjs
+// answer. The toolchain probe forks twice, so its outcome is reused for
+// TOOLCHAIN_CHECK_TTL. It answers 503 naming the failing checks while any
+// fails, along with the number of measurements in each status. Read-only
+// mirrors write nothing, so their directories are not checked.
+//
+// Optional integrations only count towards readiness when they are enabled,
+// so a server without a broker is ready as soon as it serves requests.
+// Maintenance mode does not make the server unready, as reads are still
+// served, but is reported so deployments can see it. A read-only mirror is
+// unready while its replicated storage lags more than REPLICA_MAX_LAG_SECS,
+// and a supervising server while its prover process is not connected.
+
+use axum::{extract::State, http::StatusCode, response::Json};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::{
+    AppState, circuit::ZKHOTDOG, maintenance::MaintenanceState, replica::ReplicaState,
+    supervisor::ProverState, toolchain::Toolchain,
+};
+
+// How long the outcome of a toolchain probe is reused
+const TOOLCHAIN_CHECK_TTL: Duration = Duration::from_secs(60);
+
+// The last toolchain probe made for /health
+#[derive(Default)]
+pub struct ToolchainCheck {
+    last: Mutex<Option<(Instant, Toolchain)>>,
+}
+
+impl ToolchainCheck {
+    // The toolchain as probed at most TOOLCHAIN_CHECK_TTL ago; concurrent
+    // requests wait for the same probe
+    async fn get(&self) -> Toolchain {
+        let mut last = self.last.lock().await;
+        if let Some((at, toolchain)) = last.as_ref()
+            && at.elapsed() < TOOLCHAIN_CHECK_TTL
+        {
+            return toolchain.clone();
+        }
+        let toolchain = Toolchain::detect().await;
+        *last = Some((Instant::now(), toolchain.clone()));
+        toolchain
+    }
+}
+
+#[derive(Serialize)]
+pub struct Health {
+    healthy: bool,
+    // Outcome of each check: "ok" or the reason it failed
+    checks: BTreeMap<&'static str, String>,
+    // Names of the failing checks
+    failing: Vec<&'static str>,
+    // Number of measurements in each proof status
+    measurements: BTreeMap<String, usize>,
+}
+
+// Why a directory cannot be written to, if it cannot
+async fn writable(dir: &str) -> Result<(), String> {
+    let path = format!("{}/.health-{}", dir, Uuid::new_v4());
+    tokio::fs::write(&path, b"").await.map_err(|e| format!("cannot write to {}/: {}", dir, e))?;
+    tokio::fs::remove_file(&path).await.map_err(|e| format!("cannot remove {}: {}", path, e))
+}
+
+// Handler reporting whether the proving environment is complete
+pub async fn health(State(state): State<Arc<AppState>>) -> (StatusCode, Json<Health>) {
+    let mut checks = BTreeMap::new();
+    let ok = |outcome: Result<(), String>| outcome.map_or_else(|e| e, |()| "ok".to_string());
+
+    if state.replica.is_none() {
+        checks.insert("uploads_writable", ok(writable("uploads").await));
+        checks.insert("proofs_writable", ok(writable("proofs").await));
+    }
+    let installed = |path: &str| match Path::new(path).exists() {
+        true => "ok".to_string(),
+        false => format!("{} is missing", path),
+    };
+    checks.insert("circuit_wasm", installed(ZKHOTDOG.wasm_path));
+    checks.insert("proving_key", installed(ZKHOTDOG.zkey_path));
+    let toolchain = state.toolchain_check.get().await;
+    let answered = |version: Option<String>, command: &str| match version {
+        Some(_) => "ok".to_string(),
+        None => format!("{} gives no version", command),
+    };
+    checks.insert("node", answered(toolchain.node, "node --version"));
+    checks.insert("snarkjs", answered(toolchain.snarkjs, "npx snarkjs --version"));
+
+    let mut measurements = BTreeMap::new();
+    for m in state.measurements.lock().values() {
+        *measurements.entry(format!("{:?}", m.status)).or_insert(0) += 1;
+    }
+
+    let failing: Vec<&'static str> =
+        checks.iter().filter(|(_, outcome)| *outcome != "ok").map(|(name, _)| *name).collect();
+    let healthy = failing.is_empty();
+    let status = if healthy { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(Health { healthy, checks, failing, measurements }))
+}
+
+#[derive(Serialize)]
+pub struct Readiness {
+    ready: bool,
+    // Outcome of each enabled check: "ok" or the reason it failed
+    checks: BTreeMap<&'static str, String>,
+    // Proving toolchain currently in use, for information only
+    toolchain: Toolchain,
+    // "normal", "maintenance" while changes are refused, or "read_only" on a mirror
+    mode: &'static str,
+    maintenance: MaintenanceState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    replica: Option<ReplicaState>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prover_process: Option<ProverState>,
+}
+
+// Outcome of each enabled integration check: "ok" or the reason it failed
+pub async fn checks(state: &AppState) -> BTreeMap<&'static str, String> {
+    let mut checks = BTreeMap::new();
+
+    if let Some(broker) = &state.broker {
+        let outcome = broker.check().await.map(|()| "ok".to_string());
+        checks.insert("broker", outcome.unwrap_or_else(|e| e));
+    }
+    if let Some(replica) = &state.replica {
+        checks.insert("replication", replica.check().map_or_else(|e| e, |()| "ok".to_string()));
+    }
+    if let Some(process) = &state.prover_process {
+        checks.insert("prover_process", process.check().map_or_else(|e| e, |()| "ok".to_string()));
+    }
+    checks
+}
+
+// Handler reporting whether the server can do its work
+pub async fn ready(State(state): State<Arc<AppState>>) -> (StatusCode, Json<Readiness>) {
+    let checks = checks(&state).await;
+    let ready = checks.values().all(|outcome| outcome == "ok");
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    let maintenance = state.maintenance.get();
+    let mode = match (&state.replica, maintenance.enabled) {
+        (Some(_), _) => "read_only",
+        (None, true) => "maintenance",
+        (None, false) => "normal",
+    };
+    (
+        status,
+        Json(Readiness {
+            ready,
+            checks,
+            toolchain: state.toolchain.get(),
+            mode,
+            maintenance,
+            replica: state.replica.as_ref().map(|replica| replica.state()),
+            prover_process: state.prover_process.as_ref().map(|process| process.state()),
+        }),
+    )
+}
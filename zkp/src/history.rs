@@ -0,0 +1,163 @@
+// Status history of measurements.
+//
+// Every status change is appended to the measurement's record as a
+// transition: the status it left and the one it entered, when, what made the
+// change, such as worker-2 or the deadline watchdog, and a generation counting
+// the measurement's transitions from its creation. Changes made through a
+// StoreGuard are recorded as it is released, for the source the lock was taken
+// with (Measurements::lock_as), and a worker's claim by
+// Measurements::transition, so the history is written with the status by the
+// same writer, survives restarts and moves with exports and support bundles.
+//
+// GET /measurements/{id}/history answers the transitions oldest first, each
+// with how long the measurement stayed in the status it entered. /status/{id}
+// leaves the history out. A record keeps its last MAX_TRANSITIONS transitions,
+// far more than a measurement goes through; generations keep counting when
+// older ones are dropped, so a history not starting at generation 1 was
+// truncated.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{fmt, sync::Arc};
+
+use crate::{
+    AppState, Measurement, ProofStatus, access,
+    auth::{Authorized, Operator},
+};
+
+pub const MAX_TRANSITIONS: usize = 1000;
+
+// What changed the status of a measurement
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Submission,
+    // An offline bundle imported through /measurements/import
+    Bundle,
+    Reproof,
+    // Sent through the pipeline again by POST /measurements/{id}/retry
+    Retry,
+    // Withdrawn by POST /measurements/{id}/cancel
+    Cancellation,
+    // Rebuilt from its files at startup
+    Reconcile,
+    // Released into the queue when an intake window opened
+    Intake,
+    // A proof worker, numbered from 1
+    Worker(usize),
+    // The deadline watchdog
+    Watchdog,
+    AttestationPoller,
+    // Startup recovery of the work a previous process left behind
+    Recovery,
+    // Anything not taking its lock for a particular source
+    Server,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Submission => write!(f, "submission"),
+            Source::Bundle => write!(f, "bundle"),
+            Source::Reproof => write!(f, "reproof"),
+            Source::Retry => write!(f, "retry"),
+            Source::Cancellation => write!(f, "cancellation"),
+            Source::Reconcile => write!(f, "reconcile"),
+            Source::Intake => write!(f, "intake"),
+            Source::Worker(n) => write!(f, "worker-{}", n),
+            Source::Watchdog => write!(f, "watchdog"),
+            Source::AttestationPoller => write!(f, "attestation-poller"),
+            Source::Recovery => write!(f, "recovery"),
+            Source::Server => write!(f, "server"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Transition {
+    // Number of the transition, 1 being the measurement's creation
+    pub generation: u64,
+    // None for the status the measurement was created with
+    #[serde(default)]
+    pub from: Option<ProofStatus>,
+    pub to: ProofStatus,
+    pub at: DateTime<Utc>,
+    pub source: String,
+}
+
+// Append the measurement's current status to its history, as entered at `at`
+// from `from`
+pub fn record(m: &mut Measurement, from: Option<ProofStatus>, at: DateTime<Utc>, source: Source) {
+    let generation = m.history.last().map_or(0, |t| t.generation) + 1;
+    let to = m.status.clone();
+    m.history.push(Transition { generation, from, to, at, source: source.to_string() });
+    if m.history.len() > MAX_TRANSITIONS {
+        let excess = m.history.len() - MAX_TRANSITIONS;
+        m.history.drain(..excess);
+    }
+}
+
+#[derive(Serialize)]
+pub struct HistoryEntry {
+    #[serde(flatten)]
+    transition: Transition,
+    // Seconds spent in the status entered, until the next transition or, for
+    // a status still to change, until now; absent once the measurement is
+    // Completed, Failed or Cancelled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seconds_in_status: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct History {
+    measurement_id: String,
+    status: ProofStatus,
+    // Whether older transitions were dropped past MAX_TRANSITIONS
+    truncated: bool,
+    transitions: Vec<HistoryEntry>,
+}
+
+// The history of a measurement with the time spent in each status
+pub fn describe(m: &Measurement, now: DateTime<Utc>) -> History {
+    let last_ends = match m.status {
+        ProofStatus::Completed | ProofStatus::Failed | ProofStatus::Cancelled => None,
+        _ => Some(now),
+    };
+    let transitions = m
+        .history
+        .iter()
+        .enumerate()
+        .map(|(i, t)| {
+            let ends = m.history.get(i + 1).map(|next| next.at).or(last_ends);
+            HistoryEntry {
+                transition: t.clone(),
+                seconds_in_status: ends.map(|e| (e - t.at).num_milliseconds() as f64 / 1000.0),
+            }
+        })
+        .collect();
+    History {
+        measurement_id: m.id.clone(),
+        status: m.status.clone(),
+        truncated: m.history.first().is_some_and(|t| t.generation > 1),
+        transitions,
+    }
+}
+
+// Handler answering the status transitions of a measurement
+pub async fn history(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Operator>,
+    Path(id): Path<String>,
+) -> Result<Json<History>, (StatusCode, String)> {
+    // Read the stored measurement, with the transitions other instances made
+    state.measurements.flushed().await;
+    let stored = state.measurements.store().get(&id).await.map_err(|e| {
+        (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read measurement {}: {}", id, e))
+    })?;
+    let m = access::visible(stored.as_ref(), &caller, &id)?;
+    Ok(Json(describe(m, Utc::now())))
+}
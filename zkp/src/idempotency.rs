@@ -0,0 +1,90 @@
+// Idempotency keys of submissions.
+//
+// Mobile clients retry a submission when the network drops, and every retry
+// used to become a measurement of its own with a proof of its own. A
+// submission may carry an Idempotency-Key header or an idempotencyKey form
+// field, the field winning; a later submission by the same owner with the
+// same key is answered with the measurement the key was accepted for, as the
+// first submission was, and nothing is stored or proved again. The same key
+// with other points or another coordinate system is a client bug and is
+// rejected with 409.
+//
+// The owner is the API key submitting (see access::submitter), so a client
+// whose retry leaves from another address, as a phone moving from Wi-Fi to
+// cellular does, still gets its measurement, and clients sharing an address
+// behind a NAT never answer each other's keys. Only anonymous callers are
+// told apart by their address.
+//
+// The key is stored with its measurement and released when the measurement
+// is deleted, by hand or through retention. Like the external id index, the
+// index is only modified while the measurement map is locked.
+
+use axum::{http::StatusCode, response::Json};
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::{
+    AppState, Measurement, MeasurementResponse,
+    coords::{CoordinateSystem, OriginalPoints},
+};
+
+pub const MAX_KEY_CHARS: usize = 255;
+
+// Measurement ids keyed by owner and idempotency key
+#[derive(Default)]
+pub struct IdempotencyIndex {
+    keys: Mutex<HashMap<(String, String), String>>,
+}
+
+impl IdempotencyIndex {
+    pub fn get(&self, owner: &str, key: &str) -> Option<String> {
+        self.keys.lock().unwrap().get(&(owner.to_string(), key.to_string())).cloned()
+    }
+
+    pub fn insert(&self, owner: &str, key: &str, id: &str) {
+        self.keys.lock().unwrap().insert((owner.to_string(), key.to_string()), id.to_string());
+    }
+
+    pub fn remove(&self, owner: &str, key: &str) {
+        self.keys.lock().unwrap().remove(&(owner.to_string(), key.to_string()));
+    }
+}
+
+// Check an idempotency key supplied by a client
+pub fn validate(value: &str) -> Result<String, String> {
+    let value = value.trim();
+    if value.is_empty() || value.chars().count() > MAX_KEY_CHARS {
+        return Err(format!("Idempotency key must be 1 to {} characters long", MAX_KEY_CHARS));
+    }
+    if !value.chars().all(|c| c.is_ascii_graphic()) {
+        return Err("Idempotency key may only contain printable ASCII characters".to_string());
+    }
+    Ok(value.to_string())
+}
+
+// Answer a submission repeating the key of an accepted measurement
+pub fn replay(
+    state: &AppState,
+    existing: &Measurement,
+    key: &str,
+    original_points: Option<&OriginalPoints>,
+    coordinate_system: CoordinateSystem,
+) -> Result<(StatusCode, Json<MeasurementResponse>), (StatusCode, String)> {
+    if existing.original_points.as_ref() != original_points
+        || existing.coordinate_system != coordinate_system
+    {
+        return Err((
+            StatusCode::CONFLICT,
+            format!(
+                "Idempotency key {} was already used for measurement {} with other points",
+                key, existing.id
+            ),
+        ));
+    }
+    println!("Submission with idempotency key {} answered with measurement {}", key, existing.id);
+    let response = MeasurementResponse::new(
+        &state.config.public_base_url,
+        existing.id.clone(),
+        existing.external_id.clone(),
+    );
+    Ok((StatusCode::OK, Json(response)))
+}
@@ -0,0 +1,280 @@
+// Images fetched from a URL instead of uploaded.
+//
+// A submission may send imageUrl instead of image, for clients whose photo is
+// already hosted somewhere, such as an S3 presigned URL. The image is fetched
+// once every other field has been validated, so a submission refused anyway
+// costs no download, and is then stored like an uploaded one. A fetch is
+// bounded by IMAGE_URL_TIMEOUT_SECS for the whole request and by
+// IMAGE_URL_MAX_BYTES for the body, follows at most MAX_REDIRECTS redirects
+// and must answer with an image/* Content-Type; anything else refuses the
+// submission with 422, naming the status the server answered with.
+//
+// The URL is fetched from inside the deployment, so it must not reach what
+// only the server can: every host the fetch connects to, redirect targets
+// included, is resolved first and refused with 400 when any of its addresses
+// is loopback, private, link-local or otherwise not public. The connection
+// uses the addresses checked, so a name cannot resolve differently in
+// between. IMAGE_URL_ALLOW_PRIVATE lifts this for local development; the
+// production audit refuses it (safety.rs). Webhook deliveries connect through
+// the same resolver (webhooks.rs).
+
+use axum::{body::Bytes, http::StatusCode};
+use std::{
+    io::{self, Read},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::config::Config;
+
+// Longest imageUrl a submission may send
+const MAX_URL_LENGTH: usize = 2048;
+// Redirects followed before the fetch is given up
+const MAX_REDIRECTS: u32 = 2;
+
+// Why an image could not be fetched
+#[derive(Debug)]
+pub enum FetchError {
+    // A host of the fetch resolves to an address the server must not reach
+    Private(IpAddr),
+    // The server answered with a status other than 2xx
+    Status(u16),
+    NotAnImage(String),
+    TooLarge { limit: usize },
+    Failed(String),
+}
+
+impl FetchError {
+    pub fn into_response(self) -> (StatusCode, String) {
+        match self {
+            FetchError::Private(ip) => (
+                StatusCode::BAD_REQUEST,
+                format!("imageUrl is not accepted: it resolves to the non-public address {}", ip),
+            ),
+            FetchError::Status(status) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Failed to fetch imageUrl: the server answered {}", status),
+            ),
+            FetchError::NotAnImage(content_type) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!(
+                    "Failed to fetch imageUrl: its Content-Type {} is not an image",
+                    content_type
+                ),
+            ),
+            FetchError::TooLarge { limit } => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Failed to fetch imageUrl: the image is larger than {} bytes", limit),
+            ),
+            FetchError::Failed(e) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, format!("Failed to fetch imageUrl: {}", e))
+            }
+        }
+    }
+}
+
+// The imageUrl of a submission
+pub fn validate(value: &str) -> Result<String, String> {
+    validate_url("imageUrl", value)
+}
+
+// An http(s) URL a submission sends in the field named
+pub fn validate_url(field: &str, value: &str) -> Result<String, String> {
+    let url = value.trim();
+    if url.len() > MAX_URL_LENGTH {
+        return Err(format!("{} must be at most {} characters", field, MAX_URL_LENGTH));
+    }
+    let host = url.strip_prefix("https://").or_else(|| url.strip_prefix("http://"));
+    if host.is_none_or(|host| host.is_empty() || host.starts_with('/')) {
+        return Err(format!("{} must be an http:// or https:// URL", field));
+    }
+    if url.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(format!("{} must not contain whitespace or control characters", field));
+    }
+    Ok(url.to_string())
+}
+
+// An agent connecting only to public addresses unless allow_private, and the
+// address it refused last, to tell a refusal from other failures
+pub fn public_agent(allow_private: bool) -> (ureq::AgentBuilder, Arc<Mutex<Option<IpAddr>>>) {
+    let refused: Arc<Mutex<Option<IpAddr>>> = Arc::default();
+    let resolver = {
+        let refused = refused.clone();
+        move |netloc: &str| -> io::Result<Vec<SocketAddr>> {
+            let addrs: Vec<SocketAddr> = netloc.to_socket_addrs()?.collect();
+            if let Some(addr) = addrs.iter().find(|addr| !allow_private && !is_public(addr.ip())) {
+                *refused.lock().unwrap() = Some(addr.ip());
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, "non-public address"));
+            }
+            Ok(addrs)
+        }
+    };
+    (ureq::AgentBuilder::new().resolver(resolver), refused)
+}
+
+// Fetch the image at a URL
+pub async fn fetch(config: &Config, url: String) -> Result<Bytes, FetchError> {
+    let timeout = Duration::from_secs(config.image_url_timeout_secs);
+    let (limit, allow_private) = (config.image_url_max_bytes, config.image_url_allow_private);
+    let request = tokio::task::spawn_blocking(move || get(&url, timeout, limit, allow_private));
+    request.await.map_err(|e| FetchError::Failed(e.to_string()))?
+}
+
+fn get(
+    url: &str,
+    timeout: Duration,
+    limit: usize,
+    allow_private: bool,
+) -> Result<Bytes, FetchError> {
+    let (agent, refused) = public_agent(allow_private);
+    // ureq counts the first response against its limit
+    let agent = agent.timeout(timeout).redirects(MAX_REDIRECTS + 1).build();
+
+    let response = match agent.get(url).call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(status, _)) => return Err(FetchError::Status(status)),
+        Err(e) => {
+            return Err(match *refused.lock().unwrap() {
+                Some(ip) => FetchError::Private(ip),
+                None => FetchError::Failed(e.to_string()),
+            });
+        }
+    };
+    // A redirect without a Location is answered as is
+    if !(200..300).contains(&response.status()) {
+        return Err(FetchError::Status(response.status()));
+    }
+    let content_type = response.content_type().to_string();
+    if !content_type.starts_with("image/") {
+        return Err(FetchError::NotAnImage(content_type));
+    }
+    let declared = response.header("content-length").and_then(|v| v.trim().parse::<u64>().ok());
+    if declared.is_some_and(|length| length > limit as u64) {
+        return Err(FetchError::TooLarge { limit });
+    }
+
+    // Read one byte past the limit to tell a body of exactly the limit apart
+    let mut data = Vec::new();
+    response
+        .into_reader()
+        .take(limit as u64 + 1)
+        .read_to_end(&mut data)
+        .map_err(|e| FetchError::Failed(e.to_string()))?;
+    if data.len() > limit {
+        return Err(FetchError::TooLarge { limit });
+    }
+    Ok(Bytes::from(data))
+}
+
+// Whether an address is reachable from the internet at large
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(v4) => is_public_v4(v4),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // 0.0.0.0/8, carrier-grade NAT 100.64.0.0/10 and reserved 240.0.0.0/4
+        || a == 0
+        || (a == 100 && (64..128).contains(&b))
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    let [first, second, ..] = segments;
+    let [_, _, c, d, e, f, .., w, x, y, z] = ip.octets();
+    // NAT64 64:ff9b::/96 and IPv4-compatible ::/96 carry an IPv4 address in
+    // their last 32 bits, 6to4 2002::/16 in bits 16..48. :: and ::1 fall in
+    // ::/96 too and embed 0.0.0.0/8, which is refused.
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] || segments[..6] == [0; 6] {
+        return is_public_v4(Ipv4Addr::new(w, x, y, z));
+    }
+    if first == 0x2002 {
+        return is_public_v4(Ipv4Addr::new(c, d, e, f));
+    }
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Unique local fc00::/7 and link-local fe80::/10
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80
+        // Documentation 2001:db8::/32 and discard-only 100::/64
+        || (first == 0x2001 && second == 0x0db8)
+        || segments[..4] == [0x100, 0, 0, 0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls_must_be_http_without_whitespace() {
+        assert_eq!(
+            validate_url("callbackUrl", " https://example.com/hook "),
+            Ok("https://example.com/hook".to_string())
+        );
+        assert!(validate("ftp://example.com/image.jpg").unwrap_err().starts_with("imageUrl "));
+        assert!(
+            validate_url("callbackUrl", "http:///hook").unwrap_err().starts_with("callbackUrl ")
+        );
+        assert!(validate_url("callbackUrl", "https://example.com/a b").is_err());
+        let long = format!("https://example.com/{}", "a".repeat(MAX_URL_LENGTH));
+        assert!(validate_url("callbackUrl", &long).is_err());
+    }
+
+    #[test]
+    fn internal_addresses_are_not_public() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+            "::7f00:1",
+            "::10.0.0.1",
+            "2002:c0a8:101::1",
+            "2002:7f00:1::",
+            "2001:db8::1",
+            "100::1",
+            "100::ffff:ffff:ffff:ffff",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{} is public", ip);
+        }
+        for ip in ["93.184.216.34", "2606:4700::1111", "64:ff9b::5db8:d822", "2002:5db8:d822::1"] {
+            assert!(is_public(ip.parse().unwrap()), "{} is not public", ip);
+        }
+    }
+
+    #[test]
+    fn agent_refuses_loopback_unless_allowed() {
+        let (agent, refused) = public_agent(false);
+        let result = agent.build().get("http://127.0.0.1:9/").call();
+        assert!(result.is_err());
+        assert_eq!(*refused.lock().unwrap(), Some(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+
+        let (agent, refused) = public_agent(true);
+        let _ = agent.build().get("http://127.0.0.1:9/").call();
+        assert_eq!(*refused.lock().unwrap(), None);
+    }
+}
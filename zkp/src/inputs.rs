@@ -0,0 +1,350 @@
+// Circuit inputs derived from a measurement.
+//
+// input.json is always re-derived from the authoritative measurement record
+// rather than trusted from disk: a file left behind by an earlier run, for
+// example one written with a different scaling factor, would otherwise make
+// every retry fail the same way. When an existing file disagrees with the
+// derived inputs the difference is logged and written to the audit log before
+// the file is overwritten, or the proof fails when STRICT_INPUTS is set.
+//
+// The inputs are built from the spec of the circuit (see circuit.rs), and
+// every input is checked against the width it declares for the signal, both
+// when a measurement is submitted and when input.json is written,
+// so a value the circuit cannot handle is refused up front instead of wasting
+// minutes on an unprovable witness or a proof of a wrapped-around number.
+//
+// A circuit taking a points input proves a polyline (polyline.rs): the points
+// are padded to its capacity by repeating the last one, and it is given the
+// number of segments and the squared length of each, 0 past the last. A
+// circuit taking vector1 proves an angle (angle.rs): it takes exactly three
+// points, the start, mid and end point, as the vectors from the mid point to
+// the others, and the numerator and squared denominator of the cosine of the
+// angle between them. A circuit taking min_distance_squared proves a minimum
+// length (threshold.rs): it takes exactly two points as point1 and point2,
+// which must be at least the minimum apart, and the minimum scaled and
+// squared. Any other circuit takes exactly two points as point1 and point2
+// and their squared distance.
+
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+use std::{fmt, fs};
+
+use crate::{
+    AppState, Measurement, Point3D,
+    artifacts::Artifact,
+    circuit::{self, Circuit},
+    coords,
+    pipeline::{ArtifactProblem, PipelineError, Stage},
+    threshold,
+};
+
+// An input outside the range its circuit declares for the signal
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputViolation {
+    OutOfRange { signal: String, value: i128, bits: u32, signed: bool },
+    // The circuit declares no signal the server produces
+    Undeclared { signal: String },
+    // The server produces no value for a signal the circuit declares
+    Missing { signal: String },
+    // The server produces a different number of elements than the circuit declares
+    Length { signal: String, expected: usize, found: usize },
+    // The measurement has more or fewer points than the circuit takes
+    PointCount { found: usize, min: usize, max: usize },
+    // The points are closer together than the minimum length they are to prove
+    BelowMinimum { min_length_cm: f64 },
+}
+
+impl fmt::Display for InputViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputViolation::OutOfRange { signal, value, bits, signed: true } => write!(
+                f,
+                "{} is {}, outside the circuit's limit of +/-(2^{} - 1)",
+                signal, value, bits
+            ),
+            InputViolation::OutOfRange { signal, value, bits, signed: false } => write!(
+                f,
+                "{} is {}, outside the circuit's limit of 0 to 2^{} - 1",
+                signal, value, bits
+            ),
+            InputViolation::Undeclared { signal } => {
+                write!(f, "the circuit declares no signal {}", signal)
+            }
+            InputViolation::Missing { signal } => {
+                write!(f, "no value is derived for the circuit's signal {}", signal)
+            }
+            InputViolation::Length { signal, expected, found } => {
+                write!(f, "{} has {} elements, the circuit declares {}", signal, found, expected)
+            }
+            InputViolation::PointCount { found, min, max } if min == max => {
+                write!(f, "the measurement has {} points, the circuit takes {}", found, min)
+            }
+            InputViolation::PointCount { found, min, max } => {
+                write!(
+                    f,
+                    "the measurement has {} points, the circuit takes {} to {}",
+                    found, min, max
+                )
+            }
+            InputViolation::BelowMinimum { min_length_cm } => {
+                write!(f, "the points are less than the minimum of {} cm apart", min_length_cm)
+            }
+        }
+    }
+}
+
+// One input whose value on disk differs from the derived one
+#[derive(Debug, Serialize)]
+struct InputDifference {
+    field: String,
+    on_disk: Option<Value>,
+    derived: Option<Value>,
+}
+
+// Values of the circuit's input signals for the scaled points of a
+// measurement and the minimum length it claims, in order, before they are
+// encoded
+pub fn values(
+    points: &[Point3D],
+    min_length_cm: Option<f64>,
+    circuit: &Circuit,
+) -> Result<Vec<(&'static str, Vec<i128>)>, InputViolation> {
+    let components = |point: &Point3D| coords::components(point).map(i128::from);
+    if circuit.spec.input("min_distance_squared").is_some() {
+        let [start, end] = points else {
+            return Err(InputViolation::PointCount { found: points.len(), min: 2, max: 2 });
+        };
+        let Some(min_length_cm) = min_length_cm else {
+            return Err(InputViolation::Missing { signal: "min_distance_squared".to_string() });
+        };
+        let minimum = threshold::min_distance_squared(min_length_cm);
+        if i128::from(coords::distance_squared(start, end)) < minimum {
+            return Err(InputViolation::BelowMinimum { min_length_cm });
+        }
+        return Ok(vec![
+            ("point1", components(start).to_vec()),
+            ("point2", components(end).to_vec()),
+            ("min_distance_squared", vec![minimum]),
+        ]);
+    }
+    if circuit.spec.input("vector1").is_some() {
+        let [start, mid, end] = points else {
+            return Err(InputViolation::PointCount { found: points.len(), min: 3, max: 3 });
+        };
+        let vector = |to: &Point3D| {
+            let (to, from) = (components(to), components(mid));
+            [to[0] - from[0], to[1] - from[1], to[2] - from[2]]
+        };
+        let (vector1, vector2) = (vector(start), vector(end));
+        let dot = |a: &[i128; 3], b: &[i128; 3]| a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+        // Saturate rather than overflow for points outside the circuit's
+        // range, so the width check refuses them
+        let denominator_squared = dot(&vector1, &vector1).saturating_mul(dot(&vector2, &vector2));
+        return Ok(vec![
+            ("vector1", vector1.to_vec()),
+            ("vector2", vector2.to_vec()),
+            ("cos_numerator", vec![dot(&vector1, &vector2)]),
+            ("cos_denominator_squared", vec![denominator_squared]),
+        ]);
+    }
+
+    // Calculate the distances based on the already scaled integer coordinates
+    let distances = coords::segment_distances_squared(points);
+    let capacity = match circuit.spec.input("points") {
+        Some(signal) => signal.elements() / 3,
+        None => 2,
+    };
+    if points.len() < 2 || points.len() > capacity {
+        return Err(InputViolation::PointCount { found: points.len(), min: 2, max: capacity });
+    }
+    let last = &points[points.len() - 1];
+
+    if capacity == 2 {
+        return Ok(vec![
+            ("point1", components(&points[0]).to_vec()),
+            ("point2", components(last).to_vec()),
+            ("distance_squared", vec![distances[0] as i128]),
+        ]);
+    }
+    let padded = points.iter().chain(std::iter::repeat(last)).take(capacity);
+    let segments = distances.iter().map(|d| *d as i128).chain(std::iter::repeat(0));
+    Ok(vec![
+        ("points", padded.flat_map(components).collect()),
+        ("segment_count", vec![distances.len() as i128]),
+        ("segment_distances_squared", segments.take(capacity - 1).collect()),
+    ])
+}
+
+// Inputs of a circuit for the scaled points of a measurement and the minimum
+// length it claims, encoded and checked as the circuit's spec declares
+pub fn construct(
+    points: &[Point3D],
+    min_length_cm: Option<f64>,
+    circuit: &Circuit,
+) -> Result<Value, InputViolation> {
+    let values = values(points, min_length_cm, circuit)?;
+    let values: Vec<(&str, &[i128])> =
+        values.iter().map(|(name, elements)| (*name, elements.as_slice())).collect();
+    circuit.spec.encode_inputs(&values)
+}
+
+// Inputs of the circuit a measurement is proved with
+pub fn derive(measurement: &Measurement) -> Result<Value, InputViolation> {
+    construct(
+        &measurement.path(),
+        measurement.min_length_cm,
+        &circuit::resolve(measurement.circuit.as_deref()),
+    )
+}
+
+// Exact bytes of input.json for derived inputs. These determine what gets
+// proved, so any change must be deliberate; test_inputs.sh pins them.
+pub fn render(inputs: &Value) -> serde_json::Result<String> {
+    serde_json::to_string_pretty(inputs)
+}
+
+// Write input.json for a measurement, reconciling it with any existing file
+pub fn write(state: &AppState, measurement: &Measurement) -> Result<(), PipelineError> {
+    let id = &measurement.id;
+    let path = Artifact::Input.path(id);
+    let derived = derive(measurement).map_err(|violation| PipelineError::InvalidArtifact {
+        artifact: Artifact::Input,
+        problem: ArtifactProblem::OutOfRange(violation),
+    })?;
+
+    if let Ok(content) = fs::read_to_string(&path) {
+        let on_disk = serde_json::from_str(&content).unwrap_or(Value::String(content));
+        let differences = diff(&on_disk, &derived);
+        if !differences.is_empty() {
+            println!(
+                "Warning: input.json of measurement {} differs from the measurement: {}",
+                id,
+                json!(differences)
+            );
+            state.audit.record(
+                "proof_inputs_regenerated",
+                json!({
+                    "id": id,
+                    "circuit_version": state.versions.circuit_version,
+                    "strict": state.config.strict_inputs,
+                    "differences": differences,
+                }),
+            );
+            if state.config.strict_inputs {
+                return Err(PipelineError::InvalidArtifact {
+                    artifact: Artifact::Input,
+                    problem: ArtifactProblem::DiffersFromMeasurement {
+                        fields: differences.into_iter().map(|d| d.field).collect(),
+                    },
+                });
+            }
+        }
+    }
+
+    let content = render(&derived).map_err(|e| PipelineError::InvalidArtifact {
+        artifact: Artifact::Input,
+        problem: ArtifactProblem::Malformed(e),
+    })?;
+    fs::write(&path, content).map_err(|source| PipelineError::Io { stage: Stage::Inputs, source })
+}
+
+// Top-level inputs that differ between two input documents
+fn diff(on_disk: &Value, derived: &Value) -> Vec<InputDifference> {
+    let empty = Map::new();
+    let on_disk_fields = on_disk.as_object();
+    let derived_fields = derived.as_object().unwrap_or(&empty);
+
+    // Anything that isn't an object can't be compared field by field
+    let Some(on_disk_fields) = on_disk_fields else {
+        return vec![InputDifference {
+            field: "(file)".to_string(),
+            on_disk: Some(on_disk.clone()),
+            derived: Some(derived.clone()),
+        }];
+    };
+
+    let mut fields: Vec<&String> = on_disk_fields.keys().chain(derived_fields.keys()).collect();
+    fields.sort();
+    fields.dedup();
+    fields
+        .into_iter()
+        .filter(|field| on_disk_fields.get(*field) != derived_fields.get(*field))
+        .map(|field| InputDifference {
+            field: field.clone(),
+            on_disk: on_disk_fields.get(field).cloned(),
+            derived: derived_fields.get(field).cloned(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::{CIRCUITS, Encoding};
+    use proptest::prelude::*;
+
+    // Scaled coordinates, mostly within the circuits' range and some well past it
+    fn coordinate() -> impl Strategy<Value = i64> {
+        prop_oneof![
+            3 => -coords::MAX_SCALED_MAGNITUDE..=coords::MAX_SCALED_MAGNITUDE,
+            1 => -(1i64 << 40)..=(1i64 << 40),
+        ]
+    }
+
+    fn point() -> impl Strategy<Value = Point3D> {
+        (coordinate(), coordinate(), coordinate()).prop_map(|(x, y, z)| Point3D {
+            x: x as f64,
+            y: y as f64,
+            z: z as f64,
+        })
+    }
+
+    // Value of an element of input.json as the circuit reads it
+    fn decoded(encoding: Encoding, element: &Value) -> i128 {
+        let element = element.as_str().unwrap();
+        match encoding {
+            Encoding::Signed => coords::from_field(element).unwrap().into(),
+            Encoding::Unsigned => element.parse::<u128>().unwrap().try_into().unwrap(),
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn accepted_inputs_fit_their_declared_widths(
+            circuit in 0..CIRCUITS.len(),
+            points in prop::collection::vec(point(), 2..=18),
+            min_length_cm in prop::option::of(0.0..1e6f64),
+        ) {
+            let circuit = CIRCUITS[circuit];
+            let Ok(inputs) = construct(&points, min_length_cm, &circuit) else {
+                return Ok(());
+            };
+            for signal in circuit.spec.inputs {
+                let elements = match &inputs[signal.name] {
+                    Value::Array(elements) => elements.clone(),
+                    element => vec![element.clone()],
+                };
+                prop_assert_eq!(elements.len(), signal.elements());
+                for element in &elements {
+                    let value = decoded(signal.encoding, element);
+                    prop_assert!(
+                        value.unsigned_abs() < 1u128 << signal.bits,
+                        "{} = {} exceeds {} bits", signal.name, value, signal.bits
+                    );
+                    prop_assert!(signal.encoding == Encoding::Signed || value >= 0);
+                }
+            }
+        }
+
+        #[test]
+        fn points_within_the_scaled_range_are_accepted(
+            coordinates in prop::array::uniform6(
+                -coords::MAX_SCALED_MAGNITUDE..=coords::MAX_SCALED_MAGNITUDE
+            ),
+        ) {
+            let [x1, y1, z1, x2, y2, z2] = coordinates.map(|c| c as f64);
+            let points = [Point3D { x: x1, y: y1, z: z1 }, Point3D { x: x2, y: y2, z: z2 }];
+            prop_assert!(construct(&points, None, &circuit::ZKHOTDOG).is_ok());
+        }
+    }
+}
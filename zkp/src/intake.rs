@@ -0,0 +1,197 @@
+// Intake windows restricting when new measurements are accepted.
+//
+// INTAKE_WINDOWS lists the weekly hours during which submissions are taken,
+// in INTAKE_TIMEZONE, e.g. "mon-fri 08:00-18:00; sat 10:00-14:00". A window
+// whose end is not after its start runs past midnight. Outside the windows
+// submissions are either rejected with 503 and the next opening time, or with
+// INTAKE_OUTSIDE=schedule accepted as Scheduled and released to the proof
+// queue once a window opens. Without INTAKE_WINDOWS intake is always open.
+
+use chrono::{DateTime, Datelike, Days, NaiveTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::Serialize;
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+use crate::{AppState, ProofStatus, events::EventKind, history::Source};
+
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+// What happens to submissions outside the intake windows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutsidePolicy {
+    Reject,
+    Schedule,
+}
+
+impl FromStr for OutsidePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "reject" => Ok(OutsidePolicy::Reject),
+            "schedule" => Ok(OutsidePolicy::Schedule),
+            other => Err(format!("unknown policy '{}', expected reject or schedule", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Window {
+    // Days the window opens on, indexed from Monday
+    days: [bool; 7],
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl Window {
+    fn runs_past_midnight(&self) -> bool {
+        self.end <= self.start
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IntakeSchedule {
+    windows: Vec<Window>,
+    timezone: Tz,
+}
+
+// Current intake state, for the admin stats endpoint
+#[derive(Serialize)]
+pub struct IntakeState {
+    open: bool,
+    outside: OutsidePolicy,
+    timezone: String,
+    // When intake next opens or closes
+    next_transition: Option<DateTime<Utc>>,
+}
+
+impl IntakeSchedule {
+    // Parse INTAKE_WINDOWS; an empty value means intake is always open
+    pub fn parse(windows: &str, timezone: &str) -> Result<Option<Self>, String> {
+        let timezone: Tz =
+            timezone.parse().map_err(|_| format!("unknown timezone '{}'", timezone))?;
+        let windows = windows
+            .split(';')
+            .map(str::trim)
+            .filter(|w| !w.is_empty())
+            .map(parse_window)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok((!windows.is_empty()).then_some(IntakeSchedule { windows, timezone }))
+    }
+
+    pub fn is_open(&self, at: DateTime<Utc>) -> bool {
+        let local = at.with_timezone(&self.timezone);
+        let today = local.weekday().num_days_from_monday() as usize;
+        let yesterday = (today + 6) % 7;
+        let time = local.time();
+        self.windows.iter().any(|w| {
+            if w.runs_past_midnight() {
+                (w.days[today] && time >= w.start) || (w.days[yesterday] && time < w.end)
+            } else {
+                w.days[today] && time >= w.start && time < w.end
+            }
+        })
+    }
+
+    // First moment after `now` at which intake opens or closes
+    pub fn next_transition(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let open = self.is_open(now);
+        let today = now.with_timezone(&self.timezone).date_naive();
+        let mut candidates: Vec<DateTime<Utc>> = (0..=8)
+            .filter_map(|offset| today.checked_add_days(Days::new(offset)))
+            .flat_map(|date| {
+                let weekday = date.weekday().num_days_from_monday() as usize;
+                self.windows.iter().filter(move |w| w.days[weekday]).flat_map(move |w| {
+                    let end_date =
+                        if w.runs_past_midnight() { date.succ_opt() } else { Some(date) };
+                    [Some(date.and_time(w.start)), end_date.map(|d| d.and_time(w.end))]
+                })
+            })
+            .flatten()
+            .filter_map(|local| self.timezone.from_local_datetime(&local).earliest())
+            .map(|at| at.with_timezone(&Utc))
+            .filter(|at| *at > now)
+            .collect();
+        candidates.sort();
+        candidates.into_iter().find(|at| self.is_open(*at) != open)
+    }
+
+    pub fn state(&self, outside: OutsidePolicy, now: DateTime<Utc>) -> IntakeState {
+        IntakeState {
+            open: self.is_open(now),
+            outside,
+            timezone: self.timezone.to_string(),
+            next_transition: self.next_transition(now),
+        }
+    }
+}
+
+// Parse one window such as "mon-fri 08:00-18:00" or "sun 22:00-06:00"
+fn parse_window(window: &str) -> Result<Window, String> {
+    let (days, hours) = window
+        .split_once(char::is_whitespace)
+        .ok_or(format!("intake window '{}' must look like 'mon-fri 08:00-18:00'", window))?;
+
+    let mut day_set = [false; 7];
+    for part in days.split(',') {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (parse_day(first)?, parse_day(last)?),
+            None => (parse_day(part)?, parse_day(part)?),
+        };
+        let mut day = first;
+        loop {
+            day_set[day.num_days_from_monday() as usize] = true;
+            if day == last {
+                break;
+            }
+            day = day.succ();
+        }
+    }
+
+    let (start, end) = hours
+        .trim()
+        .split_once('-')
+        .ok_or(format!("intake window '{}' is missing its hours", window))?;
+    Ok(Window { days: day_set, start: parse_time(start)?, end: parse_time(end)? })
+}
+
+fn parse_day(day: &str) -> Result<Weekday, String> {
+    day.trim().parse().map_err(|_| format!("unknown day '{}'", day.trim()))
+}
+
+// Parse HH:MM, accepting 24:00 as the end of the day
+fn parse_time(time: &str) -> Result<NaiveTime, String> {
+    match time.trim() {
+        "24:00" => Ok(NaiveTime::MIN),
+        time => NaiveTime::parse_from_str(time, "%H:%M")
+            .map_err(|_| format!("invalid time '{}', expected HH:MM", time)),
+    }
+}
+
+// Release scheduled measurements to the proof queue whenever intake is open
+pub async fn releaser(state: Arc<AppState>) {
+    let Some(schedule) = &state.config.intake_windows else {
+        return;
+    };
+    let mut interval = tokio::time::interval(TICK_INTERVAL);
+    loop {
+        interval.tick().await;
+        // Nothing is released into the queue during maintenance
+        if !schedule.is_open(Utc::now()) || state.maintenance.is_on() {
+            continue;
+        }
+
+        let mut measurements = state.measurements.lock_as(Source::Intake);
+        let mut released = measurements.filter_mut(|m| m.status == ProofStatus::Scheduled);
+        // Queue in submission order
+        released.sort_by_key(|m| m.created_at);
+        for m in released {
+            m.status = ProofStatus::Pending;
+            m.scheduled_for = None;
+            println!("Releasing scheduled measurement {} to the proof queue", m.id);
+            state.events.publish(m, EventKind::StatusChanged);
+            state.queue.push(&m.owner, &m.id);
+        }
+    }
+}
@@ -0,0 +1,116 @@
+// Registry of long-running administrative jobs.
+//
+// Admin endpoints that touch many measurements start a job and return its id
+// immediately; progress and the final report are then read from
+// GET /admin/jobs/{id}.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::{collections::HashMap, sync::Arc, sync::Mutex};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    auth::{Authorized, Operator},
+};
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+pub enum JobState {
+    Running,
+    Completed,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Job {
+    pub id: String,
+    pub kind: String,
+    // Tenant whose admin started the job, None for global jobs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub state: JobState,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub total: usize,
+    pub processed: usize,
+    // Job-specific report, filled in as the job progresses
+    pub report: Value,
+}
+
+#[derive(Default)]
+pub struct JobRegistry {
+    jobs: Mutex<HashMap<String, Job>>,
+}
+
+impl JobRegistry {
+    // Register a new running job and return its id
+    pub fn start(&self, kind: &str, total: usize, tenant: Option<String>) -> String {
+        let id = Uuid::new_v4().to_string();
+        self.resume(&id, kind, total, tenant, Utc::now());
+        id
+    }
+
+    // Register a job resumed after a restart under its original id
+    pub fn resume(
+        &self,
+        id: &str,
+        kind: &str,
+        total: usize,
+        tenant: Option<String>,
+        started_at: DateTime<Utc>,
+    ) {
+        let job = Job {
+            id: id.to_string(),
+            kind: kind.to_string(),
+            tenant,
+            state: JobState::Running,
+            started_at,
+            finished_at: None,
+            total,
+            processed: 0,
+            report: Value::Null,
+        };
+        self.jobs.lock().unwrap().insert(id.to_string(), job);
+    }
+
+    // Update the progress and report of a running job
+    pub fn update(&self, id: &str, processed: usize, report: Value) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(id) {
+            job.processed = processed;
+            job.report = report;
+        }
+    }
+
+    // Mark a job as finished with its final report
+    pub fn finish(&self, id: &str, report: Value) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(id) {
+            job.state = JobState::Completed;
+            job.finished_at = Some(Utc::now());
+            job.processed = job.total;
+            job.report = report;
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<Job> {
+        self.jobs.lock().unwrap().get(id).cloned()
+    }
+}
+
+// Handler reporting the progress of a job
+pub async fn get_job(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Operator>,
+    Path(id): Path<String>,
+) -> Result<Json<Job>, (StatusCode, String)> {
+    state
+        .jobs
+        .get(&id)
+        .filter(|job| caller.can_access(job.tenant.as_deref()))
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, format!("Job with ID {} not found", id)))
+}
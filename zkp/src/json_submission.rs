@@ -0,0 +1,146 @@
+// Submission of measurements as JSON.
+//
+// POST /measurements/json takes the fields of the multipart form of
+// POST /measurements as a JSON object, for clients that find multipart
+// awkward, with the image base64-encoded or given by imageUrl or uploadToken,
+// and a second image and a depth map, if any, base64-encoded as well:
+//
+//   {"image": "<base64>", "startPoint": {"x": .., "y": .., "z": ..},
+//    "endPoint": {...}, "metadata": {...}, "deadline": "30", ...}
+//
+// The other fields are optional and spelled as in the form; metadata and
+// clientInfo are objects rather than JSON strings, and deadline and
+// publishAt may also be numbers, as may depthMapWidth and depthMapHeight.
+// The decoded fields go through the same validation, scaling and submission
+// as the form's, so both routes store identical measurements.
+// Bodies are limited to JSON_MAX_BYTES; larger ones, malformed JSON and
+// invalid base64 are answered with 400.
+
+use axum::{
+    body::Bytes,
+    extract::{ConnectInfo, State, rejection::JsonRejection},
+    http::{HeaderMap, StatusCode},
+    response::Json,
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::Deserialize;
+use serde_json::{Number, Value};
+use std::{net::SocketAddr, sync::Arc};
+
+use crate::{
+    AppState, MeasurementResponse, Point3D, SubmissionFields,
+    auth::{Authorized, Submitter},
+    submit_fields,
+    uploads::IncomingImage,
+};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JsonSubmission {
+    // Base64 of the image file
+    image: Option<String>,
+    image_url: Option<String>,
+    upload_token: Option<String>,
+    // Base64 of a second image, such as a close-up
+    image2: Option<String>,
+    // Base64 of a depth map, with the size of a raw one
+    depth_map: Option<String>,
+    depth_map_width: Option<Text>,
+    depth_map_height: Option<Text>,
+    start_point: Option<Point3D>,
+    end_point: Option<Point3D>,
+    points: Option<Vec<Point3D>>,
+    mid_point: Option<Point3D>,
+    measurement_type: Option<String>,
+    min_length_cm: Option<Text>,
+    metadata: Option<Value>,
+    client_info: Option<Value>,
+    deadline: Option<Text>,
+    publish_at: Option<Text>,
+    coordinate_system: Option<String>,
+    external_id: Option<String>,
+    idempotency_key: Option<String>,
+    callback_url: Option<String>,
+    skip_stages: Option<String>,
+}
+
+// A field given as a string or a number, as form fields are text
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Text {
+    String(String),
+    Number(Number),
+}
+
+impl Text {
+    fn into_string(self) -> String {
+        match self {
+            Text::String(value) => value,
+            Text::Number(value) => value.to_string(),
+        }
+    }
+}
+
+// Handler for measurements submitted as JSON
+pub async fn submit_json(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Submitter>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: Result<Json<JsonSubmission>, JsonRejection>,
+) -> Result<(StatusCode, Json<MeasurementResponse>), (StatusCode, String)> {
+    let Json(body) = body.map_err(|e| {
+        (StatusCode::BAD_REQUEST, format!("Failed to process JSON body: {}", e.body_text()))
+    })?;
+
+    let image = body
+        .image
+        .map(|data| STANDARD.decode(data.trim()))
+        .transpose()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid base64 image: {}", e)))?;
+    let image2 = body
+        .image2
+        .map(|data| STANDARD.decode(data.trim()))
+        .transpose()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid base64 image2: {}", e)))?;
+    let depth_map = body
+        .depth_map
+        .map(|data| STANDARD.decode(data.trim()))
+        .transpose()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid base64 depthMap: {}", e)))?;
+    let metadata = body
+        .metadata
+        .map(|value| serde_json::to_vec(&value))
+        .transpose()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid metadata: {}", e)))?;
+    let client_info = body
+        .client_info
+        .map(|value| serde_json::to_vec(&value))
+        .transpose()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid clientInfo: {}", e)))?;
+
+    let mut fields = SubmissionFields::from_headers(&headers);
+    fields.image = image.map(|data| IncomingImage::Bytes(Bytes::from(data)));
+    fields.image_url = body.image_url;
+    fields.upload_token = body.upload_token;
+    fields.image2 = image2.map(|data| IncomingImage::Bytes(Bytes::from(data)));
+    fields.depth_map = depth_map;
+    fields.depth_map_width = body.depth_map_width.map(Text::into_string);
+    fields.depth_map_height = body.depth_map_height.map(Text::into_string);
+    fields.start_point = body.start_point;
+    fields.end_point = body.end_point;
+    fields.points = body.points;
+    fields.mid_point = body.mid_point;
+    fields.measurement_type = body.measurement_type;
+    fields.min_length_cm = body.min_length_cm.map(Text::into_string);
+    fields.metadata = metadata;
+    fields.client_info = client_info;
+    fields.deadline = body.deadline.map(Text::into_string).or(fields.deadline);
+    fields.publish_at = body.publish_at.map(Text::into_string);
+    fields.coordinate_system = body.coordinate_system;
+    fields.external_id = body.external_id;
+    fields.idempotency_key = body.idempotency_key.or(fields.idempotency_key);
+    fields.callback_url = body.callback_url;
+    fields.skip_stages = body.skip_stages;
+    submit_fields(&state, caller, remote, fields).await
+}
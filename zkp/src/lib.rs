@@ -0,0 +1,9 @@
+// Parts of the backend usable without running the server.
+//
+// groth16 verifies snarkjs proofs in-process, for the server itself and for
+// services such as the bridge that link this crate to check proofs against
+// the zkHotdog verification key. groth16_prover makes such proofs from a
+// snarkjs proving key and witness, for the server's native prover.
+
+pub mod groth16;
+pub mod groth16_prover;
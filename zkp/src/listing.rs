@@ -0,0 +1,782 @@
+// Streaming listing of measurements.
+//
+// Listings can cover tens of thousands of records, so the response body is
+// produced lazily: while the client reads, the body stream picks the keys of
+// the next batch of matching measurements, scanning the ordered index of the
+// measurements in memory on from where the previous batch ended, then reads
+// their records from the store in one call and serializes them. A listing
+// scans the index once however many batches it takes, holding the lock of
+// the measurements for one batch at a time, and neither the keys nor the
+// records of more than one batch are held, so the memory a listing takes
+// stays bounded by the batch size. A client disconnect drops the stream,
+// which stops the reads immediately.
+//
+// Measurements are listed in the order of (created_at, id). With ?limit= the
+// listing is paged by key rather than by offset: each page ends with an
+// x-next-cursor header while more measurements follow, and ?cursor= resumes
+// strictly after the last key of the previous page, so measurements submitted
+// in between never shift a page and nothing is skipped or listed twice. New
+// measurements sort last, as their created_at is the time of submission.
+//
+// A cursor is the key and a hash of the filter it was listed with, signed with
+// the key in CURSOR_SECRET_PATH, so cursors stay valid across restarts and
+// between instances sharing that file. Cursors that do not verify are
+// rejected with the error code invalid_cursor, and cursors used with another
+// filter with cursor_filter_mismatch.
+//
+// Like the status endpoint, a listing leaves out embargoed measurements unless
+// the caller is their submitter or an admin.
+//
+// ?status= may be repeated to list measurements in any of several proof
+// statuses. The statuses are kept in declaration order whatever the order of
+// the query, so a cursor follows the same filter however its query was written.
+
+use axum::{
+    Json,
+    body::{Body, Bytes},
+    extract::{ConnectInfo, Query, RawQuery, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use futures_util::stream;
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+use uuid::Uuid;
+
+use crate::{
+    AppState, AttestationData, Measurement, Point3D, ProofStatus, access,
+    auth::{Authorized, Caller, Viewer},
+    client_info::ClientInfo,
+    coords::OriginalPoints,
+    metadata::Metadata,
+    schemas::ErrorBody,
+    similarity::DuplicateFlag,
+    store::{ListKey, Measurements, StoreGuard},
+    support::{hmac_sha256, same_signature},
+    threshold::Public,
+};
+
+// Number of measurements read from the store at a time
+const LIST_BATCH_SIZE: usize = 256;
+// Page size when a cursor is given without a limit, and the largest allowed
+const DEFAULT_PAGE_SIZE: usize = 100;
+pub const MAX_PAGE_SIZE: usize = 1000;
+const NEXT_CURSOR_HEADER: &str = "x-next-cursor";
+// Measurements matching the filters, on every page
+const TOTAL_COUNT_HEADER: &str = "x-total-count";
+// Accepted values of ?status=, in declaration order
+const PROOF_STATUSES: [ProofStatus; 6] = [
+    ProofStatus::Scheduled,
+    ProofStatus::Pending,
+    ProofStatus::Processing,
+    ProofStatus::Completed,
+    ProofStatus::Failed,
+    ProofStatus::Cancelled,
+];
+
+// Criteria selecting a subset of measurements
+#[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq)]
+pub struct MeasurementFilter {
+    // Any of these statuses; empty matches every status
+    #[serde(default)]
+    pub status: Vec<ProofStatus>,
+    #[serde(default)]
+    pub owner: Option<String>,
+    #[serde(default)]
+    pub tenant: Option<String>,
+    #[serde(default)]
+    pub tag: Option<String>,
+    // Only measurements imported from offline bundles, or only live ones
+    #[serde(default)]
+    pub imported: Option<bool>,
+    // Only measurements flagged as possible duplicates, or only unflagged ones
+    #[serde(default)]
+    pub flagged_duplicate: Option<bool>,
+}
+
+impl MeasurementFilter {
+    pub fn matches(&self, m: &Measurement) -> bool {
+        (self.status.is_empty() || self.status.contains(&m.status))
+            && self.owner.as_ref().is_none_or(|owner| owner == &m.owner)
+            && self.tenant.as_ref().is_none_or(|tenant| m.tenant.as_ref() == Some(tenant))
+            && self.tag.as_ref().is_none_or(|tag| m.metadata.tags.contains(tag))
+            && self.imported.is_none_or(|imported| imported == m.imported)
+            && self.flagged_duplicate.is_none_or(|flagged| flagged == m.flagged_duplicate.is_some())
+    }
+}
+
+// Listing representation of a measurement, with artifacts reduced to a map of
+// available artifact name to size in bytes. It is served through
+// threshold::Public, leaving out the points of a threshold measurement.
+#[derive(Serialize)]
+struct MeasurementListItem<'a> {
+    id: &'a str,
+    owner: &'a str,
+    external_id: &'a Option<String>,
+    image_path: &'a str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    image_paths: &'a [String],
+    start_point: &'a Point3D,
+    end_point: &'a Point3D,
+    original_points: &'a Option<OriginalPoints>,
+    status: &'a ProofStatus,
+    attestation: &'a Option<AttestationData>,
+    metadata: &'a Metadata,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_info: &'a Option<ClientInfo>,
+    artifacts: BTreeMap<String, u64>,
+    created_at: &'a Option<DateTime<Utc>>,
+    captured_at: &'a Option<DateTime<Utc>>,
+    imported: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scheduled_for: &'a Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reprove_of: &'a Option<String>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    reproofs: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    flagged_duplicate: &'a Option<DuplicateFlag>,
+}
+
+impl<'a> From<&'a Measurement> for MeasurementListItem<'a> {
+    fn from(m: &'a Measurement) -> Self {
+        MeasurementListItem {
+            id: &m.id,
+            owner: &m.owner,
+            external_id: &m.external_id,
+            image_path: &m.image_path,
+            image_paths: &m.image_paths,
+            start_point: &m.start_point,
+            end_point: &m.end_point,
+            original_points: &m.original_points,
+            status: &m.status,
+            attestation: &m.attestation,
+            metadata: &m.metadata,
+            client_info: &m.client_info,
+            artifacts: m.artifacts.compact(),
+            created_at: &m.created_at,
+            captured_at: &m.captured_at,
+            imported: m.imported,
+            scheduled_for: &m.scheduled_for,
+            reprove_of: &m.reprove_of,
+            reproofs: &m.reproofs,
+            flagged_duplicate: &m.flagged_duplicate,
+        }
+    }
+}
+
+// What a listing covers: the measurements matching its filter that its caller
+// may see in full, with embargoes judged at one instant for every batch
+struct Selection {
+    filter: MeasurementFilter,
+    caller: Caller,
+    remote: IpAddr,
+    now: DateTime<Utc>,
+}
+
+impl Selection {
+    fn includes(&self, m: &Measurement) -> bool {
+        self.filter.matches(m) && access::may_see_in_full(&self.caller, self.remote, m, self.now)
+    }
+}
+
+// Progress of a listing stream through the measurements, in key order
+struct ListCursor {
+    selection: Selection,
+    // Key of the last measurement listed
+    after: Option<ListKey>,
+    // Key of the last measurement of the page, when another page follows
+    until: Option<ListKey>,
+    // Measurements left to list on the page
+    remaining: usize,
+    opened: bool,
+    first: bool,
+    finished: bool,
+}
+
+impl ListCursor {
+    fn new(selection: Selection, after: Option<ListKey>, page: Page) -> Self {
+        ListCursor {
+            selection,
+            after,
+            until: page.until,
+            remaining: page.size,
+            opened: false,
+            first: true,
+            finished: false,
+        }
+    }
+
+    // Read and serialize the next batch of measurements as a chunk of the
+    // JSON array
+    async fn next_chunk(&mut self, measurements: &Measurements) -> Option<Result<Bytes, String>> {
+        if self.finished {
+            return None;
+        }
+
+        let mut chunk = Vec::new();
+        if !self.opened {
+            chunk.push(b'[');
+            self.opened = true;
+        }
+
+        let keys = next_keys(
+            &measurements.lock(),
+            &self.selection,
+            self.after.as_ref(),
+            self.until.as_ref(),
+            self.remaining.min(LIST_BATCH_SIZE),
+        );
+        let Some(last) = keys.last().cloned() else {
+            chunk.push(b']');
+            self.finished = true;
+            return Some(Ok(Bytes::from(chunk)));
+        };
+        self.after = Some(last);
+        self.remaining -= keys.len();
+
+        let ids: Vec<String> = keys.into_iter().map(|(_, id)| id).collect();
+        let mut stored: HashMap<String, Measurement> =
+            match measurements.store().get_many(&ids).await {
+                Ok(batch) => batch.into_iter().map(|m| (m.id.clone(), m)).collect(),
+                Err(e) => {
+                    // Ending the body without closing the array tells the client
+                    // the listing is incomplete
+                    self.finished = true;
+                    return Some(Err(format!("Failed to read measurements to list: {}", e)));
+                }
+            };
+
+        // Measurements deleted or changed not to match since their keys were
+        // picked are skipped, and so are those that fail to serialize
+        for measurement in ids.iter().filter_map(|id| stored.remove(id)) {
+            if !self.selection.includes(&measurement) {
+                continue;
+            }
+            let item = MeasurementListItem::from(&measurement);
+            let public = Public { record: &item, threshold: measurement.min_length_cm.is_some() };
+            if let Err(e) = append_item(&mut chunk, &mut self.first, &public) {
+                println!("Failed to serialize measurement {}: {}", measurement.id, e);
+            }
+        }
+
+        Some(Ok(Bytes::from(chunk)))
+    }
+}
+
+// The measurements a listing covers after its cursor
+struct Page {
+    size: usize,
+    until: Option<ListKey>,
+}
+
+// The first `count` keys of selected measurements after `after` and up to
+// `until`, in order, scanning the index from `after` only as far as needed
+fn next_keys(
+    measurements: &StoreGuard,
+    selection: &Selection,
+    after: Option<&ListKey>,
+    until: Option<&ListKey>,
+    count: usize,
+) -> Vec<ListKey> {
+    measurements
+        .listed_after(after)
+        .take_while(|m| {
+            until.is_none_or(|(created_at, id)| (m.created_at, m.id.as_str()) <= (*created_at, id))
+        })
+        .filter(|m| selection.includes(m))
+        .take(count)
+        .map(|m| (m.created_at, m.id.clone()))
+        .collect()
+}
+
+// Append an element to the JSON array being written, leaving the chunk as it
+// was if the element fails to serialize midway
+fn append_item(
+    chunk: &mut Vec<u8>,
+    first: &mut bool,
+    item: &impl Serialize,
+) -> serde_json::Result<()> {
+    let item = serde_json::to_vec(item)?;
+    if !*first {
+        chunk.push(b',');
+    }
+    *first = false;
+    chunk.extend_from_slice(&item);
+    Ok(())
+}
+
+// Last key of a page and the hash of the filter it was listed with
+#[derive(Serialize, Deserialize)]
+struct PageKey {
+    created_at: Option<DateTime<Utc>>,
+    id: String,
+    filter: String,
+}
+
+// Key signing page cursors, generated on first start
+pub struct PageCursors {
+    secret: Vec<u8>,
+}
+
+impl PageCursors {
+    pub fn load(path: &str) -> Result<Self, String> {
+        match fs::read(path) {
+            Ok(secret) if !secret.is_empty() => Ok(PageCursors { secret }),
+            Ok(_) => Err(format!("Cursor secret {} is empty", path)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let secret = hex::encode(
+                    [Uuid::new_v4().into_bytes(), Uuid::new_v4().into_bytes()].concat(),
+                );
+                fs::write(path, &secret)
+                    .map_err(|e| format!("Failed to write cursor secret {}: {}", path, e))?;
+                Ok(PageCursors { secret: secret.into_bytes() })
+            }
+            Err(e) => Err(format!("Failed to read cursor secret {}: {}", path, e)),
+        }
+    }
+
+    // Opaque token of the key: its JSON as hex and the signature of that
+    fn encode(&self, key: &PageKey) -> String {
+        let payload = hex::encode(serde_json::to_vec(key).unwrap_or_default());
+        let signature = hmac_sha256(&self.secret, payload.as_bytes());
+        format!("{}.{}", payload, signature)
+    }
+
+    fn decode(&self, token: &str) -> Option<PageKey> {
+        let (payload, signature) = token.split_once('.')?;
+        if !same_signature(signature, &hmac_sha256(&self.secret, payload.as_bytes())) {
+            return None;
+        }
+        serde_json::from_slice(&hex::decode(payload).ok()?).ok()
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct ListQuery {
+    limit: Option<usize>,
+    cursor: Option<String>,
+    owner: Option<String>,
+    tag: Option<String>,
+    imported: Option<bool>,
+    flagged_duplicate: Option<bool>,
+}
+
+// Short hash identifying a filter
+fn filter_hash(filter: &MeasurementFilter) -> String {
+    let digest = Sha256::digest(serde_json::to_vec(filter).unwrap_or_default());
+    hex::encode(&digest[..8])
+}
+
+// The statuses given with ?status=, deduplicated and in declaration order
+fn status_filter(raw_query: Option<&str>) -> Result<Vec<ProofStatus>, String> {
+    let mut requested = Vec::new();
+    let pairs = form_urlencoded::parse(raw_query.unwrap_or_default().as_bytes());
+    for (_, value) in pairs.filter(|(name, _)| name == "status") {
+        let Some(status) = PROOF_STATUSES.iter().find(|s| format!("{:?}", s) == value) else {
+            let accepted: Vec<String> = PROOF_STATUSES.iter().map(|s| format!("{:?}", s)).collect();
+            return Err(format!(
+                "Unknown status '{}'; accepted values are {}",
+                value,
+                accepted.join(", ")
+            ));
+        };
+        requested.push(status);
+    }
+    Ok(PROOF_STATUSES.into_iter().filter(|s| requested.contains(&s)).collect())
+}
+
+fn cursor_error(error: &'static str, message: &str) -> Response {
+    let body = ErrorBody { error, message: message.to_string(), since: None };
+    (StatusCode::BAD_REQUEST, Json(body)).into_response()
+}
+
+// Handler listing measurements as a streamed JSON array, all of them or a
+// page at a time
+pub async fn list_measurements(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Viewer>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    RawQuery(raw_query): RawQuery,
+    Query(query): Query<ListQuery>,
+) -> Response {
+    let status = match status_filter(raw_query.as_deref()) {
+        Ok(status) => status,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+    // Tenant callers only ever list their own tenant
+    let filter = MeasurementFilter {
+        status,
+        owner: query.owner,
+        tenant: caller.tenant.clone(),
+        tag: query.tag,
+        imported: query.imported,
+        flagged_duplicate: query.flagged_duplicate,
+    };
+    let filter_hash = filter_hash(&filter);
+    // Embargoed measurements are only listed to their submitter and admins
+    let selection = Selection { filter, caller, remote: remote.ip(), now: state.time.now() };
+
+    let after: Option<ListKey> = match &query.cursor {
+        None => None,
+        Some(token) => {
+            let Some(key) = state.page_cursors.decode(token) else {
+                return cursor_error(
+                    "invalid_cursor",
+                    "The cursor is malformed or was not issued by this service",
+                );
+            };
+            if key.filter != filter_hash {
+                return cursor_error(
+                    "cursor_filter_mismatch",
+                    "The cursor was issued for a different filter; repeat its query or start over",
+                );
+            }
+            Some((key.created_at, key.id))
+        }
+    };
+    let limit = match query.limit {
+        Some(limit) if limit == 0 || limit > MAX_PAGE_SIZE => {
+            let message = format!("limit must be between 1 and {}", MAX_PAGE_SIZE);
+            return (StatusCode::BAD_REQUEST, message).into_response();
+        }
+        Some(limit) => Some(limit),
+        None => after.as_ref().map(|_| DEFAULT_PAGE_SIZE),
+    };
+
+    let (total, page) = {
+        let measurements = state.measurements.lock();
+        let total = measurements.values().filter(|m| selection.includes(m)).count();
+        let mut page = Page { size: limit.unwrap_or(usize::MAX), until: None };
+        if let Some(limit) = limit {
+            // One key past the page tells whether another page follows
+            let keys = next_keys(&measurements, &selection, after.as_ref(), None, limit + 1);
+            if keys.len() > limit {
+                page.until = keys.into_iter().nth(limit - 1);
+            }
+        }
+        (total, page)
+    };
+    let next_cursor = page.until.clone().map(|(created_at, id)| {
+        state.page_cursors.encode(&PageKey { created_at, id, filter: filter_hash })
+    });
+    let next_link = next_cursor.as_ref().zip(limit).map(|(cursor, limit)| {
+        format!(
+            "<{}>; rel=\"next\"",
+            next_page_url(&state.config.public_base_url, &selection.filter, limit, cursor)
+        )
+    });
+
+    // Records are read from the store, which is to hold the changes made
+    // before the listing
+    state.measurements.flushed().await;
+    let cursor = ListCursor::new(selection, after, page);
+    let body = stream::unfold((state, cursor), |(state, mut cursor)| async move {
+        let chunk = cursor.next_chunk(&state.measurements).await?.map_err(|e| {
+            println!("{}", e);
+            io::Error::other(e)
+        });
+        Some((chunk, (state, cursor)))
+    });
+
+    let mut response =
+        ([(header::CONTENT_TYPE, "application/json")], Body::from_stream(body)).into_response();
+    let headers = response.headers_mut();
+    headers.insert(TOTAL_COUNT_HEADER, total.into());
+    if let Some(next_cursor) = next_cursor
+        && let Ok(value) = next_cursor.parse()
+    {
+        headers.insert(NEXT_CURSOR_HEADER, value);
+    }
+    if let Some(link) = next_link
+        && let Ok(value) = link.parse()
+    {
+        headers.insert(header::LINK, value);
+    }
+    response
+}
+
+// URL of the page after a cursor, repeating the filters it is bound to
+fn next_page_url(base_url: &str, filter: &MeasurementFilter, limit: usize, cursor: &str) -> String {
+    let mut url = format!("{}/measurements?limit={}&cursor={}", base_url, limit, cursor);
+    for status in &filter.status {
+        url.push_str(&format!("&status={:?}", status));
+    }
+    let text_filters = [("owner", &filter.owner), ("tag", &filter.tag)];
+    for (name, value) in text_filters {
+        if let Some(value) = value {
+            url.push_str(&format!("&{}={}", name, utf8_percent_encode(value, NON_ALPHANUMERIC)));
+        }
+    }
+    let flag_filters =
+        [("imported", filter.imported), ("flagged_duplicate", filter.flagged_duplicate)];
+    for (name, value) in flag_filters {
+        if let Some(value) = value {
+            url.push_str(&format!("&{}={}", name, value));
+        }
+    }
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Failure,
+        auth::Role,
+        clock::Clock,
+        store::{Fields, MeasurementStore, MemoryStore},
+        store_cache::CacheLimits,
+    };
+    use futures_util::{FutureExt, future::BoxFuture};
+    use serde::Serializer;
+    use std::{
+        net::Ipv4Addr,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    // Memory store recording how many measurements each read returned
+    #[derive(Default)]
+    struct SizeTrackingStore {
+        inner: MemoryStore,
+        largest_read: AtomicUsize,
+        read: AtomicUsize,
+    }
+
+    impl SizeTrackingStore {
+        fn track<T>(&self, read: Result<Vec<T>, String>) -> Result<Vec<T>, String> {
+            let size = read.as_ref().map_or(0, Vec::len);
+            self.largest_read.fetch_max(size, Ordering::Relaxed);
+            self.read.fetch_add(size, Ordering::Relaxed);
+            read
+        }
+    }
+
+    impl MeasurementStore for SizeTrackingStore {
+        fn insert<'a>(&'a self, m: &'a Measurement) -> BoxFuture<'a, Result<(), String>> {
+            self.inner.insert(m)
+        }
+
+        fn get<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Option<Measurement>, String>> {
+            self.inner.get(id)
+        }
+
+        fn update_status<'a>(
+            &'a self,
+            id: &'a str,
+            from: ProofStatus,
+            to: ProofStatus,
+            failure: Option<Failure>,
+        ) -> BoxFuture<'a, Result<bool, String>> {
+            self.inner.update_status(id, from, to, failure)
+        }
+
+        fn set_attestation<'a>(
+            &'a self,
+            id: &'a str,
+            attestation: &'a AttestationData,
+        ) -> BoxFuture<'a, Result<(), String>> {
+            self.inner.set_attestation(id, attestation)
+        }
+
+        fn list(&self) -> BoxFuture<'_, Result<Vec<Measurement>, String>> {
+            async { self.track(self.inner.list().await) }.boxed()
+        }
+
+        fn get_many<'a>(
+            &'a self,
+            ids: &'a [String],
+        ) -> BoxFuture<'a, Result<Vec<Measurement>, String>> {
+            async { self.track(self.inner.get_many(ids).await) }.boxed()
+        }
+
+        fn save<'a>(&'a self, m: &'a Measurement) -> BoxFuture<'a, Result<(), String>> {
+            self.inner.save(m)
+        }
+
+        fn patch<'a>(
+            &'a self,
+            id: &'a str,
+            fields: &'a Fields,
+        ) -> BoxFuture<'a, Result<(), String>> {
+            self.inner.patch(id, fields)
+        }
+
+        fn remove<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), String>> {
+            self.inner.remove(id)
+        }
+    }
+
+    async fn listed(cursor: &mut ListCursor, measurements: &Measurements) -> Vec<Bytes> {
+        let mut chunks = Vec::new();
+        while let Some(chunk) = cursor.next_chunk(measurements).await {
+            chunks.push(chunk.unwrap());
+        }
+        chunks
+    }
+
+    // Every measurement, as listed to an admin
+    fn unfiltered() -> Selection {
+        let caller = Caller {
+            name: "admin".to_string(),
+            key_id: None,
+            role: Some(Role::Admin),
+            tenant: None,
+        };
+        Selection {
+            filter: MeasurementFilter::default(),
+            caller,
+            remote: Ipv4Addr::LOCALHOST.into(),
+            now: Utc::now(),
+        }
+    }
+
+    fn everything() -> ListCursor {
+        ListCursor::new(unfiltered(), None, Page { size: usize::MAX, until: None })
+    }
+
+    // Fails after writing part of its output, as a map with a bad key would
+    struct Unserializable;
+
+    impl Serialize for Unserializable {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            use serde::ser::{Error, SerializeMap};
+            let mut map = serializer.serialize_map(None)?;
+            map.serialize_entry("id", "partial")?;
+            Err(S::Error::custom("cannot serialize"))
+        }
+    }
+
+    #[test]
+    fn embargoed_measurements_are_only_selected_for_their_submitter_and_admins() {
+        let now = Utc::now();
+        let mut m = Measurement::fixture("m");
+        m.owner = "10.0.0.1".to_string();
+        m.publish_at = Some(now + chrono::Duration::hours(1));
+        let anonymous = |remote: [u8; 4], now| Selection {
+            caller: Caller {
+                name: "anonymous".to_string(),
+                key_id: None,
+                role: Some(Role::Submitter),
+                tenant: None,
+            },
+            remote: Ipv4Addr::from(remote).into(),
+            now,
+            ..unfiltered()
+        };
+
+        assert!(unfiltered().includes(&m));
+        assert!(anonymous([10, 0, 0, 1], now).includes(&m));
+        assert!(!anonymous([10, 0, 0, 2], now).includes(&m));
+        assert!(anonymous([10, 0, 0, 2], now + chrono::Duration::hours(1)).includes(&m));
+    }
+
+    #[test]
+    fn failed_item_leaves_the_array_valid() {
+        let (mut chunk, mut first) = (b"[".to_vec(), true);
+        append_item(&mut chunk, &mut first, &Unserializable).unwrap_err();
+        append_item(&mut chunk, &mut first, &1).unwrap();
+        append_item(&mut chunk, &mut first, &Unserializable).unwrap_err();
+        append_item(&mut chunk, &mut first, &2).unwrap();
+        chunk.push(b']');
+        assert_eq!(serde_json::from_slice::<Vec<u32>>(&chunk).unwrap(), [1, 2]);
+    }
+
+    #[tokio::test]
+    async fn threshold_measurement_is_listed_without_its_points() {
+        let measurements = Measurements::load(
+            Arc::new(MemoryStore::default()),
+            CacheLimits::default(),
+            Clock::default(),
+        )
+        .await
+        .unwrap();
+        let mut threshold = Measurement::fixture("threshold");
+        threshold.min_length_cm = Some(30.0);
+        threshold.original_points = serde_json::from_value(serde_json::json!({
+            "start": {"x": 0.1, "y": 0.2, "z": -0.3},
+            "end": {"x": 0.4, "y": 0.2, "z": -0.3},
+        }))
+        .unwrap();
+        measurements.lock().insert(threshold.id.clone(), threshold);
+        measurements.lock().insert("length".to_string(), Measurement::fixture("length"));
+        measurements.flushed().await;
+
+        let body = listed(&mut everything(), &measurements).await.concat();
+
+        let listed: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        // Listed in the order of their ids, as neither has a created_at
+        let point_fields = ["start_point", "end_point", "original_points"];
+        assert_eq!(listed[1]["id"], "threshold");
+        assert!(point_fields.iter().all(|field| listed[1].get(field).is_none()), "{}", listed[1]);
+        assert!(point_fields[..2].iter().all(|field| listed[0].get(field).is_some()));
+    }
+
+    #[tokio::test]
+    async fn streams_tens_of_thousands_of_measurements_in_bounded_chunks() {
+        const COUNT: usize = 30_000;
+        let store = Arc::new(SizeTrackingStore::default());
+        let measurements =
+            Measurements::load(store.clone(), CacheLimits::default(), Clock::default())
+                .await
+                .unwrap();
+        let ids: Vec<String> = (0..COUNT).map(|i| format!("m-{:05}", i)).collect();
+        {
+            let mut guard = measurements.lock();
+            for id in &ids {
+                guard.insert(id.clone(), Measurement::fixture(id));
+            }
+        }
+        measurements.flushed().await;
+        let item_size =
+            serde_json::to_vec(&MeasurementListItem::from(&Measurement::fixture(&ids[0])))
+                .unwrap()
+                .len();
+
+        let chunks = listed(&mut everything(), &measurements).await;
+
+        // The store is read a batch at a time, and a chunk holds at most one
+        // batch, whatever the number of records
+        assert_eq!(store.read.load(Ordering::Relaxed), COUNT);
+        assert_eq!(store.largest_read.load(Ordering::Relaxed), LIST_BATCH_SIZE);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= LIST_BATCH_SIZE * (item_size + 1) + 1));
+        assert_eq!(chunks.len(), COUNT.div_ceil(LIST_BATCH_SIZE) + 1);
+        let listed: Vec<serde_json::Value> = serde_json::from_slice(&chunks.concat()).unwrap();
+        assert_eq!(listed.len(), COUNT);
+        assert_eq!(listed[COUNT - 1]["id"], "m-29999");
+    }
+
+    #[tokio::test]
+    async fn pages_end_at_their_last_key() {
+        let measurements = Measurements::load(
+            Arc::new(MemoryStore::default()),
+            CacheLimits::default(),
+            Clock::default(),
+        )
+        .await
+        .unwrap();
+        for id in ["a", "b", "c", "d"] {
+            measurements.lock().insert(id.to_string(), Measurement::fixture(id));
+        }
+        measurements.flushed().await;
+
+        let keys = next_keys(&measurements.lock(), &unfiltered(), None, None, 3);
+        assert_eq!(keys.iter().map(|(_, id)| id.as_str()).collect::<Vec<_>>(), ["a", "b", "c"]);
+        // A page after "a" ending at "c" leaves "d" out even with room for it,
+        // as after a measurement of the page was deleted
+        measurements.lock().remove("b");
+        let page = Page { size: 2, until: Some(keys[2].clone()) };
+        let mut cursor = ListCursor::new(unfiltered(), Some(keys[0].clone()), page);
+        let body = listed(&mut cursor, &measurements).await.concat();
+        let listed: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(listed.iter().map(|m| m["id"].as_str().unwrap()).collect::<Vec<_>>(), ["c"]);
+    }
+}
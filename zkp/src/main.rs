@@ -1,31 +1,150 @@
 use axum::{
     Router,
-    body::Bytes,
-    extract::{Multipart, Path, State},
-    http::{StatusCode, header, Method},
-    response::{IntoResponse, Json},
-    routing::{get, post},
+    extract::{ConnectInfo, DefaultBodyLimit, Multipart, Path, Query, State},
+    middleware,
+    http::{StatusCode, Method},
+    http::HeaderMap,
+    response::{Json, Response},
+    routing::{get, patch, post, put},
 };
-use tower_http::cors::{CorsLayer, Any};
+use chrono::{DateTime, Utc};
+use tower_http::cors::{AllowOrigin, CorsLayer, Any};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::{
-    collections::HashMap,
-    fs::{self, File},
-    io::Write,
+    fs,
     net::SocketAddr,
-    sync::{Arc, Mutex},
+    sync::Arc,
+    time::{Duration, Instant},
 };
 use uuid::Uuid;
 
+mod access;
+mod admin;
+mod angle;
+mod artifacts;
+mod attestation_backend;
+mod attestations;
+mod audit;
+mod backfill;
+mod backup;
+mod auth;
+mod broker;
+mod bundle;
+mod cache;
+mod cancel;
+mod capabilities;
+mod chunked_uploads;
+mod circuit;
+mod circuit_artifacts;
+mod client_info;
+mod clock;
+mod commitment;
+mod config;
+mod contract;
+mod coords;
+mod deadline;
+mod depth_map;
+mod doctor;
+mod downloads;
+mod embargo;
+mod events;
+mod external_id;
+mod flat_points;
+mod health;
+mod history;
+mod idempotency;
+mod image_url;
+mod inputs;
+mod json_submission;
+mod intake;
+mod jobs;
+mod listing;
+mod maintenance;
+mod metadata;
+mod native_prover;
+mod pending;
+mod pipeline;
+mod plan;
+mod polyline;
+mod proofs;
+mod proving_keys;
+mod queue;
+mod reconcile;
+mod replica;
+mod reprove;
+mod retry;
+mod retention;
+mod rpc;
+mod safety;
+mod schemas;
+mod shadow;
+mod similarity;
+mod signer;
+mod startup;
+mod status_batch;
+mod status_events;
+mod status_reads;
+mod store;
+mod store_cache;
+mod submission;
+mod summary;
+mod supervisor;
+mod support;
+mod telemetry;
+mod tenants;
+mod threshold;
+mod thumbnails;
+mod timecheck;
+mod toolchain;
+mod uploads;
+mod version;
+mod view;
+mod webhooks;
+mod websocket;
+
+use admin::PendingDeletions;
+use angle::MeasurementType;
+use auth::{ApiKeys, Authorized, Caller, Submitter};
+use artifacts::{Artifact, Artifacts};
+use audit::AuditLog;
+use broker::Broker;
+use cache::DiskCache;
+use config::Config;
+use coords::{CoordinateSystem, OriginalPoints};
+use events::{EventBus, EventKind};
+use external_id::ExternalIdIndex;
+use flat_points::FlatPoints;
+use history::Source;
+use idempotency::IdempotencyIndex;
+use intake::OutsidePolicy;
+use jobs::JobRegistry;
+use maintenance::Maintenance;
+use metadata::Metadata;
+use queue::{ProofQueue, QueuePosition};
+use signer::Signers;
+use store::Measurements;
+use store_cache::CacheLimits;
+use similarity::DuplicateMode;
+use thumbnails::Prewarmer;
+use pipeline::{ArtifactProblem, PipelineError, Stage};
+use summary::Summaries;
+use supervisor::{ProcessRole, ProveJob};
+use tenants::Visitor;
+use toolchain::ToolchainCache;
+use uploads::{IncomingImage, UploadProgress, UploadTransaction};
+use version::{MeasurementVersions, VersionStamp};
+
 // Data structures for our application
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
 struct Point3D {
-    x: f32,
-    y: f32,
-    z: f32,
+    x: f64,
+    y: f64,
+    z: f64,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 struct AttestationData {
     #[serde(rename = "attestationId")]
     attestation_id: u64,
@@ -37,27 +156,287 @@ struct AttestationData {
     index: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 struct Measurement {
     id: String,
+    // Identity of the submitter, used to share proving capacity fairly
+    #[serde(default)]
+    owner: String,
+    // Tenant the measurement belongs to, in tenant mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tenant: Option<String>,
+    // Identifier chosen by the submitter, unique per owner
+    #[serde(default)]
+    external_id: Option<String>,
+    // Idempotency key the submission was made with, unique per owner
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    idempotency_key: Option<String>,
     image_path: String,
+    // Every image of the measurement, image_path first; empty for records from
+    // before a second image was accepted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    image_paths: Vec<String>,
+    // Depth map sent with the image, which the proof does not use yet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    depth_map: Option<depth_map::DepthMap>,
+    // Points scaled to the circuit's fixed-point integers, as proved
     start_point: Point3D,
     end_point: Point3D,
+    // Every point of a polyline of more than two points, scaled likewise,
+    // start_point and end_point being its first and last
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    points: Vec<Point3D>,
+    // Whether the measurement attests a length or the angle at mid_point
+    #[serde(default)]
+    measurement_type: MeasurementType,
+    // Point of an angle measurement where the object bends, scaled likewise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mid_point: Option<Point3D>,
+    // Points as submitted; absent for measurements recorded before they were kept
+    #[serde(default)]
+    original_points: Option<OriginalPoints>,
+    // Space the points were submitted in; ARKit world space for older records
+    #[serde(default)]
+    coordinate_system: CoordinateSystem,
     status: ProofStatus,
+    // Stages the measurement goes through and how far each got; empty for
+    // records from before plans were kept
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    plan: Vec<plan::PlannedStage>,
     attestation: Option<AttestationData>,
+    #[serde(default)]
+    artifacts: Artifacts,
+    #[serde(default)]
+    versions: MeasurementVersions,
+    // Point in time after which the client no longer wants a proof
+    #[serde(default)]
+    deadline: Option<DateTime<Utc>>,
+    // Why the measurement failed, when its status is Failed
+    #[serde(default)]
+    failure: Option<Failure>,
+    // Validated label, notes and tags supplied by the client
+    #[serde(default)]
+    metadata: Metadata,
+    // Device, app version, platform and capture method the client reported
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    client_info: Option<client_info::ClientInfo>,
+    // Until this time the measurement is hidden from the public endpoints
+    #[serde(default)]
+    publish_at: Option<DateTime<Utc>>,
+    // Whether the end of the embargo has been announced
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    publication_announced: bool,
+    // When the server accepted the measurement
+    #[serde(default)]
+    created_at: Option<DateTime<Utc>>,
+    // When the status last changed or the attestation was attached; absent
+    // for records from before it was kept
+    #[serde(default)]
+    updated_at: Option<DateTime<Utc>>,
+    // When the device took the measurement, for bundles captured offline
+    #[serde(default)]
+    captured_at: Option<DateTime<Utc>>,
+    // Whether the measurement arrived as an offline bundle
+    #[serde(default)]
+    imported: bool,
+    // When a Scheduled measurement is expected to enter the proof queue
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    scheduled_for: Option<DateTime<Utc>>,
+    // Circuit version of a polyline, an angle or requested for a reproof; None proves
+    // with the default circuit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    circuit: Option<String>,
+    // Original measurement this one re-proves under another circuit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reprove_of: Option<String>,
+    // Reproofs of this measurement, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    reproofs: Vec<String>,
+    // Difference hash of the image, for finding re-encoded copies of it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    perceptual_hash: Option<String>,
+    // SHA-256 of the stored image
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    image_sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    image_dimensions: Option<backfill::ImageDimensions>,
+    // Distance between the points in centimetres, summed over the segments of
+    // a polyline or both arms of an angle, as proved for lengths
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    length_cm: Option<f64>,
+    // Length in centimetres a threshold measurement proves its points to be
+    // at least apart; its points and length are not served
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    min_length_cm: Option<f64>,
+    // Set when the image looks like another owner's, for admin review
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    flagged_duplicate: Option<similarity::DuplicateFlag>,
+    // How the proof was submitted to zkVerify, once it was
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    receipt: Option<rpc::SubmissionReceipt>,
+    // Network the proof was submitted to for its attestation, once it was
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    attestation_backend: Option<attestation_backend::BackendKind>,
+    // URL the measurement is POSTed to once it finished
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    callback_url: Option<String>,
+    // How the last webhook delivery went
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    webhook: Option<webhooks::WebhookDelivery>,
+    // Status transitions, oldest first; left out of /status/{id} and its schema
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schemars(skip)]
+    history: Vec<history::Transition>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+impl Measurement {
+    // Scaled points in order, from the start point to the end point
+    fn path(&self) -> Vec<Point3D> {
+        angle::path(&self.start_point, self.mid_point.as_ref(), &self.end_point, &self.points)
+    }
+
+    // A Pending measurement of 1 m with only the fields a record needs, for
+    // tests to adjust
+    #[cfg(test)]
+    fn fixture(id: &str) -> Measurement {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "image_path": format!("uploads/{}.jpg", id),
+            "start_point": {"x": 0.0, "y": 0.0, "z": 0.0},
+            "end_point": {"x": 100000.0, "y": 0.0, "z": 0.0},
+            "status": "Pending",
+            "attestation": null,
+        }))
+        .unwrap()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
 enum ProofStatus {
+    // Accepted outside the intake windows, waiting for them to open
+    Scheduled,
     Pending,
     Processing,
     Completed,
     Failed,
+    // Withdrawn by its submitter or an admin before it finished
+    Cancelled,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+enum FailureClass {
+    ProofGeneration,
+    Verification,
+    DeadlineExceeded,
+    // The server restarted, or its prover process exited, while the
+    // measurement was being proved or submitted
+    Interrupted,
+    // The stored image differs from the hash recorded or committed for it
+    CommitmentMismatch,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+struct Failure {
+    class: FailureClass,
+    message: String,
 }
 
 // AppState to store measurements
 struct AppState {
-    measurements: Mutex<HashMap<String, Measurement>>,
+    config: Config,
+    measurements: Measurements,
+    queue: ProofQueue,
+    prewarmer: Prewarmer,
+    jobs: JobRegistry,
+    audit: AuditLog,
+    api_keys: ApiKeys,
+    page_cursors: listing::PageCursors,
+    pending_deletions: PendingDeletions,
+    summaries: Summaries,
+    toolchain: ToolchainCache,
+    // Toolchain probe of /health, reused for a minute
+    toolchain_check: health::ToolchainCheck,
+    signers: Signers,
+    external_ids: ExternalIdIndex,
+    idempotency_keys: IdempotencyIndex,
+    upload_progress: Arc<UploadProgress>,
+    // What the sweeps of uploads left behind by a crash reclaimed
+    abandoned_uploads: uploads::AbandonedUploads,
+    // Sessions of images uploaded in chunks, until submitted or expired
+    chunked_uploads: chunked_uploads::ChunkedUploads,
+    events: EventBus,
+    // Publisher of measurement events, when a broker is configured
+    broker: Option<Broker>,
+    // Build and circuit key versions loaded at startup
+    versions: VersionStamp,
+    // Outcome of the startup safety audit
+    safety: safety::Verdict,
+    // Read-only mode refusing changes while storage is migrated
+    maintenance: Maintenance,
+    // Disk budget and access times of the image variants
+    cache: DiskCache,
+    // Location and link signing key of support bundles
+    support: support::SupportBundles,
+    // Staleness of the replicated storage, on a read-only mirror
+    replica: Option<replica::Replica>,
+    // Allowed skew of client clocks and the skew observed
+    clock: timecheck::ClockSkew,
+    // Wall-clock time, steppable in testing builds
+    time: clock::Clock,
+    // Client deadlines on the monotonic clock
+    deadlines: deadline::Deadlines,
+    // Proving keys held in memory between proofs
+    proving_keys: Arc<proving_keys::ProvingKeys>,
+    // Proving keys read for the native prover, with PROVER=native
+    native_prover: Arc<native_prover::NativeProver>,
+    // zkVerify RPC endpoints and their health
+    rpc: rpc::Endpoints,
+    // Networks attesting proofs, and which circuit uses which
+    attestation_backends: attestation_backend::Backends,
+    // Measurements awaiting their attestation
+    attestations: attestations::AttestationPoller,
+    // Sampled proofs repeated by the shadow prover and their comparisons
+    shadow: shadow::Shadow,
+    // Content hashes of the published circuit artifacts
+    circuit_artifacts: circuit_artifacts::ArtifactHashes,
+    // Cursor and settings of the derived-field backfill
+    backfill: backfill::Backfill,
+    // Child process generating the proofs, with --supervise
+    prover_process: Option<Arc<supervisor::ProverProcess>>,
+    // Tokens stopping the work on measurements that are cancelled
+    cancellations: cancel::Cancellations,
+    // Timings of the startup phases and the consistency scan
+    startup: startup::Startup,
+    // Proof durations for the daily usage reports
+    telemetry: telemetry::Telemetry,
+    // Store reads shared by concurrent status requests
+    status_reads: status_reads::StatusReads,
+    // Checks of images against their commitments before completion
+    commitments: commitment::CommitmentChecks,
+    webhooks: webhooks::Webhooks,
+}
+
+// Status response: the measurement plus its live queue position while pending
+#[derive(Serialize, JsonSchema)]
+#[schemars(transform = threshold::private_fields_optional)]
+struct MeasurementStatus {
+    // Without its points and length for threshold measurements
+    #[serde(flatten, serialize_with = "threshold::serialize_public")]
+    measurement: Measurement,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    queue: Option<QueuePosition>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deadline_remaining_seconds: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    embargo: Option<embargo::EmbargoState>,
+    // Every proof generation of the original measurement, once it has been re-proved
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    generations: Vec<reprove::Generation>,
+    // What the measurement is still expected to produce, in strict mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pending_items: Option<Vec<pending::PendingItem>>,
+    // Whether the proved points were converted to the canonical convention
+    canonical_points: bool,
 }
 
 // Response for successful measurement submission
@@ -65,53 +444,382 @@ struct AppState {
 struct MeasurementResponse {
     url: String,
     measurement_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    external_id: Option<String>,
+}
+
+impl MeasurementResponse {
+    fn new(base_url: &str, id: String, external_id: Option<String>) -> Self {
+        MeasurementResponse {
+            url: format!("{}/status/{}", base_url, id),
+            measurement_id: id,
+            external_id,
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() {
-    // Ensure we have directories for storing data
-    fs::create_dir_all("uploads").unwrap_or_else(|_| {
-        println!("Failed to create uploads directory or it already exists");
+    let startup = startup::Startup::new();
+    let mut config = Config::from_env().unwrap_or_else(|e| {
+        println!("Invalid configuration: {}", e);
+        std::process::exit(1);
+    });
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let doctor = doctor::take_command(&mut args);
+    safety::apply_args(&mut config, args.into_iter()).unwrap_or_else(|e| {
+        println!("Invalid arguments: {}", e);
+        std::process::exit(1);
     });
+    circuit::configure(&config.angle_circuit_wasm, &config.angle_circuit_zkey);
+    if let Some(output) = doctor {
+        std::process::exit(doctor::run_command(&config, output).await);
+    }
+    if config.role == ProcessRole::Worker {
+        supervisor::run_worker(config).await;
+        return;
+    }
+
+    // A mirror only opens the replicated directories and never creates them
+    let replica = if config.read_only {
+        let replica = replica::Replica::open(config.replica_max_lag_secs).unwrap_or_else(|e| {
+            println!("{}", e);
+            std::process::exit(1);
+        });
+        println!("Starting as a read-only mirror: changes are refused and no workers run");
+        Some(replica)
+    } else {
+        // Ensure we have directories for storing data
+        fs::create_dir_all("uploads").unwrap_or_else(|_| {
+            println!("Failed to create uploads directory or it already exists");
+        });
 
-    fs::create_dir_all("proofs").unwrap_or_else(|_| {
-        println!("Failed to create proofs directory or it already exists");
+        fs::create_dir_all("proofs").unwrap_or_else(|_| {
+            println!("Failed to create proofs directory or it already exists");
+        });
+        None
+    };
+
+    // Tenant mode is pointless without keys, so it needs a global admin key
+    if config.tenants.enabled() {
+        if config.admin_api_key.is_none() {
+            println!("Invalid configuration: TENANTS requires ADMIN_API_KEY");
+            std::process::exit(1);
+        }
+        for tenant in config.tenants.names().filter(|_| !config.read_only) {
+            for dir in ["uploads", "proofs"] {
+                fs::create_dir_all(format!("{}/{}", dir, tenant)).unwrap_or_else(|e| {
+                    println!("Failed to create {}/{}: {}", dir, tenant, e);
+                });
+            }
+        }
+        println!("Tenant mode enabled for {} tenants", config.tenants.names().count());
+    }
+
+    let broker = Broker::connect(&config).await.unwrap_or_else(|e| {
+        println!("Failed to set up the message broker: {}", e);
+        std::process::exit(1);
+    });
+
+    let api_keys = ApiKeys::load(config.api_keys_path.clone()).unwrap_or_else(|e| {
+        println!("{}", e);
+        std::process::exit(1);
+    });
+    let page_cursors = listing::PageCursors::load(&config.cursor_secret_path).unwrap_or_else(|e| {
+        println!("{}", e);
+        std::process::exit(1);
+    });
+    if config.admin_api_key.is_none() && api_keys.is_empty() {
+        println!(
+            "Warning: no ADMIN_API_KEY or API keys configured, every caller has full access \
+             until the first key is created"
+        );
+    }
+    let safety = safety::enforce(&config, &api_keys).unwrap_or_else(|e| {
+        println!("Unsafe configuration: {}", e);
+        std::process::exit(1);
+    });
+
+    let maintenance = Maintenance::load(&config).unwrap_or_else(|e| {
+        println!("{}", e);
+        std::process::exit(1);
+    });
+    if maintenance.is_on() {
+        println!("Starting in maintenance mode: changes are refused until it is turned off");
+    }
+
+    // A mirror serves no records, so it leaves the store to the primary
+    let began = Instant::now();
+    let store = if config.read_only {
+        Ok(Arc::new(store::MemoryStore::default()) as Arc<dyn store::MeasurementStore>)
+    } else {
+        store::open_store(&config).await
+    };
+    let measurements = match store {
+        Ok(store) => {
+            let cache = CacheLimits::from_config(&config);
+            Measurements::load(store, cache, clock::Clock::new(&config)).await
+        }
+        Err(e) => Err(e),
+    }
+    .unwrap_or_else(|e| {
+        println!("{}", e);
+        std::process::exit(1);
+    });
+    startup.phase("store", began);
+
+    let cache = DiskCache::load(&config).unwrap_or_else(|e| {
+        println!("{}", e);
+        std::process::exit(1);
     });
 
+    let support = support::SupportBundles::new(&config);
+    let clock = timecheck::ClockSkew::new(&config);
+    let proving_keys = Arc::new(proving_keys::ProvingKeys::new(config.proving_key_pin));
+    // A supervising server leaves proving, and the keys, to its prover child
+    let native_prover = if config.supervise {
+        Arc::new(native_prover::NativeProver::default())
+    } else {
+        let began = Instant::now();
+        let prover = native_prover::NativeProver::load(&config);
+        startup.phase("native prover", began);
+        Arc::new(prover)
+    };
+    let prover_process = if config.supervise {
+        let process = supervisor::ProverProcess::start(&config).unwrap_or_else(|e| {
+            println!("{}", e);
+            std::process::exit(1);
+        });
+        Some(process)
+    } else {
+        None
+    };
+
+    let versions = VersionStamp::current(&circuit::ZKHOTDOG);
+    println!(
+        "zkHotdog server {} ({}), circuit {}",
+        versions.server_version, versions.git_hash, versions.circuit_version
+    );
+
     // Create shared application state
     let app_state = Arc::new(AppState {
-        measurements: Mutex::new(HashMap::new()),
+        queue: ProofQueue::new(config.queue_policy, config.proof_workers),
+        measurements,
+        prewarmer: Prewarmer::new(),
+        jobs: JobRegistry::default(),
+        audit: AuditLog::new(config.audit_log_path.clone()),
+        api_keys,
+        page_cursors,
+        pending_deletions: PendingDeletions::default(),
+        summaries: Summaries::new(config.summaries_path.clone()),
+        toolchain: ToolchainCache::default(),
+        toolchain_check: health::ToolchainCheck::default(),
+        signers: Signers::new(Duration::from_secs(config.signer_stuck_secs)),
+        rpc: rpc::Endpoints::new(&config),
+        time: clock::Clock::new(&config),
+        deadlines: deadline::Deadlines::new(&config),
+        shadow: shadow::Shadow::new(&config),
+        telemetry: telemetry::Telemetry::new(&config),
+        status_reads: status_reads::StatusReads::new(&config),
+        commitments: commitment::CommitmentChecks::default(),
+        webhooks: webhooks::Webhooks::new(&config),
+        attestation_backends: attestation_backend::Backends::new(&config),
+        attestations: attestations::AttestationPoller::new(&config),
+        circuit_artifacts: circuit_artifacts::ArtifactHashes::default(),
+        backfill: backfill::Backfill::new(&config),
+        external_ids: ExternalIdIndex::default(),
+        idempotency_keys: IdempotencyIndex::default(),
+        upload_progress: Arc::new(UploadProgress::default()),
+        abandoned_uploads: uploads::AbandonedUploads::default(),
+        chunked_uploads: chunked_uploads::ChunkedUploads::default(),
+        events: EventBus::new(),
+        broker,
+        config,
+        versions,
+        safety,
+        maintenance,
+        cache,
+        support,
+        replica,
+        clock,
+        proving_keys,
+        native_prover,
+        prover_process: prover_process.clone(),
+        cancellations: cancel::Cancellations::default(),
+        startup,
     });
 
-    // Configure CORS
+    tokio::spawn(toolchain::refresher(app_state.clone()));
+    tokio::spawn(store::syncer(app_state.clone()));
+    if app_state.replica.is_some() {
+        tokio::spawn(replica::watcher(app_state.clone()));
+    } else {
+        // Only unfinished measurements are recovered before serving
+        let began = Instant::now();
+        store::recover(&app_state).await;
+        app_state.startup.phase("recovery", began);
+        start_workers(&app_state);
+    }
+
+    // Forward measurement events to the message broker
+    if app_state.broker.is_some() && app_state.replica.is_none() {
+        println!("Publishing measurement events to {}", app_state.config.broker);
+        broker::start(app_state.clone());
+    }
+
+    // Configure CORS, allowing any origin unless CORS_ALLOWED_ORIGINS lists them
+    let origins = &app_state.config.cors_allowed_origins;
+    let allow_origin = if origins.is_empty() || origins.iter().any(|o| o == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(origins.iter().map(|origin| {
+            origin.parse().unwrap_or_else(|e| {
+                println!("Invalid origin {} in CORS_ALLOWED_ORIGINS: {}", origin, e);
+                std::process::exit(1);
+            })
+        }))
+    };
     let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE, Method::OPTIONS])
+        .allow_origin(allow_origin)
+        .allow_methods([
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::PATCH,
+            Method::DELETE,
+            Method::OPTIONS,
+        ])
         .allow_headers(Any);
 
     // Build our application with routes
     let app = Router::new()
-        .route("/measurements", post(handle_measurement))
-        .route("/status/{id}", get(check_proof_status))
+        .route(
+            "/measurements",
+            post(handle_measurement)
+                .layer(DefaultBodyLimit::max(uploads::MAX_REQUEST_BYTES))
+                .get(listing::list_measurements),
+        )
+        .route(
+            "/measurements/json",
+            post(json_submission::submit_json)
+                .layer(DefaultBodyLimit::max(app_state.config.json_max_bytes)),
+        )
+        .route(
+            "/measurements/import",
+            post(bundle::import_bundle)
+                .layer(DefaultBodyLimit::max(app_state.config.import_max_bytes)),
+        )
+        .route(
+            "/measurements/{id}",
+            patch(embargo::patch_measurement).delete(admin::delete_measurement),
+        )
+        .route("/measurements/{id}/artifacts/{name}", get(downloads::serve_artifact))
+        .route("/measurements/{id}/log", get(downloads::serve_log))
+        .route("/measurements/{id}/history", get(history::history))
+        .route("/proofs/{id}", get(proofs::get_proof))
+        .route("/attestation/{id}", get(contract::get_attestation))
+        .route("/measurements/{id}/reprove", post(reprove::reprove))
+        .route("/measurements/{id}/retry", post(retry::retry))
+        .route("/measurements/{id}/cancel", post(cancel::cancel))
+        .route("/measurements/by-external-id/{external_id}", get(external_id::get_by_external_id))
+        .route(status_batch::PATH, post(status_batch::batch_status))
+        .route("/status/{id}", get(status_reads::get_status))
+        .route("/status/{id}/events", get(status_events::stream))
+        .route("/ws", get(websocket::socket))
+        .route("/view/{id}", get(view::status_page))
+        .route("/uploads/progress/{id}", get(uploads::get_progress))
+        .route("/uploads", post(chunked_uploads::create))
+        .route("/uploads/{id}", get(chunked_uploads::get_session))
+        .route(
+            "/uploads/{id}/chunks/{n}",
+            put(chunked_uploads::put_chunk)
+                .layer(DefaultBodyLimit::max(app_state.config.upload_chunk_max_bytes)),
+        )
+        .route("/uploads/{id}/complete", post(chunked_uploads::complete))
         .route("/img/{id}", get(serve_image))
+        .route("/img/{id}/2", get(serve_second_image))
+        .route("/depth/{id}", get(depth_map::serve_depth_map))
+        .route("/img/{id}/thumb/{size}", get(thumbnails::serve_thumbnail))
+        .route("/img/{id}/webp", get(thumbnails::serve_webp))
+        .route("/version", get(version::version))
+        .route("/capabilities", get(capabilities::capabilities))
+        .route("/schemas", get(schemas::list))
+        .route("/schemas/{name}/{file}", get(schemas::get))
+        .route("/circuits/{version}/{artifact}", get(circuit_artifacts::serve))
+        .route("/health", get(health::health))
+        .route("/ready", get(health::ready))
+        .route("/admin/stats", get(admin::stats))
+        .route("/admin/doctor", get(doctor::doctor))
+        .route("/admin/summaries", get(summary::list))
+        .route("/admin/summaries/{date}", post(summary::regenerate))
+        .route("/admin/telemetry/preview", get(telemetry::preview))
+        .route("/admin/measurements/delete", post(admin::batch_delete))
+        .route("/admin/measurements/reprove", post(reprove::bulk_reprove))
+        .route("/admin/backfill", post(backfill::start))
+        .route("/admin/jobs/{id}", get(jobs::get_job))
+        .route("/admin/export", get(backup::export))
+        .route("/admin/import", post(backup::import))
+        .route("/admin/duplicates", get(similarity::list_duplicates))
+        .route("/admin/shadow", get(shadow::report))
+        .route("/admin/support-bundle", post(support::create))
+        .route("/support-bundles/{name}", get(support::download))
+        .route("/admin/signers", get(signer::list))
+        .route(
+            "/admin/maintenance",
+            get(maintenance::get_maintenance).put(maintenance::set_maintenance),
+        )
+        .route("/admin/keys", post(auth::create_key).get(auth::list_keys))
+        .route("/admin/keys/{id}", patch(auth::update_key).delete(auth::delete_key))
+        .route("/admin/keys/{id}/disable", post(auth::disable_key))
+        .route("/admin/keys/{id}/enable", post(auth::enable_key))
+        .route("/admin/keys/{id}/rotate", post(auth::rotate_key));
+
+    #[cfg(feature = "submission-compare")]
+    let app = app.route("/admin/submissions/{id}/compare", post(submission::compare));
+
+    let app = app
+        .layer(middleware::from_fn_with_state(app_state.clone(), maintenance::reject_mutations))
+        .layer(middleware::from_fn_with_state(app_state.clone(), replica::read_only))
         .layer(cors)
-        .with_state(app_state);
+        .with_state(app_state.clone());
 
     // Run the server
     let addr = SocketAddr::from(([0, 0, 0, 0], 3001));
     println!("Server listening on {}", addr);
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3001").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    app_state.startup.ready();
+    // Measurements missing from the store are looked for once requests are served
+    if app_state.replica.is_some() {
+        app_state.startup.scan_skipped();
+    } else {
+        reconcile::start(&app_state);
+    }
+    let server = axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>());
+    match prover_process {
+        // The prover process is stopped before the server exits
+        Some(process) => tokio::select! {
+            result = async move { server.await } => result.unwrap(),
+            () = supervisor::shutdown_signal() => process.shutdown().await,
+        },
+        None => server.await.unwrap(),
+    }
 }
 
 // Handler for receiving measurement data
 async fn handle_measurement(
     State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Submitter>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     mut multipart: Multipart,
-) -> Result<Json<MeasurementResponse>, (StatusCode, String)> {
-    let mut image_data: Option<Bytes> = None;
-    let mut start_point: Option<Point3D> = None;
-    let mut end_point: Option<Point3D> = None;
+) -> Result<(StatusCode, Json<MeasurementResponse>), (StatusCode, String)> {
+    // Report upload progress to clients polling with the request's progress id
+    let progress =
+        state.upload_progress.track(&headers).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    let mut fields = SubmissionFields::from_headers(&headers);
+    // Coordinates sent as startX, startY, ... instead of startPoint and endPoint
+    let mut flat_points = FlatPoints::default();
 
     // Process multipart form data
     while let Some(field) = multipart.next_field().await.map_err(|e| {
@@ -121,15 +829,42 @@ async fn handle_measurement(
 
         match name.as_str() {
             "image" => {
-                image_data = Some(field.bytes().await.map_err(|e| {
-                    (StatusCode::BAD_REQUEST, format!("Failed to read image data: {}", e))
+                let upload = uploads::stream_to_disk(field, progress.as_ref()).await?;
+                fields.image = Some(IncomingImage::File(upload));
+            }
+            "image2" => {
+                let upload = uploads::stream_to_disk(field, progress.as_ref()).await?;
+                fields.image2 = Some(IncomingImage::File(upload));
+            }
+            "depthMap" => {
+                fields.depth_map =
+                    Some(read_field_limited(field, state.config.depth_map_max_bytes).await?);
+            }
+            "depthMapWidth" => {
+                fields.depth_map_width = Some(field.text().await.map_err(|e| {
+                    (StatusCode::BAD_REQUEST, format!("Failed to read depthMapWidth: {}", e))
+                })?);
+            }
+            "depthMapHeight" => {
+                fields.depth_map_height = Some(field.text().await.map_err(|e| {
+                    (StatusCode::BAD_REQUEST, format!("Failed to read depthMapHeight: {}", e))
+                })?);
+            }
+            "imageUrl" => {
+                fields.image_url = Some(field.text().await.map_err(|e| {
+                    (StatusCode::BAD_REQUEST, format!("Failed to read imageUrl: {}", e))
+                })?);
+            }
+            "uploadToken" => {
+                fields.upload_token = Some(field.text().await.map_err(|e| {
+                    (StatusCode::BAD_REQUEST, format!("Failed to read uploadToken: {}", e))
                 })?);
             }
             "startPoint" => {
                 let data = field.bytes().await.map_err(|e| {
                     (StatusCode::BAD_REQUEST, format!("Failed to read startPoint data: {}", e))
                 })?;
-                start_point = Some(serde_json::from_slice(&data).map_err(|e| {
+                fields.start_point = Some(serde_json::from_slice(&data).map_err(|e| {
                     (StatusCode::BAD_REQUEST, format!("Failed to parse startPoint JSON: {}", e))
                 })?);
             }
@@ -137,255 +872,1035 @@ async fn handle_measurement(
                 let data = field.bytes().await.map_err(|e| {
                     (StatusCode::BAD_REQUEST, format!("Failed to read endPoint data: {}", e))
                 })?;
-                end_point = Some(serde_json::from_slice(&data).map_err(|e| {
+                fields.end_point = Some(serde_json::from_slice(&data).map_err(|e| {
                     (StatusCode::BAD_REQUEST, format!("Failed to parse endPoint JSON: {}", e))
                 })?);
             }
+            "midPoint" => {
+                let data = field.bytes().await.map_err(|e| {
+                    (StatusCode::BAD_REQUEST, format!("Failed to read midPoint data: {}", e))
+                })?;
+                fields.mid_point = Some(serde_json::from_slice(&data).map_err(|e| {
+                    (StatusCode::BAD_REQUEST, format!("Failed to parse midPoint JSON: {}", e))
+                })?);
+            }
+            "minLengthCm" => {
+                fields.min_length_cm = Some(field.text().await.map_err(|e| {
+                    (StatusCode::BAD_REQUEST, format!("Failed to read minLengthCm: {}", e))
+                })?);
+            }
+            "measurementType" => {
+                fields.measurement_type = Some(field.text().await.map_err(|e| {
+                    (StatusCode::BAD_REQUEST, format!("Failed to read measurementType: {}", e))
+                })?);
+            }
+            "points" => {
+                let data = field.bytes().await.map_err(|e| {
+                    (StatusCode::BAD_REQUEST, format!("Failed to read points data: {}", e))
+                })?;
+                fields.points = Some(serde_json::from_slice(&data).map_err(|e| {
+                    (StatusCode::BAD_REQUEST, format!("Failed to parse points JSON: {}", e))
+                })?);
+            }
+            "metadata" => {
+                fields.metadata = Some(read_field_limited(field, metadata::MAX_RAW_BYTES).await?);
+            }
+            "clientInfo" => {
+                // Oversized client info is a malformed field, not a large upload
+                fields.client_info = Some(
+                    read_field_limited(field, client_info::MAX_RAW_BYTES)
+                        .await
+                        .map_err(|(_, e)| (StatusCode::BAD_REQUEST, e))?,
+                );
+            }
+            "deadline" => {
+                fields.deadline = Some(field.text().await.map_err(|e| {
+                    (StatusCode::BAD_REQUEST, format!("Failed to read deadline: {}", e))
+                })?);
+            }
+            "publishAt" => {
+                fields.publish_at = Some(field.text().await.map_err(|e| {
+                    (StatusCode::BAD_REQUEST, format!("Failed to read publishAt: {}", e))
+                })?);
+            }
+            "coordinateSystem" => {
+                fields.coordinate_system = Some(field.text().await.map_err(|e| {
+                    (StatusCode::BAD_REQUEST, format!("Failed to read coordinateSystem: {}", e))
+                })?);
+            }
+            "externalId" => {
+                fields.external_id = Some(field.text().await.map_err(|e| {
+                    (StatusCode::BAD_REQUEST, format!("Failed to read externalId: {}", e))
+                })?);
+            }
+            "idempotencyKey" => {
+                fields.idempotency_key = Some(field.text().await.map_err(|e| {
+                    (StatusCode::BAD_REQUEST, format!("Failed to read idempotencyKey: {}", e))
+                })?);
+            }
+            "callbackUrl" => {
+                fields.callback_url = Some(field.text().await.map_err(|e| {
+                    (StatusCode::BAD_REQUEST, format!("Failed to read callbackUrl: {}", e))
+                })?);
+            }
+            "skipStages" => {
+                fields.skip_stages = Some(field.text().await.map_err(|e| {
+                    (StatusCode::BAD_REQUEST, format!("Failed to read skipStages: {}", e))
+                })?);
+            }
+            name if FlatPoints::is_field(name) => {
+                let value = field.text().await.map_err(|e| {
+                    (StatusCode::BAD_REQUEST, format!("Failed to read {}: {}", name, e))
+                })?;
+                flat_points.set(name, &value).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+            }
             _ => {
                 println!("Unexpected field: {}", name);
             }
         }
     }
 
+    fields.start_point = flat_points
+        .resolve(flat_points::End::Start, fields.start_point.take())
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    fields.end_point = flat_points
+        .resolve(flat_points::End::End, fields.end_point.take())
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    submit_fields(&state, caller, remote, fields).await
+}
+
+// Fields of a new measurement as the client sent them, from a multipart form
+// or a JSON body (json_submission.rs)
+#[derive(Default)]
+struct SubmissionFields {
+    image: Option<IncomingImage>,
+    // Where to fetch the image from, instead of sending it
+    image_url: Option<String>,
+    // Token of a completed chunked upload holding the image
+    upload_token: Option<String>,
+    // Second photo, such as a close-up next to the overview in image
+    image2: Option<IncomingImage>,
+    // Depth map as sent, with the size of a raw one
+    depth_map: Option<Vec<u8>>,
+    depth_map_width: Option<String>,
+    depth_map_height: Option<String>,
+    start_point: Option<Point3D>,
+    end_point: Option<Point3D>,
+    // Every point of a polyline, instead of the start and end point
+    points: Option<Vec<Point3D>>,
+    // Where an angle measurement bends
+    mid_point: Option<Point3D>,
+    measurement_type: Option<String>,
+    // Minimum length to prove instead of the length itself
+    min_length_cm: Option<String>,
+    metadata: Option<Vec<u8>>,
+    client_info: Option<Vec<u8>>,
+    deadline: Option<String>,
+    publish_at: Option<String>,
+    coordinate_system: Option<String>,
+    external_id: Option<String>,
+    idempotency_key: Option<String>,
+    callback_url: Option<String>,
+    skip_stages: Option<String>,
+}
+
+impl SubmissionFields {
+    // The deadline and idempotency key may come from headers; fields win
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let header =
+            |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+        SubmissionFields {
+            deadline: header("deadline"),
+            idempotency_key: header("idempotency-key"),
+            ..SubmissionFields::default()
+        }
+    }
+}
+
+// Validate the fields of a new measurement, scale its points and submit it
+async fn submit_fields(
+    state: &Arc<AppState>,
+    caller: Caller,
+    remote: SocketAddr,
+    fields: SubmissionFields,
+) -> Result<(StatusCode, Json<MeasurementResponse>), (StatusCode, String)> {
     // Ensure we have all required data
-    let image_data =
-        image_data.ok_or((StatusCode::BAD_REQUEST, "Missing image data".to_string()))?;
-    let start_point =
-        start_point.ok_or((StatusCode::BAD_REQUEST, "Missing start point data".to_string()))?;
-    let end_point =
-        end_point.ok_or((StatusCode::BAD_REQUEST, "Missing end point data".to_string()))?;
-
-    let start_point = Point3D {
-        x: (start_point.x * 100000.0).round(),
-        y: (start_point.y * 100000.0).round(),
-        z: (start_point.z * 100000.0).round(),
+    let image = match (fields.image, fields.image_url, fields.upload_token) {
+        (Some(image), None, None) => image,
+        (None, Some(url), None) => IncomingImage::Url(
+            image_url::validate(&url).map_err(|e| (StatusCode::BAD_REQUEST, e))?,
+        ),
+        (None, None, Some(token)) => {
+            let token = token.trim().to_string();
+            state.chunked_uploads.check(&token, &caller).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+            IncomingImage::Upload(token)
+        }
+        (None, None, None) => {
+            return Err((StatusCode::BAD_REQUEST, "Missing image data".to_string()));
+        }
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Send only one of image, imageUrl and uploadToken".to_string(),
+            ));
+        }
     };
-    let end_point = Point3D {
-        x: (end_point.x * 100000.0).round(),
-        y: (end_point.y * 100000.0).round(),
-        z: (end_point.z * 100000.0).round(),
+    let measurement_type = match fields.measurement_type {
+        Some(value) => value.parse::<MeasurementType>().map_err(|e| {
+            (StatusCode::BAD_REQUEST, format!("Invalid measurementType: {}", e))
+        })?,
+        None => MeasurementType::default(),
     };
+    let mid_point = match (measurement_type, fields.mid_point) {
+        (MeasurementType::Angle, None) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Missing mid point data: an angle measurement needs midPoint".to_string(),
+            ));
+        }
+        (MeasurementType::Angle, Some(_)) if fields.points.is_some() => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "An angle measurement takes a start, mid and end point, not points".to_string(),
+            ));
+        }
+        (MeasurementType::Length, Some(_)) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "midPoint is only accepted with measurementType angle".to_string(),
+            ));
+        }
+        (_, mid_point) => mid_point,
+    };
+    let min_length_cm = fields
+        .min_length_cm
+        .map(|value| threshold::parse(&value))
+        .transpose()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid minLengthCm: {}", e)))?;
+    let (start_point, end_point, points) = match fields.points {
+        Some(_) if fields.start_point.is_some() || fields.end_point.is_some() => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Send either points or a start and end point, not both".to_string(),
+            ));
+        }
+        Some(points) => polyline::split(points, state.config.max_polyline_points)
+            .map_err(|e| (StatusCode::BAD_REQUEST, e))?,
+        None => (
+            fields
+                .start_point
+                .ok_or((StatusCode::BAD_REQUEST, "Missing start point data".to_string()))?,
+            fields
+                .end_point
+                .ok_or((StatusCode::BAD_REQUEST, "Missing end point data".to_string()))?,
+            Vec::new(),
+        ),
+    };
+    if min_length_cm.is_some() && (measurement_type == MeasurementType::Angle || !points.is_empty())
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "minLengthCm is only accepted for a length between two points".to_string(),
+        ));
+    }
 
-    // Generate a unique ID for this measurement
-    let id = Uuid::new_v4().to_string();
+    let metadata = match fields.metadata {
+        Some(data) => metadata::parse(&data).map_err(|e| (StatusCode::BAD_REQUEST, e))?,
+        None => Metadata::default(),
+    };
+    let client_info = fields
+        .client_info
+        .map(|data| client_info::parse(&data))
+        .transpose()
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let depth_map = depth_map::validate(
+        fields.depth_map,
+        fields.depth_map_width,
+        fields.depth_map_height,
+        state.config.depth_map_max_bytes,
+    )?;
+    let external_id = fields
+        .external_id
+        .map(|value| external_id::validate(&value))
+        .transpose()
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let callback_url = fields
+        .callback_url
+        .map(|value| webhooks::validate_url(&state.webhooks, &value))
+        .transpose()
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let skip_stages = match fields.skip_stages {
+        Some(value) => plan::parse_skips(&value)
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid skipStages: {}", e)))?,
+        None => Vec::new(),
+    };
+    let idempotency_key = fields
+        .idempotency_key
+        .map(|value| idempotency::validate(&value))
+        .transpose()
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let coordinate_system = coords::coordinate_system(
+        fields.coordinate_system.as_deref(),
+        state.config.strict_coordinate_system,
+    )
+    .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
 
-    // Save the image to disk
-    let file_name = format!("{}.jpg", id);
-    let image_path = format!("uploads/{}", file_name);
-    save_file(&image_path, &image_data)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save image: {}", e)))?;
+    // Convert to the canonical convention and the fixed-point representation
+    // used by the circuit, keeping the points as captured alongside
+    let original_points = OriginalPoints {
+        start: start_point.clone(),
+        end: end_point.clone(),
+        mid: mid_point.clone(),
+        points,
+    };
+    let start_point = coords::canonical_point(coordinate_system, &start_point)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid start point: {}", e)))?;
+    let end_point = coords::canonical_point(coordinate_system, &end_point)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid end point: {}", e)))?;
+    let mid_point = mid_point
+        .map(|point| coords::canonical_point(coordinate_system, &point))
+        .transpose()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid mid point: {}", e)))?;
+    if let Some(mid_point) = &mid_point {
+        angle::check_mid_point(&start_point, mid_point, &end_point)
+            .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    }
+    let points = original_points
+        .points
+        .iter()
+        .enumerate()
+        .map(|(index, point)| {
+            coords::canonical_point(coordinate_system, point).map_err(|e| {
+                (StatusCode::BAD_REQUEST, format!("Invalid point {} of points: {}", index, e))
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let deadline = fields
+        .deadline
+        .map(|value| deadline::parse(&value, &state.clock, state.time.now()))
+        .transpose()
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let publish_at = fields
+        .publish_at
+        .map(|value| deadline::parse_future("publishAt", &value, &state.clock, state.time.now()))
+        .transpose()
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    let submission = Submission {
+        owner: access::submitter(&caller, remote.ip()),
+        key_id: caller.key_id,
+        tenant: caller.tenant,
+        external_id,
+        idempotency_key,
+        image,
+        image2: fields.image2,
+        depth_map,
+        start_point,
+        end_point,
+        circuit: threshold::circuit_for(min_length_cm)
+            .or_else(|| angle::circuit_for(measurement_type, &points)),
+        points,
+        measurement_type,
+        mid_point,
+        min_length_cm,
+        original_points: Some(original_points),
+        coordinate_system,
+        metadata,
+        client_info,
+        deadline,
+        publish_at,
+        captured_at: None,
+        imported: false,
+        reprove_of: None,
+        callback_url,
+        skip_stages,
+    };
+    submit(state, submission).await
+}
+
+// A validated measurement ready to be stored and queued
+struct Submission {
+    owner: String,
+    // Stored API key the measurement was submitted with
+    key_id: Option<String>,
+    tenant: Option<String>,
+    external_id: Option<String>,
+    idempotency_key: Option<String>,
+    image: IncomingImage,
+    image2: Option<IncomingImage>,
+    depth_map: Option<depth_map::IncomingDepthMap>,
+    // Points already converted to the circuit's fixed-point representation
+    start_point: Point3D,
+    end_point: Point3D,
+    // Every point of a polyline of more than two points, empty otherwise
+    points: Vec<Point3D>,
+    measurement_type: MeasurementType,
+    // Where an angle measurement bends, None for lengths
+    mid_point: Option<Point3D>,
+    // Minimum length a threshold measurement proves
+    min_length_cm: Option<f64>,
+    // Absent when re-proving a measurement recorded before originals were kept
+    original_points: Option<OriginalPoints>,
+    coordinate_system: CoordinateSystem,
+    metadata: Metadata,
+    client_info: Option<client_info::ClientInfo>,
+    deadline: Option<DateTime<Utc>>,
+    publish_at: Option<DateTime<Utc>>,
+    captured_at: Option<DateTime<Utc>>,
+    imported: bool,
+    reprove_of: Option<String>,
+    circuit: Option<String>,
+    callback_url: Option<String>,
+    // Stages the submitter asked to leave out of the plan
+    skip_stages: Vec<plan::StageName>,
+}
+
+// Store a new measurement and queue it for proof generation
+async fn submit(
+    state: &Arc<AppState>,
+    submission: Submission,
+) -> Result<(StatusCode, Json<MeasurementResponse>), (StatusCode, String)> {
+    let Submission {
+        owner,
+        key_id,
+        tenant,
+        external_id,
+        idempotency_key,
+        image,
+        image2,
+        depth_map,
+        start_point,
+        end_point,
+        points,
+        measurement_type,
+        mid_point,
+        min_length_cm,
+        original_points,
+        coordinate_system,
+        metadata,
+        client_info,
+        deadline,
+        publish_at,
+        captured_at,
+        imported,
+        reprove_of,
+        circuit,
+        callback_url,
+        skip_stages,
+    } = submission;
+
+    // Idempotency keys and external ids are unique per owner within a tenant
+    let owner_key = tenants::owner_key(tenant.as_deref(), &owner);
+    if let Some(key) = &idempotency_key
+        && let Some(existing) = state.idempotency_keys.get(&owner_key, key)
+        && let Some(existing) = state.measurements.lock().get(&existing).cloned()
+    {
+        return idempotency::replay(
+            state,
+            &existing,
+            key,
+            original_points.as_ref(),
+            coordinate_system,
+        );
+    }
+    if let Some(external_id) = &external_id
+        && let Some(existing) = state.external_ids.get(&owner_key, external_id)
+    {
+        return Ok(duplicate_response(state, existing, external_id));
+    }
+
+    // Refuse inputs the circuit cannot prove before any work is spent on them
+    let path = angle::path(&start_point, mid_point.as_ref(), &end_point, &points);
+    inputs::construct(&path, min_length_cm, &circuit::resolve(circuit.as_deref())).map_err(|e| {
+        (StatusCode::BAD_REQUEST, format!("The measurement cannot be proved: {}", e))
+    })?;
+
+    // Outside the intake windows, reject or park the measurement until they open
+    let now = state.time.now();
+    let scheduled = match &state.config.intake_windows {
+        Some(schedule) if !schedule.is_open(now) => {
+            let opens = schedule.next_transition(now);
+            if state.config.intake_outside == OutsidePolicy::Reject {
+                let message = match opens {
+                    Some(at) => format!("Intake is closed until {}", at.to_rfc3339()),
+                    None => "Intake is closed".to_string(),
+                };
+                return Err((StatusCode::SERVICE_UNAVAILABLE, message));
+            }
+            Some(opens)
+        }
+        _ => None,
+    };
+
+    // An image given by its URL or upload token is only fetched or claimed
+    // once nothing else refuses it
+    let mut image = image.resolve(state).await?;
+
+    // Generate a unique ID for this measurement, never reusing a taken one.
+    // The ID is claimed by saving the image under it, which fails instead of
+    // replacing an image already there, so two submissions cannot both get
+    // it. The image is removed again unless the submission gets stored and
+    // queued, and so is the tenant claimed for the ID.
+    let mut transaction = UploadTransaction::default();
+    let id = loop {
+        let id = Uuid::new_v4().to_string();
+        if let Some(tenant) = &tenant {
+            if !artifacts::claim_tenant(&id, tenant) {
+                println!("Generated measurement ID {} is already taken, retrying", id);
+                continue;
+            }
+            transaction.track_tenant(&id);
+        }
+        if !state.measurements.lock().contains_key(&id) {
+            let path = Artifact::Image.path(&id);
+            match image.save(&path) {
+                Ok(()) => {
+                    transaction.track(&path);
+                    break id;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+                Err(e) => {
+                    return Err((
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("Failed to save image: {}", e),
+                    ));
+                }
+            }
+        }
+        println!("Generated measurement ID {} is already taken, retrying", id);
+    };
+
+    let image_path = Artifact::Image.path(&id);
+    let mut image_paths = vec![image_path.clone()];
+    if let Some(mut image2) = image2 {
+        let path = Artifact::SecondImage.path(&id);
+        transaction.track(&path);
+        image2.save(&path).map_err(|e| {
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save second image: {}", e))
+        })?;
+        image_paths.push(path);
+    }
+    if let Some(depth_map) = &depth_map {
+        let path = Artifact::DepthMap.path(&id);
+        transaction.track(&path);
+        fs::write(&path, &depth_map.data).map_err(|e| {
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save depth map: {}", e))
+        })?;
+    }
+
+    // Hash the image to compare it with other owners' measurements
+    let perceptual_hash = if state.config.duplicate_mode == DuplicateMode::Off {
+        None
+    } else {
+        match similarity::hash_image(&image_path).await {
+            Ok(hash) => Some(hash),
+            Err(e) => {
+                println!("No perceptual hash for measurement {}: {}", id, e);
+                None
+            }
+        }
+    };
+
+    // Fields derived from the image; backfill.rs fills in ones that failed
+    let image_sha256 = backfill::image_sha256(&image_path)
+        .inspect_err(|e| println!("No image hash for measurement {}: {}", id, e))
+        .ok();
+    let image_dimensions = backfill::image_dimensions(&image_path)
+        .inspect_err(|e| println!("No image dimensions for measurement {}: {}", id, e))
+        .ok();
+    let length_cm = Some(coords::length_cm(&path));
 
     // Create a new measurement record
-    let measurement = Measurement {
+    let mut measurement = Measurement {
         id: id.clone(),
+        owner,
+        tenant,
+        external_id: external_id.clone(),
+        idempotency_key: idempotency_key.clone(),
         image_path,
+        image_paths,
+        depth_map: depth_map.map(|depth_map| depth_map.info),
         start_point,
         end_point,
-        status: ProofStatus::Pending,
+        points,
+        measurement_type,
+        mid_point,
+        original_points,
+        coordinate_system,
+        status: if scheduled.is_some() { ProofStatus::Scheduled } else { ProofStatus::Pending },
+        plan: plan::resolve(&state.config, &skip_stages),
         attestation: None,
+        artifacts: Artifacts::default(),
+        versions: MeasurementVersions {
+            created: Some(state.versions.clone()),
+            proved: None,
+        },
+        deadline,
+        failure: None,
+        metadata,
+        client_info,
+        publish_at,
+        publication_announced: false,
+        created_at: Some(now),
+        updated_at: Some(now),
+        captured_at,
+        imported,
+        scheduled_for: scheduled.flatten(),
+        circuit,
+        reprove_of: reprove_of.clone(),
+        reproofs: Vec::new(),
+        perceptual_hash,
+        image_sha256,
+        image_dimensions,
+        length_cm,
+        min_length_cm,
+        flagged_duplicate: None,
+        receipt: None,
+        attestation_backend: None,
+        callback_url,
+        webhook: None,
+        history: Vec::new(),
     };
+    let source = match (&reprove_of, imported) {
+        (Some(_), _) => Source::Reproof,
+        (None, true) => Source::Bundle,
+        (None, false) => Source::Submission,
+    };
+    history::record(&mut measurement, None, now, source);
+    measurement
+        .artifacts
+        .record_all(&id, &[Artifact::Image, Artifact::SecondImage, Artifact::DepthMap]);
+
+    // Store the measurement in our app state, re-checking the keys and ids now
+    // that the store is locked in case a concurrent submission claimed them
+    {
+        let mut measurements = state.measurements.lock();
+        if let Some(key) = &idempotency_key
+            && let Some(existing) = state.idempotency_keys.get(&owner_key, key)
+            && let Some(existing) = measurements.get(&existing)
+        {
+            return idempotency::replay(
+                state,
+                existing,
+                key,
+                measurement.original_points.as_ref(),
+                coordinate_system,
+            );
+        }
+        let duplicate = external_id
+            .as_ref()
+            .and_then(|external_id| state.external_ids.get(&owner_key, external_id));
+        if let (Some(existing), Some(external_id)) = (duplicate, &external_id) {
+            return Ok(duplicate_response(state, existing, external_id));
+        }
+        tenants::check_quota(&state.config.tenants, &measurements, measurement.tenant.as_deref())?;
+        if measurements.contains_key(&id) {
+            // The image was created by this submission, so it is removed
+            // again without touching the measurement holding the ID
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Measurement ID {} collided with an existing measurement", id),
+            ));
+        }
+        // Reproofs reuse their original's image and are never compared
+        if let Some(hash) = measurement.perceptual_hash.as_deref().filter(|_| reprove_of.is_none())
+            && let Some(flag) = similarity::closest_match(
+                &measurements,
+                hash,
+                &measurement.owner,
+                measurement.tenant.as_deref(),
+                state.config.duplicate_max_distance,
+            )
+        {
+            let details = json!({
+                "measurement_id": id,
+                "owner": measurement.owner,
+                "similar_to": flag.similar_to,
+                "distance": flag.distance,
+            });
+            if state.config.duplicate_mode == DuplicateMode::Reject {
+                drop(measurements);
+                state.audit.record("duplicate_rejected", details);
+                return Err((
+                    StatusCode::CONFLICT,
+                    "The image is too similar to a measurement submitted by someone else".to_string(),
+                ));
+            }
+            println!(
+                "Measurement {} flagged as a possible duplicate of {} (distance {})",
+                id, flag.similar_to, flag.distance
+            );
+            state.audit.record("duplicate_flagged", details);
+            measurement.flagged_duplicate = Some(flag);
+        }
+
+        if let Some(external_id) = &external_id {
+            state.external_ids.insert(&owner_key, external_id, &id);
+        }
+        if let Some(key) = &idempotency_key {
+            state.idempotency_keys.insert(&owner_key, key, &id);
+        }
+        // Held in memory now so concurrent submissions see the ids taken,
+        // and written to the store below
+        measurements.refresh(measurement.clone());
+    }
 
-    // Store the measurement in our app state
+    if let Err(e) = state.measurements.store().insert(&measurement).await {
+        state.measurements.lock().forget(&id);
+        if let Some(external_id) = &external_id {
+            state.external_ids.remove(&owner_key, external_id);
+        }
+        if let Some(key) = &idempotency_key {
+            state.idempotency_keys.remove(&owner_key, key);
+        }
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to store measurement {}: {}", id, e),
+        ));
+    }
+    if let Some(original) = reprove_of.as_ref()
+        && let Some(original) = state.measurements.lock().get_mut(original)
     {
-        let mut measurements = state.measurements.lock().unwrap();
-        measurements.insert(id.clone(), measurement.clone());
+        original.reproofs.push(id.clone());
     }
 
-    // Start the proof generation process in the background
-    tokio::spawn(start_proof_process(state.clone(), id.clone()));
+    state.events.publish(&measurement, EventKind::StatusChanged);
+
+    // Queue the measurement for proof generation, unless intake::releaser will
+    if scheduled.is_none() {
+        state.queue.push(&measurement.owner, &id);
+    }
+    if let Some(deadline) = deadline {
+        tokio::spawn(deadline::watch(state.clone(), id.clone(), deadline));
+    }
+    if state.config.thumbnail_prewarm {
+        state.prewarmer.enqueue(&id);
+    }
+    transaction.commit();
+    state.api_keys.record_measurement(key_id.as_deref());
 
     // Return response with URL to check status
-    Ok(Json(MeasurementResponse {
-        url: format!("http://localhost:3000/status/{}", id),
-        measurement_id: id,
-    }))
+    Ok((StatusCode::OK, Json(MeasurementResponse::new(&state.config.public_base_url, id, external_id))))
 }
 
-// Helper function to save files
-fn save_file(path: &str, data: &[u8]) -> std::io::Result<()> {
-    let mut file = File::create(path)?;
-    file.write_all(data)?;
-    Ok(())
+// Answer a submission reusing an external id with the existing measurement,
+// as a conflict unless EXTERNAL_ID_RETURN_EXISTING is set
+fn duplicate_response(
+    state: &AppState,
+    existing: String,
+    external_id: &str,
+) -> (StatusCode, Json<MeasurementResponse>) {
+    let status = if state.config.external_id_return_existing {
+        StatusCode::OK
+    } else {
+        StatusCode::CONFLICT
+    };
+    let response = MeasurementResponse::new(
+        &state.config.public_base_url,
+        existing,
+        Some(external_id.to_string()),
+    );
+    (status, Json(response))
+}
+
+// Read a multipart field, rejecting it as soon as it grows beyond the limit
+async fn read_field_limited(
+    mut field: axum::extract::multipart::Field<'_>,
+    limit: usize,
+) -> Result<Vec<u8>, (StatusCode, String)> {
+    let name = field.name().unwrap_or("").to_string();
+    let mut data = Vec::new();
+    while let Some(chunk) = field.chunk().await.map_err(|e| {
+        (StatusCode::BAD_REQUEST, format!("Failed to read {} data: {}", name, e))
+    })? {
+        if data.len() + chunk.len() > limit {
+            return Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Field {} exceeds the maximum size of {} bytes", name, limit),
+            ));
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
+// Start the background tasks that change measurements or files, none of
+// which run on a read-only mirror
+fn start_workers(state: &Arc<AppState>) {
+    println!(
+        "Starting {} proof workers with {} queue policy",
+        state.config.proof_workers, state.config.queue_policy
+    );
+    for worker in 1..=state.config.proof_workers {
+        tokio::spawn(proof_worker(state.clone(), worker));
+    }
+
+    // Start the image pre-warm workers
+    if state.config.thumbnail_prewarm {
+        for _ in 0..state.config.thumbnail_workers {
+            tokio::spawn(thumbnails::prewarm_worker(state.clone()));
+        }
+    }
+
+    tokio::spawn(embargo::ticker(state.clone()));
+    tokio::spawn(summary::scheduler(state.clone()));
+    tokio::spawn(telemetry::reporter(state.clone()));
+    tokio::spawn(webhooks::dispatcher(state.clone()));
+    tokio::spawn(intake::releaser(state.clone()));
+    tokio::spawn(cache::sweeper(state.clone()));
+    tokio::spawn(proving_keys::preload(state.clone()));
+    tokio::spawn(shadow::worker(state.clone()));
+    tokio::spawn(attestations::poller(state.clone()));
+    tokio::spawn(auth::usage_flusher(state.clone()));
+    tokio::spawn(backfill::resume(state.clone()));
+    tokio::spawn(retention::sweeper(state.clone()));
+    tokio::spawn(uploads::sweeper(state.clone()));
+    tokio::spawn(chunked_uploads::sweeper(state.clone()));
+}
+
+// Worker taking measurements off the proof queue one at a time, idle while
+// maintenance is on
+async fn proof_worker(state: Arc<AppState>, worker: usize) {
+    loop {
+        state.maintenance.wait_until_off().await;
+        let id = tokio::select! {
+            id = state.queue.pop() => id,
+            _ = state.maintenance.wait_until_on() => continue,
+        };
+        let started = Instant::now();
+        start_proof_process(state.clone(), id, Source::Worker(worker)).await;
+        state.queue.finished(started.elapsed());
+        state.summaries.record_proof(started.elapsed());
+        state.telemetry.record_proof(started.elapsed());
+    }
+}
+
+// Mark a measurement as failed and record why
+fn fail_measurement(
+    state: &AppState,
+    id: &str,
+    source: Source,
+    class: FailureClass,
+    message: String,
+) {
+    let mut measurements = state.measurements.lock_as(source);
+    // A cancelled measurement stays cancelled
+    if let Some(m) = measurements.get_mut(id).filter(|m| m.status != ProofStatus::Cancelled) {
+        plan::end_running(m, plan::StageState::Failed, Some(&message));
+        m.status = ProofStatus::Failed;
+        m.failure = Some(Failure { class, message });
+        state.events.publish(m, EventKind::StatusChanged);
+    }
 }
 
 // Background task to start the proof process
-async fn start_proof_process(state: Arc<AppState>, id: String) {
-    // Get a clone of the measurement before locking for update
-    let measurement = {
-        let measurements = state.measurements.lock().unwrap();
-        if let Some(m) = measurements.get(&id) {
-            m.clone()
-        } else {
+async fn start_proof_process(state: Arc<AppState>, id: String, worker: Source) {
+    // Read the stored measurement, after the changes that queued it
+    state.measurements.flushed().await;
+    let mut measurement = match state.measurements.store().get(&id).await {
+        Ok(Some(m)) if m.status == ProofStatus::Pending => m,
+        Ok(Some(_)) => {
+            println!("Skipping measurement {}: it is no longer pending", id);
+            return;
+        }
+        Ok(None) => {
             println!("Measurement not found: {}", id);
             return;
         }
+        Err(e) => {
+            println!("Failed to read measurement {}: {}", id, e);
+            return;
+        }
     };
 
-    // Update status to Processing
-    {
-        let mut measurements = state.measurements.lock().unwrap();
-        if let Some(m) = measurements.get_mut(&id) {
-            m.status = ProofStatus::Processing;
-        }
+    // Don't start work the client no longer wants
+    if state.deadlines.is_expired(&id, measurement.deadline) {
+        println!("Skipping measurement {}: deadline passed while queued", id);
+        let message = "Deadline passed before proof generation started".to_string();
+        fail_measurement(&state, &id, worker, FailureClass::DeadlineExceeded, message);
+        return;
     }
 
-    println!("Starting proof generation for measurement {}", id);
-
-    // Call snarkjs to generate witness and proof
-    let result = generate_snarkjs_proof(&id, &measurement).await;
+    // Taken before the claim, so a cancellation never misses the work
+    let cancellable = state.cancellations.register(&id);
+    // Measurements recorded before plans were kept run every stage
+    if measurement.plan.is_empty() {
+        measurement.plan = plan::full();
+    }
 
-    // Update status based on result
-    if result.is_ok() {
-        // Update status to Processing in a separate scope to release the lock
-        {
-            let mut measurements = state.measurements.lock().unwrap();
-            if let Some(m) = measurements.get_mut(&id) {
-                // Proof was generated successfully, now submit for verification
-                m.status = ProofStatus::Processing;
-            }
-        } // Lock is released here
-
-        // Now we can safely spawn a new task with a cloned state
-        let state_clone = state.clone();
-        let id_clone = id.clone();
-        tokio::spawn(async move {
-            println!("Submitting proof {} to zkVerify network...", id_clone);
-
-            // Run the TypeScript client using Node.js
-            let verify_result = tokio::process::Command::new("node")
-                .args(["dist/verify_client.js", &id_clone])
-                .current_dir(".") // Run from the current directory
-                .status()
-                .await;
-
-            // Update status based on verification result
-            let mut measurements = state_clone.measurements.lock().unwrap();
-            if let Some(m) = measurements.get_mut(&id_clone) {
-                m.status = match verify_result {
-                    Ok(status) if status.success() => {
-                        println!("Proof {} verified successfully on zkVerify network", id_clone);
-                        ProofStatus::Completed
-                    }
-                    _ => {
-                        println!("Proof {} verification failed on zkVerify network", id_clone);
-                        ProofStatus::Failed
-                    }
-                };
-            }
-        });
-    } else {
-        // In case of error, update status to Failed
-        let mut measurements = state.measurements.lock().unwrap();
-        if let Some(m) = measurements.get_mut(&id) {
-            println!("Proof generation failed: {:?}", result.err());
-            m.status = ProofStatus::Failed;
+    // Claim the measurement by moving it from Pending to Processing, which
+    // fails when another worker or instance got to it first
+    let claimed =
+        state.measurements.transition(measurement, ProofStatus::Processing, None, worker).await;
+    let measurement = match claimed {
+        Ok(Some(m)) => m,
+        Ok(None) => {
+            println!("Skipping measurement {}: claimed by another worker", id);
+            return;
         }
-    }
+        Err(e) => {
+            println!("Failed to claim measurement {}: {}", id, e);
+            return;
+        }
+    };
+    state.events.publish(&measurement, EventKind::StatusChanged);
+
+    plan::run(state, measurement, worker, cancellable).await;
 }
 
 // Use snarkjs to generate witness and proof
-async fn generate_snarkjs_proof(id: &str, measurement: &Measurement) -> Result<(), String> {
+async fn generate_snarkjs_proof(
+    state: &AppState,
+    id: &str,
+    measurement: &Measurement,
+) -> Result<(), PipelineError> {
     println!("Generating ZK proof using snarkjs for measurement {}", id);
 
     // Create a directory for this proof
-    let proof_dir = format!("proofs/{}", id);
+    let proof_dir = artifacts::proof_dir(id);
     fs::create_dir_all(&proof_dir)
-        .map_err(|e| format!("Failed to create proof directory: {}", e))?;
-
-    // Create input file for snarkjs
-    let input_path = format!("{}/input.json", proof_dir);
-
-    // Calculate the distance based on the coordinates
-    let dx = measurement.end_point.x as i32 - measurement.start_point.x as i32;
-    let dy = measurement.end_point.y as i32 - measurement.start_point.y as i32;
-    let dz = measurement.end_point.z as i32 - measurement.start_point.z as i32;
-    // Round to the nearest integer to ensure it's compatible with the circuit
-    let distance_squared = (dx * dx + dy * dy + dz * dz) as u32;
-
-    let input_json = serde_json::json!({
-        "point1": [
-            measurement.start_point.x,
-            measurement.start_point.y,
-            measurement.start_point.z
-        ],
-        "point2": [
-            measurement.end_point.x,
-            measurement.end_point.y,
-            measurement.end_point.z
-        ],
-        "distance_squared": distance_squared
-    });
+        .map_err(|source| PipelineError::Io { stage: Stage::Setup, source })?;
+
+    // Create input file for snarkjs from the measurement, never reusing a stale one
+    inputs::write(state, measurement)?;
+
+    // The prover process generates the witness and proof when there is one
+    let job = ProveJob {
+        id: id.to_string(),
+        tenant: measurement.tenant.clone(),
+        circuit: measurement.circuit.clone(),
+        start_point: measurement.start_point.clone(),
+        end_point: measurement.end_point.clone(),
+        points: measurement.points.clone(),
+        mid_point: measurement.mid_point.clone(),
+        min_length_cm: measurement.min_length_cm,
+        step_timeout_secs: state.config.proof_step_timeout_secs,
+        deadline_in_ms: measurement
+            .deadline
+            .map(|d| state.deadlines.remaining(id, d).as_millis() as u64),
+    };
+    let proving_time = match &state.prover_process {
+        Some(process) => process.prove(job).await?,
+        None => run_prover(&state.proving_keys, &state.native_prover, &job).await?,
+    };
+
+    // A sample is proved again by the shadow prover once workers are free
+    let circuit = circuit::resolve(measurement.circuit.as_deref());
+    state.shadow.sample(id, measurement.tenant.clone(), circuit, proving_time);
+
+    println!("Successfully generated proof for measurement {}", id);
+    Ok(())
+}
 
-    // Write input JSON to file
-    let input_content = serde_json::to_string_pretty(&input_json)
-        .map_err(|e| format!("Failed to serialize input JSON: {}", e))?;
-    fs::write(&input_path, input_content)
-        .map_err(|e| format!("Failed to write input file: {}", e))?;
+// Generate the witness and proof of a job whose input.json is written and
+// check its public signals, returning how long proving took
+async fn run_prover(
+    proving_keys: &Arc<proving_keys::ProvingKeys>,
+    native_prover: &native_prover::NativeProver,
+    job: &ProveJob,
+) -> Result<Duration, PipelineError> {
+    let id = job.id.as_str();
+    let expires = job.deadline_in_ms.map(|ms| Instant::now() + Duration::from_millis(ms));
 
     // Paths for circuit artifacts
-    let circuit_wasm = "circuit-compiled/zkHotdog_js/zkHotdog.wasm";
-    // let circuit_r1cs = "circuit/zkHotdog.r1cs";
-    let proving_key = "keys/zkHotdog_final.zkey";
+    let circuit = circuit::resolve(job.circuit.as_deref());
 
-    // Path for witness and proof output
+    // Path for input, witness and proof output
+    let proof_dir = artifacts::proof_dir(id);
+    let input_path = Artifact::Input.path(id);
     let witness_path = format!("{}/witness.wtns", proof_dir);
-    let proof_path = format!("{}/proof.json", proof_dir);
-    let public_path = format!("{}/public.json", proof_dir);
+    let proof_path = Artifact::Proof.path(id);
+    let public_path = Artifact::PublicSignals.path(id);
+    let log_path = Artifact::Log.path(id);
+
+    let step_timeout = Duration::from_secs(job.step_timeout_secs);
 
     // Step 1: Generate witness
     println!("Generating witness...");
-    let witness_status = tokio::process::Command::new("node")
-        .args([
-            "circuit-compiled/zkHotdog_js/generate_witness.js",
-            circuit_wasm,
-            &input_path,
-            &witness_path,
-        ])
-        .status()
-        .await
-        .map_err(|e| format!("Failed to execute witness generation: {}", e))?;
-
-    if !witness_status.success() {
-        return Err("Witness generation failed".to_string());
-    }
-
-    // Step 2: Generate proof
-    println!("Generating proof...");
-    let proof_status = tokio::process::Command::new("npx")
-        .args([
-            "snarkjs",
-            "groth16",
-            "prove",
-            proving_key,
-            &witness_path,
-            &proof_path,
-            &public_path,
-        ])
-        .status()
-        .await
-        .map_err(|e| format!("Failed to execute proof generation: {}", e))?;
+    pipeline::run_step(
+        Stage::Witness,
+        "node",
+        &[circuit.witness_generator, circuit.wasm_path, &input_path, &witness_path],
+        step_timeout,
+        &log_path,
+    )
+    .await?;
 
-    if !proof_status.success() {
-        return Err("Proof generation failed".to_string());
+    if expires.is_some_and(|expires| expires <= Instant::now()) {
+        return Err(PipelineError::Cancelled { stage: Stage::Prove });
     }
 
-    println!("Successfully generated proof for measurement {}", id);
-    Ok(())
+    // Step 2: Generate proof, natively when the circuit's key was loaded and
+    // otherwise with snarkjs, with the proving key kept in memory until it is done
+    let native_key = native_prover.key(&circuit);
+    let _key = match native_key {
+        Some(_) => None,
+        None => proving_keys::acquire(proving_keys, circuit).await,
+    };
+    let proving_started = Instant::now();
+    if let Some(key) = native_key {
+        println!("Generating proof natively...");
+        native_prover::prove(key, &witness_path, &proof_path, &public_path, step_timeout, &log_path)
+            .await?;
+    } else {
+        println!("Generating proof...");
+        pipeline::run_step(
+            Stage::Prove,
+            "npx",
+            &[
+                "snarkjs",
+                "groth16",
+                "prove",
+                circuit.zkey_path,
+                &witness_path,
+                &proof_path,
+                &public_path,
+            ],
+            step_timeout,
+            &log_path,
+        )
+        .await?;
+    }
+    let proving_time = proving_started.elapsed();
+
+    // The public signals must be laid out as the circuit declares, and prove our distances
+    let path = angle::path(&job.start_point, job.mid_point.as_ref(), &job.end_point, &job.points);
+    let expected = inputs::values(&path, job.min_length_cm, &circuit).map_err(|e| {
+        PipelineError::InvalidArtifact {
+            artifact: Artifact::PublicSignals,
+            problem: ArtifactProblem::OutOfRange(e),
+        }
+    })?;
+    pipeline::check_public_signals(&circuit, &expected, &public_path)?;
+    Ok(proving_time)
 }
 
 // Handler to check proof status
-async fn check_proof_status(
+pub(crate) async fn check_proof_status(
     State(state): State<Arc<AppState>>,
+    Visitor(caller): Visitor,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
     Path(id): Path<String>,
-) -> Result<Json<Measurement>, (StatusCode, String)> {
-    let mut measurements = state.measurements.lock().unwrap();
+    Query(query): Query<pending::StrictQuery>,
+) -> Result<Json<MeasurementStatus>, (StatusCode, String)> {
+    // Read the stored measurement, which other instances may have changed,
+    // after this one's own changes, sharing reads with concurrent requests
+    let stored = state.status_reads.get(&state.measurements, &id).await.map_err(|e| {
+        (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read measurement {}: {}", id, e))
+    })?;
 
-    if let Some(measurement) = measurements.get_mut(&id) {
+    // Only the submitter and admins see a measurement before its embargo ends
+    let now = state.time.now();
+    if let Some(mut measurement) =
+        stored.filter(|m| access::may_see_in_full(&caller, remote.ip(), m, now))
+    {
         // If the status is completed, check for attestation data
+        let mut found_attestation = false;
         if matches!(measurement.status, ProofStatus::Completed) && measurement.attestation.is_none()
         {
             // Check if attestation.json file exists
-            let attestation_path = format!("proofs/{}/attestation.json", id);
+            let attestation_path = Artifact::Attestation.path(&id);
             if std::path::Path::new(&attestation_path).exists() {
                 // Read and parse the attestation data
                 match fs::read_to_string(&attestation_path) {
@@ -393,7 +1908,18 @@ async fn check_proof_status(
                         match serde_json::from_str::<AttestationData>(&content) {
                             Ok(attestation_data) => {
                                 // Update the measurement with attestation data
+                                if let Err(e) = state
+                                    .measurements
+                                    .store()
+                                    .set_attestation(&id, &attestation_data)
+                                    .await
+                                {
+                                    println!("Failed to store attestation of {}: {}", id, e);
+                                }
                                 measurement.attestation = Some(attestation_data);
+                                measurement.updated_at = Some(state.time.now());
+                                measurement.artifacts.record(&id, Artifact::Attestation);
+                                found_attestation = true;
                                 println!("Found attestation data for measurement {}", id);
                             }
                             Err(e) => {
@@ -408,38 +1934,107 @@ async fn check_proof_status(
             }
         }
 
-        Ok(Json(measurement.clone()))
+        // Keep what other instances changed, and the recorded attestation, in memory
+        let mut measurements = state.measurements.lock();
+        let cached = measurements.get(&id);
+        // Concurrent requests may all find the file; the first one tells
+        let attached = found_attestation && cached.is_none_or(|m| m.attestation.is_none());
+        if found_attestation {
+            measurements.insert(id.clone(), measurement.clone());
+        } else if cached.is_none_or(|m| m.status != measurement.status) {
+            measurements.refresh(measurement.clone());
+        }
+        let generations = reprove::generations(&measurements, &measurement);
+        drop(measurements);
+        if attached {
+            webhooks::notify(&state, &id, webhooks::WebhookEvent::Attestation);
+        }
+
+        let queue = state.queue.position(&id);
+        let deadline_remaining_seconds =
+            measurement.deadline.map(|d| state.deadlines.remaining_seconds(&id, d));
+        let embargo = embargo::describe(&measurement, state.time.now());
+        let pending_items = query.strict.then(|| pending::pending_items(&measurement));
+        // Served by /measurements/{id}/history instead
+        measurement.history.clear();
+        Ok(Json(MeasurementStatus {
+            queue,
+            deadline_remaining_seconds,
+            embargo,
+            generations,
+            pending_items,
+            canonical_points: measurement.coordinate_system.is_canonical(),
+            measurement,
+        }))
     } else {
-        Err((StatusCode::NOT_FOUND, format!("Measurement with ID {} not found", id)))
+        Err(access::not_found(&id))
     }
 }
 
 // Handler to serve image files
-async fn serve_image(Path(id): Path<String>) -> Result<impl IntoResponse, (StatusCode, String)> {
-    // Construct path to the image file
-    let file_path = format!("uploads/{}.jpg", id);
-
-    // Check if the file exists
-    if !std::path::Path::new(&file_path).exists() {
-        return Err((StatusCode::NOT_FOUND, format!("Image with ID {} not found", id)));
+async fn serve_image(
+    State(state): State<Arc<AppState>>,
+    Visitor(caller): Visitor,
+    Path(id): Path<String>,
+    Query(query): Query<downloads::DownloadQuery>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    // Embargoed measurements and those of other tenants are indistinguishable
+    // from missing ones
+    let hidden = match state.measurements.lock().get(&id) {
+        Some(m) => !access::may_see_published(&caller, m, state.time.now()),
+        None => !caller.can_access(None),
+    };
+    if hidden {
+        return Err(access::not_found(&id));
     }
 
-    // Read the file
-    let image_data = match fs::read(&file_path) {
-        Ok(data) => data,
-        Err(e) => {
+    let file = downloads::ArtifactFile {
+        path: Artifact::Image.path(&id),
+        content_type: "image/jpeg",
+        filename: Some(downloads::download_name(&id, ".jpg")),
+        attachment: query.download,
+        label: format!("Image with ID {}", id),
+    };
+    // An image this instance has no measurement for may not exist either
+    downloads::serve(&headers, file, None, None).await.map_err(|e| match e.0 {
+        StatusCode::NOT_FOUND => access::not_found(&id),
+        _ => e,
+    })
+}
+
+// Handler serving the second image of a measurement sent with one
+async fn serve_second_image(
+    State(state): State<Arc<AppState>>,
+    Visitor(caller): Visitor,
+    Path(id): Path<String>,
+    Query(query): Query<downloads::DownloadQuery>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    // Hidden measurements answer like unknown ones, as for the image
+    let has_second = state
+        .measurements
+        .lock()
+        .get(&id)
+        .filter(|m| access::may_see_published(&caller, m, state.time.now()))
+        .map(|m| m.image_paths.len() > 1);
+    match has_second {
+        None => return Err(access::not_found(&id)),
+        Some(false) => {
             return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to read image: {}", e),
+                StatusCode::NOT_FOUND,
+                format!("Measurement {} has no second image", id),
             ));
         }
-    };
+        Some(true) => {}
+    }
 
-    Ok((
-        [
-            (header::CONTENT_TYPE, "image/jpeg".to_string()),
-            (header::CONTENT_DISPOSITION, format!("inline; filename=\"{}.jpg\"", id)),
-        ],
-        image_data,
-    ))
+    let file = downloads::ArtifactFile {
+        path: Artifact::SecondImage.path(&id),
+        content_type: "image/jpeg",
+        filename: Some(downloads::download_name(&id, "-2.jpg")),
+        attachment: query.download,
+        label: format!("Second image with ID {}", id),
+    };
+    downloads::serve(&headers, file, None, None).await
 }
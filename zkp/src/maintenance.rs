@@ -0,0 +1,178 @@
+// Read-only maintenance mode.
+//
+// While maintenance is on, status pages, listings and images are served as
+// usual but every POST, PUT, PATCH and DELETE is answered with 503 and the
+// error code "maintenance", so storage can be migrated under a live API.
+// POST /status/batch only reads and is served too. The
+// proof and pre-warm workers finish what they are doing and then wait without
+// taking new work; queued measurements stay queued and are picked up as soon
+// as maintenance ends.
+//
+// MAINTENANCE turns the mode on at startup. Changes made through
+// PUT /admin/maintenance are written to MAINTENANCE_STATE_PATH and take
+// precedence over MAINTENANCE from then on, so a restart keeps the mode.
+
+use axum::{
+    Json,
+    extract::{Request, State},
+    http::{Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{fs, io, sync::Arc};
+use tokio::sync::watch;
+
+use crate::{
+    AppState,
+    auth::{Admin, Authorized, Operator},
+    config::Config,
+    schemas::ErrorBody,
+    status_batch,
+};
+
+// Route that stays writable so maintenance can be ended
+const TOGGLE_PATH: &str = "/admin/maintenance";
+
+const DEFAULT_MESSAGE: &str = "The server is in maintenance mode and accepts no changes";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct MaintenanceState {
+    pub enabled: bool,
+    // Shown to clients whose changes are refused
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    // When maintenance was last turned on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<DateTime<Utc>>,
+}
+
+pub struct Maintenance {
+    path: String,
+    state: watch::Sender<MaintenanceState>,
+}
+
+impl Maintenance {
+    // Restore the state saved by the admin endpoint, or start from MAINTENANCE
+    pub fn load(config: &Config) -> Result<Self, String> {
+        let path = config.maintenance_state_path.clone();
+        let state = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| format!("Invalid maintenance state file {}: {}", path, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => MaintenanceState {
+                enabled: config.maintenance,
+                message: config.maintenance_message.clone(),
+                since: config.maintenance.then(Utc::now),
+            },
+            Err(e) => {
+                return Err(format!("Failed to read maintenance state file {}: {}", path, e));
+            }
+        };
+        Ok(Maintenance { path, state: watch::Sender::new(state) })
+    }
+
+    pub fn get(&self) -> MaintenanceState {
+        self.state.borrow().clone()
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.state.borrow().enabled
+    }
+
+    // Resolve once maintenance is off, immediately if it already is
+    pub async fn wait_until_off(&self) {
+        let _ = self.state.subscribe().wait_for(|state| !state.enabled).await;
+    }
+
+    // Resolve once maintenance is on
+    pub async fn wait_until_on(&self) {
+        let _ = self.state.subscribe().wait_for(|state| state.enabled).await;
+    }
+
+    // Persist a new state before switching to it
+    fn set(&self, state: MaintenanceState) -> io::Result<()> {
+        let content = serde_json::to_string_pretty(&state)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let temp_path = format!("{}.tmp", self.path);
+        fs::write(&temp_path, content)?;
+        fs::rename(&temp_path, &self.path)?;
+        self.state.send_replace(state);
+        Ok(())
+    }
+}
+
+// Middleware refusing changes while maintenance is on
+pub async fn reject_mutations(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mutation =
+        matches!(*request.method(), Method::POST | Method::PUT | Method::PATCH | Method::DELETE)
+            && request.uri().path() != status_batch::PATH;
+    if mutation && request.uri().path() != TOGGLE_PATH {
+        let maintenance = state.maintenance.get();
+        if maintenance.enabled {
+            let body = ErrorBody {
+                error: "maintenance",
+                message: maintenance.message.unwrap_or_else(|| DEFAULT_MESSAGE.to_string()),
+                since: maintenance.since,
+            };
+            return (StatusCode::SERVICE_UNAVAILABLE, Json(body)).into_response();
+        }
+    }
+    next.run(request).await
+}
+
+#[derive(Deserialize)]
+pub struct MaintenanceRequest {
+    enabled: bool,
+    message: Option<String>,
+}
+
+// Handler reporting whether maintenance is on
+pub async fn get_maintenance(
+    State(state): State<Arc<AppState>>,
+    _: Authorized<Operator>,
+) -> Json<MaintenanceState> {
+    Json(state.maintenance.get())
+}
+
+// Handler turning maintenance on or off
+pub async fn set_maintenance(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Admin>,
+    Json(request): Json<MaintenanceRequest>,
+) -> Result<Json<MaintenanceState>, (StatusCode, String)> {
+    caller.require_global()?;
+
+    let current = state.maintenance.get();
+    let updated = MaintenanceState {
+        enabled: request.enabled,
+        message: request.message.filter(|m| !m.trim().is_empty()),
+        // Keep the start of a maintenance window when only its message changes
+        since: match (request.enabled, current.enabled) {
+            (true, true) => current.since,
+            (true, false) => Some(Utc::now()),
+            (false, _) => None,
+        },
+    };
+    state.maintenance.set(updated.clone()).map_err(|e| {
+        (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save maintenance state: {}", e))
+    })?;
+
+    if updated.enabled != current.enabled {
+        println!(
+            "Maintenance mode {} by {}",
+            if updated.enabled { "on" } else { "off" },
+            caller.name
+        );
+    }
+    state.audit.record(
+        if updated.enabled { "maintenance_entered" } else { "maintenance_exited" },
+        json!({ "by": caller.name, "message": updated.message }),
+    );
+    Ok(Json(updated))
+}
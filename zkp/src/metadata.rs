@@ -0,0 +1,214 @@
+// User-supplied descriptive metadata (label, notes and tags).
+//
+// Everything a client sends here ends up in API responses and on the HTML
+// status page, so it is validated in one place: the payload must be a flat
+// JSON object with known keys, every field is bounded in length, tags are
+// restricted to a safe character set, and control and bidirectional override
+// characters are stripped. HTML escaping happens separately at render time.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// Largest metadata payload accepted before parsing, in bytes
+pub const MAX_RAW_BYTES: usize = 16 * 1024;
+const MAX_LABEL_CHARS: usize = 120;
+const MAX_NOTES_CHARS: usize = 2000;
+const MAX_TAGS: usize = 20;
+const MAX_TAG_CHARS: usize = 32;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, JsonSchema)]
+pub struct Metadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+// Parse and validate a raw metadata payload
+pub fn parse(raw: &[u8]) -> Result<Metadata, String> {
+    if raw.len() > MAX_RAW_BYTES {
+        return Err(format!("Metadata exceeds the maximum size of {} bytes", MAX_RAW_BYTES));
+    }
+
+    let value: Value =
+        serde_json::from_slice(raw).map_err(|e| format!("Failed to parse metadata JSON: {}", e))?;
+    let Value::Object(fields) = value else {
+        return Err("Metadata must be a JSON object".to_string());
+    };
+
+    let mut metadata = Metadata::default();
+    for (key, value) in fields {
+        match (key.as_str(), value) {
+            ("label", Value::String(label)) => {
+                metadata.label = Some(clean_text("label", &label, MAX_LABEL_CHARS)?)
+            }
+            ("notes", Value::String(notes)) => {
+                metadata.notes = Some(clean_text("notes", &notes, MAX_NOTES_CHARS)?)
+            }
+            ("tags", Value::Array(tags)) => metadata.tags = clean_tags(tags)?,
+            ("label" | "notes", _) => return Err(format!("Metadata {} must be a string", key)),
+            ("tags", _) => return Err("Metadata tags must be an array of strings".to_string()),
+            (other, _) => return Err(format!("Unknown metadata field '{}'", other)),
+        }
+    }
+
+    Ok(metadata)
+}
+
+// Strip invisible characters and enforce a length limit on free text
+fn clean_text(field: &str, value: &str, max_chars: usize) -> Result<String, String> {
+    let cleaned: String =
+        value.chars().filter(|c| !is_unsafe_char(*c) || (field == "notes" && *c == '\n')).collect();
+    let cleaned = cleaned.trim().to_string();
+
+    if cleaned.chars().count() > max_chars {
+        return Err(format!("Metadata {} exceeds {} characters", field, max_chars));
+    }
+    Ok(cleaned)
+}
+
+fn clean_tags(tags: Vec<Value>) -> Result<Vec<String>, String> {
+    if tags.len() > MAX_TAGS {
+        return Err(format!("Metadata may contain at most {} tags", MAX_TAGS));
+    }
+
+    let mut cleaned = Vec::with_capacity(tags.len());
+    for tag in tags {
+        let Value::String(tag) = tag else {
+            return Err("Metadata tags must be an array of strings".to_string());
+        };
+        let tag = tag.trim().to_lowercase();
+        if tag.is_empty() || tag.chars().count() > MAX_TAG_CHARS {
+            return Err(format!("Metadata tags must be 1 to {} characters long", MAX_TAG_CHARS));
+        }
+        if !tag.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')) {
+            return Err(format!(
+                "Metadata tag '{}' may only contain letters, digits, '-', '_' and '.'",
+                tag
+            ));
+        }
+        if !cleaned.contains(&tag) {
+            cleaned.push(tag);
+        }
+    }
+    Ok(cleaned)
+}
+
+// Control characters and Unicode bidirectional overrides/isolates
+pub fn is_unsafe_char(c: char) -> bool {
+    c.is_control()
+        || matches!(c, '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}' | '\u{200E}' | '\u{200F}')
+}
+
+// Escape text for inclusion in HTML element content or quoted attributes
+pub fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_json(value: serde_json::Value) -> Result<Metadata, String> {
+        parse(value.to_string().as_bytes())
+    }
+
+    #[test]
+    fn fields_are_cleaned_and_tags_normalized() {
+        let metadata = parse_json(serde_json::json!({
+            "label": "  Hot dog\u{0007} ",
+            "notes": "line one\nline two\r",
+            "tags": ["Grill", "grill", " summer-2025 "],
+        }))
+        .unwrap();
+        assert_eq!(metadata.label.as_deref(), Some("Hot dog"));
+        assert_eq!(metadata.notes.as_deref(), Some("line one\nline two"));
+        assert_eq!(metadata.tags, ["grill", "summer-2025"]);
+    }
+
+    #[test]
+    fn bidirectional_overrides_are_stripped() {
+        let metadata = parse_json(serde_json::json!({
+            "label": "invoice\u{202E}fdp.exe",
+            "notes": "\u{2066}isolated\u{2069} \u{200F}mark",
+        }))
+        .unwrap();
+        assert_eq!(metadata.label.as_deref(), Some("invoicefdp.exe"));
+        assert_eq!(metadata.notes.as_deref(), Some("isolated mark"));
+    }
+
+    #[test]
+    fn payloads_over_the_size_limit_are_refused_unparsed() {
+        let notes = "a".repeat(1024 * 1024);
+        let error = parse_json(serde_json::json!({ "notes": notes })).unwrap_err();
+        assert_eq!(error, format!("Metadata exceeds the maximum size of {} bytes", MAX_RAW_BYTES));
+
+        let tags: Vec<String> = (0..10_000).map(|i| format!("t{}", i)).collect();
+        assert!(
+            parse_json(serde_json::json!({ "tags": tags })).unwrap_err().contains("maximum size")
+        );
+    }
+
+    #[test]
+    fn fields_are_bounded() {
+        let label = "a".repeat(MAX_LABEL_CHARS);
+        assert!(parse_json(serde_json::json!({ "label": label })).is_ok());
+        let label = "a".repeat(MAX_LABEL_CHARS + 1);
+        assert_eq!(
+            parse_json(serde_json::json!({ "label": label })).unwrap_err(),
+            format!("Metadata label exceeds {} characters", MAX_LABEL_CHARS)
+        );
+        let notes = "é".repeat(MAX_NOTES_CHARS + 1);
+        assert!(parse_json(serde_json::json!({ "notes": notes })).is_err());
+
+        let tags: Vec<String> = (0..=MAX_TAGS).map(|i| format!("t{}", i)).collect();
+        assert_eq!(
+            parse_json(serde_json::json!({ "tags": tags })).unwrap_err(),
+            format!("Metadata may contain at most {} tags", MAX_TAGS)
+        );
+        let tag = "t".repeat(MAX_TAG_CHARS + 1);
+        assert!(parse_json(serde_json::json!({ "tags": [tag] })).is_err());
+        assert!(parse_json(serde_json::json!({ "tags": [""] })).is_err());
+    }
+
+    #[test]
+    fn unsafe_tags_and_shapes_are_refused() {
+        for tags in [
+            serde_json::json!(["<script>"]),
+            serde_json::json!(["a b"]),
+            serde_json::json!([1]),
+            serde_json::json!("grill"),
+        ] {
+            assert!(parse_json(serde_json::json!({ "tags": tags })).is_err(), "{}", tags);
+        }
+        assert!(parse_json(serde_json::json!({ "label": 1 })).is_err());
+        assert!(parse_json(serde_json::json!({ "owner": "x" })).is_err());
+        assert!(parse_json(serde_json::json!(["label"])).is_err());
+        assert!(parse(b"{").is_err());
+    }
+
+    #[test]
+    fn html_special_characters_are_escaped() {
+        assert_eq!(
+            escape_html(r#"<script>alert("x" + 'y') && 1</script>"#),
+            "&lt;script&gt;alert(&quot;x&quot; + &#39;y&#39;) &amp;&amp; 1&lt;/script&gt;"
+        );
+        assert_eq!(escape_html("plain text, ünïcode"), "plain text, ünïcode");
+        // Escaping twice escapes the ampersands again rather than leaving markup
+        assert_eq!(escape_html(&escape_html("<")), "&amp;lt;");
+    }
+}
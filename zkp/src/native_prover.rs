@@ -0,0 +1,136 @@
+// Proving in the server process instead of running snarkjs.
+//
+// `npx snarkjs groth16 prove` starts Node and reads the whole zkey for every
+// proof. With PROVER=native the proving key of every installed circuit is
+// read once at startup (see groth16_prover.rs in the library) and proofs are
+// made on a blocking thread from the witness generate_witness.js writes, as
+// the same proof.json and public.json snarkjs would write, so the checks of
+// the public signals and the zkVerify submission are unchanged.
+//
+// A circuit whose key cannot be read is logged and keeps proving with
+// snarkjs, which stays the default (PROVER=snarkjs) and the fallback. Proofs
+// are still bounded by PROOF_STEP_TIMEOUT_SECS; a proof that outlives it is
+// abandoned, though its thread runs until the proof is done.
+
+use backend::{
+    groth16,
+    groth16_prover::{self, ProvingKey, Witness},
+};
+use chrono::Utc;
+use std::{collections::HashMap, fmt, fs, str::FromStr, sync::Arc, time::Duration};
+
+use crate::{
+    circuit::{self, Circuit},
+    config::Config,
+    pipeline::{self, PipelineError, Stage},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProverKind {
+    Snarkjs,
+    Native,
+}
+
+impl FromStr for ProverKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "snarkjs" => Ok(ProverKind::Snarkjs),
+            "native" => Ok(ProverKind::Native),
+            other => Err(format!("unknown prover '{}', expected native or snarkjs", other)),
+        }
+    }
+}
+
+impl fmt::Display for ProverKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProverKind::Snarkjs => write!(f, "snarkjs"),
+            ProverKind::Native => write!(f, "native"),
+        }
+    }
+}
+
+// Proving keys read at startup, by circuit version
+#[derive(Default)]
+pub struct NativeProver {
+    keys: HashMap<&'static str, Arc<ProvingKey>>,
+}
+
+impl NativeProver {
+    // Read the proving keys of the installed circuits, with PROVER=native
+    pub fn load(config: &Config) -> Self {
+        let mut prover = NativeProver::default();
+        if config.prover != ProverKind::Native {
+            return prover;
+        }
+        for circuit in circuit::all().filter(|c| c.is_installed()) {
+            let key = fs::read(circuit.zkey_path)
+                .map_err(|e| e.to_string())
+                .and_then(|zkey| ProvingKey::from_zkey(&zkey).map_err(|e| e.to_string()));
+            match key {
+                Ok(key) => {
+                    println!(
+                        "Loaded proving key of {} for native proving ({} constraints)",
+                        circuit.version,
+                        key.constraints()
+                    );
+                    prover.keys.insert(circuit.version, Arc::new(key));
+                }
+                Err(e) => println!(
+                    "Cannot prove {} natively, falling back to snarkjs: {}: {}",
+                    circuit.version, circuit.zkey_path, e
+                ),
+            }
+        }
+        prover
+    }
+
+    // The key of a circuit, if it is proved natively
+    pub fn key(&self, circuit: &Circuit) -> Option<Arc<ProvingKey>> {
+        self.keys.get(circuit.version).cloned()
+    }
+}
+
+// Prove a witness, writing proof.json and public.json as snarkjs does
+pub async fn prove(
+    key: Arc<ProvingKey>,
+    witness_path: &str,
+    proof_path: &str,
+    public_path: &str,
+    timeout: Duration,
+    log_path: &str,
+) -> Result<(), PipelineError> {
+    let stage = Stage::Prove;
+    pipeline::append_log(
+        log_path,
+        &format!("[{}] {}: native prover {}\n", Utc::now().to_rfc3339(), stage, witness_path),
+    );
+    let wtns = fs::read(witness_path).map_err(|source| PipelineError::Io { stage, source })?;
+    let proving = tokio::task::spawn_blocking(move || {
+        let witness = Witness::from_wtns(&wtns)?;
+        groth16_prover::prove(&key, &witness)
+    });
+    let proved = match tokio::time::timeout(timeout, proving).await {
+        Ok(Ok(proved)) => proved,
+        Ok(Err(e)) => Err(groth16_prover::ProveError::Synthesis(e.to_string())),
+        Err(_) => {
+            pipeline::append_log(
+                log_path,
+                &format!("{} timed out after {} seconds\n", stage, timeout.as_secs()),
+            );
+            return Err(PipelineError::Timeout { stage, after: timeout });
+        }
+    };
+    let (proof, public) = proved.map_err(|e| {
+        pipeline::append_log(log_path, &format!("native prover failed: {}\n", e));
+        PipelineError::NativeProver { stage, message: e.to_string() }
+    })?;
+
+    fs::write(proof_path, proof.to_snarkjs())
+        .and_then(|_| fs::write(public_path, groth16::public_signals_to_snarkjs(&public)))
+        .map_err(|source| PipelineError::Io { stage, source })?;
+    pipeline::append_log(log_path, "native prover wrote the proof\n");
+    Ok(())
+}
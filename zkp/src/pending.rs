@@ -0,0 +1,112 @@
+// What a measurement is still expected to produce.
+//
+// Before the zkVerify integration a Completed measurement without an
+// attestation would never get one; now the attestation may still be on its
+// way. With ?strict=true, /status/{id} and /measurements/by-external-id/{id}
+// add pending_items, naming what is still expected, so clients can tell
+// "not yet" from "not applicable", and artifact downloads answer a file that
+// is still expected with 409 and the error code "pending" instead of 404.
+//
+// A measurement is proved, the proof is verified on zkVerify and an
+// attestation including it is published, unless its plan skips the
+// submission, when the proof is all it produces. Which of these are still
+// outstanding follows from the status and from what the measurement recorded
+// along the way. A failed measurement expects nothing more until it is
+// submitted again.
+
+use axum::{
+    Json,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Measurement, ProofStatus,
+    artifacts::Artifact,
+    plan::{self, StageName},
+    schemas::ErrorBody,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PendingItem {
+    // The proof and its public signals
+    Proof,
+    // Acceptance of the proof by zkVerify, recorded as the receipt
+    Verification,
+    // The attestation including the proof
+    Attestation,
+}
+
+impl PendingItem {
+    fn name(self) -> &'static str {
+        match self {
+            PendingItem::Proof => "proof",
+            PendingItem::Verification => "verification",
+            PendingItem::Attestation => "attestation",
+        }
+    }
+
+    // Item that produces an artifact, if the pipeline writes it
+    fn producing(artifact: Artifact) -> Option<Self> {
+        match artifact {
+            Artifact::Input | Artifact::Proof | Artifact::PublicSignals => Some(PendingItem::Proof),
+            Artifact::Attestation => Some(PendingItem::Attestation),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct StrictQuery {
+    #[serde(default)]
+    pub strict: bool,
+}
+
+// Items a measurement is still expected to produce, in pipeline order
+pub fn pending_items(measurement: &Measurement) -> Vec<PendingItem> {
+    let mut items = Vec::new();
+    match measurement.status {
+        ProofStatus::Failed | ProofStatus::Cancelled => return items,
+        ProofStatus::Scheduled | ProofStatus::Pending => {
+            items.extend([PendingItem::Proof, PendingItem::Verification]);
+        }
+        ProofStatus::Processing => {
+            if !measurement.artifacts.proof.available {
+                items.push(PendingItem::Proof);
+            }
+            if measurement.receipt.is_none() {
+                items.push(PendingItem::Verification);
+            }
+        }
+        ProofStatus::Completed => {}
+    }
+    if plan::skips(measurement, StageName::Submit) {
+        items.retain(|item| *item == PendingItem::Proof);
+        return items;
+    }
+    if measurement.attestation.is_none() {
+        items.push(PendingItem::Attestation);
+    }
+    items
+}
+
+// Answer for a missing artifact that is still expected, in strict mode
+pub fn artifact_pending(measurement: &Measurement, artifact: Artifact) -> Option<Response> {
+    let item = PendingItem::producing(artifact)?;
+    if !pending_items(measurement).contains(&item) {
+        return None;
+    }
+    let body = ErrorBody {
+        error: "pending",
+        message: format!(
+            "The {} of measurement {} is not available yet",
+            item.name(),
+            measurement.id
+        ),
+        since: measurement.created_at,
+    };
+    Some((StatusCode::CONFLICT, Json(body)).into_response())
+}
@@ -0,0 +1,454 @@
+// Errors of the proving pipeline.
+//
+// Every step of proof generation reports a PipelineError saying which stage
+// failed and why, so callers can react to the cause instead of parsing
+// messages: failures are classified for the measurement record, mapped to an
+// HTTP status where an endpoint surfaces them, and to a process exit code for
+// command-line use. External tools run through run_step, which bounds each
+// step by PROOF_STEP_TIMEOUT_SECS, keeps the tail of the tool's stderr and
+// appends its output to the measurement's log.
+
+use axum::http::StatusCode;
+use chrono::Utc;
+use std::{
+    error::Error,
+    fmt,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    process::{ExitCode, Stdio},
+    time::Duration,
+};
+
+use crate::{
+    FailureClass, artifacts::Artifact, circuit::Circuit, coords::CoordinateError,
+    inputs::InputViolation,
+};
+
+// Longest stderr excerpt kept from a failed tool, in characters
+const STDERR_EXCERPT_CHARS: usize = 2000;
+
+// Step of the pipeline an error occurred in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    // Preparing the proof directory
+    Setup,
+    Inputs,
+    Witness,
+    Prove,
+    PublicSignals,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stage::Setup => write!(f, "setup"),
+            Stage::Inputs => write!(f, "input generation"),
+            Stage::Witness => write!(f, "witness generation"),
+            Stage::Prove => write!(f, "proof generation"),
+            Stage::PublicSignals => write!(f, "public signal check"),
+        }
+    }
+}
+
+// What is wrong with an artifact the pipeline read or wrote
+#[derive(Debug)]
+pub enum ArtifactProblem {
+    Malformed(serde_json::Error),
+    // public.json holds a different number of signals than the circuit declares
+    SignalCount { expected: usize, found: usize },
+    // A proved distance differs from the one computed from the measurement
+    DistanceMismatch { signal: String, proved: i128, expected: i128 },
+    // input.json on disk disagrees with the measurement in these inputs
+    DiffersFromMeasurement { fields: Vec<String> },
+    // A field element that is not a valid coordinate
+    InvalidField(CoordinateError),
+    // An input outside the range the circuit declares for it
+    OutOfRange(InputViolation),
+}
+
+impl fmt::Display for ArtifactProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArtifactProblem::Malformed(e) => write!(f, "malformed JSON: {}", e),
+            ArtifactProblem::SignalCount { expected, found } => {
+                write!(f, "{} public signals where the circuit declares {}", found, expected)
+            }
+            ArtifactProblem::DistanceMismatch { signal, proved, expected } => {
+                write!(f, "{} {} does not match expected {}", signal, proved, expected)
+            }
+            ArtifactProblem::DiffersFromMeasurement { fields } => {
+                write!(f, "differs from the measurement in {}", fields.join(", "))
+            }
+            ArtifactProblem::InvalidField(e) => write!(f, "invalid field element: {}", e),
+            ArtifactProblem::OutOfRange(violation) => write!(f, "{}", violation),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PipelineError {
+    Io { stage: Stage, source: io::Error },
+    // An external tool could not be started
+    ChildSpawn { stage: Stage, program: &'static str, source: io::Error },
+    // An external tool exited unsuccessfully; code is None when it was killed by a signal
+    ChildExit { stage: Stage, code: Option<i32>, stderr_excerpt: String },
+    Timeout { stage: Stage, after: Duration },
+    InvalidArtifact { artifact: Artifact, problem: ArtifactProblem },
+    // The client's deadline passed, so the remaining stages were abandoned
+    Cancelled { stage: Stage },
+    // The prover child of a supervising server failed the job, or exited
+    // while holding it
+    ProverProcess { message: String, exited: bool },
+    // The native prover could not use a witness
+    NativeProver { stage: Stage, message: String },
+}
+
+impl PipelineError {
+    // Failure class recorded on the measurement
+    pub(crate) fn failure_class(&self) -> FailureClass {
+        match self {
+            PipelineError::Cancelled { .. } => FailureClass::DeadlineExceeded,
+            PipelineError::ProverProcess { exited: true, .. } => FailureClass::Interrupted,
+            _ => FailureClass::ProofGeneration,
+        }
+    }
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PipelineError::Io { stage, source } => write!(f, "{} failed: {}", stage, source),
+            PipelineError::ChildSpawn { stage, program, source } => {
+                write!(f, "{} failed: could not run {}: {}", stage, program, source)
+            }
+            PipelineError::ChildExit { stage, code, stderr_excerpt } => {
+                match code {
+                    Some(code) => write!(f, "{} failed with exit code {}", stage, code)?,
+                    None => write!(f, "{} was killed by a signal", stage)?,
+                }
+                if !stderr_excerpt.is_empty() {
+                    write!(f, ": {}", stderr_excerpt)?;
+                }
+                Ok(())
+            }
+            PipelineError::Timeout { stage, after } => {
+                write!(f, "{} timed out after {} seconds", stage, after.as_secs())
+            }
+            PipelineError::InvalidArtifact { artifact, problem } => {
+                write!(f, "{:?} artifact is invalid: {}", artifact, problem)
+            }
+            PipelineError::Cancelled { stage } => {
+                write!(f, "Deadline passed before {}", stage)
+            }
+            PipelineError::ProverProcess { message, .. } => write!(f, "{}", message),
+            PipelineError::NativeProver { stage, message } => {
+                write!(f, "{} failed in the native prover: {}", stage, message)
+            }
+        }
+    }
+}
+
+impl Error for PipelineError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PipelineError::Io { source, .. } | PipelineError::ChildSpawn { source, .. } => {
+                Some(source)
+            }
+            PipelineError::InvalidArtifact { problem: ArtifactProblem::Malformed(e), .. } => {
+                Some(e)
+            }
+            _ => None,
+        }
+    }
+}
+
+// Response for endpoints that run or report on the pipeline
+impl From<PipelineError> for (StatusCode, String) {
+    fn from(e: PipelineError) -> Self {
+        let status = match e {
+            PipelineError::Timeout { .. } => StatusCode::GATEWAY_TIMEOUT,
+            PipelineError::InvalidArtifact { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            PipelineError::Cancelled { .. } => StatusCode::CONFLICT,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, e.to_string())
+    }
+}
+
+// Exit status for command-line use, following sysexits.h
+impl From<&PipelineError> for ExitCode {
+    fn from(e: &PipelineError) -> Self {
+        ExitCode::from(match e {
+            PipelineError::Io { .. } => 74,                          // EX_IOERR
+            PipelineError::ChildSpawn { .. } => 69,                  // EX_UNAVAILABLE
+            PipelineError::ChildExit { .. } => 70,                   // EX_SOFTWARE
+            PipelineError::Timeout { .. } => 75,                     // EX_TEMPFAIL
+            PipelineError::InvalidArtifact { .. } => 65,             // EX_DATAERR
+            PipelineError::Cancelled { .. } => 75,                   // EX_TEMPFAIL
+            PipelineError::ProverProcess { exited: true, .. } => 75, // EX_TEMPFAIL
+            PipelineError::ProverProcess { .. } => 70,               // EX_SOFTWARE
+            PipelineError::NativeProver { .. } => 70,                // EX_SOFTWARE
+        })
+    }
+}
+
+// Append to a measurement's log; the log is diagnostic, so failing to write it
+// is only reported
+pub fn append_log(path: &str, text: &str) {
+    let result = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(text.as_bytes()));
+    if let Err(e) = result {
+        println!("Failed to write log {}: {}", path, e);
+    }
+}
+
+// Run an external tool for a stage, failing if it errors or outlives the
+// timeout; the tool's output is appended to the log at log_path
+pub async fn run_step(
+    stage: Stage,
+    program: &'static str,
+    args: &[&str],
+    timeout: Duration,
+    log_path: &str,
+) -> Result<(), PipelineError> {
+    append_log(
+        log_path,
+        &format!("[{}] {}: {} {}\n", Utc::now().to_rfc3339(), stage, program, args.join(" ")),
+    );
+    let child = tokio::process::Command::new(program)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|source| PipelineError::ChildSpawn { stage, program, source })?;
+
+    let output = match tokio::time::timeout(timeout, child.wait_with_output()).await {
+        Ok(output) => output.map_err(|source| PipelineError::Io { stage, source })?,
+        Err(_) => {
+            append_log(
+                log_path,
+                &format!("{} timed out after {} seconds\n", stage, timeout.as_secs()),
+            );
+            return Err(PipelineError::Timeout { stage, after: timeout });
+        }
+    };
+
+    // The tool's output still belongs in the server log too
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    print!("{}", stdout);
+    eprint!("{}", stderr);
+    append_log(
+        log_path,
+        &format!("{}{}{} exited with {}\n", stdout, stderr, program, output.status),
+    );
+
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = stderr.trim();
+    let skip = stderr.chars().count().saturating_sub(STDERR_EXCERPT_CHARS);
+    Err(PipelineError::ChildExit {
+        stage,
+        code: output.status.code(),
+        stderr_excerpt: stderr.chars().skip(skip).collect(),
+    })
+}
+
+// Check that the public signals at public_path are laid out as the circuit
+// declares and prove the expected values of its inputs
+pub fn check_public_signals(
+    circuit: &Circuit,
+    expected: &[(&str, Vec<i128>)],
+    public_path: &str,
+) -> Result<(), PipelineError> {
+    let invalid =
+        |problem| PipelineError::InvalidArtifact { artifact: Artifact::PublicSignals, problem };
+    let public_content = fs::read_to_string(public_path)
+        .map_err(|source| PipelineError::Io { stage: Stage::PublicSignals, source })?;
+    let public_signals: Vec<String> = serde_json::from_str(&public_content)
+        .map_err(|e| invalid(ArtifactProblem::Malformed(e)))?;
+    let proved = circuit.spec.decode_public(&public_signals).map_err(invalid)?;
+    for (name, expected) in expected {
+        let Some(proved) = proved.values(name) else {
+            continue;
+        };
+        if let Some((index, (proved, expected))) = proved
+            .iter()
+            .zip(expected)
+            .enumerate()
+            .find(|(_, (proved, expected))| proved != expected)
+        {
+            let signal = match circuit.spec.input(name).and_then(|signal| signal.length) {
+                Some(_) => format!("{}[{}]", name, index),
+                None => name.to_string(),
+            };
+            return Err(invalid(ArtifactProblem::DistanceMismatch {
+                signal,
+                proved: *proved,
+                expected: *expected,
+            }));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::ZKHOTDOG;
+    use uuid::Uuid;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    // A path of its own in the temporary directory, removed when dropped
+    struct TempFile(String);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("zkhotdog-{}-{}", Uuid::new_v4(), name));
+            TempFile(path.to_string_lossy().into_owned())
+        }
+
+        fn with(name: &str, content: &str) -> Self {
+            let file = TempFile::new(name);
+            fs::write(&file.0, content).unwrap();
+            file
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn check(public: &TempFile) -> Result<(), PipelineError> {
+        check_public_signals(&ZKHOTDOG, &[("distance_squared", vec![100])], &public.0)
+    }
+
+    #[test]
+    fn proved_distance_passes_the_check() {
+        assert!(check(&TempFile::with("public.json", r#"["100"]"#)).is_ok());
+    }
+
+    #[test]
+    fn missing_artifact_is_an_io_error_of_its_stage() {
+        let missing = TempFile::new("public.json");
+        let error = check(&missing).unwrap_err();
+        let PipelineError::Io { stage: Stage::PublicSignals, source } = &error else {
+            panic!("expected an I/O error, got {:?}", error);
+        };
+        assert_eq!(source.kind(), io::ErrorKind::NotFound);
+        assert_eq!(error.failure_class(), FailureClass::ProofGeneration);
+        assert_eq!(ExitCode::from(&error), ExitCode::from(74));
+    }
+
+    #[tokio::test]
+    async fn missing_tool_cannot_be_spawned() {
+        let log = TempFile::new("log.txt");
+        let error = run_step(Stage::Witness, "zkhotdog-no-such-tool", &[], TIMEOUT, &log.0)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&error, PipelineError::ChildSpawn { stage: Stage::Witness, program: "zkhotdog-no-such-tool", source } if source.kind() == io::ErrorKind::NotFound),
+            "{:?}",
+            error
+        );
+        assert_eq!(ExitCode::from(&error), ExitCode::from(69));
+    }
+
+    #[tokio::test]
+    async fn witness_failure_keeps_the_tool_exit_code_and_stderr() {
+        let log = TempFile::new("log.txt");
+        let script = "echo 'witness: wasm not found' >&2; exit 3";
+        let error =
+            run_step(Stage::Witness, "sh", &["-c", script], TIMEOUT, &log.0).await.unwrap_err();
+        let PipelineError::ChildExit { stage: Stage::Witness, code: Some(3), stderr_excerpt } =
+            &error
+        else {
+            panic!("expected a witness exit, got {:?}", error);
+        };
+        assert_eq!(stderr_excerpt, "witness: wasm not found");
+        assert_eq!(
+            error.to_string(),
+            "witness generation failed with exit code 3: witness: wasm not found"
+        );
+        assert!(fs::read_to_string(&log.0).unwrap().contains("witness: wasm not found"));
+        let (status, _) = <(StatusCode, String)>::from(error);
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn prover_killed_by_a_signal_exits_without_a_code() {
+        let log = TempFile::new("log.txt");
+        let error =
+            run_step(Stage::Prove, "sh", &["-c", "kill -9 $$"], TIMEOUT, &log.0).await.unwrap_err();
+        assert!(
+            matches!(error, PipelineError::ChildExit { stage: Stage::Prove, code: None, .. }),
+            "{:?}",
+            error
+        );
+        assert_eq!(error.to_string(), "proof generation was killed by a signal");
+        assert_eq!(ExitCode::from(&error), ExitCode::from(70));
+    }
+
+    #[tokio::test]
+    async fn prover_outliving_the_step_timeout_times_out() {
+        let log = TempFile::new("log.txt");
+        let after = Duration::from_millis(100);
+        let error = run_step(Stage::Prove, "sleep", &["5"], after, &log.0).await.unwrap_err();
+        assert!(matches!(error, PipelineError::Timeout { stage: Stage::Prove, .. }), "{:?}", error);
+        let (status, _) = <(StatusCode, String)>::from(error);
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+    }
+
+    #[test]
+    fn malformed_output_is_an_invalid_artifact() {
+        let error = check(&TempFile::with("public.json", "[\"100\"")).unwrap_err();
+        assert!(
+            matches!(
+                error,
+                PipelineError::InvalidArtifact {
+                    artifact: Artifact::PublicSignals,
+                    problem: ArtifactProblem::Malformed(_)
+                }
+            ),
+            "{:?}",
+            error
+        );
+        assert!(error.source().is_some());
+        assert_eq!(ExitCode::from(&error), ExitCode::from(65));
+        let (status, _) = <(StatusCode, String)>::from(error);
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[test]
+    fn output_of_another_layout_or_distance_is_an_invalid_artifact() {
+        let error = check(&TempFile::with("public.json", r#"["100", "1"]"#)).unwrap_err();
+        assert!(
+            matches!(
+                error,
+                PipelineError::InvalidArtifact {
+                    problem: ArtifactProblem::SignalCount { expected: 1, found: 2 },
+                    ..
+                }
+            ),
+            "{:?}",
+            error
+        );
+        let error = check(&TempFile::with("public.json", r#"["99"]"#)).unwrap_err();
+        let PipelineError::InvalidArtifact {
+            problem: ArtifactProblem::DistanceMismatch { signal, proved: 99, expected: 100 },
+            ..
+        } = &error
+        else {
+            panic!("expected a distance mismatch, got {:?}", error);
+        };
+        assert_eq!(signal, "distance_squared");
+    }
+}
@@ -0,0 +1,634 @@
+// Pipeline plans of measurements.
+//
+// A measurement goes through an ordered list of stages, its plan, resolved
+// when it is created and stored on it:
+//   - prove generates the witness and proof;
+//   - submit sends the proof to the attestation backend and waits for its
+//     attestation.
+// PIPELINE_SKIP_STAGES leaves stages out of the plan of every new measurement
+// and a submission's skipStages field out of its own, e.g. submit for
+// internal tests that should not spend fees. Skipped stages stay in the plan,
+// marked as such, and a measurement completes once every stage of its plan is
+// done or skipped. Reproofs skip what their original skipped. Stages the
+// others build on, like prove, cannot be skipped.
+//
+// start_proof_process runs the stages of a claimed measurement in turn and
+// records the state of each, when it started and finished and why it failed,
+// which /status/{id} shows. The stages from the first one that needs no proof
+// worker on run in the background, so the worker moves on to the next proof
+// while a submission waits for the network. The deadline and a cancellation
+// are checked before every stage. A retry starts the plan again at its first
+// stage, or at the one ?from= names once those before it are done, keeping
+// their output. Measurements recorded before plans were kept run every stage.
+//
+// Adding a stage takes an implementation of PipelineStage and its entry in
+// STAGES, in the order stages run.
+
+use chrono::{DateTime, Utc};
+use futures_util::{FutureExt, future::BoxFuture};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{fmt, fs, io, str::FromStr, sync::Arc};
+
+use crate::{
+    AppState, FailureClass, Measurement, ProofStatus,
+    artifacts::{self, Artifact},
+    attestation_backend::{ProofArtifacts, Receipt},
+    attestations,
+    cancel::{self, Cancellable},
+    circuit, commitment,
+    config::Config,
+    events::EventKind,
+    fail_measurement, generate_snarkjs_proof,
+    history::Source,
+    submission,
+    version::VersionStamp,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum StageName {
+    Prove,
+    Submit,
+}
+
+impl FromStr for StageName {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        STAGES.iter().map(|stage| stage.name()).find(|name| name.to_string() == s).ok_or_else(
+            || {
+                let known: Vec<String> =
+                    STAGES.iter().map(|stage| stage.name().to_string()).collect();
+                format!("unknown stage '{}', expected one of {}", s, known.join(", "))
+            },
+        )
+    }
+}
+
+impl fmt::Display for StageName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StageName::Prove => write!(f, "prove"),
+            StageName::Submit => write!(f, "submit"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum StageState {
+    Pending,
+    Running,
+    Done,
+    // Left out of the measurement's plan
+    Skipped,
+    Failed,
+    // Stopped by a cancellation of the measurement
+    Cancelled,
+}
+
+// One stage of a measurement's plan and how far it got
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PlannedStage {
+    pub stage: StageName,
+    pub state: StageState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime<Utc>>,
+    // Why the stage failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl PlannedStage {
+    fn new(stage: StageName, state: StageState) -> Self {
+        PlannedStage { stage, state, started_at: None, finished_at: None, error: None }
+    }
+}
+
+// How a run of a stage ended
+pub enum StageOutcome {
+    Done,
+    // The stage goes on elsewhere, like an attestation left to the poller,
+    // which ends it
+    Handed,
+    Failed(FailureClass, String),
+}
+
+pub trait PipelineStage: Send + Sync {
+    fn name(&self) -> StageName;
+
+    // What the stage does, as in "Deadline passed before ..."
+    fn label(&self) -> &'static str;
+
+    // Whether a measurement may leave the stage out of its plan
+    fn skippable(&self) -> bool;
+
+    // Whether the stage takes up a proof worker while it runs
+    fn needs_worker(&self) -> bool;
+
+    // Run the stage on a measurement that is Processing
+    fn run<'a>(
+        &'a self,
+        state: &'a Arc<AppState>,
+        measurement: &'a Measurement,
+        worker: Source,
+    ) -> BoxFuture<'a, StageOutcome>;
+
+    // Remove what a run of the stage left in the proof directory, before it
+    // runs again
+    fn clean(&self, id: &str) -> Result<(), String>;
+}
+
+// Every stage, in the order they run
+pub static STAGES: [&dyn PipelineStage; 2] = [&Prove, &Submit];
+
+pub fn stage(name: StageName) -> &'static dyn PipelineStage {
+    *STAGES.iter().find(|stage| stage.name() == name).expect("every stage is registered")
+}
+
+// Parse a comma-separated list of stages to skip
+pub fn parse_skips(value: &str) -> Result<Vec<StageName>, String> {
+    let mut skips = Vec::new();
+    for item in value.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        let name: StageName = item.parse()?;
+        if !stage(name).skippable() {
+            return Err(format!("stage '{}' cannot be skipped", name));
+        }
+        if !skips.contains(&name) {
+            skips.push(name);
+        }
+    }
+    Ok(skips)
+}
+
+// The plan of a new measurement, skipping what the configuration and the
+// submission ask to
+pub fn resolve(config: &Config, skips: &[StageName]) -> Vec<PlannedStage> {
+    STAGES
+        .iter()
+        .map(|stage| {
+            let name = stage.name();
+            let skipped = config.pipeline_skip_stages.contains(&name) || skips.contains(&name);
+            PlannedStage::new(name, if skipped { StageState::Skipped } else { StageState::Pending })
+        })
+        .collect()
+}
+
+// The plan of a measurement recorded before plans were kept
+pub fn full() -> Vec<PlannedStage> {
+    STAGES.iter().map(|stage| PlannedStage::new(stage.name(), StageState::Pending)).collect()
+}
+
+// Stages a plan skips
+pub fn skipped(plan: &[PlannedStage]) -> Vec<StageName> {
+    plan.iter().filter(|planned| planned.state == StageState::Skipped).map(|p| p.stage).collect()
+}
+
+// Whether a measurement's plan leaves a stage out
+pub fn skips(measurement: &Measurement, name: StageName) -> bool {
+    measurement
+        .plan
+        .iter()
+        .any(|planned| planned.stage == name && planned.state == StageState::Skipped)
+}
+
+// End the running stages of a measurement, as when it fails, is cancelled or
+// its attestation arrives
+pub fn end_running(measurement: &mut Measurement, state: StageState, error: Option<&str>) {
+    for planned in measurement.plan.iter_mut().filter(|p| p.state == StageState::Running) {
+        planned.state = state;
+        planned.finished_at = Some(Utc::now());
+        planned.error = error.map(str::to_string);
+    }
+}
+
+// Stage a retry starts at: the one asked for once those before it are done,
+// or the first one that is not skipped. Stages from it on are set back to
+// Pending.
+pub fn restart(plan: &mut [PlannedStage], from: Option<StageName>) -> Result<StageName, String> {
+    let start = match from {
+        Some(name) => {
+            let start = plan
+                .iter()
+                .position(|planned| planned.stage == name && planned.state != StageState::Skipped)
+                .ok_or_else(|| format!("The measurement's plan does not run stage {}", name))?;
+            if let Some(undone) = plan[..start]
+                .iter()
+                .find(|p| !matches!(p.state, StageState::Done | StageState::Skipped))
+            {
+                return Err(format!(
+                    "Stage {} cannot be retried before stage {} is done",
+                    name, undone.stage
+                ));
+            }
+            start
+        }
+        None => plan
+            .iter()
+            .position(|planned| planned.state != StageState::Skipped)
+            .ok_or("The measurement's plan runs no stage")?,
+    };
+    for planned in plan[start..].iter_mut().filter(|p| p.state != StageState::Skipped) {
+        *planned = PlannedStage::new(planned.stage, StageState::Pending);
+    }
+    Ok(plan[start].stage)
+}
+
+// Record how far a stage of a measurement got
+fn mark(state: &AppState, id: &str, name: StageName, to: StageState) {
+    let mut measurements = state.measurements.lock();
+    let Some(m) = measurements.get_mut(id) else {
+        return;
+    };
+    if let Some(planned) = m.plan.iter_mut().find(|planned| planned.stage == name) {
+        let now = Utc::now();
+        match to {
+            StageState::Running => {
+                *planned = PlannedStage { started_at: Some(now), ..PlannedStage::new(name, to) }
+            }
+            _ => {
+                planned.state = to;
+                planned.finished_at = Some(now);
+            }
+        }
+    }
+}
+
+// Whether the measurement was cancelled, cleaning up after it if so
+async fn cancelled(state: &AppState, id: &str) -> bool {
+    let cancelled =
+        state.measurements.lock().get(id).is_some_and(|m| m.status == ProofStatus::Cancelled);
+    if cancelled {
+        cancel::clean_up(state, id).await;
+    }
+    cancelled
+}
+
+// Run the given stages of a measurement in turn, returning whether all of
+// them are done
+async fn run_stages(
+    state: &Arc<AppState>,
+    measurement: &Measurement,
+    names: &[StageName],
+    worker: Source,
+    cancellable: &Cancellable,
+) -> bool {
+    let id = measurement.id.as_str();
+    for name in names {
+        let stage = stage(*name);
+        if cancelled(state, id).await {
+            println!("Not starting {} of measurement {}: cancelled", stage.label(), id);
+            return false;
+        }
+        // Work the client no longer wants is not started, least of all a
+        // submission costing fees
+        if state.deadlines.is_expired(id, measurement.deadline) {
+            println!("Not starting {} of measurement {}: deadline passed", stage.label(), id);
+            let message = format!("Deadline passed before {}", stage.label());
+            fail_measurement(state, id, worker, FailureClass::DeadlineExceeded, message);
+            return false;
+        }
+
+        mark(state, id, *name, StageState::Running);
+        // A cancellation drops the running stage, which kills the tool it started
+        let outcome = tokio::select! {
+            outcome = stage.run(state, measurement, worker) => outcome,
+            () = cancellable.cancelled() => {
+                println!("Stopped {} of measurement {}: cancelled", stage.label(), id);
+                mark(state, id, *name, StageState::Cancelled);
+                cancel::clean_up(state, id).await;
+                return false;
+            }
+        };
+        // The measurement may have been cancelled as the stage ended
+        if cancelled(state, id).await {
+            println!("Dropped {} of measurement {}: cancelled", stage.label(), id);
+            mark(state, id, *name, StageState::Cancelled);
+            return false;
+        }
+        match outcome {
+            StageOutcome::Done => mark(state, id, *name, StageState::Done),
+            StageOutcome::Handed => return false,
+            StageOutcome::Failed(class, message) => {
+                fail_measurement(state, id, worker, class, message);
+                return false;
+            }
+        }
+    }
+    true
+}
+
+// Complete a measurement whose every stage is done or skipped
+fn complete(state: &AppState, id: &str, worker: Source) {
+    if !commitment::confirm(state, id, worker) {
+        return;
+    }
+    let mut measurements = state.measurements.lock_as(worker);
+    if let Some(m) = measurements.get_mut(id).filter(|m| m.status == ProofStatus::Processing) {
+        m.status = ProofStatus::Completed;
+        state.events.publish(m, EventKind::StatusChanged);
+    }
+}
+
+// Run the stages of a claimed measurement's plan that are still pending. The
+// stages from the first one needing no worker on are left to a background
+// task, and the call returns once the worker is free.
+pub async fn run(
+    state: Arc<AppState>,
+    measurement: Measurement,
+    worker: Source,
+    cancellable: Cancellable,
+) {
+    let mut foreground: Vec<StageName> = measurement
+        .plan
+        .iter()
+        .filter(|planned| planned.state == StageState::Pending)
+        .map(|planned| planned.stage)
+        .collect();
+    let split = foreground.iter().position(|name| !stage(*name).needs_worker());
+    let background = foreground.split_off(split.unwrap_or(foreground.len()));
+
+    if !run_stages(&state, &measurement, &foreground, worker, &cancellable).await {
+        return;
+    }
+    if background.is_empty() {
+        complete(&state, &measurement.id, worker);
+        return;
+    }
+    tokio::spawn(async move {
+        if run_stages(&state, &measurement, &background, worker, &cancellable).await {
+            complete(&state, &measurement.id, worker);
+        }
+    });
+}
+
+// Generating the witness and proof of the measurement
+struct Prove;
+
+impl PipelineStage for Prove {
+    fn name(&self) -> StageName {
+        StageName::Prove
+    }
+
+    fn label(&self) -> &'static str {
+        "proof generation"
+    }
+
+    fn skippable(&self) -> bool {
+        false
+    }
+
+    fn needs_worker(&self) -> bool {
+        true
+    }
+
+    fn run<'a>(
+        &'a self,
+        state: &'a Arc<AppState>,
+        measurement: &'a Measurement,
+        _worker: Source,
+    ) -> BoxFuture<'a, StageOutcome> {
+        async move {
+            let id = measurement.id.as_str();
+            println!("Starting proof generation for measurement {}", id);
+            let result = generate_snarkjs_proof(state, id, measurement).await;
+
+            // Stamp the keys actually used, which may differ from those at creation
+            let circuit = circuit::resolve(measurement.circuit.as_deref());
+            let proved_with = result.is_ok().then(|| VersionStamp {
+                toolchain: Some(state.toolchain.get()),
+                ..VersionStamp::current(&circuit)
+            });
+
+            // Record whatever the proving step managed to write, even on failure
+            {
+                let mut measurements = state.measurements.lock();
+                if let Some(m) = measurements.get_mut(id) {
+                    m.artifacts.record_all(id, &Artifact::PROOF_OUTPUTS);
+                    if proved_with.is_some() {
+                        m.versions.proved = proved_with;
+                    }
+                }
+            }
+
+            if let Err(e) = result {
+                println!("Proof generation failed: {}", e);
+                let class = if state.deadlines.is_expired(id, measurement.deadline) {
+                    FailureClass::DeadlineExceeded
+                } else {
+                    e.failure_class()
+                };
+                return StageOutcome::Failed(class, e.to_string());
+            }
+
+            // Keep the payload the native submitter would send, for parity checks
+            if state.config.submission_payload_debug {
+                match submission::write(id, &circuit) {
+                    Ok(()) => {
+                        let mut measurements = state.measurements.lock();
+                        if let Some(m) = measurements.get_mut(id) {
+                            m.artifacts.record(id, Artifact::SubmissionPayload);
+                        }
+                    }
+                    Err(e) => println!("Failed to build submission payload for {}: {}", id, e),
+                }
+            }
+            StageOutcome::Done
+        }
+        .boxed()
+    }
+
+    // Everything but input.json, which is written again anyway
+    fn clean(&self, id: &str) -> Result<(), String> {
+        artifacts::clean_proof_dir(id)
+    }
+}
+
+// Submitting the proof for its attestation
+struct Submit;
+
+impl PipelineStage for Submit {
+    fn name(&self) -> StageName {
+        StageName::Submit
+    }
+
+    fn label(&self) -> &'static str {
+        "submission"
+    }
+
+    fn skippable(&self) -> bool {
+        true
+    }
+
+    fn needs_worker(&self) -> bool {
+        false
+    }
+
+    fn run<'a>(
+        &'a self,
+        state: &'a Arc<AppState>,
+        measurement: &'a Measurement,
+        worker: Source,
+    ) -> BoxFuture<'a, StageOutcome> {
+        async move {
+            let id = measurement.id.as_str();
+            let version = circuit::resolve(measurement.circuit.as_deref()).version;
+            let backend = state.attestation_backends.for_circuit(version);
+            let kind = backend.kind();
+            println!("Submitting proof {} to {}...", id, kind);
+
+            let proof = ProofArtifacts { id, tenant: measurement.tenant.as_deref() };
+            let receipt = backend.submit(state, proof).await;
+            if let Ok(Receipt::Attested(attestation)) = &receipt
+                && backend.verify(attestation)
+            {
+                attestations::write(id, attestation);
+            }
+
+            let mut measurements = state.measurements.lock_as(worker);
+            // A measurement cancelled in the meantime keeps none of it
+            let Some(m) = measurements.get_mut(id).filter(|m| m.status == ProofStatus::Processing)
+            else {
+                return StageOutcome::Handed;
+            };
+            m.attestation_backend = Some(kind);
+            m.artifacts.record_all(id, &[Artifact::Attestation, Artifact::Log]);
+            match receipt {
+                // The backend leaves the attestation to the poller
+                Ok(Receipt::Awaiting(pending)) => {
+                    println!("Proof {} finalized, awaiting its attestation", id);
+                    state.attestations.register(id, kind, pending);
+                    StageOutcome::Handed
+                }
+                Ok(Receipt::Attested(attestation)) if backend.verify(&attestation) => {
+                    println!("Proof {} verified successfully on {}", id, kind);
+                    m.attestation = Some(attestation);
+                    StageOutcome::Done
+                }
+                Ok(Receipt::Attested(attestation)) => {
+                    println!("Attestation of proof {} failed verification", id);
+                    let message = format!(
+                        "Attestation {} from {} failed verification",
+                        attestation.attestation_id, kind
+                    );
+                    StageOutcome::Failed(FailureClass::Verification, message)
+                }
+                Err(e) => {
+                    println!("Proof {} verification failed on {}: {}", id, kind, e);
+                    let message = format!("Verification on {} failed", kind);
+                    StageOutcome::Failed(FailureClass::Verification, message)
+                }
+            }
+        }
+        .boxed()
+    }
+
+    fn clean(&self, id: &str) -> Result<(), String> {
+        let dir = artifacts::proof_dir(id);
+        for path in [Artifact::Attestation.path(id), format!("{}/pending_attestation.json", dir)] {
+            match fs::remove_file(&path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(format!("Failed to remove {}: {}", path, e)),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use StageName::{Prove, Submit};
+    use StageState::{Cancelled, Done, Failed, Pending, Running, Skipped};
+
+    fn plan(stages: &[(StageName, StageState)]) -> Vec<PlannedStage> {
+        stages.iter().map(|&(stage, state)| PlannedStage::new(stage, state)).collect()
+    }
+
+    fn states(plan: &[PlannedStage]) -> Vec<StageState> {
+        plan.iter().map(|planned| planned.state).collect()
+    }
+
+    #[test]
+    fn stage_names_round_trip() {
+        for stage in STAGES {
+            assert_eq!(stage.name().to_string().parse(), Ok(stage.name()));
+        }
+        assert_eq!(
+            "bake".parse::<StageName>(),
+            Err("unknown stage 'bake', expected one of prove, submit".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_skips_takes_skippable_stages_once() {
+        assert_eq!(parse_skips(""), Ok(vec![]));
+        assert_eq!(parse_skips(" submit, ,submit"), Ok(vec![Submit]));
+        assert_eq!(parse_skips("prove"), Err("stage 'prove' cannot be skipped".to_string()));
+        assert!(parse_skips("submit,bake").unwrap_err().starts_with("unknown stage 'bake'"));
+    }
+
+    #[test]
+    fn full_plan_runs_every_stage() {
+        let full = full();
+        assert_eq!(full.iter().map(|planned| planned.stage).collect::<Vec<_>>(), [Prove, Submit]);
+        assert_eq!(states(&full), [Pending, Pending]);
+        assert!(skipped(&full).is_empty());
+        assert_eq!(skipped(&plan(&[(Prove, Done), (Submit, Skipped)])), [Submit]);
+    }
+
+    #[test]
+    fn restart_starts_at_the_first_stage_run() {
+        let mut failed = plan(&[(Prove, Done), (Submit, Failed)]);
+        failed[1].error = Some("refused".to_string());
+        assert_eq!(restart(&mut failed, None), Ok(Prove));
+        assert_eq!(states(&failed), [Pending, Pending]);
+        assert_eq!(failed[1].error, None);
+
+        let mut skipping = plan(&[(Prove, Failed), (Submit, Skipped)]);
+        assert_eq!(restart(&mut skipping, None), Ok(Prove));
+        assert_eq!(states(&skipping), [Pending, Skipped]);
+
+        let mut nothing = plan(&[(Prove, Skipped), (Submit, Skipped)]);
+        assert_eq!(restart(&mut nothing, None), Err("The measurement's plan runs no stage".into()));
+    }
+
+    #[test]
+    fn restart_from_a_stage_keeps_those_before_it() {
+        let mut failed = plan(&[(Prove, Done), (Submit, Failed)]);
+        failed[0].finished_at = Some(Utc::now());
+        assert_eq!(restart(&mut failed, Some(Submit)), Ok(Submit));
+        assert_eq!(states(&failed), [Done, Pending]);
+        assert!(failed[0].finished_at.is_some());
+
+        let mut unproved = plan(&[(Prove, Failed), (Submit, Pending)]);
+        assert_eq!(
+            restart(&mut unproved, Some(Submit)),
+            Err("Stage submit cannot be retried before stage prove is done".to_string())
+        );
+        assert_eq!(states(&unproved), [Failed, Pending]);
+
+        let mut skipping = plan(&[(Prove, Done), (Submit, Skipped)]);
+        assert_eq!(
+            restart(&mut skipping, Some(Submit)),
+            Err("The measurement's plan does not run stage submit".to_string())
+        );
+    }
+
+    #[test]
+    fn end_running_ends_only_running_stages() {
+        let mut measurement = Measurement::fixture("m");
+        measurement.status = ProofStatus::Processing;
+        measurement.plan = plan(&[(Prove, Running), (Submit, Pending)]);
+        end_running(&mut measurement, Cancelled, Some("cancelled"));
+        assert_eq!(states(&measurement.plan), [Cancelled, Pending]);
+        assert_eq!(measurement.plan[0].error.as_deref(), Some("cancelled"));
+        assert!(measurement.plan[0].finished_at.is_some());
+        assert!(measurement.plan[1].finished_at.is_none());
+        assert!(!skips(&measurement, Submit));
+    }
+}
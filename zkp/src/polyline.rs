@@ -0,0 +1,63 @@
+// Measurements along a polyline of more than two points.
+//
+// Instead of startPoint and endPoint, a submission may send its points as one
+// ordered JSON array of Point3D in the field points, at least two and at most
+// MAX_POLYLINE_POINTS of them, to measure something that is not straight. The
+// measurement's length is the sum of the lengths of its segments. Sending
+// points together with startPoint, endPoint or their coordinate fields is
+// refused.
+//
+// Measurements keep start_point and end_point as the first and last point, so
+// clients reading only those still see where the measurement starts and ends.
+// All points are kept in points, and are proved with ZKHOTDOG_POLYLINE, whose
+// public signals are the number of segments and the squared length of each.
+// A points array of exactly two points is an ordinary measurement: nothing is
+// kept in points and it is proved with ZKHOTDOG, from the same input.json as
+// if it had been sent as startPoint and endPoint.
+
+use crate::{
+    Point3D,
+    circuit::{self, POLYLINE_MAX_SEGMENTS},
+};
+
+// Most points the polyline circuit takes, the default for MAX_POLYLINE_POINTS
+pub const CAPACITY: usize = POLYLINE_MAX_SEGMENTS + 1;
+
+// Check MAX_POLYLINE_POINTS against what the polyline circuit takes
+pub fn check_limit(max: usize) -> Result<usize, String> {
+    if (2..=CAPACITY).contains(&max) {
+        Ok(max)
+    } else {
+        Err(format!(
+            "Invalid value for MAX_POLYLINE_POINTS: {} is outside 2 to {}, the points the circuit takes",
+            max, CAPACITY
+        ))
+    }
+}
+
+// Split submitted points into the start and end point of the measurement and
+// the points kept for a polyline, empty for two points
+pub fn split(points: Vec<Point3D>, max: usize) -> Result<(Point3D, Point3D, Vec<Point3D>), String> {
+    if points.len() < 2 || points.len() > max {
+        return Err(format!(
+            "points has {} points, a measurement needs 2 to {}",
+            points.len(),
+            max
+        ));
+    }
+    let start = points[0].clone();
+    let end = points[points.len() - 1].clone();
+    let kept = if points.len() > 2 { points } else { Vec::new() };
+    Ok((start, end, kept))
+}
+
+// Every point of a measurement in order, from its first to its last
+pub fn path(start: &Point3D, end: &Point3D, points: &[Point3D]) -> Vec<Point3D> {
+    if points.is_empty() { vec![start.clone(), end.clone()] } else { points.to_vec() }
+}
+
+// Circuit a new measurement with the given kept points is proved with, None
+// for the default one
+pub fn circuit_for(points: &[Point3D]) -> Option<String> {
+    (!points.is_empty()).then(|| circuit::ZKHOTDOG_POLYLINE.version.to_string())
+}
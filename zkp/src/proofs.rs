@@ -0,0 +1,97 @@
+// The proof of a measurement as one JSON document.
+//
+// GET /proofs/{id} answers the proof and public signals the pipeline wrote to
+// proofs/{id}, parsed, together with the circuit they were proved with, so a
+// frontend can show the verification details or submit the proof to a
+// contract without fetching the files one by one. Both 404s carry an error
+// code: measurement_not_found when the id names no measurement the caller may
+// see, and proof_not_available when the measurement has no proof yet, or will
+// not get one because it failed.
+//
+// The id is looked up among the known measurements before any file is read,
+// so it never reaches the filesystem unchecked. Embargoed measurements are
+// hidden like their artifacts.
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::{io, sync::Arc};
+
+use crate::{
+    AppState, ProofStatus, access, artifacts::Artifact, circuit, schemas::ErrorBody,
+    tenants::Visitor,
+};
+
+#[derive(Serialize)]
+pub struct ProofDocument {
+    measurement_id: String,
+    // Circuit version the proof was generated with
+    circuit: &'static str,
+    proof: Value,
+    public_signals: Value,
+}
+
+fn not_found(error: &'static str, message: String) -> Response {
+    (StatusCode::NOT_FOUND, Json(ErrorBody { error, message, since: None })).into_response()
+}
+
+// Parsed contents of a proof file, None when it has not been written
+pub async fn read(id: &str, artifact: Artifact) -> Result<Option<Value>, String> {
+    let content = match tokio::fs::read_to_string(artifact.path(id)).await {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Failed to read {}: {}", artifact.path(id), e)),
+    };
+    serde_json::from_str(&content)
+        .map(Some)
+        .map_err(|e| format!("Failed to parse {}: {}", artifact.path(id), e))
+}
+
+// Handler answering the proof and public signals of a measurement
+pub async fn get_proof(
+    State(state): State<Arc<AppState>>,
+    Visitor(caller): Visitor,
+    Path(id): Path<String>,
+) -> Response {
+    let known = state
+        .measurements
+        .lock()
+        .get(&id)
+        .filter(|m| access::may_see_published(&caller, m, state.time.now()))
+        .map(|m| (m.status.clone(), m.circuit.clone()));
+    let Some((status, circuit)) = known else {
+        return not_found("measurement_not_found", format!("Measurement with ID {} not found", id));
+    };
+
+    let files = match (read(&id, Artifact::Proof).await, read(&id, Artifact::PublicSignals).await) {
+        (Ok(proof), Ok(public_signals)) => proof.zip(public_signals),
+        (Err(e), _) | (_, Err(e)) => {
+            println!("Failed to read the proof of measurement {}: {}", id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
+        }
+    };
+    // A proof being written may lack its public signals for a moment
+    let Some((proof, public_signals)) = files else {
+        let message = match status {
+            ProofStatus::Failed => format!("Measurement {} failed without a proof", id),
+            ProofStatus::Cancelled => format!("Measurement {} was cancelled without a proof", id),
+            status => {
+                format!("The proof of measurement {} is not available yet ({:?})", id, status)
+            }
+        };
+        return not_found("proof_not_available", message);
+    };
+
+    Json(ProofDocument {
+        measurement_id: id,
+        circuit: circuit::resolve(circuit.as_deref()).version,
+        proof,
+        public_signals,
+    })
+    .into_response()
+}
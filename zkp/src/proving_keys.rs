@@ -0,0 +1,295 @@
+// Proving keys kept resident in memory.
+//
+// snarkjs reads the whole zkey of a circuit, hundreds of megabytes, for every
+// proof. Once the page cache drops it under memory pressure, each proof pays
+// for reading it from disk again. Proofs run in a snarkjs child process, so
+// there is no in-process prover to hand a deserialized key to; instead the
+// key file is mapped into this server, its pages are faulted in with
+// MADV_WILLNEED and, where RLIMIT_MEMLOCK allows, locked so the kernel keeps
+// them cached for every snarkjs run. Circuits proved natively (PROVER=native)
+// hold their key in the native prover instead and are not pinned.
+//
+// PROVING_KEY_PIN=eager pins the key of every installed circuit at startup,
+// lazy pins each on its first proof, for deployments that rarely prove with
+// older circuits, and off pins nothing. A key is pinned again when its file
+// changes, and the pinned keys are reported in /admin/stats.
+
+use chrono::{DateTime, Utc};
+use memmap2::{Advice, Mmap, MmapOptions};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, File},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use crate::{
+    AppState,
+    circuit::{self, Circuit},
+    native_prover::ProverKind,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PinMode {
+    Eager,
+    Lazy,
+    Off,
+}
+
+impl FromStr for PinMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "eager" => Ok(PinMode::Eager),
+            "lazy" => Ok(PinMode::Lazy),
+            "off" => Ok(PinMode::Off),
+            other => Err(format!("unknown pin mode '{}', expected eager, lazy or off", other)),
+        }
+    }
+}
+
+impl fmt::Display for PinMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PinMode::Eager => write!(f, "eager"),
+            PinMode::Lazy => write!(f, "lazy"),
+            PinMode::Off => write!(f, "off"),
+        }
+    }
+}
+
+// A proving key mapped into memory
+pub struct PinnedKey {
+    // Never read from here; the mapping only keeps the file's pages cached
+    _map: Mmap,
+    bytes: u64,
+    // Modification time of the file when it was pinned, to notice a new key
+    modified: Option<SystemTime>,
+    // Whether the pages are locked, or only advised to stay cached
+    locked: bool,
+    pinned_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct PinnedKeyStats {
+    circuit_version: &'static str,
+    bytes: u64,
+    locked: bool,
+    pinned_at: DateTime<Utc>,
+}
+
+// Proving key figures for the admin stats endpoint
+#[derive(Serialize)]
+pub struct ProvingKeyStats {
+    mode: PinMode,
+    // Bytes of proving keys mapped, locked or not
+    pinned_bytes: u64,
+    locked_bytes: u64,
+    keys: Vec<PinnedKeyStats>,
+}
+
+pub struct ProvingKeys {
+    mode: PinMode,
+    keys: Mutex<HashMap<&'static str, Arc<PinnedKey>>>,
+    // Held while a key is read in, so concurrent proofs pin it only once
+    pinning: Mutex<()>,
+}
+
+impl ProvingKeys {
+    pub fn new(mode: PinMode) -> Self {
+        ProvingKeys { mode, keys: Mutex::new(HashMap::new()), pinning: Mutex::new(()) }
+    }
+
+    // The pinned key of a circuit, pinning it first if needed. Blocks while
+    // a key is read in, so it belongs on a blocking thread.
+    pub fn get(&self, circuit: &Circuit) -> Option<Arc<PinnedKey>> {
+        if self.mode == PinMode::Off {
+            return None;
+        }
+        let pinned = || {
+            let modified = fs::metadata(circuit.zkey_path).and_then(|meta| meta.modified()).ok();
+            let keys = self.keys.lock().unwrap();
+            keys.get(circuit.version).filter(|key| key.modified == modified).cloned()
+        };
+        if let Some(key) = pinned() {
+            return Some(key);
+        }
+        let _pinning = self.pinning.lock().unwrap();
+        if let Some(key) = pinned() {
+            return Some(key);
+        }
+
+        match pin(circuit.zkey_path) {
+            Ok(key) => {
+                println!(
+                    "Pinned proving key of {} ({} bytes{})",
+                    circuit.version,
+                    key.bytes,
+                    if key.locked { ", locked" } else { "" }
+                );
+                let key = Arc::new(key);
+                self.keys.lock().unwrap().insert(circuit.version, key.clone());
+                Some(key)
+            }
+            Err(e) => {
+                println!("Failed to pin proving key of {}: {}", circuit.version, e);
+                self.keys.lock().unwrap().remove(circuit.version);
+                None
+            }
+        }
+    }
+
+    pub fn stats(&self) -> ProvingKeyStats {
+        let keys = self.keys.lock().unwrap();
+        let mut stats: Vec<PinnedKeyStats> = keys
+            .iter()
+            .map(|(version, key)| PinnedKeyStats {
+                circuit_version: version,
+                bytes: key.bytes,
+                locked: key.locked,
+                pinned_at: key.pinned_at,
+            })
+            .collect();
+        stats.sort_by_key(|key| key.circuit_version);
+        ProvingKeyStats {
+            mode: self.mode,
+            pinned_bytes: stats.iter().map(|key| key.bytes).sum(),
+            locked_bytes: stats.iter().filter(|key| key.locked).map(|key| key.bytes).sum(),
+            keys: stats,
+        }
+    }
+}
+
+// Map a key file and bring all of it into memory
+fn pin(path: &str) -> Result<PinnedKey, String> {
+    let file = File::open(path).map_err(|e| format!("Cannot open {}: {}", path, e))?;
+    let meta = file.metadata().map_err(|e| format!("Cannot stat {}: {}", path, e))?;
+    // SAFETY: the mapping is never read through, so a file changed underneath
+    // it cannot be observed
+    let map = unsafe { MmapOptions::new().populate().map(&file) }
+        .map_err(|e| format!("Cannot map {}: {}", path, e))?;
+    if let Err(e) = map.advise(Advice::WillNeed) {
+        println!("Failed to advise the kernel about {}: {}", path, e);
+    }
+    let locked = match map.lock() {
+        Ok(()) => true,
+        Err(e) => {
+            println!("Proving key {} stays unlocked, raise RLIMIT_MEMLOCK to lock it: {}", path, e);
+            false
+        }
+    };
+    Ok(PinnedKey {
+        _map: map,
+        bytes: meta.len(),
+        modified: meta.modified().ok(),
+        locked,
+        pinned_at: Utc::now(),
+    })
+}
+
+// Pin the keys of every installed circuit, in eager mode, except those the
+// native prover holds already. A supervising server cannot tell which keys
+// its prover child read, so with PROVER=native it leaves pinning to the
+// child, which pins the key of a circuit falling back to snarkjs on its
+// first proof.
+pub async fn preload(state: Arc<AppState>) {
+    if state.proving_keys.mode != PinMode::Eager {
+        return;
+    }
+    if state.config.supervise && state.config.prover == ProverKind::Native {
+        return;
+    }
+    let proved_natively = |c: &Circuit| state.native_prover.key(c).is_some();
+    for circuit in circuit::all().filter(|c| c.is_installed() && !proved_natively(c)) {
+        acquire(&state.proving_keys, circuit).await;
+    }
+}
+
+// Make sure a circuit's key is pinned before it is used for a proof
+pub async fn acquire(keys: &Arc<ProvingKeys>, circuit: Circuit) -> Option<Arc<PinnedKey>> {
+    let keys = keys.clone();
+    tokio::task::spawn_blocking(move || keys.get(&circuit)).await.ok().flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::Write,
+        os::fd::AsRawFd,
+        time::{Duration, Instant},
+    };
+    use uuid::Uuid;
+
+    // Size of the mock proving key; real ones are hundreds of megabytes
+    const KEY_BYTES: usize = 32 << 20;
+    const PROOFS: usize = 8;
+
+    unsafe extern "C" {
+        fn posix_fadvise(fd: i32, offset: i64, len: i64, advice: i32) -> i32;
+    }
+    const POSIX_FADV_DONTNEED: i32 = 4;
+
+    // A circuit whose proving key is a mock file of KEY_BYTES
+    fn mock_circuit() -> Circuit {
+        let path = std::env::temp_dir().join(format!("mock-{}.zkey", Uuid::new_v4()));
+        let mut file = File::create(&path).unwrap();
+        let chunk: Vec<u8> = (0..1 << 20).map(|i| i as u8).collect();
+        for _ in 0..KEY_BYTES / chunk.len() {
+            file.write_all(&chunk).unwrap();
+        }
+        let zkey_path = Box::leak(path.to_string_lossy().into_owned().into_boxed_str());
+        Circuit { zkey_path, ..circuit::ZKHOTDOG }
+    }
+
+    // Ask the kernel to drop the file's cached pages, as memory pressure
+    // would between two proofs. Pages of a pinned key stay mapped and are
+    // kept.
+    fn evict(path: &str) {
+        let file = File::open(path).unwrap();
+        // SAFETY: the descriptor is open for the duration of the call
+        unsafe { posix_fadvise(file.as_raw_fd(), 0, 0, POSIX_FADV_DONTNEED) };
+    }
+
+    // Median latency of a proof, which acquires the key and then reads all
+    // of it like snarkjs does
+    fn proof_latency(keys: &ProvingKeys, circuit: &Circuit) -> Duration {
+        let mut latencies: Vec<Duration> = (0..PROOFS)
+            .map(|_| {
+                evict(circuit.zkey_path);
+                let started = Instant::now();
+                keys.get(circuit);
+                assert_eq!(fs::read(circuit.zkey_path).unwrap().len(), KEY_BYTES);
+                started.elapsed()
+            })
+            .collect();
+        latencies.sort();
+        latencies[PROOFS / 2]
+    }
+
+    #[test]
+    fn pinned_keys_are_not_slower_to_prove_with() {
+        let circuit = mock_circuit();
+
+        let unpinned = ProvingKeys::new(PinMode::Off);
+        let unpinned_latency = proof_latency(&unpinned, &circuit);
+        assert!(unpinned.stats().keys.is_empty());
+
+        let pinned = ProvingKeys::new(PinMode::Lazy);
+        let pinned_latency = proof_latency(&pinned, &circuit);
+        let stats = pinned.stats();
+        assert_eq!(stats.keys.len(), 1);
+        assert_eq!(stats.pinned_bytes, KEY_BYTES as u64);
+
+        println!("Per-proof latency: {:?} pinned, {:?} unpinned", pinned_latency, unpinned_latency);
+        // Generous, so that a page cache ignoring the eviction (tmpfs) does
+        // not make this flaky; on a disk the pinned proofs are far faster
+        assert!(pinned_latency <= unpinned_latency * 2 + Duration::from_millis(5));
+        let _ = fs::remove_file(circuit.zkey_path);
+    }
+}
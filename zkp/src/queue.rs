@@ -0,0 +1,355 @@
+// Queue of measurements waiting for proof generation.
+//
+// Jobs are kept in one FIFO per owner. Under the fifo policy workers always
+// take the globally oldest job; under the fair policy they rotate through the
+// owners with queued work and take each owner's oldest job in turn, so one
+// owner submitting hundreds of measurements only delays others by one job per
+// round instead of by their whole backlog.
+
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt,
+    str::FromStr,
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+use tokio::sync::Notify;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueuePolicy {
+    Fifo,
+    Fair,
+}
+
+impl FromStr for QueuePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "fifo" => Ok(QueuePolicy::Fifo),
+            "fair" => Ok(QueuePolicy::Fair),
+            other => Err(format!("unknown queue policy '{}', expected fifo or fair", other)),
+        }
+    }
+}
+
+impl fmt::Display for QueuePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueuePolicy::Fifo => write!(f, "fifo"),
+            QueuePolicy::Fair => write!(f, "fair"),
+        }
+    }
+}
+
+// Where a queued measurement stands, as reported in its status
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct QueuePosition {
+    // Number of jobs that will start before this one
+    pub ahead: usize,
+    // Rough time until proving starts, once a proof duration has been observed
+    pub eta_seconds: Option<u64>,
+}
+
+// Queue figures for the admin stats endpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueStats {
+    pub policy: QueuePolicy,
+    pub workers: usize,
+    pub depth: usize,
+    pub per_owner: BTreeMap<String, usize>,
+    pub average_proof_seconds: Option<f64>,
+}
+
+struct Job {
+    seq: u64,
+    id: String,
+}
+
+#[derive(Default)]
+struct QueueInner {
+    next_seq: u64,
+    // Pending jobs of each owner, oldest first
+    owners: HashMap<String, VecDeque<Job>>,
+    // Owners with pending jobs, in the order the fair policy serves them
+    rotation: VecDeque<String>,
+    // Exponential moving average of proof durations in seconds
+    average_proof_seconds: Option<f64>,
+}
+
+pub struct ProofQueue {
+    policy: QueuePolicy,
+    workers: usize,
+    inner: Mutex<QueueInner>,
+    notify: Notify,
+    // Workers currently proving a measurement they took off the queue
+    busy: AtomicUsize,
+}
+
+impl ProofQueue {
+    pub fn new(policy: QueuePolicy, workers: usize) -> Self {
+        ProofQueue {
+            policy,
+            workers,
+            inner: Mutex::new(QueueInner::default()),
+            notify: Notify::new(),
+            busy: AtomicUsize::new(0),
+        }
+    }
+
+    // Add a measurement to the back of its owner's queue
+    pub fn push(&self, owner: &str, id: &str) {
+        {
+            let mut inner = self.inner.lock().unwrap();
+            let seq = inner.next_seq;
+            inner.next_seq += 1;
+
+            let jobs = inner.owners.entry(owner.to_string()).or_default();
+            jobs.push_back(Job { seq, id: id.to_string() });
+            if jobs.len() == 1 {
+                inner.rotation.push_back(owner.to_string());
+            }
+        }
+        self.notify.notify_one();
+    }
+
+    // Wait for the next measurement to prove according to the policy
+    pub async fn pop(&self) -> String {
+        loop {
+            if let Some(id) = self.try_pop() {
+                return id;
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    fn try_pop(&self) -> Option<String> {
+        let mut inner = self.inner.lock().unwrap();
+
+        let owner = match self.policy {
+            QueuePolicy::Fair => inner.rotation.pop_front()?,
+            QueuePolicy::Fifo => {
+                let owner = inner
+                    .owners
+                    .iter()
+                    .filter_map(|(owner, jobs)| jobs.front().map(|job| (job.seq, owner)))
+                    .min()
+                    .map(|(_, owner)| owner.clone())?;
+                inner.rotation.retain(|o| o != &owner);
+                owner
+            }
+        };
+
+        let jobs = inner.owners.get_mut(&owner)?;
+        let job = jobs.pop_front()?;
+        if jobs.is_empty() {
+            inner.owners.remove(&owner);
+        } else {
+            inner.rotation.push_back(owner);
+        }
+
+        // Let another idle worker check for more work
+        if !inner.owners.is_empty() {
+            self.notify.notify_one();
+        }
+        self.busy.fetch_add(1, Ordering::SeqCst);
+        Some(job.id)
+    }
+
+    // Position of a queued measurement, or None when it is not queued
+    pub fn position(&self, id: &str) -> Option<QueuePosition> {
+        let inner = self.inner.lock().unwrap();
+
+        let (owner, index, seq) = inner.owners.iter().find_map(|(owner, jobs)| {
+            jobs.iter().position(|job| job.id == id).map(|index| (owner, index, jobs[index].seq))
+        })?;
+
+        let ahead = match self.policy {
+            QueuePolicy::Fifo => inner
+                .owners
+                .values()
+                .map(|jobs| jobs.iter().take_while(|job| job.seq < seq).count())
+                .sum(),
+            QueuePolicy::Fair => {
+                // Owners served before this one in the current round get one
+                // more turn than those served after it
+                let mut before = true;
+                inner
+                    .rotation
+                    .iter()
+                    .map(|o| {
+                        if o == owner {
+                            before = false;
+                            return index;
+                        }
+                        let queued = inner.owners.get(o).map_or(0, VecDeque::len);
+                        queued.min(if before { index + 1 } else { index })
+                    })
+                    .sum()
+            }
+        };
+
+        let eta_seconds = inner
+            .average_proof_seconds
+            .map(|avg| ((ahead / self.workers + 1) as f64 * avg).round() as u64);
+
+        Some(QueuePosition { ahead, eta_seconds })
+    }
+
+    // Remove a measurement from the queue if it has not started yet
+    pub fn remove(&self, id: &str) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+
+        let Some(owner) = inner
+            .owners
+            .iter()
+            .find(|(_, jobs)| jobs.iter().any(|job| job.id == id))
+            .map(|(owner, _)| owner.clone())
+        else {
+            return false;
+        };
+
+        if let Some(jobs) = inner.owners.get_mut(&owner) {
+            jobs.retain(|job| job.id != id);
+            if jobs.is_empty() {
+                inner.owners.remove(&owner);
+                inner.rotation.retain(|o| o != &owner);
+            }
+        }
+        true
+    }
+
+    // Note that a worker finished its proof, feeding the duration into the
+    // ETA estimate
+    pub fn finished(&self, duration: Duration) {
+        self.busy.fetch_sub(1, Ordering::SeqCst);
+        let mut inner = self.inner.lock().unwrap();
+        let secs = duration.as_secs_f64();
+        inner.average_proof_seconds = Some(match inner.average_proof_seconds {
+            Some(avg) => avg * 0.8 + secs * 0.2,
+            None => secs,
+        });
+    }
+
+    // Whether nothing waits and a worker is free, leaving capacity for
+    // background work
+    pub fn is_idle(&self) -> bool {
+        self.busy.load(Ordering::SeqCst) < self.workers
+            && self.inner.lock().unwrap().owners.is_empty()
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        let inner = self.inner.lock().unwrap();
+        let per_owner: BTreeMap<String, usize> =
+            inner.owners.iter().map(|(owner, jobs)| (owner.clone(), jobs.len())).collect();
+
+        QueueStats {
+            policy: self.policy,
+            workers: self.workers,
+            depth: per_owner.values().sum(),
+            per_owner,
+            average_proof_seconds: inner.average_proof_seconds,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    // Jobs of three owners submitted interleaved, one owner with most of them
+    const SUBMISSIONS: [(&str, &str); 9] = [
+        ("alice", "a1"),
+        ("alice", "a2"),
+        ("alice", "a3"),
+        ("bob", "b1"),
+        ("alice", "a4"),
+        ("carol", "c1"),
+        ("bob", "b2"),
+        ("alice", "a5"),
+        ("carol", "c2"),
+    ];
+
+    fn queue(policy: QueuePolicy) -> Arc<ProofQueue> {
+        let queue = Arc::new(ProofQueue::new(policy, 1));
+        for (owner, id) in SUBMISSIONS {
+            queue.push(owner, id);
+        }
+        queue
+    }
+
+    // Drain the queue with one worker whose mock prover takes a millisecond
+    // per proof, and return the ids in the order their proofs completed
+    async fn completion_order(queue: Arc<ProofQueue>) -> Vec<String> {
+        let worker = tokio::spawn(async move {
+            let mut completed = Vec::new();
+            while !queue.is_idle() {
+                let id = queue.pop().await;
+                tokio::time::sleep(Duration::from_millis(1)).await;
+                queue.finished(Duration::from_millis(1));
+                completed.push(id);
+            }
+            completed
+        });
+        worker.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn fair_policy_serves_owners_round_robin() {
+        let queue = queue(QueuePolicy::Fair);
+        let order = completion_order(queue.clone()).await;
+        assert_eq!(order, ["a1", "b1", "c1", "a2", "b2", "c2", "a3", "a4", "a5"]);
+        assert_eq!(queue.stats().depth, 0);
+    }
+
+    #[tokio::test]
+    async fn fifo_policy_serves_submission_order() {
+        let order = completion_order(queue(QueuePolicy::Fifo)).await;
+        let submitted: Vec<&str> = SUBMISSIONS.iter().map(|(_, id)| *id).collect();
+        assert_eq!(order, submitted);
+    }
+
+    #[test]
+    fn stats_report_the_depth_of_each_owner() {
+        let queue = queue(QueuePolicy::Fair);
+        let stats = queue.stats();
+        assert_eq!(stats.depth, 9);
+        assert_eq!(
+            stats.per_owner,
+            BTreeMap::from([
+                ("alice".to_string(), 5),
+                ("bob".to_string(), 2),
+                ("carol".to_string(), 2)
+            ])
+        );
+
+        // A saturated worker takes one job of each owner per round
+        for _ in 0..3 {
+            queue.try_pop().unwrap();
+        }
+        let per_owner = queue.stats().per_owner;
+        assert_eq!(per_owner.values().copied().collect::<Vec<_>>(), [4, 1, 1]);
+    }
+
+    #[test]
+    fn positions_follow_the_policy() {
+        let fair = queue(QueuePolicy::Fair);
+        let fifo = queue(QueuePolicy::Fifo);
+        // Bob's second job waits for a round of all three owners, then alice's
+        assert_eq!(fair.position("b2").unwrap().ahead, 4);
+        assert_eq!(fifo.position("b2").unwrap().ahead, 6);
+        // Alice's last job waits for everyone else's
+        assert_eq!(fair.position("a5").unwrap().ahead, 8);
+        assert!(fair.position("unknown").is_none());
+
+        fair.finished(Duration::from_secs(10));
+        assert_eq!(fair.position("b2").unwrap().eta_seconds, Some(50));
+    }
+}
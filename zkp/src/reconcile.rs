@@ -0,0 +1,399 @@
+// Rebuilding measurements from the files they left behind.
+//
+// Every proofs/{id} directory and every uploads/{id}.jpg belongs to a
+// measurement that was once accepted, also when its record is missing from
+// the store, for example because it predates the store or the store was
+// lost. Reading every directory takes long with many measurements, so the
+// scan runs as a background job of kind consistency_scan once the server is
+// ready. It looks at these directories, including each tenant's, and rebuilds
+// a measurement for every id the store did not know at startup and whose
+// files were written before it:
+//
+//   - the points are read back from input.json, so an id without one cannot
+//     be rebuilt and is only counted; one holding a polyline's points is
+//     rebuilt as a polyline of the polyline circuit, while an angle's holds
+//     only the vectors between its points and a threshold measurement's only
+//     its squared minimum, and they are skipped as malformed;
+//   - with attestation.json the measurement is Completed with its
+//     attestation;
+//   - with proof.json and public.json it is Processing when the zkVerify
+//     client left pending_attestation.json behind, and otherwise Completed
+//     awaiting its attestation;
+//   - with only input.json it is Pending and is proved again.
+//
+// A measurement rebuilt as Completed has its image checked against the hash
+// its proof commits to, as on completion (commitment.rs); on a mismatch it is
+// rebuilt as Failed with the class CommitmentMismatch instead.
+//
+// Whatever the files cannot tell, such as the submitter, metadata or the
+// circuit of a reproof, is left at its default. Directories holding JSON that
+// does not parse are skipped with a log line. Rebuilt measurements left
+// Pending or Processing are then recovered like stored ones.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::Path,
+    sync::Arc,
+    time::{Instant, SystemTime},
+};
+use uuid::Uuid;
+
+use crate::{
+    AppState, AttestationData, Failure, FailureClass, Measurement, Point3D, ProofStatus,
+    artifacts::{self, Artifact, Artifacts},
+    circuit, commitment, coords,
+    history::{self, Source},
+    store,
+    version::MeasurementVersions,
+};
+
+// How the files of one id were handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Outcome {
+    Completed,
+    AwaitingAttestation,
+    Pending,
+    CommitmentMismatch,
+    WithoutInputs,
+    Malformed,
+}
+
+// Number of ids handled each way, the report of the scan's job
+#[derive(Debug, Serialize, Default, Clone)]
+struct Outcomes {
+    completed: usize,
+    awaiting_attestation: usize,
+    pending: usize,
+    commitment_mismatch: usize,
+    without_inputs: usize,
+    malformed: usize,
+}
+
+impl Outcomes {
+    fn count(&mut self, outcome: Outcome) {
+        let count = match outcome {
+            Outcome::Completed => &mut self.completed,
+            Outcome::AwaitingAttestation => &mut self.awaiting_attestation,
+            Outcome::Pending => &mut self.pending,
+            Outcome::CommitmentMismatch => &mut self.commitment_mismatch,
+            Outcome::WithoutInputs => &mut self.without_inputs,
+            Outcome::Malformed => &mut self.malformed,
+        };
+        *count += 1;
+    }
+
+    fn rebuilt(&self) -> usize {
+        self.completed + self.awaiting_attestation + self.pending + self.commitment_mismatch
+    }
+
+    fn total(&self) -> usize {
+        self.rebuilt() + self.without_inputs + self.malformed
+    }
+}
+
+// Ids between progress updates of the scan's job
+const PROGRESS_INTERVAL: usize = 1000;
+
+// Start the scan as a background job. The measurements known now are left
+// alone, so one deleted while the scan runs is not rebuilt from files that
+// are still being removed.
+pub fn start(state: &Arc<AppState>) {
+    let known: HashSet<String> = state.measurements.lock().keys().cloned().collect();
+    tokio::spawn(run(state.clone(), known));
+}
+
+async fn run(state: Arc<AppState>, known: HashSet<String>) {
+    let began = Instant::now();
+    let task_state = state.clone();
+    let found = tokio::task::spawn_blocking(move || {
+        // Files written since the start belong to submissions of this process
+        let before = task_state.startup.started_at;
+        let mut found = BTreeMap::new();
+        scan(&mut found, None, before);
+        for tenant in task_state.config.tenants.names() {
+            scan(&mut found, Some(tenant), before);
+        }
+        found.retain(|id, _| !known.contains(id));
+        found
+    })
+    .await
+    .unwrap_or_default();
+
+    let job_id = state.jobs.start("consistency_scan", found.len(), None);
+    state.startup.scan_started(&job_id);
+    let task_state = state.clone();
+    let task_job_id = job_id.clone();
+    let (outcomes, unfinished) =
+        tokio::task::spawn_blocking(move || rebuild(&task_state, &task_job_id, found))
+            .await
+            .unwrap_or_default();
+
+    let rebuilt = outcomes.rebuilt();
+    if outcomes.total() > 0 {
+        println!(
+            "Rebuilt measurements from uploads/ and proofs/: {} completed, {} awaiting their attestation, {} pending; skipped {} without input.json and {} with malformed JSON",
+            outcomes.completed,
+            outcomes.awaiting_attestation,
+            outcomes.pending,
+            outcomes.without_inputs,
+            outcomes.malformed
+        );
+    }
+    state.jobs.finish(&job_id, json!(outcomes));
+    // Rebuilt measurements left unfinished are recovered like stored ones
+    store::resume(&state, unfinished).await;
+    state.startup.scan_finished(rebuilt, began);
+}
+
+// Rebuild the measurements of the ids found, returning the outcomes and the
+// ids of those rebuilt as Pending or Processing
+fn rebuild(
+    state: &AppState,
+    job_id: &str,
+    found: BTreeMap<String, Option<String>>,
+) -> (Outcomes, Vec<String>) {
+    let mut outcomes = Outcomes::default();
+    let mut unfinished = Vec::new();
+    for (processed, (id, tenant)) in found.into_iter().enumerate() {
+        if processed % PROGRESS_INTERVAL == 0 {
+            state.jobs.update(job_id, processed, json!(outcomes));
+        }
+        if state.measurements.lock().contains_key(&id) {
+            continue;
+        }
+        if let Some(tenant) = &tenant {
+            artifacts::assign_tenant(&id, tenant);
+        }
+        let outcome = match rebuild_one(&id, tenant) {
+            Ok(measurement) => {
+                let outcome = match (&measurement.status, &measurement.attestation) {
+                    (ProofStatus::Pending, _) => Outcome::Pending,
+                    (ProofStatus::Failed, _) => {
+                        if let Some(failure) = &measurement.failure {
+                            state.commitments.alert(&id, &failure.message);
+                        }
+                        Outcome::CommitmentMismatch
+                    }
+                    (ProofStatus::Completed, Some(_)) => Outcome::Completed,
+                    _ => Outcome::AwaitingAttestation,
+                };
+                let mut measurements = state.measurements.lock();
+                if measurements.contains_key(&id) {
+                    continue;
+                }
+                if matches!(measurement.status, ProofStatus::Pending | ProofStatus::Processing) {
+                    unfinished.push(id.clone());
+                }
+                measurements.insert(id, measurement);
+                outcome
+            }
+            Err(outcome) => outcome,
+        };
+        outcomes.count(outcome);
+    }
+    (outcomes, unfinished)
+}
+
+// Collect the measurement ids found in one tenant's directories, or in the
+// top-level ones, whose files were last written before the given time
+fn scan(found: &mut BTreeMap<String, Option<String>>, tenant: Option<&String>, before: SystemTime) {
+    for dir in ["uploads", "proofs"] {
+        let dir = match tenant {
+            Some(tenant) => format!("{}/{}", dir, tenant),
+            None => dir.to_string(),
+        };
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // Images are stored as {id}.jpg next to their variants
+            let id = name.strip_suffix(".jpg").unwrap_or(&name);
+            if Uuid::parse_str(id).is_err() {
+                continue;
+            }
+            let modified = entry.metadata().and_then(|meta| meta.modified());
+            if modified.is_ok_and(|modified| modified >= before) {
+                continue;
+            }
+            found.insert(id.to_string(), tenant.cloned());
+        }
+    }
+}
+
+fn rebuild_one(id: &str, tenant: Option<String>) -> Result<Measurement, Outcome> {
+    let input = match read_json(id, Artifact::Input)? {
+        Some(input) => input,
+        None => {
+            println!("Cannot rebuild measurement {}: it has no input.json", id);
+            return Err(Outcome::WithoutInputs);
+        }
+    };
+    let (path, circuit) = points(&input).map_err(|e| {
+        println!("Skipping measurement {}: {}", id, e);
+        Outcome::Malformed
+    })?;
+
+    let attestation = match read_json(id, Artifact::Attestation)? {
+        Some(value) => Some(serde_json::from_value::<AttestationData>(value).map_err(|e| {
+            println!("Skipping measurement {}: malformed attestation.json: {}", id, e);
+            Outcome::Malformed
+        })?),
+        None => None,
+    };
+    let proved = read_json(id, Artifact::Proof)?.is_some()
+        && read_json(id, Artifact::PublicSignals)?.is_some();
+    let awaiting =
+        Path::new(&format!("{}/pending_attestation.json", artifacts::proof_dir(id))).exists();
+    let status = match (&attestation, proved) {
+        (Some(_), _) => ProofStatus::Completed,
+        (None, true) if awaiting => ProofStatus::Processing,
+        (None, true) => ProofStatus::Completed,
+        (None, false) => ProofStatus::Pending,
+    };
+    // Measurements rebuilt from files have no recorded image hash to compare with
+    let failure = match status {
+        ProofStatus::Completed => commitment::verify(id, None, &circuit::resolve(circuit))
+            .err()
+            .map(|message| Failure { class: FailureClass::CommitmentMismatch, message }),
+        _ => None,
+    };
+    let status = if failure.is_some() { ProofStatus::Failed } else { status };
+
+    let mut artifacts = Artifacts::default();
+    artifacts.record_all(id, &[Artifact::Image, Artifact::SecondImage]);
+    artifacts.record_all(id, &Artifact::PROOF_OUTPUTS);
+    artifacts.record(id, Artifact::Attestation);
+    let image_path = Artifact::Image.path(id);
+    let mut image_paths = vec![image_path.clone()];
+    if artifacts.image2.available {
+        image_paths.push(Artifact::SecondImage.path(id));
+    }
+    let created_at = [image_path.clone(), Artifact::Input.path(id)]
+        .iter()
+        .filter_map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+        .min()
+        .map(|modified: SystemTime| DateTime::<Utc>::from(modified));
+    // The last file written as the proof progressed
+    let updated_at =
+        [Artifact::Input, Artifact::Proof, Artifact::PublicSignals, Artifact::Attestation]
+            .iter()
+            .filter_map(|artifact| {
+                fs::metadata(artifact.path(id)).and_then(|meta| meta.modified()).ok()
+            })
+            .max()
+            .map(|modified: SystemTime| DateTime::<Utc>::from(modified));
+
+    let mut measurement = Measurement {
+        id: id.to_string(),
+        owner: String::new(),
+        tenant,
+        external_id: None,
+        idempotency_key: None,
+        image_path,
+        image_paths,
+        // The format and size of a depth map are not kept in its file
+        depth_map: None,
+        length_cm: Some(coords::length_cm(&path)),
+        start_point: path[0].clone(),
+        end_point: path[path.len() - 1].clone(),
+        points: if path.len() > 2 { path } else { Vec::new() },
+        measurement_type: Default::default(),
+        mid_point: None,
+        min_length_cm: None,
+        original_points: None,
+        coordinate_system: Default::default(),
+        status,
+        plan: Vec::new(),
+        attestation,
+        artifacts,
+        versions: MeasurementVersions::default(),
+        deadline: None,
+        failure,
+        metadata: Default::default(),
+        client_info: None,
+        publish_at: None,
+        publication_announced: false,
+        created_at,
+        updated_at,
+        captured_at: None,
+        imported: false,
+        scheduled_for: None,
+        circuit: circuit.map(str::to_string),
+        reprove_of: None,
+        reproofs: Vec::new(),
+        perceptual_hash: None,
+        image_sha256: None,
+        image_dimensions: None,
+        flagged_duplicate: None,
+        receipt: None,
+        attestation_backend: None,
+        callback_url: None,
+        webhook: None,
+        history: Vec::new(),
+    };
+    // The history starts with the status found in the files
+    let rebuilt_at = measurement.updated_at.unwrap_or_else(Utc::now);
+    history::record(&mut measurement, None, rebuilt_at, Source::Reconcile);
+    Ok(measurement)
+}
+
+// Contents of a JSON artifact, None when the file does not exist
+fn read_json(id: &str, artifact: Artifact) -> Result<Option<Value>, Outcome> {
+    let path = artifact.path(id);
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            println!("Skipping measurement {}: cannot read {}: {}", id, path, e);
+            return Err(Outcome::Malformed);
+        }
+    };
+    serde_json::from_str(&content).map(Some).map_err(|e| {
+        println!("Skipping measurement {}: malformed {}: {}", id, path, e);
+        Outcome::Malformed
+    })
+}
+
+// Scaled points written to input.json, as point1 and point2 or as the points
+// of a polyline up to its segment_count, and the circuit taking them if it is
+// not the default one
+fn points(input: &Value) -> Result<(Vec<Point3D>, Option<&'static str>), String> {
+    let coordinates = |name: &str, count: usize| -> Result<Vec<Point3D>, String> {
+        let components = input[name]
+            .as_array()
+            .filter(|components| components.len() >= count * 3)
+            .ok_or_else(|| format!("input.json has no {} of {} coordinates", name, count * 3))?;
+        let mut axes = Vec::with_capacity(count * 3);
+        for component in &components[..count * 3] {
+            let element = component
+                .as_str()
+                .ok_or_else(|| format!("{} in input.json is not a field element", name))?;
+            axes.push(coords::from_field(element).map_err(|e| e.to_string())? as f64);
+        }
+        Ok(axes.chunks(3).map(|axes| Point3D { x: axes[0], y: axes[1], z: axes[2] }).collect())
+    };
+    if input.get("vector1").is_some() {
+        return Err("input.json of an angle holds vectors, not its points".to_string());
+    }
+    if input.get("min_distance_squared").is_some() {
+        return Err(
+            "input.json of a threshold measurement holds no minimum in centimetres".to_string()
+        );
+    }
+    if input.get("points").is_none() {
+        let mut path = coordinates("point1", 1)?;
+        path.extend(coordinates("point2", 1)?);
+        return Ok((path, None));
+    }
+    let segments = input["segment_count"]
+        .as_str()
+        .and_then(|count| count.parse::<usize>().ok())
+        .filter(|count| *count >= 1)
+        .ok_or("input.json has no segment_count of at least 1")?;
+    Ok((coordinates("points", segments + 1)?, Some(circuit::ZKHOTDOG_POLYLINE.version)))
+}
@@ -0,0 +1,171 @@
+// Read-only mirror mode.
+//
+// A mirror in another region serves reads from storage replicated from the
+// primary without running any pipeline. With --read-only or READ_ONLY=true
+// the server opens uploads/ and proofs/ without creating or changing
+// anything, refuses every POST, PUT, PATCH and DELETE but the read POST
+// /status/batch with 405 and the error code "read_only", and starts no proof,
+// pre-warm, embargo, summary or cache workers. Startup fails at once when
+// the replicated directories are missing or unreadable.
+//
+// Replication itself happens outside the server, so staleness is derived
+// from the newest modification time found in the replicated directories.
+// Every response carries it as X-Replica-Staleness in seconds, and /ready
+// fails once it exceeds REPLICA_MAX_LAG_SECS.
+//
+// Measurement records live in the primary's embedded database, which only
+// one process can open and which cannot be copied safely while it is being
+// written, so a mirror has none to serve: /status/{id} and /img/{id} answer
+// 404.
+
+use axum::{
+    Json,
+    extract::{Request, State},
+    http::{HeaderValue, Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{
+    fs, io,
+    path::Path,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use crate::{AppState, schemas::ErrorBody, status_batch};
+
+// Directories replicated from the primary
+const REPLICATED_DIRS: [&str; 2] = ["uploads", "proofs"];
+// How deep below a replicated directory files are looked for: the tenant
+// directory, the measurement directory and its files
+const SCAN_DEPTH: usize = 3;
+// Interval between scans for the newest write
+const SCAN_INTERVAL: Duration = Duration::from_secs(10);
+
+pub const STALENESS_HEADER: &str = "x-replica-staleness";
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ReplicaState {
+    // Newest modification time in the replicated directories
+    last_write: Option<DateTime<Utc>>,
+    lag_seconds: Option<i64>,
+    max_lag_seconds: u64,
+}
+
+pub struct Replica {
+    max_lag_secs: u64,
+    last_write: RwLock<Option<DateTime<Utc>>>,
+}
+
+impl Replica {
+    // Open the replicated directories, failing if any cannot be read
+    pub fn open(max_lag_secs: u64) -> Result<Self, String> {
+        for dir in REPLICATED_DIRS {
+            fs::read_dir(dir).map_err(|e| {
+                format!(
+                    "Cannot start read-only: replicated directory {}/ is unreadable: {}",
+                    dir, e
+                )
+            })?;
+        }
+        Ok(Replica { max_lag_secs, last_write: RwLock::new(newest_write()) })
+    }
+
+    pub fn state(&self) -> ReplicaState {
+        let last_write = *self.last_write.read().unwrap();
+        ReplicaState {
+            last_write,
+            lag_seconds: last_write.map(|at| (Utc::now() - at).num_seconds().max(0)),
+            max_lag_seconds: self.max_lag_secs,
+        }
+    }
+
+    // Why the replica is too far behind to serve, if it is
+    pub fn check(&self) -> Result<(), String> {
+        match self.state().lag_seconds {
+            None => Err("no replicated files found".to_string()),
+            Some(lag) if lag as u64 > self.max_lag_secs => Err(format!(
+                "last replicated write was {} seconds ago, more than {}",
+                lag, self.max_lag_secs
+            )),
+            Some(_) => Ok(()),
+        }
+    }
+}
+
+// Newest modification time of any file in the replicated directories
+fn newest_write() -> Option<DateTime<Utc>> {
+    fn scan(path: &Path, depth: usize, newest: &mut Option<DateTime<Utc>>) -> io::Result<()> {
+        for entry in fs::read_dir(path)?.flatten() {
+            // Files removed while scanning are skipped
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            if meta.is_dir() {
+                if depth > 1 {
+                    scan(&entry.path(), depth - 1, newest)?;
+                }
+            } else if let Ok(modified) = meta.modified() {
+                let modified = DateTime::<Utc>::from(modified);
+                if newest.is_none_or(|at| modified > at) {
+                    *newest = Some(modified);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    let mut newest = None;
+    for dir in REPLICATED_DIRS {
+        if let Err(e) = scan(Path::new(dir), SCAN_DEPTH, &mut newest) {
+            println!("Failed to scan {}/ for replicated writes: {}", dir, e);
+        }
+    }
+    newest
+}
+
+// Background task following the replicated directories for new writes
+pub async fn watcher(state: Arc<AppState>) {
+    let Some(replica) = &state.replica else {
+        return;
+    };
+    let mut interval = tokio::time::interval(SCAN_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Ok(newest) = tokio::task::spawn_blocking(newest_write).await {
+            *replica.last_write.write().unwrap() = newest;
+        }
+    }
+}
+
+// Middleware refusing changes on a mirror and marking reads with their staleness
+pub async fn read_only(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(replica) = &state.replica else {
+        return next.run(request).await;
+    };
+    let mutation =
+        matches!(*request.method(), Method::POST | Method::PUT | Method::PATCH | Method::DELETE)
+            && request.uri().path() != status_batch::PATH;
+    let mut response = if mutation {
+        let body = ErrorBody {
+            error: "read_only",
+            message: "This server is a read-only mirror; send changes to the primary".to_string(),
+            since: None,
+        };
+        let mut response = (StatusCode::METHOD_NOT_ALLOWED, Json(body)).into_response();
+        response.headers_mut().insert(header::ALLOW, HeaderValue::from_static("GET, HEAD"));
+        response
+    } else {
+        next.run(request).await
+    };
+    if let Some(lag) = replica.state().lag_seconds {
+        response.headers_mut().insert(STALENESS_HEADER, HeaderValue::from(lag));
+    }
+    response
+}
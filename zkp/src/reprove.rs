@@ -0,0 +1,395 @@
+// Re-proving measurements under another circuit version.
+//
+// A reproof is a new measurement linked to its original through reprove_of.
+// It starts from the original's stored image, points and metadata and then
+// goes through intake, tenant quotas, the proof queue and the zkVerify
+// submission like any other measurement, so it is counted and paid for as a
+// proof of its own. The original keeps its proof and attestation for history
+// and lists its reproofs, and the status of either reports every generation
+// with the circuit it was proved with.
+//
+// Only originals that completed can be re-proved, and never twice with the
+// same circuit and proving key.
+
+use axum::{
+    Json,
+    body::Bytes,
+    extract::{ConnectInfo, Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{collections::HashMap, fs, net::SocketAddr, sync::Arc};
+
+use crate::{
+    AppState, Measurement, ProofStatus, Submission,
+    access::{self, Managers},
+    artifacts::Artifact,
+    auth::{Admin, Authorized, Submitter},
+    circuit::{self, Circuit},
+    depth_map::IncomingDepthMap,
+    listing::MeasurementFilter,
+    metadata::escape_html,
+    plan, submit,
+    uploads::IncomingImage,
+    version,
+};
+
+// One proof of a measurement: the original or one of its reproofs
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Generation {
+    id: String,
+    circuit_version: String,
+    status: ProofStatus,
+    attestation_id: Option<u64>,
+    created_at: Option<DateTime<Utc>>,
+}
+
+impl Generation {
+    fn of(m: &Measurement) -> Self {
+        Generation {
+            id: m.id.clone(),
+            circuit_version: circuit_version(m),
+            status: m.status.clone(),
+            attestation_id: m.attestation.as_ref().map(|a| a.attestation_id),
+            created_at: m.created_at,
+        }
+    }
+
+    // List item for the status page, linking to the generation's own page
+    pub fn to_html(&self) -> String {
+        let id = escape_html(&self.id);
+        let attestation = match self.attestation_id {
+            Some(attestation) => format!(", attestation {}", attestation),
+            None => String::new(),
+        };
+        format!(
+            "<li><a href=\"/view/{id}\">{id}</a>: {}, {}{}</li>",
+            escape_html(&self.circuit_version),
+            escape_html(&format!("{:?}", self.status)),
+            attestation
+        )
+    }
+}
+
+// Circuit version a measurement was, or is going to be, proved with
+pub fn circuit_version(m: &Measurement) -> String {
+    m.versions
+        .proved
+        .as_ref()
+        .map(|v| v.circuit_version.clone())
+        .or_else(|| m.circuit.clone())
+        .unwrap_or_else(|| circuit::ZKHOTDOG.version.to_string())
+}
+
+// Every generation of the measurement's original, oldest first, or nothing if
+// the original was never re-proved
+pub fn generations(
+    measurements: &HashMap<String, Measurement>,
+    measurement: &Measurement,
+) -> Vec<Generation> {
+    let original = match &measurement.reprove_of {
+        Some(original) => measurements.get(original),
+        None => Some(measurement),
+    };
+    let Some(original) = original.filter(|o| !o.reproofs.is_empty()) else {
+        return Vec::new();
+    };
+    std::iter::once(original)
+        .chain(original.reproofs.iter().filter_map(|id| measurements.get(id)))
+        .map(Generation::of)
+        .collect()
+}
+
+// Hash of a circuit's proving key, which may be large enough to take a while
+async fn zkey_hash(circuit: &Circuit) -> Option<String> {
+    let path = circuit.zkey_path;
+    tokio::task::spawn_blocking(move || version::hash_file(path)).await.ok().flatten()
+}
+
+// Circuit a reproof is requested for, which must be installed
+fn target_circuit(version: Option<&str>) -> Result<Circuit, (StatusCode, String)> {
+    let circuit = match version {
+        Some(version) => circuit::find(version).ok_or_else(|| {
+            let known: Vec<&str> = circuit::all().map(|c| c.version).collect();
+            (
+                StatusCode::BAD_REQUEST,
+                format!("Unknown circuit {}, available are {}", version, known.join(", ")),
+            )
+        })?,
+        None => circuit::ZKHOTDOG,
+    };
+    if !circuit.is_installed() {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("The proving key of circuit {} is not installed", circuit.version),
+        ));
+    }
+    Ok(circuit)
+}
+
+// Why a measurement cannot be re-proved with a circuit whose proving key has
+// the given hash, if it cannot
+fn refusal(
+    measurements: &HashMap<String, Measurement>,
+    m: &Measurement,
+    circuit: &Circuit,
+    zkey_hash: &Option<String>,
+) -> Option<String> {
+    if let Some(original) = &m.reprove_of {
+        return Some(format!(
+            "Measurement {} is a reproof, re-prove its original {}",
+            m.id, original
+        ));
+    }
+    if m.min_length_cm.is_some() && circuit.spec.input("min_distance_squared").is_none() {
+        return Some(format!(
+            "Measurement {} proves a minimum length, which circuit {} would reveal",
+            m.id, circuit.version
+        ));
+    }
+    if m.status != ProofStatus::Completed {
+        return Some(format!(
+            "Measurement {} is {:?}, only completed ones are re-proved",
+            m.id, m.status
+        ));
+    }
+    let generations =
+        std::iter::once(m).chain(m.reproofs.iter().filter_map(|id| measurements.get(id)));
+    for generation in generations {
+        let same_key = generation.versions.proved.as_ref().is_some_and(|proved| {
+            proved.circuit_version == circuit.version && &proved.zkey_hash == zkey_hash
+        });
+        let in_progress = generation.reprove_of.is_some()
+            && generation.circuit.as_deref() == Some(circuit.version)
+            && matches!(
+                generation.status,
+                ProofStatus::Scheduled | ProofStatus::Pending | ProofStatus::Processing
+            );
+        if same_key || in_progress {
+            return Some(format!(
+                "Measurement {} already has proof {} with circuit {} and the current proving key",
+                m.id, generation.id, circuit.version
+            ));
+        }
+    }
+    None
+}
+
+// Submit a reproof of a measurement, answering like POST /measurements
+async fn submit_reproof(
+    state: &Arc<AppState>,
+    id: &str,
+    circuit: &Circuit,
+    zkey_hash: &Option<String>,
+) -> Result<Response, (StatusCode, String)> {
+    let original = {
+        let measurements = state.measurements.lock();
+        let m = measurements.get(id).ok_or_else(|| access::not_found(id))?;
+        if let Some(reason) = refusal(&measurements, m, circuit, zkey_hash) {
+            return Err((StatusCode::CONFLICT, reason));
+        }
+        m.clone()
+    };
+
+    let image = fs::read(&original.image_path).map_err(|e| {
+        (StatusCode::CONFLICT, format!("The image of measurement {} is not available: {}", id, e))
+    })?;
+    let image2 = match original.image_paths.get(1) {
+        Some(path) => Some(fs::read(path).map_err(|e| {
+            (
+                StatusCode::CONFLICT,
+                format!("The second image of measurement {} is not available: {}", id, e),
+            )
+        })?),
+        None => None,
+    };
+    let depth_map = match original.depth_map {
+        Some(info) => Some(IncomingDepthMap {
+            data: Bytes::from(fs::read(Artifact::DepthMap.path(id)).map_err(|e| {
+                (
+                    StatusCode::CONFLICT,
+                    format!("The depth map of measurement {} is not available: {}", id, e),
+                )
+            })?),
+            info,
+        }),
+        None => None,
+    };
+    let submission = Submission {
+        owner: original.owner,
+        key_id: None,
+        tenant: original.tenant,
+        external_id: None,
+        idempotency_key: None,
+        image: IncomingImage::Bytes(Bytes::from(image)),
+        image2: image2.map(|data| IncomingImage::Bytes(Bytes::from(data))),
+        depth_map,
+        start_point: original.start_point,
+        end_point: original.end_point,
+        points: original.points,
+        measurement_type: original.measurement_type,
+        mid_point: original.mid_point,
+        min_length_cm: original.min_length_cm,
+        original_points: original.original_points,
+        coordinate_system: original.coordinate_system,
+        metadata: original.metadata,
+        client_info: original.client_info,
+        deadline: None,
+        publish_at: original.publish_at,
+        captured_at: original.captured_at,
+        imported: original.imported,
+        reprove_of: Some(original.id),
+        circuit: Some(circuit.version.to_string()),
+        callback_url: None,
+        skip_stages: plan::skipped(&original.plan),
+    };
+    submit(state, submission).await.map(IntoResponse::into_response)
+}
+
+#[derive(Deserialize)]
+pub struct ReproveQuery {
+    // Circuit version to prove with; the default circuit when absent
+    circuit: Option<String>,
+}
+
+// Handler letting the submitter re-prove a completed measurement
+pub async fn reprove(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Submitter>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    Path(id): Path<String>,
+    Query(query): Query<ReproveQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    // Checked before the circuit, so a refusal answers the same as a missing measurement
+    access::managed(
+        state.measurements.lock().get(&id),
+        &caller,
+        remote.ip(),
+        &id,
+        Managers::SubmitterOnly,
+        "re-prove a measurement",
+    )?;
+
+    let circuit = target_circuit(query.circuit.as_deref())?;
+    let zkey_hash = zkey_hash(&circuit).await;
+    submit_reproof(&state, &id, &circuit, &zkey_hash).await
+}
+
+#[derive(Deserialize)]
+pub struct BulkReproveRequest {
+    #[serde(flatten)]
+    filter: MeasurementFilter,
+    circuit: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct BulkReproveStarted {
+    job_id: String,
+    matched: usize,
+}
+
+#[derive(Serialize, Clone)]
+struct Reproof {
+    id: String,
+    reproof: String,
+}
+
+#[derive(Serialize, Clone)]
+struct SkippedMeasurement {
+    id: String,
+    reason: String,
+}
+
+#[derive(Serialize, Default, Clone)]
+struct BulkReproveReport {
+    reproved: Vec<Reproof>,
+    skipped: Vec<SkippedMeasurement>,
+}
+
+// Handler re-proving every completed original matching a filter as a
+// background job
+pub async fn bulk_reprove(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Admin>,
+    Json(mut request): Json<BulkReproveRequest>,
+) -> Result<(StatusCode, Json<BulkReproveStarted>), (StatusCode, String)> {
+    // Scoped admins only ever re-prove within their own tenant
+    if caller.tenant.is_some() {
+        if request.filter.tenant.is_some() && request.filter.tenant != caller.tenant {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "Only measurements of your own tenant can be re-proved".to_string(),
+            ));
+        }
+        request.filter.tenant = caller.tenant.clone();
+    }
+    let circuit = target_circuit(request.circuit.as_deref())?;
+
+    let matched: Vec<String> = state
+        .measurements
+        .lock()
+        .values()
+        .filter(|m| m.reprove_of.is_none() && request.filter.matches(m))
+        .map(|m| m.id.clone())
+        .collect();
+
+    let job_id = state.jobs.start("reprove", matched.len(), caller.tenant);
+    state.audit.record(
+        "reprove_started",
+        json!({
+            "job_id": job_id,
+            "filter": request.filter,
+            "circuit": circuit.version,
+            "matched": matched.len(),
+        }),
+    );
+
+    let response = BulkReproveStarted { job_id: job_id.clone(), matched: matched.len() };
+    tokio::spawn(run_bulk_reprove(state.clone(), job_id, circuit, matched));
+    Ok((StatusCode::ACCEPTED, Json(response)))
+}
+
+// Background job submitting a reproof of each of the given measurements
+async fn run_bulk_reprove(
+    state: Arc<AppState>,
+    job_id: String,
+    circuit: Circuit,
+    ids: Vec<String>,
+) {
+    let zkey_hash = zkey_hash(&circuit).await;
+    let mut report = BulkReproveReport::default();
+
+    for (processed, id) in ids.iter().enumerate() {
+        match submit_reproof(&state, id, &circuit, &zkey_hash).await {
+            Ok(response) if response.status() == StatusCode::OK => {
+                let reproof = state
+                    .measurements
+                    .lock()
+                    .get(id)
+                    .and_then(|m| m.reproofs.last().cloned())
+                    .unwrap_or_default();
+                report.reproved.push(Reproof { id: id.clone(), reproof });
+            }
+            Ok(response) => report.skipped.push(SkippedMeasurement {
+                id: id.clone(),
+                reason: format!("submission answered {}", response.status()),
+            }),
+            Err((_, reason)) => report.skipped.push(SkippedMeasurement { id: id.clone(), reason }),
+        }
+        state.jobs.update(&job_id, processed + 1, json!(report));
+    }
+
+    state.audit.record(
+        "reprove_finished",
+        json!({
+            "job_id": job_id,
+            "reproved": report.reproved.len(),
+            "skipped": report.skipped.len(),
+        }),
+    );
+    state.jobs.finish(&job_id, json!(report));
+}
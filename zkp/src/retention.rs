@@ -0,0 +1,107 @@
+// Retention of old measurements.
+//
+// With RETENTION_DAYS set, a background sweep deletes measurements created
+// longer ago than that, together with their image, its variants and their
+// proof directory, as DELETE /measurements/{id} does. Attested measurements
+// follow RETENTION_ATTESTED_DAYS instead, so a demo server can keep what made
+// it on chain longer than failed or unattested attempts. Measurements being
+// proved are never removed, nor are records from before created_at was kept,
+// whose age is unknown.
+//
+// A sweep runs every RETENTION_SWEEP_INTERVAL_SECS, outside maintenance. It
+// picks the expired measurements under the measurements lock, takes each one
+// out of the store under a lock of its own and removes its files once that
+// lock is released, so requests are never kept waiting on the disk.
+
+use chrono::{DateTime, Duration, Utc};
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::{
+    AppState, Measurement, ProofStatus, admin,
+    artifacts::{self, RemovalReport},
+};
+
+// Creation times before which measurements expire, unattested and attested
+struct Cutoffs {
+    unattested: Option<DateTime<Utc>>,
+    attested: Option<DateTime<Utc>>,
+}
+
+impl Cutoffs {
+    fn new(state: &AppState) -> Self {
+        let now = state.time.now();
+        let cutoff = |days: u64| (days > 0).then(|| now - Duration::days(days as i64));
+        Cutoffs {
+            unattested: cutoff(state.config.retention_days),
+            attested: cutoff(state.config.retention_attested_days),
+        }
+    }
+
+    fn is_expired(&self, m: &Measurement) -> bool {
+        let cutoff = if m.attestation.is_some() { self.attested } else { self.unattested };
+        m.status != ProofStatus::Processing
+            && cutoff.zip(m.created_at).is_some_and(|(cutoff, created_at)| created_at < cutoff)
+    }
+}
+
+// Background task deleting expired measurements, idle when retention is off
+pub async fn sweeper(state: Arc<AppState>) {
+    if state.config.retention_days == 0 && state.config.retention_attested_days == 0 {
+        return;
+    }
+    let interval = std::time::Duration::from_secs(state.config.retention_sweep_interval_secs);
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        state.maintenance.wait_until_off().await;
+        sweep(&state).await;
+    }
+}
+
+async fn sweep(state: &Arc<AppState>) {
+    let cutoffs = Cutoffs::new(state);
+    let expired: Vec<String> = state
+        .measurements
+        .lock()
+        .values()
+        .filter(|m| cutoffs.is_expired(m))
+        .map(|m| m.id.clone())
+        .collect();
+
+    let mut removed = Vec::new();
+    for id in expired {
+        // Skipped when it was deleted or picked up for proving meanwhile
+        let Ok(m) = admin::take_measurement(state, &mut state.measurements.lock(), &id) else {
+            continue;
+        };
+        let task_id = id.clone();
+        let files = tokio::task::spawn_blocking(move || artifacts::remove_all(&task_id))
+            .await
+            .unwrap_or_else(|e| RemovalReport { removed: Vec::new(), errors: vec![e.to_string()] });
+        state.cache.forget(&id);
+        println!(
+            "Retention removed measurement {} ({:?}, created {}) and {} files",
+            id,
+            m.status,
+            m.created_at.map(|at| at.to_rfc3339()).unwrap_or_default(),
+            files.removed.len()
+        );
+        if !files.errors.is_empty() {
+            println!("Not all files of measurement {} were removed: {:?}", id, files.errors);
+        }
+        removed.push(json!({ "id": id, "removed": files.removed, "errors": files.errors }));
+    }
+
+    if !removed.is_empty() {
+        println!("Retention sweep removed {} measurements", removed.len());
+        state.audit.record(
+            "measurements_expired",
+            json!({
+                "unattested_cutoff": cutoffs.unattested,
+                "attested_cutoff": cutoffs.attested,
+                "measurements": removed,
+            }),
+        );
+    }
+}
@@ -0,0 +1,131 @@
+// Retrying failed measurements.
+//
+// A measurement whose proof generation or zkVerify submission failed can be
+// sent through the pipeline again without uploading it anew. The retry keeps
+// the measurement's id, image and points: it goes back to Pending and into the
+// proof queue, and runs its plan (plan.rs) again from the first stage. With
+// ?from=<stage> it starts at that stage instead, once every stage before it is
+// done, so a refused submission is retried without proving again. What the
+// stages run again wrote for the failed attempt is removed from proofs/{id}
+// first, so nothing of it is mistaken for the new attempt's output or picked
+// up by the attestation poller: the proof, public signals and logs when
+// proving again, and the attestation in any case. input.json is kept, though
+// the pipeline writes it again anyway.
+//
+// Only failed measurements are retried; every other status is answered with
+// 409. The deadline of the failed attempt is dropped with it, as it would
+// fail the retry straight away.
+
+use axum::{
+    Json,
+    extract::{ConnectInfo, Path, Query, State},
+    http::StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::{net::SocketAddr, sync::Arc};
+
+use crate::{
+    AppState, ProofStatus,
+    access::{self, Managers},
+    artifacts::Artifact,
+    auth::{Authorized, Submitter},
+    events::EventKind,
+    history::Source,
+    plan::{self, StageName, StageState},
+};
+
+#[derive(Deserialize)]
+pub struct RetryQuery {
+    // Stage to start at instead of the first one
+    from: Option<StageName>,
+}
+
+#[derive(Serialize)]
+pub struct RetryResponse {
+    measurement_id: String,
+    status: ProofStatus,
+    // Stage the retry starts at
+    from: StageName,
+    url: String,
+}
+
+// Handler letting the submitter send a failed measurement through the pipeline again
+pub async fn retry(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Submitter>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    Path(id): Path<String>,
+    Query(query): Query<RetryQuery>,
+) -> Result<Json<RetryResponse>, (StatusCode, String)> {
+    let (restarted, from) = {
+        let measurements = state.measurements.lock();
+        let m = access::managed(
+            measurements.get(&id),
+            &caller,
+            remote.ip(),
+            &id,
+            Managers::SubmitterOnly,
+            "retry a measurement",
+        )?;
+        if m.status != ProofStatus::Failed {
+            return Err((
+                StatusCode::CONFLICT,
+                format!("Measurement {} is {:?}, only failed ones are retried", id, m.status),
+            ));
+        }
+        // Measurements recorded before plans were kept run every stage
+        let mut restarted = if m.plan.is_empty() { plan::full() } else { m.plan.clone() };
+        let from =
+            plan::restart(&mut restarted, query.from).map_err(|e| (StatusCode::CONFLICT, e))?;
+        (restarted, from)
+    };
+
+    // Nothing works on a failed measurement, so the files of the stages run
+    // again can go before it is reset
+    let task_id = id.clone();
+    let rerun: Vec<StageName> = restarted
+        .iter()
+        .filter(|planned| planned.state == StageState::Pending)
+        .map(|planned| planned.stage)
+        .collect();
+    tokio::task::spawn_blocking(move || {
+        rerun.iter().try_for_each(|name| plan::stage(*name).clean(&task_id))
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|cleaned| cleaned)
+    .map_err(|e| {
+        println!("Failed to clean up measurement {} for a retry: {}", id, e);
+        (StatusCode::INTERNAL_SERVER_ERROR, e)
+    })?;
+
+    let owner = {
+        let mut measurements = state.measurements.lock_as(Source::Retry);
+        // Another retry may have got here first
+        let m = measurements
+            .get_mut(&id)
+            .filter(|m| m.status == ProofStatus::Failed)
+            .ok_or((StatusCode::CONFLICT, format!("Measurement {} is already retried", id)))?;
+        m.status = ProofStatus::Pending;
+        m.plan = restarted;
+        m.failure = None;
+        m.attestation = None;
+        m.attestation_backend = None;
+        m.receipt = None;
+        m.deadline = None;
+        m.artifacts.record_all(&id, &Artifact::PROOF_OUTPUTS);
+        m.artifacts.record_all(&id, &[Artifact::Attestation, Artifact::SubmissionPayload]);
+        state.events.publish(m, EventKind::StatusChanged);
+        m.owner.clone()
+    };
+    state.deadlines.forget(&id);
+    state.queue.push(&owner, &id);
+    println!("Measurement {} queued for a retry from stage {}", id, from);
+
+    Ok(Json(RetryResponse {
+        url: format!("{}/status/{}", state.config.public_base_url, id),
+        measurement_id: id,
+        status: ProofStatus::Pending,
+        from,
+    }))
+}
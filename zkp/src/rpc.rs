@@ -0,0 +1,241 @@
+// zkVerify RPC endpoints with health tracking and failover.
+//
+// ZK_VERIFY_RPC_URLS lists the endpoints the verification client may connect
+// to, preferred first; without it the client uses the zkVerify testnet's own
+// endpoint. Every submission tries the preferred endpoint that is not
+// quarantined, and moves on to the next one when the client reports that it
+// could not connect. Other failures happen after connecting, where retrying
+// could submit the proof twice, so they are never retried elsewhere.
+//
+// Each endpoint has a circuit breaker: RPC_BREAKER_FAILURES connection
+// failures in a row quarantine it for RPC_BREAKER_COOLDOWN_SECS. The cooldown
+// doubles every time the same endpoint trips again, up to 16 times, so a
+// flapping endpoint is kept out longer than one that failed once; a
+// successful connection resets it. Once the cooldown is over the next
+// submission tries the endpoint again, and a single failure then quarantines
+// it anew. When every endpoint is quarantined the one due back soonest is
+// tried rather than stalling submissions. Cooldowns run on the monotonic clock,
+// so stepping the host clock neither lengthens nor ends a quarantine.
+//
+// Connection latency and the error rate over the last connections are kept
+// per endpoint and shown in /admin/stats. Endpoints are shown without
+// credentials or query strings, which may carry API keys.
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, sync::Mutex, time::Duration};
+use tokio::time::Instant;
+
+use crate::{clock::Clock, config::Config};
+
+// Label of the client's built-in endpoint, used when none are configured
+const DEFAULT_ENDPOINT: &str = "testnet";
+// Number of recent connections the error rate is computed over
+const RECENT_CONNECTIONS: usize = 20;
+// Largest multiple of the cooldown a repeatedly tripping endpoint gets
+const MAX_COOLDOWN_FACTOR: u32 = 16;
+// Weight of the newest latency in the moving average
+const LATENCY_WEIGHT: f64 = 0.2;
+
+// Record on a measurement of how its proof was submitted
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct SubmissionReceipt {
+    // Endpoint the verification client connected through, without credentials
+    pub endpoint: String,
+    // Endpoints tried, including the one used
+    pub attempts: u32,
+    pub submitted_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct EndpointHealth {
+    connections: u64,
+    failures: u64,
+    // Share of failures among the recent connections
+    error_rate: Option<f64>,
+    // Moving average of the time until the client was connected
+    latency_ms: Option<f64>,
+    consecutive_failures: u32,
+    // Times the breaker tripped in a row, which lengthens the cooldown
+    trips: u32,
+    quarantined_until: Option<DateTime<Utc>>,
+    // When the quarantine ends on the monotonic clock
+    #[serde(skip)]
+    quarantine_ends: Option<Instant>,
+    last_error: Option<String>,
+    #[serde(skip)]
+    recent: VecDeque<bool>,
+}
+
+// One endpoint's entry in the admin stats
+#[derive(Serialize)]
+pub struct EndpointStats {
+    endpoint: String,
+    quarantined: bool,
+    #[serde(flatten)]
+    health: EndpointHealth,
+}
+
+// RPC figures for the admin stats endpoint
+#[derive(Serialize)]
+pub struct RpcStats {
+    // Endpoint the next submission will try first
+    active: String,
+    endpoints: Vec<EndpointStats>,
+}
+
+struct Endpoint {
+    // None for the client's built-in endpoint
+    url: Option<String>,
+    label: String,
+    health: Mutex<EndpointHealth>,
+}
+
+pub struct Endpoints {
+    endpoints: Vec<Endpoint>,
+    failures_to_trip: u32,
+    cooldown: Duration,
+    clock: Clock,
+}
+
+// An endpoint to submit through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EndpointId(usize);
+
+impl Endpoints {
+    pub fn new(config: &Config) -> Self {
+        let endpoint = |url: Option<String>| Endpoint {
+            label: url.as_deref().map_or(DEFAULT_ENDPOINT.to_string(), redact),
+            url,
+            health: Mutex::default(),
+        };
+        let endpoints = if config.zkverify_rpc_urls.is_empty() {
+            vec![endpoint(None)]
+        } else {
+            config.zkverify_rpc_urls.iter().map(|url| endpoint(Some(url.clone()))).collect()
+        };
+        Endpoints {
+            endpoints,
+            failures_to_trip: config.rpc_breaker_failures.max(1),
+            cooldown: Duration::from_secs(config.rpc_breaker_cooldown_secs),
+            clock: Clock::new(config),
+        }
+    }
+
+    // Endpoints in the order a submission should try them: those not
+    // quarantined by preference, then quarantined ones by their return
+    pub fn order(&self) -> Vec<EndpointId> {
+        let now = Instant::now();
+        let mut available = Vec::new();
+        let mut quarantined = Vec::new();
+        for (index, endpoint) in self.endpoints.iter().enumerate() {
+            match endpoint.health.lock().unwrap().quarantine_ends {
+                Some(until) if until > now => quarantined.push((until, EndpointId(index))),
+                _ => available.push(EndpointId(index)),
+            }
+        }
+        quarantined.sort_by_key(|(until, _)| *until);
+        available.extend(quarantined.into_iter().map(|(_, id)| id));
+        available
+    }
+
+    // URL to hand the client, None for its built-in endpoint
+    pub fn url(&self, id: EndpointId) -> Option<&str> {
+        self.endpoints[id.0].url.as_deref()
+    }
+
+    pub fn label(&self, id: EndpointId) -> &str {
+        &self.endpoints[id.0].label
+    }
+
+    // Note a connection made in the given time, closing the breaker
+    pub fn connected(&self, id: EndpointId, latency: Duration) {
+        let mut health = self.endpoints[id.0].health.lock().unwrap();
+        health.record(true);
+        let latency_ms = latency.as_secs_f64() * 1000.0;
+        health.latency_ms = Some(match health.latency_ms {
+            Some(average) => average + LATENCY_WEIGHT * (latency_ms - average),
+            None => latency_ms,
+        });
+        health.consecutive_failures = 0;
+        health.trips = 0;
+        health.quarantined_until = None;
+        health.quarantine_ends = None;
+    }
+
+    // Note a failed connection, quarantining the endpoint when its breaker trips
+    pub fn unreachable(&self, id: EndpointId, error: &str) {
+        let endpoint = &self.endpoints[id.0];
+        let mut health = endpoint.health.lock().unwrap();
+        health.record(false);
+        health.last_error = Some(error.to_string());
+        health.consecutive_failures += 1;
+
+        // An endpoint on trial after its cooldown trips again on one failure
+        if health.consecutive_failures >= self.failures_to_trip || health.trips > 0 {
+            health.trips += 1;
+            health.consecutive_failures = 0;
+            let factor = 2u32.saturating_pow(health.trips - 1).min(MAX_COOLDOWN_FACTOR);
+            let cooldown = self.cooldown * factor;
+            health.quarantine_ends = Some(Instant::now() + cooldown);
+            health.quarantined_until =
+                Some(self.clock.now() + chrono::Duration::from_std(cooldown).unwrap_or_default());
+            println!(
+                "RPC endpoint {} quarantined for {} seconds after repeated failures",
+                endpoint.label,
+                cooldown.as_secs()
+            );
+        }
+    }
+
+    pub fn stats(&self) -> RpcStats {
+        let now = Instant::now();
+        let active = self.order().first().map(|id| self.label(*id).to_string()).unwrap_or_default();
+        let endpoints = self
+            .endpoints
+            .iter()
+            .map(|endpoint| {
+                let health = endpoint.health.lock().unwrap().clone();
+                EndpointStats {
+                    endpoint: endpoint.label.clone(),
+                    quarantined: health.quarantine_ends.is_some_and(|ends| ends > now),
+                    health,
+                }
+            })
+            .collect();
+        RpcStats { active, endpoints }
+    }
+}
+
+impl EndpointHealth {
+    fn record(&mut self, success: bool) {
+        self.connections += 1;
+        if !success {
+            self.failures += 1;
+        }
+        if self.recent.len() == RECENT_CONNECTIONS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(success);
+        let failed = self.recent.iter().filter(|success| !**success).count();
+        self.error_rate = Some(failed as f64 / self.recent.len() as f64);
+    }
+}
+
+// An endpoint URL without user credentials or query string
+pub fn redact(url: &str) -> String {
+    let url = url.split(['?', '#']).next().unwrap_or(url);
+    match url.split_once("://") {
+        Some((scheme, rest)) => {
+            let (authority, path) = rest.split_once('/').map_or((rest, ""), |(a, p)| (a, p));
+            let host = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+            if path.is_empty() {
+                format!("{}://{}", scheme, host)
+            } else {
+                format!("{}://{}/{}", scheme, host, path)
+            }
+        }
+        None => url.to_string(),
+    }
+}
@@ -0,0 +1,314 @@
+// Startup safety audit for production deployments.
+//
+// In production mode (--production or PRODUCTION=true) the server audits its
+// configuration before it starts listening and refuses to start while any
+// rule is violated, printing every violation with a hint on fixing it. A rule
+// can only be waived explicitly, with --allow-unsafe=rule,... or ALLOW_UNSAFE,
+// and every waiver is logged loudly at startup. GET /version reports the
+// verdict, so a deployment can be checked from outside.
+
+use serde::Serialize;
+use std::{fs, path::Path};
+
+use crate::{
+    attestation_backend::{self, BackendKind},
+    auth::{ApiKeys, Role},
+    circuit,
+    config::Config,
+};
+
+// Filesystems whose contents do not survive a reboot
+const VOLATILE_FILESYSTEMS: [&str; 2] = ["tmpfs", "ramfs"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rule {
+    Auth,
+    Cors,
+    BaseUrl,
+    Tmpfs,
+    LocalAttestations,
+    PrivateImageUrls,
+    PrivateWebhookUrls,
+}
+
+impl Rule {
+    const ALL: [Rule; 7] = [
+        Rule::Auth,
+        Rule::Cors,
+        Rule::BaseUrl,
+        Rule::Tmpfs,
+        Rule::LocalAttestations,
+        Rule::PrivateImageUrls,
+        Rule::PrivateWebhookUrls,
+    ];
+
+    // Name used in --allow-unsafe and in the verdict
+    fn name(self) -> &'static str {
+        match self {
+            Rule::Auth => "auth",
+            Rule::Cors => "cors",
+            Rule::BaseUrl => "base_url",
+            Rule::Tmpfs => "tmpfs",
+            Rule::LocalAttestations => "local_attestations",
+            Rule::PrivateImageUrls => "private_image_urls",
+            Rule::PrivateWebhookUrls => "private_webhook_urls",
+        }
+    }
+
+    fn hint(self) -> &'static str {
+        match self {
+            Rule::Auth => {
+                "set ADMIN_API_KEY, create keys for clients with POST /admin/keys and keep ANONYMOUS_ROLE at submitter or below"
+            }
+            Rule::Cors => "list the origins of the web frontends in CORS_ALLOWED_ORIGINS",
+            Rule::BaseUrl => "set PUBLIC_BASE_URL to the address clients reach the server at",
+            Rule::Tmpfs => "mount persistent storage at uploads/ and proofs/",
+            Rule::LocalAttestations => {
+                "set ATTESTATION_BACKEND and every backend in ATTESTATION_BACKEND_BY_CIRCUIT to zkverify"
+            }
+            Rule::PrivateImageUrls => "unset IMAGE_URL_ALLOW_PRIVATE",
+            Rule::PrivateWebhookUrls => "unset WEBHOOK_ALLOW_PRIVATE",
+        }
+    }
+}
+
+struct Violation {
+    rule: Rule,
+    problem: String,
+}
+
+// Outcome of the audit, as reported by GET /version
+#[derive(Debug, Serialize, Clone)]
+pub struct Verdict {
+    production: bool,
+    // Whether no rule is violated, waived or not
+    safe: bool,
+    violations: Vec<&'static str>,
+    // Violations let through by --allow-unsafe
+    waived: Vec<&'static str>,
+}
+
+// Apply --production, --allow-unsafe=rule,..., --read-only, --supervise and
+// --role on top of the environment
+pub fn apply_args(
+    config: &mut Config,
+    mut args: impl Iterator<Item = String>,
+) -> Result<(), String> {
+    while let Some(arg) = args.next() {
+        if arg == "--production" {
+            config.production = true;
+        } else if arg == "--read-only" {
+            config.read_only = true;
+        } else if arg == "--supervise" {
+            config.supervise = true;
+        } else if arg == "--role" {
+            let role = args.next().ok_or("--role needs a value, server or worker")?;
+            config.role = role.parse()?;
+        } else if let Some(rules) = arg.strip_prefix("--allow-unsafe=") {
+            config.allow_unsafe.extend(
+                rules.split(',').map(str::trim).filter(|r| !r.is_empty()).map(str::to_string),
+            );
+        } else {
+            return Err(format!(
+                "unknown argument {}, expected --production, --allow-unsafe=, --read-only, \
+                 --supervise or --role",
+                arg
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Audit the configuration; in production mode any violation that is not
+// waived is an error listing all of them
+pub fn enforce(config: &Config, api_keys: &ApiKeys) -> Result<Verdict, String> {
+    for name in &config.allow_unsafe {
+        if !Rule::ALL.iter().any(|rule| rule.name() == name) {
+            let known: Vec<&str> = Rule::ALL.iter().map(|rule| rule.name()).collect();
+            return Err(format!(
+                "unknown rule '{}' in --allow-unsafe, expected one of {}",
+                name,
+                known.join(", ")
+            ));
+        }
+    }
+
+    let violations = audit(config, api_keys);
+    let is_waived = |v: &&Violation| config.allow_unsafe.iter().any(|name| name == v.rule.name());
+    let verdict = Verdict {
+        production: config.production,
+        safe: violations.is_empty(),
+        violations: rule_names(violations.iter()),
+        waived: if config.production {
+            rule_names(violations.iter().filter(is_waived))
+        } else {
+            Vec::new()
+        },
+    };
+
+    if !config.production {
+        if !violations.is_empty() {
+            println!(
+                "Not in production mode; these settings would be refused with --production: {}",
+                verdict.violations.join(", ")
+            );
+        }
+        return Ok(verdict);
+    }
+
+    let (waived, refused): (Vec<&Violation>, Vec<&Violation>) =
+        violations.iter().partition(|v| is_waived(v));
+    for violation in &waived {
+        println!("!!! WARNING: UNSAFE PRODUCTION SETTING ALLOWED BY --allow-unsafe !!!");
+        println!("!!! {}: {}", violation.rule.name(), violation.problem);
+    }
+    if refused.is_empty() {
+        println!("Production safety audit passed");
+        return Ok(verdict);
+    }
+    for violation in &refused {
+        println!("Unsafe for production: {}: {}", violation.rule.name(), violation.problem);
+        println!("  To fix: {}", violation.rule.hint());
+    }
+    Err(format!(
+        "refusing to start in production mode with {} unsafe settings; fix them or waive them with --allow-unsafe={}",
+        refused.len(),
+        rule_names(refused.into_iter()).join(",")
+    ))
+}
+
+// A violated rule, for the doctor
+pub struct Finding {
+    pub rule: &'static str,
+    pub problem: String,
+    pub hint: &'static str,
+    // Whether --allow-unsafe lets it through
+    pub waived: bool,
+}
+
+// Audit the configuration without enforcing or logging anything
+pub fn findings(config: &Config, api_keys: &ApiKeys) -> Vec<Finding> {
+    audit(config, api_keys)
+        .into_iter()
+        .map(|violation| Finding {
+            rule: violation.rule.name(),
+            hint: violation.rule.hint(),
+            waived: config.allow_unsafe.iter().any(|name| name == violation.rule.name()),
+            problem: violation.problem,
+        })
+        .collect()
+}
+
+// Names of the rules broken by some violations, each once
+fn rule_names<'a>(violations: impl Iterator<Item = &'a Violation>) -> Vec<&'static str> {
+    let mut names: Vec<&'static str> = Vec::new();
+    for violation in violations {
+        if !names.contains(&violation.rule.name()) {
+            names.push(violation.rule.name());
+        }
+    }
+    names
+}
+
+fn audit(config: &Config, api_keys: &ApiKeys) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let mut violate = |rule, problem: String| violations.push(Violation { rule, problem });
+
+    if config.admin_api_key.is_none() && api_keys.is_empty() {
+        violate(
+            Rule::Auth,
+            "access control is off, no ADMIN_API_KEY is set and no API keys exist".to_string(),
+        );
+    } else if let Some(role) = config.anonymous_role.filter(|role| *role > Role::Submitter) {
+        violate(Rule::Auth, format!("requests without a key have the {} role", role));
+    }
+
+    if config.cors_allowed_origins.is_empty()
+        || config.cors_allowed_origins.iter().any(|o| o == "*")
+    {
+        violate(Rule::Cors, "CORS allows any origin".to_string());
+    }
+
+    if is_local(&config.public_base_url) {
+        violate(
+            Rule::BaseUrl,
+            format!("PUBLIC_BASE_URL {} only works on this machine", config.public_base_url),
+        );
+    }
+
+    for dir in ["uploads", "proofs"] {
+        if let Some(fstype) =
+            filesystem(dir).filter(|fs| VOLATILE_FILESYSTEMS.contains(&fs.as_str()))
+        {
+            violate(Rule::Tmpfs, format!("{}/ is on {}, so it is lost on reboot", dir, fstype));
+        }
+    }
+
+    let local: Vec<&str> = circuit::all()
+        .map(|circuit| circuit.version)
+        .filter(|version| {
+            let by_circuit = &config.attestation_backend_by_circuit;
+            attestation_backend::select(config.attestation_backend, by_circuit, version)
+                == BackendKind::Local
+        })
+        .collect();
+    if !local.is_empty() {
+        violate(
+            Rule::LocalAttestations,
+            format!("attestations of {} are fabricated by the local backend", local.join(", ")),
+        );
+    }
+
+    if config.image_url_allow_private {
+        violate(
+            Rule::PrivateImageUrls,
+            "IMAGE_URL_ALLOW_PRIVATE lets submissions make the server fetch internal addresses"
+                .to_string(),
+        );
+    }
+
+    if config.webhook_allow_private {
+        violate(
+            Rule::PrivateWebhookUrls,
+            "WEBHOOK_ALLOW_PRIVATE lets submissions make the server POST to internal addresses"
+                .to_string(),
+        );
+    }
+
+    violations
+}
+
+// Whether a URL points at the local machine
+fn is_local(url: &str) -> bool {
+    let authority = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = authority.split(['/', '?', '#']).next().unwrap_or("");
+    let authority = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    let host = match authority.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next().unwrap_or(""),
+        None => authority.split(':').next().unwrap_or(""),
+    };
+    let host = host.to_ascii_lowercase();
+    host == "localhost"
+        || host.ends_with(".localhost")
+        || host.starts_with("127.")
+        || host == "::1"
+        || host == "0.0.0.0"
+}
+
+// Type of the filesystem holding a directory, from the longest mount point
+// containing it in /proc/mounts; None where that is not available
+fn filesystem(dir: &str) -> Option<String> {
+    let path = fs::canonicalize(dir).ok()?;
+    let mounts = fs::read_to_string("/proc/mounts").ok()?;
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let mount_point = fields.nth(1)?.replace("\\040", " ");
+            let fstype = fields.next()?;
+            Some((mount_point, fstype.to_string()))
+        })
+        .filter(|(mount_point, _)| path.starts_with(Path::new(mount_point)))
+        .max_by_key(|(mount_point, _)| mount_point.len())
+        .map(|(_, fstype)| fstype)
+}
@@ -0,0 +1,119 @@
+// JSON Schemas of the documents other systems consume.
+//
+// Broker events, the measurement document of /status/{id}, attestation data
+// and the JSON error body are described by JSON Schemas derived from the Rust
+// types that produce them, so the schemas cannot drift from the payloads.
+// They are served at /schemas/{name}/{version}.json and every broker event
+// links to its schema.
+//
+// Each schema has its own version, bumped whenever a change to the types
+// could break a consumer. test_schemas.sh compares every served schema with
+// the copy committed under test_vectors/schemas and fails when one changed
+// without a new version.
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use chrono::{DateTime, Utc};
+use schemars::{JsonSchema, Schema, generate::SchemaSettings};
+use serde::Serialize;
+use std::{collections::BTreeMap, sync::Arc};
+
+use crate::{AppState, AttestationData, MeasurementStatus, broker};
+
+// Version of the measurement document of /status/{id}
+pub const MEASUREMENT_SCHEMA_VERSION: u32 = 20;
+pub const ATTESTATION_SCHEMA_VERSION: u32 = 1;
+pub const ERROR_SCHEMA_VERSION: u32 = 1;
+
+// JSON body of errors that clients are expected to handle by their code
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ErrorBody {
+    // Machine-readable code, such as "maintenance" or "read_only"
+    pub error: &'static str,
+    pub message: String,
+    // When the condition causing the error began
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since: Option<DateTime<Utc>>,
+}
+
+struct Published {
+    name: &'static str,
+    version: u32,
+    generate: fn() -> Schema,
+}
+
+const PUBLISHED: [Published; 4] = [
+    Published {
+        name: "event",
+        version: broker::EVENT_SCHEMA_VERSION,
+        generate: generate::<broker::BrokerEvent>,
+    },
+    Published {
+        name: "measurement",
+        version: MEASUREMENT_SCHEMA_VERSION,
+        generate: generate::<MeasurementStatus>,
+    },
+    Published {
+        name: "attestation",
+        version: ATTESTATION_SCHEMA_VERSION,
+        generate: generate::<AttestationData>,
+    },
+    Published { name: "error", version: ERROR_SCHEMA_VERSION, generate: generate::<ErrorBody> },
+];
+
+// Schema of a type as it is serialized
+fn generate<T: JsonSchema>() -> Schema {
+    SchemaSettings::draft2020_12().for_serialize().into_generator().into_root_schema_for::<T>()
+}
+
+// Address of the current version of a published schema
+pub fn url(base_url: &str, name: &str) -> String {
+    let version = PUBLISHED.iter().find(|p| p.name == name).map_or(0, |p| p.version);
+    format!("{}/schemas/{}/{}.json", base_url, name, version)
+}
+
+#[derive(Serialize)]
+pub struct SchemaIndexEntry {
+    version: u32,
+    url: String,
+}
+
+// Handler listing the published schemas with their current versions
+pub async fn list(
+    State(state): State<Arc<AppState>>,
+) -> Json<BTreeMap<&'static str, SchemaIndexEntry>> {
+    let base_url = &state.config.public_base_url;
+    Json(
+        PUBLISHED
+            .iter()
+            .map(|p| (p.name, SchemaIndexEntry { version: p.version, url: url(base_url, p.name) }))
+            .collect(),
+    )
+}
+
+// Handler serving one version of a schema
+pub async fn get(
+    Path((name, file)): Path<(String, String)>,
+) -> Result<Json<Schema>, (StatusCode, String)> {
+    let Some(published) = PUBLISHED.iter().find(|p| p.name == name) else {
+        let names: Vec<&str> = PUBLISHED.iter().map(|p| p.name).collect();
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("Unknown schema {}, available are {}", name, names.join(", ")),
+        ));
+    };
+    let version = file.strip_suffix(".json").and_then(|v| v.parse::<u32>().ok());
+    if version != Some(published.version) {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!(
+                "Schema {} is not available as {}, the current version is {}.json",
+                name, file, published.version
+            ),
+        ));
+    }
+    Ok(Json((published.generate)()))
+}
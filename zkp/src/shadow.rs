@@ -0,0 +1,411 @@
+// Shadow proving: a second prover checked against snarkjs on sampled proofs.
+//
+// Before proving moves off snarkjs, the replacement has to be shown to agree
+// with it on real measurements. With SHADOW_PROVING on, SHADOW_SAMPLE_PERCENT
+// percent of the proofs snarkjs generates are proved again by SHADOW_PROVER, a
+// command taking rapidsnark's arguments: proving key, witness, proof and
+// public signals paths. It proves from a copy of the witness snarkjs used and
+// writes into proofs/{id}/shadow/. Both proofs are then verified in-process
+// against the circuit's verification key (see groth16.rs), their public
+// signals compared and their proving times recorded.
+//
+// Shadow proofs never affect a measurement. They are queued once its proof is
+// done and only run while no measurement waits for a proof worker, at the
+// lowest CPU priority; at most MAX_PENDING wait, and samples beyond that are
+// dropped. Their outcome is written to proofs/{id}/shadow/comparison.json and
+// kept for the report at /admin/shadow, which flags mismatches.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::Notify;
+
+use backend::groth16::{self, Groth16Proof, VerificationKey};
+
+use crate::{
+    AppState,
+    artifacts::{self, Artifact},
+    auth::{Authorized, Operator},
+    circuit::Circuit,
+    config::Config,
+    pipeline::{self, Stage},
+};
+
+// Shadow proofs waiting for spare capacity at most
+const MAX_PENDING: usize = 100;
+// Comparisons kept for the report
+const RECENT_COMPARISONS: usize = 200;
+// How often a waiting shadow proof checks whether the workers have spare capacity
+const IDLE_POLL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    // Both proofs verify and prove the same public signals
+    Match,
+    Mismatch,
+    // The shadow prover or a verification could not run to completion
+    Failed,
+}
+
+// Result of proving one measurement with both provers
+#[derive(Debug, Clone, Serialize)]
+pub struct Comparison {
+    id: String,
+    #[serde(skip)]
+    tenant: Option<String>,
+    circuit_version: &'static str,
+    outcome: Outcome,
+    public_signals_equal: Option<bool>,
+    primary_verified: Option<bool>,
+    shadow_verified: Option<bool>,
+    primary_ms: u64,
+    shadow_ms: Option<u64>,
+    // Shadow minus primary proving time, negative when the shadow prover was faster
+    delta_ms: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    compared_at: DateTime<Utc>,
+}
+
+// Shadow proving figures for the admin stats endpoint
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct ShadowStats {
+    enabled: bool,
+    prover: String,
+    sample_percent: u32,
+    pending: usize,
+    sampled: u64,
+    // Samples dropped because too many shadow proofs were waiting
+    dropped: u64,
+    matched: u64,
+    mismatched: u64,
+    failed: u64,
+    mean_primary_ms: Option<f64>,
+    mean_shadow_ms: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct ShadowReport {
+    // Totals mix every tenant's proofs, so they are only shown to global callers
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    stats: Option<ShadowStats>,
+    // Newest first
+    comparisons: Vec<Comparison>,
+}
+
+#[derive(Deserialize)]
+pub struct ReportQuery {
+    // Only list mismatches and failures
+    #[serde(default)]
+    mismatches: bool,
+}
+
+struct Job {
+    id: String,
+    tenant: Option<String>,
+    circuit: Circuit,
+    primary: Duration,
+}
+
+#[derive(Default)]
+struct Totals {
+    sampled: u64,
+    dropped: u64,
+    matched: u64,
+    mismatched: u64,
+    failed: u64,
+    // Proving times of the comparisons both provers finished
+    timed: u64,
+    primary_ms: u64,
+    shadow_ms: u64,
+}
+
+pub struct Shadow {
+    enabled: bool,
+    prover: String,
+    sample_percent: u32,
+    pending: Mutex<VecDeque<Job>>,
+    notify: Notify,
+    totals: Mutex<Totals>,
+    recent: Mutex<VecDeque<Comparison>>,
+}
+
+impl Shadow {
+    pub fn new(config: &Config) -> Self {
+        Shadow {
+            enabled: config.shadow_proving,
+            prover: config.shadow_prover.clone(),
+            sample_percent: config.shadow_sample_percent,
+            pending: Mutex::default(),
+            notify: Notify::new(),
+            totals: Mutex::default(),
+            recent: Mutex::default(),
+        }
+    }
+
+    // Whether a measurement falls in the sample; the same ID always does
+    fn sampled(&self, id: &str) -> bool {
+        let mut hasher = DefaultHasher::new();
+        id.hash(&mut hasher);
+        self.enabled && hasher.finish() % 100 < self.sample_percent as u64
+    }
+
+    // Queue a shadow proof of a measurement snarkjs proved in the given time,
+    // if it is sampled. The witness is copied now, before a retry can replace it.
+    pub fn sample(&self, id: &str, tenant: Option<String>, circuit: Circuit, primary: Duration) {
+        if !self.sampled(id) {
+            return;
+        }
+        let mut totals = self.totals.lock().unwrap();
+        totals.sampled += 1;
+        let mut pending = self.pending.lock().unwrap();
+        if pending.len() >= MAX_PENDING {
+            totals.dropped += 1;
+            return;
+        }
+
+        let dir = shadow_dir(id);
+        let copied = fs::create_dir_all(&dir)
+            .and_then(|()| fs::copy(witness_path(&artifacts::proof_dir(id)), witness_path(&dir)));
+        if let Err(e) = copied {
+            println!("Skipping shadow proof of {}: cannot copy its witness: {}", id, e);
+            totals.failed += 1;
+            return;
+        }
+        pending.push_back(Job { id: id.to_string(), tenant, circuit, primary });
+        drop(pending);
+        self.notify.notify_one();
+    }
+
+    pub fn stats(&self) -> ShadowStats {
+        let totals = self.totals.lock().unwrap();
+        let mean = |total: u64| (totals.timed > 0).then(|| total as f64 / totals.timed as f64);
+        ShadowStats {
+            enabled: self.enabled,
+            prover: self.prover.clone(),
+            sample_percent: self.sample_percent,
+            pending: self.pending.lock().unwrap().len(),
+            sampled: totals.sampled,
+            dropped: totals.dropped,
+            matched: totals.matched,
+            mismatched: totals.mismatched,
+            failed: totals.failed,
+            mean_primary_ms: mean(totals.primary_ms),
+            mean_shadow_ms: mean(totals.shadow_ms),
+        }
+    }
+
+    fn record(&self, comparison: Comparison) {
+        {
+            let mut totals = self.totals.lock().unwrap();
+            match comparison.outcome {
+                Outcome::Match => totals.matched += 1,
+                Outcome::Mismatch => totals.mismatched += 1,
+                Outcome::Failed => totals.failed += 1,
+            }
+            if let Some(shadow_ms) = comparison.shadow_ms {
+                totals.timed += 1;
+                totals.primary_ms += comparison.primary_ms;
+                totals.shadow_ms += shadow_ms;
+            }
+        }
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == RECENT_COMPARISONS {
+            recent.pop_back();
+        }
+        recent.push_front(comparison);
+    }
+
+    async fn next(&self) -> Job {
+        loop {
+            if let Some(job) = self.pending.lock().unwrap().pop_front() {
+                return job;
+            }
+            self.notify.notified().await;
+        }
+    }
+}
+
+fn shadow_dir(id: &str) -> String {
+    format!("{}/shadow", artifacts::proof_dir(id))
+}
+
+fn witness_path(dir: &str) -> String {
+    format!("{}/witness.wtns", dir)
+}
+
+// Worker running the queued shadow proofs one at a time on spare capacity
+pub async fn worker(state: Arc<AppState>) {
+    let shadow = &state.shadow;
+    if !shadow.enabled {
+        return;
+    }
+    println!("Shadow proving {}% of proofs with {}", shadow.sample_percent, shadow.prover);
+    loop {
+        let job = shadow.next().await;
+        // Measurements always go first
+        loop {
+            state.maintenance.wait_until_off().await;
+            if state.queue.is_idle() {
+                break;
+            }
+            tokio::time::sleep(IDLE_POLL).await;
+        }
+
+        let comparison = compare(&state, &job).await;
+        match comparison.outcome {
+            Outcome::Match => println!("Shadow proof of {} matches", job.id),
+            Outcome::Mismatch => println!("Shadow proof of {} does NOT match snarkjs", job.id),
+            Outcome::Failed => println!(
+                "Shadow proof of {} failed: {}",
+                job.id,
+                comparison.error.as_deref().unwrap_or("unknown error")
+            ),
+        }
+        write_comparison(&comparison);
+        shadow.record(comparison);
+    }
+}
+
+// Prove a measurement with the shadow prover and compare the result
+async fn compare(state: &AppState, job: &Job) -> Comparison {
+    let dir = shadow_dir(&job.id);
+    let log_path = format!("{}/shadow.log", dir);
+    let proof_path = format!("{}/proof.json", dir);
+    let public_path = format!("{}/public.json", dir);
+    let timeout = Duration::from_secs(state.config.proof_step_timeout_secs);
+    let primary_ms = job.primary.as_millis() as u64;
+    let mut comparison = Comparison {
+        id: job.id.clone(),
+        tenant: job.tenant.clone(),
+        circuit_version: job.circuit.version,
+        outcome: Outcome::Failed,
+        public_signals_equal: None,
+        primary_verified: None,
+        shadow_verified: None,
+        primary_ms,
+        shadow_ms: None,
+        delta_ms: None,
+        error: None,
+        compared_at: Utc::now(),
+    };
+
+    let started = Instant::now();
+    let proved = pipeline::run_step(
+        Stage::Prove,
+        "nice",
+        &[
+            "-n",
+            "19",
+            &state.shadow.prover,
+            job.circuit.zkey_path,
+            &witness_path(&dir),
+            &proof_path,
+            &public_path,
+        ],
+        timeout,
+        &log_path,
+    )
+    .await;
+    if let Err(e) = proved {
+        comparison.error = Some(format!("shadow prover: {}", e));
+        return comparison;
+    }
+    let shadow_ms = started.elapsed().as_millis() as u64;
+    comparison.shadow_ms = Some(shadow_ms);
+    comparison.delta_ms = Some(shadow_ms as i64 - primary_ms as i64);
+
+    let primary_public = Artifact::PublicSignals.path(&job.id);
+    let checks = async {
+        let primary_verified =
+            verify(job.circuit, &primary_public, &Artifact::Proof.path(&job.id)).await?;
+        let shadow_verified = verify(job.circuit, &public_path, &proof_path).await?;
+        let equal = public_signals(&primary_public)? == public_signals(&public_path)?;
+        Ok::<_, String>((primary_verified, shadow_verified, equal))
+    };
+    match checks.await {
+        Ok((primary_verified, shadow_verified, equal)) => {
+            comparison.primary_verified = Some(primary_verified);
+            comparison.shadow_verified = Some(shadow_verified);
+            comparison.public_signals_equal = Some(equal);
+            comparison.outcome = if primary_verified && shadow_verified && equal {
+                Outcome::Match
+            } else {
+                Outcome::Mismatch
+            };
+        }
+        Err(e) => comparison.error = Some(e),
+    }
+    comparison
+}
+
+// Whether a proof verifies against the circuit's key; Err when it could not tell
+async fn verify(circuit: Circuit, public_path: &str, proof_path: &str) -> Result<bool, String> {
+    let (public_path, proof_path) = (public_path.to_string(), proof_path.to_string());
+    tokio::task::spawn_blocking(move || {
+        let read = |path: &str| {
+            fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path, e))
+        };
+        let vkey = VerificationKey::from_snarkjs(&read(circuit.vkey_path)?)
+            .map_err(|e| format!("{}: {}", circuit.vkey_path, e))?;
+        let public = groth16::parse_public_signals(&read(&public_path)?)
+            .map_err(|e| format!("{}: {}", public_path, e))?;
+        let proof = Groth16Proof::from_snarkjs(&read(&proof_path)?)
+            .map_err(|e| format!("{}: {}", proof_path, e))?;
+        groth16::verify_groth16(&vkey, &proof, &public).map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+fn public_signals(path: &str) -> Result<Vec<String>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+    serde_json::from_str(&content).map_err(|e| format!("Cannot parse {}: {}", path, e))
+}
+
+fn write_comparison(comparison: &Comparison) {
+    let path = format!("{}/comparison.json", shadow_dir(&comparison.id));
+    let tmp_path = format!("{}.tmp", path);
+    let written = serde_json::to_vec_pretty(comparison)
+        .map_err(|e| e.to_string())
+        .and_then(|json| fs::write(&tmp_path, json).map_err(|e| e.to_string()))
+        .and_then(|()| fs::rename(&tmp_path, &path).map_err(|e| e.to_string()));
+    if let Err(e) = written {
+        println!("Failed to write {}: {}", path, e);
+    }
+}
+
+// Handler listing recent shadow comparisons, newest first
+pub async fn report(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Operator>,
+    Query(query): Query<ReportQuery>,
+) -> Result<Json<ShadowReport>, (StatusCode, String)> {
+    if !state.shadow.enabled {
+        return Err((StatusCode::NOT_FOUND, "Shadow proving is off".to_string()));
+    }
+    let comparisons = state
+        .shadow
+        .recent
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|c| caller.can_access(c.tenant.as_deref()))
+        .filter(|c| !query.mismatches || c.outcome != Outcome::Match)
+        .cloned()
+        .collect();
+    let stats = caller.tenant.is_none().then(|| state.shadow.stats());
+    Ok(Json(ShadowReport { stats, comparisons }))
+}
@@ -0,0 +1,293 @@
+// Signing accounts submitting proofs to zkVerify.
+//
+// dist/verify_client.js signs with the seed phrase it is given and asks the
+// chain for the account's next nonce when it connects, so two submissions
+// from one account running side by side can be handed the same nonce and one
+// of them rejected. Submissions therefore take turns per account: a turn lasts
+// until the client reports its transaction included in a block, which uses up
+// the nonce, or until the client exits. A transaction still not included after
+// SIGNER_STUCK_SECS is reported as stuck and the turn passes on; the next
+// client is then handed the following nonce, as the stuck transaction is still
+// pending. Replacing a stuck transaction with a higher fee is left to the
+// operator, since the client cannot resubmit one.
+//
+// The default account's seed phrase is ZK_VERIFY_SEED_PHRASE or the contents
+// of ZK_VERIFY_SEED_FILE; tenants may sign with an account of their own. The
+// RPC endpoint of each run is chosen by rpc.rs.
+
+use axum::{extract::State, response::Json};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    process::Stdio,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::{
+    AppState,
+    artifacts::{self, Artifact},
+    auth::{Authorized, Operator},
+    circuit, pipeline,
+    rpc::{EndpointId, SubmissionReceipt},
+    tenants,
+};
+
+const DEFAULT_ACCOUNT: &str = "default";
+// Line the client prints once connected to the RPC endpoint
+const CONNECTED_MARKER: &str = "Connected to zkVerify network";
+// Line the client prints once its transaction is in a block
+const INCLUDED_MARKER: &str = "Transaction included in block";
+// Exit status of the client when it could not connect to the RPC endpoint
+const EXIT_UNREACHABLE: i32 = 3;
+
+#[derive(Debug, Serialize, Clone)]
+struct Submission {
+    measurement_id: String,
+    since: DateTime<Utc>,
+}
+
+// Submission activity of one account
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct AccountState {
+    // Submission currently holding the account's turn
+    current: Option<Submission>,
+    // Submissions waiting for their turn
+    waiting: usize,
+    // Submissions whose transaction was not included in time, until their client exits
+    stuck: Vec<Submission>,
+    submitted: u64,
+    failed: u64,
+    last_error: Option<String>,
+}
+
+struct Account {
+    turn: tokio::sync::Mutex<()>,
+    state: Mutex<AccountState>,
+}
+
+pub struct Signers {
+    stuck_after: Duration,
+    accounts: Mutex<BTreeMap<String, Arc<Account>>>,
+}
+
+impl Signers {
+    pub fn new(stuck_after: Duration) -> Self {
+        Signers { stuck_after, accounts: Mutex::default() }
+    }
+
+    fn account(&self, label: &str) -> Arc<Account> {
+        let mut accounts = self.accounts.lock().unwrap();
+        accounts
+            .entry(label.to_string())
+            .or_insert_with(|| {
+                Arc::new(Account { turn: tokio::sync::Mutex::new(()), state: Mutex::default() })
+            })
+            .clone()
+    }
+}
+
+// Account label and seed phrase to sign a tenant's submissions with; without a
+// seed the client falls back to the inherited ZK_VERIFY_SEED_PHRASE
+fn signing_key(tenant: Option<&str>) -> Result<(String, Option<String>), String> {
+    if let Some((tenant, seed)) = tenant.and_then(|t| tenants::seed_phrase(t).map(|s| (t, s))) {
+        return Ok((tenant.to_string(), Some(seed)));
+    }
+    match env::var("ZK_VERIFY_SEED_FILE") {
+        Ok(path) if !path.trim().is_empty() => {
+            let seed = fs::read_to_string(path.trim())
+                .map_err(|e| format!("Failed to read ZK_VERIFY_SEED_FILE {}: {}", path, e))?;
+            Ok((DEFAULT_ACCOUNT.to_string(), Some(seed.trim().to_string())))
+        }
+        _ => Ok((DEFAULT_ACCOUNT.to_string(), None)),
+    }
+}
+
+// Submit a measurement's proof with the verification client, waiting for the
+// signing account's turn first. Endpoints the client cannot connect to are
+// failed over to the next one.
+pub async fn submit(state: &AppState, id: &str, tenant: Option<&str>) -> Result<(), String> {
+    let (label, seed) = signing_key(tenant)?;
+    let account = state.signers.account(&label);
+
+    let mut result = Err("No zkVerify RPC endpoint to submit to".to_string());
+    for (tried, endpoint) in state.rpc.order().into_iter().enumerate() {
+        account.state.lock().unwrap().waiting += 1;
+        let turn = account.turn.lock().await;
+        {
+            let mut account_state = account.state.lock().unwrap();
+            account_state.waiting -= 1;
+            account_state.current =
+                Some(Submission { measurement_id: id.to_string(), since: Utc::now() });
+        }
+
+        let outcome = run_client(state, &account, id, seed.clone(), endpoint, turn).await;
+        let endpoint_label = state.rpc.label(endpoint).to_string();
+        match outcome {
+            Err(ClientError::Unreachable(e)) => {
+                println!("zkVerify RPC endpoint {} is unreachable: {}", endpoint_label, e);
+                state.rpc.unreachable(endpoint, &e);
+                result = Err(e);
+                continue;
+            }
+            Ok(latency) => {
+                state.rpc.connected(endpoint, latency);
+                result = Ok(());
+            }
+            Err(ClientError::Failed { connected, error }) => {
+                if let Some(latency) = connected {
+                    state.rpc.connected(endpoint, latency);
+                }
+                result = Err(error);
+            }
+        }
+        let receipt = SubmissionReceipt {
+            endpoint: endpoint_label,
+            attempts: tried as u32 + 1,
+            submitted_at: Utc::now(),
+        };
+        if let Some(m) = state.measurements.lock().get_mut(id) {
+            m.receipt = Some(receipt);
+        }
+        break;
+    }
+
+    let mut account_state = account.state.lock().unwrap();
+    account_state.stuck.retain(|s| s.measurement_id != id);
+    if account_state.current.as_ref().is_some_and(|c| c.measurement_id == id) {
+        account_state.current = None;
+    }
+    match &result {
+        Ok(()) => account_state.submitted += 1,
+        Err(e) => {
+            account_state.failed += 1;
+            account_state.last_error = Some(format!("{}: {}", id, e));
+        }
+    }
+    result
+}
+
+// Why a run of the client did not succeed
+enum ClientError {
+    // The client could not connect to the endpoint, so nothing was submitted
+    Unreachable(String),
+    // The client failed, after connecting in the given time if it did
+    Failed { connected: Option<Duration>, error: String },
+}
+
+// Run the client against an endpoint, giving up the account's turn once its
+// transaction is in a block or is considered stuck. Returns the time it took
+// the client to connect.
+async fn run_client(
+    state: &AppState,
+    account: &Account,
+    id: &str,
+    seed: Option<String>,
+    endpoint: EndpointId,
+    turn: tokio::sync::MutexGuard<'_, ()>,
+) -> Result<Duration, ClientError> {
+    let failed = |error| ClientError::Failed { connected: None, error };
+    let mut command = tokio::process::Command::new("node");
+    command
+        .args(["dist/verify_client.js", &artifacts::storage_key(id)])
+        .current_dir(".") // Run from the current directory
+        .stdout(Stdio::piped())
+        // A cancelled measurement's submission stops with the client
+        .kill_on_drop(true);
+    if let Some(seed) = seed {
+        command.env("ZK_VERIFY_SEED_PHRASE", seed);
+    }
+    if let Some(url) = state.rpc.url(endpoint) {
+        command.env("ZK_VERIFY_RPC_URL", url);
+    }
+    // Proofs are verified with the key of the circuit they were made with
+    let circuit = state.measurements.lock().get(id).map(|m| m.circuit.clone());
+    command.env("ZK_VERIFY_VKEY_PATH", circuit::resolve(circuit.flatten().as_deref()).vkey_path);
+    // The attestation poller waits for the attestation instead of the client
+    if state.config.attestation_polling {
+        command.env("ZK_VERIFY_DEFER_ATTESTATION", "1");
+    }
+    let started = Instant::now();
+    let mut child =
+        command.spawn().map_err(|e| failed(format!("Failed to run node client: {}", e)))?;
+
+    // Echo the client's output to the server and measurement logs while
+    // watching for the connection and the inclusion
+    let log_path = Artifact::Log.path(id);
+    pipeline::append_log(
+        &log_path,
+        &format!(
+            "[{}] zkVerify submission via {}\n",
+            Utc::now().to_rfc3339(),
+            state.rpc.label(endpoint)
+        ),
+    );
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let mut lines = BufReader::new(stdout).lines();
+    let mut turn = Some(turn);
+    let mut connected = None;
+    let stuck_at = tokio::time::Instant::now() + state.signers.stuck_after;
+    loop {
+        let line = tokio::select! {
+            line = lines.next_line() => line,
+            _ = tokio::time::sleep_until(stuck_at), if turn.is_some() => {
+                println!("Submission of proof {} is stuck, letting the next one go ahead", id);
+                let mut account_state = account.state.lock().unwrap();
+                if let Some(current) = account_state.current.take() {
+                    account_state.stuck.push(current);
+                }
+                turn = None;
+                continue;
+            }
+        };
+        match line {
+            Ok(Some(line)) => {
+                println!("{}", line);
+                pipeline::append_log(&log_path, &format!("{}\n", line));
+                if connected.is_none() && line.contains(CONNECTED_MARKER) {
+                    connected = Some(started.elapsed());
+                }
+                if line.contains(INCLUDED_MARKER) && turn.take().is_some() {
+                    account.state.lock().unwrap().current = None;
+                }
+            }
+            Ok(None) | Err(_) => break,
+        }
+    }
+    drop(turn);
+
+    let status = child.wait().await.map_err(|e| ClientError::Failed {
+        connected,
+        error: format!("Node client failed: {}", e),
+    })?;
+    match (status.success(), connected) {
+        (true, Some(latency)) => Ok(latency),
+        (true, None) => Ok(started.elapsed()),
+        (false, _) if status.code() == Some(EXIT_UNREACHABLE) => {
+            Err(ClientError::Unreachable(format!("Node client exited with {}", status)))
+        }
+        (false, connected) => Err(ClientError::Failed {
+            connected,
+            error: format!("Node client exited with {}", status),
+        }),
+    }
+}
+
+// Handler reporting the submission activity of every signing account; tenant
+// callers only see their own account
+pub async fn list(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Operator>,
+) -> Json<BTreeMap<String, AccountState>> {
+    let accounts = state.signers.accounts.lock().unwrap();
+    Json(
+        accounts
+            .iter()
+            .filter(|(label, _)| caller.tenant.is_none() || caller.tenant.as_ref() == Some(label))
+            .map(|(label, account)| (label.clone(), account.state.lock().unwrap().clone()))
+            .collect(),
+    )
+}
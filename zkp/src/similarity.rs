@@ -0,0 +1,232 @@
+// Perceptual-hash duplicate detection across measurements.
+//
+// Re-encoding or slightly cropping a photo changes every byte of it, so copies
+// are recognised by a difference hash of the image instead: the image is
+// shrunk to 9x8 grey pixels and each bit records whether a pixel is brighter
+// than its right neighbour. Similar images differ in few bits, and the
+// Hamming distance between two hashes measures how alike they look.
+//
+// Every submission is hashed at ingest and compared with the measurements of
+// other owners in the same tenant. One within DUPLICATE_MAX_DISTANCE is either
+// flagged for review under flagged_duplicate or, with DUPLICATE_MODE=reject,
+// refused. Measurements of the same owner never count, as re-shooting a scene
+// is normal, and reproofs share their original's image by design.
+
+use axum::{extract::State, http::StatusCode, response::Json};
+use chrono::{DateTime, Utc};
+use image::imageops::FilterType;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, str::FromStr, sync::Arc};
+
+use crate::{
+    AppState, Measurement, ProofStatus,
+    auth::{Authorized, Operator},
+};
+
+// Hash width and height; one more column is sampled for the comparisons
+const HASH_SIDE: u32 = 8;
+
+// What happens to submissions similar to another owner's measurement
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateMode {
+    Off,
+    Flag,
+    Reject,
+}
+
+impl FromStr for DuplicateMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(DuplicateMode::Off),
+            "flag" => Ok(DuplicateMode::Flag),
+            "reject" => Ok(DuplicateMode::Reject),
+            other => Err(format!("unknown mode '{}', expected off, flag or reject", other)),
+        }
+    }
+}
+
+// Why a measurement was flagged for review
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct DuplicateFlag {
+    // Closest earlier measurement of another owner
+    pub similar_to: String,
+    // Bits in which the two perceptual hashes differ
+    pub distance: u32,
+}
+
+// Difference hash of an image file, as 16 hex digits
+pub async fn hash_image(path: &str) -> Result<String, String> {
+    let path = path.to_string();
+    tokio::task::spawn_blocking(move || perceptual_hash(&path)).await.map_err(|e| e.to_string())?
+}
+
+// Difference hash of an image file, blocking while the image is decoded
+pub fn perceptual_hash(path: &str) -> Result<String, String> {
+    let image = image::open(path).map_err(|e| format!("Failed to decode image: {}", e))?;
+    let pixels = image.resize_exact(HASH_SIDE + 1, HASH_SIDE, FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..HASH_SIDE {
+        for x in 0..HASH_SIDE {
+            let brighter = pixels.get_pixel(x, y)[0] > pixels.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | brighter as u64;
+        }
+    }
+    Ok(format!("{:016x}", hash))
+}
+
+// Number of bits in which two hashes differ, None if either is malformed
+pub fn distance(a: &str, b: &str) -> Option<u32> {
+    let a = u64::from_str_radix(a, 16).ok()?;
+    let b = u64::from_str_radix(b, 16).ok()?;
+    Some((a ^ b).count_ones())
+}
+
+// Whether a measurement takes part in duplicate detection
+fn comparable(m: &Measurement) -> bool {
+    m.reprove_of.is_none() && m.perceptual_hash.is_some()
+}
+
+// Closest measurement of another owner in the same tenant whose image is
+// within max_distance of the hash
+pub fn closest_match(
+    measurements: &HashMap<String, Measurement>,
+    hash: &str,
+    owner: &str,
+    tenant: Option<&str>,
+    max_distance: u32,
+) -> Option<DuplicateFlag> {
+    measurements
+        .values()
+        .filter(|m| comparable(m) && m.owner != owner && m.tenant.as_deref() == tenant)
+        .filter_map(|m| {
+            let distance = distance(hash, m.perceptual_hash.as_deref()?)?;
+            (distance <= max_distance).then(|| DuplicateFlag { similar_to: m.id.clone(), distance })
+        })
+        .min_by(|a, b| a.distance.cmp(&b.distance).then_with(|| a.similar_to.cmp(&b.similar_to)))
+}
+
+#[derive(Serialize)]
+pub struct DuplicateClusters {
+    mode: DuplicateMode,
+    max_distance: u32,
+    clusters: Vec<Cluster>,
+}
+
+#[derive(Serialize)]
+struct Cluster {
+    // Oldest first
+    measurements: Vec<ClusterMember>,
+}
+
+#[derive(Serialize)]
+struct ClusterMember {
+    id: String,
+    owner: String,
+    status: ProofStatus,
+    perceptual_hash: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    flagged_duplicate: Option<DuplicateFlag>,
+    created_at: Option<DateTime<Utc>>,
+    status_url: String,
+    view_url: String,
+    image_url: String,
+}
+
+// Handler listing groups of similar images submitted by more than one owner
+pub async fn list_duplicates(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Operator>,
+) -> Result<Json<DuplicateClusters>, (StatusCode, String)> {
+    let config = &state.config;
+    if config.duplicate_mode == DuplicateMode::Off {
+        return Err((StatusCode::NOT_FOUND, "Duplicate detection is off".to_string()));
+    }
+
+    let mut members: Vec<Measurement> = state
+        .measurements
+        .lock()
+        .values()
+        .filter(|m| comparable(m) && caller.can_access(m.tenant.as_deref()))
+        .cloned()
+        .collect();
+    members.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.id.cmp(&b.id)));
+
+    // Every pair is compared, so clusters are found off the async runtime
+    let max_distance = config.duplicate_max_distance;
+    let groups = tokio::task::spawn_blocking(move || cluster(members, max_distance))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let base_url = &config.public_base_url;
+    let clusters = groups
+        .into_iter()
+        .map(|group| Cluster {
+            measurements: group
+                .into_iter()
+                .map(|m| ClusterMember {
+                    status_url: format!("{}/status/{}", base_url, m.id),
+                    view_url: format!("{}/view/{}", base_url, m.id),
+                    image_url: format!("{}/img/{}", base_url, m.id),
+                    perceptual_hash: m.perceptual_hash.unwrap_or_default(),
+                    id: m.id,
+                    owner: m.owner,
+                    status: m.status,
+                    flagged_duplicate: m.flagged_duplicate,
+                    created_at: m.created_at,
+                })
+                .collect(),
+        })
+        .collect();
+
+    Ok(Json(DuplicateClusters { mode: config.duplicate_mode, max_distance, clusters }))
+}
+
+// Group measurements linked by chains of similar images within a tenant,
+// keeping the groups that span more than one owner
+fn cluster(members: Vec<Measurement>, max_distance: u32) -> Vec<Vec<Measurement>> {
+    let mut parent: Vec<usize> = (0..members.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+
+    for i in 0..members.len() {
+        for j in i + 1..members.len() {
+            let (a, b) = (&members[i], &members[j]);
+            if a.tenant != b.tenant {
+                continue;
+            }
+            let similar = a
+                .perceptual_hash
+                .as_deref()
+                .zip(b.perceptual_hash.as_deref())
+                .and_then(|(x, y)| distance(x, y))
+                .is_some_and(|d| d <= max_distance);
+            if similar {
+                let (ri, rj) = (root(&mut parent, i), root(&mut parent, j));
+                parent[rj.max(ri)] = ri.min(rj);
+            }
+        }
+    }
+
+    // Members are sorted oldest first, and so are the groups by their oldest
+    let mut groups: Vec<Vec<Measurement>> = Vec::new();
+    let mut group_of: HashMap<usize, usize> = HashMap::new();
+    for (i, m) in members.into_iter().enumerate() {
+        let r = root(&mut parent, i);
+        let index = *group_of.entry(r).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[index].push(m);
+    }
+    groups.retain(|group| group.iter().any(|m| m.owner != group[0].owner));
+    groups
+}
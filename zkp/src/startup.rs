@@ -0,0 +1,127 @@
+// Timings of the server's startup.
+//
+// Startup is kept bounded by the work left unfinished rather than by the
+// number of measurements: the stored measurements are loaded, only those not
+// yet finished are recovered, with their files checked RECOVERY_CONCURRENCY
+// at a time, and the server starts serving. The scan of
+// uploads/ and proofs/ for measurements missing from the store, which reads
+// every directory, runs afterwards as a background job (see reconcile.rs).
+//
+// Each phase is logged with how long it took as it ends, and GET /version
+// reports them under startup together with the state of the consistency scan,
+// so a slow start can be told apart from a slow scan.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{
+    sync::Mutex,
+    time::{Instant, SystemTime},
+};
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanState {
+    // Waiting for the server to be ready
+    Waiting,
+    Running,
+    Completed,
+    // Read-only mirrors leave their files to the primary
+    Skipped,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Phase {
+    name: &'static str,
+    millis: u64,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ConsistencyScan {
+    state: ScanState,
+    // Job reporting the scan's progress under /admin/jobs/{id}, once it started
+    #[serde(skip_serializing_if = "Option::is_none")]
+    job_id: Option<String>,
+    // Measurements rebuilt from their files
+    rebuilt: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    millis: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct StartupReport {
+    started_at: DateTime<Utc>,
+    // Phases in the order they ran, with how long each took
+    phases: Vec<Phase>,
+    // Milliseconds from the process start until requests were served
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ready_after_ms: Option<u64>,
+    consistency_scan: ConsistencyScan,
+}
+
+pub struct Startup {
+    started: Instant,
+    // Files written before this belong to measurements from before the start
+    pub started_at: SystemTime,
+    report: Mutex<StartupReport>,
+}
+
+impl Startup {
+    pub fn new() -> Self {
+        let started_at = SystemTime::now();
+        Startup {
+            started: Instant::now(),
+            started_at,
+            report: Mutex::new(StartupReport {
+                started_at: started_at.into(),
+                phases: Vec::new(),
+                ready_after_ms: None,
+                consistency_scan: ConsistencyScan {
+                    state: ScanState::Waiting,
+                    job_id: None,
+                    rebuilt: 0,
+                    millis: None,
+                },
+            }),
+        }
+    }
+
+    // Record a phase that began at `began` and just ended
+    pub fn phase(&self, name: &'static str, began: Instant) {
+        let millis = began.elapsed().as_millis() as u64;
+        println!("Startup: {} took {} ms", name, millis);
+        self.report.lock().unwrap().phases.push(Phase { name, millis });
+    }
+
+    // Record that the server serves requests from now on
+    pub fn ready(&self) {
+        let millis = self.started.elapsed().as_millis() as u64;
+        println!("Startup: ready to serve after {} ms", millis);
+        self.report.lock().unwrap().ready_after_ms = Some(millis);
+    }
+
+    pub fn scan_started(&self, job_id: &str) {
+        let mut report = self.report.lock().unwrap();
+        report.consistency_scan.state = ScanState::Running;
+        report.consistency_scan.job_id = Some(job_id.to_string());
+    }
+
+    pub fn scan_finished(&self, rebuilt: usize, began: Instant) {
+        let millis = began.elapsed().as_millis() as u64;
+        println!(
+            "Startup: consistency scan took {} ms and rebuilt {} measurements",
+            millis, rebuilt
+        );
+        let mut report = self.report.lock().unwrap();
+        report.consistency_scan.state = ScanState::Completed;
+        report.consistency_scan.rebuilt = rebuilt;
+        report.consistency_scan.millis = Some(millis);
+    }
+
+    pub fn scan_skipped(&self) {
+        self.report.lock().unwrap().consistency_scan.state = ScanState::Skipped;
+    }
+
+    pub fn report(&self) -> StartupReport {
+        self.report.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,74 @@
+// Statuses of many measurements in one request.
+//
+// POST /status/batch takes a JSON array of up to MAX_BATCH_IDS measurement
+// ids, so a gallery polls once instead of once per measurement, and answers
+// an object mapping each id to what /status/{id} answers for it, or to
+// {"error": "not_found"} when no measurement the caller may see has it.
+// Each id is looked up on its own, with the same loading of attestation.json
+// for completed measurements, so the measurements lock is only held for one
+// id at a time and never across the filesystem reads of the whole batch.
+// Though it is a POST, it changes nothing, so neither maintenance mode nor a
+// read-only mirror refuses it.
+
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    http::StatusCode,
+    response::Json,
+};
+use serde::Serialize;
+use std::{collections::BTreeMap, net::SocketAddr, sync::Arc};
+
+use crate::{
+    AppState, MeasurementStatus, check_proof_status, pending::StrictQuery, tenants::Visitor,
+};
+
+// A read despite its method, so it is served in maintenance and on mirrors
+pub const PATH: &str = "/status/batch";
+
+// Most ids one request may ask for
+pub const MAX_BATCH_IDS: usize = 100;
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum BatchEntry {
+    Found(Box<MeasurementStatus>),
+    Missing { error: &'static str },
+}
+
+// Handler answering the statuses of a list of measurements
+pub async fn batch_status(
+    State(state): State<Arc<AppState>>,
+    Visitor(caller): Visitor,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    Query(query): Query<StrictQuery>,
+    Json(ids): Json<Vec<String>>,
+) -> Result<Json<BTreeMap<String, BatchEntry>>, (StatusCode, String)> {
+    if ids.len() > MAX_BATCH_IDS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("At most {} ids may be asked for at once, got {}", MAX_BATCH_IDS, ids.len()),
+        ));
+    }
+
+    let mut statuses = BTreeMap::new();
+    for id in ids {
+        if statuses.contains_key(&id) {
+            continue;
+        }
+        let status = check_proof_status(
+            State(state.clone()),
+            Visitor(caller.clone()),
+            ConnectInfo(remote),
+            Path(id.clone()),
+            Query(StrictQuery { strict: query.strict }),
+        )
+        .await;
+        let entry = match status {
+            Ok(Json(status)) => BatchEntry::Found(Box::new(status)),
+            Err((StatusCode::NOT_FOUND, _)) => BatchEntry::Missing { error: "not_found" },
+            Err(e) => return Err(e),
+        };
+        statuses.insert(id, entry);
+    }
+    Ok(Json(statuses))
+}
@@ -0,0 +1,166 @@
+// Server-Sent Events stream of a measurement's status.
+//
+// GET /status/{id}/events saves clients from polling /status/{id}: it answers
+// a text/event-stream that starts with a `status` event carrying the current
+// status, then sends a `status` event on every status transition and an
+// `attestation` event once the attestation is attached. Each event's data is
+// what /status/{id} answers at that moment, so ?strict=true works the same.
+// The stream closes after the measurement reached a terminal status: Failed
+// or Cancelled, or Completed once its attestation was sent, which normally
+// comes with the completion.
+//
+// Transitions are learned from the event bus, which only carries the changes
+// of this instance, so the stored measurement is also read every
+// RECHECK_INTERVAL. This catches changes made by other instances sharing the
+// store, and attestation.json files the status endpoint picks up. A
+// measurement that is deleted, or can no longer be seen, ends the stream.
+// Unknown and hidden measurements are answered with 404 like /status/{id}.
+
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    http::StatusCode,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures_util::stream::{self, Stream};
+use std::{collections::VecDeque, convert::Infallible, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+    AppState, MeasurementStatus, ProofStatus, auth::Caller, check_proof_status,
+    events::MeasurementEvent, pending::StrictQuery, tenants::Visitor,
+};
+
+// Interval at which the stored measurement is read again between events
+const RECHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// A stream following one measurement
+struct Follow {
+    state: Arc<AppState>,
+    caller: Caller,
+    remote: SocketAddr,
+    id: String,
+    strict: bool,
+    events: broadcast::Receiver<MeasurementEvent>,
+    // Status and whether the attestation was sent, as last sent
+    sent_status: Option<ProofStatus>,
+    sent_attestation: bool,
+    queued: VecDeque<Event>,
+    finished: bool,
+}
+
+impl Follow {
+    // What /status/{id} answers for the measurement now, None once it is gone
+    async fn read(&self) -> Option<MeasurementStatus> {
+        check_proof_status(
+            State(self.state.clone()),
+            Visitor(self.caller.clone()),
+            ConnectInfo(self.remote),
+            Path(self.id.clone()),
+            Query(StrictQuery { strict: self.strict }),
+        )
+        .await
+        .ok()
+        .map(|status| status.0)
+    }
+
+    // Queue the events telling what changed since the last ones
+    fn queue_changes(&mut self, current: &MeasurementStatus) {
+        let measurement = &current.measurement;
+        let data = match serde_json::to_string(current) {
+            Ok(data) => data,
+            Err(e) => {
+                println!("Failed to serialize the status of {} for its stream: {}", self.id, e);
+                return;
+            }
+        };
+        if self.sent_status.as_ref() != Some(&measurement.status) {
+            self.queued.push_back(Event::default().event("status").data(data.clone()));
+            self.sent_status = Some(measurement.status.clone());
+        }
+        if measurement.attestation.is_some() && !self.sent_attestation {
+            self.queued.push_back(Event::default().event("attestation").data(data));
+            self.sent_attestation = true;
+        }
+        self.finished = match measurement.status {
+            ProofStatus::Failed | ProofStatus::Cancelled => true,
+            ProofStatus::Completed => self.sent_attestation,
+            _ => false,
+        };
+    }
+}
+
+// Wait until the measurement may have changed: an event about it, events
+// missed by falling behind, or the next recheck. After events, the status
+// reads window passes first, so the read that follows cannot share one that
+// started before the change.
+pub async fn changed(
+    state: &AppState,
+    events: &mut broadcast::Receiver<MeasurementEvent>,
+    id: &str,
+) {
+    let wait = tokio::time::sleep(RECHECK_INTERVAL);
+    tokio::pin!(wait);
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) if event.id != id => continue,
+                Ok(_) | Err(RecvError::Lagged(_)) => {
+                    return tokio::time::sleep(state.status_reads.window()).await;
+                }
+                // The bus lives as long as the server; recheck alone
+                Err(RecvError::Closed) => return wait.await,
+            },
+            _ = &mut wait => return,
+        }
+    }
+}
+
+// Handler streaming the status changes of a measurement as Server-Sent Events
+pub async fn stream(
+    State(state): State<Arc<AppState>>,
+    Visitor(caller): Visitor,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    Path(id): Path<String>,
+    Query(query): Query<StrictQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, (StatusCode, String)> {
+    // Subscribe before the first read, so no change after it is missed
+    let events = state.events.subscribe();
+    let current = check_proof_status(
+        State(state.clone()),
+        Visitor(caller.clone()),
+        ConnectInfo(remote),
+        Path(id.clone()),
+        Query(StrictQuery { strict: query.strict }),
+    )
+    .await?
+    .0;
+
+    let mut follow = Follow {
+        state,
+        caller,
+        remote,
+        id,
+        strict: query.strict,
+        events,
+        sent_status: None,
+        sent_attestation: false,
+        queued: VecDeque::new(),
+        finished: false,
+    };
+    follow.queue_changes(&current);
+
+    let events = stream::unfold(follow, |mut follow| async move {
+        loop {
+            if let Some(event) = follow.queued.pop_front() {
+                return Some((Ok(event), follow));
+            }
+            if follow.finished {
+                return None;
+            }
+            changed(&follow.state, &mut follow.events, &follow.id).await;
+            let current = follow.read().await?;
+            follow.queue_changes(&current);
+        }
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
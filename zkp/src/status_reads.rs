@@ -0,0 +1,213 @@
+// Coalesced status reads and revalidation of /status/{id}.
+//
+// Every status poll reads the measurement from the store, as other instances
+// may have changed it, which with a database is one query per poll. When many
+// clients poll the same measurement, requests for an id arriving within
+// STATUS_COALESCE_WINDOW_MS of a read that started share it instead of
+// reading again. A change is therefore hidden for at most the window after it
+// was stored: a request always shares a read that started after any change
+// older than the window. A window of 0 reads for every request.
+//
+// GET /status/{id} also carries an ETag over its body and answers
+// If-None-Match naming it with an empty 304, so pollers whose measurement did
+// not change only get headers back.
+//
+// With ?wait=<seconds>, up to MAX_WAIT_SECS, the request is held open until
+// the status differs from ?since=, the status the client last saw, or from
+// the current one without it, and answers as soon as it does; once the wait
+// is over it answers the status as it is, so clients tell a change from a
+// timeout by the status. The pipeline publishes every transition on the event
+// bus, which wakes waiting requests; changes made by other instances are
+// picked up by reading the store again every few seconds, like
+// /status/{id}/events. Without wait, requests are answered at once.
+
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Json, Response},
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::OnceCell;
+
+use crate::{
+    AppState, Measurement, MeasurementStatus, ProofStatus, check_proof_status, config::Config,
+    downloads, pending::StrictQuery, status_events, store::Measurements, tenants::Visitor,
+};
+
+// Reads kept before finished ones are dropped from the table
+const PRUNE_AT: usize = 1024;
+// Longest a status request may be held open
+const MAX_WAIT_SECS: u64 = 60;
+
+type Read = Result<Option<Measurement>, String>;
+
+// A store read that requests arriving within the window share
+struct Flight {
+    started: Instant,
+    read: Arc<OnceCell<Read>>,
+}
+
+// Status read figures for the admin stats endpoint
+#[derive(Serialize)]
+pub struct StatusReadStats {
+    window_ms: u64,
+    // Reads of the store made for status requests
+    fetches: u64,
+    // Requests answered with a read started for another request
+    coalesced: u64,
+}
+
+pub struct StatusReads {
+    window: Duration,
+    flights: Mutex<HashMap<String, Flight>>,
+    fetches: AtomicU64,
+    coalesced: AtomicU64,
+}
+
+impl StatusReads {
+    pub fn new(config: &Config) -> Self {
+        StatusReads {
+            window: Duration::from_millis(config.status_coalesce_window_ms),
+            flights: Mutex::default(),
+            fetches: AtomicU64::new(0),
+            coalesced: AtomicU64::new(0),
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    // The stored measurement, after this instance's own changes, from a read
+    // started at most the window ago
+    pub async fn get(&self, measurements: &Measurements, id: &str) -> Read {
+        let (read, leading) = {
+            let mut flights = self.flights.lock().unwrap();
+            match flights.get(id).filter(|flight| flight.started.elapsed() < self.window) {
+                Some(flight) => (flight.read.clone(), false),
+                None => {
+                    if flights.len() >= PRUNE_AT {
+                        flights.retain(|_, flight| flight.started.elapsed() < self.window);
+                    }
+                    let read = Arc::new(OnceCell::new());
+                    if !self.window.is_zero() {
+                        let flight = Flight { started: Instant::now(), read: read.clone() };
+                        flights.insert(id.to_string(), flight);
+                    }
+                    (read, true)
+                }
+            }
+        };
+        let counter = if leading { &self.fetches } else { &self.coalesced };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        // Should the leading request go away, a request sharing the read
+        // makes it instead
+        read.get_or_init(|| async {
+            measurements.flushed().await;
+            measurements.store().get(id).await
+        })
+        .await
+        .clone()
+    }
+
+    pub fn stats(&self) -> StatusReadStats {
+        StatusReadStats {
+            window_ms: self.window.as_millis() as u64,
+            fetches: self.fetches.load(Ordering::Relaxed),
+            coalesced: self.coalesced.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct StatusQuery {
+    #[serde(default)]
+    strict: bool,
+    // Seconds to wait for the status to change
+    wait: Option<u64>,
+    // Status the client last saw
+    since: Option<ProofStatus>,
+}
+
+// The status once it differs from `since`, or when `wait` is over
+async fn wait_for_change(
+    state: Arc<AppState>,
+    visitor: Visitor,
+    remote: SocketAddr,
+    id: String,
+    strict: bool,
+    wait: Duration,
+    since: Option<ProofStatus>,
+) -> Result<MeasurementStatus, (StatusCode, String)> {
+    let read = || {
+        let query = Query(StrictQuery { strict });
+        check_proof_status(
+            State(state.clone()),
+            Visitor(visitor.0.clone()),
+            ConnectInfo(remote),
+            Path(id.clone()),
+            query,
+        )
+    };
+    // Subscribe before the first read, so no change after it is missed
+    let mut events = state.events.subscribe();
+    let Json(mut current) = read().await?;
+    let since = since.unwrap_or_else(|| current.measurement.status.clone());
+    let deadline = Instant::now() + wait;
+    while current.measurement.status == since {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let changed = status_events::changed(&state, &mut events, &id);
+        if remaining.is_zero() || tokio::time::timeout(remaining, changed).await.is_err() {
+            break;
+        }
+        current = read().await?.0;
+    }
+    Ok(current)
+}
+
+// Handler answering the status of a measurement, or 304 when the client's
+// copy, named by If-None-Match, is still current
+pub async fn get_status(
+    State(state): State<Arc<AppState>>,
+    visitor: Visitor,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    Path(id): Path<String>,
+    Query(query): Query<StatusQuery>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let status = match query.wait.filter(|wait| *wait > 0) {
+        Some(wait) if wait > MAX_WAIT_SECS => {
+            let message = format!("wait must be at most {} seconds", MAX_WAIT_SECS);
+            return Err((StatusCode::BAD_REQUEST, message));
+        }
+        Some(wait) => {
+            let wait = Duration::from_secs(wait);
+            wait_for_change(state, visitor, remote, id, query.strict, wait, query.since).await?
+        }
+        None => {
+            let query = Query(StrictQuery { strict: query.strict });
+            check_proof_status(State(state), visitor, ConnectInfo(remote), Path(id), query).await?.0
+        }
+    };
+    let body = serde_json::to_vec(&status).map_err(|e| {
+        (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to serialize the status: {}", e))
+    })?;
+    let etag = format!("\"{}\"", &hex::encode(Sha256::digest(&body))[..32]);
+    let headers_out =
+        [(header::ETAG, etag.clone()), (header::CACHE_CONTROL, "no-cache".to_string())];
+    if downloads::matches_etag(&headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, headers_out).into_response());
+    }
+    Ok((headers_out, [(header::CONTENT_TYPE, "application/json")], body).into_response())
+}
@@ -0,0 +1,1886 @@
+// Measurement storage.
+//
+// Measurements are kept in a MeasurementStore chosen with MEASUREMENT_STORE:
+// the embedded sled database at MEASUREMENTS_DB_PATH (the default), memory
+// only, or Postgres at DATABASE_URL, which several instances behind a load
+// balancer can share (built with the postgres feature). Submissions, a proof
+// worker claiming a measurement and status polls go through the store itself,
+// so every instance sees the others' writes. Single reads are answered from
+// the cache of store_cache.rs, which each write of this process invalidates.
+//
+// The status of a stored measurement only changes through update_status, a
+// compare-and-set on the status it is expected to have, so two instances
+// cannot both prove a measurement or overwrite each other's transitions. save
+// writes a new record whole and patch only the fields a change touched, both
+// keeping the stored status and failure, so instances changing different
+// fields of a measurement do not undo each other's changes.
+//
+// Each process also keeps the measurements it loaded or handled in memory,
+// where listings, admin views and the background workers read them. That copy
+// is locked like the map it wraps, next to an index of the measurements in
+// listing order, (created_at, id), which listings range-scan from their
+// cursor a batch at a time. The guard remembers which measurements were
+// changed through get_mut, filter_mut, insert and remove and, once released,
+// hands exactly those to a writer task applying them to the store in order: a
+// changed status as update_status from the status seen when the measurement
+// was taken, then the other changed fields with patch, including the
+// updated_at and the history transition the guard stamps on every measurement
+// whose status it saw change, attributed to the source the lock was taken for.
+//
+// With a shared store the copy of each instance only holds what it loaded and
+// wrote, so syncer refreshes it from the store every STORE_SYNC_INTERVAL_MS:
+// listings, admin stats and the workers of an instance see the measurements
+// other instances submitted, changed or deleted once the next sync ran. A
+// shared store numbers its writes with a version, and each sync only reads
+// what was written or removed after the version the previous one got to.
+// Writes of this process are numbered as they are handed to the writer, and a
+// measurement with a write the store may not hold yet keeps its copy; it is
+// read again by the first sync after the store holds the write.
+//
+// At startup every stored measurement is loaded and recover picks up where the
+// previous process stopped before any worker runs: the files of tenants'
+// measurements are found under their tenant's directories again, external ids
+// and idempotency keys are indexed again, and only the measurements not yet
+// Completed, Failed or Cancelled are looked at further, so startup takes as
+// long as the work left over rather than the whole history. Their proof
+// outputs and attestations are checked on disk RECOVERY_CONCURRENCY at a
+// time, Pending measurements re-enter the queue in submission order and
+// deadlines are watched again. A Processing measurement was proved or
+// submitted when the process stopped; it keeps waiting for its attestation
+// when the zkVerify client left a pending attestation behind and attestation
+// polling is on, and is otherwise failed as Interrupted so it can be retried.
+// In a shared store another instance may be working on it, so it is left
+// alone.
+//
+// A read-only mirror keeps its measurements in memory and serves no records.
+
+use chrono::{DateTime, Utc};
+use futures_util::{FutureExt, StreamExt, future::BoxFuture, stream};
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt,
+    ops::{Bound, Deref},
+    str::FromStr,
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    AppState, AttestationData, Failure, FailureClass, Measurement, ProofStatus,
+    artifacts::{self, Artifact, Artifacts},
+    attestation_backend::{BackendKind, PendingAttestation},
+    clock::Clock,
+    config::Config,
+    deadline, fail_measurement,
+    history::{self, Source},
+    store_cache::{CacheLimits, CachedStore, MeasurementCacheStats},
+    tenants,
+};
+
+// Attempts at a write to the store before it is given up
+const WRITE_ATTEMPTS: u32 = 5;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+
+// Fields of a measurement's record written by a patch, by name; null removes
+// a field, leaving it to its default
+pub type Fields = serde_json::Map<String, Value>;
+
+// Fields only update_status changes
+const STATUS_FIELDS: [&str; 2] = ["status", "failure"];
+
+// What changed in a shared store after one of its versions
+#[derive(Default)]
+pub struct Changes {
+    // Measurements written since, as now stored
+    pub saved: Vec<Measurement>,
+    pub removed: Vec<String>,
+    // Version to ask for the next changes after
+    pub version: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreKind {
+    Sled,
+    Memory,
+    Postgres,
+    // Memory store with injected faults, for tests
+    Faulty,
+}
+
+impl FromStr for StoreKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "sled" => Ok(StoreKind::Sled),
+            "memory" => Ok(StoreKind::Memory),
+            "postgres" => Ok(StoreKind::Postgres),
+            "faulty" => Ok(StoreKind::Faulty),
+            other => Err(format!(
+                "unknown measurement store '{}', expected sled, memory, postgres or faulty",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for StoreKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreKind::Sled => write!(f, "sled"),
+            StoreKind::Memory => write!(f, "memory"),
+            StoreKind::Postgres => write!(f, "postgres"),
+            StoreKind::Faulty => write!(f, "faulty"),
+        }
+    }
+}
+
+// Backend holding the measurements
+pub trait MeasurementStore: Send + Sync {
+    // Store a new measurement, failing when its id is taken
+    fn insert<'a>(&'a self, measurement: &'a Measurement) -> BoxFuture<'a, Result<(), String>>;
+
+    fn get<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Option<Measurement>, String>>;
+
+    // Change the status and failure of a measurement if its status still is
+    // `from`, returning whether it did
+    fn update_status<'a>(
+        &'a self,
+        id: &'a str,
+        from: ProofStatus,
+        to: ProofStatus,
+        failure: Option<Failure>,
+    ) -> BoxFuture<'a, Result<bool, String>>;
+
+    fn set_attestation<'a>(
+        &'a self,
+        id: &'a str,
+        attestation: &'a AttestationData,
+    ) -> BoxFuture<'a, Result<(), String>>;
+
+    fn list(&self) -> BoxFuture<'_, Result<Vec<Measurement>, String>>;
+
+    // The stored measurements among `ids`, in no particular order, leaving
+    // out those no longer stored. Listings read a batch at a time with it.
+    fn get_many<'a>(
+        &'a self,
+        ids: &'a [String],
+    ) -> BoxFuture<'a, Result<Vec<Measurement>, String>> {
+        async move {
+            let mut found = Vec::with_capacity(ids.len());
+            for id in ids {
+                found.extend(self.get(id).await?);
+            }
+            Ok(found)
+        }
+        .boxed()
+    }
+
+    // Write a measurement, keeping the status and failure already stored for it
+    fn save<'a>(&'a self, measurement: &'a Measurement) -> BoxFuture<'a, Result<(), String>>;
+
+    // Write some fields of a stored measurement, leaving the others as stored.
+    // A measurement no longer stored is left removed.
+    fn patch<'a>(&'a self, id: &'a str, fields: &'a Fields) -> BoxFuture<'a, Result<(), String>>;
+
+    fn remove<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), String>>;
+
+    // Whether other instances work on the same measurements
+    fn shared(&self) -> bool {
+        false
+    }
+
+    // What was written and removed after `version`, as returned by an earlier
+    // call, 0 listing every measurement. Only shared stores, which are
+    // synced, keep versions.
+    fn changes(&self, _version: u64) -> BoxFuture<'_, Result<Changes, String>> {
+        async { Err("the store keeps no versions".to_string()) }.boxed()
+    }
+}
+
+// A measurement with the fields of a patch written over its record
+fn patched(stored: &Measurement, fields: &Fields) -> Result<Measurement, String> {
+    let Ok(Value::Object(mut record)) = serde_json::to_value(stored) else {
+        return Err(format!("unwritable measurement {}", stored.id));
+    };
+    for (name, value) in fields.iter().filter(|(name, _)| !STATUS_FIELDS.contains(&name.as_str())) {
+        match value {
+            Value::Null => record.remove(name),
+            value => record.insert(name.clone(), value.clone()),
+        };
+    }
+    serde_json::from_value(Value::Object(record))
+        .map_err(|e| format!("patch of measurement {} does not fit: {}", stored.id, e))
+}
+
+// The fields of a measurement that differ from how it was taken, but for
+// those update_status writes
+fn changed_fields(taken: &Measurement, m: &Measurement) -> Fields {
+    let (Ok(Value::Object(taken)), Ok(Value::Object(m))) =
+        (serde_json::to_value(taken), serde_json::to_value(m))
+    else {
+        return Fields::new();
+    };
+    let removed =
+        taken.keys().filter(|name| !m.contains_key(*name)).map(|name| (name.clone(), Value::Null));
+    let written = m
+        .iter()
+        .filter(|(name, value)| taken.get(*name) != Some(value))
+        .map(|(name, value)| (name.clone(), value.clone()));
+    written.chain(removed).filter(|(name, _)| !STATUS_FIELDS.contains(&name.as_str())).collect()
+}
+
+// Store for the configured backend, if it was compiled in
+pub async fn open_store(config: &Config) -> Result<Arc<dyn MeasurementStore>, String> {
+    match config.measurement_store {
+        StoreKind::Sled => Ok(Arc::new(SledStore::open(&config.measurements_db_path)?)),
+        StoreKind::Memory => Ok(Arc::new(MemoryStore::default())),
+        #[cfg(feature = "postgres")]
+        StoreKind::Postgres => {
+            Ok(Arc::new(postgres::PostgresStore::connect(&config.database_url).await?))
+        }
+        #[cfg(not(feature = "postgres"))]
+        StoreKind::Postgres => {
+            Err("MEASUREMENT_STORE=postgres requires building with the postgres feature"
+                .to_string())
+        }
+        #[cfg(feature = "testing")]
+        StoreKind::Faulty => Ok(Arc::new(faulty::FaultyStore::new(&config.store_faults)?)),
+        #[cfg(not(feature = "testing"))]
+        StoreKind::Faulty => {
+            Err("MEASUREMENT_STORE=faulty requires building with the testing feature".to_string())
+        }
+    }
+}
+
+// Measurements kept in memory only
+#[derive(Default)]
+pub struct MemoryStore {
+    measurements: Mutex<HashMap<String, Measurement>>,
+}
+
+impl MeasurementStore for MemoryStore {
+    fn insert<'a>(&'a self, measurement: &'a Measurement) -> BoxFuture<'a, Result<(), String>> {
+        let mut measurements = self.measurements.lock().unwrap();
+        let result = if measurements.contains_key(&measurement.id) {
+            Err(format!("measurement {} already exists", measurement.id))
+        } else {
+            measurements.insert(measurement.id.clone(), measurement.clone());
+            Ok(())
+        };
+        async move { result }.boxed()
+    }
+
+    fn get<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Option<Measurement>, String>> {
+        let measurement = self.measurements.lock().unwrap().get(id).cloned();
+        async move { Ok(measurement) }.boxed()
+    }
+
+    fn update_status<'a>(
+        &'a self,
+        id: &'a str,
+        from: ProofStatus,
+        to: ProofStatus,
+        failure: Option<Failure>,
+    ) -> BoxFuture<'a, Result<bool, String>> {
+        let mut measurements = self.measurements.lock().unwrap();
+        let updated = match measurements.get_mut(id).filter(|m| m.status == from) {
+            Some(m) => {
+                m.status = to;
+                m.failure = failure;
+                true
+            }
+            None => false,
+        };
+        async move { Ok(updated) }.boxed()
+    }
+
+    fn set_attestation<'a>(
+        &'a self,
+        id: &'a str,
+        attestation: &'a AttestationData,
+    ) -> BoxFuture<'a, Result<(), String>> {
+        if let Some(m) = self.measurements.lock().unwrap().get_mut(id) {
+            m.attestation = Some(attestation.clone());
+        }
+        async move { Ok(()) }.boxed()
+    }
+
+    fn list(&self) -> BoxFuture<'_, Result<Vec<Measurement>, String>> {
+        let measurements = self.measurements.lock().unwrap().values().cloned().collect();
+        async move { Ok(measurements) }.boxed()
+    }
+
+    fn save<'a>(&'a self, measurement: &'a Measurement) -> BoxFuture<'a, Result<(), String>> {
+        let mut measurements = self.measurements.lock().unwrap();
+        let mut record = measurement.clone();
+        if let Some(stored) = measurements.get(&measurement.id) {
+            record.status = stored.status.clone();
+            record.failure = stored.failure.clone();
+        }
+        measurements.insert(record.id.clone(), record);
+        async move { Ok(()) }.boxed()
+    }
+
+    fn patch<'a>(&'a self, id: &'a str, fields: &'a Fields) -> BoxFuture<'a, Result<(), String>> {
+        let mut measurements = self.measurements.lock().unwrap();
+        let result = match measurements.get_mut(id) {
+            Some(m) => patched(m, fields).map(|record| *m = record),
+            None => Ok(()),
+        };
+        async move { result }.boxed()
+    }
+
+    fn remove<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        self.measurements.lock().unwrap().remove(id);
+        async move { Ok(()) }.boxed()
+    }
+}
+
+// Memory store reporting itself shared and versioning its writes like a
+// shared store, standing in for a store several instances write to
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct SharedMemoryStore {
+    inner: MemoryStore,
+    versions: Mutex<Versions>,
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct Versions {
+    last: u64,
+    // Version of the last write of each stored measurement, and of each removal
+    written: HashMap<String, u64>,
+    removed: Vec<(String, u64)>,
+}
+
+#[cfg(test)]
+impl SharedMemoryStore {
+    fn written(&self, id: &str) {
+        let mut versions = self.versions.lock().unwrap();
+        versions.last += 1;
+        let version = versions.last;
+        versions.written.insert(id.to_string(), version);
+    }
+}
+
+#[cfg(test)]
+impl MeasurementStore for SharedMemoryStore {
+    fn insert<'a>(&'a self, measurement: &'a Measurement) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            self.inner.insert(measurement).await?;
+            self.written(&measurement.id);
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn get<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Option<Measurement>, String>> {
+        self.inner.get(id)
+    }
+
+    fn update_status<'a>(
+        &'a self,
+        id: &'a str,
+        from: ProofStatus,
+        to: ProofStatus,
+        failure: Option<Failure>,
+    ) -> BoxFuture<'a, Result<bool, String>> {
+        async move {
+            let updated = self.inner.update_status(id, from, to, failure).await?;
+            if updated {
+                self.written(id);
+            }
+            Ok(updated)
+        }
+        .boxed()
+    }
+
+    fn set_attestation<'a>(
+        &'a self,
+        id: &'a str,
+        attestation: &'a AttestationData,
+    ) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            self.inner.set_attestation(id, attestation).await?;
+            self.written(id);
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn list(&self) -> BoxFuture<'_, Result<Vec<Measurement>, String>> {
+        self.inner.list()
+    }
+
+    fn save<'a>(&'a self, measurement: &'a Measurement) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            self.inner.save(measurement).await?;
+            self.written(&measurement.id);
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn patch<'a>(&'a self, id: &'a str, fields: &'a Fields) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            self.inner.patch(id, fields).await?;
+            self.written(id);
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn remove<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            self.inner.remove(id).await?;
+            let mut versions = self.versions.lock().unwrap();
+            versions.last += 1;
+            let version = versions.last;
+            versions.written.remove(id);
+            versions.removed.push((id.to_string(), version));
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn shared(&self) -> bool {
+        true
+    }
+
+    fn changes(&self, version: u64) -> BoxFuture<'_, Result<Changes, String>> {
+        let (written, removed, last) = {
+            let versions = self.versions.lock().unwrap();
+            let after = |&(_, written): &(&String, &u64)| *written > version;
+            let written: Vec<String> =
+                versions.written.iter().filter(after).map(|(id, _)| id.clone()).collect();
+            let removed = versions
+                .removed
+                .iter()
+                .filter(|(_, removed)| *removed > version)
+                .map(|(id, _)| id.clone())
+                .collect();
+            (written, removed, versions.last)
+        };
+        let stored = self.inner.measurements.lock().unwrap();
+        let saved = written.iter().filter_map(|id| stored.get(id).cloned()).collect();
+        async move { Ok(Changes { saved, removed, version: last }) }.boxed()
+    }
+}
+
+// Measurements in the embedded sled database, as JSON keyed by id. Every write
+// is a compare-and-swap of the whole record and is flushed before it resolves.
+pub struct SledStore {
+    db: sled::Db,
+}
+
+impl SledStore {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let db = sled::open(path)
+            .map_err(|e| format!("Failed to open measurement database {}: {}", path, e))?;
+        Ok(SledStore { db })
+    }
+
+    // Replace the record of a measurement with what change makes of the stored
+    // one, retrying when it changed in between. Returns whether it was written.
+    async fn modify(
+        &self,
+        id: &str,
+        mut change: impl FnMut(Option<Measurement>) -> Option<Measurement>,
+    ) -> Result<bool, String> {
+        loop {
+            let current = self.db.get(id).map_err(|e| e.to_string())?;
+            let stored = current
+                .as_ref()
+                .map(|value| serde_json::from_slice::<Measurement>(value))
+                .transpose()
+                .map_err(|e| format!("unreadable measurement {}: {}", id, e))?;
+            let Some(record) = change(stored) else {
+                return Ok(false);
+            };
+            let value = serde_json::to_vec(&record).map_err(|e| e.to_string())?;
+            let swapped =
+                self.db.compare_and_swap(id, current, Some(value)).map_err(|e| e.to_string())?;
+            if swapped.is_ok() {
+                self.db.flush_async().await.map_err(|e| e.to_string())?;
+                return Ok(true);
+            }
+        }
+    }
+}
+
+impl MeasurementStore for SledStore {
+    fn insert<'a>(&'a self, measurement: &'a Measurement) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            let inserted = self
+                .modify(&measurement.id, |stored| stored.is_none().then(|| measurement.clone()))
+                .await?;
+            if inserted {
+                Ok(())
+            } else {
+                Err(format!("measurement {} already exists", measurement.id))
+            }
+        }
+        .boxed()
+    }
+
+    fn get<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Option<Measurement>, String>> {
+        async move {
+            let value = self.db.get(id).map_err(|e| e.to_string())?;
+            value
+                .map(|value| serde_json::from_slice(&value))
+                .transpose()
+                .map_err(|e| format!("unreadable measurement {}: {}", id, e))
+        }
+        .boxed()
+    }
+
+    fn update_status<'a>(
+        &'a self,
+        id: &'a str,
+        from: ProofStatus,
+        to: ProofStatus,
+        failure: Option<Failure>,
+    ) -> BoxFuture<'a, Result<bool, String>> {
+        async move {
+            self.modify(id, |stored| {
+                let mut m = stored.filter(|m| m.status == from)?;
+                m.status = to.clone();
+                m.failure = failure.clone();
+                Some(m)
+            })
+            .await
+        }
+        .boxed()
+    }
+
+    fn set_attestation<'a>(
+        &'a self,
+        id: &'a str,
+        attestation: &'a AttestationData,
+    ) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            self.modify(id, |stored| {
+                let mut m = stored?;
+                m.attestation = Some(attestation.clone());
+                Some(m)
+            })
+            .await?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn list(&self) -> BoxFuture<'_, Result<Vec<Measurement>, String>> {
+        async move {
+            let mut measurements = Vec::new();
+            for entry in self.db.iter() {
+                let (key, value) = entry.map_err(|e| e.to_string())?;
+                match serde_json::from_slice::<Measurement>(&value) {
+                    Ok(m) => measurements.push(m),
+                    Err(e) => println!(
+                        "Skipping unreadable measurement {}: {}",
+                        String::from_utf8_lossy(&key),
+                        e
+                    ),
+                }
+            }
+            Ok(measurements)
+        }
+        .boxed()
+    }
+
+    fn save<'a>(&'a self, measurement: &'a Measurement) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            self.modify(&measurement.id, |stored| {
+                let mut record = measurement.clone();
+                if let Some(stored) = stored {
+                    record.status = stored.status;
+                    record.failure = stored.failure;
+                }
+                Some(record)
+            })
+            .await?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn patch<'a>(&'a self, id: &'a str, fields: &'a Fields) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            let mut unfit = None;
+            self.modify(id, |stored| match patched(&stored?, fields) {
+                Ok(record) => Some(record),
+                Err(e) => {
+                    unfit = Some(e);
+                    None
+                }
+            })
+            .await?;
+            unfit.map_or(Ok(()), Err)
+        }
+        .boxed()
+    }
+
+    fn remove<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            self.db.remove(id).map_err(|e| e.to_string())?;
+            self.db.flush_async().await.map_err(|e| e.to_string())?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+// A change made in memory, waiting to be written to the store
+enum Write {
+    Status { id: String, from: ProofStatus, to: ProofStatus, failure: Option<Failure> },
+    Save(Box<Measurement>),
+    Patch { id: String, fields: Fields },
+    Remove(String),
+    // Resolves once the writes before it are in the store
+    Barrier(oneshot::Sender<()>),
+}
+
+impl Write {
+    fn id(&self) -> Option<&str> {
+        match self {
+            Write::Status { id, .. } | Write::Patch { id, .. } | Write::Remove(id) => Some(id),
+            Write::Save(m) => Some(&m.id),
+            Write::Barrier(_) => None,
+        }
+    }
+}
+
+// The writes handed to the writer, numbered in the order they were sent so a
+// sync with a shared store can tell which changes made in memory the store
+// may not hold yet
+struct Writes {
+    sender: mpsc::UnboundedSender<(u64, Write)>,
+    sent: Mutex<SentWrites>,
+    // Number of the last write the writer finished
+    applied: Arc<AtomicU64>,
+}
+
+#[derive(Default)]
+struct SentWrites {
+    count: u64,
+    // Number of the last write of each measurement not yet seen to be applied
+    // by a sync
+    by_id: HashMap<String, u64>,
+}
+
+impl SentWrites {
+    // Whether a write of the measurement may not be in the store yet
+    fn unwritten(&self, id: &str, applied: u64) -> bool {
+        self.by_id.get(id).is_some_and(|&number| number > applied)
+    }
+}
+
+impl Writes {
+    fn send(&self, write: Write) -> bool {
+        let mut sent = self.sent.lock().unwrap();
+        sent.count += 1;
+        let number = sent.count;
+        if let Some(id) = write.id() {
+            sent.by_id.insert(id.to_string(), number);
+        }
+        self.sender.send((number, write)).is_ok()
+    }
+}
+
+// Apply the changes made in memory to the store, in the order they were made.
+// A write that fails is retried with exponential backoff before moving on, as
+// a status lost here would leave the stored measurement in its old state for
+// good.
+async fn writer(
+    store: Arc<dyn MeasurementStore>,
+    mut writes: mpsc::UnboundedReceiver<(u64, Write)>,
+    applied: Arc<AtomicU64>,
+) {
+    while let Some((number, write)) = writes.recv().await {
+        let Some(id) = write.id().map(str::to_string) else {
+            applied.store(number, Ordering::Release);
+            if let Write::Barrier(done) = write {
+                let _ = done.send(());
+            }
+            continue;
+        };
+        let mut backoff = INITIAL_BACKOFF;
+        for attempt in 1..=WRITE_ATTEMPTS {
+            let Err(e) = apply(store.as_ref(), &write).await else {
+                break;
+            };
+            println!(
+                "Failed to store measurement {} (attempt {}/{}): {}",
+                id, attempt, WRITE_ATTEMPTS, e
+            );
+            if attempt < WRITE_ATTEMPTS {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+        }
+        applied.store(number, Ordering::Release);
+    }
+}
+
+async fn apply(store: &dyn MeasurementStore, write: &Write) -> Result<(), String> {
+    match write {
+        Write::Status { id, from, to, failure } => {
+            if !store.update_status(id, from.clone(), to.clone(), failure.clone()).await? {
+                println!(
+                    "Not storing status {:?} of measurement {}: it is no longer {:?}",
+                    to, id, from
+                );
+            }
+            Ok(())
+        }
+        Write::Save(m) => store.save(m).await,
+        Write::Patch { id, fields } => store.patch(id, fields).await,
+        Write::Remove(id) => store.remove(id).await,
+        Write::Barrier(_) => Ok(()),
+    }
+}
+
+// Position of a measurement in listings, which are ordered by this key
+pub type ListKey = (Option<DateTime<Utc>>, String);
+
+fn list_key(m: &Measurement) -> ListKey {
+    (m.created_at, m.id.clone())
+}
+
+// The measurements held in memory and their listing order
+#[derive(Default)]
+struct Held {
+    by_id: HashMap<String, Measurement>,
+    order: BTreeSet<ListKey>,
+}
+
+impl Held {
+    fn insert(&mut self, m: Measurement) -> Option<Measurement> {
+        self.order.insert(list_key(&m));
+        let replaced = self.by_id.insert(m.id.clone(), m)?;
+        self.reorder(&replaced);
+        Some(replaced)
+    }
+
+    fn remove(&mut self, id: &str) -> Option<Measurement> {
+        let removed = self.by_id.remove(id)?;
+        self.order.remove(&list_key(&removed));
+        Some(removed)
+    }
+
+    // Move a measurement whose key may have changed from what it was in
+    // `before` to where it is now
+    fn reorder(&mut self, before: &Measurement) {
+        let Some(m) = self.by_id.get(&before.id) else {
+            return;
+        };
+        if m.created_at != before.created_at {
+            self.order.remove(&list_key(before));
+            self.order.insert(list_key(m));
+        }
+    }
+}
+
+// The store and the measurements this process holds in memory
+pub struct Measurements {
+    measurements: Mutex<Held>,
+    store: Arc<CachedStore>,
+    writes: Writes,
+    synced: Mutex<Synced>,
+    // Time stamped on the status changes made in memory
+    clock: Clock,
+}
+
+// Where syncing with a shared store got to
+#[derive(Default)]
+struct Synced {
+    // Version of the store the last sync read the changes up to
+    version: u64,
+    // Measurements whose stored change a sync skipped for a write the store
+    // did not hold yet, to be read again once it does
+    recheck: HashSet<String>,
+}
+
+impl Measurements {
+    // Load every stored measurement and start writing changes back, reading
+    // them through a cache with the given limits
+    pub async fn load(
+        store: Arc<dyn MeasurementStore>,
+        cache: CacheLimits,
+        clock: Clock,
+    ) -> Result<Self, String> {
+        let store = Arc::new(CachedStore::new(store, cache));
+        let (stored, version) = if store.shared() {
+            store.changes(0).await.map(|changes| (changes.saved, changes.version))
+        } else {
+            store.list().await.map(|stored| (stored, 0))
+        }
+        .map_err(|e| format!("Failed to load the stored measurements: {}", e))?;
+        let mut measurements = Held::default();
+        for m in stored {
+            measurements.insert(m);
+        }
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let applied = Arc::new(AtomicU64::new(0));
+        tokio::spawn(writer(store.clone(), receiver, applied.clone()));
+        let writes = Writes { sender, sent: Mutex::default(), applied };
+        let synced = Mutex::new(Synced { version, recheck: HashSet::new() });
+        Ok(Measurements { measurements: Mutex::new(measurements), store, writes, synced, clock })
+    }
+
+    pub fn store(&self) -> &dyn MeasurementStore {
+        self.store.as_ref()
+    }
+
+    // The store without the cache in front of it, for consistency checks
+    pub fn uncached_store(&self) -> &dyn MeasurementStore {
+        self.store.uncached()
+    }
+
+    pub fn cache_stats(&self) -> MeasurementCacheStats {
+        self.store.stats()
+    }
+
+    pub fn lock(&self) -> StoreGuard<'_> {
+        self.lock_as(Source::Server)
+    }
+
+    // Lock the measurements, recording the status changes made through the
+    // guard as made by `source`
+    pub fn lock_as(&self, source: Source) -> StoreGuard<'_> {
+        StoreGuard {
+            measurements: self.measurements.lock().unwrap(),
+            writes: &self.writes,
+            clock: &self.clock,
+            source,
+            changed: HashMap::new(),
+            removed: Vec::new(),
+        }
+    }
+
+    // Wait until the changes released before the call are in the store
+    pub async fn flushed(&self) {
+        let (done, written) = oneshot::channel();
+        // Taking the lock waits for a guard still handing over its changes
+        drop(self.lock());
+        if self.writes.send(Write::Barrier(done)) {
+            let _ = written.await;
+        }
+    }
+
+    // Change the status of a stored measurement to `to` if it still has the
+    // status it was read with, then keep it in memory. Returns the changed
+    // measurement, or None when its status changed in the meantime.
+    pub async fn transition(
+        &self,
+        mut measurement: Measurement,
+        to: ProofStatus,
+        failure: Option<Failure>,
+        source: Source,
+    ) -> Result<Option<Measurement>, String> {
+        let from = measurement.status.clone();
+        if !self
+            .store
+            .update_status(&measurement.id, from.clone(), to.clone(), failure.clone())
+            .await?
+        {
+            return Ok(None);
+        }
+        let now = self.clock.now();
+        measurement.status = to;
+        measurement.failure = failure;
+        measurement.updated_at = Some(now);
+        history::record(&mut measurement, Some(from), now, source);
+        // Written again for updated_at and the history, which update_status
+        // leaves as stored
+        self.lock().insert(measurement.id.clone(), measurement.clone());
+        Ok(Some(measurement))
+    }
+
+    // Bring the measurements in memory up to date with the store, taking the
+    // changes other instances wrote to a shared one since the last sync. A
+    // measurement changed by this process keeps its copy until the store
+    // holds the change. Returns how many measurements changed, none for a
+    // store only this process writes.
+    pub async fn sync(&self) -> Result<usize, String> {
+        if !self.store.shared() {
+            return Ok(0);
+        }
+        // Every write up to this one is in what the store returns next
+        let applied = self.writes.applied.load(Ordering::Acquire);
+        let since = self.synced.lock().unwrap().version;
+        let store = self.store.uncached();
+        let mut changes = store.changes(since).await?;
+        let recheck: Vec<String> = {
+            let synced = self.synced.lock().unwrap();
+            let sent = self.writes.sent.lock().unwrap();
+            synced.recheck.iter().filter(|id| !sent.unwritten(id, applied)).cloned().collect()
+        };
+        for id in recheck {
+            if changes.saved.iter().any(|m| m.id == id) {
+                continue;
+            }
+            match store.get(&id).await? {
+                Some(m) => changes.saved.push(m),
+                None => changes.removed.push(id),
+            }
+        }
+
+        let mut measurements = self.measurements.lock().unwrap();
+        let mut sent = self.writes.sent.lock().unwrap();
+        let mut synced = self.synced.lock().unwrap();
+        let saved: HashSet<String> = changes.saved.iter().map(|m| m.id.clone()).collect();
+        let mut changed = 0;
+        for m in changes.saved {
+            synced.recheck.remove(&m.id);
+            if sent.unwritten(&m.id, applied) {
+                synced.recheck.insert(m.id);
+                continue;
+            }
+            if measurements.by_id.get(&m.id).is_some_and(|kept| same_record(kept, &m)) {
+                continue;
+            }
+            if let Some(tenant) = &m.tenant {
+                artifacts::assign_tenant(&m.id, tenant);
+            }
+            measurements.insert(m);
+            changed += 1;
+        }
+        for id in changes.removed.into_iter().filter(|id| !saved.contains(id)) {
+            synced.recheck.remove(&id);
+            if sent.unwritten(&id, applied) {
+                synced.recheck.insert(id);
+            } else if measurements.remove(&id).is_some() {
+                changed += 1;
+            }
+        }
+        synced.version = changes.version;
+        sent.by_id.retain(|_, number| *number > applied);
+        Ok(changed)
+    }
+}
+
+fn same_record(a: &Measurement, b: &Measurement) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
+
+// Background task refreshing the measurements in memory from a shared store
+// every STORE_SYNC_INTERVAL_MS, so listings, admin views and the background
+// workers of this instance see the measurements other instances submitted,
+// changed and deleted. Idle for a store only this process writes.
+pub async fn syncer(state: Arc<AppState>) {
+    let interval = state.config.store_sync_interval_ms;
+    if interval == 0 || !state.measurements.store().shared() {
+        return;
+    }
+    let mut interval = tokio::time::interval(Duration::from_millis(interval));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        match state.measurements.sync().await {
+            Ok(0) => {}
+            Ok(changed) => println!("Synced {} measurements changed in the store", changed),
+            Err(e) => println!("Failed to sync the measurements with the store: {}", e),
+        }
+    }
+}
+
+// Locked measurements, handing the changes made through it to the writer once
+// released
+pub(crate) struct StoreGuard<'a> {
+    measurements: MutexGuard<'a, Held>,
+    writes: &'a Writes,
+    clock: &'a Clock,
+    // What status changes made through the guard are recorded as made by
+    source: Source,
+    // Changed measurements, as first taken unless they were inserted
+    changed: HashMap<String, Option<Measurement>>,
+    removed: Vec<String>,
+}
+
+impl StoreGuard<'_> {
+    pub fn get_mut(&mut self, id: &str) -> Option<&mut Measurement> {
+        let m = self.measurements.by_id.get_mut(id)?;
+        self.changed.entry(id.to_string()).or_insert_with(|| Some(m.clone()));
+        Some(m)
+    }
+
+    // Measurements matching the predicate, to be changed
+    pub fn filter_mut(
+        &mut self,
+        mut predicate: impl FnMut(&Measurement) -> bool,
+    ) -> Vec<&mut Measurement> {
+        let matched: Vec<&mut Measurement> =
+            self.measurements.by_id.values_mut().filter(|m| predicate(m)).collect();
+        for m in &matched {
+            self.changed.entry(m.id.clone()).or_insert_with(|| Some((*m).clone()));
+        }
+        matched
+    }
+
+    pub fn insert(&mut self, id: String, measurement: Measurement) -> Option<Measurement> {
+        self.removed.retain(|removed| *removed != id);
+        self.changed.insert(id, None);
+        self.measurements.insert(measurement)
+    }
+
+    pub fn remove(&mut self, id: &str) -> Option<Measurement> {
+        let removed = self.measurements.remove(id)?;
+        self.changed.remove(id);
+        self.removed.push(id.to_string());
+        Some(removed)
+    }
+
+    // Keep a measurement read from or already written to the store in memory,
+    // without writing it again
+    pub fn refresh(&mut self, measurement: Measurement) {
+        if let Some(tenant) = &measurement.tenant {
+            artifacts::assign_tenant(&measurement.id, tenant);
+        }
+        self.measurements.insert(measurement);
+    }
+
+    // Drop a measurement from memory without removing it from the store
+    pub fn forget(&mut self, id: &str) {
+        self.measurements.remove(id);
+        self.changed.remove(id);
+    }
+
+    // The measurements after `after` in listing order, from the first one
+    // when None
+    pub fn listed_after(&self, after: Option<&ListKey>) -> impl Iterator<Item = &Measurement> {
+        let from = after.map_or(Bound::Unbounded, Bound::Excluded);
+        let Held { by_id, order } = &*self.measurements;
+        order.range::<ListKey, _>((from, Bound::Unbounded)).filter_map(|(_, id)| by_id.get(id))
+    }
+}
+
+impl Deref for StoreGuard<'_> {
+    type Target = HashMap<String, Measurement>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.measurements.by_id
+    }
+}
+
+impl Drop for StoreGuard<'_> {
+    fn drop(&mut self) {
+        for (id, taken) in self.changed.drain() {
+            if let Some(taken) = &taken {
+                self.measurements.reorder(taken);
+            }
+            let Some(m) = self.measurements.by_id.get_mut(&id) else {
+                continue;
+            };
+            let Some(taken) = taken else {
+                self.writes.send(Write::Save(Box::new(m.clone())));
+                continue;
+            };
+            if taken.status != m.status {
+                let now = self.clock.now();
+                m.updated_at = Some(now);
+                history::record(m, Some(taken.status.clone()), now, self.source);
+                self.writes.send(Write::Status {
+                    id: id.clone(),
+                    from: taken.status.clone(),
+                    to: m.status.clone(),
+                    failure: m.failure.clone(),
+                });
+            }
+            let fields = changed_fields(&taken, m);
+            if !fields.is_empty() {
+                self.writes.send(Write::Patch { id, fields });
+            }
+        }
+        for id in self.removed.drain(..) {
+            self.writes.send(Write::Remove(id));
+        }
+    }
+}
+
+// Restore the in-memory state derived from the stored measurements and resume
+// or fail the work the previous process left behind
+pub async fn recover(state: &Arc<AppState>) {
+    let mut unfinished = Vec::new();
+    {
+        let measurements = state.measurements.lock();
+        if !measurements.is_empty() {
+            println!("Loaded {} measurements from the store", measurements.len());
+        }
+        for m in measurements.values() {
+            if let Some(tenant) = &m.tenant {
+                artifacts::assign_tenant(&m.id, tenant);
+            }
+            let owner = tenants::owner_key(m.tenant.as_deref(), &m.owner);
+            if let Some(external_id) = &m.external_id {
+                state.external_ids.insert(&owner, external_id, &m.id);
+            }
+            if let Some(key) = &m.idempotency_key {
+                state.idempotency_keys.insert(&owner, key, &m.id);
+            }
+            if !matches!(
+                m.status,
+                ProofStatus::Completed | ProofStatus::Failed | ProofStatus::Cancelled
+            ) {
+                unfinished.push(m.id.clone());
+            }
+        }
+    }
+    resume(state, unfinished).await;
+}
+
+// What the files of an unfinished measurement tell at startup
+struct FileCheck {
+    id: String,
+    artifacts: Artifacts,
+    pending_attestation: Option<PendingAttestation>,
+}
+
+// Resume or fail the unfinished measurements with the given ids, checking
+// their files RECOVERY_CONCURRENCY at a time
+pub async fn resume(state: &Arc<AppState>, ids: Vec<String>) {
+    let shared = state.measurements.store().shared();
+    let checks: Vec<FileCheck> = stream::iter(ids)
+        .map(|id| {
+            let recorded = state
+                .measurements
+                .lock()
+                .get(&id)
+                .map(|m| (m.artifacts.clone(), m.status == ProofStatus::Processing));
+            tokio::task::spawn_blocking(move || {
+                let (mut artifacts, processing) = recorded?;
+                artifacts.record_all(&id, &Artifact::PROOF_OUTPUTS);
+                artifacts.record(&id, Artifact::Attestation);
+                let pending_attestation = PendingAttestation::load(&id).ok().filter(|_| processing);
+                Some(FileCheck { id, artifacts, pending_attestation })
+            })
+        })
+        .buffer_unordered(state.config.recovery_concurrency)
+        .filter_map(|check| async move { check.ok().flatten() })
+        .collect()
+        .await;
+
+    let mut interrupted = Vec::new();
+    let mut pending = Vec::new();
+    {
+        let mut measurements = state.measurements.lock();
+        for check in checks {
+            let Some(m) = measurements.get(&check.id) else {
+                continue;
+            };
+            match m.status {
+                ProofStatus::Pending => pending.push((m.created_at, m.owner.clone(), m.id.clone())),
+                ProofStatus::Processing => interrupted.push((
+                    m.id.clone(),
+                    m.attestation_backend,
+                    check.pending_attestation,
+                )),
+                _ => {}
+            }
+            if matches!(m.status, ProofStatus::Pending | ProofStatus::Scheduled)
+                && let Some(deadline) = m.deadline
+            {
+                tokio::spawn(deadline::watch(state.clone(), m.id.clone(), deadline));
+            }
+            // Files the previous process wrote without recording them; in a
+            // shared store another instance may be writing them
+            if !shared
+                && m.artifacts != check.artifacts
+                && let Some(m) = measurements.get_mut(&check.id)
+            {
+                m.artifacts = check.artifacts;
+            }
+        }
+    }
+
+    // Queue in submission order; a measurement another instance claims first
+    // is skipped by the worker
+    pending.sort();
+    for (_, owner, id) in &pending {
+        state.queue.push(owner, id);
+    }
+    if !pending.is_empty() {
+        println!("Queued {} pending measurements again", pending.len());
+    }
+
+    if shared {
+        return;
+    }
+    for (id, backend, pending_attestation) in interrupted {
+        // A measurement submitted for its attestation keeps waiting for it;
+        // records from before backends were kept were submitted to zkVerify
+        if state.config.attestation_polling
+            && let Some(pending) = pending_attestation
+        {
+            let backend = backend.unwrap_or(BackendKind::ZkVerify);
+            state.attestations.register(&id, backend, pending);
+            println!("Measurement {} is awaiting its attestation again", id);
+            continue;
+        }
+        println!("Measurement {} was interrupted by a restart", id);
+        let message = "Proof generation or submission was interrupted by a restart".to_string();
+        fail_measurement(state, &id, Source::Recovery, FailureClass::Interrupted, message);
+    }
+}
+
+#[cfg(feature = "postgres")]
+mod postgres {
+    use futures_util::{FutureExt, future::BoxFuture};
+    use sqlx::{PgPool, Row, postgres::PgPoolOptions};
+
+    use super::{Changes, Fields, MeasurementStore};
+    use crate::{AttestationData, Failure, Measurement, ProofStatus};
+
+    const MAX_CONNECTIONS: u32 = 10;
+
+    const SCHEMA: [&str; 5] = [
+        "CREATE SEQUENCE IF NOT EXISTS measurement_versions",
+        "CREATE TABLE IF NOT EXISTS measurements (
+            id TEXT PRIMARY KEY,
+            status TEXT NOT NULL,
+            record JSONB NOT NULL,
+            updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+        )",
+        "ALTER TABLE measurements
+         ADD COLUMN IF NOT EXISTS version BIGINT NOT NULL DEFAULT nextval('measurement_versions')",
+        "CREATE INDEX IF NOT EXISTS measurements_by_version ON measurements (version)",
+        "CREATE TABLE IF NOT EXISTS measurement_removals (
+            id TEXT NOT NULL,
+            version BIGINT NOT NULL DEFAULT nextval('measurement_versions'),
+            removed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+        )",
+    ];
+
+    // Writes older than this are committed, as no single statement runs this
+    // long; changes after the first younger one are read again by the next
+    // sync, in case a write holding an earlier version commits later
+    const SETTLED: &str = "interval '5 seconds'";
+    // How long removals are kept for instances to sync them
+    const REMOVALS_KEPT: &str = "interval '1 day'";
+
+    // Measurements in a Postgres table shared by every instance. The record is
+    // kept as JSONB next to its status, which update_status compares and sets
+    // in one statement. Every write takes the next version from a sequence,
+    // and removals are kept with theirs for a day, so changes reads only what
+    // was written after a version.
+    pub struct PostgresStore {
+        pool: PgPool,
+    }
+
+    impl PostgresStore {
+        pub async fn connect(url: &str) -> Result<Self, String> {
+            let pool = PgPoolOptions::new()
+                .max_connections(MAX_CONNECTIONS)
+                .connect(url)
+                .await
+                .map_err(|e| format!("Failed to connect to Postgres: {}", e))?;
+            for statement in SCHEMA {
+                sqlx::query(statement)
+                    .execute(&pool)
+                    .await
+                    .map_err(|e| format!("Failed to create the measurements table: {}", e))?;
+            }
+            Ok(PostgresStore { pool })
+        }
+    }
+
+    fn status_name(status: &ProofStatus) -> String {
+        format!("{:?}", status)
+    }
+
+    fn json<T: serde::Serialize>(value: &T) -> Result<String, String> {
+        serde_json::to_string(value).map_err(|e| e.to_string())
+    }
+
+    fn parse(record: &str) -> Result<Measurement, String> {
+        serde_json::from_str(record).map_err(|e| format!("unreadable measurement: {}", e))
+    }
+
+    impl MeasurementStore for PostgresStore {
+        fn insert<'a>(&'a self, measurement: &'a Measurement) -> BoxFuture<'a, Result<(), String>> {
+            async move {
+                sqlx::query(
+                    "INSERT INTO measurements (id, status, record) VALUES ($1, $2, $3::jsonb)",
+                )
+                .bind(&measurement.id)
+                .bind(status_name(&measurement.status))
+                .bind(json(measurement)?)
+                .execute(&self.pool)
+                .await
+                .map_err(|e| e.to_string())?;
+                Ok(())
+            }
+            .boxed()
+        }
+
+        fn get<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Option<Measurement>, String>> {
+            async move {
+                let row = sqlx::query("SELECT record::text FROM measurements WHERE id = $1")
+                    .bind(id)
+                    .fetch_optional(&self.pool)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                row.map(|row| parse(row.get(0))).transpose()
+            }
+            .boxed()
+        }
+
+        fn get_many<'a>(
+            &'a self,
+            ids: &'a [String],
+        ) -> BoxFuture<'a, Result<Vec<Measurement>, String>> {
+            async move {
+                let rows = sqlx::query("SELECT record::text FROM measurements WHERE id = ANY($1)")
+                    .bind(ids)
+                    .fetch_all(&self.pool)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                rows.iter().map(|row| parse(row.get(0))).collect()
+            }
+            .boxed()
+        }
+
+        fn update_status<'a>(
+            &'a self,
+            id: &'a str,
+            from: ProofStatus,
+            to: ProofStatus,
+            failure: Option<Failure>,
+        ) -> BoxFuture<'a, Result<bool, String>> {
+            async move {
+                let result = sqlx::query(
+                    "UPDATE measurements
+                     SET status = $3,
+                         record = record || jsonb_build_object('status', $3::text, 'failure', $4::jsonb),
+                         version = nextval('measurement_versions'),
+                         updated_at = now()
+                     WHERE id = $1 AND status = $2",
+                )
+                .bind(id)
+                .bind(status_name(&from))
+                .bind(status_name(&to))
+                .bind(json(&failure)?)
+                .execute(&self.pool)
+                .await
+                .map_err(|e| e.to_string())?;
+                Ok(result.rows_affected() == 1)
+            }
+            .boxed()
+        }
+
+        fn set_attestation<'a>(
+            &'a self,
+            id: &'a str,
+            attestation: &'a AttestationData,
+        ) -> BoxFuture<'a, Result<(), String>> {
+            async move {
+                sqlx::query(
+                    "UPDATE measurements
+                     SET record = jsonb_set(record, '{attestation}', $2::jsonb),
+                         version = nextval('measurement_versions'),
+                         updated_at = now()
+                     WHERE id = $1",
+                )
+                .bind(id)
+                .bind(json(attestation)?)
+                .execute(&self.pool)
+                .await
+                .map_err(|e| e.to_string())?;
+                Ok(())
+            }
+            .boxed()
+        }
+
+        fn list(&self) -> BoxFuture<'_, Result<Vec<Measurement>, String>> {
+            async move {
+                let rows = sqlx::query("SELECT record::text FROM measurements ORDER BY id")
+                    .fetch_all(&self.pool)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                let mut measurements = Vec::new();
+                for row in rows {
+                    match parse(row.get(0)) {
+                        Ok(m) => measurements.push(m),
+                        Err(e) => println!("Skipping stored measurement: {}", e),
+                    }
+                }
+                Ok(measurements)
+            }
+            .boxed()
+        }
+
+        fn save<'a>(&'a self, measurement: &'a Measurement) -> BoxFuture<'a, Result<(), String>> {
+            async move {
+                sqlx::query(
+                    "INSERT INTO measurements (id, status, record) VALUES ($1, $2, $3::jsonb)
+                     ON CONFLICT (id) DO UPDATE
+                     SET record = EXCLUDED.record || jsonb_build_object(
+                             'status', measurements.record->'status',
+                             'failure', measurements.record->'failure'),
+                         version = nextval('measurement_versions'),
+                         updated_at = now()",
+                )
+                .bind(&measurement.id)
+                .bind(status_name(&measurement.status))
+                .bind(json(measurement)?)
+                .execute(&self.pool)
+                .await
+                .map_err(|e| e.to_string())?;
+                Ok(())
+            }
+            .boxed()
+        }
+
+        fn patch<'a>(
+            &'a self,
+            id: &'a str,
+            fields: &'a Fields,
+        ) -> BoxFuture<'a, Result<(), String>> {
+            async move {
+                // Null fields are removed, and status and failure left to update_status
+                sqlx::query(
+                    "UPDATE measurements
+                     SET record = (record || ($2::jsonb - 'status' - 'failure'))
+                             - ARRAY(SELECT key FROM jsonb_each($2::jsonb) WHERE value = 'null'),
+                         version = nextval('measurement_versions'),
+                         updated_at = now()
+                     WHERE id = $1",
+                )
+                .bind(id)
+                .bind(json(fields)?)
+                .execute(&self.pool)
+                .await
+                .map_err(|e| e.to_string())?;
+                Ok(())
+            }
+            .boxed()
+        }
+
+        fn remove<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), String>> {
+            async move {
+                let mut transaction = self.pool.begin().await.map_err(|e| e.to_string())?;
+                sqlx::query("DELETE FROM measurements WHERE id = $1")
+                    .bind(id)
+                    .execute(&mut *transaction)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                sqlx::query("INSERT INTO measurement_removals (id) VALUES ($1)")
+                    .bind(id)
+                    .execute(&mut *transaction)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                sqlx::query(&format!(
+                    "DELETE FROM measurement_removals WHERE removed_at < now() - {}",
+                    REMOVALS_KEPT
+                ))
+                .execute(&mut *transaction)
+                .await
+                .map_err(|e| e.to_string())?;
+                transaction.commit().await.map_err(|e| e.to_string())
+            }
+            .boxed()
+        }
+
+        fn shared(&self) -> bool {
+            true
+        }
+
+        fn changes(&self, version: u64) -> BoxFuture<'_, Result<Changes, String>> {
+            async move {
+                let since = version as i64;
+                let rows = sqlx::query(&format!(
+                    "SELECT record::text, version, updated_at < now() - {}
+                     FROM measurements WHERE version > $1 ORDER BY version",
+                    SETTLED
+                ))
+                .bind(since)
+                .fetch_all(&self.pool)
+                .await
+                .map_err(|e| e.to_string())?;
+                let removals = sqlx::query(&format!(
+                    "SELECT id, version, removed_at < now() - {}
+                     FROM measurement_removals WHERE version > $1 AND $1 > 0",
+                    SETTLED
+                ))
+                .bind(since)
+                .fetch_all(&self.pool)
+                .await
+                .map_err(|e| e.to_string())?;
+
+                // Up to the first version written too recently to be sure
+                // every earlier one is committed
+                let mut latest = since;
+                let mut unsettled = i64::MAX;
+                let mut changes = Changes::default();
+                for row in &rows {
+                    match parse(row.get(0)) {
+                        Ok(m) => changes.saved.push(m),
+                        Err(e) => println!("Skipping stored measurement: {}", e),
+                    }
+                }
+                for row in &removals {
+                    changes.removed.push(row.get(0));
+                }
+                for row in rows.iter().chain(&removals) {
+                    let (version, settled): (i64, bool) = (row.get(1), row.get(2));
+                    latest = latest.max(version);
+                    if !settled {
+                        unsettled = unsettled.min(version);
+                    }
+                }
+                changes.version = latest.min(unsettled - 1) as u64;
+                Ok(changes)
+            }
+            .boxed()
+        }
+    }
+}
+
+#[cfg(feature = "testing")]
+mod faulty {
+    use futures_util::{FutureExt, future::BoxFuture};
+    use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+    use super::{Fields, MeasurementStore, MemoryStore};
+    use crate::{AttestationData, Failure, Measurement, ProofStatus};
+
+    const OPERATIONS: [&str; 9] = [
+        "insert",
+        "get",
+        "update_status",
+        "set_attestation",
+        "list",
+        "save",
+        "patch",
+        "remove",
+        "any",
+    ];
+
+    // Faults injected into the store, from STORE_FAULTS: comma-separated
+    // latency_ms=N, capacity=N and any number of fail=OPERATION:N, failing
+    // the Nth call of an operation, or of any operation with "any"
+    #[derive(Debug, Default)]
+    struct Faults {
+        latency: Duration,
+        capacity: Option<usize>,
+        failures: Vec<(String, u64)>,
+    }
+
+    impl Faults {
+        fn parse(spec: &str) -> Result<Self, String> {
+            let mut faults = Faults::default();
+            for fault in spec.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+                let invalid = || format!("Invalid fault '{}' in STORE_FAULTS", fault);
+                let (name, value) = fault.split_once('=').ok_or_else(invalid)?;
+                match name.trim() {
+                    "latency_ms" => {
+                        faults.latency =
+                            Duration::from_millis(value.parse().map_err(|_| invalid())?)
+                    }
+                    "capacity" => faults.capacity = Some(value.parse().map_err(|_| invalid())?),
+                    "fail" => {
+                        let (operation, nth) = value.split_once(':').ok_or_else(invalid)?;
+                        if !OPERATIONS.contains(&operation) {
+                            return Err(invalid());
+                        }
+                        let nth = nth.parse().ok().filter(|n| *n > 0).ok_or_else(invalid)?;
+                        faults.failures.push((operation.to_string(), nth));
+                    }
+                    _ => return Err(invalid()),
+                }
+            }
+            Ok(faults)
+        }
+    }
+
+    // Memory store delaying every call by the injected latency, failing the
+    // chosen calls and refusing new measurements beyond its capacity
+    pub struct FaultyStore {
+        inner: MemoryStore,
+        faults: Faults,
+        calls: Mutex<HashMap<&'static str, u64>>,
+    }
+
+    impl FaultyStore {
+        pub fn new(spec: &str) -> Result<Self, String> {
+            let faults = Faults::parse(spec)?;
+            println!("Injecting measurement store faults: {:?}", faults);
+            Ok(FaultyStore { inner: MemoryStore::default(), faults, calls: Mutex::default() })
+        }
+
+        // Count a call, wait out the latency and fail it if it was chosen to
+        async fn call(&self, operation: &'static str) -> Result<(), String> {
+            let failing = {
+                let mut calls = self.calls.lock().unwrap();
+                let mut count = |operation| {
+                    let count = calls.entry(operation).or_default();
+                    *count += 1;
+                    *count
+                };
+                let (nth, nth_of_any) = (count(operation), count("any"));
+                self.faults.failures.iter().any(|(failing, n)| {
+                    (failing == operation && *n == nth) || (failing == "any" && *n == nth_of_any)
+                })
+            };
+            if !self.faults.latency.is_zero() {
+                tokio::time::sleep(self.faults.latency).await;
+            }
+            if failing {
+                return Err(format!("injected failure of {} call", operation));
+            }
+            Ok(())
+        }
+
+        // Refuse a measurement the store has no room for
+        fn make_room(&self, id: &str) -> Result<(), String> {
+            let measurements = self.inner.measurements.lock().unwrap();
+            match self.faults.capacity {
+                Some(capacity)
+                    if !measurements.contains_key(id) && measurements.len() >= capacity =>
+                {
+                    Err(format!("store is full at {} measurements", capacity))
+                }
+                _ => Ok(()),
+            }
+        }
+    }
+
+    impl MeasurementStore for FaultyStore {
+        fn insert<'a>(&'a self, measurement: &'a Measurement) -> BoxFuture<'a, Result<(), String>> {
+            async move {
+                self.call("insert").await?;
+                self.make_room(&measurement.id)?;
+                self.inner.insert(measurement).await
+            }
+            .boxed()
+        }
+
+        fn get<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Option<Measurement>, String>> {
+            async move {
+                self.call("get").await?;
+                self.inner.get(id).await
+            }
+            .boxed()
+        }
+
+        fn update_status<'a>(
+            &'a self,
+            id: &'a str,
+            from: ProofStatus,
+            to: ProofStatus,
+            failure: Option<Failure>,
+        ) -> BoxFuture<'a, Result<bool, String>> {
+            async move {
+                self.call("update_status").await?;
+                self.inner.update_status(id, from, to, failure).await
+            }
+            .boxed()
+        }
+
+        fn set_attestation<'a>(
+            &'a self,
+            id: &'a str,
+            attestation: &'a AttestationData,
+        ) -> BoxFuture<'a, Result<(), String>> {
+            async move {
+                self.call("set_attestation").await?;
+                self.inner.set_attestation(id, attestation).await
+            }
+            .boxed()
+        }
+
+        fn list(&self) -> BoxFuture<'_, Result<Vec<Measurement>, String>> {
+            async move {
+                self.call("list").await?;
+                self.inner.list().await
+            }
+            .boxed()
+        }
+
+        fn save<'a>(&'a self, measurement: &'a Measurement) -> BoxFuture<'a, Result<(), String>> {
+            async move {
+                self.call("save").await?;
+                self.make_room(&measurement.id)?;
+                self.inner.save(measurement).await
+            }
+            .boxed()
+        }
+
+        fn patch<'a>(
+            &'a self,
+            id: &'a str,
+            fields: &'a Fields,
+        ) -> BoxFuture<'a, Result<(), String>> {
+            async move {
+                self.call("patch").await?;
+                self.inner.patch(id, fields).await
+            }
+            .boxed()
+        }
+
+        fn remove<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), String>> {
+            async move {
+                self.call("remove").await?;
+                self.inner.remove(id).await
+            }
+            .boxed()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    // Two instances sharing one store
+    async fn instances() -> (Measurements, Measurements) {
+        let store: Arc<dyn MeasurementStore> = Arc::new(SharedMemoryStore::default());
+        let load = || Measurements::load(store.clone(), CacheLimits::default(), Clock::default());
+        (load().await.unwrap(), load().await.unwrap())
+    }
+
+    fn status(measurements: &Measurements, id: &str) -> Option<ProofStatus> {
+        measurements.lock().get(id).map(|m| m.status.clone())
+    }
+
+    #[tokio::test]
+    async fn listing_order_follows_inserts_changes_and_syncs() {
+        let (a, b) = instances().await;
+        let listed = |measurements: &Measurements, after: Option<&ListKey>| -> Vec<String> {
+            measurements.lock().listed_after(after).map(|m| m.id.clone()).collect()
+        };
+        let at = |seconds| Some(DateTime::from_timestamp(seconds, 0).unwrap());
+        for (id, created_at) in [("a", 3), ("b", 1), ("c", 2)] {
+            let mut m = Measurement::fixture(id);
+            m.created_at = at(created_at);
+            a.lock().insert(id.to_string(), m);
+        }
+        assert_eq!(listed(&a, None), ["b", "c", "a"]);
+        assert_eq!(listed(&a, Some(&(at(1), "b".to_string()))), ["c", "a"]);
+
+        a.lock().get_mut("b").unwrap().created_at = at(4);
+        a.lock().remove("c");
+        assert_eq!(listed(&a, None), ["a", "b"]);
+        a.flushed().await;
+        b.sync().await.unwrap();
+        assert_eq!(listed(&b, None), ["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn instances_see_each_others_writes_once_synced() {
+        let (a, b) = instances().await;
+        a.lock().insert("m".to_string(), Measurement::fixture("m"));
+        a.flushed().await;
+        assert_eq!(status(&b, "m"), None);
+        assert_eq!(b.sync().await, Ok(1));
+        assert_eq!(status(&b, "m"), Some(ProofStatus::Pending));
+
+        let pending = a.store().get("m").await.unwrap().unwrap();
+        a.transition(pending, ProofStatus::Processing, None, Source::Worker(1)).await.unwrap();
+        a.flushed().await;
+        assert_eq!(status(&b, "m"), Some(ProofStatus::Pending));
+        assert_eq!(b.sync().await, Ok(1));
+        assert_eq!(status(&b, "m"), Some(ProofStatus::Processing));
+        assert_eq!(b.lock()["m"].history.len(), 1);
+
+        // Nothing changed since
+        assert_eq!(b.sync().await, Ok(0));
+
+        a.lock().remove("m");
+        a.flushed().await;
+        assert_eq!(b.sync().await, Ok(1));
+        assert_eq!(status(&b, "m"), None);
+    }
+
+    #[tokio::test]
+    async fn changes_not_yet_written_survive_a_sync() {
+        let (a, b) = instances().await;
+        a.lock().insert("m".to_string(), Measurement::fixture("m"));
+        b.lock().insert("n".to_string(), Measurement::fixture("n"));
+        // The writer of b has not run yet, as nothing yielded since
+        b.lock().get_mut("n").unwrap().metadata.tags = vec!["hotdog".to_string()];
+        assert_eq!(b.sync().await, Ok(0));
+        assert_eq!(b.lock()["n"].metadata.tags, ["hotdog"]);
+
+        b.flushed().await;
+        a.flushed().await;
+        assert_eq!(b.sync().await, Ok(1));
+        assert_eq!(b.lock()["n"].metadata.tags, ["hotdog"]);
+        assert_eq!(status(&b, "m"), Some(ProofStatus::Pending));
+        assert_eq!(a.sync().await, Ok(1));
+        assert_eq!(a.lock()["n"].metadata.tags, ["hotdog"]);
+    }
+
+    #[tokio::test]
+    async fn instances_changing_different_fields_keep_both_changes() {
+        let (a, b) = instances().await;
+        a.lock().insert("m".to_string(), Measurement::fixture("m"));
+        a.flushed().await;
+        b.sync().await.unwrap();
+
+        let deadline = Utc::now();
+        a.lock().get_mut("m").unwrap().metadata.tags = vec!["hotdog".to_string()];
+        b.lock().get_mut("m").unwrap().deadline = Some(deadline);
+        // Taking a measurement without changing it writes nothing
+        b.lock().get_mut("m");
+        a.flushed().await;
+        b.flushed().await;
+
+        let stored = a.store().get("m").await.unwrap().unwrap();
+        assert_eq!(stored.metadata.tags, ["hotdog"]);
+        assert_eq!(stored.deadline, Some(deadline));
+        assert_eq!(a.sync().await, Ok(1));
+        assert_eq!(b.sync().await, Ok(1));
+        assert_eq!(b.lock()["m"].metadata.tags, ["hotdog"]);
+    }
+
+    #[tokio::test]
+    async fn changes_skipped_for_an_unwritten_write_are_read_again() {
+        let (a, b) = instances().await;
+        a.lock().insert("m".to_string(), Measurement::fixture("m"));
+        a.flushed().await;
+        b.sync().await.unwrap();
+
+        let deadline = Utc::now();
+        a.lock().get_mut("m").unwrap().deadline = Some(deadline);
+        a.flushed().await;
+        // The writer of b does not run before the sync, as nothing yields
+        b.lock().get_mut("m").unwrap().metadata.tags = vec!["hotdog".to_string()];
+        // The change of a comes after the version b synced to, but b still
+        // has a write of its own to make
+        assert_eq!(b.sync().await, Ok(0));
+        assert_eq!(b.lock()["m"].deadline, None);
+
+        b.flushed().await;
+        assert_eq!(b.sync().await, Ok(1));
+        assert_eq!(b.lock()["m"].deadline, Some(deadline));
+        assert_eq!(b.lock()["m"].metadata.tags, ["hotdog"]);
+        assert_eq!(b.sync().await, Ok(0));
+    }
+
+    #[tokio::test]
+    async fn a_store_only_this_process_writes_needs_no_sync() {
+        let measurements = Measurements::load(
+            Arc::new(MemoryStore::default()),
+            CacheLimits::default(),
+            Clock::default(),
+        )
+        .await
+        .unwrap();
+        measurements.lock().insert("m".to_string(), Measurement::fixture("m"));
+        measurements.flushed().await;
+        assert!(!measurements.store().shared());
+        assert_eq!(measurements.sync().await, Ok(0));
+    }
+}
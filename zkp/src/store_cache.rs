@@ -0,0 +1,451 @@
+// Read-through cache of recently read measurements in front of the store.
+//
+// Status polls, proof workers and the history endpoint read a measurement
+// from the store every time, which with Postgres is a round trip to the
+// database per poll while clients poll their own measurement every second.
+// CachedStore wraps the configured store and, when it is shared, keeps up to
+// MEASUREMENT_CACHE_CAPACITY of the measurements it read, dropping the least
+// recently read one when full; 0 turns the cache off. The memory and sled
+// stores live in the process already, where a read costs about what a cache
+// hit would, so they are read directly.
+//
+// Every write goes through the wrapped store first and then drops the entry it
+// changed, so a read following a write of this process, such as the status
+// poll after a transition it triggered, never sees the old record. A read
+// racing a write is not cached: writes bump a generation, and a record read
+// under an older generation is returned without being kept. Listings are
+// never cached.
+//
+// A shared store is also written by other instances, whose writes this one
+// cannot see. Entries are only served for STATUS_COALESCE_WINDOW_MS after
+// they were read, the delay status reads already accept, so with a window of
+// 0 nothing is cached. Consistency checks, such as the store probe of
+// /admin/doctor, read the wrapped store directly.
+
+use futures_util::{FutureExt, future::BoxFuture};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    AttestationData, Failure, Measurement, ProofStatus,
+    config::Config,
+    store::{Changes, Fields, MeasurementStore},
+};
+
+// How many measurements of a shared store are cached and how long they stay
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheLimits {
+    pub capacity: usize,
+    pub shared_ttl: Duration,
+}
+
+impl CacheLimits {
+    pub fn from_config(config: &Config) -> Self {
+        CacheLimits {
+            capacity: config.measurement_cache_capacity,
+            shared_ttl: Duration::from_millis(config.status_coalesce_window_ms),
+        }
+    }
+}
+
+// Measurement cache figures for the admin stats endpoint
+#[derive(Serialize)]
+pub struct MeasurementCacheStats {
+    capacity: usize,
+    entries: usize,
+    // Reads answered from the cache, and those that went to the store
+    hits: u64,
+    misses: u64,
+    // Entries dropped to make room, and dropped because they were written
+    evictions: u64,
+    invalidations: u64,
+}
+
+struct Entry {
+    measurement: Measurement,
+    read_at: Instant,
+    // Position in the recency order
+    used: u64,
+}
+
+// Cached measurements by id, and their ids from least to most recently read
+#[derive(Default)]
+struct Entries {
+    by_id: HashMap<String, Entry>,
+    recency: BTreeMap<u64, String>,
+    clock: u64,
+    // Bumped by every write, so reads racing it are not cached
+    generation: u64,
+}
+
+pub struct CachedStore {
+    inner: Arc<dyn MeasurementStore>,
+    capacity: usize,
+    // How long an entry is served
+    ttl: Duration,
+    entries: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    invalidations: AtomicU64,
+}
+
+impl CachedStore {
+    pub fn new(inner: Arc<dyn MeasurementStore>, limits: CacheLimits) -> Self {
+        let shared = inner.shared();
+        CachedStore {
+            capacity: if shared && !limits.shared_ttl.is_zero() { limits.capacity } else { 0 },
+            ttl: limits.shared_ttl,
+            inner,
+            entries: Mutex::default(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            invalidations: AtomicU64::new(0),
+        }
+    }
+
+    // The wrapped store, for reads that must not be answered from the cache
+    pub fn uncached(&self) -> &dyn MeasurementStore {
+        self.inner.as_ref()
+    }
+
+    pub fn stats(&self) -> MeasurementCacheStats {
+        MeasurementCacheStats {
+            capacity: self.capacity,
+            entries: self.entries.lock().unwrap().by_id.len(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            invalidations: self.invalidations.load(Ordering::Relaxed),
+        }
+    }
+
+    // A cached measurement still fresh enough to serve, marked as just read
+    fn cached(&self, id: &str) -> Option<Measurement> {
+        let mut entries = self.entries.lock().unwrap();
+        let Entries { by_id, recency, clock, .. } = &mut *entries;
+        let entry = by_id.get_mut(id)?;
+        if entry.read_at.elapsed() >= self.ttl {
+            recency.remove(&entry.used);
+            by_id.remove(id);
+            return None;
+        }
+        *clock += 1;
+        recency.remove(&entry.used);
+        recency.insert(*clock, id.to_string());
+        entry.used = *clock;
+        Some(entry.measurement.clone())
+    }
+
+    // Keep a measurement read under the given generation, unless a write
+    // happened since
+    fn keep(&self, measurement: &Measurement, generation: u64, read_at: Instant) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.generation != generation {
+            return;
+        }
+        let Entries { by_id, recency, clock, .. } = &mut *entries;
+        *clock += 1;
+        recency.insert(*clock, measurement.id.clone());
+        let entry = Entry { measurement: measurement.clone(), read_at, used: *clock };
+        if let Some(replaced) = by_id.insert(measurement.id.clone(), entry) {
+            recency.remove(&replaced.used);
+        }
+        while by_id.len() > self.capacity {
+            let Some((_, id)) = recency.pop_first() else {
+                break;
+            };
+            by_id.remove(&id);
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Drop the entry of a measurement that was written
+    fn invalidate(&self, id: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.generation += 1;
+        if let Some(entry) = entries.by_id.remove(id) {
+            entries.recency.remove(&entry.used);
+            self.invalidations.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl MeasurementStore for CachedStore {
+    fn insert<'a>(&'a self, measurement: &'a Measurement) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            let result = self.inner.insert(measurement).await;
+            self.invalidate(&measurement.id);
+            result
+        }
+        .boxed()
+    }
+
+    fn get<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Option<Measurement>, String>> {
+        async move {
+            if self.capacity == 0 {
+                return self.inner.get(id).await;
+            }
+            if let Some(measurement) = self.cached(id) {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(Some(measurement));
+            }
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            let generation = self.entries.lock().unwrap().generation;
+            let read_at = Instant::now();
+            let read = self.inner.get(id).await?;
+            if let Some(measurement) = &read {
+                self.keep(measurement, generation, read_at);
+            }
+            Ok(read)
+        }
+        .boxed()
+    }
+
+    fn update_status<'a>(
+        &'a self,
+        id: &'a str,
+        from: ProofStatus,
+        to: ProofStatus,
+        failure: Option<Failure>,
+    ) -> BoxFuture<'a, Result<bool, String>> {
+        async move {
+            let result = self.inner.update_status(id, from, to, failure).await;
+            self.invalidate(id);
+            result
+        }
+        .boxed()
+    }
+
+    fn set_attestation<'a>(
+        &'a self,
+        id: &'a str,
+        attestation: &'a AttestationData,
+    ) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            let result = self.inner.set_attestation(id, attestation).await;
+            self.invalidate(id);
+            result
+        }
+        .boxed()
+    }
+
+    fn list(&self) -> BoxFuture<'_, Result<Vec<Measurement>, String>> {
+        self.inner.list()
+    }
+
+    // Listings read every measurement once, which would only push the polled
+    // ones out of the cache
+    fn get_many<'a>(
+        &'a self,
+        ids: &'a [String],
+    ) -> BoxFuture<'a, Result<Vec<Measurement>, String>> {
+        self.inner.get_many(ids)
+    }
+
+    fn save<'a>(&'a self, measurement: &'a Measurement) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            let result = self.inner.save(measurement).await;
+            self.invalidate(&measurement.id);
+            result
+        }
+        .boxed()
+    }
+
+    fn patch<'a>(&'a self, id: &'a str, fields: &'a Fields) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            let result = self.inner.patch(id, fields).await;
+            self.invalidate(id);
+            result
+        }
+        .boxed()
+    }
+
+    fn remove<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            let result = self.inner.remove(id).await;
+            self.invalidate(id);
+            result
+        }
+        .boxed()
+    }
+
+    fn shared(&self) -> bool {
+        self.inner.shared()
+    }
+
+    fn changes(&self, version: u64) -> BoxFuture<'_, Result<Changes, String>> {
+        self.inner.changes(version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::Clock,
+        history::Source,
+        store::{Measurements, MemoryStore, SharedMemoryStore},
+    };
+
+    // A cached store only this instance writes to, though shared
+    fn cached_memory(capacity: usize) -> CachedStore {
+        let limits = CacheLimits { capacity, shared_ttl: Duration::from_secs(60) };
+        CachedStore::new(Arc::new(SharedMemoryStore::default()), limits)
+    }
+
+    async fn status(store: &dyn MeasurementStore, id: &str) -> Option<ProofStatus> {
+        store.get(id).await.unwrap().map(|m| m.status)
+    }
+
+    #[tokio::test]
+    async fn reads_are_answered_from_the_cache() {
+        let store = cached_memory(10);
+        store.insert(&Measurement::fixture("a")).await.unwrap();
+        assert_eq!(status(&store, "a").await, Some(ProofStatus::Pending));
+        assert_eq!(status(&store, "a").await, Some(ProofStatus::Pending));
+        assert_eq!(status(&store, "unknown").await, None);
+        let stats = store.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 2, 1));
+    }
+
+    #[tokio::test]
+    async fn reads_see_the_writes_before_them() {
+        let store = cached_memory(10);
+        store.insert(&Measurement::fixture("a")).await.unwrap();
+        assert_eq!(status(&store, "a").await, Some(ProofStatus::Pending));
+
+        store
+            .update_status("a", ProofStatus::Pending, ProofStatus::Processing, None)
+            .await
+            .unwrap();
+        assert_eq!(status(&store, "a").await, Some(ProofStatus::Processing));
+
+        let mut changed = store.get("a").await.unwrap().unwrap();
+        changed.metadata.tags = vec!["hotdog".to_string()];
+        store.save(&changed).await.unwrap();
+        assert_eq!(store.get("a").await.unwrap().unwrap().metadata.tags, ["hotdog"]);
+
+        let attestation: AttestationData = serde_json::from_value(serde_json::json!({
+            "attestationId": 7,
+            "merklePath": [],
+            "leafCount": 1,
+            "index": 0,
+        }))
+        .unwrap();
+        store.set_attestation("a", &attestation).await.unwrap();
+        assert!(store.get("a").await.unwrap().unwrap().attestation.is_some());
+
+        store.remove("a").await.unwrap();
+        assert_eq!(status(&store, "a").await, None);
+        assert_eq!(store.stats().invalidations, 4);
+    }
+
+    #[tokio::test]
+    async fn transitions_are_read_back_by_the_instance_making_them() {
+        let limits = CacheLimits { capacity: 10, shared_ttl: Duration::from_secs(60) };
+        let measurements =
+            Measurements::load(Arc::new(SharedMemoryStore::default()), limits, Clock::default())
+                .await
+                .unwrap();
+        measurements.lock().insert("a".to_string(), Measurement::fixture("a"));
+        measurements.flushed().await;
+        assert_eq!(status(measurements.store(), "a").await, Some(ProofStatus::Pending));
+
+        // A transition made by a worker, as the poller that triggered it reads it
+        let pending = measurements.store().get("a").await.unwrap().unwrap();
+        measurements
+            .transition(pending, ProofStatus::Processing, None, Source::Worker(1))
+            .await
+            .unwrap();
+        assert_eq!(status(measurements.store(), "a").await, Some(ProofStatus::Processing));
+
+        // A change made in memory, once written back as status reads wait for
+        measurements.lock().get_mut("a").unwrap().status = ProofStatus::Completed;
+        measurements.flushed().await;
+        assert_eq!(status(measurements.store(), "a").await, Some(ProofStatus::Completed));
+    }
+
+    #[tokio::test]
+    async fn least_recently_read_measurement_is_evicted() {
+        let store = cached_memory(2);
+        for id in ["a", "b", "c"] {
+            store.insert(&Measurement::fixture(id)).await.unwrap();
+        }
+        for id in ["a", "b", "a", "c"] {
+            store.get(id).await.unwrap();
+        }
+        let stats = store.stats();
+        assert_eq!((stats.entries, stats.evictions), (2, 1));
+
+        store.get("a").await.unwrap();
+        store.get("b").await.unwrap();
+        assert_eq!((store.stats().hits, store.stats().misses), (2, 4));
+    }
+
+    #[tokio::test]
+    async fn read_racing_a_write_is_not_kept() {
+        let store = cached_memory(10);
+        store.insert(&Measurement::fixture("a")).await.unwrap();
+        let generation = store.entries.lock().unwrap().generation;
+        let read = store.inner.get("a").await.unwrap().unwrap();
+        store
+            .update_status("a", ProofStatus::Pending, ProofStatus::Processing, None)
+            .await
+            .unwrap();
+        store.keep(&read, generation, Instant::now());
+        assert_eq!(status(&store, "a").await, Some(ProofStatus::Processing));
+    }
+
+    #[tokio::test]
+    async fn local_stores_are_read_directly() {
+        let limits = CacheLimits { capacity: 10, shared_ttl: Duration::from_secs(60) };
+        let store = CachedStore::new(Arc::new(MemoryStore::default()), limits);
+        store.insert(&Measurement::fixture("a")).await.unwrap();
+        store.get("a").await.unwrap();
+        store.get("a").await.unwrap();
+        let stats = store.stats();
+        assert_eq!((stats.capacity, stats.hits, stats.entries), (0, 0, 0));
+    }
+
+    #[tokio::test]
+    async fn zero_capacity_reads_the_store_every_time() {
+        let store = cached_memory(0);
+        store.insert(&Measurement::fixture("a")).await.unwrap();
+        store.get("a").await.unwrap();
+        store.get("a").await.unwrap();
+        let stats = store.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (0, 0, 0));
+    }
+
+    #[tokio::test]
+    async fn entries_of_a_shared_store_expire_after_the_window() {
+        let shared = Arc::new(SharedMemoryStore::default());
+        let window = Duration::from_millis(100);
+        let store =
+            CachedStore::new(shared.clone(), CacheLimits { capacity: 10, shared_ttl: window });
+        shared.insert(&Measurement::fixture("a")).await.unwrap();
+        assert_eq!(status(&store, "a").await, Some(ProofStatus::Pending));
+
+        // Another instance's write is only seen once the entry expired
+        shared
+            .update_status("a", ProofStatus::Pending, ProofStatus::Processing, None)
+            .await
+            .unwrap();
+        assert_eq!(status(&store, "a").await, Some(ProofStatus::Pending));
+        tokio::time::sleep(window).await;
+        assert_eq!(status(&store, "a").await, Some(ProofStatus::Processing));
+
+        let uncached =
+            CachedStore::new(shared, CacheLimits { capacity: 10, shared_ttl: Duration::ZERO });
+        assert_eq!(uncached.stats().capacity, 0);
+    }
+}
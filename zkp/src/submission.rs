@@ -0,0 +1,289 @@
+// Native construction of the payload submitted to zkVerify.
+//
+// The proof is still submitted by dist/verify_client.js, but the submitter is
+// being ported to Rust. To prove the port builds byte-identical submissions,
+// SUBMISSION_PAYLOAD_DEBUG writes the payload the native code would send to
+// proofs/{id}/submission_payload.json. With the submission-compare feature,
+// an admin endpoint additionally runs the node client in capture-only mode and
+// diffs both payloads field by field.
+//
+// Encoding follows the zkVerify groth16 verifier for snarkjs proofs on BN254:
+// every field element is a 0x-prefixed 32-byte big-endian hex string, G1
+// points are x || y, G2 points are x.c0 || x.c1 || y.c0 || y.c1, and the
+// projective z coordinate produced by snarkjs is dropped.
+
+use serde::Serialize;
+use serde_json::Value;
+use std::fs;
+
+use crate::{artifacts::Artifact, circuit::Circuit};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubmissionPayload {
+    pub proof: FormattedProof,
+    pub public_signals: Vec<String>,
+    pub vk: FormattedVk,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FormattedProof {
+    pub curve: &'static str,
+    pub proof: ProofPoints,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProofPoints {
+    pub a: String,
+    pub b: String,
+    pub c: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FormattedVk {
+    pub curve: &'static str,
+    pub alpha_g1: String,
+    pub beta_g2: String,
+    pub gamma_g2: String,
+    pub delta_g2: String,
+    pub gamma_abc_g1: Vec<String>,
+}
+
+// Build the payload for a measurement from its proof files and the circuit's key
+pub fn build(id: &str, circuit: &Circuit) -> Result<SubmissionPayload, String> {
+    let proof = read_json(&Artifact::Proof.path(id))?;
+    let public_signals = read_json(&Artifact::PublicSignals.path(id))?;
+    let vk = read_json(circuit.vkey_path)?;
+
+    let public_signals = public_signals
+        .as_array()
+        .ok_or("public.json must be an array")?
+        .iter()
+        .map(|signal| field_to_hex(signal.as_str().ok_or("Public signals must be strings")?))
+        .collect::<Result<Vec<_>, _>>()?;
+    if public_signals.len() != circuit.spec.public_count() {
+        return Err(format!(
+            "public.json holds {} signals, circuit {} declares {}",
+            public_signals.len(),
+            circuit.version,
+            circuit.spec.public_count()
+        ));
+    }
+
+    Ok(SubmissionPayload {
+        proof: FormattedProof {
+            curve: "Bn254",
+            proof: ProofPoints {
+                a: g1_point(&proof["pi_a"])?,
+                b: g2_point(&proof["pi_b"])?,
+                c: g1_point(&proof["pi_c"])?,
+            },
+        },
+        public_signals,
+        vk: FormattedVk {
+            curve: "Bn254",
+            alpha_g1: g1_point(&vk["vk_alpha_1"])?,
+            beta_g2: g2_point(&vk["vk_beta_2"])?,
+            gamma_g2: g2_point(&vk["vk_gamma_2"])?,
+            delta_g2: g2_point(&vk["vk_delta_2"])?,
+            gamma_abc_g1: vk["IC"]
+                .as_array()
+                .ok_or("Verification key is missing IC")?
+                .iter()
+                .map(g1_point)
+                .collect::<Result<Vec<_>, _>>()?,
+        },
+    })
+}
+
+// Build the payload and write it to proofs/{id}/submission_payload.json
+pub fn write(id: &str, circuit: &Circuit) -> Result<(), String> {
+    let payload = build(id, circuit)?;
+    let content = serde_json::to_string_pretty(&payload)
+        .map_err(|e| format!("Failed to serialize submission payload: {}", e))?;
+    fs::write(Artifact::SubmissionPayload.path(id), content)
+        .map_err(|e| format!("Failed to write submission payload: {}", e))
+}
+
+fn read_json(path: &str) -> Result<Value, String> {
+    let content =
+        fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    serde_json::from_str(&content).map_err(|e| format!("Failed to parse {}: {}", path, e))
+}
+
+// Affine G1 point from snarkjs' [x, y, z] representation
+fn g1_point(point: &Value) -> Result<String, String> {
+    let x = coordinate(&point[0])?;
+    let y = coordinate(&point[1])?;
+    Ok(format!("{}{}", x, &y[2..]))
+}
+
+// Affine G2 point from snarkjs' [[x.c0, x.c1], [y.c0, y.c1], z] representation
+fn g2_point(point: &Value) -> Result<String, String> {
+    let mut encoded = String::from("0x");
+    for coordinate_pair in [&point[0], &point[1]] {
+        for component in [&coordinate_pair[0], &coordinate_pair[1]] {
+            encoded.push_str(&coordinate(component)?[2..]);
+        }
+    }
+    Ok(encoded)
+}
+
+fn coordinate(value: &Value) -> Result<String, String> {
+    field_to_hex(value.as_str().ok_or("Curve point coordinates must be decimal strings")?)
+}
+
+// Encode a decimal field element as 0x-prefixed 32-byte big-endian hex
+fn field_to_hex(decimal: &str) -> Result<String, String> {
+    if decimal.is_empty() || !decimal.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!("Invalid field element '{}'", decimal));
+    }
+
+    let mut bytes = [0u8; 32];
+    for digit in decimal.bytes() {
+        let mut carry = (digit - b'0') as u32;
+        for byte in bytes.iter_mut().rev() {
+            let value = *byte as u32 * 10 + carry;
+            *byte = value as u8;
+            carry = value >> 8;
+        }
+        if carry != 0 {
+            return Err(format!("Field element '{}' does not fit in 32 bytes", decimal));
+        }
+    }
+
+    Ok(format!("0x{}", hex::encode(bytes)))
+}
+
+#[cfg(feature = "submission-compare")]
+pub use compare::compare;
+
+// Comparison of the native payload with the one built by the node client
+#[cfg(feature = "submission-compare")]
+mod compare {
+    use axum::{
+        extract::{Path, State},
+        http::StatusCode,
+        response::Json,
+    };
+    use serde::Serialize;
+    use serde_json::Value;
+    use std::sync::Arc;
+
+    use super::{read_json, write};
+    use crate::{
+        AppState, access, artifacts,
+        auth::{Authorized, Operator},
+        circuit,
+        toolchain::Toolchain,
+    };
+
+    #[derive(Serialize)]
+    pub struct ComparisonReport {
+        id: String,
+        identical: bool,
+        differences: Vec<Difference>,
+        toolchain: ToolchainComparison,
+    }
+
+    // Toolchain the proof was generated with against the one that formatted it now
+    #[derive(Serialize)]
+    struct ToolchainComparison {
+        proved: Option<Toolchain>,
+        current: Toolchain,
+        // Differences may stem from the toolchain rather than the payload code
+        changed: bool,
+    }
+
+    // One field whose value differs, identified by its JSON path
+    #[derive(Serialize)]
+    struct Difference {
+        path: String,
+        native: Option<Value>,
+        node: Option<Value>,
+    }
+
+    // Handler building both payloads for a proved measurement and diffing them
+    pub async fn compare(
+        State(state): State<Arc<AppState>>,
+        Authorized(caller, _): Authorized<Operator>,
+        Path(id): Path<String>,
+    ) -> Result<Json<ComparisonReport>, (StatusCode, String)> {
+        let measurement = state.measurements.lock().get(&id).cloned();
+        let measurement = access::visible(measurement.as_ref(), &caller, &id)?;
+        let proved = measurement.versions.proved.as_ref().and_then(|v| v.toolchain.clone());
+        let circuit = circuit::resolve(measurement.circuit.as_deref());
+
+        let native_path = artifacts::Artifact::SubmissionPayload.path(&id);
+        let native = write(&id, &circuit)
+            .and_then(|()| read_json(&native_path))
+            .map_err(|e| (StatusCode::CONFLICT, e))?;
+
+        // Capture mode formats the payload without connecting to zkVerify
+        let capture_path = format!("{}/submission_payload.node.json", artifacts::proof_dir(&id));
+        let output = tokio::process::Command::new("node")
+            .args([
+                "dist/verify_client.js",
+                &artifacts::storage_key(&id),
+                "--capture",
+                &capture_path,
+            ])
+            .env("ZK_VERIFY_VKEY_PATH", circuit.vkey_path)
+            .output()
+            .await
+            .map_err(|e| {
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to run node client: {}", e))
+            })?;
+        if !output.status.success() {
+            return Err((
+                StatusCode::BAD_GATEWAY,
+                format!(
+                    "Node client capture failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            ));
+        }
+        let node = read_json(&capture_path).map_err(|e| (StatusCode::BAD_GATEWAY, e))?;
+
+        let mut differences = Vec::new();
+        diff("", Some(&native), Some(&node), &mut differences);
+        let current = state.toolchain.get();
+        let toolchain = ToolchainComparison {
+            changed: proved.as_ref().is_some_and(|proved| *proved != current),
+            proved,
+            current,
+        };
+        if toolchain.changed {
+            println!("Toolchain of measurement {} changed since it was proved", id);
+        }
+        Ok(Json(ComparisonReport { id, identical: differences.is_empty(), differences, toolchain }))
+    }
+
+    // Recursively collect the leaves that differ between two JSON values
+    fn diff(path: &str, native: Option<&Value>, node: Option<&Value>, out: &mut Vec<Difference>) {
+        match (native, node) {
+            (Some(Value::Object(a)), Some(Value::Object(b))) => {
+                let mut keys: Vec<&String> = a.keys().chain(b.keys()).collect();
+                keys.sort();
+                keys.dedup();
+                for key in keys {
+                    let child =
+                        if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                    diff(&child, a.get(key), b.get(key), out);
+                }
+            }
+            (Some(Value::Array(a)), Some(Value::Array(b))) => {
+                for index in 0..a.len().max(b.len()) {
+                    diff(&format!("{}[{}]", path, index), a.get(index), b.get(index), out);
+                }
+            }
+            (a, b) if a == b => {}
+            (a, b) => out.push(Difference {
+                path: path.to_string(),
+                native: a.cloned(),
+                node: b.cloned(),
+            }),
+        }
+    }
+}
@@ -0,0 +1,222 @@
+// Daily activity summaries for operators.
+//
+// Shortly after midnight (SUMMARY_TIME, UTC) the previous day's activity is
+// rolled up into one summary: measurements created, how many of them
+// completed or failed, the slowest proof and how much disk the uploads and
+// proofs take. Summaries are kept in SUMMARIES_PATH keyed by date, so running
+// the roll-up again for a day replaces its summary instead of adding another.
+// Days without any activity still get a summary with zero counts.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Json,
+};
+use chrono::{Days, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::Path as FsPath,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    AppState, Measurement, ProofStatus,
+    auth::{Authorized, Operator},
+};
+
+const DEFAULT_DAYS: u64 = 30;
+const MAX_DAYS: u64 = 366;
+// Directories whose size is reported as disk usage
+const DATA_DIRS: [&str; 2] = ["uploads", "proofs"];
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DailySummary {
+    date: NaiveDate,
+    // Measurements created that day, and what has become of them
+    measurements: usize,
+    completed: usize,
+    failed: usize,
+    // Share of finished measurements that completed, absent if none finished
+    success_rate: Option<f64>,
+    // Longest proof generation that finished that day, while the server was running
+    slowest_proof_seconds: Option<f64>,
+    // Bytes in uploads/ and proofs/ when the summary was made
+    disk_bytes: u64,
+    // Change in disk_bytes since the previous day's summary
+    disk_growth_bytes: Option<i64>,
+    generated_at: chrono::DateTime<Utc>,
+}
+
+pub struct Summaries {
+    path: String,
+    // Serializes read-modify-write cycles of the summaries file
+    file_lock: Mutex<()>,
+    // Slowest proof duration per day, in seconds
+    slowest_proofs: Mutex<BTreeMap<NaiveDate, f64>>,
+}
+
+impl Summaries {
+    pub fn new(path: String) -> Self {
+        Summaries { path, file_lock: Mutex::new(()), slowest_proofs: Mutex::default() }
+    }
+
+    // Note the duration of a proof that just finished
+    pub fn record_proof(&self, duration: Duration) {
+        let mut slowest = self.slowest_proofs.lock().unwrap();
+        let seconds = slowest.entry(Utc::now().date_naive()).or_insert(0.0);
+        *seconds = seconds.max(duration.as_secs_f64());
+    }
+
+    fn load(&self) -> io::Result<BTreeMap<NaiveDate, DailySummary>> {
+        match fs::read_to_string(&self.path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Aggregate a day's activity and store it, replacing any earlier summary
+    pub fn summarize(&self, state: &AppState, date: NaiveDate) -> io::Result<DailySummary> {
+        let DayCounts { measurements, completed, failed } = count_day(state, date, |_| {});
+        let finished = completed + failed;
+        let slowest_proof_seconds = self.slowest_proofs.lock().unwrap().get(&date).copied();
+        let disk_bytes = DATA_DIRS.iter().map(|dir| dir_size(FsPath::new(dir))).sum();
+
+        let _guard = self.file_lock.lock().unwrap();
+        let mut summaries = self.load()?;
+        let previous = date.checked_sub_days(Days::new(1)).and_then(|d| summaries.get(&d));
+        let summary = DailySummary {
+            date,
+            measurements,
+            completed,
+            failed,
+            success_rate: (finished > 0).then(|| completed as f64 / finished as f64),
+            slowest_proof_seconds,
+            disk_bytes,
+            disk_growth_bytes: previous.map(|p| disk_bytes as i64 - p.disk_bytes as i64),
+            generated_at: Utc::now(),
+        };
+        summaries.insert(date, summary.clone());
+
+        // Write a new file and swap it in so a crash never leaves a partial one
+        let content = serde_json::to_string_pretty(&summaries)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let temp_path = format!("{}.tmp", self.path);
+        fs::write(&temp_path, content)?;
+        fs::rename(&temp_path, &self.path)?;
+        Ok(summary)
+    }
+}
+
+// Measurements created on a day, and how many of them completed or failed
+pub struct DayCounts {
+    pub measurements: usize,
+    pub completed: usize,
+    pub failed: usize,
+}
+
+// Count the measurements created on a day, passing each of them to `each`
+pub fn count_day(
+    state: &AppState,
+    date: NaiveDate,
+    mut each: impl FnMut(&Measurement),
+) -> DayCounts {
+    let mut counts = DayCounts { measurements: 0, completed: 0, failed: 0 };
+    for m in state.measurements.lock().values() {
+        if m.created_at.is_none_or(|created| created.date_naive() != date) {
+            continue;
+        }
+        counts.measurements += 1;
+        match m.status {
+            ProofStatus::Completed => counts.completed += 1,
+            ProofStatus::Failed => counts.failed += 1,
+            _ => {}
+        }
+        each(m);
+    }
+    counts
+}
+
+// Total size of the files below a directory
+fn dir_size(path: &FsPath) -> u64 {
+    let Ok(entries) = fs::read_dir(path) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(meta) if meta.is_dir() => dir_size(&entry.path()),
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+// Summarize the previous day every day at the configured time
+pub async fn scheduler(state: Arc<AppState>) {
+    loop {
+        let now = Utc::now();
+        let mut next = now.date_naive().and_time(state.config.summary_time).and_utc();
+        if next <= now {
+            next += chrono::Duration::days(1);
+        }
+        tokio::time::sleep((next - now).to_std().unwrap_or_default()).await;
+
+        let Some(date) = next.date_naive().checked_sub_days(Days::new(1)) else {
+            continue;
+        };
+        match state.summaries.summarize(&state, date) {
+            Ok(summary) => println!(
+                "Daily summary for {}: {} measurements, {} completed, {} failed",
+                date, summary.measurements, summary.completed, summary.failed
+            ),
+            Err(e) => println!("Failed to write daily summary for {}: {}", date, e),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SummaryQuery {
+    days: Option<u64>,
+}
+
+// Handler returning the summaries of the last `days` days, newest first
+pub async fn list(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Operator>,
+    Query(query): Query<SummaryQuery>,
+) -> Result<Json<Vec<DailySummary>>, (StatusCode, String)> {
+    caller.require_global()?;
+    let days = query.days.unwrap_or(DEFAULT_DAYS).clamp(1, MAX_DAYS);
+    let today = Utc::now().date_naive();
+    let since = today.checked_sub_days(Days::new(days)).unwrap_or(NaiveDate::MIN);
+
+    let summaries = {
+        let _guard = state.summaries.file_lock.lock().unwrap();
+        state.summaries.load().map_err(|e| {
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read summaries: {}", e))
+        })?
+    };
+    Ok(Json(summaries.into_values().rev().filter(|s| s.date >= since).collect()))
+}
+
+// Handler (re)generating the summary of one day
+pub async fn regenerate(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Operator>,
+    Path(date): Path<NaiveDate>,
+) -> Result<Json<DailySummary>, (StatusCode, String)> {
+    caller.require_global()?;
+    if date > Utc::now().date_naive() {
+        return Err((StatusCode::BAD_REQUEST, format!("{} is in the future", date)));
+    }
+    state
+        .summaries
+        .summarize(&state, date)
+        .map(Json)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write summary: {}", e)))
+}
@@ -0,0 +1,546 @@
+// Proving in a child process.
+//
+// By default proofs are generated by tasks of the server process. With
+// --supervise the server starts itself again with --role worker, and that
+// child process generates every witness and proof, so a prover leaking memory
+// or killed by the OOM killer no longer takes the API down with it. The queue,
+// the store and the submission to zkVerify stay in the server.
+//
+// The child connects to a Unix socket the server listens on at
+// PROVER_SOCKET_PATH. Both sides send JSON messages, each preceded by its
+// length as a 4-byte big-endian integer: the server hands over a ProveJob
+// once the measurement's input.json is written, numbered so the child can
+// answer the jobs it runs concurrently in any order, and the child answers
+// with the time proving took or the failure. When the server stops waiting
+// for a job, because its measurement was cancelled, it withdraws the job by
+// its number, and the child drops it, killing the tools it started.
+//
+// When the child exits, the jobs it held fail as Interrupted, like work cut
+// short by a restart, and it is started again after a backoff doubling from 1
+// second up to a minute, back at 1 second once a child stayed up for a
+// minute. Jobs handed over meanwhile wait for the next child. /ready reports
+// the child and is unready while none is connected. On SIGTERM or Ctrl-C the
+// server stops the child before exiting, and a child whose server is gone
+// exits as soon as the socket closes. The child runs in a process group of its
+// own that is killed with it, so no witness generator or prover it started
+// outlives it.
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::{
+    collections::HashMap,
+    io,
+    str::FromStr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    net::{UnixListener, UnixStream},
+    process::{Child, Command},
+    sync::{Notify, mpsc, oneshot, watch},
+    task::AbortHandle,
+};
+
+use crate::{
+    Point3D, artifacts,
+    config::Config,
+    native_prover::NativeProver,
+    pipeline::{PipelineError, Stage},
+    proving_keys::ProvingKeys,
+};
+
+// Largest message either side accepts
+const MAX_MESSAGE_BYTES: usize = 1024 * 1024;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+// Uptime after which a child counts as healthy and the backoff starts over
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+// Time a started child has to connect
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessRole {
+    Server,
+    // The prover child of a supervising server
+    Worker,
+}
+
+impl FromStr for ProcessRole {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "server" => Ok(ProcessRole::Server),
+            "worker" => Ok(ProcessRole::Worker),
+            _ => Err(format!("unknown role {}, expected server or worker", s)),
+        }
+    }
+}
+
+// Everything the prover needs to prove a measurement whose input.json is written
+#[derive(Serialize, Deserialize)]
+pub struct ProveJob {
+    pub id: String,
+    pub tenant: Option<String>,
+    pub circuit: Option<String>,
+    pub start_point: Point3D,
+    pub end_point: Point3D,
+    // Every point of a polyline of more than two points
+    #[serde(default)]
+    pub points: Vec<Point3D>,
+    // Where an angle measurement bends
+    #[serde(default)]
+    pub mid_point: Option<Point3D>,
+    // Minimum length a threshold measurement proves
+    #[serde(default)]
+    pub min_length_cm: Option<f64>,
+    pub step_timeout_secs: u64,
+    // Time left until the client's deadline when the job was handed over
+    pub deadline_in_ms: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Request {
+    // Boxed to keep withdrawals small; serialized the same
+    Prove { job: u64, prove: Box<ProveJob> },
+    Withdraw { job: u64 },
+}
+
+#[derive(Serialize, Deserialize)]
+struct JobFailure {
+    message: String,
+    // The deadline passed before the proof was generated
+    cancelled: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Outcome {
+    job: u64,
+    // Milliseconds proving took
+    result: Result<u64, JobFailure>,
+}
+
+// A message with its length prefix
+fn encode(message: &impl Serialize) -> Result<Vec<u8>, String> {
+    let body = serde_json::to_vec(message).map_err(|e| e.to_string())?;
+    let mut frame = (body.len() as u32).to_be_bytes().to_vec();
+    frame.extend(body);
+    Ok(frame)
+}
+
+// The next message, None once the other side closed the socket
+async fn read_message<T: DeserializeOwned>(
+    reader: &mut (impl AsyncRead + Unpin),
+) -> io::Result<Option<T>> {
+    let mut length = [0; 4];
+    match reader.read_exact(&mut length).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let length = u32::from_be_bytes(length) as usize;
+    if length > MAX_MESSAGE_BYTES {
+        return Err(io::Error::other(format!("message of {} bytes is too large", length)));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await?;
+    serde_json::from_slice(&body).map(Some).map_err(io::Error::other)
+}
+
+// The prover child as reported by /ready
+#[derive(Serialize, Clone, Default)]
+pub struct ProverState {
+    connected: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pid: Option<u32>,
+    restarts: u64,
+    // How the last child ended
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_exit: Option<String>,
+    // Jobs handed to the child and not answered yet
+    jobs: usize,
+}
+
+// The connected child, numbered so its jobs can be told from its successor's
+struct Connection {
+    generation: u64,
+    frames: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+#[derive(Default)]
+struct Jobs {
+    connection: Option<Connection>,
+    // Answer channels of the handed over jobs, with the generation holding them
+    waiting: HashMap<u64, (u64, oneshot::Sender<Result<u64, JobFailure>>)>,
+}
+
+// The server's side of the prover child
+pub struct ProverProcess {
+    jobs: Mutex<Jobs>,
+    next_job: AtomicU64,
+    // Woken when a child connects
+    connected: Notify,
+    state: Mutex<ProverState>,
+    stop: watch::Sender<bool>,
+    stopped: watch::Sender<bool>,
+}
+
+impl ProverProcess {
+    // Listen on the socket and start the child
+    pub fn start(config: &Config) -> Result<Arc<Self>, String> {
+        if config.read_only {
+            return Err("A read-only mirror proves nothing, so it cannot supervise a prover".into());
+        }
+        let path = &config.prover_socket_path;
+        // A socket left behind by a previous server would refuse the bind
+        match std::fs::remove_file(path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("Failed to remove the old socket {}: {}", path, e)),
+        }
+        let listener = UnixListener::bind(path)
+            .map_err(|e| format!("Failed to listen for the prover process on {}: {}", path, e))?;
+        println!("Proving in a child process connecting to {}", path);
+
+        let process = Arc::new(ProverProcess {
+            jobs: Mutex::new(Jobs::default()),
+            next_job: AtomicU64::new(1),
+            connected: Notify::new(),
+            state: Mutex::new(ProverState::default()),
+            stop: watch::Sender::new(false),
+            stopped: watch::Sender::new(false),
+        });
+        tokio::spawn(supervise(process.clone(), listener));
+        Ok(process)
+    }
+
+    // Hand a job to the child and wait for its outcome, returning how long
+    // proving took. The job is withdrawn when the returned future is dropped
+    // before the outcome arrived.
+    pub async fn prove(&self, job: ProveJob) -> Result<Duration, PipelineError> {
+        let id = job.id.clone();
+        let number = self.next_job.fetch_add(1, Ordering::Relaxed);
+        let frame = encode(&Request::Prove { job: number, prove: Box::new(job) })
+            .map_err(|message| PipelineError::ProverProcess { message, exited: false })?;
+        let (done, outcome) = oneshot::channel();
+        let mut done = Some(done);
+        loop {
+            let connected = self.connected.notified();
+            {
+                let mut jobs = self.jobs.lock().unwrap();
+                let generation = jobs
+                    .connection
+                    .as_ref()
+                    .filter(|connection| connection.frames.send(frame.clone()).is_ok())
+                    .map(|connection| connection.generation);
+                if let Some(generation) = generation
+                    && let Some(done) = done.take()
+                {
+                    jobs.waiting.insert(number, (generation, done));
+                    break;
+                }
+            }
+            println!("Measurement {} waits for the prover process", id);
+            connected.await;
+        }
+
+        let _handed_over = HandedOver { process: self, job: number };
+        match outcome.await {
+            Ok(Ok(ms)) => Ok(Duration::from_millis(ms)),
+            Ok(Err(failure)) if failure.cancelled => {
+                Err(PipelineError::Cancelled { stage: Stage::Prove })
+            }
+            Ok(Err(failure)) => {
+                Err(PipelineError::ProverProcess { message: failure.message, exited: false })
+            }
+            Err(_) => Err(PipelineError::ProverProcess {
+                message: "The prover process exited during proof generation".to_string(),
+                exited: true,
+            }),
+        }
+    }
+
+    // Whether a child is connected to take jobs
+    pub fn check(&self) -> Result<(), String> {
+        let state = self.state.lock().unwrap();
+        match (state.connected, &state.last_exit) {
+            (true, _) => Ok(()),
+            (false, Some(exit)) => {
+                Err(format!("prover process not connected, the last one {}", exit))
+            }
+            (false, None) => Err("prover process not connected yet".to_string()),
+        }
+    }
+
+    pub fn state(&self) -> ProverState {
+        let mut state = self.state.lock().unwrap().clone();
+        state.jobs = self.jobs.lock().unwrap().waiting.len();
+        state
+    }
+
+    // Stop the child and wait until it exited
+    pub async fn shutdown(&self) {
+        println!("Stopping the prover process");
+        self.stop.send_replace(true);
+        let _ = self.stopped.subscribe().wait_for(|stopped| *stopped).await;
+    }
+
+    fn connect(&self, generation: u64, frames: mpsc::UnboundedSender<Vec<u8>>) {
+        self.jobs.lock().unwrap().connection = Some(Connection { generation, frames });
+        self.state.lock().unwrap().connected = true;
+        self.connected.notify_waiters();
+    }
+
+    // Forget the child, failing the jobs it held
+    fn disconnect(&self, generation: u64) {
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.connection = None;
+        jobs.waiting.retain(|_, (held_by, _)| *held_by != generation);
+        let mut state = self.state.lock().unwrap();
+        state.connected = false;
+        state.pid = None;
+    }
+
+    fn finish(&self, outcome: Outcome) {
+        if let Some((_, done)) = self.jobs.lock().unwrap().waiting.remove(&outcome.job) {
+            let _ = done.send(outcome.result);
+        }
+    }
+}
+
+// A job handed to the child, withdrawn when dropped while the child still
+// holds it
+struct HandedOver<'a> {
+    process: &'a ProverProcess,
+    job: u64,
+}
+
+impl Drop for HandedOver<'_> {
+    fn drop(&mut self) {
+        let mut jobs = self.process.jobs.lock().unwrap();
+        if jobs.waiting.remove(&self.job).is_none() {
+            return;
+        }
+        if let (Some(connection), Ok(frame)) =
+            (&jobs.connection, encode(&Request::Withdraw { job: self.job }))
+        {
+            let _ = connection.frames.send(frame);
+        }
+    }
+}
+
+// Keep a child running until the server stops
+async fn supervise(process: Arc<ProverProcess>, listener: UnixListener) {
+    let mut stop = process.stop.subscribe();
+    let mut backoff = INITIAL_BACKOFF;
+    for generation in 1.. {
+        let started = Instant::now();
+        let Some(reason) = run_child(&process, &listener, generation, &mut stop).await else {
+            break;
+        };
+        if started.elapsed() >= STABLE_AFTER {
+            backoff = INITIAL_BACKOFF;
+        }
+        println!("Prover process {}, restarting it in {} seconds", reason, backoff.as_secs());
+        {
+            let mut state = process.state.lock().unwrap();
+            state.restarts += 1;
+            state.last_exit = Some(reason);
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(backoff) => {}
+            _ = stop.wait_for(|stop| *stop) => break,
+        }
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+    process.stopped.send_replace(true);
+}
+
+// How a child stopped serving
+enum Ended {
+    Exited(io::Result<std::process::ExitStatus>),
+    Lost(String),
+    Stopped,
+}
+
+// Start a child and serve it until it exits, returning why, or None when the
+// server stopped it
+async fn run_child(
+    process: &ProverProcess,
+    listener: &UnixListener,
+    generation: u64,
+    stop: &mut watch::Receiver<bool>,
+) -> Option<String> {
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => return Some(format!("could not be started: {}", e)),
+    };
+    // In a process group of its own, so the witness generators and provers it
+    // runs can be stopped with it
+    let mut command = Command::new(exe);
+    command.args(["--role", "worker"]).process_group(0).kill_on_drop(true);
+    let mut child: Child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => return Some(format!("could not be started: {}", e)),
+    };
+    let group = child.id();
+    process.state.lock().unwrap().pid = child.id();
+    println!("Started prover process {}", child.id().unwrap_or_default());
+
+    let accepted = tokio::select! {
+        accepted = tokio::time::timeout(CONNECT_TIMEOUT, listener.accept()) => match accepted {
+            Ok(Ok((stream, _))) => Ok(stream),
+            Ok(Err(e)) => Err(Ended::Lost(format!("could not connect: {}", e))),
+            Err(_) => Err(Ended::Lost(format!(
+                "did not connect within {} seconds",
+                CONNECT_TIMEOUT.as_secs()
+            ))),
+        },
+        status = child.wait() => Err(Ended::Exited(status)),
+        _ = stop.wait_for(|stop| *stop) => Err(Ended::Stopped),
+    };
+    let ended = match accepted {
+        Ok(stream) => serve_child(process, stream, generation, &mut child, stop).await,
+        Err(ended) => ended,
+    };
+    process.disconnect(generation);
+    if !matches!(ended, Ended::Exited(_)) {
+        let _ = child.kill().await;
+    }
+    if let Some(group) = group {
+        let kill = Command::new("kill")
+            .args(["-KILL", "--", &format!("-{}", group)])
+            .stderr(std::process::Stdio::null())
+            .status();
+        let _ = kill.await;
+    }
+    match ended {
+        Ended::Exited(Ok(status)) => Some(format!("exited with {}", status)),
+        Ended::Exited(Err(e)) => Some(format!("could not be waited for: {}", e)),
+        Ended::Lost(reason) => Some(reason),
+        Ended::Stopped => {
+            println!("Prover process stopped");
+            None
+        }
+    }
+}
+
+// Exchange messages with a connected child until it goes away
+async fn serve_child(
+    process: &ProverProcess,
+    stream: UnixStream,
+    generation: u64,
+    child: &mut Child,
+    stop: &mut watch::Receiver<bool>,
+) -> Ended {
+    let (mut reader, mut writer) = stream.into_split();
+    let (frames, mut outgoing) = mpsc::unbounded_channel::<Vec<u8>>();
+    process.connect(generation, frames);
+    println!("Prover process {} connected", child.id().unwrap_or_default());
+
+    let writing = async {
+        while let Some(frame) = outgoing.recv().await {
+            writer.write_all(&frame).await?;
+        }
+        Ok::<_, io::Error>(())
+    };
+    let reading = async {
+        loop {
+            match read_message::<Outcome>(&mut reader).await {
+                Ok(Some(outcome)) => process.finish(outcome),
+                Ok(None) => return "closed its connection".to_string(),
+                Err(e) => return format!("sent an unreadable message: {}", e),
+            }
+        }
+    };
+    tokio::select! {
+        status = child.wait() => Ended::Exited(status),
+        reason = reading => Ended::Lost(reason),
+        Err(e) = writing => Ended::Lost(format!("could not be written to: {}", e)),
+        _ = stop.wait_for(|stop| *stop) => Ended::Stopped,
+    }
+}
+
+// Run as the prover child: prove the jobs the server hands over until it
+// closes the socket
+pub async fn run_worker(config: Config) {
+    let path = &config.prover_socket_path;
+    let stream = UnixStream::connect(path).await.unwrap_or_else(|e| {
+        println!("Prover process failed to connect to {}: {}", path, e);
+        std::process::exit(1);
+    });
+    let proving_keys = Arc::new(ProvingKeys::new(config.proving_key_pin));
+    let native_prover = Arc::new(NativeProver::load(&config));
+    let (mut reader, mut writer) = stream.into_split();
+    let (outcomes, mut outgoing) = mpsc::unbounded_channel::<Vec<u8>>();
+    tokio::spawn(async move {
+        while let Some(frame) = outgoing.recv().await {
+            if let Err(e) = writer.write_all(&frame).await {
+                println!("Prover process failed to answer the server: {}", e);
+                break;
+            }
+        }
+    });
+
+    // Tasks of the jobs being proved, by number, so they can be withdrawn
+    let running: Arc<Mutex<HashMap<u64, AbortHandle>>> = Arc::default();
+    loop {
+        let (job, prove) = match read_message::<Request>(&mut reader).await {
+            Ok(Some(Request::Prove { job, prove })) => (job, *prove),
+            Ok(Some(Request::Withdraw { job })) => {
+                // Aborting drops the job's step, which kills its tool
+                if let Some(task) = running.lock().unwrap().remove(&job) {
+                    task.abort();
+                }
+                continue;
+            }
+            Ok(None) => {
+                println!("Server closed the prover connection, exiting");
+                return;
+            }
+            Err(e) => {
+                println!("Prover process failed to read from the server: {}", e);
+                std::process::exit(1);
+            }
+        };
+        let proving_keys = proving_keys.clone();
+        let native_prover = native_prover.clone();
+        let outcomes = outcomes.clone();
+        let task_running = running.clone();
+        // Held while spawning, so the task cannot finish before it is known
+        let mut tasks = running.lock().unwrap();
+        let task = tokio::spawn(async move {
+            if let Some(tenant) = &prove.tenant {
+                artifacts::assign_tenant(&prove.id, tenant);
+            }
+            let result = crate::run_prover(&proving_keys, &native_prover, &prove)
+                .await
+                .map(|proving_time| proving_time.as_millis() as u64)
+                .map_err(|e| JobFailure {
+                    cancelled: matches!(e, PipelineError::Cancelled { .. }),
+                    message: e.to_string(),
+                });
+            match encode(&Outcome { job, result }) {
+                Ok(frame) => {
+                    let _ = outcomes.send(frame);
+                }
+                Err(e) => println!("Failed to encode the outcome of {}: {}", prove.id, e),
+            }
+            task_running.lock().unwrap().remove(&job);
+        });
+        tasks.insert(job, task.abort_handle());
+    }
+}
+
+// Resolves on SIGTERM or Ctrl-C
+pub async fn shutdown_signal() {
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .expect("failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
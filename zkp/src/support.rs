@@ -0,0 +1,533 @@
+// Support bundles for bug reports.
+//
+// POST /admin/support-bundle gathers everything needed to debug a measurement,
+// or every measurement created in a time range, into one zip archive:
+//
+//   bundle.json                     scope, who generated it and when
+//   versions.json                   server build, circuit keys and toolchain
+//   config.txt                      effective configuration, secrets masked
+//   audit.jsonl                     audit entries about the measurements
+//   server.log                      lines of SERVER_LOG_PATH naming them
+//   measurements/{id}/record.json   the measurement as stored
+//   measurements/{id}/pipeline.log  output of the proving tools
+//   measurements/{id}/timings.json  when each pipeline step started
+//   measurements/{id}/artifacts.json size and SHA-256 of every file
+//   measurements/{id}/history.json  status transitions and time in each status
+//
+// The images themselves are only added with include_image, and config.txt
+// only for global callers. Bundles are written to SUPPORT_BUNDLE_DIR and
+// downloaded through a URL signed with SUPPORT_BUNDLE_SECRET that expires
+// after SUPPORT_BUNDLE_TTL_SECS; expired bundles are deleted when the next one
+// is generated.
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Write},
+    sync::Arc,
+};
+use uuid::Uuid;
+use zip::{ZipWriter, write::SimpleFileOptions};
+
+use crate::{
+    AppState, Measurement, access,
+    artifacts::Artifact,
+    auth::{Admin, Authorized, Caller},
+    circuit,
+    config::Config,
+    downloads::{self, ArtifactFile},
+    history, rpc, timecheck,
+    version::{self, VersionStamp},
+};
+
+// Measurements a time-range bundle may hold
+const MAX_RANGE_MEASUREMENTS: usize = 100;
+// Lines kept from the end of the audit and server logs
+const MAX_LOG_LINES: usize = 5000;
+const MASK: &str = "********";
+
+pub struct SupportBundles {
+    dir: String,
+    ttl: Duration,
+    // Key signing download URLs; random per process unless configured
+    secret: Vec<u8>,
+}
+
+impl SupportBundles {
+    pub fn new(config: &Config) -> Self {
+        let secret = match &config.support_bundle_secret {
+            Some(secret) => secret.as_bytes().to_vec(),
+            None => [Uuid::new_v4().into_bytes(), Uuid::new_v4().into_bytes()].concat(),
+        };
+        SupportBundles {
+            dir: config.support_bundle_dir.clone(),
+            ttl: Duration::seconds(config.support_bundle_ttl_secs as i64),
+            secret,
+        }
+    }
+
+    fn signature(&self, name: &str, expires: i64) -> String {
+        hmac_sha256(&self.secret, format!("{}:{}", name, expires).as_bytes())
+    }
+}
+
+// HMAC-SHA256 of a message as hex, per RFC 2104
+pub(crate) fn hmac_sha256(key: &[u8], message: &[u8]) -> String {
+    const BLOCK_BYTES: usize = 64;
+    let mut block = [0u8; BLOCK_BYTES];
+    if key.len() > BLOCK_BYTES {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let pad = |byte: u8| block.iter().map(|k| k ^ byte).collect::<Vec<u8>>();
+    let inner = Sha256::new().chain_update(pad(0x36)).chain_update(message).finalize();
+    hex::encode(Sha256::new().chain_update(pad(0x5c)).chain_update(inner).finalize())
+}
+
+// Compare without stopping at the first difference, so timing reveals nothing
+pub(crate) fn same_signature(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[derive(Deserialize)]
+pub struct SupportBundleRequest {
+    measurement_id: Option<String>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    #[serde(default)]
+    include_image: bool,
+}
+
+#[derive(Serialize)]
+pub struct SupportBundleCreated {
+    bundle: String,
+    bytes: u64,
+    measurements: Vec<String>,
+    download_url: String,
+    expires_at: DateTime<Utc>,
+}
+
+// What a bundle covers
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "snake_case")]
+enum Scope {
+    Measurement { measurement_id: String },
+    Range { from: DateTime<Utc>, to: DateTime<Utc> },
+}
+
+// Everything the archive is built from, gathered while holding the locks
+struct Contents {
+    scope: Scope,
+    generated_by: String,
+    generated_at: DateTime<Utc>,
+    include_image: bool,
+    measurements: Vec<Measurement>,
+    versions: VersionStamp,
+    config: Option<String>,
+    audit_log_path: String,
+    server_log_path: Option<String>,
+}
+
+// Handler assembling a support bundle for a measurement or a time range
+pub async fn create(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Admin>,
+    Json(request): Json<SupportBundleRequest>,
+) -> Result<(StatusCode, Json<SupportBundleCreated>), (StatusCode, String)> {
+    let scope = match (request.measurement_id, request.from, request.to) {
+        (Some(measurement_id), None, None) => Scope::Measurement { measurement_id },
+        (None, Some(from), Some(to)) if from < to => Scope::Range { from, to },
+        (None, Some(_), Some(_)) => {
+            return Err((StatusCode::BAD_REQUEST, "from must be before to".to_string()));
+        }
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Give either measurement_id or both from and to".to_string(),
+            ));
+        }
+    };
+    let measurements = select(&state, &caller, &scope)?;
+
+    let contents = Contents {
+        scope: scope.clone(),
+        generated_by: caller.name.clone(),
+        generated_at: Utc::now(),
+        include_image: request.include_image,
+        measurements,
+        versions: VersionStamp { toolchain: Some(state.toolchain.get()), ..state.versions.clone() },
+        config: caller.tenant.is_none().then(|| masked_config(&state.config)),
+        audit_log_path: state.config.audit_log_path.clone(),
+        server_log_path: state.config.server_log_path.clone(),
+    };
+    let ids: Vec<String> = contents.measurements.iter().map(|m| m.id.clone()).collect();
+
+    let name = format!(
+        "support-{}-{}.zip",
+        contents.generated_at.format("%Y%m%dT%H%M%SZ"),
+        &Uuid::new_v4().simple().to_string()[..8]
+    );
+    let dir = state.support.dir.clone();
+    let ttl = state.support.ttl;
+    let path = format!("{}/{}", dir, name);
+    let bytes = tokio::task::spawn_blocking(move || {
+        remove_expired(&dir, ttl);
+        fs::create_dir_all(&dir)?;
+        write_archive(&path, &contents)
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .map_err(|e| {
+        (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write support bundle: {}", e))
+    })?;
+
+    let expires_at = Utc::now() + ttl;
+    let expires = expires_at.timestamp();
+    let download_url = format!(
+        "{}/support-bundles/{}?expires={}&signature={}",
+        state.config.public_base_url,
+        name,
+        expires,
+        state.support.signature(&name, expires)
+    );
+
+    println!("Support bundle {} generated by {} for {} measurements", name, caller.name, ids.len());
+    state.audit.record(
+        "support_bundle_created",
+        json!({
+            "by": caller.name,
+            "bundle": name,
+            "scope": scope,
+            "measurements": ids,
+            "include_image": request.include_image,
+        }),
+    );
+    Ok((
+        StatusCode::CREATED,
+        Json(SupportBundleCreated {
+            bundle: name,
+            bytes,
+            measurements: ids,
+            download_url,
+            expires_at,
+        }),
+    ))
+}
+
+// Measurements of the scope the caller may see
+fn select(
+    state: &AppState,
+    caller: &Caller,
+    scope: &Scope,
+) -> Result<Vec<Measurement>, (StatusCode, String)> {
+    let measurements = state.measurements.lock();
+    match scope {
+        Scope::Measurement { measurement_id } => {
+            access::visible(measurements.get(measurement_id), caller, measurement_id)
+                .map(|m| vec![m.clone()])
+        }
+        Scope::Range { from, to } => {
+            let mut selected: Vec<Measurement> = measurements
+                .values()
+                .filter(|m| caller.can_access(m.tenant.as_deref()))
+                .filter(|m| m.created_at.is_some_and(|at| at >= *from && at < *to))
+                .cloned()
+                .collect();
+            if selected.len() > MAX_RANGE_MEASUREMENTS {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!(
+                        "{} measurements were created in this range, a bundle holds at most {}; narrow the range",
+                        selected.len(),
+                        MAX_RANGE_MEASUREMENTS
+                    ),
+                ));
+            }
+            selected.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.id.cmp(&b.id)));
+            Ok(selected)
+        }
+    }
+}
+
+// Debug form of the configuration with every secret replaced. Fields holding
+// secrets must be masked here when they are added to Config.
+fn masked_config(config: &Config) -> String {
+    let mut config = config.clone();
+    for secret in
+        [&mut config.admin_api_key, &mut config.support_bundle_secret, &mut config.webhook_secret]
+    {
+        if secret.is_some() {
+            *secret = Some(MASK.to_string());
+        }
+    }
+    // Broker and database URLs may carry credentials as user:password@host
+    for url in [&mut config.broker_url, &mut config.database_url] {
+        if let Some((scheme, rest)) = url.split_once("://")
+            && let Some((_, host)) = rest.rsplit_once('@')
+        {
+            *url = format!("{}://{}@{}", scheme, MASK, host);
+        }
+    }
+    // RPC URLs may carry API keys in their credentials or query string
+    for url in &mut config.zkverify_rpc_urls {
+        *url = rpc::redact(url);
+    }
+    format!("{:#?}\n", config)
+}
+
+// Write the archive to a temporary file and move it in place, returning its size
+fn write_archive(path: &str, contents: &Contents) -> io::Result<u64> {
+    let temp_path = format!("{}.tmp", path);
+    let result = write_entries(&temp_path, contents);
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result?;
+    fs::rename(&temp_path, path)?;
+    Ok(fs::metadata(path)?.len())
+}
+
+fn write_entries(path: &str, contents: &Contents) -> io::Result<()> {
+    let mut zip = ZipWriter::new(File::create(path)?);
+    let options = SimpleFileOptions::default();
+    let to_io = |e: zip::result::ZipError| io::Error::other(e);
+    let add = |zip: &mut ZipWriter<File>, name: &str, data: &[u8]| -> io::Result<()> {
+        zip.start_file(name, options).map_err(to_io)?;
+        zip.write_all(data)
+    };
+    let pretty = |value: &Value| serde_json::to_vec_pretty(value).map_err(io::Error::other);
+
+    let ids: Vec<&str> = contents.measurements.iter().map(|m| m.id.as_str()).collect();
+    let summary = json!({
+        "scope": contents.scope,
+        "generated_by": contents.generated_by,
+        "generated_at": contents.generated_at,
+        "include_image": contents.include_image,
+        "measurements": ids,
+    });
+    add(&mut zip, "bundle.json", &pretty(&summary)?)?;
+
+    let circuits: Vec<Value> = circuit::all()
+        .map(|c| {
+            json!({
+                "version": c.version,
+                "installed": c.is_installed(),
+                "zkeyHash": version::hash_file(c.zkey_path),
+                "vkeyHash": version::hash_file(c.vkey_path),
+            })
+        })
+        .collect();
+    let versions = json!({ "server": contents.versions, "circuits": circuits });
+    add(&mut zip, "versions.json", &pretty(&versions)?)?;
+
+    if let Some(config) = &contents.config {
+        add(&mut zip, "config.txt", config.as_bytes())?;
+    }
+
+    // Audit entries name measurements in their details; a range bundle also
+    // gets every entry written during the range
+    let audit = matching_lines(&contents.audit_log_path, |line| {
+        ids.iter().any(|id| line.contains(id))
+            || match &contents.scope {
+                Scope::Range { from, to } => {
+                    audit_time(line).is_some_and(|at| at >= *from && at < *to)
+                }
+                Scope::Measurement { .. } => false,
+            }
+    });
+    add(&mut zip, "audit.jsonl", audit.as_bytes())?;
+
+    if let Some(server_log) = &contents.server_log_path {
+        let lines = matching_lines(server_log, |line| ids.iter().any(|id| line.contains(id)));
+        add(&mut zip, "server.log", lines.as_bytes())?;
+    }
+
+    for m in &contents.measurements {
+        let dir = format!("measurements/{}", m.id);
+        let record = serde_json::to_value(m).map_err(io::Error::other)?;
+        add(&mut zip, &format!("{}/record.json", dir), &pretty(&record)?)?;
+
+        let log = fs::read_to_string(Artifact::Log.path(&m.id)).unwrap_or_default();
+        if !log.is_empty() {
+            add(&mut zip, &format!("{}/pipeline.log", dir), log.as_bytes())?;
+        }
+        add(&mut zip, &format!("{}/timings.json", dir), &pretty(&timings(m, &log))?)?;
+        add(&mut zip, &format!("{}/artifacts.json", dir), &pretty(&artifact_hashes(&m.id))?)?;
+        let history = serde_json::to_value(history::describe(m, contents.generated_at))
+            .map_err(io::Error::other)?;
+        add(&mut zip, &format!("{}/history.json", dir), &pretty(&history)?)?;
+
+        if contents.include_image {
+            for (artifact, name) in
+                [(Artifact::Image, "image.jpg"), (Artifact::SecondImage, "image2.jpg")]
+            {
+                match fs::read(artifact.path(&m.id)) {
+                    Ok(image) => add(&mut zip, &format!("{}/{}", dir, name), &image)?,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+    }
+
+    zip.finish().map_err(to_io)?;
+    Ok(())
+}
+
+// Lines of a log file passing the filter, at most the last MAX_LOG_LINES; a
+// missing file has no lines
+fn matching_lines(path: &str, keep: impl Fn(&str) -> bool) -> String {
+    let Ok(file) = File::open(path) else {
+        return String::new();
+    };
+    let mut kept = VecDeque::new();
+    for line in BufReader::new(file).lines().map_while(Result::ok) {
+        if keep(&line) {
+            if kept.len() == MAX_LOG_LINES {
+                kept.pop_front();
+            }
+            kept.push_back(line);
+        }
+    }
+    kept.into_iter().map(|line| line + "\n").collect()
+}
+
+fn audit_time(line: &str) -> Option<DateTime<Utc>> {
+    let entry: Value = serde_json::from_str(line).ok()?;
+    entry.get("timestamp")?.as_str()?.parse().ok()
+}
+
+// Start of each pipeline step, from the timestamps run_step writes to the log,
+// with the time until the next step began
+fn timings(m: &Measurement, log: &str) -> Value {
+    let starts: Vec<(DateTime<Utc>, &str)> = log
+        .lines()
+        .filter_map(|line| {
+            let (at, rest) = line.strip_prefix('[')?.split_once("] ")?;
+            let (stage, _) = rest.split_once(": ")?;
+            Some((at.parse().ok()?, stage))
+        })
+        .collect();
+    let steps: Vec<Value> = starts
+        .iter()
+        .enumerate()
+        .map(|(i, (at, stage))| {
+            let seconds =
+                starts.get(i + 1).map(|(next, _)| (*next - *at).num_milliseconds() as f64 / 1000.0);
+            json!({ "stage": stage, "started_at": at, "seconds_until_next": seconds })
+        })
+        .collect();
+    json!({
+        "created_at": m.created_at,
+        "captured_at": m.captured_at,
+        "scheduled_for": m.scheduled_for,
+        "status": m.status,
+        "steps": steps,
+    })
+}
+
+// Size and SHA-256 of every file held for a measurement, the images included
+fn artifact_hashes(id: &str) -> Value {
+    let fixed = [
+        ("image", Artifact::Image),
+        ("image2", Artifact::SecondImage),
+        ("input", Artifact::Input),
+        ("proof", Artifact::Proof),
+        ("publicSignals", Artifact::PublicSignals),
+        ("attestation", Artifact::Attestation),
+        ("webp", Artifact::Webp),
+        ("submissionPayload", Artifact::SubmissionPayload),
+        ("depthMap", Artifact::DepthMap),
+        ("log", Artifact::Log),
+    ];
+    let thumbnails: Vec<(String, Artifact)> =
+        fs::read_dir(Artifact::Image.path(id).rsplit_once('/').map_or(".", |(dir, _)| dir))
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|file| {
+                let name = file.file_name().to_string_lossy().to_string();
+                let size = name.strip_prefix(id)?.strip_prefix("_thumb_")?.strip_suffix(".jpg")?;
+                let size: u32 = size.parse().ok()?;
+                Some((format!("thumbnail{}", size), Artifact::Thumbnail(size)))
+            })
+            .collect();
+
+    let mut hashes = BTreeMap::new();
+    for (name, artifact) in
+        fixed.map(|(name, a)| (name.to_string(), a)).into_iter().chain(thumbnails)
+    {
+        let path = artifact.path(id);
+        if let Ok(meta) = fs::metadata(&path) {
+            hashes
+                .insert(name, json!({ "bytes": meta.len(), "sha256": version::hash_file(&path) }));
+        }
+    }
+    json!(hashes)
+}
+
+// Delete bundles whose download links have expired
+fn remove_expired(dir: &str, ttl: Duration) {
+    let Ok(files) = fs::read_dir(dir) else {
+        return;
+    };
+    let cutoff = Utc::now() - ttl;
+    for file in files.flatten() {
+        let expired = file
+            .metadata()
+            .and_then(|meta| meta.modified())
+            .is_ok_and(|modified| DateTime::<Utc>::from(modified) < cutoff);
+        if expired && let Err(e) = fs::remove_file(file.path()) {
+            println!("Failed to remove expired support bundle {:?}: {}", file.path(), e);
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DownloadQuery {
+    expires: i64,
+    signature: String,
+}
+
+// Handler serving a bundle to whoever holds its signed link
+pub async fn download(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Query(query): Query<DownloadQuery>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let valid_name = name.starts_with("support-")
+        && name.ends_with(".zip")
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+    if !valid_name
+        || !same_signature(&query.signature, &state.support.signature(&name, query.expires))
+    {
+        return Err((StatusCode::FORBIDDEN, "Invalid support bundle link".to_string()));
+    }
+    // The expiry was set by this server's own clock, so no skew is allowed
+    let expires = DateTime::from_timestamp(query.expires, 0).unwrap_or_default();
+    if timecheck::not_older_than(expires, Utc::now(), Duration::zero(), Duration::zero()).is_err() {
+        return Err((StatusCode::GONE, "This support bundle link has expired".to_string()));
+    }
+
+    let file = ArtifactFile {
+        path: format!("{}/{}", state.support.dir, name),
+        content_type: "application/zip",
+        // A zip archive is always saved, whatever ?download= says
+        filename: Some(name.clone()),
+        attachment: true,
+        label: format!("Support bundle {}", name),
+    };
+    downloads::serve(&headers, file, None, None).await
+}
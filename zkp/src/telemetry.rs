@@ -0,0 +1,216 @@
+// Opt-in anonymous usage telemetry.
+//
+// With TELEMETRY_ENABLED=true the server reports each day, at SUMMARY_TIME
+// (UTC) together with the daily summary, how the previous day went to
+// TELEMETRY_ENDPOINT as one JSON POST: how many of the day's measurements
+// completed and failed, counted as in the daily summary (summary.rs), the
+// circuit versions the completed ones were proved with, the server version
+// and percentiles of the day's proof durations in whole seconds. Nothing
+// about single measurements leaves the server: no ids, contents, owners,
+// tenants or client addresses, and nothing naming the deployment.
+//
+// Telemetry is off by default. When on, the exact body is printed before it
+// is sent, and GET /admin/telemetry/preview shows the body the next report
+// would carry, as far as the day has gone, whether telemetry is on or not.
+//
+// Telemetry fails closed: reports are built and sent by their own task, the
+// POST gives up after SEND_TIMEOUT, and any failure drops that day's report
+// with a log line, without retrying or keeping it. Proof workers only note
+// their durations.
+
+use axum::{extract::State, http::StatusCode, response::Json};
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    AppState, ProofStatus,
+    auth::{Authorized, Operator},
+    config::Config,
+    reprove, summary,
+};
+
+// Version of the report's layout, raised when fields change
+const REPORT_VERSION: u32 = 1;
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Serialize)]
+pub struct ProofCounts {
+    completed: usize,
+    failed: usize,
+}
+
+// Proof durations in whole seconds
+#[derive(Debug, Serialize)]
+pub struct ProofSeconds {
+    samples: usize,
+    p50: u64,
+    p90: u64,
+    p99: u64,
+}
+
+// The body of one day's report
+#[derive(Debug, Serialize)]
+pub struct Report {
+    report_version: u32,
+    date: NaiveDate,
+    server_version: String,
+    // Measurements created that day that completed or failed
+    proofs: ProofCounts,
+    // Completed measurements by the circuit version they were proved with
+    circuit_versions: BTreeMap<String, usize>,
+    // Absent if no proof finished that day while the server was running
+    #[serde(skip_serializing_if = "Option::is_none")]
+    proof_seconds: Option<ProofSeconds>,
+}
+
+pub struct Telemetry {
+    enabled: bool,
+    endpoint: Option<String>,
+    // Durations of the proofs that finished on each day, in seconds, for the
+    // day being reported and the one in progress
+    durations: Mutex<BTreeMap<NaiveDate, Vec<f64>>>,
+}
+
+impl Telemetry {
+    pub fn new(config: &Config) -> Self {
+        Telemetry {
+            enabled: config.telemetry_enabled,
+            endpoint: config.telemetry_endpoint.clone(),
+            durations: Mutex::default(),
+        }
+    }
+
+    // Note the duration of a proof that just finished
+    pub fn record_proof(&self, duration: Duration) {
+        let today = Utc::now().date_naive();
+        let mut durations = self.durations.lock().unwrap();
+        durations.entry(today).or_default().push(duration.as_secs_f64());
+        if let Some(yesterday) = today.checked_sub_days(Days::new(1)) {
+            durations.retain(|date, _| *date >= yesterday);
+        }
+    }
+
+    // Aggregate the report of a day
+    fn report(&self, state: &AppState, date: NaiveDate) -> Report {
+        let mut circuit_versions = BTreeMap::new();
+        let counts = summary::count_day(state, date, |m| {
+            if m.status == ProofStatus::Completed {
+                *circuit_versions.entry(reprove::circuit_version(m)).or_insert(0) += 1;
+            }
+        });
+
+        let mut seconds = self.durations.lock().unwrap().get(&date).cloned().unwrap_or_default();
+        seconds.sort_by(f64::total_cmp);
+        let percentile =
+            |p: f64| seconds[((seconds.len() - 1) as f64 * p).round() as usize].round() as u64;
+        Report {
+            report_version: REPORT_VERSION,
+            date,
+            server_version: state.versions.server_version.clone(),
+            proofs: ProofCounts { completed: counts.completed, failed: counts.failed },
+            circuit_versions,
+            proof_seconds: (!seconds.is_empty()).then(|| ProofSeconds {
+                samples: seconds.len(),
+                p50: percentile(0.5),
+                p90: percentile(0.9),
+                p99: percentile(0.99),
+            }),
+        }
+    }
+}
+
+// Next time the daily reports are made
+fn next_report_at(state: &AppState, now: DateTime<Utc>) -> DateTime<Utc> {
+    let next = now.date_naive().and_time(state.config.summary_time).and_utc();
+    if next <= now { next + chrono::Duration::days(1) } else { next }
+}
+
+// POST a report's body, giving up after SEND_TIMEOUT
+async fn send(endpoint: &str, body: String) -> Result<(), String> {
+    let endpoint = endpoint.to_string();
+    let agent = ureq::AgentBuilder::new().timeout(SEND_TIMEOUT).build();
+    let request = tokio::task::spawn_blocking(move || {
+        agent
+            .post(&endpoint)
+            .set("Content-Type", "application/json")
+            .send_string(&body)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    });
+    match tokio::time::timeout(SEND_TIMEOUT, request).await {
+        Ok(Ok(sent)) => sent,
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err("timed out".to_string()),
+    }
+}
+
+// Report the previous day every day at the configured time, while enabled
+pub async fn reporter(state: Arc<AppState>) {
+    let telemetry = &state.telemetry;
+    if !telemetry.enabled {
+        return;
+    }
+    let Some(endpoint) = telemetry.endpoint.as_deref() else {
+        println!("Telemetry is enabled but TELEMETRY_ENDPOINT is not set, nothing is sent");
+        return;
+    };
+    println!(
+        "Telemetry is enabled: a daily report is sent to {} at {} UTC, see GET /admin/telemetry/preview",
+        endpoint, state.config.summary_time
+    );
+
+    loop {
+        let now = Utc::now();
+        let next = next_report_at(&state, now);
+        tokio::time::sleep((next - now).to_std().unwrap_or_default()).await;
+
+        let Some(date) = next.date_naive().checked_sub_days(Days::new(1)) else {
+            continue;
+        };
+        let body = match serde_json::to_string(&telemetry.report(&state, date)) {
+            Ok(body) => body,
+            Err(e) => {
+                println!("Telemetry report for {} dropped: {}", date, e);
+                continue;
+            }
+        };
+        println!("Sending telemetry report to {}: {}", endpoint, body);
+        match send(endpoint, body).await {
+            Ok(()) => println!("Telemetry report for {} sent", date),
+            Err(e) => println!("Telemetry report for {} dropped: {}", date, e),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct TelemetryPreview {
+    enabled: bool,
+    // Where reports go, if set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    endpoint: Option<String>,
+    next_report_at: DateTime<Utc>,
+    // The body of the next report, with the day in progress counted so far
+    report: Report,
+}
+
+// Handler showing what the next telemetry report would send
+pub async fn preview(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Operator>,
+) -> Result<Json<TelemetryPreview>, (StatusCode, String)> {
+    caller.require_global()?;
+    let now = Utc::now();
+    let next_report_at = next_report_at(&state, now);
+    let date = next_report_at.date_naive().checked_sub_days(Days::new(1)).unwrap_or(NaiveDate::MIN);
+    Ok(Json(TelemetryPreview {
+        enabled: state.telemetry.enabled,
+        endpoint: state.telemetry.endpoint.clone(),
+        next_report_at,
+        report: state.telemetry.report(&state, date),
+    }))
+}
@@ -0,0 +1,161 @@
+// Optional multi-tenant mode.
+//
+// TENANTS lists the tenants sharing the deployment, each optionally with a
+// quota on the measurements it may have in progress, e.g. "acme:100,globex".
+// API keys created for a tenant only ever see that tenant's measurements, keys
+// and jobs; keys without a tenant, including ADMIN_API_KEY, are global. A
+// tenant's measurements are stored under uploads/{tenant}/ and proofs/{tenant}/
+// and verified with ZK_VERIFY_SEED_PHRASE_{TENANT} when that is set.
+//
+// Measurements of another tenant are reported as not found rather than
+// forbidden, so their ids cannot be probed. In tenant mode every endpoint
+// touching measurements requires a key, including the public status pages.
+
+use axum::{
+    extract::FromRequestParts,
+    http::{StatusCode, request::Parts},
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    sync::Arc,
+};
+
+use crate::{
+    AppState, Measurement, ProofStatus,
+    auth::{self, AuthError, Caller},
+};
+
+const MAX_TENANT_CHARS: usize = 64;
+
+// Configured tenants and their in-progress quota, if any
+#[derive(Debug, Clone, Default)]
+pub struct Tenants {
+    quotas: BTreeMap<String, Option<usize>>,
+}
+
+impl Tenants {
+    // Parse TENANTS; an empty value leaves tenant mode off
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let mut quotas = BTreeMap::new();
+        for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (name, quota) = match entry.split_once(':') {
+                Some((name, quota)) => {
+                    let quota = quota
+                        .trim()
+                        .parse()
+                        .map_err(|_| format!("invalid quota '{}' for tenant {}", quota, name))?;
+                    (name.trim(), Some(quota))
+                }
+                None => (entry, None),
+            };
+            validate(name)?;
+            if quotas.insert(name.to_string(), quota).is_some() {
+                return Err(format!("tenant {} is listed twice", name));
+            }
+        }
+        Ok(Tenants { quotas })
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.quotas.is_empty()
+    }
+
+    pub fn contains(&self, tenant: &str) -> bool {
+        self.quotas.contains_key(tenant)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.quotas.keys()
+    }
+
+    pub fn quota(&self, tenant: &str) -> Option<usize> {
+        self.quotas.get(tenant).copied().flatten()
+    }
+}
+
+// Tenant names become directory names, so keep them to a safe alphabet
+fn validate(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_TENANT_CHARS
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "tenant '{}' must be 1 to {} lowercase letters, digits, '-' or '_'",
+            name, MAX_TENANT_CHARS
+        ))
+    }
+}
+
+// Key under which external ids are indexed, so tenants behind the same
+// address never see each other's ids
+pub fn owner_key(tenant: Option<&str>, owner: &str) -> String {
+    match tenant {
+        Some(tenant) => format!("{}/{}", tenant, owner),
+        None => owner.to_string(),
+    }
+}
+
+// zkVerify seed phrase of a tenant with its own account, from
+// ZK_VERIFY_SEED_PHRASE_{TENANT} with the name upper-cased and '-' as '_'
+pub fn seed_phrase(tenant: &str) -> Option<String> {
+    let name = format!("ZK_VERIFY_SEED_PHRASE_{}", tenant.to_ascii_uppercase().replace('-', "_"));
+    env::var(name).ok().filter(|seed| !seed.trim().is_empty())
+}
+
+// Reject a submission that would take the tenant past its quota; called with
+// the measurement store locked so concurrent submissions cannot overshoot
+pub fn check_quota(
+    tenants: &Tenants,
+    measurements: &HashMap<String, Measurement>,
+    tenant: Option<&str>,
+) -> Result<(), (StatusCode, String)> {
+    let Some((tenant, quota)) = tenant.and_then(|t| tenants.quota(t).map(|q| (t, q))) else {
+        return Ok(());
+    };
+    let in_progress = measurements
+        .values()
+        .filter(|m| m.tenant.as_deref() == Some(tenant))
+        .filter(|m| {
+            matches!(
+                m.status,
+                ProofStatus::Scheduled | ProofStatus::Pending | ProofStatus::Processing
+            )
+        })
+        .count();
+    if in_progress >= quota {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            format!(
+                "Tenant {} has {} measurements in progress, the limit is {}",
+                tenant, in_progress, quota
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// Caller of an endpoint that is public outside tenant mode, identified by
+// its key in every mode like the callers of other endpoints, so an admin is
+// known as one and a key that is not valid is refused. In tenant mode a key is
+// required, and the caller only sees its own tenant's measurements.
+pub struct Visitor(pub Caller);
+
+impl FromRequestParts<Arc<AppState>> for Visitor {
+    type Rejection = AuthError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let caller = auth::identify(parts, state)?;
+        if state.config.tenants.enabled() && caller.role.is_none() {
+            return Err((StatusCode::UNAUTHORIZED, "An API key is required".to_string()).into());
+        }
+        Ok(Visitor(caller))
+    }
+}
@@ -0,0 +1,146 @@
+// Threshold proofs: that a length is at least a public minimum.
+//
+// A submission may send minLengthCm, a positive number of centimetres, to
+// prove that its start and end point are at least that far apart without
+// revealing the points or the length. The claim is kept in min_length_cm and
+// proved with ZKHOTDOG_THRESHOLD, whose only public signal is the minimum,
+// scaled like the points and squared. The scaled minimum is rounded up, so
+// a proof never claims less than the client asked for. Points closer together
+// than the minimum are refused before proving, as the circuit cannot prove
+// them. Only a length between two points can be a threshold measurement.
+//
+// What the server tells about a threshold measurement leaves out its points
+// and length: /status/{id} and everything answering with it, webhooks and the
+// public view, and its input.json is not served. GET /measurements lists it
+// without them as well; only the stored record keeps them.
+
+use schemars::Schema;
+use serde::{Serialize, Serializer, ser::Error};
+
+use crate::{Measurement, circuit, coords::SCALE};
+
+// Fields of a measurement revealing its points or length
+const PRIVATE_FIELDS: [&str; 6] =
+    ["start_point", "end_point", "points", "mid_point", "original_points", "length_cm"];
+
+// Parse minLengthCm
+pub fn parse(value: &str) -> Result<f64, String> {
+    match value.trim().parse::<f64>() {
+        Ok(cm) if cm.is_finite() && cm > 0.0 => Ok(cm),
+        _ => Err(format!("'{}' is not a positive number of centimetres", value)),
+    }
+}
+
+// Scaled squared distance a proof of the minimum length claims, saturating
+// for lengths far beyond what the circuit takes
+pub fn min_distance_squared(min_length_cm: f64) -> i128 {
+    let scaled = (min_length_cm / 100.0 * SCALE).ceil() as i128;
+    scaled.saturating_mul(scaled)
+}
+
+// Circuit a new measurement with the given minimum is proved with, None
+// without one
+pub fn circuit_for(min_length_cm: Option<f64>) -> Option<String> {
+    min_length_cm.map(|_| circuit::ZKHOTDOG_THRESHOLD.version.to_string())
+}
+
+// Serialize a measurement as it is served, without its private fields when
+// it proves a minimum length
+pub fn serialize_public<S: Serializer>(
+    measurement: &Measurement,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    Public { record: measurement, threshold: measurement.min_length_cm.is_some() }
+        .serialize(serializer)
+}
+
+// A representation of a measurement as it is served, such as its listing
+// item, without the private fields when the measurement proves a minimum
+pub struct Public<'a, T> {
+    pub record: &'a T,
+    pub threshold: bool,
+}
+
+impl<T: Serialize> Serialize for Public<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if !self.threshold {
+            return self.record.serialize(serializer);
+        }
+        let mut value = serde_json::to_value(self.record).map_err(S::Error::custom)?;
+        if let Some(fields) = value.as_object_mut() {
+            for field in PRIVATE_FIELDS {
+                fields.remove(field);
+            }
+        }
+        value.serialize(serializer)
+    }
+}
+
+// Schema of a served measurement: the private fields are left out for
+// threshold measurements, so none of them is required
+pub fn private_fields_optional(schema: &mut Schema) {
+    if let Some(required) = schema.get_mut("required").and_then(|r| r.as_array_mut()) {
+        required.retain(|field| !PRIVATE_FIELDS.iter().any(|private| field == private));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parse_takes_positive_centimetres() {
+        assert_eq!(parse("30"), Ok(30.0));
+        assert_eq!(parse(" 12.5 "), Ok(12.5));
+        for value in ["abc", "-1", "0", "inf", "NaN", ""] {
+            assert_eq!(
+                parse(value),
+                Err(format!("'{}' is not a positive number of centimetres", value))
+            );
+        }
+    }
+
+    #[test]
+    fn minimum_is_scaled_rounded_up_and_squared() {
+        assert_eq!(min_distance_squared(30.0), 900_000_000);
+        assert_eq!(min_distance_squared(50.001), 50_001 * 50_001);
+        // A fraction of a scaled unit still counts as a whole one
+        assert_eq!(min_distance_squared(0.0001), 1);
+        assert_eq!(min_distance_squared(30.0001), 30_001 * 30_001);
+        assert_eq!(min_distance_squared(1e300), i128::MAX);
+    }
+
+    #[test]
+    fn only_a_minimum_selects_the_threshold_circuit() {
+        assert_eq!(circuit_for(Some(30.0)).as_deref(), Some("zkhotdog-threshold-v1"));
+        assert_eq!(circuit_for(None), None);
+    }
+
+    #[test]
+    fn public_leaves_out_private_fields_of_thresholds() {
+        let record = json!({
+            "id": "m",
+            "start_point": {"x": 0.0, "y": 0.0, "z": 0.0},
+            "end_point": {"x": 1.0, "y": 0.0, "z": 0.0},
+            "mid_point": null,
+            "points": null,
+            "original_points": null,
+            "length_cm": 100.0,
+            "min_length_cm": 30.0,
+        });
+        let threshold = serde_json::to_value(Public { record: &record, threshold: true }).unwrap();
+        assert_eq!(threshold, json!({"id": "m", "min_length_cm": 30.0}));
+        let ordinary = serde_json::to_value(Public { record: &record, threshold: false }).unwrap();
+        assert_eq!(ordinary, record);
+    }
+
+    #[test]
+    fn private_fields_are_not_required() {
+        let mut schema =
+            Schema::try_from(json!({"required": ["id", "start_point", "length_cm", "status"]}))
+                .unwrap();
+        private_fields_optional(&mut schema);
+        assert_eq!(schema.get("required"), Some(&json!(["id", "status"])));
+    }
+}
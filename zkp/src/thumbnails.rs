@@ -0,0 +1,254 @@
+// Thumbnails and WebP variants of uploaded images.
+//
+// Variants are rendered on demand the first time they are requested. When
+// pre-warming is enabled, a small dedicated worker pool also renders every
+// configured size right after upload so the first gallery load does not pay
+// for image decoding. The pool is independent of the proof workers and its
+// failures never touch a measurement's proof status. Variants count against
+// the disk budget of cache.rs, which may evict them at any time.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+};
+use image::{ImageFormat, imageops::FilterType};
+use serde::Serialize;
+use std::{
+    fs,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+};
+use tokio::sync::{Mutex, mpsc};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    artifacts::Artifact,
+    auth::Caller,
+    cache,
+    downloads::{self, ArtifactFile, DownloadQuery},
+    tenants::Visitor,
+};
+
+// Pre-warm figures for the admin stats endpoint
+#[derive(Serialize)]
+pub struct PrewarmStats {
+    pub enabled: bool,
+    pub workers: usize,
+    pub depth: usize,
+    pub completed: u64,
+    pub failed: u64,
+}
+
+pub struct Prewarmer {
+    sender: mpsc::UnboundedSender<String>,
+    receiver: Mutex<mpsc::UnboundedReceiver<String>>,
+    depth: AtomicUsize,
+    completed: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl Prewarmer {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        Prewarmer {
+            sender,
+            receiver: Mutex::new(receiver),
+            depth: AtomicUsize::new(0),
+            completed: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+        }
+    }
+
+    // Queue the image variants of a freshly uploaded measurement
+    pub fn enqueue(&self, id: &str) {
+        if self.sender.send(id.to_string()).is_ok() {
+            self.depth.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn stats(&self, enabled: bool, workers: usize) -> PrewarmStats {
+        PrewarmStats {
+            enabled,
+            workers,
+            depth: self.depth.load(Ordering::Relaxed),
+            completed: self.completed.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// Worker rendering the variants of queued measurements one at a time
+pub async fn prewarm_worker(state: Arc<AppState>) {
+    loop {
+        state.maintenance.wait_until_off().await;
+        let received = tokio::select! {
+            received = async { state.prewarmer.receiver.lock().await.recv().await } => received,
+            _ = state.maintenance.wait_until_on() => continue,
+        };
+        let Some(id) = received else {
+            return;
+        };
+        state.prewarmer.depth.fetch_sub(1, Ordering::Relaxed);
+
+        let mut variants: Vec<Artifact> =
+            state.config.thumbnail_sizes.iter().map(|size| Artifact::Thumbnail(*size)).collect();
+        variants.push(Artifact::Webp);
+
+        let mut failed = false;
+        for variant in variants {
+            let task_id = id.clone();
+            let result = tokio::task::spawn_blocking(move || render(&task_id, variant))
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|r| r);
+            match result {
+                Ok(rendered) => {
+                    if rendered {
+                        state.cache.stored(&id, variant);
+                    }
+                    record(&state, &id, variant);
+                }
+                Err(e) => {
+                    println!("Failed to pre-warm {:?} for measurement {}: {}", variant, id, e);
+                    failed = true;
+                }
+            }
+        }
+
+        cache::enforce(&state);
+        if failed {
+            state.prewarmer.failed.fetch_add(1, Ordering::Relaxed);
+        } else {
+            state.prewarmer.completed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+// Render an image variant from the uploaded original unless it already
+// exists, returning whether it had to be rendered
+fn render(id: &str, variant: Artifact) -> Result<bool, String> {
+    let target = variant.path(id);
+    if std::path::Path::new(&target).exists() {
+        return Ok(false);
+    }
+
+    let original = image::open(Artifact::Image.path(id))
+        .map_err(|e| format!("Failed to decode image: {}", e))?;
+
+    // Write to a temporary file first so readers never see a partial variant
+    let temp = format!("{}.{}.tmp", target, Uuid::new_v4());
+    let result = match variant {
+        Artifact::Thumbnail(size) => {
+            // Small originals are re-encoded as they are rather than upscaled
+            let scaled = if original.width() > size || original.height() > size {
+                original.resize(size, size, FilterType::Triangle)
+            } else {
+                original
+            };
+            scaled.to_rgb8().save_with_format(&temp, ImageFormat::Jpeg)
+        }
+        Artifact::Webp => original.to_rgba8().save_with_format(&temp, ImageFormat::WebP),
+        other => return Err(format!("{:?} is not an image variant", other)),
+    };
+    result.map_err(|e| format!("Failed to encode image: {}", e))?;
+    fs::rename(&temp, &target).map_err(|e| format!("Failed to store image variant: {}", e))?;
+    Ok(true)
+}
+
+// Note a rendered variant on the measurement's artifacts
+fn record(state: &AppState, id: &str, variant: Artifact) {
+    let mut measurements = state.measurements.lock();
+    if let Some(m) = measurements.get_mut(id) {
+        m.artifacts.record(id, variant);
+    }
+}
+
+// Render (if needed) and serve a variant of a measurement's image
+async fn serve_variant(
+    state: Arc<AppState>,
+    caller: Caller,
+    headers: HeaderMap,
+    id: String,
+    variant: Artifact,
+    content_type: &'static str,
+    download: bool,
+) -> Result<Response, (StatusCode, String)> {
+    // Embargoed measurements and those of other tenants are indistinguishable
+    // from missing ones
+    downloads::check_visible(&state, &caller, &id, false)?;
+
+    // The variant must survive eviction until it is open for the response
+    let _pin = state.cache.pin(&id, variant);
+    // A read-only mirror serves the variants replicated from the primary and
+    // renders none of its own
+    if state.replica.is_none() {
+        let task_id = id.clone();
+        let rendered = tokio::task::spawn_blocking(move || render(&task_id, variant))
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+            .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+        record(&state, &id, variant);
+        state.cache.accessed(&id, variant, !rendered);
+        if rendered {
+            cache::enforce(&state);
+        }
+    }
+
+    let suffix = match variant {
+        Artifact::Thumbnail(size) => format!("-thumb-{}.jpg", size),
+        _ => ".webp".to_string(),
+    };
+    let file = ArtifactFile {
+        path: variant.path(&id),
+        content_type,
+        filename: Some(downloads::download_name(&id, &suffix)),
+        attachment: download,
+        label: format!("Image variant of measurement {}", id),
+    };
+    downloads::serve(&headers, file, None, None).await
+}
+
+// Handler serving a thumbnail of one of the configured sizes
+pub async fn serve_thumbnail(
+    State(state): State<Arc<AppState>>,
+    Visitor(caller): Visitor,
+    Path((id, size)): Path<(String, u32)>,
+    Query(query): Query<DownloadQuery>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    if !state.config.thumbnail_sizes.contains(&size) {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!(
+                "Thumbnail size {} is not available, supported sizes are {:?}",
+                size, state.config.thumbnail_sizes
+            ),
+        ));
+    }
+
+    serve_variant(
+        state,
+        caller,
+        headers,
+        id,
+        Artifact::Thumbnail(size),
+        "image/jpeg",
+        query.download,
+    )
+    .await
+}
+
+// Handler serving the WebP variant of the full image
+pub async fn serve_webp(
+    State(state): State<Arc<AppState>>,
+    Visitor(caller): Visitor,
+    Path(id): Path<String>,
+    Query(query): Query<DownloadQuery>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    serve_variant(state, caller, headers, id, Artifact::Webp, "image/webp", query.download).await
+}
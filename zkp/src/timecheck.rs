@@ -0,0 +1,149 @@
+// Validation of timestamps against this server's clock.
+//
+// Timestamps sent by clients come from clocks that may be off by a few
+// minutes, so every window check tolerates the same CLOCK_SKEW_SECS: a time
+// may lie that far in the future, and a time that must not be older than
+// some age may exceed it by as much. Failed checks report how far off the
+// timestamp was so a client can tell a bad clock from a stale request.
+//
+// A client timestamp ahead of this server can only come from a clock running
+// fast. Ones ahead by more than CLOCK_SKEW_WARN_SECS are logged with their
+// source and counted in /admin/stats, to find devices whose clocks need
+// fixing before they start failing the checks.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::{
+    fmt,
+    sync::atomic::{AtomicI64, AtomicU64, Ordering},
+};
+
+use crate::config::Config;
+
+// Why a timestamp falls outside its window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeError {
+    // Later than now by more than the allowed skew
+    TooNew { skew: Duration, allowed: Duration },
+    // Older than the window, including the allowed skew
+    TooOld { age: Duration, allowed: Duration },
+}
+
+impl fmt::Display for TimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimeError::TooNew { skew, allowed } => write!(
+                f,
+                "is {} in the future, more than the {} of clock skew allowed",
+                describe(*skew),
+                describe(*allowed)
+            ),
+            TimeError::TooOld { age, allowed } => {
+                write!(f, "is {} old, more than the {} allowed", describe(*age), describe(*allowed))
+            }
+        }
+    }
+}
+
+// A duration in whole days and seconds
+fn describe(duration: Duration) -> String {
+    let plural = |n: i64, unit: &str| format!("{} {}{}", n, unit, if n == 1 { "" } else { "s" });
+    let (days, seconds) = (duration.num_days(), duration.num_seconds() % 86400);
+    match (days, seconds) {
+        (0, seconds) => plural(seconds, "second"),
+        (days, 0) => plural(days, "day"),
+        (days, seconds) => format!("{} and {}", plural(days, "day"), plural(seconds, "second")),
+    }
+}
+
+// Check that a timestamp is no later than now plus the allowed skew
+pub fn not_newer_than(
+    at: DateTime<Utc>,
+    now: DateTime<Utc>,
+    skew: Duration,
+) -> Result<(), TimeError> {
+    let ahead = at - now;
+    if ahead > skew {
+        return Err(TimeError::TooNew { skew: ahead, allowed: skew });
+    }
+    Ok(())
+}
+
+// Check that a timestamp is no older than max_age plus the allowed skew
+pub fn not_older_than(
+    at: DateTime<Utc>,
+    now: DateTime<Utc>,
+    max_age: Duration,
+    skew: Duration,
+) -> Result<(), TimeError> {
+    let age = now - at;
+    let allowed = max_age + skew;
+    if age > allowed {
+        return Err(TimeError::TooOld { age, allowed });
+    }
+    Ok(())
+}
+
+// Figures on client clocks for the admin stats endpoint
+#[derive(Serialize)]
+pub struct ClockSkewStats {
+    pub allowed_seconds: i64,
+    pub warn_seconds: i64,
+    // Client timestamps seen ahead of this server by more than warn_seconds
+    pub skewed_timestamps: u64,
+    pub largest_skew_seconds: i64,
+}
+
+// The configured tolerance, and what was observed of client clocks
+pub struct ClockSkew {
+    allowed: Duration,
+    warn: Duration,
+    skewed: AtomicU64,
+    largest_secs: AtomicI64,
+}
+
+impl ClockSkew {
+    pub fn new(config: &Config) -> Self {
+        ClockSkew {
+            allowed: Duration::seconds(config.clock_skew_secs as i64),
+            warn: Duration::seconds(config.clock_skew_warn_secs as i64),
+            skewed: AtomicU64::new(0),
+            largest_secs: AtomicI64::new(0),
+        }
+    }
+
+    // Check a client timestamp that cannot lie in the future
+    pub fn not_newer_than(&self, at: DateTime<Utc>, now: DateTime<Utc>) -> Result<(), TimeError> {
+        not_newer_than(at, now, self.allowed)
+    }
+
+    // Note how far ahead the clock of an authenticated client is, warning
+    // when it is far enough to need fixing; source names the client
+    pub fn observe(&self, source: &str, at: DateTime<Utc>, now: DateTime<Utc>) {
+        let ahead = at - now;
+        if ahead > self.warn {
+            println!("Clock of {} is {} ahead of this server", source, describe(ahead));
+            self.skewed.fetch_add(1, Ordering::Relaxed);
+            self.largest_secs.fetch_max(ahead.num_seconds(), Ordering::Relaxed);
+        }
+    }
+
+    // Check a client timestamp that may be at most max_age old
+    pub fn not_older_than(
+        &self,
+        at: DateTime<Utc>,
+        now: DateTime<Utc>,
+        max_age: Duration,
+    ) -> Result<(), TimeError> {
+        not_older_than(at, now, max_age, self.allowed)
+    }
+
+    pub fn stats(&self) -> ClockSkewStats {
+        ClockSkewStats {
+            allowed_seconds: self.allowed.num_seconds(),
+            warn_seconds: self.warn.num_seconds(),
+            skewed_timestamps: self.skewed.load(Ordering::Relaxed),
+            largest_skew_seconds: self.largest_secs.load(Ordering::Relaxed),
+        }
+    }
+}
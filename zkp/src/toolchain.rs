@@ -0,0 +1,87 @@
+// Versions of the external proving toolchain.
+//
+// Proofs generated by different snarkjs releases can differ in subtle ways,
+// so the node and snarkjs versions are detected at startup and again every
+// TOOLCHAIN_REFRESH_SECS, and the versions active when a proof is generated
+// are stamped onto the measurement. A version that cannot be determined is
+// reported as absent rather than failing anything.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    process::Stdio,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use crate::AppState;
+
+// Longest a version probe may take; npx can hang trying to reach the registry
+const PROBE_TIMEOUT: Duration = Duration::from_secs(20);
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, JsonSchema)]
+pub struct Toolchain {
+    pub node: Option<String>,
+    pub snarkjs: Option<String>,
+}
+
+impl Toolchain {
+    pub async fn detect() -> Self {
+        Toolchain {
+            node: probe("node", &["--version"]).await,
+            snarkjs: probe("npx", &["snarkjs", "--version"])
+                .await
+                .map(|v| v.trim_start_matches("snarkjs@").to_string()),
+        }
+    }
+}
+
+// First line a tool prints when asked for its version
+async fn probe(program: &str, args: &[&str]) -> Option<String> {
+    let output = tokio::process::Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    // snarkjs prints its version in the usage banner and exits non-zero
+    let output = tokio::time::timeout(PROBE_TIMEOUT, output).await.ok()?.ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout.lines().map(str::trim).find(|line| !line.is_empty()).map(str::to_string)
+}
+
+// Most recently detected toolchain
+#[derive(Default)]
+pub struct ToolchainCache {
+    current: RwLock<Toolchain>,
+}
+
+impl ToolchainCache {
+    pub fn get(&self) -> Toolchain {
+        self.current.read().unwrap().clone()
+    }
+
+    fn set(&self, toolchain: Toolchain) {
+        *self.current.write().unwrap() = toolchain;
+    }
+}
+
+// Detect the toolchain now and then periodically, logging any change
+pub async fn refresher(state: Arc<AppState>) {
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(state.config.toolchain_refresh_secs));
+    let mut first = true;
+    loop {
+        interval.tick().await;
+        let detected = Toolchain::detect().await;
+        if first || detected != state.toolchain.get() {
+            first = false;
+            println!(
+                "Proving toolchain: node {}, snarkjs {}",
+                detected.node.as_deref().unwrap_or("unavailable"),
+                detected.snarkjs.as_deref().unwrap_or("unavailable")
+            );
+            state.toolchain.set(detected);
+        }
+    }
+}
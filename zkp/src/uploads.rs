@@ -0,0 +1,456 @@
+// Streaming of uploaded images to disk, with progress reporting.
+//
+// Images are written to a temporary file in uploads/ chunk by chunk as they
+// arrive instead of being buffered in memory, and moved into place once the
+// measurement is stored. A client that sends an Upload-Progress-Id header can
+// poll GET /uploads/progress/{id} meanwhile to learn how many bytes have been
+// received out of the request's Content-Length. Progress entries are dropped
+// shortly after their request finishes.
+//
+// A request that fails or is abandoned must not leave files behind. The
+// temporary file is removed unless it was moved into place, and every file a
+// submission writes from then on is tracked by an UploadTransaction, which
+// removes them again unless the submission is committed once its measurement
+// is stored and queued. Both guards act on drop, so they also clean up when
+// the client disconnects and the handler is cancelled midway.
+//
+// Only a server that dies mid-upload leaves a temporary file behind. A sweep
+// removes those untouched for UPLOAD_ABANDONED_TTL_SECS, or with
+// UPLOAD_SWEEP_DRY_RUN only counts them. An upload still being written can
+// finish while it is swept, so a file is first claimed by renaming it to a
+// tombstone: an upload that was moved into place meanwhile is no longer
+// there to claim, and a claimed file written to since is given back instead
+// of removed. Only then is the tombstone deleted.
+
+use axum::{
+    body::Bytes,
+    extract::{Path, State, multipart::Field},
+    http::{HeaderMap, StatusCode, header},
+    response::Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Write},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::{
+    AppState, artifacts,
+    image_url::{self, FetchError},
+};
+
+// How long a finished upload's progress stays available
+const PROGRESS_TTL: Duration = Duration::from_secs(60);
+const MAX_PROGRESS_ID_CHARS: usize = 128;
+pub const PROGRESS_HEADER: &str = "upload-progress-id";
+// Largest accepted POST /measurements request, image and fields together
+pub const MAX_REQUEST_BYTES: usize = 2 * 1024 * 1024;
+// Name prefixes of uploads being written and of those claimed by a sweep
+const INCOMING_PREFIX: &str = ".incoming-";
+const TOMBSTONE_PREFIX: &str = ".abandoned-";
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Progress {
+    received: u64,
+    // Content-Length of the request, when the client sent one
+    total: Option<u64>,
+    done: bool,
+    #[serde(skip)]
+    finished: Option<Instant>,
+}
+
+#[derive(Default)]
+pub struct UploadProgress {
+    entries: Mutex<HashMap<String, Progress>>,
+}
+
+impl UploadProgress {
+    // Start tracking the request if it carries a valid progress id
+    pub fn track(self: &Arc<Self>, headers: &HeaderMap) -> Result<Option<ProgressGuard>, String> {
+        let Some(value) = headers.get(PROGRESS_HEADER) else {
+            return Ok(None);
+        };
+        let id = value
+            .to_str()
+            .ok()
+            .map(str::trim)
+            .filter(|id| {
+                !id.is_empty()
+                    && id.len() <= MAX_PROGRESS_ID_CHARS
+                    && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            })
+            .ok_or(format!(
+                "Upload-Progress-Id must be 1 to {} letters, digits, '-' or '_'",
+                MAX_PROGRESS_ID_CHARS
+            ))?
+            .to_string();
+        let total = headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok());
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, p| p.finished.is_none_or(|f| f.elapsed() < PROGRESS_TTL));
+        entries.insert(id.clone(), Progress { received: 0, total, done: false, finished: None });
+
+        Ok(Some(ProgressGuard { registry: self.clone(), id }))
+    }
+
+    fn get(&self, id: &str) -> Option<Progress> {
+        let entries = self.entries.lock().unwrap();
+        entries.get(id).filter(|p| p.finished.is_none_or(|f| f.elapsed() < PROGRESS_TTL)).cloned()
+    }
+}
+
+// Progress of one request; marks the upload as done when dropped
+pub struct ProgressGuard {
+    registry: Arc<UploadProgress>,
+    id: String,
+}
+
+impl ProgressGuard {
+    fn add(&self, bytes: usize) {
+        if let Some(progress) = self.registry.entries.lock().unwrap().get_mut(&self.id) {
+            progress.received += bytes as u64;
+        }
+    }
+}
+
+impl Drop for ProgressGuard {
+    fn drop(&mut self) {
+        if let Some(progress) = self.registry.entries.lock().unwrap().get_mut(&self.id) {
+            progress.done = true;
+            progress.finished = Some(Instant::now());
+        }
+    }
+}
+
+// Uploaded image waiting for its measurement, removed unless persisted
+pub struct TempUpload {
+    path: String,
+    persisted: bool,
+}
+
+impl TempUpload {
+    // A file written elsewhere, such as a chunked upload, taken over
+    pub fn at(path: String) -> Self {
+        TempUpload { path, persisted: false }
+    }
+
+    // Move the upload to its final location, which must not exist yet: a
+    // hard link fails where rename would replace the file
+    pub fn persist(&mut self, path: &str) -> io::Result<()> {
+        fs::hard_link(&self.path, path)?;
+        let _ = fs::remove_file(&self.path);
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for TempUpload {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+// Files written for a submission, removed unless it is committed
+#[derive(Default)]
+pub struct UploadTransaction {
+    files: Vec<String>,
+    // Id whose tenant the submission claimed, released along with the files
+    tenant_of: Option<String>,
+    committed: bool,
+}
+
+impl UploadTransaction {
+    // Track a file before writing it, so a partial write is removed too
+    pub fn track(&mut self, path: &str) {
+        self.files.push(path.to_string());
+    }
+
+    // Track the tenant claimed for an id, releasing the one claimed for an
+    // id given up before
+    pub fn track_tenant(&mut self, id: &str) {
+        if let Some(previous) = self.tenant_of.replace(id.to_string()) {
+            artifacts::release_tenant(&previous);
+        }
+    }
+
+    // Keep the files, once the measurement they belong to is stored
+    pub fn commit(mut self) {
+        self.committed = true;
+    }
+}
+
+impl Drop for UploadTransaction {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        for path in &self.files {
+            match fs::remove_file(path) {
+                Ok(()) => println!("Removed {} of an unfinished submission", path),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => println!("Failed to remove {} of an unfinished submission: {}", path, e),
+            }
+        }
+        if let Some(id) = &self.tenant_of {
+            artifacts::release_tenant(id);
+        }
+    }
+}
+
+// Image of a new measurement, either still in memory or already on disk, or
+// still to be fetched from its URL (image_url.rs) or claimed by the token of
+// a chunked upload (chunked_uploads.rs)
+pub enum IncomingImage {
+    Bytes(Bytes),
+    File(TempUpload),
+    Url(String),
+    Upload(String),
+}
+
+impl IncomingImage {
+    // Fetch an image given by its URL or claim a chunked upload; others are
+    // returned as they are
+    pub async fn resolve(self, state: &AppState) -> Result<IncomingImage, (StatusCode, String)> {
+        match self {
+            IncomingImage::Url(url) => image_url::fetch(&state.config, url)
+                .await
+                .map(IncomingImage::Bytes)
+                .map_err(FetchError::into_response),
+            IncomingImage::Upload(token) => state
+                .chunked_uploads
+                .claim(&token)
+                .map(IncomingImage::File)
+                .map_err(|e| (StatusCode::BAD_REQUEST, e)),
+            image => Ok(image),
+        }
+    }
+
+    // Store the image at its final location, failing with AlreadyExists
+    // instead of replacing a file there
+    pub fn save(&mut self, path: &str) -> io::Result<()> {
+        match self {
+            IncomingImage::Bytes(data) => {
+                fs::OpenOptions::new().write(true).create_new(true).open(path)?.write_all(data)
+            }
+            IncomingImage::File(upload) => upload.persist(path),
+            IncomingImage::Url(url) => Err(io::Error::other(format!("{} was not fetched", url))),
+            IncomingImage::Upload(_) => Err(io::Error::other("the upload was not claimed")),
+        }
+    }
+}
+
+// Write a multipart field to a temporary file as its chunks arrive
+pub async fn stream_to_disk(
+    mut field: Field<'_>,
+    progress: Option<&ProgressGuard>,
+) -> Result<TempUpload, (StatusCode, String)> {
+    let upload = TempUpload {
+        path: format!("uploads/{}{}", INCOMING_PREFIX, Uuid::new_v4()),
+        persisted: false,
+    };
+    let mut file = tokio::fs::File::create(&upload.path).await.map_err(|e| {
+        (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create upload file: {}", e))
+    })?;
+
+    while let Some(chunk) = field
+        .chunk()
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read image data: {}", e)))?
+    {
+        file.write_all(&chunk).await.map_err(|e| {
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write image: {}", e))
+        })?;
+        if let Some(progress) = progress {
+            progress.add(chunk.len());
+        }
+    }
+    file.flush().await.map_err(|e| {
+        (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write image: {}", e))
+    })?;
+
+    Ok(upload)
+}
+
+// Handler reporting the progress of an upload
+pub async fn get_progress(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<Progress>, (StatusCode, String)> {
+    state
+        .upload_progress
+        .get(&id)
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, format!("No upload with progress ID {}", id)))
+}
+
+// What the abandoned upload sweeps did so far
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct AbandonedUploadStats {
+    sweeps: u64,
+    // Abandoned uploads removed, and their size
+    reclaimed: u64,
+    reclaimed_bytes: u64,
+    // Claimed uploads given back because they were written to meanwhile
+    released: u64,
+    // Abandoned uploads left in place by the last dry run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dry_run_found: Option<u64>,
+    last_sweep_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Default)]
+pub struct AbandonedUploads {
+    stats: Mutex<AbandonedUploadStats>,
+}
+
+impl AbandonedUploads {
+    pub fn stats(&self) -> AbandonedUploadStats {
+        self.stats.lock().unwrap().clone()
+    }
+}
+
+// Outcome of one sweep of uploads/
+#[derive(Default)]
+struct Sweep {
+    reclaimed: Vec<(String, u64)>,
+    released: u64,
+    found: u64,
+}
+
+// Whether a file was last written longer ago than the TTL
+fn is_stale(metadata: &fs::Metadata, ttl: Duration) -> bool {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|idle| idle >= ttl)
+}
+
+// Claim and remove the uploads untouched for the TTL
+fn sweep_dir(ttl: Duration, dry_run: bool) -> io::Result<Sweep> {
+    let mut sweep = Sweep::default();
+    for entry in fs::read_dir("uploads")? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let path = format!("uploads/{}", name);
+        // Claimed by a sweep that was interrupted, and judged again
+        if let Some(rest) = name.strip_prefix(TOMBSTONE_PREFIX) {
+            if !dry_run {
+                let _ = fs::rename(&path, format!("uploads/{}{}", INCOMING_PREFIX, rest));
+            }
+            continue;
+        }
+        let Some(rest) = name.strip_prefix(INCOMING_PREFIX) else {
+            continue;
+        };
+        if !entry.metadata().is_ok_and(|metadata| is_stale(&metadata, ttl)) {
+            continue;
+        }
+        if dry_run {
+            sweep.found += 1;
+            continue;
+        }
+
+        let tombstone = format!("uploads/{}{}", TOMBSTONE_PREFIX, rest);
+        match fs::rename(&path, &tombstone) {
+            Ok(()) => {}
+            // Moved into place by its submission meanwhile
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                println!("Failed to claim abandoned upload {}: {}", path, e);
+                continue;
+            }
+        }
+        let metadata = fs::metadata(&tombstone)?;
+        if !is_stale(&metadata, ttl) {
+            fs::rename(&tombstone, &path)?;
+            sweep.released += 1;
+            continue;
+        }
+        fs::remove_file(&tombstone)?;
+        sweep.reclaimed.push((path, metadata.len()));
+    }
+    Ok(sweep)
+}
+
+// Background task removing abandoned uploads, idle when
+// UPLOAD_ABANDONED_TTL_SECS is 0
+pub async fn sweeper(state: Arc<AppState>) {
+    let ttl = state.config.upload_abandoned_ttl_secs;
+    if ttl == 0 {
+        return;
+    }
+    let every = ttl.min(state.config.retention_sweep_interval_secs);
+    let mut interval = tokio::time::interval(Duration::from_secs(every));
+    let (ttl, dry_run) = (Duration::from_secs(ttl), state.config.upload_sweep_dry_run);
+    loop {
+        interval.tick().await;
+        state.maintenance.wait_until_off().await;
+        let sweep = tokio::task::spawn_blocking(move || sweep_dir(ttl, dry_run))
+            .await
+            .unwrap_or_else(|e| Err(io::Error::other(e)));
+        let sweep = match sweep {
+            Ok(sweep) => sweep,
+            Err(e) => {
+                println!("Abandoned upload sweep failed: {}", e);
+                continue;
+            }
+        };
+
+        for (path, bytes) in &sweep.reclaimed {
+            println!("Removed abandoned upload {} ({} bytes)", path, bytes);
+        }
+        if dry_run && sweep.found > 0 {
+            println!("Dry run: {} abandoned uploads would be removed", sweep.found);
+        }
+        let mut stats = state.abandoned_uploads.stats.lock().unwrap();
+        stats.sweeps += 1;
+        stats.reclaimed += sweep.reclaimed.len() as u64;
+        stats.reclaimed_bytes += sweep.reclaimed.iter().map(|(_, bytes)| bytes).sum::<u64>();
+        stats.released += sweep.released;
+        stats.dry_run_found = dry_run.then_some(sweep.found);
+        stats.last_sweep_at = Some(Utc::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejected_submission_releases_its_tenant() {
+        let id = Uuid::new_v4().to_string();
+        let mut transaction = UploadTransaction::default();
+        assert!(artifacts::claim_tenant(&id, "acme"));
+        transaction.track_tenant(&id);
+        assert!(!artifacts::claim_tenant(&id, "other"));
+        assert_eq!(artifacts::storage_key(&id), format!("acme/{}", id));
+        drop(transaction);
+        assert_eq!(artifacts::storage_key(&id), id);
+    }
+
+    #[test]
+    fn retried_id_releases_the_tenant_given_up() {
+        let (given_up, kept) = (Uuid::new_v4().to_string(), Uuid::new_v4().to_string());
+        let mut transaction = UploadTransaction::default();
+        artifacts::claim_tenant(&given_up, "acme");
+        transaction.track_tenant(&given_up);
+        artifacts::claim_tenant(&kept, "acme");
+        transaction.track_tenant(&kept);
+        assert_eq!(artifacts::storage_key(&given_up), given_up);
+        transaction.commit();
+        assert_eq!(artifacts::storage_key(&kept), format!("acme/{}", kept));
+        artifacts::release_tenant(&kept);
+    }
+}
@@ -3,11 +3,113 @@ import {
   zkVerifySession,
   Library,
   CurveType,
+  ProofType,
   ZkVerifyEvents,
 } from "zkverifyjs";
 import * as fs from "fs";
 import * as path from "path";
 
+// Exit status telling the server that the RPC endpoint could not be reached,
+// so nothing was submitted and another endpoint may be tried
+const EXIT_UNREACHABLE = 3;
+
+// With ZK_VERIFY_DEFER_ATTESTATION=1 the client stops once its transaction
+// is finalized and leaves waiting for the attestation to the server's poller
+const DEFER_ATTESTATION = process.env.ZK_VERIFY_DEFER_ATTESTATION === "1";
+
+/**
+ * Raised when no session could be opened with the RPC endpoint
+ */
+class EndpointUnreachable extends Error {}
+
+/**
+ * Start a session builder on the endpoint the server chose in
+ * ZK_VERIFY_RPC_URL, or on the testnet's own endpoint without one
+ */
+function network() {
+  const url = process.env.ZK_VERIFY_RPC_URL;
+  return url
+    ? zkVerifySession.start().Custom(url)
+    : zkVerifySession.start().Testnet();
+}
+
+/**
+ * Load the proof, public signals and verification key of a proof
+ * @param proofId The UUID of the proof
+ * @returns The parsed proof files and the proof directory
+ */
+function loadProofData(proofId: string) {
+  // Construct paths to proof files
+  const proofDir = path.join(process.cwd(), "proofs", proofId);
+  const proofPath = path.join(proofDir, "proof.json");
+  const publicPath = path.join(proofDir, "public.json");
+  // The server names the key of the circuit the proof was made with
+  const vkPath = path.join(
+    process.cwd(),
+    process.env.ZK_VERIFY_VKEY_PATH || path.join("keys", "verification_key.json"),
+  );
+
+  if (
+    !fs.existsSync(proofPath) ||
+    !fs.existsSync(publicPath) ||
+    !fs.existsSync(vkPath)
+  ) {
+    throw new Error(`Required files not found. Please check the paths.`);
+  }
+
+  // Read proof, public input files, and verification key
+  const proof = JSON.parse(fs.readFileSync(proofPath, "utf8"));
+  const publicSignals = JSON.parse(fs.readFileSync(publicPath, "utf8"));
+  const key = JSON.parse(fs.readFileSync(vkPath, "utf8"));
+
+  return { proofDir, proof, publicSignals, key };
+}
+
+/**
+ * Format the payload that would be submitted for a proof and write it to a
+ * file without submitting anything, so it can be compared with the payload
+ * built by the Rust backend
+ * @param proofId The UUID of the proof
+ * @param outPath Where to write the formatted payload
+ */
+export async function capturePayload(
+  proofId: string,
+  outPath: string,
+): Promise<void> {
+  const { proof, publicSignals, key } = loadProofData(proofId);
+
+  // A session without an account is read-only and never submits
+  const session = await network();
+
+  try {
+    const { formattedProof, formattedPubs, formattedVk } =
+      await session.format(
+        {
+          proofType: ProofType.groth16,
+          config: { library: Library.snarkjs, curve: CurveType.bn128 },
+        },
+        proof,
+        publicSignals,
+        key,
+      );
+
+    fs.writeFileSync(
+      outPath,
+      JSON.stringify(
+        {
+          proof: formattedProof,
+          publicSignals: formattedPubs,
+          vk: formattedVk,
+        },
+        null,
+        2,
+      ),
+    );
+  } finally {
+    await session.close();
+  }
+}
+
 /**
  * Submit a proof to the zkVerify network for verification
  * @param proofId The UUID of the proof to verify
@@ -17,24 +119,7 @@ export async function verifyProof(proofId: string): Promise<boolean> {
   try {
     console.log(`Submitting proof ${proofId} to zkVerify network...`);
 
-    // Construct paths to proof files
-    const proofDir = path.join(process.cwd(), "proofs", proofId);
-    const proofPath = path.join(proofDir, "proof.json");
-    const publicPath = path.join(proofDir, "public.json");
-    const vkPath = path.join(process.cwd(), "keys", "verification_key.json");
-
-    if (
-      !fs.existsSync(proofPath) ||
-      !fs.existsSync(publicPath) ||
-      !fs.existsSync(vkPath)
-    ) {
-      throw new Error(`Required files not found. Please check the paths.`);
-    }
-
-    // Read proof, public input files, and verification key
-    const proof = JSON.parse(fs.readFileSync(proofPath, "utf8"));
-    const publicSignals = JSON.parse(fs.readFileSync(publicPath, "utf8"));
-    const key = JSON.parse(fs.readFileSync(vkPath, "utf8"));
+    const { proofDir, proof, publicSignals, key } = loadProofData(proofId);
 
     console.log("Loaded proof data and verification key");
 
@@ -46,21 +131,23 @@ export async function verifyProof(proofId: string): Promise<boolean> {
       );
     }
 
-    // Start a session with zkVerify network
-    const session = await zkVerifySession
-      .start()
-      .Testnet() // Use testnet network
-      .withAccount(seedPhrase); // Use account from seed phrase
+    // Start a session with zkVerify network using the seed phrase's account
+    let session;
+    try {
+      session = await network().withAccount(seedPhrase);
+    } catch (error) {
+      throw new EndpointUnreachable(String(error));
+    }
 
     console.log("Connected to zkVerify network");
 
     try {
       // Execute verification with the provided key
-      const { events, transactionResult } = await session
-        .verify()
-        .groth16(Library.snarkjs, CurveType.bn128)
-        .waitForPublishedAttestation()
-        .execute({
+      let verifier = session.verify().groth16(Library.snarkjs, CurveType.bn128);
+      if (!DEFER_ATTESTATION) {
+        verifier = verifier.waitForPublishedAttestation();
+      }
+      const { events, transactionResult } = await verifier.execute({
           proofData: {
             proof: proof,
             publicSignals: publicSignals,
@@ -86,6 +173,7 @@ export async function verifyProof(proofId: string): Promise<boolean> {
         if (eventData.leafDigest && eventData.attestationId) {
           // Store the leaf digest mapped to the transaction ID
           leafDigest = eventData.leafDigest;
+          attestationId = eventData.attestationId;
           console.log(
             `Stored leaf digest ${eventData.leafDigest} for transaction ${eventData.attestationId}`,
           );
@@ -111,6 +199,18 @@ export async function verifyProof(proofId: string): Promise<boolean> {
       // Wait for the transaction to complete
       await transactionResult;
 
+      if (DEFER_ATTESTATION) {
+        // The server polls for the attestation this leaf will be part of
+        console.log(
+          `Deferring attestation ${attestationId} for leaf digest ${leafDigest}`,
+        );
+        fs.writeFileSync(
+          path.join(proofDir, "pending_attestation.json"),
+          JSON.stringify({ attestationId, leafDigest }, null, 2),
+        );
+        return true;
+      }
+
       console.log(
         `Calling poe with attestation ID: ${attestationId}, leaf digest: ${leafDigest}`,
       );
@@ -147,13 +247,92 @@ export async function verifyProof(proofId: string): Promise<boolean> {
       await session.close();
     }
   } catch (error) {
+    if (error instanceof EndpointUnreachable) {
+      throw error;
+    }
     console.error("Proof verification failed:", error);
     return false;
   }
 }
 
+/**
+ * A measurement whose attestation the server is waiting for
+ */
+interface AwaitedLeaf {
+  id: string;
+  attestationId: number;
+  leafDigest: string;
+}
+
+/**
+ * Look up the proofs of existence of a batch of leaves in one session and
+ * write those already published. Attestations are published in order of
+ * their ID, so once one is not published the higher ones are not looked up.
+ * @param inPath JSON array of awaited leaves
+ * @param outPath Where to write the published attestations by measurement ID
+ */
+export async function pollAttestations(
+  inPath: string,
+  outPath: string,
+): Promise<void> {
+  const leaves: AwaitedLeaf[] = JSON.parse(fs.readFileSync(inPath, "utf8"));
+  leaves.sort((a, b) => a.attestationId - b.attestationId);
+
+  // A session without an account is read-only and never submits
+  let session;
+  try {
+    session = await network();
+  } catch (error) {
+    throw new EndpointUnreachable(String(error));
+  }
+
+  let rpcCalls = 0;
+  const published: Record<string, object> = {};
+  try {
+    for (const leaf of leaves) {
+      rpcCalls++;
+      try {
+        const proofDetails = await session.poe(
+          leaf.attestationId,
+          leaf.leafDigest,
+        );
+        published[leaf.id] = {
+          attestationId: leaf.attestationId,
+          merklePath: proofDetails.proof,
+          leafCount: proofDetails.numberOfLeaves,
+          index: proofDetails.leafIndex,
+        };
+      } catch (error) {
+        console.log(
+          `Attestation ${leaf.attestationId} is not published yet: ${error}`,
+        );
+        break;
+      }
+    }
+  } finally {
+    await session.close();
+  }
+
+  fs.writeFileSync(outPath, JSON.stringify({ rpcCalls, published }, null, 2));
+}
+
+// --poll <leaves> <results> looks up attestations for the server's poller
+if (require.main === module && process.argv[2] === "--poll") {
+  const [inPath, outPath] = process.argv.slice(3, 5);
+  if (!inPath || !outPath) {
+    console.error("Please provide the leaves and results files after --poll");
+    process.exit(1);
+  }
+
+  pollAttestations(inPath, outPath)
+    .then(() => process.exit(0))
+    .catch((error) => {
+      console.error("Error:", error);
+      process.exit(error instanceof EndpointUnreachable ? EXIT_UNREACHABLE : 1);
+    });
+}
 // If this script is called directly with a proof ID
-if (require.main === module) {
+else if (require.main === module) {
   // Check if proof ID was provided as command line argument
   const proofId = process.argv[2];
 
@@ -162,13 +341,35 @@ if (require.main === module) {
     process.exit(1);
   }
 
-  verifyProof(proofId)
-    .then((result) => {
-      console.log(`Proof verification ${result ? "succeeded" : "failed"}`);
-      process.exit(result ? 0 : 1);
-    })
-    .catch((error) => {
-      console.error("Error:", error);
+  // --capture <file> only formats the payload, for parity checks
+  const captureIndex = process.argv.indexOf("--capture");
+  if (captureIndex !== -1) {
+    const outPath = process.argv[captureIndex + 1];
+    if (!outPath) {
+      console.error("Please provide an output file after --capture");
       process.exit(1);
-    });
+    }
+
+    capturePayload(proofId, outPath)
+      .then(() => {
+        console.log(`Captured submission payload to ${outPath}`);
+        process.exit(0);
+      })
+      .catch((error) => {
+        console.error("Error:", error);
+        process.exit(1);
+      });
+  } else {
+    verifyProof(proofId)
+      .then((result) => {
+        console.log(`Proof verification ${result ? "succeeded" : "failed"}`);
+        process.exit(result ? 0 : 1);
+      })
+      .catch((error) => {
+        console.error("Error:", error);
+        process.exit(
+          error instanceof EndpointUnreachable ? EXIT_UNREACHABLE : 1,
+        );
+      });
+  }
 }
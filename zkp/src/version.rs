@@ -0,0 +1,85 @@
+// Build and circuit provenance.
+//
+// Every measurement records which server build and which circuit keys it was
+// created and proved with, and proofs also the node and snarkjs versions, so
+// old proofs can be traced back to the exact artifacts that produced them.
+
+use axum::{extract::State, response::Json};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{fs::File, io, sync::Arc};
+
+use crate::{
+    AppState, circuit::Circuit, safety::Verdict, startup::StartupReport, toolchain::Toolchain,
+};
+
+pub const SERVER_VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_HASH: &str = env!("ZKHOTDOG_GIT_HASH");
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+pub struct VersionStamp {
+    #[serde(rename = "serverVersion")]
+    pub server_version: String,
+    #[serde(rename = "gitHash")]
+    pub git_hash: String,
+    #[serde(rename = "circuitVersion")]
+    pub circuit_version: String,
+    // SHA-256 of the proving and verification keys, when present on disk
+    #[serde(rename = "zkeyHash")]
+    pub zkey_hash: Option<String>,
+    #[serde(rename = "vkeyHash")]
+    pub vkey_hash: Option<String>,
+    // node and snarkjs versions, recorded for proofs and reported as currently active
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub toolchain: Option<Toolchain>,
+}
+
+impl VersionStamp {
+    // Stamp for the running binary and the circuit keys currently on disk
+    pub fn current(circuit: &Circuit) -> Self {
+        VersionStamp {
+            server_version: SERVER_VERSION.to_string(),
+            git_hash: GIT_HASH.to_string(),
+            circuit_version: circuit.version.to_string(),
+            zkey_hash: hash_file(circuit.zkey_path),
+            vkey_hash: hash_file(circuit.vkey_path),
+            toolchain: None,
+        }
+    }
+}
+
+// Versions a measurement was created and proved with
+#[derive(Debug, Serialize, Deserialize, Clone, Default, JsonSchema)]
+pub struct MeasurementVersions {
+    pub created: Option<VersionStamp>,
+    pub proved: Option<VersionStamp>,
+}
+
+// Hex-encoded SHA-256 of a file, or None if it cannot be read
+pub fn hash_file(path: &str) -> Option<String> {
+    let mut file = File::open(path).ok()?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).ok()?;
+    Some(hex::encode(hasher.finalize()))
+}
+
+// Response of GET /version
+#[derive(Serialize)]
+pub struct VersionInfo {
+    #[serde(flatten)]
+    stamp: VersionStamp,
+    safety: Verdict,
+    startup: StartupReport,
+}
+
+// Handler reporting the running build, the circuit artifacts loaded at startup,
+// the proving toolchain currently in use, the startup safety verdict and how
+// long the startup took
+pub async fn version(State(state): State<Arc<AppState>>) -> Json<VersionInfo> {
+    Json(VersionInfo {
+        stamp: VersionStamp { toolchain: Some(state.toolchain.get()), ..state.versions.clone() },
+        safety: state.safety.clone(),
+        startup: state.startup.report(),
+    })
+}
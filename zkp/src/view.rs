@@ -0,0 +1,153 @@
+// Human-readable HTML status page for a measurement.
+//
+// Measurements under embargo are reported as not found until published.
+//
+// Every value interpolated into the page goes through escape_html, including
+// the id taken from the URL, so user-supplied metadata can never inject markup.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Html,
+};
+use std::sync::Arc;
+
+use crate::{AppState, Measurement, access, metadata::escape_html, reprove, tenants::Visitor};
+
+// Handler rendering the status page
+pub async fn status_page(
+    State(state): State<Arc<AppState>>,
+    Visitor(caller): Visitor,
+    Path(id): Path<String>,
+) -> Result<Html<String>, (StatusCode, Html<String>)> {
+    // Embargoed measurements and those of other tenants are indistinguishable
+    // from missing ones
+    let (measurement, generations) = {
+        let measurements = state.measurements.lock();
+        match measurements.get(&id) {
+            Some(m) => (Some(m.clone()), reprove::generations(&measurements, m)),
+            None => (None, Vec::new()),
+        }
+    };
+    let Some(measurement) =
+        measurement.filter(|m| access::may_see_published(&caller, m, state.time.now()))
+    else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Html(format!("<p>Measurement {} not found</p>", escape_html(&id))),
+        ));
+    };
+    Ok(Html(render(&measurement, &generations)))
+}
+
+// The status page of a measurement the caller may see
+fn render(measurement: &Measurement, generations: &[reprove::Generation]) -> String {
+    let id = escape_html(&measurement.id);
+    let title =
+        measurement.metadata.label.as_deref().map(escape_html).unwrap_or_else(|| id.clone());
+
+    let mut details = String::new();
+    details.push_str(&format!(
+        "<dt>Status</dt><dd>{}</dd>",
+        escape_html(&format!("{:?}", measurement.status))
+    ));
+    match (&measurement.original_points, measurement.min_length_cm) {
+        // Threshold measurements keep their points to themselves
+        (_, Some(min_length_cm)) => {
+            details.push_str(&format!("<dt>Length</dt><dd>At least {} cm</dd>", min_length_cm));
+        }
+        (Some(points), None) => {
+            for (name, point) in [("Start", &points.start), ("End", &points.end)] {
+                details.push_str(&format!(
+                    "<dt>{}</dt><dd>({}, {}, {}) m</dd>",
+                    name, point.x, point.y, point.z
+                ));
+            }
+            details.push_str(&format!(
+                "<dt>Coordinates</dt><dd>{}</dd>",
+                measurement.coordinate_system
+            ));
+        }
+        (None, None) => details.push_str("<dt>Points</dt><dd>Not recorded</dd>"),
+    }
+    if measurement.imported {
+        details.push_str("<dt>Source</dt><dd>Imported offline bundle</dd>");
+    }
+    if let Some(captured_at) = measurement.captured_at {
+        details.push_str(&format!("<dt>Captured</dt><dd>{}</dd>", captured_at.to_rfc3339()));
+    }
+    if let Some(attestation) = &measurement.attestation {
+        let network =
+            measurement.attestation_backend.map(|b| format!(" on {}", b)).unwrap_or_default();
+        details.push_str(&format!(
+            "<dt>Attestation</dt><dd>{}{}</dd>",
+            attestation.attestation_id, network
+        ));
+    }
+    if !generations.is_empty() {
+        let items: Vec<String> = generations.iter().map(reprove::Generation::to_html).collect();
+        details.push_str(&format!("<dt>Proofs</dt><dd><ol>{}</ol></dd>", items.concat()));
+    }
+    if let Some(notes) = &measurement.metadata.notes {
+        details.push_str(&format!(
+            "<dt>Notes</dt><dd>{}</dd>",
+            escape_html(notes).replace('\n', "<br>")
+        ));
+    }
+    if !measurement.metadata.tags.is_empty() {
+        let tags: Vec<String> = measurement
+            .metadata
+            .tags
+            .iter()
+            .map(|tag| format!("<li>{}</li>", escape_html(tag)))
+            .collect();
+        details.push_str(&format!("<dt>Tags</dt><dd><ul>{}</ul></dd>", tags.concat()));
+    }
+
+    let second_image = if measurement.image_paths.len() > 1 {
+        format!("<img src=\"/img/{id}/2\" alt=\"{title}, second image\" style=\"max-width:100%\">")
+    } else {
+        String::new()
+    };
+
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{title} - zkHotdog</title></head>\
+         <body><h1>{title}</h1><p>Measurement {id}</p><dl>{details}</dl>\
+         <img src=\"/img/{id}\" alt=\"{title}\" style=\"max-width:100%\">{second_image}</body></html>"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ProofStatus, metadata};
+
+    fn measurement(metadata: &str) -> Measurement {
+        let mut m = Measurement::fixture("m");
+        m.status = ProofStatus::Completed;
+        m.metadata = metadata::parse(metadata.as_bytes()).unwrap();
+        m
+    }
+
+    #[test]
+    fn metadata_cannot_inject_markup() {
+        let page = render(
+            &measurement(
+                r#"{"label": "<script>alert(1)</script>", "notes": "a\"><img src=x onerror=alert(1)>\nb", "tags": ["x"]}"#,
+            ),
+            &[],
+        );
+        assert!(!page.contains("<script>"));
+        assert!(!page.contains("<img src=x"));
+        assert!(page.contains("<title>&lt;script&gt;alert(1)&lt;/script&gt; - zkHotdog</title>"));
+        assert!(page.contains("alt=\"&lt;script&gt;alert(1)&lt;/script&gt;\""));
+        assert!(page.contains("a&quot;&gt;&lt;img src=x onerror=alert(1)&gt;<br>b"));
+    }
+
+    #[test]
+    fn bidirectional_overrides_do_not_reach_the_page() {
+        let page = render(&measurement("{\"label\": \"cod\\u202egnp.exe\"}"), &[]);
+        assert!(page.contains("<h1>codgnp.exe</h1>"));
+        assert!(!page.contains('\u{202e}'));
+    }
+}
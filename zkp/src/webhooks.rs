@@ -0,0 +1,257 @@
+// Webhook callbacks for finished measurements.
+//
+// A submission may name a callbackUrl. When the measurement reaches Completed
+// or Failed, and again when its attestation is attached after completion, the
+// measurement record is POSTed there as JSON, without its history. Receivers
+// authenticate the body by its X-Zkhotdog-Signature header, "sha256=" and the
+// hex HMAC-SHA256 of the body under WEBHOOK_SECRET; X-Zkhotdog-Event names
+// what happened.
+//
+// Callbacks need WEBHOOK_SECRET, and submissions naming one are refused
+// without it. A delivery answered with a 5xx status, or without any answer,
+// is retried with exponential backoff up to WEBHOOK_MAX_ATTEMPTS attempts;
+// other answers end it. The measurement's webhook field records the last
+// delivery and how it went, so /status/{id} shows whether it arrived.
+// Deliveries in progress are not resumed after a restart.
+//
+// Like an imageUrl, a callback URL must not reach what only the server can:
+// deliveries connect through the resolver of image_url.rs, and one whose host
+// resolves to a loopback, private, link-local or otherwise non-public address
+// fails without being sent or retried. WEBHOOK_ALLOW_PRIVATE lifts this for
+// local development; the production audit refuses it (safety.rs).
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{net::IpAddr, sync::Arc, time::Duration};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    AppState, ProofStatus, config::Config, events::EventKind, image_url, support::hmac_sha256,
+    threshold,
+};
+
+// Longest a single delivery attempt may take
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    Completed,
+    Failed,
+    // The attestation was attached after the measurement completed
+    Attestation,
+}
+
+impl WebhookEvent {
+    fn name(self) -> &'static str {
+        match self {
+            WebhookEvent::Completed => "completed",
+            WebhookEvent::Failed => "failed",
+            WebhookEvent::Attestation => "attestation",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Delivered,
+    // The last attempt failed and another one follows
+    Retrying,
+    Failed,
+}
+
+// The last webhook delivery of a measurement
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct WebhookDelivery {
+    pub event: WebhookEvent,
+    pub outcome: Outcome,
+    pub attempts: u32,
+    // HTTP status the receiver answered the last attempt with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_status: Option<u16>,
+    // Why the last attempt failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    // When the last attempt was made
+    pub at: DateTime<Utc>,
+}
+
+pub struct Webhooks {
+    secret: Option<String>,
+    max_attempts: u32,
+    allow_private: bool,
+}
+
+impl Webhooks {
+    pub fn new(config: &Config) -> Self {
+        Webhooks {
+            secret: config.webhook_secret.clone(),
+            max_attempts: config.webhook_max_attempts,
+            allow_private: config.webhook_allow_private,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.secret.is_some()
+    }
+}
+
+// The callback URL of a submission, refused when webhooks are off
+pub fn validate_url(webhooks: &Webhooks, value: &str) -> Result<String, String> {
+    if !webhooks.enabled() {
+        return Err("callbackUrl is not accepted: webhooks are not enabled on this server".into());
+    }
+    image_url::validate_url("callbackUrl", value)
+}
+
+// Deliver the measurement to its callback URL, if it has one, in the background
+pub fn notify(state: &Arc<AppState>, id: &str, event: WebhookEvent) {
+    if !state.webhooks.enabled() {
+        return;
+    }
+    let has_callback = state.measurements.lock().get(id).is_some_and(|m| m.callback_url.is_some());
+    if has_callback {
+        tokio::spawn(deliver(state.clone(), id.to_string(), event));
+    }
+}
+
+// Why an attempt got no answer
+enum SendError {
+    // The callback host resolves to an address the server must not reach
+    Private(IpAddr),
+    Failed(String),
+}
+
+// Outcome of one attempt: the status answered, or why there was no answer
+async fn post(
+    url: &str,
+    allow_private: bool,
+    event: WebhookEvent,
+    signature: &str,
+    body: Vec<u8>,
+) -> Result<u16, SendError> {
+    let url = url.to_string();
+    let signature = format!("sha256={}", signature);
+    let (agent, refused) = image_url::public_agent(allow_private);
+    // Redirects are not followed, so the body only goes where the submitter said
+    let agent = agent.timeout(SEND_TIMEOUT).redirects(0).build();
+    let request = tokio::task::spawn_blocking(move || {
+        match agent
+            .post(&url)
+            .set("Content-Type", "application/json")
+            .set("X-Zkhotdog-Event", event.name())
+            .set("X-Zkhotdog-Signature", &signature)
+            .send_bytes(&body)
+        {
+            Ok(response) => Ok(response.status()),
+            Err(ureq::Error::Status(status, _)) => Ok(status),
+            Err(e) => Err(match *refused.lock().unwrap() {
+                Some(ip) => SendError::Private(ip),
+                None => SendError::Failed(e.to_string()),
+            }),
+        }
+    });
+    request.await.map_err(|e| SendError::Failed(e.to_string()))?
+}
+
+async fn deliver(state: Arc<AppState>, id: String, event: WebhookEvent) {
+    let Some(secret) = state.webhooks.secret.as_deref() else {
+        return;
+    };
+    let (url, body) = {
+        let measurements = state.measurements.lock();
+        let Some(m) = measurements.get(&id) else {
+            return;
+        };
+        let Some(url) = m.callback_url.clone() else {
+            return;
+        };
+        let mut m = m.clone();
+        // Served by /measurements/{id}/history instead
+        m.history.clear();
+        let mut body = Vec::new();
+        match threshold::serialize_public(&m, &mut serde_json::Serializer::new(&mut body)) {
+            Ok(()) => (url, body),
+            Err(e) => {
+                println!("Failed to serialize measurement {} for its webhook: {}", id, e);
+                return;
+            }
+        }
+    };
+    let signature = hmac_sha256(secret.as_bytes(), &body);
+
+    let max_attempts = state.webhooks.max_attempts.max(1);
+    let allow_private = state.webhooks.allow_private;
+    let mut backoff = INITIAL_BACKOFF;
+    for attempt in 1..=max_attempts {
+        let (response_status, error, retry) =
+            match post(&url, allow_private, event, &signature, body.clone()).await {
+                Ok(status) if (200..300).contains(&status) => (Some(status), None, false),
+                Ok(status) => (Some(status), Some(format!("answered {}", status)), status >= 500),
+                Err(SendError::Private(ip)) => {
+                    (None, Some(format!("resolves to the non-public address {}", ip)), false)
+                }
+                Err(SendError::Failed(e)) => (None, Some(e), true),
+            };
+        let outcome = match (&error, retry && attempt < max_attempts) {
+            (None, _) => Outcome::Delivered,
+            (Some(_), true) => Outcome::Retrying,
+            (Some(_), false) => Outcome::Failed,
+        };
+        match &error {
+            None => println!("Webhook {} of measurement {} delivered", event.name(), id),
+            Some(e) => println!(
+                "Webhook {} of measurement {} failed (attempt {}/{}): {}",
+                event.name(),
+                id,
+                attempt,
+                max_attempts,
+                e
+            ),
+        }
+        if let Some(m) = state.measurements.lock().get_mut(&id) {
+            m.webhook = Some(WebhookDelivery {
+                event,
+                outcome,
+                attempts: attempt,
+                response_status,
+                error,
+                at: state.time.now(),
+            });
+        }
+        if outcome != Outcome::Retrying {
+            return;
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+// Deliver the webhooks of measurements reaching Completed or Failed
+pub async fn dispatcher(state: Arc<AppState>) {
+    if !state.webhooks.enabled() {
+        return;
+    }
+    let mut events = state.events.subscribe();
+    loop {
+        match events.recv().await {
+            Ok(event) if event.kind == EventKind::StatusChanged => {
+                let event_kind = match event.status {
+                    ProofStatus::Completed => WebhookEvent::Completed,
+                    ProofStatus::Failed => WebhookEvent::Failed,
+                    _ => continue,
+                };
+                notify(&state, &event.id, event_kind);
+            }
+            Ok(_) => {}
+            Err(RecvError::Lagged(missed)) => {
+                println!("Webhook dispatcher fell behind, {} events not delivered", missed);
+            }
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
@@ -0,0 +1,146 @@
+// WebSocket feed of measurement activity.
+//
+// GET /ws upgrades to a WebSocket that pushes every event of the event bus as
+// one JSON text message, so a dashboard can follow all measurements over a
+// single connection instead of polling each of them. A new measurement shows
+// up as its first status change, to Pending or Scheduled. Tenant keys only
+// receive the events of their own tenant.
+//
+// Clients narrow the feed by sending {"ids": [...]}, which replaces the ids
+// followed so far, and widen it again with {"ids": null}. Every subscription
+// message is answered with {"subscribed": ...} naming what is followed now,
+// or with {"error": ...} leaving the subscription as it was.
+//
+// The proof pipeline never waits for a client: publishing on the bus does not
+// block, and a client reading too slowly to keep up with it, or one a message
+// cannot be written to within SEND_TIMEOUT, is closed with 1013 (try again
+// later) and has to reconnect and catch up through /status/{id}.
+
+use axum::{
+    body::Bytes,
+    extract::{
+        State, WebSocketUpgrade,
+        ws::{CloseFrame, Message, WebSocket, close_code},
+    },
+    response::Response,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::{collections::BTreeSet, sync::Arc, time::Duration};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+    AppState,
+    auth::{Authorized, Caller, Viewer},
+    events::MeasurementEvent,
+};
+
+// Most ids one subscription may follow
+const MAX_SUBSCRIBED_IDS: usize = 1000;
+// Longest a message may take to be written before the client is dropped
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+// Interval of pings keeping idle connections open through proxies
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Subscription {
+    ids: Option<BTreeSet<String>>,
+}
+
+// The ids a subscription message asks for, None for every measurement
+fn parse_subscription(text: &str) -> Result<Option<BTreeSet<String>>, String> {
+    let subscription: Subscription = serde_json::from_str(text)
+        .map_err(|e| format!("Expected {{\"ids\": [...]}} or {{\"ids\": null}}: {}", e))?;
+    if let Some(ids) = &subscription.ids
+        && ids.len() > MAX_SUBSCRIBED_IDS
+    {
+        return Err(format!(
+            "At most {} ids may be followed at once, got {}",
+            MAX_SUBSCRIBED_IDS,
+            ids.len()
+        ));
+    }
+    Ok(subscription.ids)
+}
+
+// Write a message, failing if the client does not take it within SEND_TIMEOUT
+async fn send(socket: &mut WebSocket, message: Message) -> Result<(), String> {
+    match tokio::time::timeout(SEND_TIMEOUT, socket.send(message)).await {
+        Ok(sent) => sent.map_err(|e| e.to_string()),
+        Err(_) => Err("timed out".to_string()),
+    }
+}
+
+// Push the events the caller may see until either side closes
+async fn follow(
+    mut socket: WebSocket,
+    caller: Caller,
+    mut events: broadcast::Receiver<MeasurementEvent>,
+) {
+    let mut ids: Option<BTreeSet<String>> = None;
+    let mut ping = tokio::time::interval(PING_INTERVAL);
+    ping.reset();
+
+    loop {
+        let message = tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => {
+                    if !caller.can_access(event.tenant.as_deref())
+                        || ids.as_ref().is_some_and(|ids| !ids.contains(&event.id))
+                    {
+                        continue;
+                    }
+                    match serde_json::to_string(&event) {
+                        Ok(text) => Message::Text(text.into()),
+                        Err(e) => {
+                            println!("Failed to serialize event of {} for a WebSocket: {}", event.id, e);
+                            continue;
+                        }
+                    }
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    println!("Closing WebSocket of {}: {} events behind", caller.name, missed);
+                    let close = CloseFrame {
+                        code: close_code::AGAIN,
+                        reason: "Too slow to keep up with the events, reconnect".into(),
+                    };
+                    let _ = send(&mut socket, Message::Close(Some(close))).await;
+                    return;
+                }
+                Err(RecvError::Closed) => return,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let reply = match parse_subscription(&text) {
+                        Ok(subscribed) => {
+                            ids = subscribed;
+                            json!({ "subscribed": ids })
+                        }
+                        Err(message) => json!({ "error": message }),
+                    };
+                    Message::Text(reply.to_string().into())
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                // Pings are answered by the socket itself
+                Some(Ok(_)) => continue,
+            },
+            _ = ping.tick() => Message::Ping(Bytes::new()),
+        };
+        if let Err(e) = send(&mut socket, message).await {
+            println!("Dropping WebSocket of {}: {}", caller.name, e);
+            return;
+        }
+    }
+}
+
+// Handler upgrading to the activity feed
+pub async fn socket(
+    State(state): State<Arc<AppState>>,
+    Authorized(caller, _): Authorized<Viewer>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    // Subscribe before the handshake, so no event after it is missed
+    let events = state.events.subscribe();
+    upgrade.on_upgrade(move |socket| follow(socket, caller, events))
+}
@@ -0,0 +1,69 @@
+#!/bin/bash
+set -e
+
+# Test the sweep of abandoned uploads. Starts its own server with the mock
+# toolchain in test_vectors/mock_toolchain and UPLOAD_ABANDONED_TTL_SECS=3 and
+# checks that:
+#   - an unfinished upload left untouched for longer is removed, and one
+#     claimed by an interrupted sweep is judged again and removed too;
+#   - an unfinished upload still being written is kept;
+#   - submissions keep their images while the sweeps run;
+#   - /admin/stats counts the reclaimed uploads and their bytes;
+#   - with UPLOAD_SWEEP_DRY_RUN abandoned uploads are only counted.
+
+source "$(dirname "$0")/test_lib.sh"
+
+enter_work_dir
+mkdir -p uploads
+
+SERVER_ENV=(UPLOAD_ABANDONED_TTL_SECS=3 RETENTION_SWEEP_INTERVAL_SECS=1)
+
+exists() {
+  [ -e "$1" ] && echo present || echo absent
+}
+
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' | jq -r .measurement_id
+}
+
+# Uploads left behind by a server that died mid-upload, and by a sweep
+head -c 1000 /dev/zero > uploads/.incoming-stale
+head -c 500 /dev/zero > uploads/.abandoned-claimed
+touch -d '1 hour ago' uploads/.incoming-stale uploads/.abandoned-claimed
+touch uploads/.incoming-live
+
+start_server
+IDS=()
+for _ in $(seq 1 8); do
+  touch uploads/.incoming-live
+  IDS+=("$(submit)")
+  sleep 0.5
+done
+for _ in $(seq 1 8); do
+  touch uploads/.incoming-live
+  sleep 0.5
+done
+
+check "stale upload" "$(exists uploads/.incoming-stale)" "absent"
+check "claimed upload" "$(exists uploads/.incoming-claimed) $(exists uploads/.abandoned-claimed)" \
+  "absent absent"
+check "upload being written" "$(exists uploads/.incoming-live)" "present"
+MISSING=0
+for id in "${IDS[@]}"; do
+  [ -f "uploads/$id.jpg" ] || MISSING=$((MISSING + 1))
+done
+check "images of submissions" "$MISSING" "0"
+STATS=$(curl -s "$SERVER_URL/admin/stats" | jq -c '.abandoned_uploads | [.reclaimed, .reclaimed_bytes]')
+check "reclaimed" "$STATS" "[2,1500]"
+
+touch -d '1 hour ago' uploads/.incoming-live
+start_server UPLOAD_SWEEP_DRY_RUN=true
+sleep 3
+check "kept in a dry run" "$(exists uploads/.incoming-live)" "present"
+check "found in a dry run" "$(curl -s "$SERVER_URL/admin/stats" |
+  jq -c '.abandoned_uploads | [.dry_run_found, .reclaimed]')" "[1,0]"
+
+finish "abandoned upload"
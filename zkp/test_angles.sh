@@ -0,0 +1,104 @@
+#!/bin/bash
+set -e
+
+# Test angle measurements. Starts its own server with the mock toolchain in
+# test_vectors/mock_toolchain and the angle circuit's files moved with
+# ANGLE_CIRCUIT_WASM and ANGLE_CIRCUIT_ZKEY, and checks that:
+#   - measurementType angle with a midPoint is proved with the angle circuit
+#     from those files, keeping the mid point and proving the numerator and
+#     squared denominator of the cosine, also when sent as JSON;
+#   - an angle without midPoint, a midPoint without angle, an angle with
+#     points, a mid point on an end point and an unknown measurementType are
+#     rejected with 400;
+#   - a proof of another cosine fails the measurement naming the signal, and
+#     an angle is refused by GET /attestation/{id}.
+#
+# Also needs base64.
+
+source "$(dirname "$0")/test_lib.sh"
+
+enter_work_dir
+mkdir -p angle
+touch angle/angle.wasm angle/angle.zkey
+
+start_server ANGLE_CIRCUIT_WASM=angle/angle.wasm ANGLE_CIRCUIT_ZKEY=angle/angle.zkey
+
+START='{"x": 0.1, "y": 0.2, "z": 0.3}'
+MID='{"x": 0.4, "y": -0.2, "z": 0.3}'
+END='{"x": -0.5, "y": -0.2, "z": 0.15}'
+
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" -F "image=@$IMAGE" "$@"
+}
+
+angle() {
+  submit -F "startPoint=$START" -F "endPoint=$END" "$@"
+}
+
+code() {
+  submit -o /dev/null -w '%{http_code}' "$@"
+}
+
+wait_status() {
+  local current
+  for _ in $(seq 1 30); do
+    current=$(curl -s "$SERVER_URL/status/$1" | jq -r .status)
+    [ "$current" == "$2" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+status() {
+  curl -s "$SERVER_URL/status/$1" | jq -c "$2"
+}
+
+check "angles advertised" "$(curl -s "$SERVER_URL/capabilities" | jq -c \
+  '[.features.angle_mode.enabled, (.circuits[] | select(.version == "zkhotdog-angle-v1") | .available)]')" \
+  '[true,true]'
+
+ANGLE=$(angle -F measurementType=angle -F "midPoint=$MID" | jq -r .measurement_id)
+check "angle proved" "$(wait_status "$ANGLE" Completed)" "Completed"
+check "angle circuit" "$(status "$ANGLE" '[.measurement_type, .circuit]')" \
+  '["angle","zkhotdog-angle-v1"]'
+check "mid point kept" "$(status "$ANGLE" '[.mid_point, .original_points.mid]')" \
+  '[{"x":40000,"y":-20000,"z":30000},{"x":0.4,"y":-0.2,"z":0.3}]'
+# 0.5 m + sqrt(0.8325) m along both arms
+check "length of both arms" "$(status "$ANGLE" .length_cm)" "141.241"
+check "cosine proved" "$(cat "proofs/$ANGLE/public.json")" '["2700000000","20812500000000000000"]'
+check "configured files" "$(grep -c 'angle/generate_witness.js angle/angle.wasm' "proofs/$ANGLE/pipeline.log")" "1"
+check "configured key" "$(grep -c 'prove angle/angle.zkey' "proofs/$ANGLE/pipeline.log")" "1"
+
+BODY=$(jq -n --arg image "$(base64 -w0 "$IMAGE")" --argjson start "$START" --argjson mid "$MID" \
+  --argjson stop "$END" \
+  '{image: $image, measurementType: "angle", startPoint: $start, midPoint: $mid, endPoint: $stop}')
+AS_JSON=$(curl -s -X POST "$SERVER_URL/measurements/json" -H 'Content-Type: application/json' \
+  -d "$BODY" | jq -r .measurement_id)
+check "angle as JSON" "$(status "$AS_JSON" '[.measurement_type, .mid_point, .circuit]')" \
+  "$(status "$ANGLE" '[.measurement_type, .mid_point, .circuit]')"
+
+LENGTH=$(angle | jq -r .measurement_id)
+check "length by default" "$(status "$LENGTH" '[.measurement_type, .mid_point, .circuit]')" \
+  '["length",null,null]'
+
+check "without midPoint" "$(angle -F measurementType=angle | grep -c "Missing mid point data")" "1"
+check "midPoint of a length" "$(angle -F "midPoint=$MID" | grep -c "midPoint is only accepted")" "1"
+check "with points" "$(code -F measurementType=angle -F "midPoint=$MID" \
+  -F "points=[$START, $MID, $END]")" "400"
+check "mid on an end" "$(angle -F measurementType=angle -F "midPoint=$START" |
+  grep -c "midPoint coincides with startPoint")" "1"
+check "unknown type" "$(angle -F measurementType=area | grep -c "unknown measurement type 'area'")" "1"
+check "mid point out of range" "$(angle -F measurementType=angle -F 'midPoint={"x": 1e6, "y": 0, "z": 0}' |
+  grep -c "Invalid mid point")" "1"
+
+# The angle proved with another numerator
+echo '["1","20812500000000000000"]' > mock_public_signals
+WRONG=$(angle -F measurementType=angle -F "midPoint=$MID" | jq -r .measurement_id)
+check "wrong cosine" "$(wait_status "$WRONG" Failed)" "Failed"
+rm mock_public_signals
+check "signal named" "$(status "$WRONG" .failure.message |
+  grep -c 'cos_numerator 1 does not match expected 2700000000')" "1"
+
+check "not mintable" "$(curl -s "$SERVER_URL/attestation/$ANGLE" | jq -r .error)" "not_mintable"
+
+finish "angle"
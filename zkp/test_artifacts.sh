@@ -0,0 +1,140 @@
+#!/bin/bash
+set -e
+
+# Tests the artifact download endpoints: tails of multi-megabyte synthetic
+# logs, the response size guard, byte ranges, ETag revalidation, the
+# pipeline files, the proof document and the names they are offered under.
+# A measurement is submitted, and once its pipeline is done its log is
+# replaced by synthetic content with known lines.
+#
+# Unlike most tests this does not start a server: it needs one already
+# running at SERVER_URL (default http://localhost:3001) from the zkp
+# directory, as it rewrites the log under proofs/ itself. Needs jq; set
+# API_KEY if access control is on (the log needs an operator key).
+
+source "$(dirname "$0")/test_lib.sh"
+
+AUTH=()
+if [ -n "$API_KEY" ]; then
+  AUTH=(-H "X-Api-Key: $API_KEY")
+fi
+BODY=$(mktemp)
+HEADERS=$(mktemp)
+trap 'rm -f "$BODY" "$HEADERS"' EXIT
+
+# GET a path into $BODY and $HEADERS, printing the status code
+get() {
+  local path=$1
+  shift
+  curl -s -o "$BODY" -D "$HEADERS" -w "%{http_code}" "${AUTH[@]}" "$@" "$SERVER_URL$path"
+}
+
+header() {
+  grep -i "^$1:" "$HEADERS" | cut -d' ' -f2- | tr -d '\r'
+}
+
+if ! curl -s -o /dev/null "$SERVER_URL/health"; then
+  echo -e "${RED}No server at $SERVER_URL; start one from this directory first${NC}"
+  exit 1
+fi
+
+RESPONSE=$(curl -s -X POST "${AUTH[@]}" "$SERVER_URL/measurements" \
+  -F "image=@$IMAGE" \
+  -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+  -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}')
+ID=$(echo "$RESPONSE" | jq -r '.measurement_id // empty')
+if [ -z "$ID" ]; then
+  echo -e "${RED}Submission failed: $RESPONSE${NC}"
+  exit 1
+fi
+
+# Wait until nothing writes to the log any more
+for _ in $(seq 1 60); do
+  STATUS=$(curl -s "${AUTH[@]}" "$SERVER_URL/status/$ID" | jq -r .status)
+  [ "$STATUS" == "Completed" ] || [ "$STATUS" == "Failed" ] && break
+  sleep 1
+done
+LOG="proofs/$ID/pipeline.log"
+LIMIT=$(curl -s "$SERVER_URL/capabilities" | jq .limits.max_log_response_bytes)
+
+# Pipeline files
+check "input artifact" "$(get "/measurements/$ID/artifacts/input")" "200"
+cmp -s "$BODY" "proofs/$ID/input.json" && check "input artifact content" "same" "same" \
+  || check "input artifact content" "different" "same"
+check "input artifact length" "$(header Content-Length)" "$(wc -c < "proofs/$ID/input.json" | tr -d ' ')"
+check "unknown artifact" "$(get "/measurements/$ID/artifacts/secrets")" "404"
+
+# The proof and public signals as one document
+check "proof" "$(get "/proofs/$ID")" "200"
+check "proof content" "$(jq -cS .proof "$BODY")" "$(jq -cS . "proofs/$ID/proof.json")"
+check "public signals content" "$(jq -cS .public_signals "$BODY")" "$(jq -cS . "proofs/$ID/public.json")"
+check "proof of an unknown measurement" \
+  "$(get "/proofs/00000000-0000-0000-0000-000000000000") $(jq -r .error "$BODY")" "404 measurement_not_found"
+check "proof of a path" "$(get "/proofs/..%2F..%2Fetc%2Fpasswd") $(jq -r .error "$BODY")" \
+  "404 measurement_not_found"
+
+# File names: shown inline by default, saved with ?download=true
+SHORT_ID=${ID:0:8}
+get "/measurements/$ID/artifacts/input" > /dev/null
+check "inline input" "$(header Content-Disposition)" "inline; filename=\"zkhotdog-$SHORT_ID-input.json\""
+get "/measurements/$ID/artifacts/publicSignals?download=true" > /dev/null
+check "downloaded public signals" "$(header Content-Disposition)" \
+  "attachment; filename=\"zkhotdog-$SHORT_ID-public-signals.json\""
+get "/img/$ID" > /dev/null
+check "inline image" "$(header Content-Disposition)" "inline; filename=\"zkhotdog-$SHORT_ID.jpg\""
+get "/img/$ID?download=true" > /dev/null
+check "downloaded image" "$(header Content-Disposition)" "attachment; filename=\"zkhotdog-$SHORT_ID.jpg\""
+get "/measurements/$ID/log?download=true" > /dev/null
+check "downloaded log" "$(header Content-Disposition)" "attachment; filename=\"zkhotdog-$SHORT_ID-log.txt\""
+check "invalid download flag" "$(get "/img/$ID?download=maybe")" "400"
+
+# A short log without a final line break
+printf 'first\nsecond\nthird' > "$LOG"
+get "/measurements/$ID/log?tail=10" > /dev/null
+check "tail of a short log" "$(cat "$BODY")" "$(printf 'first\nsecond\nthird')"
+get "/measurements/$ID/log?tail=1" > /dev/null
+check "last line without line break" "$(cat "$BODY")" "third"
+check "invalid tail" "$(get "/measurements/$ID/log?tail=lots")" "400"
+
+# A multi-megabyte log with numbered lines
+seq -f "log line %08g of the synthetic pipeline output" 1 200000 > "$LOG"
+SIZE=$(wc -c < "$LOG" | tr -d ' ')
+echo "Synthetic log is $SIZE bytes, served up to $LIMIT at once"
+
+check "whole log over the limit" "$(get "/measurements/$ID/log")" "413"
+
+check "tail of 10 lines" "$(get "/measurements/$ID/log?tail=10")" "200"
+check "tail line count" "$(wc -l < "$BODY" | tr -d ' ')" "10"
+check "tail first line" "$(head -1 "$BODY")" "log line 00199991 of the synthetic pipeline output"
+check "tail last line" "$(tail -1 "$BODY")" "log line 00200000 of the synthetic pipeline output"
+check "tail length" "$(header Content-Length)" "$(wc -c < "$BODY" | tr -d ' ')"
+
+get "/measurements/$ID/log?tail=0" > /dev/null
+check "tail of 0 lines" "$(wc -c < "$BODY" | tr -d ' ')" "0"
+
+get "/measurements/$ID/log?tail=64KB" > /dev/null
+check "tail of 64KB" "$(wc -c < "$BODY" | tr -d ' ')" "65536"
+tail -c 65536 "$LOG" | cmp -s - "$BODY" && check "tail of 64KB content" "same" "same" \
+  || check "tail of 64KB content" "different" "same"
+
+get "/measurements/$ID/log?tail=150000" > /dev/null
+check "tail cut to the limit" "$(wc -c < "$BODY" | tr -d ' ')" "$LIMIT"
+check "cut tail ends the log" "$(tail -1 "$BODY")" "log line 00200000 of the synthetic pipeline output"
+
+# Ranges
+check "range" "$(get "/measurements/$ID/log" -H 'Range: bytes=0-99')" "206"
+check "range length" "$(wc -c < "$BODY" | tr -d ' ')" "100"
+check "range header" "$(header Content-Range)" "bytes 0-99/$SIZE"
+get "/measurements/$ID/log" -H 'Range: bytes=-20' > /dev/null
+check "suffix range" "$(cat "$BODY")" "$(tail -c 20 "$LOG")"
+check "range past the end" "$(get "/measurements/$ID/log" -H "Range: bytes=$SIZE-")" "416"
+
+# Revalidation
+get "/measurements/$ID/log?tail=10" > /dev/null
+ETAG=$(header ETag)
+check "unchanged tail" "$(get "/measurements/$ID/log?tail=10" -H "If-None-Match: $ETAG")" "304"
+echo "one more line" >> "$LOG"
+check "changed tail" "$(get "/measurements/$ID/log?tail=10" -H "If-None-Match: $ETAG")" "200"
+check "changed tail last line" "$(tail -1 "$BODY")" "one more line"
+
+finish "artifact"
@@ -0,0 +1,75 @@
+#!/bin/bash
+set -e
+
+# Test the attestation backends. Starts its own server with the mock
+# toolchain in test_vectors/mock_toolchain and checks that:
+#   - with ATTESTATION_BACKEND=local a measurement is attested without the
+#     zkVerify client, its status and status page name the backend, and
+#     attestation.json is written;
+#   - /capabilities reports the backend of each circuit and /version flags the
+#     local backend as unsafe for production, which refuses to start with it;
+#   - ATTESTATION_BACKEND_BY_CIRCUIT overrides the deployment's backend;
+#   - unknown backends are refused at startup.
+
+source "$(dirname "$0")/test_lib.sh"
+
+enter_work_dir
+
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' | jq -r .measurement_id
+}
+
+wait_status() {
+  local current
+  for _ in $(seq 1 30); do
+    current=$(curl -s "$SERVER_URL/status/$1" | jq -r .status)
+    [ "$current" == "$2" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+start_server ATTESTATION_BACKEND=local
+LOCAL=$(submit)
+check "attested locally" "$(wait_status "$LOCAL" Completed)" "Completed"
+check "backend in the status" \
+  "$(curl -s "$SERVER_URL/status/$LOCAL" | jq -c '[.attestation_backend, .attestation.leafCount]')" \
+  '["local",1]'
+check "attestation written" "$(jq -r .leafCount "proofs/$LOCAL/attestation.json")" "1"
+check "zkVerify client not run" "$(grep -c 'zkVerify network' server.log || true)" "0"
+check "backend on the status page" \
+  "$(curl -s "$SERVER_URL/view/$LOCAL" | grep -c ' on local</dd>' || true)" "1"
+check "backend in the capabilities" \
+  "$(curl -s "$SERVER_URL/capabilities" | jq -r '.circuits[0].attestation_backend')" "local"
+check "unsafe for production" \
+  "$(curl -s "$SERVER_URL/version" | jq -r '.safety.violations | index("local_attestations") != null')" \
+  "true"
+
+kill $SERVER_PID
+wait $SERVER_PID 2>/dev/null || true
+ALLOWED="--allow-unsafe=auth,cors,base_url,tmpfs"
+check "refused in production" "$(ATTESTATION_BACKEND=local timeout 10 "$BACKEND" --production \
+  "$ALLOWED" 2>&1 | grep -c 'local_attestations: attestations of zkhotdog-v1' || true)" "1"
+
+start_server ATTESTATION_BACKEND=zkverify ATTESTATION_BACKEND_BY_CIRCUIT=zkhotdog-v1:local
+OVERRIDDEN=$(submit)
+check "overridden for the circuit" "$(wait_status "$OVERRIDDEN" Completed)" "Completed"
+check "backend of the circuit" \
+  "$(curl -s "$SERVER_URL/status/$OVERRIDDEN" | jq -r .attestation_backend)" "local"
+
+start_server ATTESTATION_BACKEND=local ATTESTATION_BACKEND_BY_CIRCUIT=zkhotdog-v1:zkverify
+ZKVERIFY=$(submit)
+check "submitted to zkVerify" "$(wait_status "$ZKVERIFY" Completed)" "Completed"
+check "zkVerify backend" \
+  "$(curl -s "$SERVER_URL/status/$ZKVERIFY" | jq -r .attestation_backend)" "zkverify"
+check "zkVerify client run" "$(grep -c "Submitting proof $ZKVERIFY to zkVerify network" server.log)" "1"
+
+kill $SERVER_PID
+wait $SERVER_PID 2>/dev/null || true
+check "unknown backend" "$(ATTESTATION_BACKEND=ethereum timeout 10 "$BACKEND" 2>&1 |
+  grep -c "unknown attestation backend 'ethereum'" || true)" "1"
+
+finish "attestation backend"
@@ -0,0 +1,101 @@
+#!/bin/bash
+set -e
+
+# Attestation poller test. Starts its own server with the mock toolchain in
+# test_vectors/mock_toolchain and ATTESTATION_POLLING on, so submissions stop
+# at finalization and a single poller looks their attestations up. The mock
+# assigns the attestation ID in mock_attestation_id and publishes those up to
+# mock_published. Checks that:
+#   - measurements stay Processing until their attestation is published;
+#   - publishing one attestation completes exactly its measurements;
+#   - one never published fails after ATTESTATION_MAX_WAIT_SECS;
+#   - /attestation/{id} answers 409 until the attestation is published, then
+#     the arguments of mintWithAttestation with the leaf digest, and 404 once
+#     the measurement failed;
+#   - /admin/stats reports rounds, RPC calls and the distribution of waits.
+
+source "$(dirname "$0")/test_lib.sh"
+
+MAX_WAIT=15
+
+enter_work_dir
+echo 0 > mock_published
+start_server ATTESTATION_POLLING=true ATTESTATION_POLL_INTERVAL_SECS=1 \
+  ATTESTATION_BACKOFF_MAX_SECS=2 ATTESTATION_MAX_WAIT_SECS=$MAX_WAIT \
+  VK_HASH_BY_CIRCUIT=zkhotdog-v1:0x$(printf '11%.0s' $(seq 1 32))
+
+# Submit a measurement whose proof goes into the given attestation
+submit() {
+  echo "$1" > mock_attestation_id
+  local id
+  id=$(curl -s -X POST "$SERVER_URL/measurements" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' | jq -r '.measurement_id // empty')
+  # Wait for the submission, so the next one gets its own attestation ID
+  for _ in $(seq 1 60); do
+    [ -f "proofs/$id/pending_attestation.json" ] && break
+    sleep 0.2
+  done
+  echo "$id"
+}
+
+status() {
+  curl -s "$SERVER_URL/status/$1" | jq -r .status
+}
+
+# Wait until a measurement reaches a status, within the given seconds
+wait_for() {
+  local id="$1" expected="$2"
+  for _ in $(seq 1 $(($3 * 2))); do
+    [ "$(status "$id")" == "$expected" ] && break
+    sleep 0.5
+  done
+  status "$id"
+}
+
+FIRST=$(submit 1)
+SECOND=$(submit 1)
+LATER=$(submit 2)
+NEVER=$(submit 3)
+sleep 3
+check "awaiting" "$(curl -s "$SERVER_URL/admin/stats" | jq .attestations.awaiting)" "4"
+check "unpublished stays Processing" "$(status "$FIRST")" "Processing"
+check "contract attestation awaited" \
+  "$(curl -s -o response.json -w '%{http_code}' "$SERVER_URL/attestation/$FIRST") $(jq -r .error response.json)" \
+  "409 attestation_pending"
+
+# Attestation 1 completes exactly its two measurements
+echo 1 > mock_published
+check "first of attestation 1" "$(wait_for "$FIRST" Completed 10)" "Completed"
+check "second of attestation 1" "$(wait_for "$SECOND" Completed 10)" "Completed"
+check "attestation recorded" "$(curl -s "$SERVER_URL/status/$FIRST" | jq .attestation.attestationId)" "1"
+curl -s "$SERVER_URL/attestation/$FIRST" > response.json
+check "contract arguments" \
+  "$(jq -c '.mint_with_attestation | [._attestationId, ._merklePath, ._leafCount, ._index]' response.json)" \
+  '["1",[],"1","0"]'
+check "contract statement" "$(jq -r .mint_with_attestation.lengthInCm response.json)" \
+  "$(jq -r '.[0]' "proofs/$FIRST/public.json")"
+check "leaf digest" "$(jq -r '.leaf_digest | test("^0x[0-9a-f]{64}$")' response.json)" "true"
+check "same leaf for the same statement" \
+  "$(curl -s "$SERVER_URL/attestation/$SECOND" | jq -r .leaf_digest)" "$(jq -r .leaf_digest response.json)"
+check "attestation 2 still awaited" "$(status "$LATER")" "Processing"
+
+echo 2 > mock_published
+check "attestation 2" "$(wait_for "$LATER" Completed 10)" "Completed"
+
+# Attestation 3 is never published
+check "never published" "$(wait_for "$NEVER" Failed $((MAX_WAIT + 10)))" "Failed"
+check "failure class" "$(curl -s "$SERVER_URL/status/$NEVER" | jq -r .failure.class)" "Verification"
+check "contract attestation of a failed measurement" \
+  "$(curl -s -o response.json -w '%{http_code}' "$SERVER_URL/attestation/$NEVER") $(jq -r .error response.json)" \
+  "404 attestation_not_available"
+
+STATS=$(curl -s "$SERVER_URL/admin/stats" | jq .attestations)
+check "published" "$(echo "$STATS" | jq .published)" "3"
+check "timed out" "$(echo "$STATS" | jq .timed_out)" "1"
+check "wait samples" "$(echo "$STATS" | jq .wait_seconds.samples)" "3"
+# One call per published leaf plus at most one per round for the rest
+check "rpc calls bounded" "$(echo "$STATS" | jq '.rpc_calls <= .published + .rounds')" "true"
+
+finish "attestation poller"
@@ -0,0 +1,82 @@
+#!/bin/bash
+set -e
+
+# Test the backfill of derived measurement fields. Starts its own server with
+# the mock toolchain in test_vectors/mock_toolchain and DUPLICATE_MODE=off, so
+# submissions are stored without a perceptual hash, and with small, slow
+# backfill batches. Checks that:
+#   - new measurements get their image hash, dimensions and length at ingest;
+#   - a backfill fills in the missing perceptual hashes, leaves fields already
+#     set alone and reports per-field counts;
+#   - unknown fields are refused and a second backfill waits for the first;
+#   - a backfill left unfinished in BACKFILL_STATE_PATH resumes at startup
+#     under its job id.
+#
+# Also needs sha256sum.
+
+source "$(dirname "$0")/test_lib.sh"
+
+RESUMED_JOB="00000000-0000-4000-8000-000000000000"
+# Per-field counts of a job report as [updated, present, failed]
+COUNTS='[.updated, .present, .failed]'
+
+enter_work_dir
+
+SERVER_ENV=(DUPLICATE_MODE=off BACKFILL_BATCH_SIZE=2 BACKFILL_BATCH_DELAY_MS=1000)
+
+# Wait for a job to complete and print it
+wait_job() {
+  for _ in $(seq 1 30); do
+    JOB=$(curl -s "$SERVER_URL/admin/jobs/$1")
+    if [ "$(echo "$JOB" | jq -r .state)" == "Completed" ]; then
+      echo "$JOB"
+      return
+    fi
+    sleep 1
+  done
+  echo "$JOB"
+}
+
+# An unfinished backfill from before the restart
+cat > backfill.json <<JSON
+{"job_id": "$RESUMED_JOB", "tenant": null, "fields": ["length"],
+ "started_at": "2025-01-01T00:00:00Z", "total": 10, "cursor": "5", "processed": 5,
+ "counts": {"length": {"updated": 5, "present": 0, "failed": 0}}, "failures": [], "finished": false}
+JSON
+start_server
+
+JOB=$(wait_job "$RESUMED_JOB")
+check "resumed job" "$(echo "$JOB" | jq -r .state)" "Completed"
+check "resumed counts" "$(echo "$JOB" | jq -c ".report.counts.length | $COUNTS")" '[5,0,0]'
+check "resumed state saved" "$(jq -r .finished backfill.json)" "true"
+
+IDS=()
+for _ in 1 2 3; do
+  IDS+=("$(curl -s -X POST "$SERVER_URL/measurements" -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' | jq -r .measurement_id)")
+done
+STATUS=$(curl -s "$SERVER_URL/status/${IDS[0]}")
+check "image hash at ingest" "$(echo "$STATUS" | jq -r .image_sha256)" "$(sha256sum "$IMAGE" | cut -d' ' -f1)"
+check "dimensions at ingest" "$(echo "$STATUS" | jq -r '.image_dimensions.width > 0')" "true"
+check "length at ingest" "$(echo "$STATUS" | jq -r .length_cm)" "30"
+check "no perceptual hash" "$(echo "$STATUS" | jq -r '.perceptual_hash // "none"')" "none"
+
+STATUS=$(curl -s -o /dev/null -w '%{http_code}' -X POST "$SERVER_URL/admin/backfill?fields=colour")
+check "unknown field" "$STATUS" "400"
+
+STARTED=$(curl -s -X POST "$SERVER_URL/admin/backfill?fields=perceptual_hash,length")
+JOB_ID=$(echo "$STARTED" | jq -r .job_id)
+check "backfill total" "$(echo "$STARTED" | jq -r .total)" "3"
+STATUS=$(curl -s -o /dev/null -w '%{http_code}' -X POST "$SERVER_URL/admin/backfill")
+check "second backfill" "$STATUS" "409"
+
+JOB=$(wait_job "$JOB_ID")
+check "backfill job" "$(echo "$JOB" | jq -r .state)" "Completed"
+check "perceptual hashes" "$(echo "$JOB" | jq -c ".report.counts.perceptual_hash | $COUNTS")" '[3,0,0]'
+check "lengths" "$(echo "$JOB" | jq -c ".report.counts.length | $COUNTS")" '[0,3,0]'
+check "cursor" "$(echo "$JOB" | jq -r .report.cursor)" "$(printf '%s\n' "${IDS[@]}" | sort | tail -1)"
+check "perceptual hash stored" \
+  "$(curl -s "$SERVER_URL/status/${IDS[0]}" | jq -r '.perceptual_hash | length')" "16"
+
+finish "backfill"
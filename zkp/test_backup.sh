@@ -0,0 +1,110 @@
+#!/bin/bash
+set -e
+
+# Test the export and import of measurement records. Starts a server with the
+# mock toolchain in test_vectors/mock_toolchain and access control on, exports
+# its measurements, then starts a second server in a fresh directory, imports
+# them there and copies uploads/ and proofs/ over. Checks that:
+#   - only admins can export and import;
+#   - the export holds one record per measurement with its attestation;
+#   - completed measurements import with their image and external id, while
+#     lines that are not measurement records, ids that are not measurement ids
+#     and measurements still being proved are reported by line;
+#   - importing again skips the known ids, or replaces them with
+#     ?overwrite=true;
+#   - imported measurements survive a restart;
+#   - both operations are audited.
+
+source "$(dirname "$0")/test_lib.sh"
+
+ADMIN_KEY="backup-test-admin-key"
+
+enter_work_dir
+mkdir -p old/dist new/dist
+cd old
+
+SERVER_ENV=(ADMIN_API_KEY="$ADMIN_KEY")
+
+admin() {
+  curl -s -H "X-Api-Key: $ADMIN_KEY" "$@"
+}
+
+submit() {
+  admin -X POST "$SERVER_URL/measurements" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' \
+    -F "externalId=$1" | jq -r .measurement_id
+}
+
+status() {
+  admin "$SERVER_URL/status/$1" | jq -r .status
+}
+
+wait_status() {
+  local current
+  for _ in $(seq 1 30); do
+    current=$(status "$1")
+    [ "$current" == "$2" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+# Imported, overwritten and skipped counts and the lines of failed records
+import() {
+  admin -X POST -H 'Content-Type: application/x-ndjson' --data-binary "@$1" \
+    "$SERVER_URL/admin/import$2" | jq -c '[.imported, .overwritten, .skipped, [.errors[].line]]'
+}
+
+start_server
+FIRST=$(submit backup-1)
+SECOND=$(submit backup-2)
+check "first measurement" "$(wait_status "$FIRST" Completed)" "Completed"
+check "second measurement" "$(wait_status "$SECOND" Completed)" "Completed"
+echo 60 > mock_prove_delay
+PROVING=$(submit backup-3)
+check "measurement being proved" "$(wait_status "$PROVING" Processing)" "Processing"
+
+check "anonymous export" \
+  "$(curl -s -o /dev/null -w '%{http_code}' "$SERVER_URL/admin/export")" "403"
+check "export content type" \
+  "$(admin -o export.ndjson -w '%{content_type}' "$SERVER_URL/admin/export")" "application/x-ndjson"
+check "exported records" "$(jq -r .id export.ndjson | tr '\n' ' ')" "$FIRST $SECOND $PROVING "
+check "exported attestations" "$(jq -r '.attestation != null' export.ndjson | tr '\n' ' ')" \
+  "true true false "
+check "export audited" "$(jq -r 'select(.action == "measurements_exported") | .details.count' audit.log)" "3"
+
+cd ../new
+{
+  cat ../old/export.ndjson
+  echo 'not a measurement'
+  head -n 1 ../old/export.ndjson | jq -c '.id = "../../etc"'
+} > import.ndjson
+head -n 2 ../old/export.ndjson > completed.ndjson
+start_server
+
+check "anonymous import" "$(curl -s -o /dev/null -w '%{http_code}' -X POST \
+  --data-binary @import.ndjson "$SERVER_URL/admin/import")" "403"
+check "import" "$(import import.ndjson)" "[2,0,0,[3,4,5]]"
+check "imported statuses" "$(status "$FIRST") $(status "$SECOND")" "Completed Completed"
+check "measurement being proved not imported" \
+  "$(curl -s -o /dev/null -w '%{http_code}' -H "X-Api-Key: $ADMIN_KEY" "$SERVER_URL/status/$PROVING")" "404"
+# Copied before the first start, the files would be rebuilt into records
+# with less information, which only ?overwrite=true replaces
+cp -r ../old/uploads ../old/proofs .
+check "imported image" \
+  "$(curl -s -o /dev/null -w '%{http_code}' -H "X-Api-Key: $ADMIN_KEY" "$SERVER_URL/img/$FIRST")" "200"
+check "imported external id" \
+  "$(admin "$SERVER_URL/measurements/by-external-id/backup-2" | jq -r .id)" "$SECOND"
+check "import again" "$(import completed.ndjson)" "[0,0,2,[]]"
+check "import with overwrite" "$(import completed.ndjson '?overwrite=true')" "[0,2,0,[]]"
+check "overwritten statuses" "$(status "$FIRST") $(status "$SECOND")" "Completed Completed"
+
+start_server
+check "statuses after a restart" "$(status "$FIRST") $(status "$SECOND")" "Completed Completed"
+check "imports audited" \
+  "$(jq -c 'select(.action == "measurements_imported") | .details | [.imported, .overwritten, .skipped, .failed]' audit.log | tr '\n' ' ')" \
+  "[2,0,0,3] [0,0,2,0] [0,2,0,0] "
+
+finish "backup"
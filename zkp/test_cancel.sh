@@ -0,0 +1,94 @@
+#!/bin/bash
+set -e
+
+# Test cancellations. Starts its own server with the mock toolchain in
+# test_vectors/mock_toolchain and a single proof worker, keeps the worker busy
+# with a slow mock prover and checks that:
+#   - a queued measurement is cancelled and never proved;
+#   - cancelling the measurement being proved kills the prover, removes the
+#     files of the attempt but input.json and frees the worker, so the next
+#     measurement is proved at once;
+#   - the cancellation shows in the history;
+#   - cancelling a completed or already cancelled measurement is answered
+#     with 409 and an unknown one with 404.
+
+source "$(dirname "$0")/test_lib.sh"
+
+enter_work_dir
+
+start_server PROOF_WORKERS=1
+
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' | jq -r .measurement_id
+}
+
+status() {
+  curl -s "$SERVER_URL/status/$1" | jq -r .status
+}
+
+wait_status() {
+  local current
+  for _ in $(seq 1 10); do
+    current=$(status "$1")
+    [ "$current" == "$2" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+# The status a cancellation answers and the one it cancelled in
+cancel() {
+  curl -s -X POST "$SERVER_URL/measurements/$1/cancel" | jq -c '[.status, .previous_status]'
+}
+
+cancel_code() {
+  curl -s -o /dev/null -w '%{http_code}' -X POST "$SERVER_URL/measurements/$1/cancel"
+}
+
+# Mock provers running for a measurement
+provers() {
+  pgrep -fc "groth16 prove .*$1" || true
+}
+
+# The only worker is kept busy while the next measurement waits for it
+echo 60 > mock_prove_delay
+BUSY=$(submit)
+check "measurement being proved" "$(wait_status "$BUSY" Processing)" "Processing"
+QUEUED=$(submit)
+check "measurement queued" "$(status "$QUEUED")" "Pending"
+
+check "cancelling a queued measurement" "$(cancel "$QUEUED")" '["Cancelled","Pending"]'
+check "queued measurement cancelled" "$(status "$QUEUED")" "Cancelled"
+
+for _ in $(seq 1 10); do
+  [ "$(provers "$BUSY")" -gt 0 ] && break
+  sleep 1
+done
+check "prover running" "$(provers "$BUSY")" "1"
+check "cancelling a running proof" "$(cancel "$BUSY")" '["Cancelled","Processing"]'
+for _ in $(seq 1 10); do
+  [ "$(provers "$BUSY")" == "0" ] && break
+  sleep 1
+done
+check "prover killed" "$(provers "$BUSY")" "0"
+check "files of the attempt removed" "$(ls "proofs/$BUSY" | tr '\n' ' ')" "input.json "
+check "cancelled in the history" \
+  "$(curl -s "$SERVER_URL/measurements/$BUSY/history" | jq -r '[.transitions[] | "\(.to)@\(.source)"] | join(" ")')" \
+  "Pending@submission Processing@worker-1 Cancelled@cancellation"
+
+# Without the cancellation the worker would be busy for another minute
+rm mock_prove_delay
+NEXT=$(submit)
+check "next measurement proved" "$(wait_status "$NEXT" Completed)" "Completed"
+check "running proof stays cancelled" "$(status "$BUSY")" "Cancelled"
+check "queued measurement never proved" "$(status "$QUEUED")" "Cancelled"
+check "no proof of the queued measurement" "$(ls "proofs/$QUEUED" 2>/dev/null | grep -c '^proof.json$' || true)" "0"
+
+check "cancelling a completed measurement" "$(cancel_code "$NEXT")" "409"
+check "cancelling twice" "$(cancel_code "$BUSY")" "409"
+check "unknown measurement" "$(cancel_code 00000000-0000-0000-0000-000000000000)" "404"
+
+finish "cancellation"
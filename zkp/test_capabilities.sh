@@ -0,0 +1,51 @@
+#!/bin/bash
+set -e
+
+# Cross-checks GET /capabilities against the router. Every endpoint of an
+# enabled feature must be routed: probed with placeholder path parameters it
+# may be rejected by its handler (400, 401, 404 with a message, ...), but never
+# with the router's empty 404 or a 405 for an unrouted method. Endpoints of
+# disabled features must not succeed.
+#
+# Run while the server is running; set API_KEY if access control is on.
+
+source "$(dirname "$0")/test_lib.sh"
+
+AUTH=()
+if [ -n "$API_KEY" ]; then
+  AUTH=(-H "X-Api-Key: $API_KEY")
+fi
+
+CAPABILITIES=$(curl -sf "$SERVER_URL/capabilities")
+
+# Prints "<status> <body length>" for a request to an endpoint like "GET /img/{id}"
+probe() {
+  local method=${1%% *}
+  local path=${1#* }
+  # Placeholders are replaced by values no measurement or job can have
+  path=$(echo "$path" | sed -e 's/{size}/1/g' -e 's/{[a-z_]*}/capabilities-probe/g')
+  curl -s -o /tmp/capabilities_probe_body -w "%{http_code}" -X "$method" "${AUTH[@]}" \
+    "$SERVER_URL$path"
+  echo " $(wc -c < /tmp/capabilities_probe_body)"
+}
+
+while IFS=$'\t' read -r FEATURE ENABLED ENDPOINT; do
+  read -r STATUS LENGTH <<< "$(probe "$ENDPOINT")"
+  if [ "$ENABLED" == "true" ]; then
+    if [ "$STATUS" == "405" ] || { [ "$STATUS" == "404" ] && [ "$LENGTH" == "0" ]; }; then
+      echo -e "${RED}$FEATURE is enabled but $ENDPOINT is not routed ($STATUS)${NC}"
+      FAILED=1
+    else
+      echo -e "${GREEN}$FEATURE: $ENDPOINT is routed ($STATUS)${NC}"
+    fi
+  elif [[ "$STATUS" == 2* ]]; then
+    echo -e "${RED}$FEATURE is disabled but $ENDPOINT answered $STATUS${NC}"
+    FAILED=1
+  else
+    echo -e "${GREEN}$FEATURE: disabled, $ENDPOINT answered $STATUS${NC}"
+  fi
+done < <(echo "$CAPABILITIES" | jq -r \
+  '.features | to_entries[] | .key as $f | .value.enabled as $e | (.value.endpoints // [])[] | [$f, $e, .] | @tsv')
+
+rm -f /tmp/capabilities_probe_body
+finish "capability"
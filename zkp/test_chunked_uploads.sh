@@ -0,0 +1,167 @@
+#!/bin/bash
+set -e
+
+# Test resumable chunked uploads. Starts its own server with the mock
+# toolchain in test_vectors/mock_toolchain and checks that:
+#   - an image uploaded in chunks, with and without Content-Range, completes
+#     with its SHA-256 and a token a measurement is submitted with, as a form
+#     field or in a JSON body, storing the same image;
+#   - a chunk sent again while it is the last one replaces it, and chunks out
+#     of order, ranges starting elsewhere, empty chunks and chunks after
+#     completion are refused;
+#   - completing before the declared size arrived, chunks and uploads over
+#     UPLOAD_CHUNK_MAX_BYTES and UPLOAD_SESSION_MAX_BYTES, unknown and used
+#     tokens and a token sent with an image are refused, while a submission
+#     refused for another field leaves its token usable;
+#   - with API keys, another key's sessions and tokens are unknown;
+#   - sessions untouched for UPLOAD_SESSION_TTL_SECS, and session data left by
+#     an earlier run, are removed.
+#
+# Also needs split and sha256sum.
+
+source "$(dirname "$0")/test_lib.sh"
+
+ADMIN_KEY="chunked-upload-admin"
+IMAGE_SHA256=$(sha256sum < "$IMAGE" | cut -d' ' -f1)
+IMAGE_BYTES=$(wc -c < "$IMAGE")
+
+enter_work_dir
+mkdir -p uploads chunks
+# The image in chunks of 200 bytes: chunks/aa, chunks/ab, ...
+split -b 200 "$IMAGE" chunks/
+
+SERVER_ENV=(UPLOAD_CHUNK_MAX_BYTES=300 UPLOAD_SESSION_MAX_BYTES=1000)
+
+# Open an upload session with the given JSON body
+open_session() {
+  curl -s -X POST "$SERVER_URL/uploads" -H 'Content-Type: application/json' -d "$1" "${@:2}" |
+    jq -r .upload_id
+}
+
+# Send a file as chunk n of an upload, printing the response body
+chunk() {
+  curl -s -X PUT "$SERVER_URL/uploads/$1/chunks/$2" --data-binary "@$3" "${@:4}"
+}
+
+chunk_code() {
+  chunk "$@" -o /dev/null -w '%{http_code}'
+}
+
+# Send every chunk of the image
+upload_all() {
+  local n=0
+  for file in chunks/*; do
+    chunk "$1" "$n" "$file" "${@:2}" > /dev/null
+    n=$((n + 1))
+  done
+}
+
+complete() {
+  curl -s -X POST "$SERVER_URL/uploads/$1/complete" "${@:2}"
+}
+
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' "$@"
+}
+
+wait_status() {
+  local current
+  for _ in $(seq 1 30); do
+    current=$(curl -s "$SERVER_URL/status/$1" | jq -r .status)
+    [ "$current" == "$2" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+start_server
+check "resumable uploads advertised" "$(curl -s "$SERVER_URL/capabilities" | jq -c \
+  '[.features.resumable_uploads.enabled, .limits.max_chunk_bytes, .limits.max_upload_session_bytes]')" \
+  '[true,300,1000]'
+
+UPLOAD=$(open_session "{\"size\": $IMAGE_BYTES}")
+check "first chunk" "$(chunk "$UPLOAD" 0 chunks/aa | jq -c '[.status, .chunks, .received_bytes]')" \
+  '["open",1,200]'
+check "skipped chunk" "$(chunk "$UPLOAD" 2 chunks/ac | grep -c 'expects chunk 1 next')" "1"
+check "malformed range" "$(chunk_code "$UPLOAD" 1 chunks/ab -H 'Content-Range: 200-399')" "400"
+check "range elsewhere" "$(chunk_code "$UPLOAD" 1 chunks/ab -H "Content-Range: bytes 0-199/$IMAGE_BYTES")" "409"
+check "range of another size" "$(chunk_code "$UPLOAD" 1 chunks/ab -H 'Content-Range: bytes 200-399/5000')" "400"
+# A wrong chunk 1, replaced by the right one
+check "ranged chunk" "$(chunk "$UPLOAD" 1 chunks/ac -H "Content-Range: bytes 200-399/$IMAGE_BYTES" |
+  jq -c '[.chunks, .received_bytes]')" '[2,400]'
+check "chunk replaced" "$(chunk "$UPLOAD" 1 chunks/ab -H 'Content-Range: bytes 200-399/*' |
+  jq -c '[.chunks, .received_bytes]')" '[2,400]'
+check "empty chunk" "$(curl -s -o /dev/null -w '%{http_code}' -X PUT "$SERVER_URL/uploads/$UPLOAD/chunks/2")" "400"
+check "incomplete" "$(complete "$UPLOAD" | grep -c "has 400 of its $IMAGE_BYTES bytes")" "1"
+check "resumed from" "$(curl -s "$SERVER_URL/uploads/$UPLOAD" | jq -c '[.chunks, .received_bytes]')" '[2,400]'
+chunk "$UPLOAD" 2 chunks/ac > /dev/null
+chunk "$UPLOAD" 3 chunks/ad > /dev/null
+COMPLETED=$(complete "$UPLOAD")
+TOKEN=$(echo "$COMPLETED" | jq -r .upload_token)
+check "completed" "$(echo "$COMPLETED" | jq -c '[.status, .received_bytes, .sha256]')" \
+  "[\"completed\",$IMAGE_BYTES,\"$IMAGE_SHA256\"]"
+check "completed again" "$(complete "$UPLOAD" | jq -r .upload_token)" "$TOKEN"
+check "chunk after completion" "$(chunk_code "$UPLOAD" 3 chunks/ad)" "409"
+
+check "token with an image" "$(submit -F "image=@$IMAGE" -F "uploadToken=$TOKEN" |
+  grep -c 'only one of image, imageUrl and uploadToken')" "1"
+check "refused for another field" "$(curl -s -o /dev/null -w '%{http_code}' -X POST "$SERVER_URL/measurements" \
+  -F "uploadToken=$TOKEN" -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}')" "400"
+MEASUREMENT=$(submit -F "uploadToken=$TOKEN" | jq -r .measurement_id)
+check "uploaded image proved" "$(wait_status "$MEASUREMENT" Completed)" "Completed"
+check "uploaded image stored" "$(curl -s "$SERVER_URL/img/$MEASUREMENT" | sha256sum | cut -d' ' -f1)" \
+  "$IMAGE_SHA256"
+check "session claimed" "$(curl -s -o /dev/null -w '%{http_code}' "$SERVER_URL/uploads/$UPLOAD")" "404"
+check "session data moved" "$(ls -A uploads | grep -c '^\.session-' || true)" "0"
+check "token used" "$(submit -F "uploadToken=$TOKEN" | grep -c 'names no completed upload')" "1"
+check "unknown token" "$(submit -F 'uploadToken=nope' | grep -c 'names no completed upload')" "1"
+
+# Without a declared size, and submitted as JSON
+UPLOAD=$(open_session '')
+upload_all "$UPLOAD"
+TOKEN=$(complete "$UPLOAD" | jq -r .upload_token)
+AS_JSON=$(curl -s -X POST "$SERVER_URL/measurements/json" -H 'Content-Type: application/json' \
+  -d "{\"uploadToken\": \"$TOKEN\", \"startPoint\": {\"x\": 0.1, \"y\": 0.2, \"z\": -0.3},
+       \"endPoint\": {\"x\": 0.4, \"y\": 0.2, \"z\": -0.3}}" | jq -r .measurement_id)
+check "uploaded as JSON" "$(curl -s "$SERVER_URL/status/$AS_JSON" | jq -r .image_sha256)" "$IMAGE_SHA256"
+
+check "empty upload" "$(complete "$(open_session '{}')" | grep -c 'has no chunks yet')" "1"
+head -c 301 /dev/zero > large
+check "chunk too large" "$(chunk_code "$(open_session '{}')" 0 large)" "413"
+check "declared too large" "$(curl -s -o /dev/null -w '%{http_code}' -X POST "$SERVER_URL/uploads" \
+  -d '{"size": 1001}')" "413"
+head -c 300 /dev/zero > full
+UPLOAD=$(open_session '{}')
+for n in 0 1 2; do chunk "$UPLOAD" "$n" full > /dev/null; done
+check "upload too large" "$(chunk_code "$UPLOAD" 3 full)" "413"
+check "declared size holds" "$(chunk_code "$(open_session '{"size": 250}')" 0 full)" "413"
+stop_server
+
+# Session data left by an earlier run, and sessions of other keys
+touch -d '1 hour ago' uploads/.session-earlier-run
+start_server ADMIN_API_KEY="$ADMIN_KEY" UPLOAD_SESSION_TTL_SECS=2 RETENTION_SWEEP_INTERVAL_SECS=1
+new_key() {
+  curl -s -X POST "$SERVER_URL/admin/keys" -H "X-Api-Key: $ADMIN_KEY" \
+    -H 'Content-Type: application/json' -d "{\"name\": \"$1\", \"role\": \"submitter\"}" | jq -r .key
+}
+KEY_A=$(new_key phone-a)
+KEY_B=$(new_key phone-b)
+UPLOAD=$(open_session '{}' -H "X-Api-Key: $KEY_A")
+upload_all "$UPLOAD" -H "X-Api-Key: $KEY_A"
+TOKEN=$(complete "$UPLOAD" -H "X-Api-Key: $KEY_A" | jq -r .upload_token)
+check "session of another key" "$(curl -s -o /dev/null -w '%{http_code}' "$SERVER_URL/uploads/$UPLOAD" \
+  -H "X-Api-Key: $KEY_B")" "404"
+check "chunk to another key's session" "$(chunk_code "$UPLOAD" 0 chunks/aa -H "X-Api-Key: $KEY_B")" "404"
+check "token of another key" "$(submit -H "X-Api-Key: $KEY_B" -F "uploadToken=$TOKEN" |
+  grep -c 'names no completed upload')" "1"
+check "earlier run's data removed" "$(ls -A uploads | grep -c 'earlier-run' || true)" "0"
+sleep 4
+check "session expired" "$(curl -s -o /dev/null -w '%{http_code}' "$SERVER_URL/uploads/$UPLOAD" \
+  -H "X-Api-Key: $KEY_A")" "404"
+check "expired data removed" "$(ls -A uploads | grep -c '^\.session-' || true)" "0"
+check "expired token" "$(submit -H "X-Api-Key: $KEY_A" -F "uploadToken=$TOKEN" |
+  grep -c 'names no completed upload')" "1"
+
+finish "chunked upload"
@@ -0,0 +1,56 @@
+#!/bin/bash
+set -e
+
+# Test the circuit artifacts published for client-side verification. Starts
+# its own server with the mock toolchain in test_vectors/mock_toolchain,
+# placeholder circuit files and only the verification key allowlisted in
+# PUBLIC_CIRCUIT_ARTIFACTS. Checks that:
+#   - the verification key is served with immutable caching and its SHA-256
+#     as ETag, and revalidates with 304;
+#   - the wasm, the proving key and unknown versions are answered with 404;
+#   - /capabilities lists what each circuit version publishes;
+#   - a server allowlisting the proving key refuses to start.
+#
+# Also needs sha256sum.
+
+source "$(dirname "$0")/test_lib.sh"
+
+enter_work_dir
+mkdir -p keys circuit-compiled/zkHotdog_js
+echo '{"protocol": "groth16", "curve": "bn128", "nPublic": 5}' > keys/verification_key.json
+echo 'not a real proving key' > keys/zkHotdog_final.zkey
+echo 'not a real wasm' > circuit-compiled/zkHotdog_js/zkHotdog.wasm
+
+# The proving key can never be allowlisted
+STATUS=0
+PATH="$MOCK_TOOLCHAIN:$PATH" \
+  PUBLIC_CIRCUIT_ARTIFACTS="zkhotdog-v1:zkHotdog_final.zkey" \
+  timeout 10 "$BACKEND" > refused.log 2>&1 || STATUS=$?
+check "start with the proving key allowlisted" "$([ "$STATUS" -ne 0 ] && [ "$STATUS" -ne 124 ] && echo refused)" "refused"
+
+start_server PUBLIC_CIRCUIT_ARTIFACTS="zkhotdog-v1:vkey.json"
+
+status() {
+  curl -s -o /dev/null -w '%{http_code}' "$@"
+}
+
+URL="$SERVER_URL/circuits/zkhotdog-v1/vkey.json"
+HEADERS=$(curl -s -D - -o vkey.json "$URL" | tr -d '\r')
+HASH=$(sha256sum keys/verification_key.json | cut -d' ' -f1)
+check "vkey" "$(cmp -s vkey.json keys/verification_key.json && echo identical)" "identical"
+check "content type" "$(echo "$HEADERS" | grep -i '^content-type:' | cut -d' ' -f2)" "application/json"
+check "cache control" "$(echo "$HEADERS" | grep -i '^cache-control:' | cut -d' ' -f2-)" "public, max-age=31536000, immutable"
+check "etag" "$(echo "$HEADERS" | grep -i '^etag:' | cut -d' ' -f2)" "\"$HASH\""
+check "revalidation" "$(status -H "If-None-Match: \"$HASH\"" "$URL")" "304"
+
+check "wasm not allowlisted" "$(status "$SERVER_URL/circuits/zkhotdog-v1/circuit.wasm")" "404"
+check "proving key" "$(status "$SERVER_URL/circuits/zkhotdog-v1/zkHotdog_final.zkey")" "404"
+check "unknown version" "$(status "$SERVER_URL/circuits/zkhotdog-v0/vkey.json")" "404"
+
+CAPABILITIES=$(curl -s "$SERVER_URL/capabilities")
+check "feature" "$(echo "$CAPABILITIES" | jq -r .features.circuit_artifacts.enabled)" "true"
+check "published artifacts" \
+  "$(echo "$CAPABILITIES" | jq -c '.circuits[] | select(.version == "zkhotdog-v1") | .published_artifacts')" \
+  '["vkey.json"]'
+
+finish "circuit artifact"
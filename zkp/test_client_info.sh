@@ -0,0 +1,84 @@
+#!/bin/bash
+set -e
+
+# Test the client info sent with a measurement. Starts its own server with the
+# mock toolchain in test_vectors/mock_toolchain and checks that:
+#   - a clientInfo sent as a form field or as an object in a JSON body is
+#     stored with its values trimmed and reported as client_info by
+#     /status/{id} and GET /measurements, and measurements without one
+#     report none;
+#   - unknown keys, nested objects and arrays, numbers, empty and overlong
+#     values, control characters, malformed JSON, arrays and objects over
+#     1024 bytes are rejected with 400, and nothing is stored for them.
+
+source "$(dirname "$0")/test_lib.sh"
+
+enter_work_dir
+mkdir -p uploads
+
+start_server
+
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' "$@"
+}
+
+# Status code of a submission with the given clientInfo
+code() {
+  submit -o /dev/null -w '%{http_code}' -F "clientInfo=$1"
+}
+
+client_info() {
+  curl -s "$SERVER_URL/status/$1" | jq -c .client_info
+}
+
+check "limit advertised" "$(curl -s "$SERVER_URL/capabilities" | jq .limits.max_client_info_bytes)" "1024"
+
+FORM=$(submit -F 'clientInfo={"device": " iPhone15,2 ", "appVersion": "2.3.1", "platform": "iOS 17.4",
+  "captureMethod": "lidar"}' | jq -r .measurement_id)
+check "form stored" "$(client_info "$FORM")" \
+  '{"device":"iPhone15,2","appVersion":"2.3.1","platform":"iOS 17.4","captureMethod":"lidar"}'
+check "listed" "$(curl -s "$SERVER_URL/measurements" |
+  jq -c --arg id "$FORM" '.[] | select(.id == $id) | .client_info.device')" '"iPhone15,2"'
+
+PARTIAL=$(submit -F 'clientInfo={"captureMethod": "manual"}' | jq -r .measurement_id)
+check "partial stored" "$(client_info "$PARTIAL")" '{"captureMethod":"manual"}'
+
+AS_JSON=$(curl -s -X POST "$SERVER_URL/measurements/json" -H 'Content-Type: application/json' \
+  -d "$(jq -n --arg image "$(base64 -w0 "$IMAGE")" '{image: $image,
+    startPoint: {x: 0.1, y: 0.2, z: -0.3}, endPoint: {x: 0.4, y: 0.2, z: -0.3},
+    clientInfo: {device: "Pixel 8 Pro", platform: "Android 14"}}')" | jq -r .measurement_id)
+check "JSON stored" "$(client_info "$AS_JSON")" '{"device":"Pixel 8 Pro","platform":"Android 14"}'
+
+PLAIN=$(submit | jq -r .measurement_id)
+check "none reported" "$(curl -s "$SERVER_URL/status/$PLAIN" | jq 'has("client_info")')" "false"
+check "none listed" "$(curl -s "$SERVER_URL/measurements" |
+  jq --arg id "$PLAIN" '.[] | select(.id == $id) | has("client_info")')" "false"
+
+BEFORE=$(ls uploads | wc -l)
+check "unknown key" "$(submit -F 'clientInfo={"device": "x", "owner": "y"}' |
+  grep -c "Unknown clientInfo field 'owner'")" "1"
+check "nested object" "$(submit -F 'clientInfo={"device": {"model": "x"}}' |
+  grep -c 'clientInfo device must be a string')" "1"
+check "nested array" "$(code '{"platform": ["iOS", "17.4"]}')" "400"
+check "number" "$(code '{"appVersion": 2}')" "400"
+check "empty value" "$(code '{"device": "  "}')" "400"
+check "overlong device" "$(submit -F "clientInfo={\"device\": \"$(printf 'x%.0s' $(seq 1 65))\"}" |
+  grep -c 'must be 1 to 64 characters long')" "1"
+check "overlong version" "$(code "{\"appVersion\": \"$(printf '1%.0s' $(seq 1 33))\"}")" "400"
+check "control character" "$(code '{"device": "iPhone\u0007"}')" "400"
+check "bidi override" "$(code "$(printf '{"device": "iPhone\xe2\x80\xae"}')")" "400"
+check "malformed" "$(code '{"device": ')" "400"
+check "not an object" "$(code '["iPhone"]')" "400"
+check "deeply nested" "$(code "$(printf '{"device": %.0s' $(seq 1 100))\"x\"$(printf '}%.0s' $(seq 1 100))")" "400"
+check "too large" "$(submit -F "clientInfo={\"device\": \"x\", \"padding\": \"$(head -c 1100 /dev/zero | tr '\0' a)\"}" |
+  grep -c 'exceeds the maximum size of 1024 bytes')" "1"
+check "too large as JSON" "$(curl -s -o /dev/null -w '%{http_code}' -X POST "$SERVER_URL/measurements/json" \
+  -H 'Content-Type: application/json' -d "$(jq -n --arg image "$(base64 -w0 "$IMAGE")" \
+  --arg pad "$(head -c 1100 /dev/zero | tr '\0' a)" '{image: $image,
+    startPoint: {x: 0.1, y: 0.2, z: -0.3}, endPoint: {x: 0.4, y: 0.2, z: -0.3},
+    clientInfo: {device: $pad}}')")" "400"
+check "nothing stored" "$(ls uploads | wc -l)" "$BEFORE"
+
+finish "client info"
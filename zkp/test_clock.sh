@@ -0,0 +1,102 @@
+#!/bin/bash
+set -e
+
+# Test that stepping the host clock does not upset timers. Starts its own
+# server with the mock toolchain in test_vectors/mock_toolchain, attestation
+# polling on, an unreachable RPC endpoint before a working one and a single
+# proof worker, and steps its wall clock through CLOCK_STEP_PATH. Checks that:
+#   - a measurement awaiting its attestation is neither timed out by a step
+#     forward of an hour nor left unpolled by a step back;
+#   - a quarantined RPC endpoint stays quarantined across a step forward, and
+#     comes back after its cooldown across a step back;
+#   - a queued measurement does not miss its deadline by a step forward, and
+#     one with a short deadline still fails on time across a step back.
+#
+# The server must be built with the testing feature (cargo build --features
+# testing).
+
+source "$(dirname "$0")/test_lib.sh"
+
+COOLDOWN=10
+
+enter_work_dir
+echo 0 > clock_step
+echo 1 > mock_attestation_id
+echo 0 > mock_published
+start_server CLOCK_STEP_PATH=clock_step PROOF_WORKERS=1 ATTESTATION_POLLING=true \
+  ATTESTATION_POLL_INTERVAL_SECS=1 ATTESTATION_BACKOFF_MAX_SECS=2 ATTESTATION_MAX_WAIT_SECS=60 \
+  ZK_VERIFY_RPC_URLS="wss://unreachable.example.com,wss://backup.example.com" \
+  RPC_BREAKER_FAILURES=1 RPC_BREAKER_COOLDOWN_SECS=$COOLDOWN
+
+# Submit a measurement, with a deadline in seconds if given, and print its ID
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' \
+    ${1:+-F "deadline=$1"} | jq -r .measurement_id
+}
+
+status() {
+  curl -s "$SERVER_URL/status/$1" | jq -r .status
+}
+
+# Wait until a measurement reaches the given status and print the last one seen
+wait_status() {
+  local current
+  for _ in $(seq 1 30); do
+    current=$(status "$1")
+    [ "$current" == "$2" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+primary_quarantined() {
+  curl -s "$SERVER_URL/admin/stats" | jq -r '.rpc.endpoints[0].quarantined'
+}
+
+# Submitting trips the breaker of the unreachable endpoint
+AWAITED=$(submit)
+for _ in $(seq 1 30); do
+  [ -f "proofs/$AWAITED/pending_attestation.json" ] && break
+  sleep 0.2
+done
+TRIPPED=$SECONDS
+check "awaiting attestation" "$(status "$AWAITED")" "Processing"
+check "primary quarantined" "$(primary_quarantined)" "true"
+
+# An hour forward is past the maximum wait and the cooldown
+echo 3600 > clock_step
+sleep 4
+check "awaited after a step forward" "$(status "$AWAITED")" "Processing"
+check "primary quarantined after a step forward" "$(primary_quarantined)" "true"
+
+# Back to the real time, which puts every check of the last rounds an hour ahead
+echo 0 > clock_step
+echo 1 > mock_published
+check "attested after a step back" "$(wait_status "$AWAITED" Completed)" "Completed"
+
+# An hour before the trip, the cooldown still ends on time
+echo -3600 > clock_step
+while [ $((SECONDS - TRIPPED)) -le $COOLDOWN ]; do
+  sleep 1
+done
+check "primary back after its cooldown" "$(primary_quarantined)" "false"
+
+# With the worker busy, one measurement waits for a minute and one for two seconds
+echo 6 > mock_prove_delay
+BUSY=$(submit)
+check "busy" "$(wait_status "$BUSY" Processing)" "Processing"
+QUEUED=$(submit 60)
+echo 0 > clock_step
+check "deadline left after a step forward" \
+  "$(curl -s "$SERVER_URL/status/$QUEUED" | jq '.deadline_remaining_seconds > 50')" "true"
+SHORT=$(submit 2)
+echo -3600 > clock_step
+check "short deadline across a step back" "$(wait_status "$SHORT" Failed)" "Failed"
+check "failure class" "$(curl -s "$SERVER_URL/status/$SHORT" | jq -r .failure.class)" \
+  "DeadlineExceeded"
+check "queued within its deadline" "$(wait_status "$QUEUED" Completed)" "Completed"
+
+finish "clock step"
@@ -0,0 +1,73 @@
+#!/bin/bash
+set -e
+
+# Test the image commitment check. Starts its own server with the mock
+# toolchain in test_vectors/mock_toolchain and checks that:
+#   - a measurement whose image is intact completes;
+#   - a measurement whose stored image is altered while it is being proved
+#     fails with the class CommitmentMismatch instead of completing, with both
+#     hashes in its failure and log, and is counted in /admin/stats;
+#   - the doctor's image_commitments check names a completed measurement
+#     whose image was altered afterwards.
+
+source "$(dirname "$0")/test_lib.sh"
+
+ADMIN_KEY="commitment-test-admin-key"
+IMAGE_SHA256=$(sha256sum "$IMAGE" | cut -d' ' -f1)
+
+enter_work_dir
+
+start_server ADMIN_API_KEY="$ADMIN_KEY"
+
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" -H "X-Api-Key: $ADMIN_KEY" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' | jq -r .measurement_id
+}
+
+wait_status() {
+  local current
+  for _ in $(seq 1 30); do
+    current=$(curl -s "$SERVER_URL/status/$1" | jq -r .status)
+    [ "$current" == "$2" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+admin() {
+  curl -s -H "X-Api-Key: $ADMIN_KEY" "$SERVER_URL$1"
+}
+
+INTACT=$(submit)
+check "intact image" "$(wait_status "$INTACT" Completed)" "Completed"
+
+# The image is altered on disk while the measurement is being proved
+echo 3 > mock_prove_delay
+ALTERED=$(submit)
+check "being proved" "$(wait_status "$ALTERED" Processing)" "Processing"
+printf 'tampered' >> "uploads/$ALTERED.jpg"
+STORED_SHA256=$(sha256sum "uploads/$ALTERED.jpg" | cut -d' ' -f1)
+rm mock_prove_delay
+check "altered image" "$(wait_status "$ALTERED" Failed)" "Failed"
+check "failure class" "$(curl -s "$SERVER_URL/status/$ALTERED" | jq -r .failure.class)" \
+  "CommitmentMismatch"
+check "failure names both hashes" "$(curl -s "$SERVER_URL/status/$ALTERED" | jq -r .failure.message |
+  grep -c "$STORED_SHA256.*$IMAGE_SHA256")" "1"
+check "logged" "$(admin "/measurements/$ALTERED/log" | grep -c 'Image commitment mismatch')" "1"
+check "alert" "$(grep -c "ALERT: image commitment mismatch for measurement $ALTERED" server.log)" "1"
+check "counted" "$(admin /admin/stats | jq -c "[.commitments.mismatched, .commitments.last_mismatch.id == \"$ALTERED\"]")" \
+  '[1,true]'
+
+# A completed measurement whose image is altered afterwards
+check "doctor before" "$(admin /admin/doctor |
+  jq -r '.checks[] | select(.name == "image_commitments") | .status')" "pass"
+printf 'tampered' >> "uploads/$INTACT.jpg"
+admin /admin/doctor > report.json
+check "doctor after" "$(jq -r '.checks[] | select(.name == "image_commitments") | .status' report.json)" \
+  "fail"
+check "doctor names it" "$(jq -r '.checks[] | select(.name == "image_commitments") | .detail' report.json |
+  grep -c "$INTACT")" "1"
+
+finish "image commitment"
@@ -0,0 +1,136 @@
+#!/bin/bash
+set -e
+
+# Contract test of the response shapes clients depend on. Starts its own
+# server with the mock toolchain in test_vectors/mock_toolchain, replays the
+# requests below, the multipart submission, status polls, listings and their
+# error cases among them, and reduces every response to its shape: the HTTP
+# status and, for JSON bodies, the path and JSON type of every value, so ids,
+# times and numbers may differ between runs while renamed, removed, added or
+# retyped fields do not. Each shape is compared with
+# test_vectors/contracts/<case>.txt.
+#
+# Paths listed in test_vectors/contracts/allowlist.txt, one "<case> <path>"
+# per line, are left out of the comparison, with everything below them. List
+# a field there only while a reviewed change to it rolls out.
+#
+# When a change is intended, record the new shapes and review the diff with
+#   cargo build && UPDATE_GOLDEN=1 ./test_contracts.sh
+# then commit the fixtures with the change. Any other difference fails.
+
+source "$(dirname "$0")/test_lib.sh"
+
+FIXTURES_DIR="$ZKP_DIR/test_vectors/contracts"
+ALLOWLIST="$FIXTURES_DIR/allowlist.txt"
+
+enter_work_dir
+
+start_server
+
+# Shape of a response: its status, then one "<path> <type>" line per value of
+# a JSON body, or "body text" for anything else. Elements of an array share
+# the path "[]".
+shape() {
+  echo "status $1"
+  if jq -e . response.txt > /dev/null 2>&1; then
+    jq -r 'tostream | select(length == 2)
+      | [(.[0] | map(if type == "number" then "[]" else ".\(.)" end) | join("")),
+         (.[1] | if type == "array" or type == "object" then tojson else type end)]
+      | "body\(.[0]) \(.[1])"' response.txt | sort -u
+  else
+    echo "body text"
+  fi
+}
+
+# Lines of a shape outside the allowlisted paths of a case
+allowed() {
+  local case="$1" path
+  local filter=()
+  if [ -f "$ALLOWLIST" ]; then
+    while read -r listed path; do
+      [ "$listed" == "$case" ] && filter+=(-e "^${path//[/\\[} " -e "^${path//[/\\[}[.[]")
+    done < <(grep -v '^#' "$ALLOWLIST")
+  fi
+  if [ ${#filter[@]} -eq 0 ]; then
+    cat
+  else
+    grep -v "${filter[@]}" || true
+  fi
+}
+
+# Replay one request and compare the shape of its response with the fixture
+replay() {
+  local case="$1" code actual expected="$FIXTURES_DIR/$1.txt"
+  shift
+  code=$(curl -s -o response.txt -w '%{http_code}' "$@")
+  actual=$(shape "$code")
+
+  if [ -n "$UPDATE_GOLDEN" ]; then
+    mkdir -p "$FIXTURES_DIR"
+    if [ ! -f "$expected" ]; then
+      echo -e "${GREEN}$case: recorded${NC}"
+    elif [ "$actual" != "$(cat "$expected")" ]; then
+      echo -e "${GREEN}$case: updated${NC}"
+      diff "$expected" <(echo "$actual") || true
+    fi
+    echo "$actual" > "$expected"
+  elif [ ! -f "$expected" ]; then
+    echo -e "${RED}$case: no fixture, record it with UPDATE_GOLDEN=1${NC}"
+    FAILED=1
+  elif [ "$(allowed "$case" < "$expected")" == "$(echo "$actual" | allowed "$case")" ]; then
+    echo -e "${GREEN}$case: ok${NC}"
+  else
+    echo -e "${RED}$case: response shape changed${NC}"
+    diff <(allowed "$case" < "$expected") <(echo "$actual" | allowed "$case") || true
+    FAILED=1
+  fi
+}
+
+wait_completed() {
+  for _ in $(seq 1 30); do
+    [ "$(curl -s "$SERVER_URL/status/$1" | jq -r .status)" == "Completed" ] && break
+    sleep 1
+  done
+}
+
+POINTS=(-F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}')
+
+replay ready "$SERVER_URL/ready"
+replay health "$SERVER_URL/health"
+replay version "$SERVER_URL/version"
+replay capabilities "$SERVER_URL/capabilities"
+replay schemas "$SERVER_URL/schemas"
+
+replay submission -X POST "$SERVER_URL/measurements" -F "image=@$IMAGE" "${POINTS[@]}" \
+  -F 'externalId=contract-1' -F 'coordinateSystem=arkit_world'
+ID=$(jq -r .measurement_id response.txt)
+replay submission_without_points -X POST "$SERVER_URL/measurements" -F "image=@$IMAGE"
+replay submission_unknown_coordinate_system -X POST "$SERVER_URL/measurements" \
+  -F "image=@$IMAGE" "${POINTS[@]}" -F 'coordinateSystem=unity_world'
+replay submission_duplicate_external_id -X POST "$SERVER_URL/measurements" \
+  -F "image=@$IMAGE" "${POINTS[@]}" -F 'externalId=contract-1'
+
+wait_completed "$ID"
+replay status "$SERVER_URL/status/$ID"
+replay status_strict "$SERVER_URL/status/$ID?strict=true"
+replay status_unknown "$SERVER_URL/status/00000000-0000-0000-0000-000000000000"
+replay by_external_id "$SERVER_URL/measurements/by-external-id/contract-1"
+replay history "$SERVER_URL/measurements/$ID/history"
+replay proof "$SERVER_URL/proofs/$ID"
+replay proof_unknown "$SERVER_URL/proofs/00000000-0000-0000-0000-000000000000"
+replay attestation "$SERVER_URL/attestation/$ID"
+replay attestation_unknown "$SERVER_URL/attestation/00000000-0000-0000-0000-000000000000"
+
+SECOND=$(curl -s -X POST "$SERVER_URL/measurements" -F "image=@$IMAGE" "${POINTS[@]}" |
+  jq -r .measurement_id)
+wait_completed "$SECOND"
+replay listing "$SERVER_URL/measurements"
+replay listing_page "$SERVER_URL/measurements?limit=1"
+replay listing_status "$SERVER_URL/measurements?status=Completed"
+replay listing_unknown_status "$SERVER_URL/measurements?status=Lost"
+replay listing_bad_cursor "$SERVER_URL/measurements?limit=1&cursor=not-a-cursor"
+
+replay admin_stats "$SERVER_URL/admin/stats"
+replay deletion -X DELETE "$SERVER_URL/measurements/$SECOND"
+
+finish "contract"
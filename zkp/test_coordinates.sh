@@ -0,0 +1,61 @@
+#!/bin/bash
+set -e
+
+# Test the coordinateSystem of submissions. Starts its own server with the
+# mock toolchain in test_vectors/mock_toolchain, first as usual and then with
+# STRICT_COORDINATE_SYSTEM, and checks that:
+#   - a submission without a coordinate system is taken as arkit_world;
+#   - the status reports the system submitted and whether the points were
+#     converted to the canonical convention, which custom points are not;
+#   - the original points are kept as submitted;
+#   - unknown systems, the former names arkit_camera and arcore_world among
+#     them, are rejected with 400 naming the accepted values;
+#   - in strict mode a submission without a coordinate system is rejected.
+# The conversions themselves are pinned by the coordinate_* vectors of
+# test_inputs.sh.
+
+source "$(dirname "$0")/test_lib.sh"
+
+ACCEPTED="arkit_image, arkit_world, arfoundation_world, custom"
+
+enter_work_dir
+
+# Submit a measurement in the given coordinate system, if any, into response.txt
+# and print the HTTP status
+submit() {
+  curl -s -o response.txt -w '%{http_code}' -X POST "$SERVER_URL/measurements" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' \
+    ${1:+-F "coordinateSystem=$1"}
+}
+
+# Coordinate system, canonical flag and original start point of the last submission
+reported() {
+  local id
+  id=$(jq -r .measurement_id response.txt)
+  curl -s "$SERVER_URL/status/$id" |
+    jq -r '"\(.coordinate_system) \(.canonical_points) \(.original_points.start | [.x, .y, .z] | join(","))"'
+}
+
+start_server
+check "legacy submission" "$(submit)" "200"
+check "legacy reported" "$(reported)" "arkit_world true 0.1,0.2,-0.3"
+check "arkit_image submission" "$(submit arkit_image)" "200"
+check "arkit_image reported" "$(reported)" "arkit_image true 0.1,0.2,-0.3"
+check "arfoundation_world submission" "$(submit arfoundation_world)" "200"
+check "arfoundation_world reported" "$(reported)" "arfoundation_world true 0.1,0.2,-0.3"
+check "custom submission" "$(submit custom)" "200"
+check "custom reported" "$(reported)" "custom false 0.1,0.2,-0.3"
+check "unknown system" "$(submit unity_world) $(cat response.txt)" \
+  "400 Unknown coordinate system 'unity_world'; accepted values are $ACCEPTED"
+check "former arcore_world name" "$(submit arcore_world)" "400"
+
+start_server STRICT_COORDINATE_SYSTEM=true
+check "strict without a system" "$(submit) $(cat response.txt)" \
+  "400 Missing coordinate system; this server requires one of $ACCEPTED"
+check "strict with a system" "$(submit arkit_world)" "200"
+check "strict reported" "$(reported)" "arkit_world true 0.1,0.2,-0.3"
+check "strict unknown system" "$(submit ARKIT_WORLD)" "400"
+
+finish "coordinate system"
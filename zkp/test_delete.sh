@@ -0,0 +1,72 @@
+#!/bin/bash
+set -e
+
+# Test DELETE /measurements/{id}. Starts its own server with the mock toolchain
+# in test_vectors/mock_toolchain and a single proof worker, and checks that:
+#   - deleting a completed measurement answers it with the files removed, and
+#     leaves neither its image nor its proof directory behind;
+#   - the deleted measurement and its external id are gone;
+#   - a measurement being proved cannot be deleted (409) until it finishes;
+#   - unknown ids answer 404.
+
+source "$(dirname "$0")/test_lib.sh"
+
+enter_work_dir
+
+start_server PROOF_WORKERS=1
+
+# Submit a measurement with the given external id and print its ID
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' \
+    -F "externalId=$1" | jq -r .measurement_id
+}
+
+status() {
+  curl -s "$SERVER_URL/status/$1" | jq -r .status
+}
+
+# Wait until a measurement reaches the given status and print the last one seen
+wait_status() {
+  local current
+  for _ in $(seq 1 30); do
+    current=$(status "$1")
+    [ "$current" == "$2" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+http_code() {
+  curl -s -o /dev/null -w '%{http_code}' "$@"
+}
+
+COMPLETED=$(submit completed)
+check "completed" "$(wait_status "$COMPLETED" Completed)" "Completed"
+check "files before deletion" "$(ls "uploads/$COMPLETED.jpg" "proofs/$COMPLETED" > /dev/null && echo present)" "present"
+
+curl -s -X DELETE "$SERVER_URL/measurements/$COMPLETED" > deleted.json
+check "deleted measurement" "$(jq -r .id deleted.json)" "$COMPLETED"
+check "deleted status" "$(jq -r .status deleted.json)" "Completed"
+check "removed files" "$(jq -c '.files.removed | map(sub(".*/"; "")) | sort' deleted.json)" \
+  "[\"$COMPLETED\",\"$COMPLETED.jpg\"]"
+check "file errors" "$(jq -c .files.errors deleted.json)" "[]"
+check "image after deletion" "$(ls uploads | grep -c "$COMPLETED" || true)" "0"
+check "proof directory after deletion" "$(ls proofs | grep -c "$COMPLETED" || true)" "0"
+check "status after deletion" "$(http_code "$SERVER_URL/status/$COMPLETED")" "404"
+check "external id after deletion" \
+  "$(http_code "$SERVER_URL/measurements/by-external-id/completed")" "404"
+check "deleting again" "$(http_code -X DELETE "$SERVER_URL/measurements/$COMPLETED")" "404"
+check "unknown id" "$(http_code -X DELETE "$SERVER_URL/measurements/unknown")" "404"
+
+# A measurement being proved keeps its files until it is done
+echo 3 > mock_prove_delay
+BUSY=$(submit busy)
+check "processing" "$(wait_status "$BUSY" Processing)" "Processing"
+check "deleting while processing" "$(http_code -X DELETE "$SERVER_URL/measurements/$BUSY")" "409"
+check "finished" "$(wait_status "$BUSY" Completed)" "Completed"
+check "deleting once finished" "$(http_code -X DELETE "$SERVER_URL/measurements/$BUSY")" "200"
+
+finish "deletion"
@@ -0,0 +1,122 @@
+#!/bin/bash
+set -e
+
+# Test depth maps sent with a measurement. Starts its own server with the
+# mock toolchain in test_vectors/mock_toolchain and DEPTH_MAP_MAX_BYTES=4096,
+# and checks that:
+#   - a PNG depth map, and a raw float16 grid with its width and height, as
+#     form fields or base64 in a JSON body, are stored as
+#     uploads/{id}_depth.bin and recorded with their format, size and SHA-256;
+#   - GET /depth/{id} serves them as image/png or application/octet-stream,
+#     and answers 404 for measurements without one and unknown ids;
+#   - the proof input of a measurement with a depth map is that of the same
+#     measurement without one;
+#   - a grid without its size or of another size, a size without a depth map,
+#     a broken PNG and a PNG of another size are rejected with 400 and a depth
+#     map over DEPTH_MAP_MAX_BYTES with 413, and deleting a measurement
+#     removes its depth map.
+#
+# Also needs base64, python3 and sha256sum.
+
+source "$(dirname "$0")/test_lib.sh"
+
+enter_work_dir
+mkdir -p uploads
+
+# depth.png: 8 x 6 16-bit grayscale; grid.bin: 4 x 3 float16 depths in metres;
+# large.bin: 50 x 50 float16 depths, 5000 bytes
+python3 -c '
+import struct, zlib
+def chunk(kind, data):
+    return struct.pack(">I", len(data)) + kind + data + struct.pack(">I", zlib.crc32(kind + data))
+rows = b"".join(b"\x00" + b"".join(struct.pack(">H", 1000 + x * y) for x in range(8)) for y in range(6))
+png = b"\x89PNG\r\n\x1a\n" + chunk(b"IHDR", struct.pack(">IIBBBBB", 8, 6, 16, 0, 0, 0, 0)) \
+    + chunk(b"IDAT", zlib.compress(rows)) + chunk(b"IEND", b"")
+open("depth.png", "wb").write(png)
+open("grid.bin", "wb").write(b"".join(struct.pack("<e", 0.25 + i / 100) for i in range(12)))
+open("large.bin", "wb").write(struct.pack("<e", 1.0) * 2500)
+'
+head -c 40 depth.png > broken.png
+
+start_server DEPTH_MAP_MAX_BYTES=4096
+
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' "$@"
+}
+
+code() {
+  submit -o /dev/null -w '%{http_code}' "$@"
+}
+
+wait_status() {
+  local current
+  for _ in $(seq 1 30); do
+    current=$(curl -s "$SERVER_URL/status/$1" | jq -r .status)
+    [ "$current" == "$2" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+sha() {
+  sha256sum < "$1" | cut -d' ' -f1
+}
+
+check "depth maps advertised" "$(curl -s "$SERVER_URL/capabilities" | jq -c \
+  '[.features.depth_maps.enabled, .features.depth_maps.endpoints, .limits.max_depth_map_bytes]')" \
+  '[true,["GET /depth/{id}"],4096]'
+
+PNG=$(submit -F "depthMap=@depth.png" | jq -r .measurement_id)
+check "png recorded" "$(curl -s "$SERVER_URL/status/$PNG" | jq -c \
+  '[.depth_map.format, .depth_map.width, .depth_map.height, .depth_map.bytes, .depth_map.sha256]')" \
+  "[\"png\",8,6,$(wc -c < depth.png),\"$(sha depth.png)\"]"
+check "png stored" "$(sha "uploads/${PNG}_depth.bin")" "$(sha depth.png)"
+check "png reported" "$(curl -s "$SERVER_URL/status/$PNG" | jq -c '.artifacts.depthMap')" \
+  "{\"available\":true,\"bytes\":$(wc -c < depth.png)}"
+check "png served" "$(curl -s "$SERVER_URL/depth/$PNG" | sha256sum | cut -d' ' -f1)" "$(sha depth.png)"
+check "png content type" "$(curl -s -o /dev/null -w '%{content_type}' "$SERVER_URL/depth/$PNG")" "image/png"
+check "png download" "$(curl -s -D - -o /dev/null "$SERVER_URL/depth/$PNG?download=true" |
+  grep -ci 'content-disposition: attachment.*-depth\.png')" "1"
+
+GRID=$(submit -F "depthMap=@grid.bin" -F depthMapWidth=4 -F depthMapHeight=3 | jq -r .measurement_id)
+check "grid recorded" "$(curl -s "$SERVER_URL/status/$GRID" | jq -c \
+  '[.depth_map.format, .depth_map.width, .depth_map.height, .depth_map.sha256]')" \
+  "[\"float16\",4,3,\"$(sha grid.bin)\"]"
+check "grid served" "$(curl -s "$SERVER_URL/depth/$GRID" | sha256sum | cut -d' ' -f1)" "$(sha grid.bin)"
+check "grid content type" "$(curl -s -o /dev/null -w '%{content_type}' "$SERVER_URL/depth/$GRID")" \
+  "application/octet-stream"
+
+BODY=$(jq -n --arg image "$(base64 -w0 "$IMAGE")" --arg depth "$(base64 -w0 grid.bin)" \
+  '{image: $image, depthMap: $depth, depthMapWidth: 4, depthMapHeight: "3",
+    startPoint: {x: 0.1, y: 0.2, z: -0.3}, endPoint: {x: 0.4, y: 0.2, z: -0.3}}')
+AS_JSON=$(curl -s -X POST "$SERVER_URL/measurements/json" -H 'Content-Type: application/json' \
+  -d "$BODY" | jq -r .measurement_id)
+check "grid as JSON" "$(curl -s "$SERVER_URL/status/$AS_JSON" | jq -r .depth_map.sha256)" "$(sha grid.bin)"
+
+PLAIN=$(submit | jq -r .measurement_id)
+check "plain proved" "$(wait_status "$PLAIN" Completed)" "Completed"
+check "with depth map proved" "$(wait_status "$PNG" Completed)" "Completed"
+check "proof input unchanged" "$(sha "proofs/$PNG/input.json")" "$(sha "proofs/$PLAIN/input.json")"
+check "none recorded" "$(curl -s "$SERVER_URL/status/$PLAIN" |
+  jq -c '[has("depth_map"), (.artifacts | has("depthMap"))]')" '[false,false]'
+check "none served" "$(curl -s -w ' %{http_code}' "$SERVER_URL/depth/$PLAIN" |
+  grep -c 'has no depth map 404')" "1"
+check "unknown id" "$(curl -s -o /dev/null -w '%{http_code}' \
+  "$SERVER_URL/depth/00000000-0000-0000-0000-000000000000")" "404"
+
+check "grid without size" "$(submit -F "depthMap=@grid.bin" |
+  grep -c 'needs depthMapWidth and depthMapHeight')" "1"
+check "grid of another size" "$(submit -F "depthMap=@grid.bin" -F depthMapWidth=3 -F depthMapHeight=3 |
+  grep -c 'holds 18 bytes, not 24')" "1"
+check "size without depth map" "$(code -F depthMapWidth=4 -F depthMapHeight=3)" "400"
+check "invalid size" "$(code -F "depthMap=@grid.bin" -F depthMapWidth=-4 -F depthMapHeight=3)" "400"
+check "broken png" "$(submit -F "depthMap=@broken.png" | grep -c 'not a valid PNG')" "1"
+check "png of another size" "$(code -F "depthMap=@depth.png" -F depthMapWidth=6 -F depthMapHeight=8)" "400"
+check "too large" "$(code -F "depthMap=@large.bin" -F depthMapWidth=50 -F depthMapHeight=50)" "413"
+
+check "deleted" "$(curl -s -o /dev/null -w '%{http_code}' -X DELETE "$SERVER_URL/measurements/$GRID")" "200"
+check "depth map removed" "$(ls uploads | grep -c "^${GRID}_depth" || true)" "0"
+
+finish "depth map"
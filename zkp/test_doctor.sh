@@ -0,0 +1,139 @@
+#!/bin/bash
+set -e
+
+# Test the doctor. Runs `backend doctor` in a directory with stand-ins for the
+# circuit files, the mock toolchain in test_vectors/mock_toolchain and a mock
+# zkVerify RPC endpoint on port 3002, and checks that:
+#   - every check passes or is skipped when the environment is complete, and
+#     --json prints the report alone with exit code 0;
+#   - an unreachable endpoint among reachable ones warns and exits with 1;
+#   - a clock far from the latest block, or a missing proving key, fails with
+#     a remediation and exit code 2;
+#   - while a server holds the database, the command's store check fails and
+#     points at GET /admin/doctor, which passes it within the server;
+#   - a measurement left processing for longer than its steps could take, by
+#     a server killed while proving it, is reported as a stuck queue.
+#
+# Also needs python3, and port 3002 must be free.
+
+source "$(dirname "$0")/test_lib.sh"
+
+RPC_URL="http://localhost:3002"
+ADMIN_KEY="doctor-test-admin-key"
+
+enter_work_dir
+mkdir -p uploads proofs circuit-compiled/zkHotdog_js circuit-compiled/zkHotdogPolyline_js \
+  circuit-compiled/zkHotdogAngle_js circuit-compiled/zkHotdogThreshold_js keys
+touch circuit-compiled/zkHotdog_js/zkHotdog.wasm circuit-compiled/zkHotdog_js/generate_witness.js \
+  keys/zkHotdog_final.zkey keys/verification_key.json dist/verify_client.js \
+  circuit-compiled/zkHotdogPolyline_js/zkHotdogPolyline.wasm \
+  circuit-compiled/zkHotdogPolyline_js/generate_witness.js \
+  keys/zkHotdogPolyline_final.zkey keys/polyline_verification_key.json \
+  circuit-compiled/zkHotdogAngle_js/zkHotdogAngle.wasm \
+  circuit-compiled/zkHotdogAngle_js/generate_witness.js \
+  keys/zkHotdogAngle_final.zkey keys/angle_verification_key.json \
+  circuit-compiled/zkHotdogThreshold_js/zkHotdogThreshold.wasm \
+  circuit-compiled/zkHotdogThreshold_js/generate_witness.js \
+  keys/zkHotdogThreshold_final.zkey keys/threshold_verification_key.json
+
+# JSON-RPC endpoint answering state_getStorage with the time of its latest
+# block: now, minus the seconds in ./mock_block_lag
+python3 -c '
+import http.server, json, os, struct, time
+class Handler(http.server.BaseHTTPRequestHandler):
+    def do_POST(self):
+        request = json.loads(self.rfile.read(int(self.headers["Content-Length"])))
+        lag = float(open("mock_block_lag").read()) if os.path.exists("mock_block_lag") else 0
+        millis = int((time.time() - lag) * 1000)
+        body = json.dumps({"jsonrpc": "2.0", "id": request["id"],
+                           "result": "0x" + struct.pack("<Q", millis).hex()}).encode()
+        self.send_response(200)
+        self.send_header("Content-Type", "application/json")
+        self.send_header("Content-Length", str(len(body)))
+        self.end_headers()
+        self.wfile.write(body)
+    def log_message(self, *args):
+        pass
+http.server.HTTPServer(("127.0.0.1", 3002), Handler).serve_forever()
+' &
+RPC_PID=$!
+BACKGROUND_PIDS+=" $RPC_PID"
+sleep 1
+
+# Run the command with the given environment, keeping its report in
+# report.json and printing its exit code
+doctor() {
+  local code=0
+  env "$@" PATH="$MOCK_TOOLCHAIN:$PATH" ZK_VERIFY_RPC_URLS="${RPC_URLS:-$RPC_URL}" \
+    "$BACKEND" doctor --json > report.json || code=$?
+  echo "$code"
+}
+
+# The status of a check in report.json
+status_of() {
+  jq -r --arg name "$1" '.checks[] | select(.name == $name) | .status' "${2:-report.json}"
+}
+
+# Only the safety audit may warn, where uploads/ is on tmpfs
+SAFE="ADMIN_API_KEY=x CORS_ALLOWED_ORIGINS=https://app.example.com PUBLIC_BASE_URL=https://zkhotdog.example.com"
+check "complete environment" "$(doctor $SAFE)" "$(jq -r '.status | if . == "warn" then 1 else 0 end' report.json)"
+check "report is JSON alone" "$(jq -r .ran_in report.json)" "command"
+for name in circuit_artifacts toolchain process_spawn store file_storage queue rpc clock; do
+  check "$name" "$(status_of $name)" "pass"
+done
+check "no remediation on passing checks" \
+  "$(jq '[.checks[] | select(.status == "pass" and has("remediation"))] | length' report.json)" "0"
+
+# Warnings
+check "unreachable endpoint" "$(RPC_URLS="$RPC_URL,http://localhost:1" doctor $SAFE)" "1"
+check "rpc" "$(status_of rpc)" "warn"
+check "rpc detail" "$(jq -r '.checks[] | select(.name == "rpc") | .detail' report.json | grep -c 'unreachable: http://localhost:1')" "1"
+
+# Failures
+echo 900 > mock_block_lag
+check "clock far off" "$(doctor)" "2"
+check "clock" "$(status_of clock)" "fail"
+check "clock remediation" "$(jq -r '.checks[] | select(.name == "clock") | .remediation' report.json | grep -c NTP)" "1"
+rm mock_block_lag
+mv keys/zkHotdog_final.zkey keys/zkHotdog_final.zkey.away
+check "missing proving key" "$(doctor)" "2"
+check "circuit_artifacts" "$(status_of circuit_artifacts)" "fail"
+mv keys/zkHotdog_final.zkey.away keys/zkHotdog_final.zkey
+
+TEXT=$(PATH="$MOCK_TOOLCHAIN:$PATH" ZK_VERIFY_RPC_URLS=$RPC_URL "$BACKEND" doctor || true)
+check "text output" "$(echo "$TEXT" | grep -c '^PASS  store')" "1"
+
+# Within a running server
+start_server ADMIN_API_KEY="$ADMIN_KEY" ZK_VERIFY_RPC_URLS=$RPC_URL
+
+check "command while the server runs" "$(doctor)" "2"
+check "store locked" "$(status_of store)" "fail"
+check "store remediation" "$(jq -r '.checks[] | select(.name == "store") | .remediation' report.json | grep -c '/admin/doctor')" "1"
+
+check "endpoint needs an admin key" \
+  "$(curl -s -o /dev/null -w '%{http_code}' "$SERVER_URL/admin/doctor")" "403"
+curl -s -H "X-Api-Key: $ADMIN_KEY" "$SERVER_URL/admin/doctor" > server_report.json
+check "endpoint runs in the server" "$(jq -r .ran_in server_report.json)" "server"
+check "server store" "$(status_of store server_report.json)" "pass"
+check "server queue" "$(status_of queue server_report.json)" "pass"
+
+# A server killed while proving leaves its measurement Processing, for
+# longer than steps timing out after a second could take
+echo 6 > mock_prove_delay
+ID=$(curl -s -X POST "$SERVER_URL/measurements" -H "X-Api-Key: $ADMIN_KEY" \
+  -F "image=@$IMAGE" \
+  -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+  -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' | jq -r .measurement_id)
+for _ in $(seq 1 20); do
+  [ "$(curl -s "$SERVER_URL/status/$ID" | jq -r .status)" == "Processing" ] && break
+  sleep 0.2
+done
+{ kill -9 $SERVER_PID && wait $SERVER_PID; } 2>/dev/null || true
+sleep 4
+check "stuck measurement" "$(doctor PROOF_STEP_TIMEOUT_SECS=1 SIGNER_STUCK_SECS=1)" "2"
+check "queue" "$(status_of queue)" "fail"
+check "stuck measurement named" \
+  "$(jq -r '.checks[] | select(.name == "queue") | .detail' report.json | grep -c "$ID")" "1"
+rm mock_prove_delay
+
+finish "doctor"
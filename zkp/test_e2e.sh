@@ -0,0 +1,161 @@
+#!/bin/bash
+
+# End-to-end black-box test against a running instance, for local development,
+# staging and production canaries alike. Only the public HTTP API is used:
+#
+#   - submit the test image with two points and follow the status through
+#     every transition until the proof is verified
+#   - download the image and check it matches the upload byte for byte
+#   - check the proof artifacts are reported and the public view renders
+#   - induce a failure with a one-second deadline and check it is reported;
+#     skipped when the prover finishes within the deadline, as mock ones do
+#
+# Set BASE_URL to the instance (default http://localhost:3001), API_KEY when
+# access control is on, and JUNIT_REPORT to write a JUnit XML report for CI.
+# Verification needs a working prover and zkVerify account on the instance;
+# E2E_TIMEOUT_SECS bounds the wait for it (default 600).
+
+source "$(dirname "$0")/test_lib.sh"
+
+BASE_URL="${BASE_URL:-http://localhost:3001}"
+TIMEOUT_SECS="${E2E_TIMEOUT_SECS:-600}"
+IMAGE="test_vectors/inputs/image.jpg"
+AUTH=()
+if [ -n "$API_KEY" ]; then
+  AUTH=(-H "X-Api-Key: $API_KEY")
+fi
+
+WORK_DIR=$(mktemp -d)
+trap 'rm -rf "$WORK_DIR"' EXIT
+
+CASES=()
+FAILURES=0
+STARTED=$(date +%s)
+
+# Record the outcome of a test case: pass <name> or fail <name> <message>
+pass() {
+  echo -e "${GREEN}PASS $1${NC}"
+  CASES+=("<testcase classname=\"e2e\" name=\"$1\"/>")
+}
+skip() {
+  echo "SKIP $1: $2"
+  CASES+=("<testcase classname=\"e2e\" name=\"$1\"><skipped message=\"$2\"/></testcase>")
+}
+fail() {
+  echo -e "${RED}FAIL $1: $2${NC}"
+  local message
+  message=$(echo "$2" | sed -e 's/&/\&amp;/g' -e 's/</\&lt;/g' -e 's/>/\&gt;/g' -e 's/"/\&quot;/g')
+  CASES+=("<testcase classname=\"e2e\" name=\"$1\"><failure message=\"$message\"/></testcase>")
+  FAILURES=$((FAILURES + 1))
+}
+
+write_report() {
+  [ -z "$JUNIT_REPORT" ] && return
+  {
+    echo '<?xml version="1.0" encoding="UTF-8"?>'
+    echo "<testsuite name=\"zkhotdog-e2e\" tests=\"${#CASES[@]}\" failures=\"$FAILURES\" time=\"$(($(date +%s) - STARTED))\">"
+    printf '%s\n' "${CASES[@]}"
+    echo '</testsuite>'
+  } > "$JUNIT_REPORT"
+  echo "JUnit report written to $JUNIT_REPORT"
+}
+
+submit() {
+  curl -s -X POST "${AUTH[@]}" "$BASE_URL/measurements" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' \
+    "$@"
+}
+
+# Happy path: submission
+RESPONSE=$(submit -F 'metadata={"label": "e2e"}')
+ID=$(echo "$RESPONSE" | jq -r '.measurement_id // empty' 2>/dev/null)
+if [ -n "$ID" ]; then
+  pass "submit"
+else
+  fail "submit" "no measurement id in response: $RESPONSE"
+  write_report
+  exit 1
+fi
+
+# Happy path: every status until the proof is verified, in order
+TRANSITIONS="" STATUS=""
+DEADLINE=$(($(date +%s) + TIMEOUT_SECS))
+while [ "$(date +%s)" -lt "$DEADLINE" ]; do
+  STATUS=$(curl -s "${AUTH[@]}" "$BASE_URL/status/$ID" | jq -r '.status // empty')
+  if [ -n "$STATUS" ] && [ "${TRANSITIONS##* }" != "$STATUS" ]; then
+    TRANSITIONS="$TRANSITIONS $STATUS"
+  fi
+  [ "$STATUS" == "Completed" ] || [ "$STATUS" == "Failed" ] && break
+  sleep 1
+done
+TRANSITIONS="${TRANSITIONS# }"
+case "$TRANSITIONS" in
+  "Pending Processing Completed" | "Processing Completed" | "Pending Completed" | "Completed")
+    pass "status transitions"
+    ;;
+  *)
+    fail "status transitions" "observed '$TRANSITIONS'"
+    ;;
+esac
+
+STATUS_JSON=$(curl -s "${AUTH[@]}" "$BASE_URL/status/$ID")
+
+# Image download matches the upload
+curl -s "${AUTH[@]}" -o "$WORK_DIR/image.jpg" "$BASE_URL/img/$ID"
+if cmp -s "$IMAGE" "$WORK_DIR/image.jpg"; then
+  pass "image download"
+else
+  fail "image download" "downloaded image differs from the upload"
+fi
+
+# Proof artifacts are reported with their sizes
+MISSING=$(echo "$STATUS_JSON" | jq -r \
+  '[.artifacts | to_entries[] | select(.key | IN("image", "input", "proof", "publicSignals", "attestation")) | select(.value.available | not) | .key] | join(", ")')
+if [ -z "$MISSING" ]; then
+  pass "artifacts"
+else
+  fail "artifacts" "not available: $MISSING"
+fi
+
+# The attestation is recorded once verified
+if echo "$STATUS_JSON" | jq -e '.attestation.attestationId' > /dev/null; then
+  pass "attestation"
+else
+  fail "attestation" "no attestation in $STATUS_JSON"
+fi
+
+# Public verification view
+VIEW=$(curl -s "${AUTH[@]}" "$BASE_URL/view/$ID")
+if echo "$VIEW" | grep -q "$ID" && echo "$VIEW" | grep -q "Completed"; then
+  pass "public view"
+else
+  fail "public view" "status page does not show the completed measurement"
+fi
+
+# Induced failure: a deadline that passes before proving must fail the measurement
+RESPONSE=$(submit -F "deadline=1")
+FAILED_ID=$(echo "$RESPONSE" | jq -r '.measurement_id // empty' 2>/dev/null)
+CLASS="" STATUS=""
+for _ in $(seq 1 30); do
+  FAILED_JSON=$(curl -s "${AUTH[@]}" "$BASE_URL/status/$FAILED_ID")
+  STATUS=$(echo "$FAILED_JSON" | jq -r '.status // empty')
+  CLASS=$(echo "$FAILED_JSON" | jq -r '.failure.class // empty')
+  [ "$STATUS" == "Completed" ] || [ "$STATUS" == "Failed" ] && break
+  sleep 1
+done
+if [ "$CLASS" == "DeadlineExceeded" ]; then
+  pass "induced failure"
+elif [ "$STATUS" == "Completed" ]; then
+  skip "induced failure" "the proof was generated within the one-second deadline"
+else
+  fail "induced failure" "expected DeadlineExceeded, got '${CLASS:-no failure}' for $RESPONSE"
+fi
+
+write_report
+if [ "$FAILURES" -ne 0 ]; then
+  echo -e "${RED}$FAILURES end-to-end checks failed${NC}"
+  exit 1
+fi
+echo -e "${GREEN}All end-to-end checks passed!${NC}"
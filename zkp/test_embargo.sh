@@ -0,0 +1,72 @@
+#!/bin/bash
+set -e
+
+# Test that embargoed measurements stay out of sight until their release.
+# Starts its own server with the mock toolchain in test_vectors/mock_toolchain
+# and ADMIN_API_KEY set, submits a measurement with a submitter key from
+# 127.0.0.1 and a publishAt a few seconds ahead, and checks that:
+#   - before publishAt, another submitter's key and an anonymous caller from
+#     127.0.0.2 neither find it with /status/{id} nor see it in
+#     GET /measurements, while the submitter and an admin do;
+#   - once publishAt has passed, the other callers find and list it too.
+
+source "$(dirname "$0")/test_lib.sh"
+
+SERVER_URL="http://127.0.0.1:3001"
+ADMIN_KEY="zkh_test_admin_key"
+EMBARGO_SECS=8
+
+enter_work_dir
+start_server ADMIN_API_KEY="$ADMIN_KEY"
+
+new_key() {
+  curl -s -X POST "$SERVER_URL/admin/keys" -H "X-Api-Key: $ADMIN_KEY" \
+    -H 'Content-Type: application/json' -d "{\"name\": \"$1\", \"role\": \"submitter\"}" |
+    jq -r .key
+}
+SUBMITTER_KEY=$(new_key field-app)
+OTHER_KEY=$(new_key other-app)
+
+# Request from the given address with the given key, empty for none
+request() {
+  local from="$1" key="$2" path="$3"
+  curl -s --interface "$from" ${key:+-H "X-Api-Key: $key"} "$SERVER_URL$path" "${@:4}"
+}
+
+# Status code of /status/{id} for the caller
+polled() {
+  request "$1" "$2" "/status/$ID" -o /dev/null -w '%{http_code}'
+}
+
+# Whether GET /measurements lists the measurement to the caller, and the
+# total it reports
+listed() {
+  local found
+  found=$(request "$1" "$2" /measurements -D headers.txt | jq --arg id "$ID" 'any(.id == $id)')
+  echo "$found $(grep -i '^x-total-count:' headers.txt | tr -d '\r' | cut -d' ' -f2)"
+}
+
+ID=$(curl -s -X POST "$SERVER_URL/measurements" -H "X-Api-Key: $SUBMITTER_KEY" \
+  -F "image=@$IMAGE" \
+  -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+  -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' \
+  -F "publishAt=$EMBARGO_SECS" | jq -r .measurement_id)
+PUBLISH_AT=$(($(date +%s) + EMBARGO_SECS))
+
+check "submitter polls before publishAt" "$(polled 127.0.0.1 "$SUBMITTER_KEY")" "200"
+check "admin polls before publishAt" "$(polled 127.0.0.2 "$ADMIN_KEY")" "200"
+check "other submitter polls before publishAt" "$(polled 127.0.0.1 "$OTHER_KEY")" "404"
+check "anonymous poll before publishAt" "$(polled 127.0.0.2 "")" "404"
+check "submitter lists before publishAt" "$(listed 127.0.0.1 "$SUBMITTER_KEY")" "true 1"
+check "admin lists before publishAt" "$(listed 127.0.0.2 "$ADMIN_KEY")" "true 1"
+check "other submitter lists before publishAt" "$(listed 127.0.0.1 "$OTHER_KEY")" "false 0"
+check "anonymous listing before publishAt" "$(listed 127.0.0.2 "")" "false 0"
+
+sleep $((PUBLISH_AT - $(date +%s) + 1))
+
+check "other submitter polls after publishAt" "$(polled 127.0.0.1 "$OTHER_KEY")" "200"
+check "anonymous poll after publishAt" "$(polled 127.0.0.2 "")" "200"
+check "other submitter lists after publishAt" "$(listed 127.0.0.1 "$OTHER_KEY")" "true 1"
+check "anonymous listing after publishAt" "$(listed 127.0.0.2 "")" "true 1"
+
+finish "embargo"
@@ -0,0 +1,71 @@
+#!/bin/bash
+set -e
+
+# Test points sent as one form field per coordinate. Starts its own server
+# with the mock toolchain in test_vectors/mock_toolchain and checks that:
+#   - startX, startY, startZ, endX, endY and endZ make the same measurement
+#     as startPoint and endPoint, also with one point in each style;
+#   - a point sent in both styles is accepted when they agree and rejected
+#     with 400 naming both when they do not;
+#   - a missing coordinate field, one sent twice and a value that is not a
+#     plain decimal number are rejected with 400.
+
+source "$(dirname "$0")/test_lib.sh"
+
+enter_work_dir
+
+start_server
+
+START_JSON='startPoint={"x": 0.1, "y": 0.2, "z": -0.3}'
+END_JSON='endPoint={"x": 0.4, "y": 0.2, "z": -0.3}'
+START_FLAT=(-F startX=0.1 -F startY=0.2 -F startZ=-0.3)
+END_FLAT=(-F endX=0.4 -F endY=0.2 -F endZ=-0.3)
+
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" -F "image=@$IMAGE" "$@"
+}
+
+wait_status() {
+  local current
+  for _ in $(seq 1 30); do
+    current=$(curl -s "$SERVER_URL/status/$1" | jq -r .status)
+    [ "$current" == "$2" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+# The points of a measurement, as scaled and as sent
+points() {
+  curl -s "$SERVER_URL/status/$1" | jq -c '[.start_point, .end_point, .original_points]'
+}
+
+JSON=$(submit -F "$START_JSON" -F "$END_JSON" | jq -r .measurement_id)
+FLAT=$(submit "${START_FLAT[@]}" "${END_FLAT[@]}" | jq -r .measurement_id)
+check "flat fields" "$(wait_status "$FLAT" Completed)" "Completed"
+check "same points as JSON" "$(points "$FLAT")" "$(points "$JSON")"
+
+MIXED=$(submit -F "$START_JSON" "${END_FLAT[@]}" | jq -r .measurement_id)
+check "one point in each style" "$(points "$MIXED")" "$(points "$JSON")"
+BOTH=$(submit -F "$START_JSON" "${START_FLAT[@]}" -F "$END_JSON" "${END_FLAT[@]}" |
+  jq -r .measurement_id)
+check "both styles agreeing" "$(points "$BOTH")" "$(points "$JSON")"
+
+check "both styles disagreeing" \
+  "$(submit -F "$START_JSON" -F startX=0.1 -F startY=0.2 -F startZ=0.3 -F "$END_JSON")" \
+  "startPoint (0.1, 0.2, -0.3) and startX/startY/startZ (0.1, 0.2, 0.3) disagree; send the point in one style"
+check "missing coordinate" "$(submit -F startX=0.1 -F startY=0.2 "${END_FLAT[@]}")" \
+  "Missing startZ: a point sent as separate fields needs startX, startY, startZ"
+check "missing coordinates" "$(submit "${START_FLAT[@]}" -F endY=0.2)" \
+  "Missing endX and endZ: a point sent as separate fields needs endX, endY, endZ"
+check "missing point" "$(submit "${START_FLAT[@]}")" "Missing end point data"
+check "duplicated coordinate" "$(submit "${START_FLAT[@]}" -F startX=0.1 "${END_FLAT[@]}")" \
+  "startX was sent more than once"
+for value in abc 1e3 NaN inf 0x10 "" . 1.2.3; do
+  check "not a decimal: '$value'" "$(submit "${START_FLAT[@]}" -F endX="$value" -F endY=0.2 -F endZ=-0.3 \
+    -o /dev/null -w '%{http_code}')" "400"
+done
+check "decimal forms" "$(submit -F startX=+1 -F startY=.5 -F startZ=-2. "${END_FLAT[@]}" |
+  jq -r .measurement_id | grep -c -- -)" "1"
+
+finish "flat point"
@@ -0,0 +1,90 @@
+#!/bin/bash
+set -e
+
+# Test of the in-process Groth16 verifier through the verify_proof tool. The
+# fixtures in test_vectors/groth16 are a proof that 9 is a square, made with
+# arkworks and written as snarkjs writes them. Checks that:
+#   - the known-good proof verifies, and the mock toolchain's proof verifies
+#     for any public signal against the mock key;
+#   - a changed public signal, swapped proof points or a proof for another key
+#     do not verify;
+#   - public signals at or above the field modulus, including the good signal
+#     plus the modulus, and ones with leading zeros are refused as malformed;
+#   - points off the curve or not in affine form, a wrong number of public
+#     signals and other protocols are refused as malformed.
+#
+# Needs jq and a built verify_proof (cargo build). Set VERIFY_PROOF to use
+# another binary.
+
+source "$(dirname "$0")/test_lib.sh"
+
+VERIFY_PROOF="${VERIFY_PROOF:-$ZKP_DIR/target/debug/verify_proof}"
+FIXTURES="$ZKP_DIR/test_vectors/groth16"
+# Order of the BN254 scalar field
+MODULUS=21888242871839275222246405745257275088548364400416034343698204186575808495617
+
+enter_work_dir
+
+# Verify against a key, printing the exit status and, for errors, the message
+verify() {
+  local output status=0
+  output=$("$VERIFY_PROOF" "$@") || status=$?
+  if [ "$status" -eq 2 ]; then
+    echo "$status ${output#*: }"
+  else
+    echo "$status"
+  fi
+}
+
+# A copy of the good proof changed by a jq filter
+tampered_proof() {
+  jq "$1" "$FIXTURES/proof.json" > proof.json
+  echo proof.json
+}
+
+signals() {
+  echo "$1" > public.json
+  echo public.json
+}
+
+KEY="$FIXTURES/verification_key.json"
+check "known-good proof" "$(verify "$KEY" "$FIXTURES/public.json" "$FIXTURES/proof.json")" "0"
+check "mock proof, signal 0" \
+  "$(verify "$MOCK_TOOLCHAIN/verification_key.json" "$(signals '["0"]')" "$MOCK_TOOLCHAIN/proof.json")" "0"
+check "mock proof, signal 123456789" \
+  "$(verify "$MOCK_TOOLCHAIN/verification_key.json" "$(signals '["123456789"]')" "$MOCK_TOOLCHAIN/proof.json")" "0"
+
+check "changed public signal" "$(verify "$KEY" "$(signals '["10"]')" "$FIXTURES/proof.json")" "1"
+check "swapped pi_a and pi_c" \
+  "$(verify "$KEY" "$FIXTURES/public.json" "$(tampered_proof '.pi_a as $a | .pi_a = .pi_c | .pi_c = $a')")" "1"
+check "proof for another key" "$(verify "$KEY" "$FIXTURES/public.json" "$MOCK_TOOLCHAIN/proof.json")" "1"
+
+check "signal equal to the modulus" "$(verify "$KEY" "$(signals "[\"$MODULUS\"]")" "$FIXTURES/proof.json")" \
+  "2 '$MODULUS' is not a canonical field element"
+# 9 plus the modulus is 9 in the field, so only a strict parser refuses it
+NINE_PLUS_MODULUS=21888242871839275222246405745257275088548364400416034343698204186575808495626
+check "good signal plus the modulus" \
+  "$(verify "$KEY" "$(signals "[\"$NINE_PLUS_MODULUS\"]")" "$FIXTURES/proof.json")" \
+  "2 '$NINE_PLUS_MODULUS' is not a canonical field element"
+check "leading zero" "$(verify "$KEY" "$(signals '["09"]')" "$FIXTURES/proof.json")" \
+  "2 '09' is not a canonical field element"
+check "two public signals" "$(verify "$KEY" "$(signals '["9", "9"]')" "$FIXTURES/proof.json")" \
+  "2 expected 1 public signals, got 2"
+
+check "pi_a off the curve" \
+  "$(verify "$KEY" "$FIXTURES/public.json" "$(tampered_proof '.pi_a[1] = "1"')")" \
+  "2 pi_a is not a valid curve point"
+check "pi_b off the curve" \
+  "$(verify "$KEY" "$FIXTURES/public.json" "$(tampered_proof '.pi_b[0][1] = "1"')")" \
+  "2 pi_b is not a valid curve point"
+check "projective pi_c" \
+  "$(verify "$KEY" "$FIXTURES/public.json" "$(tampered_proof '.pi_c[2] = "2"')")" \
+  "2 pi_c is not a valid curve point"
+check "plonk proof" \
+  "$(verify "$KEY" "$FIXTURES/public.json" "$(tampered_proof '.protocol = "plonk"')")" \
+  "2 unsupported protocol 'plonk'"
+jq '.nPublic = 2' "$KEY" > key.json
+check "key with a missing IC point" "$(verify key.json "$FIXTURES/public.json" "$FIXTURES/proof.json")" \
+  "2 malformed JSON: nPublic is 2 but IC has 2 points"
+
+finish "Groth16 verifier"
@@ -0,0 +1,73 @@
+#!/bin/bash
+set -e
+
+# Test the health report. Starts its own server with the mock toolchain in
+# test_vectors/mock_toolchain, behind an npx that fails while ./npx_missing
+# exists, and checks that GET /health:
+#   - answers 503 naming the circuit WASM and proving key while they are
+#     missing, and snarkjs while npx fails;
+#   - reuses the toolchain probe, so snarkjs stays failing once npx works
+#     again until the probe is made anew;
+#   - answers 200 once everything is installed, with the number of
+#     measurements in each status;
+#   - names an unwritable uploads/ directory.
+#
+# The unwritable directory is only noticed when not running as root.
+
+source "$(dirname "$0")/test_lib.sh"
+
+enter_work_dir
+mkdir -p bin
+# uploads/ is made unwritable below, and has to be writable to be removed
+trap 'chmod u+w "$WORK_DIR/uploads" 2>/dev/null; kill $SERVER_PID 2>/dev/null || true; rm -rf "$WORK_DIR"' EXIT
+
+cat > bin/npx << EOF_NPX
+#!/bin/bash
+[ -f npx_missing ] && { echo "npx: command not found" >&2; exit 127; }
+exec "$MOCK_TOOLCHAIN/npx" "\$@"
+EOF_NPX
+chmod +x bin/npx
+touch npx_missing
+
+SERVER_ENV=(PATH="$WORK_DIR/bin:$MOCK_TOOLCHAIN:$PATH")
+
+# Probe the health, keeping the report in health.json and printing the status
+health() {
+  curl -s -o health.json -w '%{http_code}' "$SERVER_URL/health"
+}
+
+start_server
+check "incomplete environment" "$(health)" "503"
+check "failing checks" "$(jq -c .failing health.json)" '["circuit_wasm","proving_key","snarkjs"]'
+check "missing key named" "$(jq -r .checks.proving_key health.json)" "keys/zkHotdog_final.zkey is missing"
+check "node" "$(jq -r .checks.node health.json)" "ok"
+
+# The failed probe is reused
+rm npx_missing
+mkdir -p circuit-compiled/zkHotdog_js keys
+touch circuit-compiled/zkHotdog_js/zkHotdog.wasm keys/zkHotdog_final.zkey
+check "toolchain probe reused" "$(health) $(jq -c .failing health.json)" '503 ["snarkjs"]'
+stop_server
+
+start_server
+check "complete environment" "$(health)" "200"
+check "healthy" "$(jq -c '[.healthy, .failing]' health.json)" '[true,[]]'
+ID=$(curl -s -X POST "$SERVER_URL/measurements" \
+  -F "image=@$IMAGE" \
+  -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+  -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' | jq -r .measurement_id)
+for _ in $(seq 1 30); do
+  [ "$(curl -s "$SERVER_URL/status/$ID" | jq -r .status)" == "Completed" ] && break
+  sleep 1
+done
+health > /dev/null
+check "measurement counts" "$(jq -c .measurements health.json)" '{"Completed":1}'
+
+if [ "$(id -u)" -ne 0 ]; then
+  chmod a-w uploads
+  check "unwritable uploads" "$(health) $(jq -c .failing health.json)" '503 ["uploads_writable"]'
+  chmod u+w uploads
+fi
+stop_server
+
+finish "health"
@@ -0,0 +1,128 @@
+#!/bin/bash
+set -e
+
+# Test that hidden measurements are answered like unknown ones. Starts its
+# own server with the mock toolchain in test_vectors/mock_toolchain and
+# ADMIN_API_KEY set, submits measurements with a submitter key from
+# 127.0.0.1 and checks, against an ID no measurement has, that:
+#   - an embargoed measurement is answered with the same status and body by
+#     the view, image, artifact, proof and attestation endpoints, and by the
+#     status endpoint to another submitter's key;
+#   - another submitter's key, even from 127.0.0.1, gets the same status and
+#     body from the endpoints changing a measurement, even with a reproof
+#     circuit that does not exist;
+#   - an admin calling from 127.0.0.2 gets 403 where only the submitter may
+#     act, and the submitter's key is still let through from 127.0.0.2;
+#   - the public endpoints check keys like the others, refusing an unknown one.
+
+source "$(dirname "$0")/test_lib.sh"
+
+SERVER_URL="http://127.0.0.1:3001"
+ADMIN_KEY="zkh_test_admin_key"
+UNKNOWN="00000000-0000-0000-0000-000000000000"
+
+enter_work_dir
+start_server ADMIN_API_KEY="$ADMIN_KEY"
+
+SUBMITTER_KEY=$(curl -s -X POST "$SERVER_URL/admin/keys" -H "X-Api-Key: $ADMIN_KEY" \
+  -H 'Content-Type: application/json' -d '{"name": "field-app", "role": "submitter"}' |
+  jq -r .key)
+OTHER_KEY=$(curl -s -X POST "$SERVER_URL/admin/keys" -H "X-Api-Key: $ADMIN_KEY" \
+  -H 'Content-Type: application/json' -d '{"name": "other-app", "role": "submitter"}' |
+  jq -r .key)
+
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" -H "X-Api-Key: $SUBMITTER_KEY" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' "$@" | jq -r .measurement_id
+}
+
+wait_status() {
+  local current
+  for _ in $(seq 1 30); do
+    current=$(curl -s -H "X-Api-Key: $SUBMITTER_KEY" "$SERVER_URL/status/$1" | jq -r .status)
+    [ "$current" == "$2" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+# Status and body of a request from the given address with the given key,
+# with the measurement ID in the path written as {id}
+answer() {
+  local from="$1" key="$2" id="$3" method="$4" path="$5"
+  shift 5
+  curl -s -w ' %{http_code}' --interface "$from" -X "$method" -H "X-Api-Key: $key" \
+    "$SERVER_URL${path//\{id\}/$id}" "$@" | sed "s/$id/{id}/g"
+}
+
+# Check a request is answered the same for a hidden measurement as for an
+# unknown one, and not found
+same_as_unknown() {
+  local name="$1" hidden="$2" from="$3" key="$4"
+  shift 4
+  local expected actual
+  expected=$(answer "$from" "$key" "$UNKNOWN" "$@")
+  actual=$(answer "$from" "$key" "$hidden" "$@")
+  check "$name answered like an unknown ID" "$actual" "$expected"
+  check "$name not found" "${actual##* }" "404"
+}
+
+OWNED=$(submit)
+check "measurement of 127.0.0.1" "$(wait_status "$OWNED" Completed)" "Completed"
+EMBARGOED=$(submit -F 'publishAt=3600')
+check "embargoed measurement" "$(wait_status "$EMBARGOED" Completed)" "Completed"
+
+for path in /view/{id} /img/{id} /img/{id}/thumb/256 /measurements/{id}/artifacts/proof \
+  /proofs/{id} /attestation/{id}; do
+  same_as_unknown "embargoed GET $path" "$EMBARGOED" 127.0.0.1 "$SUBMITTER_KEY" GET "$path"
+done
+same_as_unknown "embargoed GET /status/{id}" "$EMBARGOED" 127.0.0.1 "$OTHER_KEY" GET /status/{id}
+
+JSON=(-H 'Content-Type: application/json')
+same_as_unknown "other submitter's PATCH" "$OWNED" 127.0.0.1 "$OTHER_KEY" \
+  PATCH /measurements/{id} "${JSON[@]}" -d '{"publish_at": null}'
+same_as_unknown "other submitter's DELETE" "$OWNED" 127.0.0.1 "$OTHER_KEY" \
+  DELETE /measurements/{id}
+same_as_unknown "other submitter's reproof" "$OWNED" 127.0.0.1 "$OTHER_KEY" \
+  POST /measurements/{id}/reprove
+same_as_unknown "other submitter's reproof with an unknown circuit" "$OWNED" 127.0.0.1 \
+  "$OTHER_KEY" POST '/measurements/{id}/reprove?circuit=no-such-circuit'
+same_as_unknown "other submitter's retry" "$OWNED" 127.0.0.1 "$OTHER_KEY" \
+  POST /measurements/{id}/retry
+
+# Status of a request, called like answer
+status_of() {
+  local from="$1" key="$2" id="$3" method="$4" path="$5"
+  shift 5
+  curl -s -o /dev/null -w '%{http_code}' --interface "$from" -X "$method" \
+    -H "X-Api-Key: $key" "$SERVER_URL${path//\{id\}/$id}" "$@"
+}
+
+check "unknown key on a public endpoint" \
+  "$(status_of 127.0.0.1 zkh_no_such_key "$OWNED" GET /img/{id})" "401"
+check "admin on a public endpoint" "$(status_of 127.0.0.2 "$ADMIN_KEY" "$OWNED" GET /img/{id})" "200"
+
+# Admins are told a measurement they may not change exists
+check "admin's PATCH" "$(status_of 127.0.0.2 "$ADMIN_KEY" "$EMBARGOED" PATCH /measurements/{id} \
+  "${JSON[@]}" -d '{"publish_at": null}')" "403"
+check "admin's reproof" \
+  "$(status_of 127.0.0.2 "$ADMIN_KEY" "$OWNED" POST /measurements/{id}/reprove)" "403"
+check "admin's retry" \
+  "$(status_of 127.0.0.2 "$ADMIN_KEY" "$OWNED" POST /measurements/{id}/retry)" "403"
+check "admin's retry of an unknown ID" \
+  "$(status_of 127.0.0.2 "$ADMIN_KEY" "$UNKNOWN" POST /measurements/{id}/retry)" "404"
+
+# The submitter's key is let through from any address, up to the checks on
+# the measurement itself
+check "submitter's retry of a completed measurement" \
+  "$(status_of 127.0.0.2 "$SUBMITTER_KEY" "$OWNED" POST /measurements/{id}/retry)" "409"
+check "submitter lifts the embargo" "$(status_of 127.0.0.2 "$SUBMITTER_KEY" "$EMBARGOED" \
+  PATCH /measurements/{id} "${JSON[@]}" -d '{"publish_at": null}')" "200"
+check "published image" \
+  "$(status_of 127.0.0.1 "$SUBMITTER_KEY" "$EMBARGOED" GET /img/{id})" "200"
+check "admin deletes another submitter's measurement" \
+  "$(status_of 127.0.0.2 "$ADMIN_KEY" "$OWNED" DELETE /measurements/{id})" "200"
+
+finish "hidden measurement"
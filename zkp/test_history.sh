@@ -0,0 +1,75 @@
+#!/bin/bash
+set -e
+
+# Test the status history of measurements. Starts its own server with the
+# mock toolchain in test_vectors/mock_toolchain and a single proof worker and
+# checks that:
+#   - a proved measurement's history lists its creation, the worker's claim
+#     and its completion in order, with generations and the time spent in
+#     every status but the final one;
+#   - /status/{id} leaves the history out;
+#   - a measurement whose deadline passes in the queue is failed by the
+#     watchdog, and one being proved at a restart by the recovery;
+#   - histories are unchanged by a restart;
+#   - unknown measurements are answered with 404.
+
+source "$(dirname "$0")/test_lib.sh"
+
+enter_work_dir
+
+SERVER_ENV=(PROOF_WORKERS=1)
+
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' "$@" | jq -r .measurement_id
+}
+
+wait_status() {
+  local current
+  for _ in $(seq 1 30); do
+    current=$(curl -s "$SERVER_URL/status/$1" | jq -r .status)
+    [ "$current" == "$2" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+# Transitions of a measurement as "<generation>:<from>><to>@<source>"
+transitions() {
+  curl -s "$SERVER_URL/measurements/$1/history" |
+    jq -r '[.transitions[] | "\(.generation):\(.from)>\(.to)@\(.source)"] | join(" ")'
+}
+
+start_server
+PROVED=$(submit)
+check "proved measurement" "$(wait_status "$PROVED" Completed)" "Completed"
+check "transitions" "$(transitions "$PROVED")" \
+  "1:null>Pending@submission 2:Pending>Processing@worker-1 3:Processing>Completed@worker-1"
+check "time in each status" "$(curl -s "$SERVER_URL/measurements/$PROVED/history" |
+  jq -c '[.truncated, [.transitions[] | .seconds_in_status | type]]')" \
+  '[false,["number","number","null"]]'
+check "history left out of the status" \
+  "$(curl -s "$SERVER_URL/status/$PROVED" | jq 'has("history")')" "false"
+
+# The only worker is kept busy while the next measurement waits for it
+echo 60 > mock_prove_delay
+INTERRUPTED=$(submit)
+check "measurement being proved" "$(wait_status "$INTERRUPTED" Processing)" "Processing"
+EXPIRED=$(submit -F 'deadline=1')
+check "deadline passed in the queue" "$(wait_status "$EXPIRED" Failed)" "Failed"
+check "failed by the watchdog" "$(transitions "$EXPIRED")" \
+  "1:null>Pending@submission 2:Pending>Failed@watchdog"
+
+BEFORE=$(curl -s "$SERVER_URL/measurements/$PROVED/history" | jq -c .transitions)
+rm mock_prove_delay
+start_server
+check "history after a restart" \
+  "$(curl -s "$SERVER_URL/measurements/$PROVED/history" | jq -c .transitions)" "$BEFORE"
+check "failed by the recovery" "$(transitions "$INTERRUPTED")" \
+  "1:null>Pending@submission 2:Pending>Processing@worker-1 3:Processing>Failed@recovery"
+check "unknown measurement" "$(curl -s -o /dev/null -w '%{http_code}' \
+  "$SERVER_URL/measurements/00000000-0000-0000-0000-000000000000/history")" "404"
+
+finish "history"
@@ -0,0 +1,104 @@
+#!/bin/bash
+set -e
+
+# Test idempotency keys of submissions. Starts its own server with the mock
+# toolchain in test_vectors/mock_toolchain and checks that:
+#   - a submission retried with the same Idempotency-Key header is answered
+#     with the first measurement, status 200, and proved only once;
+#   - concurrent retries with the same key all get the same measurement;
+#   - the key is also taken from the idempotencyKey form field, and keys are
+#     validated;
+#   - reusing a key with other points or another coordinate system answers
+#     409;
+#   - keys are remembered across a restart;
+#   - deleting a measurement releases its key;
+#   - with API keys, a key is scoped to the API key submitting, not to the
+#     address it submits from.
+
+source "$(dirname "$0")/test_lib.sh"
+
+SERVER_URL="http://127.0.0.1:3001"
+START='{"x": 0.1, "y": 0.2, "z": -0.3}'
+END='{"x": 0.4, "y": 0.2, "z": -0.3}'
+
+enter_work_dir
+
+# Submit with the given Idempotency-Key header and end point, printing the
+# HTTP status and the measurement ID or error
+submit() {
+  local output
+  output=$(mktemp -p .)
+  local code
+  code=$(curl -s -o "$output" -w '%{http_code}' -X POST "$SERVER_URL/measurements" \
+    -H "Idempotency-Key: $1" \
+    -F "image=@$IMAGE" -F "startPoint=$START" -F "endPoint=${2:-$END}" "${@:3}")
+  echo "$code $(jq -r .measurement_id "$output" 2>/dev/null || cat "$output")"
+  rm -f "$output"
+}
+
+proofs_started() {
+  grep -c "Starting proof generation for measurement $1" server.log || true
+}
+
+start_server
+FIRST=$(submit retry-1)
+ID=${FIRST#200 }
+check "first submission" "${FIRST%% *}" "200"
+check "retry" "$(submit retry-1)" "200 $ID"
+check "retry with the same points in another notation" \
+  "$(submit retry-1 '{"x": 0.40, "y": 0.2, "z": -0.3}')" "200 $ID"
+check "retry with other points" "$(submit retry-1 '{"x": 0.5, "y": 0.2, "z": -0.3}')" \
+  "409 Idempotency key retry-1 was already used for measurement $ID with other points"
+check "retry in another coordinate system" \
+  "$(submit retry-1 "$END" -F coordinateSystem=arfoundation_world | cut -c1-3)" "409"
+check "measurements stored" "$(curl -s "$SERVER_URL/measurements" | jq length)" "1"
+sleep 3
+check "proofs started" "$(proofs_started "$ID")" "1"
+
+# Retries racing each other while the image uploads
+RACERS=()
+for i in 1 2 3 4 5; do
+  submit race-1 > "race.$i" &
+  RACERS+=($!)
+done
+wait "${RACERS[@]}"
+check "concurrent retries" "$(cut -d' ' -f1,2 race.* | sort -u | wc -l)" "1"
+check "concurrent status" "$(cut -d' ' -f1 race.1)" "200"
+
+FIELD=$(curl -s -X POST "$SERVER_URL/measurements" -F "image=@$IMAGE" -F "startPoint=$START" \
+  -F "endPoint=$END" -F 'idempotencyKey=field-1' | jq -r .measurement_id)
+check "key from the form field" "$(submit field-1)" "200 $FIELD"
+check "empty key" "$(submit empty-1 "$END" -F 'idempotencyKey= ' | cut -c1-3)" "400"
+check "key too long" "$(submit "$(printf 'k%.0s' $(seq 1 256))" | cut -c1-3)" "400"
+
+start_server
+check "retry after a restart" "$(submit retry-1)" "200 $ID"
+
+for _ in $(seq 1 30); do
+  [ "$(curl -s "$SERVER_URL/status/$ID" | jq -r .status)" == "Completed" ] && break
+  sleep 1
+done
+check "deletion" "$(curl -s -o /dev/null -w '%{http_code}' -X DELETE "$SERVER_URL/measurements/$ID")" "200"
+SECOND=$(submit retry-1)
+check "key released by the deletion" "${SECOND%% *} $([ "${SECOND#200 }" != "$ID" ] && echo new)" \
+  "200 new"
+
+# Idempotency keys belong to the API key, wherever it calls from
+ADMIN_KEY="zkh_test_admin_key"
+start_server ADMIN_API_KEY="$ADMIN_KEY"
+new_key() {
+  curl -s -X POST "$SERVER_URL/admin/keys" -H "X-Api-Key: $ADMIN_KEY" \
+    -H 'Content-Type: application/json' -d "{\"name\": \"$1\", \"role\": \"submitter\"}" |
+    jq -r .key
+}
+APP_KEY=$(new_key field-app)
+OTHER_KEY=$(new_key other-app)
+KEYED=$(submit keyed-1 "$END" -H "X-Api-Key: $APP_KEY")
+check "keyed submission" "${KEYED%% *}" "200"
+check "retry from another address" \
+  "$(submit keyed-1 "$END" -H "X-Api-Key: $APP_KEY" --interface 127.0.0.2)" "$KEYED"
+OTHER=$(submit keyed-1 "$END" -H "X-Api-Key: $OTHER_KEY")
+check "same key from another API key" "${OTHER%% *} $([ "$OTHER" != "$KEYED" ] && echo new)" \
+  "200 new"
+
+finish "idempotency"
@@ -0,0 +1,148 @@
+#!/bin/bash
+set -e
+
+# Test images fetched from a URL. Starts its own server with the mock
+# toolchain in test_vectors/mock_toolchain and an image host on port 3002,
+# and checks that:
+#   - imageUrls resolving to loopback, private or link-local addresses are
+#     rejected with 400 without being fetched, unless IMAGE_URL_ALLOW_PRIVATE
+#     is set, which the production audit reports;
+#   - an image sent as imageUrl, also in a JSON body or after two redirects,
+#     is stored and proved like an uploaded one;
+#   - a third redirect, an answer other than 2xx, a Content-Type other than
+#     an image, an image larger than IMAGE_URL_MAX_BYTES with or without a
+#     Content-Length and a host slower than IMAGE_URL_TIMEOUT_SECS are
+#     answered with 422;
+#   - image together with imageUrl and a URL that is not http(s) are rejected
+#     with 400, and a submission refused for another field fetches nothing.
+#
+# Also needs python3 and sha256sum, and port 3002 must be free.
+
+source "$(dirname "$0")/test_lib.sh"
+
+HOST_URL="http://127.0.0.1:3002"
+
+enter_work_dir
+
+# Image host appending every path asked for to requests.log:
+#   /image.jpg      the test image
+#   /hop/N          N redirects, then the image
+#   /page           an HTML page
+#   /missing        404
+#   /big, /stream   2000 bytes of image, with and without a Content-Length
+#   /slow           the image after 4 seconds
+IMAGE="$IMAGE" python3 -c '
+import http.server, os, time
+image = open(os.environ["IMAGE"], "rb").read()
+class Handler(http.server.BaseHTTPRequestHandler):
+    def answer(self, status, content_type, body, length=True):
+        self.send_response(status)
+        self.send_header("Content-Type", content_type)
+        if length:
+            self.send_header("Content-Length", str(len(body)))
+        self.end_headers()
+        self.wfile.write(body)
+    def do_GET(self):
+        with open("requests.log", "a") as f:
+            f.write(self.path + "\n")
+        if self.path == "/image.jpg":
+            self.answer(200, "image/jpeg", image)
+        elif self.path.startswith("/hop/"):
+            hops = int(self.path[5:])
+            if hops == 0:
+                self.answer(200, "image/jpeg", image)
+            else:
+                self.send_response(302)
+                self.send_header("Location", "/hop/%d" % (hops - 1))
+                self.send_header("Content-Length", "0")
+                self.end_headers()
+        elif self.path == "/page":
+            self.answer(200, "text/html; charset=utf-8", b"<html></html>")
+        elif self.path == "/big":
+            self.answer(200, "image/jpeg", image + bytes(2000 - len(image)))
+        elif self.path == "/stream":
+            self.answer(200, "image/jpeg", image + bytes(2000 - len(image)), length=False)
+        elif self.path == "/slow":
+            time.sleep(4)
+            self.answer(200, "image/jpeg", image)
+        else:
+            self.answer(404, "text/plain", b"not found")
+    def log_message(self, *args):
+        pass
+http.server.ThreadingHTTPServer(("127.0.0.1", 3002), Handler).serve_forever()
+' &
+HOST_PID=$!
+BACKGROUND_PIDS+=" $HOST_PID"
+touch requests.log
+
+# Submit a measurement with the given fields, printing the status code and body
+submit() {
+  curl -s -w '\n%{http_code}' -X POST "$SERVER_URL/measurements" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' "$@"
+}
+
+# Status code of a submission of the given imageUrl
+code() {
+  submit -F "imageUrl=$1" | tail -n 1
+}
+
+wait_status() {
+  local current
+  for _ in $(seq 1 30); do
+    current=$(curl -s "$SERVER_URL/status/$1" | jq -r .status)
+    [ "$current" == "$2" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+start_server
+check "image urls advertised" "$(curl -s "$SERVER_URL/capabilities" | jq -c \
+  '[.features.image_urls.enabled, .limits.max_image_url_bytes]')" '[true,2097152]'
+for url in "$HOST_URL/image.jpg" http://localhost:3002/image.jpg "http://[::1]:3002/image.jpg" \
+  http://10.0.0.1/image.jpg http://169.254.169.254/latest/meta-data http://0.0.0.0:3002/image.jpg; do
+  check "refused $url" "$(code "$url")" "400"
+done
+check "address named" "$(submit -F "imageUrl=$HOST_URL/image.jpg" |
+  grep -c 'non-public address 127.0.0.1')" "1"
+check "nothing fetched" "$(wc -l < requests.log)" "0"
+check "audit passes" "$(curl -s "$SERVER_URL/version" | jq '.safety.violations | index("private_image_urls")')" "null"
+stop_server
+
+start_server IMAGE_URL_ALLOW_PRIVATE=true IMAGE_URL_MAX_BYTES=1000 IMAGE_URL_TIMEOUT_SECS=2
+check "audit reports private urls" \
+  "$(curl -s "$SERVER_URL/version" | jq '.safety.violations | index("private_image_urls") != null')" "true"
+
+FETCHED=$(submit -F "imageUrl=$HOST_URL/image.jpg" | head -n 1 | jq -r .measurement_id)
+check "fetched image proved" "$(wait_status "$FETCHED" Completed)" "Completed"
+check "fetched image stored" "$(curl -s "$SERVER_URL/img/$FETCHED" | sha256sum | cut -d' ' -f1)" \
+  "$(sha256sum < "$IMAGE" | cut -d' ' -f1)"
+check "fetched image hashed" "$(curl -s "$SERVER_URL/status/$FETCHED" | jq -r .image_sha256)" \
+  "$(sha256sum < "$IMAGE" | cut -d' ' -f1)"
+
+AS_JSON=$(curl -s -X POST "$SERVER_URL/measurements/json" -H 'Content-Type: application/json' \
+  -d "{\"imageUrl\": \"$HOST_URL/image.jpg\", \"startPoint\": {\"x\": 0.1, \"y\": 0.2, \"z\": -0.3},
+       \"endPoint\": {\"x\": 0.4, \"y\": 0.2, \"z\": -0.3}}" | jq -r .measurement_id)
+check "image url as JSON" "$(curl -s "$SERVER_URL/status/$AS_JSON" | jq -r .image_sha256)" \
+  "$(sha256sum < "$IMAGE" | cut -d' ' -f1)"
+
+check "two redirects" "$(code "$HOST_URL/hop/2")" "200"
+check "three redirects" "$(code "$HOST_URL/hop/3")" "422"
+check "upstream status" "$(submit -F "imageUrl=$HOST_URL/missing" | grep -c 'the server answered 404')" "1"
+check "not an image" "$(submit -F "imageUrl=$HOST_URL/page" | grep -c 'Content-Type text/html is not an image')" "1"
+check "too large" "$(submit -F "imageUrl=$HOST_URL/big" | grep -c 'larger than 1000 bytes')" "1"
+check "too large, undeclared" "$(code "$HOST_URL/stream")" "422"
+START=$(date +%s)
+check "too slow" "$(code "$HOST_URL/slow")" "422"
+check "timeout bounded" "$(( $(date +%s) - START < 4 ))" "1"
+
+check "image and imageUrl" "$(submit -F "image=@$IMAGE" -F "imageUrl=$HOST_URL/image.jpg" |
+  grep -c 'only one of image, imageUrl and uploadToken')" "1"
+check "not http" "$(code ftp://127.0.0.1/image.jpg)" "400"
+BEFORE=$(wc -l < requests.log)
+check "other field refused" "$(curl -s -o /dev/null -w '%{http_code}' -X POST "$SERVER_URL/measurements" \
+  -F "imageUrl=$HOST_URL/image.jpg" -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}')" "400"
+check "refused before fetching" "$(wc -l < requests.log)" "$BEFORE"
+
+finish "image URL"
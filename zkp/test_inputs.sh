@@ -0,0 +1,79 @@
+#!/bin/bash
+set -e
+
+# Golden-file test for circuit input construction. Every test vector in
+# test_vectors/inputs/<name>.request.json is submitted to the running server,
+# and the input.json it writes is compared byte for byte with
+# test_vectors/inputs/<name>.input.json. The coordinate_* vectors submit the
+# same points as negative in each coordinate system, pinning the conversion of
+# each system to the canonical convention, the angle vector pins the vectors
+# and cosine an angle measurement is proved with, and the threshold vector the
+# rounding of a minimum length.
+#
+# input.json determines what gets proved, so a mismatch means the encoding
+# changed. If the change is intended, regenerate the fixtures with
+#   UPDATE_GOLDEN=1 ./test_inputs.sh
+# and commit them together with the change, noting it in the circuit version.
+#
+# Run from the zkp directory while the server runs from the same directory.
+
+source "$(dirname "$0")/test_lib.sh"
+
+VECTORS_DIR="test_vectors/inputs"
+
+for REQUEST in "$VECTORS_DIR"/*.request.json; do
+  NAME=$(basename "$REQUEST" .request.json)
+  EXPECTED="$VECTORS_DIR/$NAME.input.json"
+  # Vectors without a coordinate system stand for legacy clients
+  SYSTEM=$(jq -r '.coordinateSystem // empty' "$REQUEST")
+
+  # Polyline vectors list their points instead of a start and end point,
+  # angle vectors add the mid point
+  if jq -e .points "$REQUEST" > /dev/null; then
+    POINTS=(-F "points=$(jq -c .points "$REQUEST")")
+  else
+    POINTS=(-F "startPoint=$(jq -c .startPoint "$REQUEST")" -F "endPoint=$(jq -c .endPoint "$REQUEST")")
+  fi
+  if jq -e .midPoint "$REQUEST" > /dev/null; then
+    POINTS+=(-F "measurementType=angle" -F "midPoint=$(jq -c .midPoint "$REQUEST")")
+  fi
+  if jq -e .minLengthCm "$REQUEST" > /dev/null; then
+    POINTS+=(-F "minLengthCm=$(jq -r .minLengthCm "$REQUEST")")
+  fi
+
+  RESPONSE=$(curl -s -X POST "$SERVER_URL/measurements" \
+    -F "image=@$IMAGE" \
+    "${POINTS[@]}" \
+    ${SYSTEM:+-F "coordinateSystem=$SYSTEM"})
+  ID=$(echo "$RESPONSE" | jq -r '.measurement_id // empty')
+  if [ -z "$ID" ]; then
+    echo -e "${RED}$NAME: submission failed: $RESPONSE${NC}"
+    FAILED=1
+    continue
+  fi
+
+  # input.json is written before the witness, even without compiled circuits
+  ACTUAL="proofs/$ID/input.json"
+  for _ in $(seq 1 30); do
+    [ -f "$ACTUAL" ] && break
+    sleep 1
+  done
+  if [ ! -f "$ACTUAL" ]; then
+    echo -e "${RED}$NAME: no input.json was written for measurement $ID${NC}"
+    FAILED=1
+    continue
+  fi
+
+  if [ -n "$UPDATE_GOLDEN" ]; then
+    cp "$ACTUAL" "$EXPECTED"
+    echo -e "${GREEN}$NAME: fixture updated${NC}"
+  elif cmp -s "$ACTUAL" "$EXPECTED"; then
+    echo -e "${GREEN}$NAME: ok${NC}"
+  else
+    echo -e "${RED}$NAME: input.json differs from $EXPECTED${NC}"
+    diff "$EXPECTED" "$ACTUAL" || true
+    FAILED=1
+  fi
+done
+
+finish "golden input"
@@ -0,0 +1,98 @@
+#!/bin/bash
+set -e
+
+# Test measurements submitted as JSON. Starts its own server with the mock
+# toolchain in test_vectors/mock_toolchain and a small JSON_MAX_BYTES and
+# checks that:
+#   - a measurement sent to POST /measurements/json with a base64 image
+#     completes and is stored like the same measurement sent as a multipart
+#     form: same scaled points, image hash and metadata;
+#   - the form's optional fields and headers are honoured;
+#   - invalid base64, malformed JSON, missing fields and a body over
+#     JSON_MAX_BYTES are rejected with 400.
+#
+# Also needs base64.
+
+source "$(dirname "$0")/test_lib.sh"
+
+enter_work_dir
+
+start_server JSON_MAX_BYTES=65536
+
+POINTS='"startPoint": {"x": 0.1, "y": 0.2, "z": -0.3}, "endPoint": {"x": 0.4, "y": 0.2, "z": -0.3}'
+IMAGE_B64=$(base64 -w0 "$IMAGE")
+
+# POST a JSON body to /measurements/json
+submit_json() {
+  local body="$1"
+  shift
+  curl -s -X POST "$SERVER_URL/measurements/json" \
+    -H 'Content-Type: application/json' --data-binary "$body" "$@"
+}
+
+wait_status() {
+  local current
+  for _ in $(seq 1 30); do
+    current=$(curl -s "$SERVER_URL/status/$1" | jq -r .status)
+    [ "$current" == "$2" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+# What both routes should store alike
+stored() {
+  curl -s "$SERVER_URL/status/$1" |
+    jq -c '[.start_point, .end_point, .original_points, .image_sha256, .metadata, .length_cm]'
+}
+
+JSON_ID=$(submit_json "{\"image\": \"$IMAGE_B64\", $POINTS, \"metadata\": {\"label\": \"hotdog\"}}" |
+  jq -r .measurement_id)
+check "JSON measurement" "$(wait_status "$JSON_ID" Completed)" "Completed"
+
+FORM_ID=$(curl -s -X POST "$SERVER_URL/measurements" \
+  -F "image=@$IMAGE" \
+  -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+  -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' \
+  -F 'metadata={"label": "hotdog"}' | jq -r .measurement_id)
+check "form measurement" "$(wait_status "$FORM_ID" Completed)" "Completed"
+check "stored alike" "$(stored "$JSON_ID")" "$(stored "$FORM_ID")"
+check "image kept" "$(curl -s "$SERVER_URL/img/$JSON_ID" | cmp -s - "$IMAGE" && echo same)" "same"
+
+# Optional fields are spelled as in the form, numbers allowed for times
+OPTIONAL=$(submit_json "{\"image\": \"$IMAGE_B64\", $POINTS, \"deadline\": 600, \
+  \"externalId\": \"json-1\", \"skipStages\": \"submit\", \"coordinateSystem\": \"arkit_world\"}" |
+  jq -r .measurement_id)
+check "optional fields" "$(wait_status "$OPTIONAL" Completed)" "Completed"
+check "fields honoured" "$(curl -s "$SERVER_URL/status/$OPTIONAL" |
+  jq -c '[.external_id, .deadline != null, .plan[1].state]')" '["json-1",true,"skipped"]'
+check "external id repeated" \
+  "$(submit_json "{\"image\": \"$IMAGE_B64\", $POINTS, \"externalId\": \"json-1\"}" \
+    -o /dev/null -w '%{http_code}')" "409"
+
+KEYED_BODY="{\"image\": \"$IMAGE_B64\", $POINTS}"
+KEYED=$(submit_json "$KEYED_BODY" -H 'Idempotency-Key: json-key' | jq -r .measurement_id)
+check "idempotency key header" \
+  "$(submit_json "$KEYED_BODY" -H 'Idempotency-Key: json-key' | jq -r .measurement_id)" "$KEYED"
+
+code() {
+  submit_json "$1" -o /dev/null -w '%{http_code}'
+}
+
+check "invalid base64" \
+  "$(submit_json "{\"image\": \"not base64!\", $POINTS}" | grep -c '^Invalid base64 image')" "1"
+check "malformed JSON" "$(code "{\"image\": \"$IMAGE_B64\", $POINTS")" "400"
+check "missing image" "$(submit_json "{$POINTS}")" "Missing image data"
+check "missing end point" \
+  "$(submit_json "{\"image\": \"$IMAGE_B64\", \"startPoint\": {\"x\": 0, \"y\": 0, \"z\": 0}}")" \
+  "Missing end point data"
+check "invalid point" \
+  "$(code "{\"image\": \"$IMAGE_B64\", \"startPoint\": {\"x\": \"far\"}, \"endPoint\": {}}")" "400"
+check "not JSON" "$(curl -s -o /dev/null -w '%{http_code}' -X POST "$SERVER_URL/measurements/json" \
+  --data-binary "{\"image\": \"$IMAGE_B64\", $POINTS}")" "400"
+
+LARGE_B64=$(head -c 60000 /dev/zero | base64 -w0)
+check "body over JSON_MAX_BYTES" "$(code "{\"image\": \"$LARGE_B64\", $POINTS}")" "400"
+check "limit in capabilities" "$(curl -s "$SERVER_URL/capabilities" | jq .limits.max_json_bytes)" "65536"
+
+finish "JSON submission"
@@ -0,0 +1,127 @@
+#!/bin/bash
+set -e
+
+# Test the API key lifecycle. Starts its own server with the mock toolchain in
+# test_vectors/mock_toolchain and ADMIN_API_KEY set. Checks that:
+#   - requests and submitted measurements are counted per key;
+#   - after a rotation both the old and the new key work until the grace
+#     period ends, after which the old key is refused with "key_expired";
+#   - a rotated key cannot be rotated again;
+#   - a disabled key is refused with "key_disabled" until enabled again;
+#   - a deleted key is unknown;
+#   - the usage survives in API_KEYS_PATH and every action is audited;
+#   - a key of each role, and a request without one, reaches the endpoints of
+#     its role and those below and is refused with 403 by those above, while
+#     an unknown key is refused with 401 everywhere.
+
+
+source "$(dirname "$0")/test_lib.sh"
+
+ADMIN_KEY="zkh_test_admin_key"
+GRACE_SECS=3
+
+enter_work_dir
+start_server ADMIN_API_KEY="$ADMIN_KEY"
+
+admin() {
+  local method="$1" path="$2"
+  shift 2
+  curl -s -X "$method" "$SERVER_URL$path" -H "X-Api-Key: $ADMIN_KEY" "$@"
+}
+
+# Status of listing the measurements with a key
+listing() {
+  curl -s -o /dev/null -w '%{http_code}' "$SERVER_URL/measurements" -H "X-Api-Key: $1"
+}
+
+# Error code a key is refused with
+refusal() {
+  curl -s "$SERVER_URL/measurements" -H "X-Api-Key: $1" | jq -r .error
+}
+
+key_field() {
+  admin GET /admin/keys | jq -r --arg id "$1" ".[] | select(.id == \$id) | .$2"
+}
+
+# Status of an endpoint of each class, viewer to admin, for a key; none for
+# requests without one
+admitted() {
+  local header=()
+  [ "$1" != none ] && header=(-H "X-Api-Key: $1")
+  local statuses=()
+  for path in /measurements /measurements/by-external-id/missing /admin/stats /admin/keys; do
+    statuses+=("$(curl -s -o /dev/null -w '%{http_code}' "${header[@]}" "$SERVER_URL$path")")
+  done
+  echo "${statuses[*]}"
+}
+
+CREATED=$(admin POST /admin/keys -H 'Content-Type: application/json' \
+  -d '{"name": "field-app", "role": "submitter"}')
+OLD_ID=$(echo "$CREATED" | jq -r .id)
+OLD_KEY=$(echo "$CREATED" | jq -r .key)
+
+# Usage is counted per key
+listing "$OLD_KEY" > /dev/null
+listing "$OLD_KEY" > /dev/null
+curl -s -o /dev/null -X POST "$SERVER_URL/measurements" -H "X-Api-Key: $OLD_KEY" \
+  -F "image=@$IMAGE" \
+  -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+  -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}'
+check "requests" "$(key_field "$OLD_ID" requests)" "3"
+check "measurements" "$(key_field "$OLD_ID" measurements)" "1"
+check "last used" "$(key_field "$OLD_ID" last_used_at | cut -c1-4)" "$(date -u +%Y)"
+check "idle keys" "$(admin GET '/admin/keys?idle_days=1' | jq length)" "0"
+
+# Rotation: both keys work during the grace period
+ROTATED=$(admin POST "/admin/keys/$OLD_ID/rotate?grace_secs=$GRACE_SECS")
+NEW_ID=$(echo "$ROTATED" | jq -r .id)
+NEW_KEY=$(echo "$ROTATED" | jq -r .key)
+check "new key replaces" "$(echo "$ROTATED" | jq -r .replaces)" "$OLD_ID"
+check "old key replaced by" "$(key_field "$OLD_ID" replaced_by)" "$NEW_ID"
+check "old key during grace" "$(listing "$OLD_KEY")" "200"
+check "new key during grace" "$(listing "$NEW_KEY")" "200"
+STATUS=$(admin POST "/admin/keys/$OLD_ID/rotate" -o /dev/null -w '%{http_code}')
+check "second rotation" "$STATUS" "409"
+
+# After the grace period only the new key works
+sleep $((GRACE_SECS + 1))
+check "old key after grace" "$(listing "$OLD_KEY")" "401"
+check "old key error" "$(refusal "$OLD_KEY")" "key_expired"
+check "new key after grace" "$(listing "$NEW_KEY")" "200"
+
+# Disabling and enabling
+check "disable" "$(admin POST "/admin/keys/$NEW_ID/disable" | jq -r '.disabled_at != null')" "true"
+check "disabled key" "$(listing "$NEW_KEY")" "403"
+check "disabled key error" "$(refusal "$NEW_KEY")" "key_disabled"
+NEW_REQUESTS=$(jq -r --arg id "$NEW_ID" '.[] | select(.id == $id) | .requests' api-keys.json)
+check "usage saved" "$NEW_REQUESTS" "2"
+admin POST "/admin/keys/$NEW_ID/enable" > /dev/null
+check "enabled key" "$(listing "$NEW_KEY")" "200"
+
+# Deletion
+STATUS=$(admin DELETE "/admin/keys/$NEW_ID" -o /dev/null -w '%{http_code}')
+check "delete" "$STATUS" "204"
+check "deleted key" "$(listing "$NEW_KEY")" "401"
+
+for action in api_key_created api_key_rotated api_key_disabled api_key_enabled api_key_revoked; do
+  check "audited $action" "$(jq -r .action audit.log | grep -c "^$action$")" "1"
+done
+
+# Every role against every endpoint class; the submitter endpoint answers 404
+# for the unknown external id once admitted
+for role in viewer submitter operator admin; do
+  KEY=$(admin POST /admin/keys -H 'Content-Type: application/json' \
+    -d "{\"name\": \"$role-key\", \"role\": \"$role\"}" | jq -r .key)
+  case $role in
+    viewer) expected="200 403 403 403" ;;
+    submitter) expected="200 404 403 403" ;;
+    operator) expected="200 404 200 403" ;;
+    admin) expected="200 404 200 200" ;;
+  esac
+  check "$role endpoints" "$(admitted "$KEY")" "$expected"
+done
+# Requests without a key have the default ANONYMOUS_ROLE, submitter
+check "anonymous endpoints" "$(admitted none)" "200 404 403 403"
+check "unknown key endpoints" "$(admitted zkh_unknown)" "401 401 401 401"
+
+finish "API key"
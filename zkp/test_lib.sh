@@ -0,0 +1,86 @@
+# Setup shared by the test scripts, sourced from the zkp directory with
+#
+#   source "$(dirname "$0")/test_lib.sh"
+#
+# It defines the colours, check, finish and the paths below. Scripts testing a
+# running server reach it at SERVER_URL (default http://localhost:3001).
+# Scripts starting their own server also use enter_work_dir and start_server;
+# those need jq and a built server (cargo build), run it on port 3001, which
+# must be free, and start the server binary in BACKEND when set.
+
+GREEN='\033[0;32m'
+RED='\033[0;31m'
+YELLOW='\033[1;33m'
+NC='\033[0m' # No Color
+
+SERVER_URL="${SERVER_URL:-http://localhost:3001}"
+ZKP_DIR=$(pwd)
+BACKEND="${BACKEND:-$ZKP_DIR/target/debug/backend}"
+IMAGE="$ZKP_DIR/test_vectors/inputs/image.jpg"
+MOCK_TOOLCHAIN="$ZKP_DIR/test_vectors/mock_toolchain"
+FAILED=0
+SERVER_PID=
+# Further background processes to kill on exit, such as mock hosts
+BACKGROUND_PIDS=
+# Settings every server of a script starts with, as VAR=value
+SERVER_ENV=()
+
+# Compare a value with the expected one, remembering a mismatch in FAILED
+check() {
+  local name="$1" actual="$2" expected="$3"
+  if [ "$actual" == "$expected" ]; then
+    echo -e "${GREEN}$name: $actual${NC}"
+  else
+    echo -e "${RED}$name: expected $expected, got $actual${NC}"
+    FAILED=1
+  fi
+}
+
+# Move to a temporary directory with an empty dist/, removed on exit together
+# with the server and BACKGROUND_PIDS
+enter_work_dir() {
+  WORK_DIR=$(mktemp -d)
+  mkdir -p "$WORK_DIR/dist"
+  cd "$WORK_DIR"
+  trap 'kill $SERVER_PID $BACKGROUND_PIDS 2>/dev/null || true; rm -rf "$WORK_DIR"' EXIT
+}
+
+# Wait up to 30 seconds for the server to answer its health check
+wait_for_server() {
+  for _ in $(seq 1 30); do
+    curl -s "$SERVER_URL/health" > /dev/null && return
+    sleep 1
+  done
+}
+
+stop_server() {
+  if [ -n "$SERVER_PID" ]; then
+    kill $SERVER_PID 2>/dev/null || true
+    wait $SERVER_PID 2>/dev/null || true
+    SERVER_PID=
+  fi
+}
+
+# Start the server on the mock toolchain with SERVER_ENV and the given
+# VAR=value settings, which may also replace PATH, replacing a running server,
+# and wait until it serves. Its output is appended to server.log.
+start_server() {
+  stop_server
+  env PATH="$MOCK_TOOLCHAIN:$PATH" "${SERVER_ENV[@]}" "$@" "$BACKEND" >> server.log 2>&1 &
+  SERVER_PID=$!
+  wait_for_server
+}
+
+# Report the outcome of the checks, named like "deletion", with the end of the
+# server log when one failed, and exit with it
+finish() {
+  if [ "$FAILED" -ne 0 ]; then
+    echo -e "${RED}${1^} checks failed${NC}"
+    if [ -f server.log ]; then
+      echo "Server log:"
+      tail -n 100 server.log
+    fi
+    exit 1
+  fi
+  echo -e "${GREEN}All $1 checks passed${NC}"
+}
@@ -0,0 +1,197 @@
+#!/bin/bash
+set -e
+
+# Test of the native Groth16 prover. The fixtures in test_vectors/native_prover
+# are the proving and verification keys snarkjs made for the circuit
+# c <== a * b (from ark-circom's test vectors), a witness of it for a = 3 and
+# b = 11, and a proof snarkjs made with the key, kept as the layout proof.json
+# must have. Checks that:
+#   - prove_native proves the witness with the zkey, the proof verifies
+#     against the verification key and proof.json and public.json are laid
+#     out exactly as snarkjs writes them;
+#   - files that are not a zkey or a witness, truncated ones and a witness of
+#     another circuit are refused;
+#   - a server with PROVER=native, whose zkHotdog key is the fixture key,
+#     advertises it, proves a measurement of distance squared 33 in process,
+#     without snarkjs, into a proof that verifies, and fails a measurement
+#     whose proved distance differs from its own;
+#   - the key is not pinned for snarkjs as well, also when a supervising
+#     server leaves proving to its prover process, while a key that cannot be
+#     read is, and falls back to snarkjs; an unknown PROVER stops the server
+#     from starting.
+# With the compiled circuit, its keys and snarkjs installed in this directory,
+# the same input.json is also proved with snarkjs and the native prover, and
+# both proofs are verified against keys/verification_key.json; otherwise
+# that is skipped.
+#
+# Also needs prove_native and verify_proof (cargo build).
+
+source "$(dirname "$0")/test_lib.sh"
+
+PROVE_NATIVE="$ZKP_DIR/target/debug/prove_native"
+VERIFY_PROOF="$ZKP_DIR/target/debug/verify_proof"
+FIXTURES="$ZKP_DIR/test_vectors/native_prover"
+
+enter_work_dir
+mkdir -p keys bin
+SERVER_ENV=(PATH="$WORK_DIR/bin:$MOCK_TOOLCHAIN:$PATH" PROVER=native)
+
+# The fixture witness stands in for generate_witness.js, or the file named in
+# ./witness_source; anything else is left to the mock node
+cat > bin/node << EOF
+#!/bin/bash
+case "\$1" in
+  *generate_witness.js)
+    cp "\$(cat witness_source 2>/dev/null || echo "$FIXTURES/multiplier.wtns")" "\$4"
+    ;;
+  *)
+    exec "$MOCK_TOOLCHAIN/node" "\$@"
+    ;;
+esac
+EOF
+chmod +x bin/node
+
+# Whether two proof.json files match byte for byte once their numbers are masked
+same_layout() {
+  cmp -s <(sed -E 's/"[0-9]+"/"N"/g' "$1") <(sed -E 's/"[0-9]+"/"N"/g' "$2") && echo same || echo different
+}
+
+# Exit status of prove_native with the given arguments
+prove_status() {
+  local status=0
+  "$PROVE_NATIVE" "$@" > /dev/null || status=$?
+  echo "$status"
+}
+
+check "fixture proved" "$(prove_status "$FIXTURES/multiplier.zkey" "$FIXTURES/multiplier.wtns" \
+  proof.json public.json)" "0"
+check "fixture verifies" "$("$VERIFY_PROOF" "$FIXTURES/verification_key.json" public.json proof.json)" "OK"
+check "public signals" "$(cat public.json)" $'[\n "33"\n]'
+check "proof layout" "$(same_layout proof.json "$FIXTURES/snarkjs_proof.json")" "same"
+"$PROVE_NATIVE" "$FIXTURES/multiplier.zkey" "$FIXTURES/multiplier.wtns" again.json again_public.json > /dev/null
+check "randomized" "$(cmp -s proof.json again.json && echo same || echo different)" "different"
+
+head -c 1000 "$FIXTURES/multiplier.zkey" > truncated.zkey
+head -c 100 "$FIXTURES/multiplier.wtns" > truncated.wtns
+check "not a zkey" "$("$PROVE_NATIVE" "$FIXTURES/multiplier.wtns" "$FIXTURES/multiplier.wtns" p.json q.json |
+  grep -c 'not a zkey file')" "1"
+check "truncated zkey" "$(prove_status truncated.zkey "$FIXTURES/multiplier.wtns" p.json q.json)" "2"
+check "not a witness" "$(prove_status "$FIXTURES/multiplier.zkey" "$FIXTURES/multiplier.zkey" p.json q.json)" "2"
+check "truncated witness" "$(prove_status "$FIXTURES/multiplier.zkey" truncated.wtns p.json q.json)" "2"
+# A witness of five signals, one more than the circuit has
+python3 -c '
+import sys
+wtns = open(sys.argv[1], "rb").read()
+header_end = 12 + 12 + 40
+values = wtns[header_end + 12:]
+header = wtns[12 + 12:header_end - 4] + (5).to_bytes(4, "little")
+body = values + (7).to_bytes(32, "little")
+sections = (1).to_bytes(4, "little") + len(header).to_bytes(8, "little") + header \
+    + (2).to_bytes(4, "little") + len(body).to_bytes(8, "little") + body
+open("other.wtns", "wb").write(wtns[:12] + sections)
+' "$FIXTURES/multiplier.wtns"
+check "witness of another circuit" "$("$PROVE_NATIVE" "$FIXTURES/multiplier.zkey" other.wtns p.json q.json |
+  grep -c 'expected a witness of 4 signals, got 5')" "1"
+check "nothing written" "$(ls p.json q.json 2>/dev/null | wc -l)" "0"
+
+# Submit a measurement with the given end point and print its ID once it is done
+prove() {
+  local id status=""
+  id=$(curl -s -X POST "$SERVER_URL/measurements" -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0, "y": 0, "z": 0}' -F "endPoint=$1" | jq -r '.measurement_id // empty')
+  for _ in $(seq 1 60); do
+    status=$(curl -s "$SERVER_URL/status/$id" | jq -r '.status // empty')
+    [ "$status" == "Completed" ] || [ "$status" == "Failed" ] && break
+    sleep 0.5
+  done
+  echo "$id"
+}
+
+status() {
+  curl -s "$SERVER_URL/status/$1" | jq -r .status
+}
+
+# The fixture circuit proves c = a * b = 33, the distance squared of (5, 2, 2)
+# scaled units from the origin
+cp "$FIXTURES/multiplier.zkey" keys/zkHotdog_final.zkey
+cp "$FIXTURES/verification_key.json" keys/verification_key.json
+start_server
+check "native prover advertised" "$(curl -s "$SERVER_URL/capabilities" |
+  jq .features.native_prover.enabled)" "true"
+check "key loaded" "$(grep -c 'Loaded proving key of zkhotdog-v1 for native proving' server.log)" "1"
+check "key not pinned" "$(grep -c 'Pinned proving key' server.log || true)" "0"
+
+NATIVE=$(prove '{"x": 0.00005, "y": 0.00002, "z": 0.00002}')
+check "proved natively" "$(status "$NATIVE")" "Completed"
+check "proof verifies" "$("$VERIFY_PROOF" keys/verification_key.json \
+  "proofs/$NATIVE/public.json" "proofs/$NATIVE/proof.json")" "OK"
+check "proved signals" "$(cat "proofs/$NATIVE/public.json")" $'[\n "33"\n]'
+check "snarkjs not run" "$(grep -c 'npx\|snarkjs' "proofs/$NATIVE/pipeline.log" || true)" "0"
+check "native prover logged" "$(grep -c 'native prover wrote the proof' "proofs/$NATIVE/pipeline.log")" "1"
+
+OTHER=$(prove '{"x": 0.00005, "y": 0.00002, "z": 0.00003}')
+check "other distance refused" "$(curl -s "$SERVER_URL/status/$OTHER" |
+  jq -r '[.status, (.failure.message | test("does not match expected"))] | join(" ")')" "Failed true"
+
+cp "$FIXTURES/multiplier.wtns" short.wtns
+truncate -s 100 short.wtns
+echo "$WORK_DIR/short.wtns" > witness_source
+BROKEN=$(prove '{"x": 0.00005, "y": 0.00002, "z": 0.00002}')
+check "bad witness fails" "$(curl -s "$SERVER_URL/status/$BROKEN" |
+  jq -r '[.status, (.failure.message | test("native prover"))] | join(" ")')" "Failed true"
+rm witness_source
+
+# A supervising server leaves the key to its prover process, pinning nothing
+stop_server
+: > server.log
+start_server SUPERVISE=true
+SUPERVISED=$(prove '{"x": 0.00005, "y": 0.00002, "z": 0.00002}')
+check "proved by the prover process" "$(status "$SUPERVISED")" "Completed"
+check "supervised key loaded once" "$(grep -c 'Loaded proving key of zkhotdog-v1' server.log)" "1"
+check "supervised key not pinned" "$(grep -c 'Pinned proving key' server.log || true)" "0"
+
+# A key that cannot be read leaves proving to the (mock) snarkjs
+truncate -s 1000 keys/zkHotdog_final.zkey
+stop_server
+: > server.log
+start_server
+check "fallback logged" "$(grep -c 'Cannot prove zkhotdog-v1 natively, falling back to snarkjs' server.log)" "1"
+FALLBACK=$(prove '{"x": 0.00005, "y": 0.00002, "z": 0.00002}')
+check "proved with snarkjs" "$(status "$FALLBACK")" "Completed"
+check "fallback key pinned" "$(grep -c 'Pinned proving key of zkhotdog-v1' server.log)" "1"
+check "snarkjs proof" "$(cmp -s "proofs/$FALLBACK/proof.json" "$MOCK_TOOLCHAIN/proof.json" && echo mock)" "mock"
+
+stop_server
+STATUS=0
+PROVER=rapidsnark timeout 10 "$BACKEND" > invalid.log 2>&1 || STATUS=$?
+check "unknown prover refused" "$STATUS $(grep -c "unknown prover 'rapidsnark'" invalid.log)" "1 1"
+
+# Both provers on the real circuit, when it is built and snarkjs is installed
+cd "$ZKP_DIR"
+REAL_ZKEY=keys/zkHotdog_final.zkey
+REAL_VKEY=keys/verification_key.json
+GENERATOR=circuit-compiled/zkHotdog_js/generate_witness.js
+WASM=circuit-compiled/zkHotdog_js/zkHotdog.wasm
+if [ -f "$REAL_ZKEY" ] && [ -f "$REAL_VKEY" ] && [ -f "$WASM" ] &&
+  npx --no-install snarkjs --help 2>&1 | grep -v mock | grep -q '^snarkjs@'; then
+  cp test_vectors/inputs/positive.input.json "$WORK_DIR/input.json"
+  node "$GENERATOR" "$WASM" "$WORK_DIR/input.json" "$WORK_DIR/real.wtns"
+  npx --no-install snarkjs groth16 prove "$REAL_ZKEY" "$WORK_DIR/real.wtns" \
+    "$WORK_DIR/snarkjs_proof.json" "$WORK_DIR/snarkjs_public.json"
+  "$PROVE_NATIVE" "$REAL_ZKEY" "$WORK_DIR/real.wtns" "$WORK_DIR/native_proof.json" "$WORK_DIR/native_public.json"
+  check "snarkjs proof verifies" "$("$VERIFY_PROOF" "$REAL_VKEY" "$WORK_DIR/snarkjs_public.json" \
+    "$WORK_DIR/snarkjs_proof.json")" "OK"
+  check "native proof verifies" "$("$VERIFY_PROOF" "$REAL_VKEY" "$WORK_DIR/native_public.json" \
+    "$WORK_DIR/native_proof.json")" "OK"
+  check "snarkjs accepts native proof" "$(npx --no-install snarkjs groth16 verify "$REAL_VKEY" \
+    "$WORK_DIR/native_public.json" "$WORK_DIR/native_proof.json" | grep -c 'OK')" "1"
+  check "same public signals" "$(cmp -s "$WORK_DIR/snarkjs_public.json" "$WORK_DIR/native_public.json" &&
+    echo same)" "same"
+  check "same proof layout" "$(same_layout "$WORK_DIR/native_proof.json" "$WORK_DIR/snarkjs_proof.json")" \
+    "same"
+else
+  echo -e "${YELLOW}snarkjs comparison: skipped, needs the compiled circuit, its keys and snarkjs${NC}"
+fi
+cd "$WORK_DIR"
+
+finish "native prover"
@@ -0,0 +1,115 @@
+#!/bin/bash
+set -e
+
+# Test keyset pagination of GET /measurements. Starts its own server with the
+# mock toolchain in test_vectors/mock_toolchain and checks that:
+#   - paging with ?limit= while measurements are submitted between pages, and
+#     across a restart, lists every measurement exactly once, in the order of
+#     the full listing;
+#   - pages report the total count, and their Link header leads to the next
+#     page with the same filters;
+#   - ?status= filters by any of the given proof statuses, pages with the
+#     statuses in either order and rejects unknown statuses with 400;
+#   - a cursor used with another filter is rejected as cursor_filter_mismatch;
+#   - a tampered cursor is rejected as invalid_cursor, and limit=0 with 400.
+
+source "$(dirname "$0")/test_lib.sh"
+
+enter_work_dir
+
+SERVER_ENV=(PUBLIC_BASE_URL="$SERVER_URL")
+
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' > /dev/null
+}
+
+# Fetch a page into page.json and print its next cursor, if any
+fetch_page() {
+  curl -s -D headers.txt "$SERVER_URL/measurements?$1" > page.json
+  grep -i '^x-next-cursor:' headers.txt | cut -d' ' -f2 | tr -d '\r'
+}
+
+start_server
+for _ in $(seq 1 5); do
+  submit
+done
+
+# Page through two at a time, submitting between the first pages and
+# restarting the server after the second
+: > paged.txt
+CURSOR=$(fetch_page "limit=2")
+PAGES=1
+jq -r '.[].id' page.json >> paged.txt
+while [ -n "$CURSOR" ]; do
+  if [ "$PAGES" -le 3 ]; then
+    submit
+  fi
+  if [ "$PAGES" -eq 2 ]; then
+    kill $SERVER_PID
+    wait $SERVER_PID 2>/dev/null || true
+    start_server
+  fi
+  CURSOR=$(fetch_page "limit=2&cursor=$CURSOR")
+  PAGES=$((PAGES + 1))
+  jq -r '.[].id' page.json >> paged.txt
+  [ "$PAGES" -le 20 ] || break
+done
+
+curl -s "$SERVER_URL/measurements" | jq -r '.[].id' > all.txt
+check "measurements listed" "$(wc -l < all.txt | tr -d ' ')" "8"
+check "measurements paged" "$(wc -l < paged.txt | tr -d ' ')" "8"
+check "listed twice" "$(sort paged.txt | uniq -d | wc -l | tr -d ' ')" "0"
+check "same order as the full listing" "$(cmp -s all.txt paged.txt && echo same)" "same"
+check "pages" "$PAGES" "4"
+
+# The next link repeats the limit and filters
+fetch_page "limit=3&imported=false" > /dev/null
+check "total count" "$(grep -i '^x-total-count:' headers.txt | cut -d' ' -f2 | tr -d '\r')" "8"
+NEXT=$(grep -i '^link:' headers.txt | sed 's/^[^<]*<\([^>]*\)>; rel="next".*/\1/' | tr -d '\r')
+check "next link filters" "$(echo "$NEXT" | grep -o 'limit=3.*imported=false' > /dev/null && echo kept)" "kept"
+check "next link page" "$(curl -s "$NEXT" | jq -r '.[].id' | tr '\n' ' ')" "$(sed -n 4,6p all.txt | tr '\n' ' ')"
+
+# Filter by proof status once every measurement has been proven
+for _ in $(seq 1 30); do
+  curl -s "$SERVER_URL/measurements?status=Pending&status=Processing" | jq -e 'length == 0' > /dev/null && break
+  sleep 1
+done
+# Measurements interrupted by the restart fail, the others complete
+curl -s "$SERVER_URL/measurements" > all.json
+for status in Completed Failed; do
+  fetch_page "status=$status" > /dev/null
+  check "$status filter" "$(jq -r '.[].id' page.json | tr '\n' ' ')" \
+    "$(jq -r --arg s "$status" '.[] | select(.status == $s) | .id' all.json | tr '\n' ' ')"
+done
+fetch_page "status=Pending&status=Scheduled" > /dev/null
+check "status filter without matches" \
+  "$(jq length page.json) $(grep -i '^x-total-count:' headers.txt | cut -d' ' -f2 | tr -d '\r')" "0 0"
+CURSOR=$(fetch_page "limit=3&status=Failed&status=Completed")
+check "status filter total count" "$(grep -i '^x-total-count:' headers.txt | cut -d' ' -f2 | tr -d '\r')" "8"
+NEXT=$(grep -i '^link:' headers.txt | sed 's/^[^<]*<\([^>]*\)>; rel="next".*/\1/' | tr -d '\r')
+check "next link statuses" "$(echo "$NEXT" | grep -o 'status=Completed&status=Failed' > /dev/null && echo kept)" "kept"
+fetch_page "limit=3&status=Completed&status=Failed&cursor=$CURSOR" > /dev/null
+check "statuses in another order" "$(jq -r '.[].id' page.json | tr '\n' ' ')" "$(sed -n 4,6p all.txt | tr '\n' ' ')"
+curl -s "$SERVER_URL/measurements?limit=3&status=Completed&cursor=$CURSOR" > error.json
+check "cursor with other statuses" "$(jq -r .error error.json)" "cursor_filter_mismatch"
+curl -s -D headers.txt "$SERVER_URL/measurements?status=Completed&status=Done" > error.txt
+check "unknown status" "$(head -1 headers.txt | cut -d' ' -f2) $(cat error.txt)" \
+  "400 Unknown status 'Done'; accepted values are Scheduled, Pending, Processing, Completed, Failed, Cancelled"
+
+# Cursors are bound to their filter
+CURSOR=$(fetch_page "limit=1&owner=127.0.0.1")
+check "filtered page" "$(jq length page.json)" "1"
+curl -s "$SERVER_URL/measurements?limit=1&cursor=$CURSOR" > error.json
+check "cursor with another filter" "$(jq -r .error error.json)" "cursor_filter_mismatch"
+check "cursor with the same filter" \
+  "$(curl -s -o /dev/null -w '%{http_code}' "$SERVER_URL/measurements?limit=1&owner=127.0.0.1&cursor=$CURSOR")" "200"
+TAMPERED="${CURSOR:0:10}0${CURSOR:11}"
+[ "$TAMPERED" != "$CURSOR" ] || TAMPERED="${CURSOR:0:10}1${CURSOR:11}"
+curl -s -D headers.txt "$SERVER_URL/measurements?limit=1&owner=127.0.0.1&cursor=$TAMPERED" > error.json
+check "tampered cursor" "$(head -1 headers.txt | cut -d' ' -f2) $(jq -r .error error.json)" "400 invalid_cursor"
+check "limit 0" "$(curl -s -o /dev/null -w '%{http_code}' "$SERVER_URL/measurements?limit=0")" "400"
+
+finish "pagination"
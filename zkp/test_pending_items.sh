@@ -0,0 +1,94 @@
+#!/bin/bash
+set -e
+
+# Test the strict status mode. Starts its own server with the mock toolchain in
+# test_vectors/mock_toolchain twice: first with a slow mock prover, a single
+# proof worker and attestations delivered by the verification client, then
+# with the attestation poller on. Checks that in each configuration:
+#   - pending_items lists what is still expected at every stage, and is only
+#     reported with ?strict=true;
+#   - a failed measurement expects nothing;
+#   - with ?strict=true, artifacts still expected are answered with 409 and
+#     "pending", and artifacts that will never exist with 404.
+
+source "$(dirname "$0")/test_lib.sh"
+
+enter_work_dir
+
+SERVER_ENV=(PROOF_WORKERS=1)
+
+# Submit a measurement, with extra form fields, and print its ID
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' "$@" | jq -r .measurement_id
+}
+
+pending() {
+  curl -s "$SERVER_URL/status/$1?strict=true" | jq -c .pending_items
+}
+
+# Wait until a measurement reaches the given status and print the last one seen
+wait_status() {
+  local current
+  for _ in $(seq 1 30); do
+    current=$(curl -s "$SERVER_URL/status/$1" | jq -r .status)
+    [ "$current" == "$2" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+# Status code of an artifact download, and its error code if there is one
+artifact() {
+  local code
+  code=$(curl -s -o artifact.out -w '%{http_code}' \
+    "$SERVER_URL/measurements/$1/artifacts/$2?strict=$3")
+  echo "$code $(jq -r '.error // empty' artifact.out 2>/dev/null)" | xargs
+}
+
+ALL='["proof","verification","attestation"]'
+
+# Attestations delivered by the verification client
+echo 4 > mock_prove_delay
+start_server
+PROVING=$(submit)
+check "processing" "$(wait_status "$PROVING" Processing)" "Processing"
+QUEUED=$(submit)
+EXPIRING=$(submit -F deadline=2)
+check "queued items" "$(pending "$QUEUED")" "$ALL"
+check "proving items" "$(pending "$PROVING")" "$ALL"
+check "not strict" "$(curl -s "$SERVER_URL/status/$PROVING" | jq 'has("pending_items")')" "false"
+check "strict proof while proving" "$(artifact "$PROVING" proof true)" "409 pending"
+check "proof while proving" "$(artifact "$PROVING" proof false)" "404"
+check "failed" "$(wait_status "$EXPIRING" Failed)" "Failed"
+check "failed items" "$(pending "$EXPIRING")" "[]"
+check "strict proof of failed" "$(artifact "$EXPIRING" proof true)" "404"
+check "completed" "$(wait_status "$PROVING" Completed)" "Completed"
+check "completed items" "$(pending "$PROVING")" "[]"
+check "strict attestation of completed" "$(artifact "$PROVING" attestation true)" "200"
+check "queued completes" "$(wait_status "$QUEUED" Completed)" "Completed"
+stop_server
+rm mock_prove_delay
+
+# Attestations looked up by the poller
+echo 0 > mock_published
+start_server ATTESTATION_POLLING=true ATTESTATION_POLL_INTERVAL_SECS=1
+AWAITING=$(submit -F externalId=awaiting)
+for _ in $(seq 1 30); do
+  [ "$(pending "$AWAITING")" == '["attestation"]' ] && break
+  sleep 1
+done
+check "awaiting attestation items" "$(pending "$AWAITING")" '["attestation"]'
+check "awaiting status" "$(wait_status "$AWAITING" Processing)" "Processing"
+check "strict proof while awaiting" "$(artifact "$AWAITING" proof true)" "200"
+check "strict attestation while awaiting" "$(artifact "$AWAITING" attestation true)" "409 pending"
+check "by external id" \
+  "$(curl -s "$SERVER_URL/measurements/by-external-id/awaiting?strict=true" | jq -c .pending_items)" \
+  '["attestation"]'
+echo 1 > mock_published
+check "attested" "$(wait_status "$AWAITING" Completed)" "Completed"
+check "attested items" "$(pending "$AWAITING")" "[]"
+
+finish "strict status"
@@ -0,0 +1,115 @@
+#!/bin/bash
+set -e
+
+# Test that measurements survive a restart. Starts its own server with the mock
+# toolchain in test_vectors/mock_toolchain and a single proof worker, and makes
+# the mock prover hang so one measurement is Processing and another Pending
+# when the server is killed. After starting it again, checks that:
+#   - a completed measurement is still there with its status and external id;
+#   - the interrupted Processing measurement failed as Interrupted;
+#   - the Pending measurement was queued again and completes;
+#   - new submissions are stored next to the recovered ones;
+#   - with the database removed, measurements are rebuilt from uploads/ and
+#     proofs/ by the consistency scan after startup, skipping an image without
+#     inputs and malformed JSON.
+#
+# Also needs setsid.
+
+source "$(dirname "$0")/test_lib.sh"
+
+enter_work_dir
+
+# Unlike the one of test_lib.sh, the server runs in its own process group, so
+# a crash takes the hanging prover with it
+start_server() {
+  PATH="$MOCK_TOOLCHAIN:$PATH" PROOF_WORKERS=1 setsid "$BACKEND" >> server.log 2>&1 &
+  SERVER_PID=$!
+  wait_for_server
+}
+
+# Submit a measurement with the given external id and print its ID
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' \
+    -F "externalId=$1" | jq -r .measurement_id
+}
+
+status() {
+  curl -s "$SERVER_URL/status/$1" | jq -r .status
+}
+
+# Wait until a measurement reaches the given status and print the last one seen
+wait_status() {
+  local current
+  for _ in $(seq 1 30); do
+    current=$(status "$1")
+    [ "$current" == "$2" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+# Wait for the consistency scan of the running server and print its state
+wait_scan() {
+  local state
+  for _ in $(seq 1 30); do
+    state=$(curl -s "$SERVER_URL/version" | jq -r .startup.consistency_scan.state)
+    [ "$state" == "completed" ] && break
+    sleep 1
+  done
+  echo "$state"
+}
+
+start_server
+COMPLETED=$(submit before-restart)
+check "completed before restart" "$(wait_status "$COMPLETED" Completed)" "Completed"
+
+# With the prover hanging, the only worker keeps the next measurement busy
+echo 600 > mock_prove_delay
+INTERRUPTED=$(submit interrupted)
+check "processing before restart" "$(wait_status "$INTERRUPTED" Processing)" "Processing"
+QUEUED=$(submit queued)
+check "pending before restart" "$(status "$QUEUED")" "Pending"
+
+kill -9 -- -$SERVER_PID
+wait $SERVER_PID 2>/dev/null || true
+rm mock_prove_delay
+start_server
+
+check "completed after restart" "$(status "$COMPLETED")" "Completed"
+check "external id after restart" \
+  "$(curl -s "$SERVER_URL/measurements/by-external-id/before-restart" | jq -r .id)" "$COMPLETED"
+check "external id still taken" "$(submit before-restart)" "$COMPLETED"
+check "interrupted measurement" "$(status "$INTERRUPTED")" "Failed"
+check "failure class" "$(curl -s "$SERVER_URL/status/$INTERRUPTED" | jq -r .failure.class)" "Interrupted"
+check "queued measurement" "$(wait_status "$QUEUED" Completed)" "Completed"
+AFTER=$(submit after-restart)
+check "new measurement" "$(wait_status "$AFTER" Completed)" "Completed"
+check "measurements listed" "$(curl -s "$SERVER_URL/measurements" | jq length)" "4"
+
+# Without the database, measurements are rebuilt from their files
+kill $SERVER_PID
+wait $SERVER_PID 2>/dev/null || true
+rm -rf measurements.db
+ORPHAN=00000000-0000-4000-8000-000000000001
+MALFORMED=00000000-0000-4000-8000-000000000002
+cp "$IMAGE" "uploads/$ORPHAN.jpg"
+mkdir "proofs/$MALFORMED"
+echo '{"point1": [' > "proofs/$MALFORMED/input.json"
+start_server
+check "consistency scan" "$(wait_scan)" "completed"
+check "scan rebuilt" "$(curl -s "$SERVER_URL/version" | jq .startup.consistency_scan.rebuilt)" "4"
+
+check "rebuilt completed" "$(status "$COMPLETED")" "Completed"
+check "rebuilt attestation" \
+  "$(curl -s "$SERVER_URL/status/$COMPLETED" | jq .attestation.attestationId)" "1"
+check "rebuilt length" "$(curl -s "$SERVER_URL/status/$COMPLETED" | jq .length_cm)" "30"
+# The interrupted measurement only got as far as its inputs, so it is proved again
+check "rebuilt interrupted" "$(wait_status "$INTERRUPTED" Completed)" "Completed"
+check "image without inputs" "$(curl -s -o /dev/null -w '%{http_code}' "$SERVER_URL/status/$ORPHAN")" "404"
+check "malformed inputs" "$(curl -s -o /dev/null -w '%{http_code}' "$SERVER_URL/status/$MALFORMED")" "404"
+check "rebuild summary" "$(grep -c '3 completed, 0 awaiting their attestation, 1 pending; skipped 1 without input.json and 1 with malformed JSON' server.log)" "1"
+
+finish "persistence"
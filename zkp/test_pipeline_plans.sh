@@ -0,0 +1,110 @@
+#!/bin/bash
+set -e
+
+# Test the pipeline plans of measurements. Starts its own server with the
+# mock toolchain in test_vectors/mock_toolchain and a single proof worker and
+# checks that:
+#   - a measurement reports every stage of its plan while queued and runs
+#     them, with when each started and finished;
+#   - skipStages=submit completes a measurement once it is proved, without a
+#     submission or an attestation, while skipping prove or an unknown stage
+#     is refused, as is PIPELINE_SKIP_STAGES=prove at startup;
+#   - a refused submission fails its submit stage, and a retry from submit
+#     completes it with the proof of the first attempt, while a retry from
+#     submit before prove is done, or from an unknown stage, is refused;
+#   - a cancellation during proving marks the prove stage cancelled.
+
+source "$(dirname "$0")/test_lib.sh"
+
+enter_work_dir
+
+# A stage no measurement can do without is refused before the server starts
+STARTUP=$(PIPELINE_SKIP_STAGES=prove timeout 10 "$BACKEND" 2>&1 || true)
+check "skipping prove at startup" \
+  "$(echo "$STARTUP" | grep -c "stage 'prove' cannot be skipped")" "1"
+
+start_server PROOF_WORKERS=1
+
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' "$@"
+}
+
+wait_status() {
+  local current
+  for _ in $(seq 1 30); do
+    current=$(curl -s "$SERVER_URL/status/$1" | jq -r .status)
+    [ "$current" == "$2" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+# The stages of a measurement's plan and their states
+plan() {
+  curl -s "$SERVER_URL/status/$1" | jq -r '[.plan[] | "\(.stage):\(.state)"] | join(" ")'
+}
+
+# HTTP status of a retry with the given query
+retry_code() {
+  curl -s -o /dev/null -w '%{http_code}' -X POST "$SERVER_URL/measurements/$1/retry$2"
+}
+
+FULL=$(submit | jq -r .measurement_id)
+check "full plan" "$(wait_status "$FULL" Completed)" "Completed"
+check "stages run" "$(plan "$FULL")" "prove:done submit:done"
+check "stage times" "$(curl -s "$SERVER_URL/status/$FULL" |
+  jq -c '[.plan[] | (.started_at != null and .finished_at != null and .finished_at >= .started_at)]')" \
+  '[true,true]'
+
+SKIPPED=$(submit -F 'skipStages=submit' | jq -r .measurement_id)
+check "without a submission" "$(wait_status "$SKIPPED" Completed)" "Completed"
+check "stage skipped" "$(plan "$SKIPPED")" "prove:done submit:skipped"
+check "nothing submitted" "$(curl -s "$SERVER_URL/status/$SKIPPED?strict=true" |
+  jq -c '[.attestation, .attestation_backend, .pending_items]')" '[null,null,[]]'
+check "proof without attestation" "$(ls "proofs/$SKIPPED" | grep -c '^proof.json$\|^attestation.json$')" "1"
+
+check "skipping prove" "$(submit -F 'skipStages=prove' -o /dev/null -w '%{http_code}')" "400"
+check "skipping an unknown stage" \
+  "$(submit -F 'skipStages=submit,bake' | grep -c "unknown stage 'bake'")" "1"
+
+touch mock_submit_fails
+REFUSED=$(submit | jq -r .measurement_id)
+check "submission refused" "$(wait_status "$REFUSED" Failed)" "Failed"
+rm mock_submit_fails
+check "failed stage" "$(plan "$REFUSED")" "prove:done submit:failed"
+check "stage error" "$(curl -s "$SERVER_URL/status/$REFUSED" | jq -r '.plan[1].error')" \
+  "Verification on zkverify failed"
+PROVED_AT=$(curl -s "$SERVER_URL/status/$REFUSED" | jq -r '.plan[0].finished_at')
+
+check "retry from an unknown stage" "$(retry_code "$REFUSED" "?from=bake")" "400"
+# Proving again would take ten seconds, so a quick completion means it did not
+echo 10 > mock_prove_delay
+check "retry from submit" "$(curl -s -X POST "$SERVER_URL/measurements/$REFUSED/retry?from=submit" |
+  jq -c '[.status, .from]')" '["Pending","submit"]'
+check "proof kept for the retry" "$(ls "proofs/$REFUSED" | grep -c '^proof.json$')" "1"
+check "retried from submit" "$(wait_status "$REFUSED" Completed)" "Completed"
+check "prove not run again" "$(curl -s "$SERVER_URL/status/$REFUSED" |
+  jq -c --arg at "$PROVED_AT" '[.plan[0].finished_at == $at, .plan[1].state, .attestation.attestationId]')" \
+  '[true,"done",1]'
+
+# The only worker is kept busy while the next measurement waits for it
+BUSY=$(submit | jq -r .measurement_id)
+check "measurement being proved" "$(wait_status "$BUSY" Processing)" "Processing"
+check "stage running" "$(plan "$BUSY")" "prove:running submit:pending"
+QUEUED=$(submit | jq -r .measurement_id)
+check "queued plan" "$(plan "$QUEUED")" "prove:pending submit:pending"
+EXPIRED=$(submit -F 'deadline=1' | jq -r .measurement_id)
+check "deadline passed in the queue" "$(wait_status "$EXPIRED" Failed)" "Failed"
+check "retry from submit before prove" "$(curl -s -X POST \
+  "$SERVER_URL/measurements/$EXPIRED/retry?from=submit")" \
+  "Stage submit cannot be retried before stage prove is done"
+check "cancelled while proving" \
+  "$(curl -s -X POST "$SERVER_URL/measurements/$BUSY/cancel" | jq -r .status)" "Cancelled"
+check "stage cancelled" "$(plan "$BUSY")" "prove:cancelled submit:pending"
+curl -s -o /dev/null -X POST "$SERVER_URL/measurements/$QUEUED/cancel"
+rm mock_prove_delay
+
+finish "pipeline plan"
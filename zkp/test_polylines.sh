@@ -0,0 +1,111 @@
+#!/bin/bash
+set -e
+
+# Test measurements along a polyline. Starts its own server with the mock
+# toolchain in test_vectors/mock_toolchain and MAX_POLYLINE_POINTS=5 and
+# checks that:
+#   - points with more than two points is proved with the polyline circuit,
+#     keeping the first and last as the start and end point and the length
+#     as the sum of the segments, also when sent as JSON;
+#   - points with two points makes the same measurement as startPoint and
+#     endPoint, with the same input.json;
+#   - fewer than two points, more than MAX_POLYLINE_POINTS, points together
+#     with startPoint or coordinate fields, and malformed points are rejected
+#     with 400, as is MAX_POLYLINE_POINTS beyond the circuit at startup;
+#   - a proof of another segment length fails the measurement naming the
+#     segment, and a polyline is refused by GET /attestation/{id}.
+
+source "$(dirname "$0")/test_lib.sh"
+
+enter_work_dir
+
+# More points than the circuit takes are refused before the server starts
+STARTUP=$(MAX_POLYLINE_POINTS=18 timeout 10 "$BACKEND" 2>&1 || true)
+check "limit beyond the circuit" \
+  "$(echo "$STARTUP" | grep -c "MAX_POLYLINE_POINTS: 18 is outside 2 to 17")" "1"
+
+start_server MAX_POLYLINE_POINTS=5
+
+POINTS='[{"x": 0.1, "y": 0.2, "z": 0.3}, {"x": 0.4, "y": -0.2, "z": 0.3},
+  {"x": -0.5, "y": -0.2, "z": 0.15}, {"x": -0.5, "y": 0.6, "z": 0.15}]'
+
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" -F "image=@$IMAGE" "$@"
+}
+
+code() {
+  submit -o /dev/null -w '%{http_code}' "$@"
+}
+
+wait_status() {
+  local current
+  for _ in $(seq 1 30); do
+    current=$(curl -s "$SERVER_URL/status/$1" | jq -r .status)
+    [ "$current" == "$2" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+status() {
+  curl -s "$SERVER_URL/status/$1" | jq -c "$2"
+}
+
+check "segments advertised" "$(curl -s "$SERVER_URL/capabilities" | jq -c \
+  '[.features.polylines.enabled, .limits.max_segments, ([.circuits[].version] | index("zkhotdog-polyline-v1") != null)]')" \
+  '[true,4,true]'
+
+POLYLINE=$(submit -F "points=$POINTS" | jq -r .measurement_id)
+check "polyline proved" "$(wait_status "$POLYLINE" Completed)" "Completed"
+check "polyline circuit" "$(status "$POLYLINE" .circuit)" '"zkhotdog-polyline-v1"'
+check "start and end point" "$(status "$POLYLINE" '[.start_point, .end_point]')" \
+  '[{"x":10000,"y":20000,"z":30000},{"x":-50000,"y":60000,"z":15000}]'
+check "points kept" "$(status "$POLYLINE" '[(.points | length), .original_points.points[1]]')" \
+  '[4,{"x":0.4,"y":-0.2,"z":0.3}]'
+# 0.5 m + sqrt(0.8325) m + 0.8 m
+check "length of all segments" "$(status "$POLYLINE" .length_cm)" "221.241"
+check "segments proved" "$(jq -c '.[:4]' "proofs/$POLYLINE/public.json")" \
+  '["3","2500000000","8325000000","6400000000"]'
+
+BODY=$(jq -n --arg image "$(base64 -w0 "$IMAGE")" --argjson points "$POINTS" \
+  '{image: $image, points: $points}')
+AS_JSON=$(curl -s -X POST "$SERVER_URL/measurements/json" -H 'Content-Type: application/json' \
+  -d "$BODY" | jq -r .measurement_id)
+check "polyline as JSON" "$(status "$AS_JSON" '[.points, .length_cm]')" \
+  "$(status "$POLYLINE" '[.points, .length_cm]')"
+
+TWO=$(submit -F 'points=[{"x": 0.1, "y": 0.2, "z": 0.3}, {"x": 1.5, "y": 2.5, "z": 3.5}]' |
+  jq -r .measurement_id)
+ENDS=$(submit -F 'startPoint={"x": 0.1, "y": 0.2, "z": 0.3}' \
+  -F 'endPoint={"x": 1.5, "y": 2.5, "z": 3.5}' | jq -r .measurement_id)
+check "two points proved" "$(wait_status "$TWO" Completed)" "Completed"
+check "two points as a segment" "$(status "$TWO" '[.circuit, .points, .original_points.points]')" \
+  '[null,null,null]'
+check "same as start and end" "$(status "$TWO" '[.start_point, .end_point, .length_cm]')" \
+  "$(status "$ENDS" '[.start_point, .end_point, .length_cm]')"
+wait_status "$ENDS" Completed > /dev/null
+check "same input.json" "$(cmp -s "proofs/$TWO/input.json" "proofs/$ENDS/input.json" && echo same)" \
+  "same"
+
+check "one point" "$(code -F 'points=[{"x": 0.1, "y": 0.2, "z": 0.3}]')" "400"
+SIX=$(jq -c '. + .[:2]' <<< "$POINTS")
+check "more than the limit" "$(submit -F "points=$SIX" | grep -c "points has 6 points, a measurement needs 2 to 5")" "1"
+check "with startPoint" "$(submit -F "points=$POINTS" -F 'startPoint={"x": 0.1, "y": 0.2, "z": 0.3}' |
+  grep -c "Send either points or a start and end point")" "1"
+check "with coordinate fields" \
+  "$(code -F "points=$POINTS" -F endX=0.4 -F endY=0.2 -F endZ=-0.3)" "400"
+check "malformed points" "$(code -F 'points={"x": 0.1}')" "400"
+check "point out of range" "$(submit -F 'points=[{"x": 0, "y": 0, "z": 0}, {"x": 1e6, "y": 0, "z": 0}, {"x": 0, "y": 0, "z": 0}]' |
+  grep -c "Invalid point 1 of points")" "1"
+
+# The second segment proved with another length
+jq -c '.[2] = "1"' "proofs/$POLYLINE/public.json" > mock_public_signals
+WRONG=$(submit -F "points=$POINTS" | jq -r .measurement_id)
+check "wrong segment" "$(wait_status "$WRONG" Failed)" "Failed"
+rm mock_public_signals
+check "segment named" "$(status "$WRONG" .failure.message |
+  grep -c 'segment_distances_squared\[1\] 1 does not match expected 8325000000')" "1"
+
+check "not mintable" "$(curl -s "$SERVER_URL/attestation/$POLYLINE" | jq -r .error)" "not_mintable"
+
+finish "polyline"
@@ -1,10 +1,7 @@
 #!/bin/bash
 set -e
 
-# Colors for output
-GREEN='\033[0;32m'
-RED='\033[0;31m'
-NC='\033[0m' # No Color
+source "$(dirname "$0")/test_lib.sh"
 
 # Create test directory if it doesn't exist
 mkdir -p test_data
@@ -0,0 +1,99 @@
+#!/bin/bash
+set -e
+
+# Test the retention sweep. Starts its own server with the mock toolchain in
+# test_vectors/mock_toolchain, a retention of 7 days and of 30 days for
+# attested measurements, a sweep every second and a single proof worker, and
+# steps its wall clock through CLOCK_STEP_PATH. Checks that:
+#   - nothing is removed before its retention has passed;
+#   - 8 days on, a measurement that failed without an attestation is removed
+#     with its image and proof directory, while an attested one is kept;
+#   - 31 days on, the attested measurement is removed too;
+#   - a measurement being proved is kept throughout;
+#   - every removal is logged and audited.
+#
+# The server must be built with the testing feature (cargo build --features
+# testing).
+
+source "$(dirname "$0")/test_lib.sh"
+
+DAY=86400
+
+enter_work_dir
+echo 0 > clock_step
+
+SERVER_ENV=(CLOCK_STEP_PATH=clock_step PROOF_WORKERS=1 RETENTION_DAYS=7 RETENTION_ATTESTED_DAYS=30
+  RETENTION_SWEEP_INTERVAL_SECS=1)
+
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' | jq -r .measurement_id
+}
+
+# Status of a measurement, or deleted once it is gone
+status() {
+  if [ "$(curl -s -o status.json -w '%{http_code}' "$SERVER_URL/status/$1")" == "404" ]; then
+    echo deleted
+  else
+    jq -r .status status.json
+  fi
+}
+
+# Wait until a measurement reaches the given status and print the last one seen
+wait_status() {
+  local current
+  for _ in $(seq 1 30); do
+    current=$(status "$1")
+    [ "$current" == "$2" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+files() {
+  local found=""
+  [ -e "uploads/$1.jpg" ] && found="image"
+  [ -e "proofs/$1" ] && found="$found proofs"
+  echo "${found:-none}"
+}
+
+start_server
+ATTESTED=$(submit)
+check "attested measurement" "$(wait_status "$ATTESTED" Completed)" "Completed"
+check "attestation" "$(curl -s "$SERVER_URL/measurements" | jq -r --arg id "$ATTESTED" \
+  '.[] | select(.id == $id) | .attestation != null')" "true"
+
+# A measurement interrupted by a restart fails without an attestation
+echo 60 > mock_prove_delay
+UNATTESTED=$(submit)
+check "interrupted measurement" "$(wait_status "$UNATTESTED" Processing)" "Processing"
+kill $SERVER_PID
+wait $SERVER_PID 2>/dev/null || true
+start_server
+check "unattested measurement" "$(status "$UNATTESTED")" "Failed"
+PROVING=$(submit)
+check "measurement being proved" "$(wait_status "$PROVING" Processing)" "Processing"
+
+sleep 2
+check "kept within retention" "$(status "$UNATTESTED") $(status "$ATTESTED")" "Failed Completed"
+
+echo $((8 * DAY)) > clock_step
+check "unattested after 8 days" "$(wait_status "$UNATTESTED" deleted)" "deleted"
+check "unattested files" "$(files "$UNATTESTED")" "none"
+check "attested after 8 days" "$(status "$ATTESTED")" "Completed"
+check "attested files" "$(files "$ATTESTED")" "image proofs"
+
+echo $((31 * DAY)) > clock_step
+check "attested after 31 days" "$(wait_status "$ATTESTED" deleted)" "deleted"
+check "attested files after 31 days" "$(files "$ATTESTED")" "none"
+check "being proved after 31 days" "$(status "$PROVING")" "Processing"
+
+check "removals logged" \
+  "$(grep -c -e "Retention removed measurement $UNATTESTED" -e "Retention removed measurement $ATTESTED" server.log)" "2"
+check "removals audited" \
+  "$(jq -r 'select(.action == "measurements_expired") | .details.measurements[].id' audit.log | tr '\n' ' ')" \
+  "$UNATTESTED $ATTESTED "
+
+finish "retention"
@@ -0,0 +1,90 @@
+#!/bin/bash
+set -e
+
+# Test retries of failed measurements. Starts its own server with the mock
+# toolchain in test_vectors/mock_toolchain and a single proof worker and
+# checks that:
+#   - a measurement failed by its deadline completes once retried, and the
+#     retry answers its new status and shows in its history;
+#   - one whose zkVerify submission was refused completes once retried, with
+#     the proof of the failed attempt removed and input.json kept;
+#   - retrying a measurement that has not failed is answered with 409, one of
+#     another client with 403 and an unknown one with 404.
+
+source "$(dirname "$0")/test_lib.sh"
+
+enter_work_dir
+
+start_server PROOF_WORKERS=1
+
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' "$@" | jq -r .measurement_id
+}
+
+wait_status() {
+  local current
+  for _ in $(seq 1 30); do
+    current=$(curl -s "$SERVER_URL/status/$1" | jq -r .status)
+    [ "$current" == "$2" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+# HTTP status of a retry
+retry_code() {
+  curl -s -o /dev/null -w '%{http_code}' -X POST "$SERVER_URL/measurements/$1/retry"
+}
+
+# Statuses and sources of a measurement's transitions
+transitions() {
+  curl -s "$SERVER_URL/measurements/$1/history" |
+    jq -r '[.transitions[] | "\(.to)@\(.source)"] | join(" ")'
+}
+
+# The only worker is kept busy while the next measurement waits for it
+echo 5 > mock_prove_delay
+BUSY=$(submit)
+check "measurement being proved" "$(wait_status "$BUSY" Processing)" "Processing"
+EXPIRED=$(submit -F 'deadline=1')
+check "deadline passed in the queue" "$(wait_status "$EXPIRED" Failed)" "Failed"
+check "retrying while processing" "$(retry_code "$BUSY")" "409"
+rm mock_prove_delay
+
+check "retry response" "$(curl -s -X POST "$SERVER_URL/measurements/$EXPIRED/retry" |
+  jq -c '[.measurement_id == "'"$EXPIRED"'", .status, (.url | endswith("/status/'"$EXPIRED"'"))]')" \
+  '[true,"Pending",true]'
+check "retried after its deadline" "$(wait_status "$EXPIRED" Completed)" "Completed"
+check "deadline dropped" "$(curl -s "$SERVER_URL/status/$EXPIRED" | jq -c '[.deadline, .failure]')" \
+  '[null,null]'
+check "retry in the history" "$(transitions "$EXPIRED")" \
+  "Pending@submission Failed@watchdog Pending@retry Processing@worker-1 Completed@worker-1"
+check "retrying a completed measurement" "$(retry_code "$EXPIRED")" "409"
+
+touch mock_submit_fails
+REFUSED=$(submit)
+check "submission refused" "$(wait_status "$REFUSED" Failed)" "Failed"
+check "failure class" "$(curl -s "$SERVER_URL/status/$REFUSED" | jq -r .failure.class)" \
+  "Verification"
+check "proof of the failed attempt" "$(ls "proofs/$REFUSED" | grep -c '^proof.json$')" "1"
+rm mock_submit_fails
+
+check "another client" "$(curl -s -o /dev/null -w '%{http_code}' -X POST --interface 127.0.0.2 \
+  "http://127.0.0.1:3001/measurements/$REFUSED/retry")" "403"
+
+# Slow the retry down to look at the proof directory before it is proved again
+echo 3 > mock_prove_delay
+check "retrying a failed submission" "$(retry_code "$REFUSED")" "200"
+check "files kept for the retry" "$(ls "proofs/$REFUSED" | tr '\n' ' ')" "input.json "
+check "retrying twice" "$(retry_code "$REFUSED")" "409"
+check "retried after a refused submission" "$(wait_status "$REFUSED" Completed)" "Completed"
+check "attestation of the retry" \
+  "$(curl -s "$SERVER_URL/status/$REFUSED" | jq -c '[.attestation.attestationId, .failure]')" '[1,null]'
+rm mock_prove_delay
+
+check "unknown measurement" "$(retry_code 00000000-0000-0000-0000-000000000000)" "404"
+
+finish "retry"
@@ -0,0 +1,46 @@
+#!/bin/bash
+set -e
+
+# Compatibility test for the published JSON Schemas. Every schema listed by
+# /schemas is fetched in its current version and compared with
+# test_vectors/schemas/<name>/<version>.json.
+#
+# The schemas are derived from the types producing the payloads, so a
+# mismatch means a payload changed without its schema version being bumped,
+# which would silently break consumers. When the change is intended, bump the
+# version of the schema in the code, then record the new version with
+#   UPDATE_GOLDEN=1 ./test_schemas.sh
+# and commit it next to the old ones. Fixtures of earlier versions are kept.
+
+source "$(dirname "$0")/test_lib.sh"
+
+FIXTURES_DIR="test_vectors/schemas"
+
+INDEX=$(curl -s "$SERVER_URL/schemas")
+for NAME in $(echo "$INDEX" | jq -r 'keys[]'); do
+  VERSION=$(echo "$INDEX" | jq -r --arg name "$NAME" '.[$name].version')
+  EXPECTED="$FIXTURES_DIR/$NAME/$VERSION.json"
+  ACTUAL=$(curl -s "$SERVER_URL/schemas/$NAME/$VERSION.json" | jq -S .)
+
+  if [ -n "$UPDATE_GOLDEN" ]; then
+    if [ -f "$EXPECTED" ] && [ "$ACTUAL" != "$(cat "$EXPECTED")" ]; then
+      echo -e "${RED}$NAME: version $VERSION is already published with another schema; bump its version${NC}"
+      FAILED=1
+      continue
+    fi
+    mkdir -p "$FIXTURES_DIR/$NAME"
+    echo "$ACTUAL" > "$EXPECTED"
+    echo -e "${GREEN}$NAME: version $VERSION recorded${NC}"
+  elif [ ! -f "$EXPECTED" ]; then
+    echo -e "${RED}$NAME: version $VERSION has no fixture, record it with UPDATE_GOLDEN=1${NC}"
+    FAILED=1
+  elif [ "$ACTUAL" == "$(cat "$EXPECTED")" ]; then
+    echo -e "${GREEN}$NAME: ok${NC}"
+  else
+    echo -e "${RED}$NAME: schema changed without a new version (still $VERSION)${NC}"
+    diff "$EXPECTED" <(echo "$ACTUAL") || true
+    FAILED=1
+  fi
+done
+
+finish "schema compatibility"
@@ -0,0 +1,97 @@
+#!/bin/bash
+set -e
+
+# Test measurements with a second image. Starts its own server with the mock
+# toolchain in test_vectors/mock_toolchain and checks that:
+#   - a measurement sent with image2, as a form field or base64 in a JSON
+#     body, stores it as uploads/{id}_2.jpg, reports it in its artifacts and
+#     serves it from /img/{id}/2 and its public view;
+#   - /img/{id}/2 answers 404 for a measurement sent with one image and for
+#     unknown ids, and an invalid base64 image2 is rejected with 400;
+#   - a submission refused for another field leaves neither image behind, a
+#     reproof carries the second image over and deleting a measurement
+#     removes both.
+#
+# Also needs base64 and sha256sum.
+
+source "$(dirname "$0")/test_lib.sh"
+
+enter_work_dir
+mkdir -p uploads keys
+# A close-up that differs from the overview
+cp "$IMAGE" close-up.jpg
+echo "close-up" >> close-up.jpg
+CLOSE_UP_SHA256=$(sha256sum < close-up.jpg | cut -d' ' -f1)
+
+start_server
+
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' "$@"
+}
+
+wait_status() {
+  local current
+  for _ in $(seq 1 30); do
+    current=$(curl -s "$SERVER_URL/status/$1" | jq -r .status)
+    [ "$current" == "$2" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+code() {
+  curl -s -o /dev/null -w '%{http_code}' "$@"
+}
+
+second_sha256() {
+  curl -s "$SERVER_URL/img/$1/2" | sha256sum | cut -d' ' -f1
+}
+
+check "second images advertised" "$(curl -s "$SERVER_URL/capabilities" | jq -c \
+  '[.features.second_images.enabled, .features.second_images.endpoints]')" '[true,["GET /img/{id}/2"]]'
+
+TWO=$(submit -F "image2=@close-up.jpg" | jq -r .measurement_id)
+check "two images proved" "$(wait_status "$TWO" Completed)" "Completed"
+check "second image stored" "$(sha256sum < "uploads/${TWO}_2.jpg" | cut -d' ' -f1)" "$CLOSE_UP_SHA256"
+check "second image reported" "$(curl -s "$SERVER_URL/status/$TWO" | jq -c \
+  "[.artifacts.image.bytes, .artifacts.image2.available, .artifacts.image2.bytes]")" \
+  "[$(wc -c < "$IMAGE"),true,$(wc -c < close-up.jpg)]"
+check "second image served" "$(second_sha256 "$TWO")" "$CLOSE_UP_SHA256"
+check "first image unchanged" "$(curl -s "$SERVER_URL/img/$TWO" | sha256sum | cut -d' ' -f1)" \
+  "$(sha256sum < "$IMAGE" | cut -d' ' -f1)"
+check "second image download" "$(curl -s -D - -o /dev/null "$SERVER_URL/img/$TWO/2?download=true" |
+  grep -ci 'content-disposition: attachment.*-2\.jpg')" "1"
+check "view shows both" "$(curl -s "$SERVER_URL/view/$TWO" | grep -c "/img/$TWO/2")" "1"
+
+ONE=$(submit | jq -r .measurement_id)
+check "one image: not reported" "$(curl -s "$SERVER_URL/status/$ONE" | jq '.artifacts | has("image2")')" "false"
+check "one image: 404" "$(curl -s -w ' %{http_code}' "$SERVER_URL/img/$ONE/2" |
+  grep -c 'has no second image 404')" "1"
+check "unknown id" "$(code "$SERVER_URL/img/00000000-0000-0000-0000-000000000000/2")" "404"
+check "view shows one" "$(curl -s "$SERVER_URL/view/$ONE" | grep -c "/img/$ONE/2" || true)" "0"
+
+BODY=$(jq -n --arg image "$(base64 -w0 "$IMAGE")" --arg close "$(base64 -w0 close-up.jpg)" \
+  '{image: $image, image2: $close, startPoint: {x: 0.1, y: 0.2, z: -0.3}, endPoint: {x: 0.4, y: 0.2, z: -0.3}}')
+AS_JSON=$(curl -s -X POST "$SERVER_URL/measurements/json" -H 'Content-Type: application/json' \
+  -d "$BODY" | jq -r .measurement_id)
+check "second image as JSON" "$(second_sha256 "$AS_JSON")" "$CLOSE_UP_SHA256"
+check "invalid base64" "$(curl -s -X POST "$SERVER_URL/measurements/json" -H 'Content-Type: application/json' \
+  -d "$(echo "$BODY" | jq '.image2 = "not base64!"')" | grep -c 'Invalid base64 image2')" "1"
+
+BEFORE=$(ls uploads | wc -l)
+check "refused for another field" "$(code -X POST "$SERVER_URL/measurements" -F "image=@$IMAGE" \
+  -F "image2=@close-up.jpg" -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}')" "400"
+check "nothing left behind" "$(ls uploads | wc -l)" "$BEFORE"
+
+# A proving key other than the one the measurement was proved with allows a reproof
+echo "another key" > keys/zkHotdog_final.zkey
+REPROOF=$(curl -s -X POST "$SERVER_URL/measurements/$TWO/reprove" | jq -r .measurement_id)
+check "reproof keeps the second image" "$(second_sha256 "$REPROOF")" "$CLOSE_UP_SHA256"
+
+check "deleted" "$(code -X DELETE "$SERVER_URL/measurements/$TWO")" "200"
+check "both images removed" "$(ls uploads | grep -c "^$TWO" || true)" "0"
+check "second image gone" "$(code "$SERVER_URL/img/$TWO/2")" "404"
+
+finish "second image"
@@ -0,0 +1,73 @@
+#!/bin/bash
+set -e
+
+# Shadow proving test. Starts its own server with the mock toolchain in
+# test_vectors/mock_toolchain, sampling every proof for its mock shadow
+# prover, and checks that:
+#   - agreeing shadow proofs are reported as matches with their timings, and
+#     written to proofs/{id}/shadow/comparison.json;
+#   - a shadow prover proving other public signals is flagged as a mismatch
+#     in /admin/shadow, while the measurement still completes as usual;
+#   - /admin/stats sums the comparisons up.
+
+source "$(dirname "$0")/test_lib.sh"
+
+enter_work_dir
+# Proofs are verified against the key the mock proof verifies with
+mkdir keys
+cp "$MOCK_TOOLCHAIN/verification_key.json" keys/
+
+SERVER_ENV=(SHADOW_PROVING=true SHADOW_SAMPLE_PERCENT=100
+  SHADOW_PROVER="$MOCK_TOOLCHAIN/shadow_prover")
+
+# Submit a measurement, wait for it to complete and print its ID
+prove() {
+  local id status=""
+  id=$(curl -s -X POST "$SERVER_URL/measurements" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' | jq -r '.measurement_id // empty')
+  for _ in $(seq 1 60); do
+    status=$(curl -s "$SERVER_URL/status/$id" | jq -r '.status // empty')
+    [ "$status" == "Completed" ] || [ "$status" == "Failed" ] && break
+    sleep 0.5
+  done
+  echo "$id"
+}
+
+# Wait until no shadow proof is pending and print the comparison of a measurement
+comparison() {
+  for _ in $(seq 1 30); do
+    [ -f "proofs/$1/shadow/comparison.json" ] && break
+    sleep 0.5
+  done
+  curl -s "$SERVER_URL/admin/shadow" | jq -c --arg id "$1" '.comparisons[] | select(.id == $id)'
+}
+
+# Agreeing provers
+start_server
+for i in 1 2; do
+  ID=$(prove)
+  RESULT=$(comparison "$ID")
+  check "proof $i outcome" "$(echo "$RESULT" | jq -r .outcome)" "match"
+  check "proof $i shadow verified" "$(echo "$RESULT" | jq -r .shadow_verified)" "true"
+  check "proof $i timed" "$(echo "$RESULT" | jq '.shadow_ms != null and .delta_ms != null')" "true"
+done
+check "comparison file" "$(jq -r .outcome "proofs/$ID/shadow/comparison.json")" "match"
+
+# A shadow prover proving another distance
+start_server MOCK_SHADOW_OFFSET=1
+ID=$(prove)
+check "measurement unaffected" "$(curl -s "$SERVER_URL/status/$ID" | jq -r .status)" "Completed"
+RESULT=$(comparison "$ID")
+check "mismatch outcome" "$(echo "$RESULT" | jq -r .outcome)" "mismatch"
+check "mismatch public signals" "$(echo "$RESULT" | jq -r .public_signals_equal)" "false"
+check "mismatch verified" "$(echo "$RESULT" | jq -r .shadow_verified)" "true"
+check "mismatches listed" \
+  "$(curl -s "$SERVER_URL/admin/shadow?mismatches=true" | jq -r '[.comparisons[].id] | join(",")')" "$ID"
+
+STATS=$(curl -s "$SERVER_URL/admin/stats" | jq .shadow)
+check "sampled" "$(echo "$STATS" | jq -r .sampled)" "1"
+check "mismatched" "$(echo "$STATS" | jq -r .mismatched)" "1"
+
+finish "shadow proving"
@@ -0,0 +1,98 @@
+#!/bin/bash
+set -e
+
+# Test what an instance sharing a Postgres store sees of the writes of other
+# instances. Starts its own server with the mock toolchain in
+# test_vectors/mock_toolchain on the postgres store at DATABASE_URL, stands in
+# for another instance by writing to the measurements table with psql, and
+# checks that:
+#   - a status read finds a measurement another instance stored at once;
+#   - listings and /admin/stats show it, its changes and its deletion once
+#     STORE_SYNC_INTERVAL_MS passed;
+#   - with STORE_SYNC_INTERVAL_MS=0, listings keep showing what the instance
+#     loaded and wrote itself, and the measurements whose status it read.
+#
+# Also needs psql, a server built with --features postgres (set BACKEND to
+# it) and DATABASE_URL naming a scratch database, whose measurements tables
+# are dropped; skipped without DATABASE_URL.
+
+source "$(dirname "$0")/test_lib.sh"
+
+if [ -z "$DATABASE_URL" ]; then
+  echo "DATABASE_URL is not set; skipping the shared store checks"
+  exit 0
+fi
+
+ADMIN_KEY="zkh_test_admin_key"
+SYNC_MS=500
+
+enter_work_dir
+
+SERVER_ENV=(MEASUREMENT_STORE=postgres ADMIN_API_KEY="$ADMIN_KEY")
+
+sql() {
+  psql "$DATABASE_URL" -qtAX -v ON_ERROR_STOP=1 -c "$1"
+}
+
+# Store a copy of measurement $1 under the id $2, as another instance would
+copy() {
+  sql "INSERT INTO measurements (id, status, record)
+       SELECT '$2', status, jsonb_set(record, '{id}', to_jsonb('$2'::text))
+       FROM measurements WHERE id = '$1'"
+}
+
+listed() {
+  curl -s "$SERVER_URL/measurements" | jq -r "[.[] | select(.id == \"$1\")] | length"
+}
+
+after_sync() {
+  sleep "$(awk "BEGIN { print 3 * $SYNC_MS / 1000 }")"
+}
+
+sql "DROP TABLE IF EXISTS measurements, measurement_removals"
+sql "DROP SEQUENCE IF EXISTS measurement_versions"
+start_server STORE_SYNC_INTERVAL_MS=$SYNC_MS
+
+ID=$(curl -s -X POST "$SERVER_URL/measurements" -F "image=@$IMAGE" \
+  -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+  -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' | jq -r .measurement_id)
+for _ in $(seq 1 30); do
+  [ "$(curl -s "$SERVER_URL/status/$ID" | jq -r .status)" == "Completed" ] && break
+  sleep 1
+done
+check "own measurement listed" "$(listed "$ID")" "1"
+
+OTHER=$(cat /proc/sys/kernel/random/uuid)
+copy "$ID" "$OTHER"
+check "status of another instance's measurement" \
+  "$(curl -s "$SERVER_URL/status/$OTHER" | jq -r .status)" "Completed"
+after_sync
+check "listed once synced" "$(listed "$OTHER")" "1"
+check "counted once synced" \
+  "$(curl -s -H "X-Api-Key: $ADMIN_KEY" "$SERVER_URL/admin/stats" | jq .measurements.Completed)" "2"
+
+sql "UPDATE measurements SET record = jsonb_set(record, '{metadata}', '{\"label\": \"elsewhere\"}'),
+       version = nextval('measurement_versions')
+     WHERE id = '$OTHER'"
+after_sync
+check "change listed once synced" \
+  "$(curl -s "$SERVER_URL/measurements" | jq -r ".[] | select(.id == \"$OTHER\") | .metadata.label")" \
+  "elsewhere"
+
+sql "DELETE FROM measurements WHERE id = '$OTHER';
+     INSERT INTO measurement_removals (id) VALUES ('$OTHER')"
+after_sync
+check "deletion listed once synced" "$(listed "$OTHER")" "0"
+
+# Without syncing only what was there at startup is listed
+start_server STORE_SYNC_INTERVAL_MS=0
+OTHER=$(cat /proc/sys/kernel/random/uuid)
+copy "$ID" "$OTHER"
+after_sync
+check "not listed without syncing" "$(listed "$OTHER")" "0"
+check "own measurement still listed" "$(listed "$ID")" "1"
+check "status without syncing" "$(curl -s "$SERVER_URL/status/$OTHER" | jq -r .status)" "Completed"
+check "listed once its status was read" "$(listed "$OTHER")" "1"
+
+sql "DELETE FROM measurements"
+finish "shared store"
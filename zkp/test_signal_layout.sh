@@ -0,0 +1,68 @@
+#!/bin/bash
+set -e
+
+# Test the public-signal layout declared by the circuit spec. Starts its own
+# server with the mock toolchain in test_vectors/mock_toolchain and checks
+# that:
+#   - /capabilities describes the input and public signals of zkhotdog-v1
+#     with their widths and encodings;
+#   - a proof whose public.json matches the spec completes, and the contract
+#     attestation carries the decoded distance;
+#   - a public.json with more signals than the spec declares, or a value
+#     wider than its signal, fails the measurement.
+
+source "$(dirname "$0")/test_lib.sh"
+
+enter_work_dir
+
+start_server
+
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' | jq -r .measurement_id
+}
+
+# Wait for a measurement to finish and print its status
+wait_finished() {
+  local current
+  for _ in $(seq 1 30); do
+    current=$(curl -s "$SERVER_URL/status/$1" | jq -r .status)
+    [ "$current" == "Completed" ] || [ "$current" == "Failed" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+failure() {
+  curl -s "$SERVER_URL/status/$1" | jq -r .failure.message
+}
+
+CIRCUIT=$(curl -s "$SERVER_URL/capabilities" | jq '.circuits[] | select(.version == "zkhotdog-v1")')
+check "input signals" "$(echo "$CIRCUIT" | jq -c '[.inputs[] | [.signal, .length, .bits, .encoding]]')" \
+  '[["point1",3,30,"signed"],["point2",3,30,"signed"],["distance_squared",null,62,"unsigned"]]'
+check "public signals" "$(echo "$CIRCUIT" | jq -c '[.public_signals[] | [.signal, .bits, .encoding]]')" \
+  '[["distance_squared",62,"unsigned"]]'
+
+# As the spec declares
+ID=$(submit)
+check "matching public.json" "$(wait_finished "$ID")" "Completed"
+check "decoded distance" "$(curl -s "$SERVER_URL/attestation/$ID" | jq -r .mint_with_attestation.lengthInCm)" \
+  "$(jq -r '.[0]' "proofs/$ID/public.json")"
+
+# One signal too many
+DISTANCE=$(jq -r .distance_squared "proofs/$ID/input.json")
+echo "[\"$DISTANCE\", \"1\"]" > mock_public_signals
+ID=$(submit)
+check "extra public signal" "$(wait_finished "$ID")" "Failed"
+check "extra public signal failure" "$(failure "$ID" | grep -c "2 public signals where the circuit declares 1")" "1"
+
+# A distance wider than its signal
+echo "[\"$((1 << 62))\"]" > mock_public_signals
+ID=$(submit)
+check "wide public signal" "$(wait_finished "$ID")" "Failed"
+check "wide public signal failure" "$(failure "$ID" | grep -c "distance_squared is $((1 << 62)), outside")" "1"
+rm mock_public_signals
+
+finish "signal layout"
@@ -0,0 +1,107 @@
+#!/bin/bash
+set -e
+
+# Benchmark of the server's startup time with many measurements. Writes the
+# files of STARTUP_RECORDS synthetic measurements that were proved and are
+# awaiting their attestation, plus a few with only their inputs, into a fresh
+# directory, starts its own server with the mock toolchain there and checks
+# that:
+#   - it is ready within STARTUP_BUDGET_SECS although none of them is stored,
+#     as the consistency scan rebuilding them runs after startup;
+#   - the scan rebuilds every one of them and reports its job;
+#   - the measurements with only their inputs are proved;
+#   - after a restart with all of them stored, it is ready within the budget
+#     again, with only the unfinished ones recovered, and the scan rebuilds
+#     nothing.
+# The phase timings of GET /version are printed for both starts.
+
+source "$(dirname "$0")/test_lib.sh"
+
+INPUT="$ZKP_DIR/test_vectors/inputs/coordinate_arkit_world.input.json"
+RECORDS="${STARTUP_RECORDS:-20000}"
+BUDGET_SECS="${STARTUP_BUDGET_SECS:-10}"
+UNFINISHED=5
+
+enter_work_dir
+mkdir -p proofs
+
+# Start the server and set ELAPSED to the milliseconds until /ready answered 200
+start_server() {
+  local began now
+  began=$(date +%s%3N)
+  PATH="$MOCK_TOOLCHAIN:$PATH" "$BACKEND" >> server.log 2>&1 &
+  SERVER_PID=$!
+  for _ in $(seq 1 $((BUDGET_SECS * 20 + 100))); do
+    [ "$(curl -s -o /dev/null -w '%{http_code}' "$SERVER_URL/ready")" == "200" ] && break
+    sleep 0.1
+  done
+  now=$(date +%s%3N)
+  ELAPSED=$((now - began))
+}
+
+# Wait for the consistency scan of the running server and print its state
+wait_scan() {
+  local state
+  for _ in $(seq 1 300); do
+    state=$(curl -s "$SERVER_URL/version" | jq -r .startup.consistency_scan.state)
+    [ "$state" == "completed" ] && break
+    sleep 1
+  done
+  echo "$state"
+}
+
+within_budget() {
+  [ "$1" -le $((BUDGET_SECS * 1000)) ] && echo yes || echo "no ($1 ms)"
+}
+
+completed_count() {
+  curl -s "$SERVER_URL/measurements" | jq '[.[] | select(.status == "Completed")] | length'
+}
+
+# Synthetic measurements, written with shell builtins so that tens of
+# thousands take seconds
+echo "Writing the files of $RECORDS measurements"
+INPUT_JSON=$(cat "$INPUT")
+ids=()
+for i in $(seq 1 $((RECORDS + UNFINISHED))); do
+  ids+=("proofs/$(printf '00000000-0000-4000-8000-%012d' "$i")")
+done
+mkdir "${ids[@]}"
+for i in "${!ids[@]}"; do
+  dir="${ids[$i]}"
+  echo "$INPUT_JSON" > "$dir/input.json"
+  # The last few only have their inputs, so they are proved again
+  if [ "$i" -lt "$RECORDS" ]; then
+    echo '{}' > "$dir/proof.json"
+    echo '[]' > "$dir/public.json"
+  fi
+done
+TOTAL=$((RECORDS + UNFINISHED))
+
+# Nothing stored: the files are only read after startup
+start_server
+check "ready within ${BUDGET_SECS}s without a store" "$(within_budget "$ELAPSED")" "yes"
+curl -s "$SERVER_URL/version" | jq -c .startup.phases
+check "consistency scan" "$(wait_scan)" "completed"
+SCAN=$(curl -s "$SERVER_URL/version" | jq .startup.consistency_scan)
+echo "Consistency scan took $(echo "$SCAN" | jq .millis) ms"
+check "scan rebuilt" "$(echo "$SCAN" | jq .rebuilt)" "$TOTAL"
+JOB_ID=$(echo "$SCAN" | jq -r .job_id)
+check "scan job" "$(curl -s "$SERVER_URL/admin/jobs/$JOB_ID" | jq -c '[.kind, .report.completed, .report.awaiting_attestation, .report.pending]')" \
+  "[\"consistency_scan\",0,$RECORDS,$UNFINISHED]"
+for _ in $(seq 1 60); do
+  [ "$(completed_count)" == "$TOTAL" ] && break
+  sleep 1
+done
+check "unfinished proved" "$(completed_count)" "$TOTAL"
+stop_server
+
+# Everything stored and finished: only the store is read before serving
+start_server
+check "ready within ${BUDGET_SECS}s with $TOTAL stored" "$(within_budget "$ELAPSED")" "yes"
+curl -s "$SERVER_URL/version" | jq -c .startup.phases
+check "stored measurements" "$(grep -c "Loaded $TOTAL measurements from the store" server.log)" "1"
+check "second scan" "$(wait_scan)" "completed"
+check "second scan rebuilt" "$(curl -s "$SERVER_URL/version" | jq .startup.consistency_scan.rebuilt)" "0"
+
+finish "startup"
@@ -0,0 +1,74 @@
+#!/bin/bash
+set -e
+
+# Test the batch status lookup. Starts its own server with the mock toolchain
+# in test_vectors/mock_toolchain and checks that:
+#   - POST /status/batch answers each known id with its status, unknown ids
+#     with a not_found marker and repeated ids once;
+#   - ?strict=true adds pending_items like /status/{id};
+#   - more than 100 ids are refused;
+#   - it is served during maintenance, while submissions are refused.
+
+source "$(dirname "$0")/test_lib.sh"
+
+ADMIN_KEY="batch-test-admin-key"
+UNKNOWN="00000000-0000-0000-0000-000000000000"
+
+enter_work_dir
+start_server ADMIN_API_KEY="$ADMIN_KEY"
+
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' | jq -r .measurement_id
+}
+
+wait_status() {
+  local current
+  for _ in $(seq 1 30); do
+    current=$(curl -s "$SERVER_URL/status/$1" | jq -r .status)
+    [ "$current" == "$2" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+# POST a JSON body to /status/batch, writing the response to response.json
+# and printing the status code
+batch() {
+  curl -s -o response.json -w '%{http_code}' -X POST "$SERVER_URL/status/batch$2" \
+    -H 'Content-Type: application/json' -d "$1"
+}
+
+FIRST=$(submit)
+SECOND=$(submit)
+check "first completed" "$(wait_status "$FIRST" Completed)" "Completed"
+check "second completed" "$(wait_status "$SECOND" Completed)" "Completed"
+
+check "batch" "$(batch "[\"$FIRST\", \"$SECOND\", \"$UNKNOWN\", \"$FIRST\"]")" "200"
+check "entries" "$(jq 'length' response.json)" "3"
+check "statuses" "$(jq -r "[.\"$FIRST\".status, .\"$SECOND\".status] | join(\",\")" response.json)" \
+  "Completed,Completed"
+[ "$(jq -cS ".\"$FIRST\"" response.json)" == "$(curl -s "$SERVER_URL/status/$FIRST" | jq -cS .)" ] &&
+  SAME=same
+check "same as /status" "${SAME:-different}" "same"
+check "unknown id" "$(jq -c ".\"$UNKNOWN\"" response.json)" '{"error":"not_found"}'
+check "without strict" "$(jq ".\"$FIRST\" | has(\"pending_items\")" response.json)" "false"
+
+check "strict batch" "$(batch "[\"$FIRST\"]" '?strict=true')" "200"
+check "pending items" "$(jq -c ".\"$FIRST\".pending_items" response.json)" "[]"
+
+check "empty batch" "$(batch '[]') $(cat response.json)" "200 {}"
+TOO_MANY=$(jq -nc "[range(101) | \"$UNKNOWN\"]")
+check "more than 100 ids" "$(batch "$TOO_MANY")" "400"
+check "not an array" "$(batch '{"ids": []}')" "422"
+
+curl -s -X PUT "$SERVER_URL/admin/maintenance" -H "Authorization: Bearer $ADMIN_KEY" \
+  -H 'Content-Type: application/json' -d '{"enabled": true}' > /dev/null
+check "submission during maintenance" "$(curl -s -o /dev/null -w '%{http_code}' \
+  -X POST "$SERVER_URL/measurements" -F "image=@$IMAGE")" "503"
+check "batch during maintenance" "$(batch "[\"$FIRST\"]") $(jq -r ".\"$FIRST\".status" response.json)" \
+  "200 Completed"
+
+finish "batch status"
@@ -0,0 +1,91 @@
+#!/bin/bash
+set -e
+
+# Test coalesced status reads. Starts its own server with the mock toolchain
+# in test_vectors/mock_toolchain, a single proof worker and a 300 ms
+# STATUS_COALESCE_WINDOW_MS, and checks that:
+#   - a burst of concurrent polls of one measurement shares store reads, as
+#     counted by /admin/stats, which go to the local store directly rather
+#     than through the measurement cache;
+#   - /status/{id} carries an ETag and answers If-None-Match naming it with
+#     an empty 304;
+#   - while the measurement is polled without pause, its cancellation shows
+#     in every poll made once the window has passed, and changes the ETag.
+
+source "$(dirname "$0")/test_lib.sh"
+
+WINDOW_MS=300
+
+enter_work_dir
+
+start_server PROOF_WORKERS=1 STATUS_COALESCE_WINDOW_MS=$WINDOW_MS
+
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' | jq -r .measurement_id
+}
+
+status() {
+  curl -s "$SERVER_URL/status/$1" | jq -r .status
+}
+
+wait_status() {
+  local current
+  for _ in $(seq 1 10); do
+    current=$(status "$1")
+    [ "$current" == "$2" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+etag() {
+  curl -s -D - -o /dev/null "$SERVER_URL/status/$1" | tr -d '\r' | sed -n 's/^etag: //Ip'
+}
+
+reads() {
+  curl -s "$SERVER_URL/admin/stats" | jq -c ".status_reads.$1"
+}
+
+# A measurement that stays Processing until it is cancelled
+echo 60 > mock_prove_delay
+ID=$(submit)
+check "measurement being proved" "$(wait_status "$ID" Processing)" "Processing"
+check "window" "$(reads window_ms)" "$WINDOW_MS"
+
+# A burst of polls shares reads
+BEFORE=$(reads fetches)
+seq 1 50 | xargs -P 50 -I{} curl -s -o /dev/null "$SERVER_URL/status/$ID"
+FETCHES=$(($(reads fetches) - BEFORE))
+check "burst coalesced" "$([ "$(reads coalesced)" -gt 0 ] && echo yes || echo "no")" "yes"
+check "fewer reads than polls" "$([ "$FETCHES" -lt 50 ] && echo yes || echo "no ($FETCHES)")" "yes"
+check "local store not cached" \
+  "$(curl -s "$SERVER_URL/admin/stats" | jq '.measurement_cache.capacity == 0 and .measurement_cache.hits == 0')" "true"
+
+# Revalidation
+ETAG=$(etag "$ID")
+check "etag sent" "$([ -n "$ETAG" ] && echo yes || echo no)" "yes"
+check "unchanged status" \
+  "$(curl -s -o /dev/null -w '%{http_code} %{size_download}' -H "If-None-Match: $ETAG" "$SERVER_URL/status/$ID")" "304 0"
+
+# Keep polling while the measurement is cancelled, so reads are shared the
+# whole time
+(while true; do curl -s -o /dev/null "$SERVER_URL/status/$ID"; done) &
+POLLER_PID=$!
+BACKGROUND_PIDS+=" $POLLER_PID"
+sleep 1
+check "cancelled" "$(curl -s -o /dev/null -w '%{http_code}' -X POST "$SERVER_URL/measurements/$ID/cancel")" "200"
+sleep "$(awk "BEGIN { print $WINDOW_MS / 1000 }")"
+STALE=0
+for _ in $(seq 1 20); do
+  [ "$(status "$ID")" == "Cancelled" ] || STALE=$((STALE + 1))
+done
+kill $POLLER_PID
+check "stale polls after the window" "$STALE" "0"
+check "changed status" \
+  "$(curl -s -o /dev/null -w '%{http_code}' -H "If-None-Match: $ETAG" "$SERVER_URL/status/$ID")" "200"
+rm mock_prove_delay
+
+finish "status coalescing"
@@ -0,0 +1,115 @@
+#!/bin/bash
+set -e
+
+# Test the status event stream. Starts its own server with the mock toolchain
+# in test_vectors/mock_toolchain and a single proof worker and checks that
+# GET /status/{id}/events:
+#   - starts with the current status, sends every transition of a queued
+#     measurement and its attestation, and closes once it completed;
+#   - closes after Failed when the zkVerify submission fails;
+#   - closes after Cancelled when the measurement is cancelled;
+#   - sends the status and attestation of a completed measurement at once;
+#   - answers unknown measurements with 404.
+
+source "$(dirname "$0")/test_lib.sh"
+
+enter_work_dir
+
+start_server PROOF_WORKERS=1
+
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' | jq -r .measurement_id
+}
+
+status() {
+  curl -s "$SERVER_URL/status/$1" | jq -r .status
+}
+
+wait_status() {
+  local current
+  for _ in $(seq 1 10); do
+    current=$(status "$1")
+    [ "$current" == "$2" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+# Follow a measurement's stream into events-<id>.txt in the background, for
+# at most 60 seconds
+follow() {
+  timeout 60 curl -sN "$SERVER_URL/status/$1/events" > "events-$1.txt" &
+  STREAM_PID=$!
+  sleep 1
+}
+
+# Wait for the stream and set STREAM_END to how it ended: closed by the
+# server, or cut by the timeout
+wait_stream() {
+  local code=0
+  wait $STREAM_PID || code=$?
+  [ "$code" == "0" ] && STREAM_END="closed" || STREAM_END="timed out ($code)"
+}
+
+# The events of a stream as "<event>:<status>"
+events() {
+  local event line out=()
+  while IFS= read -r line; do
+    case "$line" in
+      "event: "*) event=${line#event: } ;;
+      "data: "*) out+=("$event:$(echo "${line#data: }" | jq -r .status)") ;;
+    esac
+  done < "events-$1.txt"
+  echo "${out[*]}"
+}
+
+# The only worker is busy while the followed measurement waits for it
+echo 3 > mock_prove_delay
+BUSY=$(submit)
+check "worker busy" "$(wait_status "$BUSY" Processing)" "Processing"
+QUEUED=$(submit)
+check "measurement queued" "$(status "$QUEUED")" "Pending"
+follow "$QUEUED"
+wait_stream
+check "stream of a queued measurement" "$STREAM_END" "closed"
+check "transitions and attestation" "$(events "$QUEUED")" \
+  "status:Pending status:Processing status:Completed attestation:Completed"
+check "attestation sent" \
+  "$(grep -A1 '^event: attestation' "events-$QUEUED.txt" | sed -n 's/^data: //p' | jq 'has("attestation") and .attestation != null')" "true"
+rm mock_prove_delay
+
+# A refused submission ends in Failed
+touch mock_submit_fails
+FAILING=$(submit)
+follow "$FAILING"
+wait_stream
+check "stream of a failing measurement" "$STREAM_END" "closed"
+check "ends with the failure" "$(events "$FAILING" | awk '{print $NF}')" "status:Failed"
+rm mock_submit_fails
+
+# A cancellation ends the stream of a measurement being proved
+echo 60 > mock_prove_delay
+CANCELLED=$(submit)
+check "measurement being proved" "$(wait_status "$CANCELLED" Processing)" "Processing"
+follow "$CANCELLED"
+curl -s -o /dev/null -X POST "$SERVER_URL/measurements/$CANCELLED/cancel"
+wait_stream
+check "stream of a cancelled measurement" "$STREAM_END" "closed"
+check "cancellation" "$(events "$CANCELLED")" "status:Processing status:Cancelled"
+rm mock_prove_delay
+
+# A finished measurement is told at once
+follow "$QUEUED"
+wait_stream
+check "stream of a completed measurement" "$STREAM_END" "closed"
+check "status and attestation at once" "$(events "$QUEUED")" "status:Completed attestation:Completed"
+
+check "content type" \
+  "$(curl -s -o /dev/null -w '%{content_type}' "$SERVER_URL/status/$QUEUED/events")" "text/event-stream"
+check "unknown measurement" \
+  "$(curl -s -o /dev/null -w '%{http_code}' "$SERVER_URL/status/00000000-0000-0000-0000-000000000000/events")" "404"
+
+finish "status event"
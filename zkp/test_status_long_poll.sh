@@ -0,0 +1,60 @@
+#!/bin/bash
+set -e
+
+# Test long-polling of /status/:id. Starts its own server with the mock
+# toolchain in test_vectors/mock_toolchain and checks that:
+#   - ?wait= holds the request until the status differs from ?since=, and
+#     answers as soon as the proof completes;
+#   - a status already differing from ?since= is answered at once;
+#   - without ?since= the request waits for the current status to change, and
+#     answers it unchanged once the wait is over;
+#   - requests without ?wait= are answered at once, and waits above the limit,
+#     unknown statuses and unknown measurements are refused.
+
+source "$(dirname "$0")/test_lib.sh"
+
+enter_work_dir
+
+start_server
+
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' | jq -r .measurement_id
+}
+
+# Poll with the given query, printing the status answered and the whole
+# seconds the request took
+poll() {
+  curl -s -o poll.json -w '%{time_total}' "$SERVER_URL/status/$1?$2" > time.txt
+  echo "$(jq -r .status poll.json) $(cut -d. -f1 time.txt)s"
+}
+
+echo 4 > mock_prove_delay
+ID=$(submit)
+for _ in $(seq 1 20); do
+  [ "$(curl -s "$SERVER_URL/status/$ID" | jq -r .status)" == "Processing" ] && break
+  sleep 0.2
+done
+check "without wait" "$(poll "$ID" "")" "Processing 0s"
+check "status already changed" "$(poll "$ID" "wait=30&since=Pending")" "Processing 0s"
+
+# Held until the proof completes about four seconds in, well before the wait
+RESULT=$(poll "$ID" "wait=30&since=Processing")
+SECONDS_TAKEN=${RESULT##* }
+check "answered on completion" "${RESULT% *}" "Completed"
+check "answered before the wait is over" "$([ "${SECONDS_TAKEN%s}" -ge 1 ] && [ "${SECONDS_TAKEN%s}" -le 10 ] && echo yes)" "yes"
+rm mock_prove_delay
+
+check "wait over without a change" "$(poll "$ID" "wait=2")" "Completed 2s"
+check "strict with wait" "$(curl -s "$SERVER_URL/status/$ID?wait=1&strict=true" | jq -c .pending_items)" "[]"
+
+check "wait above the limit" \
+  "$(curl -s -o /dev/null -w '%{http_code}' "$SERVER_URL/status/$ID?wait=61")" "400"
+check "unknown status" \
+  "$(curl -s -o /dev/null -w '%{http_code}' "$SERVER_URL/status/$ID?wait=5&since=Baking")" "400"
+check "unknown measurement" \
+  "$(curl -s -o /dev/null -w '%{http_code} %{time_total}' "$SERVER_URL/status/00000000-0000-0000-0000-000000000000?wait=5" | cut -d. -f1)" "404 0"
+
+finish "long-polling"
@@ -0,0 +1,88 @@
+#!/bin/bash
+set -e
+
+# Test the pipeline against a measurement store that fails. Starts its own
+# server with the mock toolchain in test_vectors/mock_toolchain on the faulty
+# store (MEASUREMENT_STORE=faulty) and checks that:
+#   - a proof failing on the filesystem reaches Failed in the store even when
+#     writing that status fails once, instead of staying Processing for good;
+#   - a submission the full store refuses is answered with 500 and leaves no
+#     image behind;
+#   - with every store call delayed and no measurement cache, measurements
+#     still complete and status reads wait for the store.
+#
+# The server must be built with the testing feature (cargo build --features
+# testing).
+
+source "$(dirname "$0")/test_lib.sh"
+
+enter_work_dir
+
+# Start the server injecting the given faults
+start_server() {
+  PATH="$MOCK_TOOLCHAIN:$PATH" \
+    MEASUREMENT_STORE=faulty \
+    STORE_FAULTS="$1" \
+    "$BACKEND" >> server.log 2>&1 &
+  SERVER_PID=$!
+  for _ in $(seq 1 30); do
+    curl -s "$SERVER_URL/health" > /dev/null && return
+    sleep 1
+  done
+  # Without the testing feature the server refuses the faulty store
+  echo -e "${RED}The server did not start; server log:${NC}"
+  cat server.log
+  exit 1
+}
+
+# Submit a measurement and print the response status and body
+submit() {
+  curl -s -w '\n%{http_code}' -X POST "$SERVER_URL/measurements" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}'
+}
+
+status() {
+  curl -s "$SERVER_URL/status/$1" | jq -r .status
+}
+
+# Wait until a measurement reaches the given status and print the last one seen
+wait_status() {
+  local current
+  for _ in $(seq 1 30); do
+    current=$(status "$1")
+    [ "$current" == "$2" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+# proofs/ is a file, so every proof fails creating its directory. The claim
+# is the first status change and the failure the second, which the store
+# refuses once.
+touch proofs
+start_server "fail=update_status:2,capacity=2"
+
+FIRST=$(submit | head -1 | jq -r .measurement_id)
+check "failed proof" "$(wait_status "$FIRST" Failed)" "Failed"
+check "failure class" "$(curl -s "$SERVER_URL/status/$FIRST" | jq -r .failure.class)" "ProofGeneration"
+check "retried status write" "$(grep -c "Failed to store measurement $FIRST (attempt 1/" server.log)" "1"
+
+SECOND=$(submit | head -1 | jq -r .measurement_id)
+check "second measurement" "$(wait_status "$SECOND" Failed)" "Failed"
+check "submission to a full store" "$(submit | tail -1)" "500"
+check "images kept" "$(ls uploads | wc -l | tr -d ' ')" "2"
+
+kill $SERVER_PID
+wait $SERVER_PID 2>/dev/null || true
+rm -rf proofs uploads
+
+# Every store call takes 300ms, and status reads are not cached in front of it
+MEASUREMENT_CACHE_CAPACITY=0 start_server "latency_ms=300"
+SLOW=$(submit | head -1 | jq -r .measurement_id)
+check "slow store" "$(wait_status "$SLOW" Completed)" "Completed"
+SECONDS_TAKEN=$(curl -s -o /dev/null -w '%{time_total}' "$SERVER_URL/status/$SLOW")
+check "status waits for the store" "$(awk -v t="$SECONDS_TAKEN" 'BEGIN { print (t >= 0.3) }')" "1"
+
+finish "store fault"
@@ -0,0 +1,86 @@
+#!/bin/bash
+set -e
+
+# Test proving in a child process. Starts its own server with --supervise and
+# the mock toolchain in test_vectors/mock_toolchain and checks that:
+#   - /ready reports the connected prover process and a measurement is proved
+#     by it;
+#   - a killed prover process is restarted and proves the next measurement;
+#   - a measurement being proved when the prover process is killed fails as
+#     Interrupted;
+#   - stopping the server with SIGTERM stops the prover process too.
+
+source "$(dirname "$0")/test_lib.sh"
+
+enter_work_dir
+
+PATH="$MOCK_TOOLCHAIN:$PATH" "$BACKEND" --supervise > server.log 2>&1 &
+SERVER_PID=$!
+
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' | jq -r .measurement_id
+}
+
+wait_status() {
+  local current
+  for _ in $(seq 1 30); do
+    current=$(curl -s "$SERVER_URL/status/$1" | jq -r .status)
+    [ "$current" == "$2" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+# Wait until a prover process is connected, printing /ready's status code
+wait_ready() {
+  local code
+  for _ in $(seq 1 30); do
+    code=$(curl -s -o /dev/null -w '%{http_code}' "$SERVER_URL/ready")
+    [ "$code" == "200" ] && break
+    sleep 1
+  done
+  echo "$code"
+}
+
+prover() {
+  curl -s "$SERVER_URL/ready" | jq -r ".prover_process.$1"
+}
+
+check "ready with a prover process" "$(wait_ready)" "200"
+check "prover check" "$(curl -s "$SERVER_URL/ready" | jq -r .checks.prover_process)" "ok"
+FIRST_PID=$(prover pid)
+PROVED=$(submit)
+check "measurement proved by the prover process" "$(wait_status "$PROVED" Completed)" "Completed"
+
+kill -9 "$FIRST_PID"
+check "ready after a restart" "$(wait_ready)" "200"
+check "restarts" "$(prover restarts)" "1"
+check "new prover process" "$([ "$(prover pid)" != "$FIRST_PID" ] && echo yes)" "yes"
+AFTER_RESTART=$(submit)
+check "measurement proved after a restart" "$(wait_status "$AFTER_RESTART" Completed)" "Completed"
+
+# Kill the prover process while it proves
+echo 60 > mock_prove_delay
+INTERRUPTED=$(submit)
+for _ in $(seq 1 30); do
+  [ "$(prover jobs)" == "1" ] && break
+  sleep 1
+done
+check "job held by the prover process" "$(prover jobs)" "1"
+kill -9 "$(prover pid)"
+check "measurement being proved" "$(wait_status "$INTERRUPTED" Failed)" "Failed"
+check "failure class" "$(curl -s "$SERVER_URL/status/$INTERRUPTED" | jq -r .failure.class)" \
+  "Interrupted"
+rm mock_prove_delay
+check "ready after the second restart" "$(wait_ready)" "200"
+
+LAST_PID=$(prover pid)
+kill "$SERVER_PID"
+wait "$SERVER_PID" 2>/dev/null || true
+check "prover process stopped with the server" \
+  "$(kill -0 "$LAST_PID" 2>/dev/null && echo running || echo stopped)" "stopped"
+
+finish "prover process"
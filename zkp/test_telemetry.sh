@@ -0,0 +1,129 @@
+#!/bin/bash
+set -e
+
+# Test the opt-in usage telemetry. Starts its own server with the mock
+# toolchain in test_vectors/mock_toolchain, with SUMMARY_TIME set to the next
+# minute, and checks that:
+#   - with telemetry enabled and a local listener as its endpoint, the report
+#     received at SUMMARY_TIME is the body the server printed, carries only
+#     the expected fields and no measurement ID;
+#   - /admin/telemetry/preview then counts the measurement proved today;
+#   - with an endpoint nothing listens on, the report is dropped with a log
+#     line and measurements are still proved;
+#   - with telemetry disabled, the preview says so and nothing is announced.
+# Each report waits for SUMMARY_TIME, so the script takes a few minutes.
+#
+# Also needs python3, and port 3002 must be free.
+
+source "$(dirname "$0")/test_lib.sh"
+
+LISTENER_URL="http://localhost:3002/report"
+
+enter_work_dir
+
+# Endpoint appending every body it receives as a line to received.jsonl
+python3 -c '
+import http.server
+class Handler(http.server.BaseHTTPRequestHandler):
+    def do_POST(self):
+        body = self.rfile.read(int(self.headers["Content-Length"]))
+        with open("received.jsonl", "ab") as f:
+            f.write(body + b"\n")
+        self.send_response(204)
+        self.end_headers()
+    def log_message(self, *args):
+        pass
+http.server.HTTPServer(("127.0.0.1", 3002), Handler).serve_forever()
+' &
+LISTENER_PID=$!
+BACKGROUND_PIDS+=" $LISTENER_PID"
+touch received.jsonl
+
+# Start the server with the given environment, SUMMARY_TIME being the next
+# minute that leaves it time to start
+start_server() {
+  local minutes=1
+  [ "$(date -u +%-S)" -ge 40 ] && minutes=2
+  SUMMARY_TIME=$(date -u -d "+$minutes min" +%H:%M)
+  env "$@" SUMMARY_TIME="$SUMMARY_TIME" PATH="$MOCK_TOOLCHAIN:$PATH" \
+    "$BACKEND" > server.log 2>&1 &
+  SERVER_PID=$!
+  wait_for_server
+}
+
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' | jq -r .measurement_id
+}
+
+wait_completed() {
+  local current
+  for _ in $(seq 1 30); do
+    current=$(curl -s "$SERVER_URL/status/$1" | jq -r .status)
+    [ "$current" == "Completed" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+# Wait until the server log has a line matching the pattern, past SUMMARY_TIME
+wait_log() {
+  for _ in $(seq 1 150); do
+    grep -q "$1" server.log && break
+    sleep 1
+  done
+  grep -c "$1" server.log || true
+}
+
+preview() {
+  curl -s "$SERVER_URL/admin/telemetry/preview"
+}
+
+# Enabled, reporting to the listener
+start_server TELEMETRY_ENABLED=true TELEMETRY_ENDPOINT="$LISTENER_URL"
+check "announced" "$(grep -c "Telemetry is enabled: a daily report is sent to $LISTENER_URL" server.log)" "1"
+check "capability" "$(curl -s "$SERVER_URL/capabilities" | jq .features.telemetry.enabled)" "true"
+ID=$(submit)
+check "measurement proved" "$(wait_completed "$ID")" "Completed"
+echo "Waiting for the report at $SUMMARY_TIME UTC"
+check "report sent" "$(wait_log "Telemetry report for .* sent")" "1"
+check "reports received" "$(wc -l < received.jsonl)" "1"
+PRINTED=$(grep "Sending telemetry report to $LISTENER_URL: " server.log | sed 's/^Sending telemetry report to [^ ]*: //')
+check "received the printed body" "$(head -n 1 received.jsonl)" "$PRINTED"
+REPORT=$(head -n 1 received.jsonl)
+check "report fields" "$(echo "$REPORT" | jq -c 'keys - ["proof_seconds"]')" \
+  '["circuit_versions","date","proofs","report_version","server_version"]'
+check "report date" "$(echo "$REPORT" | jq -r .date)" "$(date -u -d yesterday +%F)"
+check "server version" "$(echo "$REPORT" | jq -r .server_version)" \
+  "$(curl -s "$SERVER_URL/version" | jq -r .serverVersion)"
+check "measurement ID sent" "$(grep -c "$ID" received.jsonl || true)" "0"
+
+# The next report covers today, with the proved measurement
+PREVIEW=$(preview)
+check "preview enabled" "$(echo "$PREVIEW" | jq .enabled)" "true"
+check "preview date" "$(echo "$PREVIEW" | jq -r .report.date)" "$(date -u +%F)"
+check "preview proofs" "$(echo "$PREVIEW" | jq -c .report.proofs)" '{"completed":1,"failed":0}'
+check "preview circuits" "$(echo "$PREVIEW" | jq -c .report.circuit_versions)" '{"zkhotdog-v1":1}'
+check "preview proof samples" "$(echo "$PREVIEW" | jq .report.proof_seconds.samples)" "1"
+stop_server
+
+# Enabled, with nothing listening at the endpoint
+start_server TELEMETRY_ENABLED=true TELEMETRY_ENDPOINT=http://localhost:3003/report
+echo "Waiting for the report at $SUMMARY_TIME UTC"
+check "unreachable report dropped" "$(wait_log "Telemetry report for .* dropped")" "1"
+check "still serving" "$(curl -s -o /dev/null -w '%{http_code}' "$SERVER_URL/ready")" "200"
+ID=$(submit)
+check "measurement proved after the drop" "$(wait_completed "$ID")" "Completed"
+stop_server
+
+# Disabled
+start_server
+check "disabled preview" "$(preview | jq -c '[.enabled, .report.report_version]')" "[false,1]"
+check "disabled capability" "$(curl -s "$SERVER_URL/capabilities" | jq .features.telemetry.enabled)" "false"
+check "disabled announcement" "$(grep -c "Telemetry" server.log || true)" "0"
+check "reports received in total" "$(wc -l < received.jsonl)" "1"
+stop_server
+
+finish "telemetry"
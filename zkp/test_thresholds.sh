@@ -0,0 +1,118 @@
+#!/bin/bash
+set -e
+
+# Test threshold proofs. Starts its own server with the mock toolchain in
+# test_vectors/mock_toolchain and checks that:
+#   - a measurement sent with minLengthCm is proved with the threshold circuit,
+#     whose only public signal is the minimum scaled and squared, also when
+#     sent as JSON;
+#   - its status, from /status/{id}, /status/batch and by external id, holds
+#     the minimum but not its points or length, its input.json is not served
+#     and its public view shows only the minimum;
+#   - points closer together than the minimum, a minimum with an angle or a
+#     polyline and a minimum that is not a positive number are rejected with
+#     400;
+#   - a proof of another minimum fails the measurement naming the signal, a
+#     reproof with a circuit revealing the length is refused, and so is
+#     GET /attestation/{id}.
+#
+# Also needs base64.
+
+source "$(dirname "$0")/test_lib.sh"
+
+enter_work_dir
+mkdir -p keys
+# The default circuit's proving key, so reproofs get as far as the refusal
+touch keys/zkHotdog_final.zkey
+
+start_server
+
+# 0.5 m apart
+START='{"x": 0.1, "y": 0.2, "z": 0.3}'
+END='{"x": 0.4, "y": -0.2, "z": 0.3}'
+
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" -F "image=@$IMAGE" "$@"
+}
+
+length() {
+  submit -F "startPoint=$START" -F "endPoint=$END" "$@"
+}
+
+code() {
+  submit -o /dev/null -w '%{http_code}' "$@"
+}
+
+wait_status() {
+  local current
+  for _ in $(seq 1 30); do
+    current=$(curl -s "$SERVER_URL/status/$1" | jq -r .status)
+    [ "$current" == "$2" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+status() {
+  curl -s "$SERVER_URL/status/$1" | jq -c "$2"
+}
+
+# Whether any object of a document reveals points or a length
+PRIVATE='[.. | objects | select(has("start_point") or has("end_point") or has("original_points")
+  or has("length_cm"))] | length'
+
+check "thresholds advertised" "$(curl -s "$SERVER_URL/capabilities" | jq -c \
+  '[.features.threshold_proofs.enabled, ([.circuits[].version] | index("zkhotdog-threshold-v1") != null)]')" \
+  '[true,true]'
+
+THRESHOLD=$(length -F minLengthCm=30 -F externalId=threshold-1 | jq -r .measurement_id)
+check "threshold proved" "$(wait_status "$THRESHOLD" Completed)" "Completed"
+check "threshold circuit" "$(status "$THRESHOLD" '[.circuit, .min_length_cm]')" \
+  '["zkhotdog-threshold-v1",30]'
+check "minimum proved" "$(cat "proofs/$THRESHOLD/public.json")" '["900000000"]'
+check "status keeps points private" "$(status "$THRESHOLD" "$PRIVATE")" "0"
+check "batch keeps points private" "$(curl -s -X POST "$SERVER_URL/status/batch" \
+  -H 'Content-Type: application/json' -d "[\"$THRESHOLD\"]" | jq "$PRIVATE")" "0"
+check "external id keeps points private" \
+  "$(curl -s "$SERVER_URL/measurements/by-external-id/threshold-1" | jq "$PRIVATE")" "0"
+check "listing keeps points private" "$(curl -s "$SERVER_URL/measurements" \
+  | jq --arg id "$THRESHOLD" "[.[] | select(.id == \$id)] | $PRIVATE")" "0"
+check "input not served" "$(curl -s -o /dev/null -w '%{http_code}' \
+  "$SERVER_URL/measurements/$THRESHOLD/artifacts/input")" "403"
+check "proof served" "$(curl -s -o /dev/null -w '%{http_code}' \
+  "$SERVER_URL/measurements/$THRESHOLD/artifacts/proof")" "200"
+VIEW=$(curl -s "$SERVER_URL/view/$THRESHOLD")
+check "view shows the minimum" "$(echo "$VIEW" | grep -c 'At least 30 cm')" "1"
+check "view hides the points" "$(echo "$VIEW" | grep -c '<dt>Start</dt>' || true)" "0"
+
+ORDINARY=$(length | jq -r .measurement_id)
+check "lengths still show points" "$(status "$ORDINARY" "$PRIVATE")" "1"
+
+BODY=$(jq -n --arg image "$(base64 -w0 "$IMAGE")" --argjson start "$START" --argjson stop "$END" \
+  '{image: $image, startPoint: $start, endPoint: $stop, minLengthCm: 30}')
+AS_JSON=$(curl -s -X POST "$SERVER_URL/measurements/json" -H 'Content-Type: application/json' \
+  -d "$BODY" | jq -r .measurement_id)
+check "threshold as JSON" "$(status "$AS_JSON" '[.circuit, .min_length_cm]')" \
+  "$(status "$THRESHOLD" '[.circuit, .min_length_cm]')"
+
+check "below the minimum" "$(length -F minLengthCm=50.001 |
+  grep -c "less than the minimum of 50.001 cm apart")" "1"
+check "exactly the minimum" "$(length -F minLengthCm=50 | jq -r '.measurement_id != null')" "true"
+check "with an angle" "$(length -F minLengthCm=30 -F measurementType=angle \
+  -F 'midPoint={"x": 0, "y": 0, "z": 0}' | grep -c "minLengthCm is only accepted")" "1"
+check "with a polyline" "$(code -F minLengthCm=30 -F "points=[$START, $END, $START]")" "400"
+check "invalid minimum" "$(length -F minLengthCm=abc | grep -c "Invalid minLengthCm")" "1"
+
+# The measurement proved with another minimum
+echo '["1"]' > mock_public_signals
+WRONG=$(length -F minLengthCm=30 | jq -r .measurement_id)
+check "wrong minimum" "$(wait_status "$WRONG" Failed)" "Failed"
+rm mock_public_signals
+check "signal named" "$(status "$WRONG" .failure.message |
+  grep -c 'min_distance_squared 1 does not match expected 900000000')" "1"
+
+REPROOF=$(curl -s -X POST "$SERVER_URL/measurements/$THRESHOLD/reprove")
+check "reproof refused" "$(echo "$REPROOF" | grep -c "proves a minimum length, which circuit zkhotdog-v1 would reveal")" "1"
+check "not mintable" "$(curl -s "$SERVER_URL/attestation/$THRESHOLD" | jq -r .error)" "not_mintable"
+
+finish "threshold"
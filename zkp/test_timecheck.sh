@@ -0,0 +1,85 @@
+#!/bin/bash
+set -e
+
+# Boundary test of the clock skew window of client timestamps. Deadlines and
+# bundle capture times are sent just inside and just outside the window of
+# CLOCK_SKEW_SECS, with a margin of MARGIN_SECS for the time the request takes.
+#
+# Set CLOCK_SKEW_SECS and IMPORT_MAX_AGE_DAYS to the server's values
+# (defaults 300 and 30). The bundle checks need a trusted device key: start
+# the server with the public key of an Ed25519 PEM key in IMPORT_TRUSTED_KEYS
+# and pass the PEM file as BUNDLE_KEY, otherwise they are skipped. Set API_KEY
+# when access control is on. Needs jq, openssl and zip.
+
+source "$(dirname "$0")/test_lib.sh"
+
+SKEW="${CLOCK_SKEW_SECS:-300}"
+MARGIN_SECS="${MARGIN_SECS:-30}"
+MAX_AGE_DAYS="${IMPORT_MAX_AGE_DAYS:-30}"
+AUTH=()
+[ -n "$API_KEY" ] && AUTH=(-H "X-Api-Key: $API_KEY")
+
+# RFC 3339 time the given number of seconds from now, negative for the past
+at() {
+  date -u -d "@$(($(date +%s) + $1))" +%Y-%m-%dT%H:%M:%SZ
+}
+
+# Check the HTTP status of a response written by curl -w '\n%{http_code}'
+expect() {
+  local name="$1" expected="$2" response="$3"
+  local status
+  status=$(echo "$response" | tail -n1)
+  if [ "$status" = "$expected" ]; then
+    echo -e "${GREEN}$name: $status${NC}"
+  else
+    echo -e "${RED}$name: expected $expected, got $status: $(echo "$response" | sed '$d')${NC}"
+    FAILED=1
+  fi
+}
+
+submit() {
+  curl -s -w '\n%{http_code}' -X POST "$SERVER_URL/measurements" "${AUTH[@]}" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x":0,"y":0,"z":0}' \
+    -F 'endPoint={"x":1,"y":1,"z":1}' \
+    "$@"
+}
+
+# Deadlines given as timestamps may lie in the past by up to the skew
+expect "deadline in the future" 200 "$(submit -F "deadline=$(at 60)")"
+expect "deadline behind within the skew" 200 "$(submit -F "deadline=$(at $((MARGIN_SECS - SKEW)))")"
+expect "deadline behind beyond the skew" 400 "$(submit -F "deadline=$(at $((-SKEW - MARGIN_SECS)))")"
+expect "deadline of zero seconds" 400 "$(submit -F "deadline=0")"
+expect "publishAt behind beyond the skew" 400 "$(submit -F "publishAt=$(at $((-SKEW - MARGIN_SECS)))")"
+
+# Bundles are checked for their capture time, which may lie ahead by the skew
+import() {
+  local captured_at="$1" dir
+  dir=$(mktemp -d)
+  cp "$IMAGE" "$dir/photo.jpg"
+  local public_key
+  public_key=$(openssl pkey -in "$BUNDLE_KEY" -pubout -outform DER | tail -c 32 | xxd -p -c 64)
+  jq -n --arg key "$public_key" --arg at "$captured_at" \
+    --arg sha "$(sha256sum "$dir/photo.jpg" | cut -d' ' -f1)" \
+    '{schema_version: 1, public_key: $key, captured_at: $at, image: "photo.jpg",
+      files: {"photo.jpg": $sha}, start_point: {x: 0, y: 0, z: 0}, end_point: {x: 1, y: 1, z: 1}}' \
+    > "$dir/manifest.json"
+  openssl pkeyutl -sign -rawin -inkey "$BUNDLE_KEY" -in "$dir/manifest.json" | xxd -p | tr -d '\n' \
+    > "$dir/manifest.sig"
+  (cd "$dir" && zip -q bundle.zip manifest.json manifest.sig photo.jpg)
+  curl -s -w '\n%{http_code}' -X POST "$SERVER_URL/measurements/import" "${AUTH[@]}" \
+    --data-binary "@$dir/bundle.zip"
+  rm -rf "$dir"
+}
+
+if [ -z "$BUNDLE_KEY" ]; then
+  echo -e "${YELLOW}bundle capture times: skipped, BUNDLE_KEY is not set${NC}"
+else
+  expect "captured_at ahead within the skew" 200 "$(import "$(at $((SKEW - MARGIN_SECS)))")"
+  expect "captured_at ahead beyond the skew" 400 "$(import "$(at $((SKEW + MARGIN_SECS)))")"
+  OLDEST=$((MAX_AGE_DAYS * 86400 + SKEW))
+  expect "captured_at old within the skew" 200 "$(import "$(at $((MARGIN_SECS - OLDEST)))")"
+  expect "captured_at old beyond the skew" 400 "$(import "$(at $((-OLDEST - MARGIN_SECS)))")"
+fi
+
+finish "clock skew"
@@ -0,0 +1,62 @@
+#!/bin/bash
+set -e
+
+# Test that failed and abandoned submissions leave no files behind. Starts its
+# own server with the mock toolchain in test_vectors/mock_toolchain and a
+# tenant whose quota of 0 rejects every submission after its image is saved.
+# Checks that:
+#   - a client disconnecting in the middle of the image leaves no temporary file;
+#   - a submission missing its points after the image leaves nothing;
+#   - a submission rejected after its image was stored leaves nothing;
+#   - a successful submission keeps exactly its image.
+
+source "$(dirname "$0")/test_lib.sh"
+
+ADMIN_KEY="zkh_test_admin_key"
+
+enter_work_dir
+start_server TENANTS="full:0,open" ADMIN_API_KEY="$ADMIN_KEY"
+
+# Files under uploads/, one per line
+files() {
+  find uploads -type f | sort
+}
+
+key_for() {
+  curl -s -X POST "$SERVER_URL/admin/keys" -H "X-Api-Key: $ADMIN_KEY" \
+    -H 'Content-Type: application/json' \
+    -d "{\"name\": \"$1\", \"role\": \"submitter\", \"tenant\": \"$1\"}" | jq -r .key
+}
+FULL_KEY=$(key_for full)
+OPEN_KEY=$(key_for open)
+
+# A client disconnecting while its image is still streaming in
+head -c $((1900 * 1024)) /dev/urandom > big.jpg
+curl -s -o /dev/null --limit-rate 200K --max-time 3 -X POST "$SERVER_URL/measurements" \
+  -H "X-Api-Key: $OPEN_KEY" -F "image=@big.jpg" \
+  -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' || true
+sleep 1
+check "files after a disconnect" "$(files | wc -l | tr -d ' ')" "0"
+
+# Points missing after the image
+STATUS=$(curl -s -o /dev/null -w '%{http_code}' -X POST "$SERVER_URL/measurements" \
+  -H "X-Api-Key: $OPEN_KEY" -F "image=@$IMAGE" -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}')
+check "missing end point" "$STATUS" "400"
+check "files after a missing point" "$(files | wc -l | tr -d ' ')" "0"
+
+# Rejected by the tenant quota once the image is stored
+STATUS=$(curl -s -o /dev/null -w '%{http_code}' -X POST "$SERVER_URL/measurements" \
+  -H "X-Api-Key: $FULL_KEY" -F "image=@$IMAGE" \
+  -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+  -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}')
+check "over quota" "$STATUS" "429"
+check "files after a rejection" "$(files | wc -l | tr -d ' ')" "0"
+
+# A successful submission keeps its image
+ID=$(curl -s -X POST "$SERVER_URL/measurements" \
+  -H "X-Api-Key: $OPEN_KEY" -F "image=@$IMAGE" \
+  -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+  -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' | jq -r '.measurement_id // empty')
+check "files after a submission" "$(files | grep -v _thumb_ | grep -v '\.webp$')" "uploads/open/$ID.jpg"
+
+finish "upload cleanup"
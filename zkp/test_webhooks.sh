@@ -0,0 +1,140 @@
+#!/bin/bash
+set -e
+
+# Test webhook callbacks. Starts its own server with the mock toolchain in
+# test_vectors/mock_toolchain, WEBHOOK_SECRET set, three delivery attempts and
+# WEBHOOK_ALLOW_PRIVATE, and a local receiver on port 3002, and checks that:
+#   - a completed measurement is POSTed to its callbackUrl with its event and
+#     a valid HMAC signature, and /status/{id} reports the delivery;
+#   - 5xx answers are retried until the receiver accepts, while a 4xx answer
+#     ends the delivery at once;
+#   - a measurement failing at submission is delivered as failed;
+#   - without WEBHOOK_ALLOW_PRIVATE, a callback on a loopback or metadata
+#     address fails without being sent or retried, and the production audit
+#     reports WEBHOOK_ALLOW_PRIVATE;
+#   - malformed callback URLs are refused, as are callback URLs on a server
+#     without WEBHOOK_SECRET, where /capabilities lists webhooks as disabled.
+#
+# Also needs python3, and port 3002 must be free.
+
+source "$(dirname "$0")/test_lib.sh"
+
+RECEIVER_URL="http://localhost:3002/hook"
+SECRET="webhook-test-secret"
+
+enter_work_dir
+
+# Receiver appending every delivery to received.jsonl with whether its
+# signature is valid, answering with the status codes listed in ./responses
+# one after another, then 200
+SECRET="$SECRET" python3 -c '
+import hashlib, hmac, http.server, json, os
+class Handler(http.server.BaseHTTPRequestHandler):
+    def do_POST(self):
+        body = self.rfile.read(int(self.headers["Content-Length"]))
+        expected = "sha256=" + hmac.new(os.environ["SECRET"].encode(), body, hashlib.sha256).hexdigest()
+        signed = hmac.compare_digest(expected, self.headers.get("X-Zkhotdog-Signature", ""))
+        codes = open("responses").read().split() if os.path.exists("responses") else []
+        with open("responses", "w") as f:
+            f.write(" ".join(codes[1:]))
+        record = {"event": self.headers.get("X-Zkhotdog-Event"), "signed": signed,
+                  "status": int(codes[0]) if codes else 200, "body": json.loads(body)}
+        with open("received.jsonl", "a") as f:
+            f.write(json.dumps(record) + "\n")
+        self.send_response(record["status"])
+        self.end_headers()
+    def log_message(self, *args):
+        pass
+http.server.HTTPServer(("127.0.0.1", 3002), Handler).serve_forever()
+' &
+RECEIVER_PID=$!
+BACKGROUND_PIDS+=" $RECEIVER_PID"
+touch received.jsonl
+
+# Submit with the given callback URL and print the response body
+submit_raw() {
+  curl -s -X POST "$SERVER_URL/measurements" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' \
+    -F "callbackUrl=$1"
+}
+
+submit() {
+  submit_raw "$RECEIVER_URL" | jq -r .measurement_id
+}
+
+# Wait until the measurement's last delivery is no longer being retried, and
+# print it as "<event> <outcome> <attempts> <response_status>"
+wait_delivery() {
+  local delivery
+  for _ in $(seq 1 30); do
+    delivery=$(curl -s "$SERVER_URL/status/$1" | jq -r '.webhook | select(. != null) | "\(.event) \(.outcome) \(.attempts) \(.response_status)"')
+    [ -n "$delivery" ] && [ "$(echo "$delivery" | cut -d' ' -f2)" != "retrying" ] && break
+    sleep 1
+  done
+  echo "$delivery"
+}
+
+# The deliveries received for a measurement, as "<event>:<status answered>"
+received() {
+  jq -r --arg id "$1" 'select(.body.id == $id) | "\(.event):\(.status)"' received.jsonl | paste -sd ' '
+}
+
+start_server WEBHOOK_SECRET="$SECRET" WEBHOOK_MAX_ATTEMPTS=3 WEBHOOK_ALLOW_PRIVATE=true
+check "audit reports private callbacks" \
+  "$(curl -s "$SERVER_URL/version" | jq '.safety.violations | index("private_webhook_urls") != null')" "true"
+check "capability" "$(curl -s "$SERVER_URL/capabilities" | jq .features.webhooks.enabled)" "true"
+
+# Delivered at once
+ID=$(submit)
+check "completed delivery" "$(wait_delivery "$ID")" "completed delivered 1 200"
+check "received" "$(received "$ID")" "completed:200"
+DELIVERY=$(jq -c --arg id "$ID" 'select(.body.id == $id)' received.jsonl)
+check "signature valid" "$(echo "$DELIVERY" | jq .signed)" "true"
+check "body status" "$(echo "$DELIVERY" | jq -r .body.status)" "Completed"
+check "body callback" "$(echo "$DELIVERY" | jq -r .body.callback_url)" "$RECEIVER_URL"
+check "body history left out" "$(echo "$DELIVERY" | jq '.body | has("history")')" "false"
+
+# Retried after 5xx answers
+echo "503 502" > responses
+ID=$(submit)
+check "retried delivery" "$(wait_delivery "$ID")" "completed delivered 3 200"
+check "received after retries" "$(received "$ID")" "completed:503 completed:502 completed:200"
+
+# Not retried after a 4xx answer
+echo "410" > responses
+ID=$(submit)
+check "refused delivery" "$(wait_delivery "$ID")" "completed failed 1 410"
+check "received once" "$(received "$ID")" "completed:410"
+
+# A failed measurement
+touch mock_submit_fails
+ID=$(submit)
+check "failed delivery" "$(wait_delivery "$ID")" "failed delivered 1 200"
+check "failed body" "$(jq -r --arg id "$ID" 'select(.body.id == $id) | .body.status' received.jsonl)" "Failed"
+rm mock_submit_fails
+
+check "malformed callback" \
+  "$(submit_raw 'ftp://localhost/hook' | grep -c 'http:// or https://')" "1"
+stop_server
+
+# Internal callback addresses are never contacted
+start_server WEBHOOK_SECRET="$SECRET" WEBHOOK_MAX_ATTEMPTS=3
+check "audit passes" "$(curl -s "$SERVER_URL/version" | jq '.safety.violations | index("private_webhook_urls")')" "null"
+ID=$(submit)
+check "loopback delivery" "$(wait_delivery "$ID")" "completed failed 1 null"
+check "loopback error" "$(curl -s "$SERVER_URL/status/$ID" | jq -r .webhook.error)" \
+  "resolves to the non-public address 127.0.0.1"
+check "loopback not received" "$(received "$ID")" ""
+ID=$(submit_raw "http://169.254.169.254/latest/meta-data/" | jq -r .measurement_id)
+check "metadata delivery" "$(wait_delivery "$ID")" "completed failed 1 null"
+stop_server
+
+# Without a secret
+start_server
+check "capability without secret" "$(curl -s "$SERVER_URL/capabilities" | jq .features.webhooks.enabled)" "false"
+check "callback refused" "$(submit_raw "$RECEIVER_URL" | grep -c 'webhooks are not enabled')" "1"
+stop_server
+
+finish "webhook"
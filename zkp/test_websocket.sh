@@ -0,0 +1,139 @@
+#!/bin/bash
+set -e
+
+# Test the WebSocket activity feed. Starts its own server with the mock
+# toolchain in test_vectors/mock_toolchain and a single proof worker and checks
+# that GET /ws:
+#   - pushes the creation and every status change of all measurements to an
+#     unfiltered client;
+#   - only pushes the events of the followed ids after {"ids": [...]}, and
+#     acknowledges the subscription;
+#   - answers a malformed subscription with an error, keeping the connection;
+#   - refuses requests that are no WebSocket handshake.
+#
+# Also needs python3.
+
+source "$(dirname "$0")/test_lib.sh"
+
+enter_work_dir
+
+# Minimal WebSocket client: sends each message argument as a text frame, then
+# prints every text message received as one line, and "close:<code>" when the
+# server closes, for at most the given seconds
+cat > ws_client.py << 'EOF'
+import base64, os, socket, struct, sys, time
+
+seconds, messages = float(sys.argv[1]), sys.argv[2:]
+sock = socket.create_connection(("localhost", 3001))
+key = base64.b64encode(os.urandom(16)).decode()
+sock.sendall((
+    "GET /ws HTTP/1.1\r\nHost: localhost:3001\r\nUpgrade: websocket\r\n"
+    "Connection: Upgrade\r\nSec-WebSocket-Key: %s\r\nSec-WebSocket-Version: 13\r\n\r\n" % key
+).encode())
+buffer = b""
+while b"\r\n\r\n" not in buffer:
+    buffer += sock.recv(4096)
+head, buffer = buffer.split(b"\r\n\r\n", 1)
+if b" 101 " not in head.split(b"\r\n")[0]:
+    sys.exit("handshake refused: " + head.decode())
+
+def send(opcode, payload):
+    mask = os.urandom(4)
+    length = len(payload)
+    header = bytes([0x80 | opcode])
+    if length < 126:
+        header += bytes([0x80 | length])
+    else:
+        header += bytes([0x80 | 126]) + struct.pack(">H", length)
+    sock.sendall(header + mask + bytes(b ^ mask[i % 4] for i, b in enumerate(payload)))
+
+def read(n):
+    global buffer
+    while len(buffer) < n:
+        chunk = sock.recv(4096)
+        if not chunk:
+            raise EOFError
+        buffer += chunk
+    data, buffer = buffer[:n], buffer[n:]
+    return data
+
+for message in messages:
+    send(0x1, message.encode())
+deadline = time.time() + seconds
+try:
+    while True:
+        sock.settimeout(max(deadline - time.time(), 0.01))
+        first, second = read(2)
+        length = second & 0x7F
+        if length == 126:
+            length = struct.unpack(">H", read(2))[0]
+        elif length == 127:
+            length = struct.unpack(">Q", read(8))[0]
+        payload = read(length)
+        opcode = first & 0x0F
+        if opcode == 0x1:
+            print(payload.decode(), flush=True)
+        elif opcode == 0x8:
+            print("close:%d" % struct.unpack(">H", payload[:2])[0], flush=True)
+            break
+        elif opcode == 0x9:
+            send(0xA, payload)
+except (socket.timeout, EOFError):
+    pass
+EOF
+
+start_server PROOF_WORKERS=1
+
+submit() {
+  curl -s -X POST "$SERVER_URL/measurements" \
+    -F "image=@$IMAGE" \
+    -F 'startPoint={"x": 0.1, "y": 0.2, "z": -0.3}' \
+    -F 'endPoint={"x": 0.4, "y": 0.2, "z": -0.3}' | jq -r .measurement_id
+}
+
+wait_completed() {
+  local current
+  for _ in $(seq 1 30); do
+    current=$(curl -s "$SERVER_URL/status/$1" | jq -r .status)
+    [ "$current" == "Completed" ] && break
+    sleep 1
+  done
+  echo "$current"
+}
+
+# The statuses a client received for a measurement
+statuses() {
+  jq -r --arg id "$2" 'select(.id == $id) | .status' "$1" | paste -sd ' '
+}
+
+# An unfiltered client, and one following only the first measurement
+python3 ws_client.py 20 > all.jsonl &
+ALL_PID=$!
+sleep 1
+echo 2 > mock_prove_delay
+FIRST=$(submit)
+python3 ws_client.py 20 "{\"ids\": [\"$FIRST\"]}" > first.jsonl &
+FIRST_PID=$!
+sleep 1
+SECOND=$(submit)
+check "first completed" "$(wait_completed "$FIRST")" "Completed"
+check "second completed" "$(wait_completed "$SECOND")" "Completed"
+rm mock_prove_delay
+wait $ALL_PID $FIRST_PID
+
+check "all: first measurement" "$(statuses all.jsonl "$FIRST")" "Pending Processing Completed"
+check "all: second measurement" "$(statuses all.jsonl "$SECOND")" "Pending Processing Completed"
+check "event fields" "$(head -n 1 all.jsonl | jq -c 'keys')" '["at","id","kind","status"]'
+check "subscription acknowledged" "$(head -n 1 first.jsonl | jq -c .subscribed)" "[\"$FIRST\"]"
+check "followed measurement" "$(statuses first.jsonl "$FIRST" | awk '{print $NF}')" "Completed"
+check "other measurement left out" "$(grep -c "$SECOND" first.jsonl || true)" "0"
+
+# Malformed subscriptions are answered, and the connection stays usable
+python3 ws_client.py 2 '{"id": ["x"]}' '{"ids": null}' > malformed.jsonl
+check "malformed subscription" "$(sed -n 1p malformed.jsonl | jq 'has("error")')" "true"
+check "subscription after the error" "$(sed -n 2p malformed.jsonl | jq -c .)" '{"subscribed":null}'
+
+check "plain GET refused" \
+  "$(curl -s -o /dev/null -w '%{http_code}' "$SERVER_URL/ws")" "400"
+
+finish "WebSocket"